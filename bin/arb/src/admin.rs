@@ -0,0 +1,103 @@
+use std::net::SocketAddr;
+
+use eyre::{bail, Result};
+use itertools::Itertools;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info, warn};
+
+use crate::defi::pool_quarantine;
+
+/// Serve the admin commands in the background, one per line, each answered with `ok` or `error: <reason>`:
+/// - `log <directives>`: logs e.g. `arb=trace` on top of the initial directives, until the next `log`.
+///   `log` alone goes back to the initial directives.
+/// - `quarantine`: `ok` followed by the pools in quarantine, as `<pool_id>:<protocol>:<seconds left>`.
+///
+/// There is no authentication, `addr` is expected to be a local one. Returns the bound address.
+pub async fn serve(addr: SocketAddr) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    info!(%local_addr, "admin server started");
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(error) => {
+                    warn!(?error, "Accept admin connection failed");
+                    continue;
+                }
+            };
+
+            tokio::spawn(async move {
+                if let Err(error) = handle_connection(stream).await {
+                    debug!(?error, "Serve admin commands failed");
+                }
+            });
+        }
+    });
+
+    Ok(local_addr)
+}
+
+async fn handle_connection(stream: TcpStream) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let reply = match handle_command(line.trim()) {
+            Ok(output) if output.is_empty() => "ok\n".to_string(),
+            Ok(output) => format!("ok {output}\n"),
+            Err(error) => format!("error: {error}\n"),
+        };
+        writer.write_all(reply.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+// returns the output of the command, if any
+fn handle_command(command: &str) -> Result<String> {
+    let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+    match name {
+        "log" => {
+            mev_logger::set_directives(arg.trim())?;
+            info!(directives = %arg.trim(), "log directives set");
+            Ok(String::new())
+        }
+        "quarantine" => Ok(pool_quarantine()
+            .quarantined()
+            .into_iter()
+            .map(|(pool_id, protocol, left)| format!("{pool_id}:{protocol}:{}", left.as_secs()))
+            .join(" ")),
+        _ => bail!("unknown command {name:?}, expected `log <directives>` or `quarantine`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_admin_commands() {
+        let addr = serve(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"restart\nlog arb=loud\nquarantine\n").await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        let mut replies = String::new();
+        stream.read_to_string(&mut replies).await.unwrap();
+        let replies = replies.lines().collect::<Vec<_>>();
+        assert_eq!(replies.len(), 3, "{replies:?}");
+        assert!(
+            replies[0].starts_with("error: unknown command \"restart\""),
+            "{replies:?}"
+        );
+        // invalid, whether or not a test initialized the logger
+        assert!(replies[1].starts_with("error: "), "{replies:?}");
+        assert!(replies[2].starts_with("ok"), "{replies:?}");
+    }
+}
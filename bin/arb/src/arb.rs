@@ -1,45 +1,105 @@
 //! Example:
 //! cargo run -r --bin arb run --coin-type \
 //!     "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN"
+//!
+//! Batch mode, one `coin_type[,pool_id]` per line:
+//! cargo run -r --bin arb run --coin-file coins.txt --concurrency 8 --json-out result.json
 
 use std::{
+    cmp::Ordering,
     fmt,
+    future::Future,
+    panic::AssertUnwindSafe,
     str::FromStr,
     sync::Arc,
     time::{Duration, Instant},
 };
 
 use async_trait::async_trait;
-use clap::Parser;
-use eyre::{ensure, ContextCompat, Result};
+use clap::{Parser, ValueEnum};
+use dex_indexer::types::Protocol;
+use eyre::{bail, ensure, ContextCompat, Result};
+use futures::FutureExt;
 use itertools::Itertools;
 use object_pool::ObjectPool;
+use serde::{Serialize, Serializer};
 use simulator::{HttpSimulator, SimulateCtx, Simulator};
-use sui_sdk::SuiClientBuilder;
+use sui_json_rpc_types::SuiEvent;
+use sui_sdk::{SuiClientBuilder, SUI_COIN_TYPE};
 use sui_types::{
     base_types::{ObjectID, ObjectRef, SuiAddress},
     transaction::TransactionData,
 };
-use tokio::task::JoinSet;
-use tracing::{debug, info, instrument, Instrument};
-use utils::coin;
+use tokio::task::{JoinError, JoinSet};
+use tracing::{debug, error, info, instrument, Instrument};
+use utils::coin::{self, GasCoinFilter};
 
 use crate::{
     common::get_latest_epoch,
     common::search::{golden_section_search_maximize, SearchGoal},
-    defi::{Defi, Path, TradeType},
-    types::Source,
+    config::{GAS_BUDGET, MAX_GAS_COINS, MIN_GAS_COIN_BALANCE},
+    defi::{
+        Defi, HopFill, Path, PoolTrials, ProtocolFilter, TradeType, DEFAULT_MAX_POOLS_PER_PROTOCOL,
+        DEFAULT_MAX_SIMULATED_PATHS,
+    },
+    types::{DeadlineExceeded, Source},
     HttpConfig,
 };
 
 #[derive(Clone, Debug, Parser)]
 pub struct Args {
-    #[arg(long)]
-    pub coin_type: String,
+    #[arg(long, required_unless_present = "coin_file", conflicts_with = "coin_file")]
+    pub coin_type: Option<String>,
 
     #[arg(long)]
     pub pool_id: Option<String>,
 
+    /// newline-separated coin types, each optionally followed by `,pool_id`
+    #[arg(long)]
+    pub coin_file: Option<String>,
+
+    /// max number of coins searched concurrently in batch mode
+    #[arg(long, default_value_t = 4)]
+    pub concurrency: usize,
+
+    /// write the batch summary as json to this file
+    #[arg(long)]
+    pub json_out: Option<String>,
+
+    /// output format of a single coin's result
+    #[arg(long, value_enum, default_value_t = OutputFormat::Debug)]
+    pub output: OutputFormat,
+
+    /// write a single coin's result as json to this file
+    #[arg(long)]
+    pub output_file: Option<String>,
+
+    /// also trade through SUI-rooted cycles of up to this many hops, 0 to disable
+    #[arg(long, default_value_t = 0)]
+    pub max_cycle_hops: usize,
+
+    /// comma-separated protocols whose pools are ignored, e.g. `cetus,turbos`
+    #[arg(long, value_delimiter = ',')]
+    pub disable_protocols: Vec<Protocol>,
+
+    /// comma-separated protocols whose pools are the only ones searched, all of them if empty
+    #[arg(long, value_delimiter = ',')]
+    pub only_protocols: Vec<Protocol>,
+
+    /// of the pools searched per coin, keep this many of each protocol before ranking the others by
+    /// liquidity, 0 to only rank them by liquidity
+    #[arg(long, default_value_t = DEFAULT_MAX_POOLS_PER_PROTOCOL)]
+    pub max_pools_per_protocol: usize,
+
+    /// of the paths whose pools can all be quoted locally, only simulate this many best estimated ones,
+    /// 0 to simulate all of them
+    #[arg(long, default_value_t = DEFAULT_MAX_SIMULATED_PATHS)]
+    pub max_simulated_paths: usize,
+
+    /// never flashloan from Navi, the paths whose first hop can't flashloan are rotated or dropped
+    #[arg(long)]
+    pub disable_navi: bool,
+
     #[arg(
         long,
         default_value = ""
@@ -50,6 +110,12 @@ pub struct Args {
     pub http_config: HttpConfig,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Debug,
+    Json,
+}
+
 pub async fn run(args: Args) -> Result<()> {
     mev_logger::init_console_logger_with_directives(None, &["arb=debug", "dex_indexer=debug"]);
 
@@ -61,55 +127,342 @@ pub async fn run(args: Args) -> Result<()> {
     let sender = SuiAddress::from_str(&args.sender).map_err(|e| eyre::eyre!(e))?;
 
     //创建一个对象池，用于管理Simulator实例
-    //每个Simulator实例都使用一个新的Tokio运行时来执行HTTP请求
-    let simulator_pool = ObjectPool::new(1, move || {
-        tokio::runtime::Runtime::new()
-            .unwrap()
-            .block_on(async { Box::new(HttpSimulator::new(&rpc_url, &ipc_path).await) as Box<dyn Simulator> })
+    //每个Simulator实例都在当前的Tokio运行时上异步初始化
+    let simulator_pool = ObjectPool::new_async(1, move || {
+        let rpc_url = rpc_url.clone();
+        let ipc_path = ipc_path.clone();
+
+        async move { Box::new(HttpSimulator::new(&rpc_url, &ipc_path).await) as Box<dyn Simulator> }
     });
 
-    let arb = Arb::new(&args.http_config.rpc_url, Arc::new(simulator_pool)).await?;
+    let protocol_filter = ProtocolFilter::new(args.only_protocols, args.disable_protocols);
+    let arb = Arb::new_with_protocol_filter(
+        &args.http_config.rpc_url,
+        Arc::new(simulator_pool),
+        false,
+        protocol_filter,
+    )
+    .await?
+    .with_max_cycle_hops(args.max_cycle_hops)
+    .with_max_pools_per_protocol(args.max_pools_per_protocol)
+    .with_max_simulated_paths(args.max_simulated_paths)
+    .with_navi(!args.disable_navi);
     let sui = SuiClientBuilder::default().build(&args.http_config.rpc_url).await?;
-    let gas_coins = coin::get_gas_coin_refs(&sui, sender, None).await?;
+    let gas_filter = GasCoinFilter::default()
+        .with_min_balance(MIN_GAS_COIN_BALANCE)
+        .with_max_coins(MAX_GAS_COINS)
+        .with_required_budget(GAS_BUDGET);
+    let gas_coins = coin::get_gas_coin_refs(&sui, sender, &gas_filter).await?;
     let epoch = get_latest_epoch(&sui).await?;
     let sim_ctx = SimulateCtx::new(epoch, vec![]);
-    let pool_id = args.pool_id.as_deref().map(ObjectID::from_hex_literal).transpose()?;
 
-    let result = arb
-        .find_opportunity(
-            sender,
-            &args.coin_type,
-            pool_id,
-            gas_coins,
-            sim_ctx,
-            true,
-            Source::Public,
-        )
-        .await?;
+    let Some(coin_file) = args.coin_file else {
+        let coin_type = args.coin_type.context("--coin-type or --coin-file is required")?;
+        let pool_id = args.pool_id.as_deref().map(ObjectID::from_hex_literal).transpose()?;
+
+        let result = arb
+            .find_opportunity(sender, &coin_type, pool_id, gas_coins, sim_ctx, true, Source::Public)
+            .await?;
+
+        match args.output {
+            OutputFormat::Debug => info!("{result:#?}"),
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&result)?),
+        }
+        if let Some(output_file) = args.output_file {
+            std::fs::write(&output_file, serde_json::to_string_pretty(&result)?)?;
+            info!("Result written to {}", output_file);
+        }
+        return Ok(());
+    };
+
+    let coins = parse_coin_file(&std::fs::read_to_string(&coin_file)?)?;
+    info!("Running arb for {} coins from {}", coins.len(), coin_file);
+
+    let arb = Arc::new(arb);
+    let results = run_batch(coins, args.concurrency, |coin_type, pool_id| {
+        let arb = arb.clone();
+        let gas_coins = gas_coins.clone();
+        let sim_ctx = sim_ctx.clone();
+        async move {
+            arb.find_opportunity(sender, &coin_type, pool_id, gas_coins, sim_ctx, true, Source::Public)
+                .await
+        }
+    })
+    .await;
+
+    let mut rows = results.into_iter().map(BatchRow::from).collect_vec();
+    rows.sort_by(|a, b| b.profit.cmp(&a.profit));
+
+    println!("{}", BatchRow::header());
+    for row in &rows {
+        println!("{row}");
+    }
+
+    if let Some(json_out) = args.json_out {
+        std::fs::write(&json_out, serde_json::to_string_pretty(&rows)?)?;
+        info!("Batch summary written to {}", json_out);
+    }
 
-    info!("{result:#?}");
     Ok(())
 }
 
-#[derive(Debug)]
+// Parses lines of `coin_type[,pool_id]`, empty lines and lines starting with `#` are skipped.
+fn parse_coin_file(content: &str) -> Result<Vec<(String, Option<ObjectID>)>> {
+    let mut coins = vec![];
+    for (line_no, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // the pool id follows the last comma outside of the type params, e.g. `0x1::lp::LP<0x2::a::A, 0x3::b::B>,0x4`
+        let mut depth = 0i32;
+        let pool_sep = line.char_indices().rev().find_map(|(i, c)| {
+            match c {
+                '>' => depth += 1,
+                '<' => depth -= 1,
+                ',' if depth == 0 => return Some(i),
+                _ => {}
+            }
+            None
+        });
+        let (coin_type, pool_id) = match pool_sep.map(|i| (&line[..i], &line[i + 1..])) {
+            Some((coin_type, pool_id)) => {
+                let pool_id = ObjectID::from_hex_literal(pool_id.trim())
+                    .map_err(|e| eyre::eyre!("line {}: invalid pool_id {:?}: {}", line_no + 1, pool_id, e))?;
+                (coin_type.trim(), Some(pool_id))
+            }
+            None => (line, None),
+        };
+        if coin_type.is_empty() {
+            bail!("line {}: empty coin type", line_no + 1);
+        }
+
+        coins.push((coin_type.to_string(), pool_id));
+    }
+
+    Ok(coins)
+}
+
+struct BatchResult<T> {
+    coin_type: String,
+    pool_id: Option<ObjectID>,
+    result: Result<T>,
+    duration: Duration,
+}
+
+// Runs `find` for each coin with at most `concurrency` of them in flight.
+// A failed or panicked coin doesn't abort the batch, its error is kept in the result.
+async fn run_batch<F, Fut, T>(
+    coins: Vec<(String, Option<ObjectID>)>,
+    concurrency: usize,
+    find: F,
+) -> Vec<BatchResult<T>>
+where
+    F: Fn(String, Option<ObjectID>) -> Fut,
+    Fut: Future<Output = Result<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    let concurrency = concurrency.max(1);
+    let mut results = Vec::with_capacity(coins.len());
+    let mut joinset = JoinSet::new();
+
+    for (coin_type, pool_id) in coins {
+        if joinset.len() >= concurrency {
+            if let Some(joined) = joinset.join_next().await {
+                push_joined(&mut results, joined);
+            }
+        }
+
+        let fut = find(coin_type.clone(), pool_id);
+        joinset.spawn(
+            async move {
+                let start = Instant::now();
+                let result = AssertUnwindSafe(fut).catch_unwind().await.unwrap_or_else(|panic| {
+                    let msg = panic
+                        .downcast_ref::<&str>()
+                        .map(|msg| msg.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    error!(%coin_type, ?pool_id, "find panicked: {}", msg);
+                    Err(eyre::eyre!("panicked: {}", msg))
+                });
+                BatchResult {
+                    coin_type,
+                    pool_id,
+                    result,
+                    duration: start.elapsed(),
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    while let Some(joined) = joinset.join_next().await {
+        push_joined(&mut results, joined);
+    }
+
+    results
+}
+
+// The panics are caught in the task, so a join error is a cancelled task whose coin is unknown.
+fn push_joined<T>(results: &mut Vec<BatchResult<T>>, joined: Result<BatchResult<T>, JoinError>) {
+    match joined {
+        Ok(result) => results.push(result),
+        Err(e) => error!("batch task failed: {:?}", e),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BatchRow {
+    coin_type: String,
+    pool_id: Option<String>,
+    profit: u64,
+    path: String,
+    duration_ms: u64,
+    error: Option<String>,
+}
+
+impl BatchRow {
+    fn header() -> String {
+        format!(
+            "{:<80} {:>15} {:>10}  {}",
+            "coin", "profit", "duration", "best path / error"
+        )
+    }
+}
+
+impl From<BatchResult<ArbResult>> for BatchRow {
+    fn from(res: BatchResult<ArbResult>) -> Self {
+        let (profit, path, error) = match res.result {
+            Ok(arb_res) => {
+                let trial_res = arb_res.best_trial_result;
+                let path = trial_res
+                    .trade_path
+                    .path
+                    .iter()
+                    .map(|dex| format!("{}({})", dex.protocol(), dex.object_id()))
+                    .join(" -> ");
+                (trial_res.profit, path, None)
+            }
+            Err(error) => (0, String::new(), Some(format!("{error:#}"))),
+        };
+
+        Self {
+            coin_type: res.coin_type,
+            pool_id: res.pool_id.map(|id| id.to_string()),
+            profit,
+            path,
+            duration_ms: res.duration.as_millis() as u64,
+            error,
+        }
+    }
+}
+
+impl fmt::Display for BatchRow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<80} {:>15} {:>8}ms  {}",
+            self.coin_type,
+            coin::format_sui_with_symbol(self.profit),
+            self.duration_ms,
+            self.error.as_deref().unwrap_or(&self.path)
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct ArbResult {
+    #[serde(rename = "create_trial_ctx_ms", serialize_with = "serialize_ms")]
     pub create_trial_ctx_duration: Duration,
+    #[serde(rename = "grid_search_ms", serialize_with = "serialize_ms")]
     pub grid_search_duration: Duration,
+    #[serde(rename = "gss_ms", serialize_with = "serialize_opt_ms")]
     pub gss_duration: Option<Duration>,
     pub best_trial_result: TrialResult,
     pub cache_misses: u64,
     pub source: Source,
+    #[serde(rename = "tx_digest", serialize_with = "serialize_tx_digest")]
     pub tx_data: TransactionData,
 }
 
+fn serialize_ms<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+fn serialize_opt_ms<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    duration.map(|d| d.as_millis() as u64).serialize(serializer)
+}
+
+fn serialize_tx_digest<S: Serializer>(tx_data: &TransactionData, serializer: S) -> Result<S::Ok, S::Error> {
+    tx_data.digest().serialize(serializer)
+}
+
 pub struct Arb {
     defi: Defi,
 }
 
 impl Arb {
-    pub async fn new(http_url: &str, simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>) -> Result<Self> {
-        let defi = Defi::new(http_url, simulator_pool).await?;
-        Ok(Self { defi })
+    pub async fn new(
+        http_url: &str,
+        simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
+        dry_run: bool,
+    ) -> Result<Self> {
+        let defi = Defi::new(http_url, simulator_pool, dry_run).await?;
+        Ok(Self::new_with_defi(defi))
+    }
+
+    /// Same as `new`, see `Defi::new_with_protocol_filter`.
+    pub async fn new_with_protocol_filter(
+        http_url: &str,
+        simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
+        dry_run: bool,
+        protocol_filter: ProtocolFilter,
+    ) -> Result<Self> {
+        let defi = Defi::new_with_protocol_filter(http_url, simulator_pool, dry_run, protocol_filter).await?;
+        Ok(Self::new_with_defi(defi))
+    }
+
+    /// Same as `new`, e.g. with a `Defi` of another `DexSearcher`, see `Defi::new_with_searcher`.
+    pub fn new_with_defi(defi: Defi) -> Self {
+        Self { defi }
+    }
+
+    /// Also trade through SUI-rooted cycles of up to `max_cycle_hops` hops, 0 to disable.
+    pub fn with_max_cycle_hops(mut self, max_cycle_hops: usize) -> Self {
+        self.defi = self.defi.with_max_cycle_hops(max_cycle_hops);
+        self
+    }
+
+    /// Only trade through the pools of the protocols `protocol_filter` allows.
+    pub fn with_protocol_filter(mut self, protocol_filter: ProtocolFilter) -> Self {
+        self.defi = self.defi.with_protocol_filter(protocol_filter);
+        self
+    }
+
+    /// See `Defi::with_max_pools_per_protocol`.
+    pub fn with_max_pools_per_protocol(mut self, max_pools_per_protocol: usize) -> Self {
+        self.defi = self.defi.with_max_pools_per_protocol(max_pools_per_protocol);
+        self
+    }
+
+    /// See `Defi::without_navi`.
+    pub fn with_navi(mut self, navi: bool) -> Self {
+        if !navi {
+            self.defi = self.defi.without_navi();
+        }
+        self
+    }
+
+    /// See `Defi::with_max_simulated_paths`.
+    pub fn with_max_simulated_paths(mut self, max_simulated_paths: usize) -> Self {
+        self.defi = self.defi.with_max_simulated_paths(max_simulated_paths);
+        self
+    }
+
+    pub async fn warm_up(&self, coin_types: &[String]) {
+        self.defi.warm_up(coin_types).await
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -122,6 +475,25 @@ impl Arb {
         sim_ctx: SimulateCtx, //表示模拟交易上下文，包含当前的epoch等信息
         use_gss: bool, //表示是否使用黄金分割搜索算法来优化交易参数
         source: Source, //表示交易的来源，是公开交易还是私有的
+    ) -> Result<ArbResult> {
+        let deadline = source.deadline();
+        self.find_opportunity_before(sender, coin_type, pool_id, gas_coins, sim_ctx, use_gss, source, deadline)
+            .await
+    }
+
+    /// Same as `find_opportunity`, with the search aborted with `DeadlineExceeded` once `deadline` has passed
+    /// instead of the deadline of `source`, e.g. the max age of a private tx.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn find_opportunity_before(
+        &self,
+        sender: SuiAddress,
+        coin_type: &str,
+        pool_id: Option<ObjectID>,
+        gas_coins: Vec<ObjectRef>,
+        sim_ctx: SimulateCtx,
+        use_gss: bool,
+        source: Source,
+        deadline: Option<u64>,
     ) -> Result<ArbResult> {
         let gas_price = sim_ctx.epoch.gas_price;
 
@@ -135,6 +507,7 @@ impl Arb {
                     pool_id,           // 可选资金池ID
                     gas_coins.clone(), // Gas代币引用，用于支付gas的代币
                     sim_ctx,           // 模拟上下文，包含epoch等区块链状态
+                    deadline,          // shio截止时间，超时则放弃
                 )
                 .await?,
             );
@@ -171,6 +544,10 @@ impl Arb {
             (max_trial_res, timer.elapsed())
         };
 
+        // no need to refine the grid result if the bid can no longer be submitted, and the trials that were
+        // aborted by the deadline are not a lack of opportunity
+        DeadlineExceeded::check(deadline)?;
+
         //这段代码是网格搜索算法的最后一道验证，确保只有真正能盈利的交易参数才会被采用。
         ensure!(
             max_trial_res.profit > 0,
@@ -205,6 +582,12 @@ impl Arb {
             cache_misses
         );
 
+        // only the chosen trial is parsed into hop fills
+        max_trial_res.hop_fills = self
+            .defi
+            .hop_fills(&max_trial_res.trade_path, &max_trial_res.events)
+            .await;
+
         let TrialResult {
             amount_in, //参与套利交易的输入金额
             trade_path, //表示套利交易的路径
@@ -221,10 +604,21 @@ impl Arb {
         //设置投标金额
         source = source.with_bid_amount(*profit / 10 * 9);
 
+        // the pools may have been paused since the paths were searched, `PoolPaused` then
+        let trade_path = self.defi.refresh_path(trade_path).await?;
+
         //构建交易数据
         let tx_data = self
             .defi
-            .build_final_tx_data(sender, *amount_in, trade_path, gas_coins, gas_price, source)
+            .build_final_tx_data(
+                sender,
+                *amount_in,
+                &trade_path,
+                gas_coins,
+                gas_price,
+                max_trial_res.gas_budget,
+                source,
+            )
             .await?;
 
         Ok(ArbResult {
@@ -246,8 +640,18 @@ pub struct TrialCtx {
     pool_id: Option<ObjectID>,
     buy_paths: Vec<Path>,
     sell_paths: Vec<Path>,
+    circular_paths: Vec<Path>,
     gas_coins: Vec<ObjectRef>,
     sim_ctx: SimulateCtx,
+    deadline: Option<u64>,
+    // what the trials did in each pool, counted by the pool quarantine once they are all over
+    pool_trials: PoolTrials,
+}
+
+impl Drop for TrialCtx {
+    fn drop(&mut self) {
+        self.defi.record_pool_trials(&self.pool_trials);
+    }
 }
 
 impl TrialCtx {
@@ -258,6 +662,7 @@ impl TrialCtx {
         pool_id: Option<ObjectID>,
         gas_coins: Vec<ObjectRef>,
         sim_ctx: SimulateCtx,
+        deadline: Option<u64>,
     ) -> Result<Self> {
         let buy_paths = defi.find_buy_paths(coin_type).await?;
         ensure!(!buy_paths.is_empty(), "no buy paths found for {}", coin_type);
@@ -265,11 +670,24 @@ impl TrialCtx {
         let sell_paths = defi.find_sell_paths(coin_type).await?;
         ensure!(!sell_paths.is_empty(), "no sell paths found for {}", coin_type);
 
+        // SUI-rooted cycles through the coin, e.g. SUI -> coin -> B -> SUI, that don't split into
+        // a buy path and a sell path around the coin
+        let max_cycle_hops = defi.max_cycle_hops();
+        let circular_paths = if max_cycle_hops > 0 && !coin::is_native_coin(coin_type) {
+            defi.find_circular_paths(SUI_COIN_TYPE, max_cycle_hops)
+                .await?
+                .into_iter()
+                .filter(|p| p.contains_coin(coin_type) && (pool_id.is_none() || p.contains_pool(pool_id)))
+                .collect_vec()
+        } else {
+            vec![]
+        };
+
         if pool_id.is_some() {
             let buy_paths_contain_pool = buy_paths.iter().any(|p| p.contains_pool(pool_id));
             let sell_paths_contain_pool = sell_paths.iter().any(|p| p.contains_pool(pool_id));
             ensure!(
-                buy_paths_contain_pool || sell_paths_contain_pool,
+                buy_paths_contain_pool || sell_paths_contain_pool || !circular_paths.is_empty(),
                 "no paths found for the fluctuating pool: {:?}",
                 pool_id
             );
@@ -282,8 +700,11 @@ impl TrialCtx {
             pool_id,
             buy_paths,
             sell_paths,
+            circular_paths,
             gas_coins,
             sim_ctx,
+            deadline,
+            pool_trials: PoolTrials::default(),
         })
     }
 
@@ -309,29 +730,40 @@ impl TrialCtx {
                 TradeType::Swap,
                 &self.gas_coins,
                 &self.sim_ctx,
+                self.deadline,
+                self.pool_id,
+                &self.pool_trials,
             )
-            .await?;
+            .await;
         let buy_elapsed = timer.elapsed();
 
         let timer = Instant::now();
-        // append sell paths to the best buy path
-        let best_buy_path = best_buy_res.path;
-        let buy_path_contains_pool = best_buy_path.contains_pool(self.pool_id);
-        let trade_paths = self
-            .sell_paths
-            .iter()
-            .filter_map(|p| {
-                // - buy_path and sell_path should not have common pools
-                // - either buy_path or sell_path should contain the swapped_pool
-                if best_buy_path.is_disjoint(p) && (buy_path_contains_pool || p.contains_pool(self.pool_id)) {
-                    let mut path = best_buy_path.clone();
-                    path.path.extend(p.path.clone());
-                    Some(path)
-                } else {
-                    None
-                }
-            })
-            .collect_vec();
+        let mut trade_paths = match best_buy_res {
+            // append sell paths to the best buy path
+            Ok(best_buy_res) => {
+                let best_buy_path = best_buy_res.path;
+                let buy_path_contains_pool = best_buy_path.contains_pool(self.pool_id);
+                self.sell_paths
+                    .iter()
+                    .filter_map(|p| {
+                        // - buy_path and sell_path should not have common pools
+                        // - either buy_path or sell_path should contain the swapped_pool
+                        if best_buy_path.is_disjoint(p) && (buy_path_contains_pool || p.contains_pool(self.pool_id)) {
+                            let mut path = best_buy_path.clone();
+                            path.path.extend(p.path.clone());
+                            Some(path)
+                        } else {
+                            None
+                        }
+                    })
+                    .collect_vec()
+            }
+            // the cycles don't depend on the buy paths
+            Err(_) if !self.circular_paths.is_empty() => vec![],
+            Err(error) => return Err(error),
+        };
+        trade_paths.extend(self.circular_paths.iter().cloned());
+        let trade_paths = self.defi.flashloan_paths(trade_paths);
         ensure!(
             !trade_paths.is_empty(),
             "no trade paths found for coin {}, pool_id: {:?}",
@@ -349,6 +781,9 @@ impl TrialCtx {
                 TradeType::Flashloan,
                 &self.gas_coins,
                 &self.sim_ctx,
+                self.deadline,
+                self.pool_id,
+                &self.pool_trials,
             )
             .await?;
 
@@ -364,45 +799,82 @@ impl TrialCtx {
             &self.coin_type,
             amount_in,
             profit as u64,
+            best_trade_res.gas_cost,
             best_trade_res.path,
             best_trade_res.cache_misses,
-        );
+            best_trade_res.events,
+        )
+        .with_gas_budget(best_trade_res.gas_budget);
 
         Ok(result)
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct TrialResult {
     pub coin_type: String,  //表示套利交易中使用的代币类型
     pub amount_in: u64, //参与套利交易的输入金额
     pub profit: u64, //表示套利交易的利润
+    pub gas_cost: i64, //表示套利交易的gas成本
+    pub gas_budget: u64, //表示最终交易的gas预算, 0表示使用GAS_BUDGET
     pub trade_path: Path, //表示套利交易的路径
     pub cache_misses: u64, //表示缓存未命中的次数
+    pub hop_fills: Vec<HopFill>, //表示每一跳的实际成交量, 只为最终选中的试算解析
+    #[serde(skip)]
+    pub events: Vec<SuiEvent>, //表示试算模拟产生的事件
+}
+
+/// The greater, the better: a larger profit, then a lower gas cost, then the path with fewer hops
+/// and smaller pool ids.
+impl Ord for TrialResult {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.profit
+            .cmp(&other.profit)
+            .then_with(|| other.gas_cost.cmp(&self.gas_cost))
+            .then_with(|| self.trade_path.tie_break_key().cmp(&other.trade_path.tie_break_key()))
+    }
 }
 
 impl PartialOrd for TrialResult {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.profit.partial_cmp(&other.profit)
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
 impl PartialEq for TrialResult {
     fn eq(&self, other: &Self) -> bool {
-        self.profit == other.profit
+        self.cmp(other) == Ordering::Equal
     }
 }
 
+impl Eq for TrialResult {}
+
 impl TrialResult {
-    pub fn new(coin_type: &str, amount_in: u64, profit: u64, trade_path: Path, cache_misses: u64) -> Self {
+    pub fn new(
+        coin_type: &str,
+        amount_in: u64,
+        profit: u64,
+        gas_cost: i64,
+        trade_path: Path,
+        cache_misses: u64,
+        events: Vec<SuiEvent>,
+    ) -> Self {
         Self {
             coin_type: coin_type.to_string(),
             amount_in,
             profit,
+            gas_cost,
             trade_path,
             cache_misses,
+            events,
+            ..Default::default()
         }
     }
+
+    pub fn with_gas_budget(mut self, gas_budget: u64) -> Self {
+        self.gas_budget = gas_budget;
+        self
+    }
 }
 
 impl fmt::Display for TrialResult {
@@ -427,22 +899,223 @@ impl SearchGoal<TrialCtx, u64, TrialResult> for TrialGoal {
 
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
+    use std::{
+        str::FromStr,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
 
     use simulator::{DBSimulator, HttpSimulator, Simulator};
-    use sui_types::base_types::SuiAddress;
+    use sui_json_rpc_types::BalanceChange;
+    use sui_types::{
+        base_types::SuiAddress,
+        digests::TransactionDigest,
+        object::{Object, Owner},
+        programmable_transaction_builder::ProgrammableTransactionBuilder,
+        transaction::{Command, ObjectArg},
+        TypeTag, SUI_CLOCK_OBJECT_ID,
+    };
 
     use super::*;
-    use crate::config::tests::{TEST_ATTACKER, TEST_HTTP_URL};
+    use crate::{
+        config::tests::{TEST_ATTACKER, TEST_HTTP_URL},
+        defi::stub_path,
+    };
+
+    #[test]
+    fn test_trial_result_tie_break() {
+        let (pool1, pool2, pool3) = (
+            ObjectID::from_single_byte(1),
+            ObjectID::from_single_byte(2),
+            ObjectID::from_single_byte(3),
+        );
+        let trial_result = |profit, gas_cost, pool_ids: &[ObjectID]| {
+            TrialResult::new("0x2::sui::SUI", 1_000, profit, gas_cost, stub_path(pool_ids), 0, vec![])
+        };
+
+        // profit first
+        assert!(trial_result(101, 10, &[pool1, pool2, pool3]) > trial_result(100, 1, &[pool1]));
+        // then the lower gas cost
+        assert!(trial_result(100, 1, &[pool1, pool2, pool3]) > trial_result(100, 10, &[pool1]));
+        // then fewer hops
+        assert!(trial_result(100, 1, &[pool3, pool2]) > trial_result(100, 1, &[pool1, pool2, pool3]));
+        // then the smaller pool ids
+        assert!(trial_result(100, 1, &[pool1, pool3]) > trial_result(100, 1, &[pool2, pool3]));
+
+        assert_eq!(trial_result(100, 1, &[pool1]), trial_result(100, 1, &[pool1]));
+        assert_ne!(trial_result(100, 1, &[pool1]), trial_result(100, 1, &[pool2]));
+    }
+
+    #[test]
+    fn test_parse_coin_file() {
+        let content = "
+# nightly scan
+0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN
+
+0xce7ff77a83ea0cb6fd39bd8748e2ec89a3f41e8efdc3f4eb123e0ca37b184db2::buck::BUCK, 0x1
+";
+        let coins = parse_coin_file(content).unwrap();
+        assert_eq!(
+            coins,
+            vec![
+                (
+                    "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN".to_string(),
+                    None
+                ),
+                (
+                    "0xce7ff77a83ea0cb6fd39bd8748e2ec89a3f41e8efdc3f4eb123e0ca37b184db2::buck::BUCK".to_string(),
+                    Some(ObjectID::from_single_byte(1))
+                ),
+            ]
+        );
+
+        assert!(parse_coin_file("0x2::sui::SUI,not_an_id").is_err());
+        assert!(parse_coin_file(",0x1").is_err());
+    }
+
+    #[test]
+    fn test_parse_coin_file_generic_coin_type() {
+        let content = "
+0x1::lp::LP<0x2::sui::SUI, 0x3::usdc::USDC>
+0x1::lp::LP<0x2::sui::SUI, 0x3::usdc::USDC>, 0x5
+";
+        let coins = parse_coin_file(content).unwrap();
+        assert_eq!(
+            coins,
+            vec![
+                ("0x1::lp::LP<0x2::sui::SUI, 0x3::usdc::USDC>".to_string(), None),
+                (
+                    "0x1::lp::LP<0x2::sui::SUI, 0x3::usdc::USDC>".to_string(),
+                    Some(ObjectID::from_single_byte(5))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_arb_result_json() {
+        let sender = SuiAddress::random_for_testing_only();
+        let tx_data = TransactionData::new_transfer_sui(
+            sender,
+            sender,
+            Some(1),
+            sui_types::base_types::random_object_ref(),
+            1_000_000,
+            750,
+        );
+        let tx_digest = tx_data.digest();
+        let coin_type = "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN";
+
+        let arb_result = ArbResult {
+            create_trial_ctx_duration: Duration::from_millis(12),
+            grid_search_duration: Duration::from_millis(345),
+            gss_duration: None,
+            best_trial_result: TrialResult::new(coin_type, 1_000_000_000, 42, 0, Path::default(), 3, vec![]),
+            cache_misses: 3,
+            source: Source::Public,
+            tx_data,
+        };
+
+        let json = serde_json::to_value(&arb_result).unwrap();
+        assert_eq!(json["create_trial_ctx_ms"], 12);
+        assert_eq!(json["grid_search_ms"], 345);
+        assert!(json["gss_ms"].is_null());
+        assert_eq!(json["cache_misses"], 3);
+        assert_eq!(json["source"], "Public");
+        assert_eq!(json["tx_digest"], tx_digest.to_string());
+
+        let trial_res = &json["best_trial_result"];
+        assert_eq!(trial_res["coin_type"], coin_type);
+        assert_eq!(trial_res["amount_in"], 1_000_000_000u64);
+        assert_eq!(trial_res["profit"], 42);
+        assert_eq!(trial_res["trade_path"], serde_json::json!([]));
+        assert_eq!(trial_res["hop_fills"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_run_batch_concurrency_bound() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let coins = (0..20).map(|i| (format!("0x{i}::coin::COIN"), None)).collect_vec();
+
+        // a stub of Arb::find_opportunity
+        let results = run_batch(coins, 3, |coin_type, _| {
+            let in_flight = in_flight.clone();
+            let max_in_flight = max_in_flight.clone();
+            async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, Ordering::SeqCst);
+                for _ in 0..10 {
+                    tokio::task::yield_now().await;
+                }
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                ensure!(coin_type != "0x7::coin::COIN", "no opportunity");
+                Ok(coin_type)
+            }
+        })
+        .await;
+
+        assert_eq!(results.len(), 20);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 3);
+        assert_eq!(results.iter().filter(|res| res.result.is_err()).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_batch_keeps_running_past_a_panic() {
+        let coins = (0..10).map(|i| (format!("0x{i}::coin::COIN"), None)).collect_vec();
+
+        let results = run_batch(coins, 2, |coin_type, _| async move {
+            if coin_type == "0x1::coin::COIN" {
+                panic!("invariant violation");
+            }
+            Ok(coin_type)
+        })
+        .await;
+
+        assert_eq!(results.len(), 10);
+        let failed = results.iter().filter(|res| res.result.is_err()).collect_vec();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].coin_type, "0x1::coin::COIN");
+        let error = failed[0].result.as_ref().unwrap_err().to_string();
+        assert!(error.contains("invariant violation"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_find_opportunity_of_stale_private_tx() {
+        let simulator_pool = ObjectPool::new_async(1, || async {
+            Box::new(HttpSimulator::new(TEST_HTTP_URL, &None).await) as Box<dyn Simulator>
+        });
+        let sui = SuiClientBuilder::default().build(TEST_HTTP_URL).await.unwrap();
+        let sim_ctx = SimulateCtx::new(get_latest_epoch(&sui).await.unwrap(), vec![]);
+        let arb = Arb::new(TEST_HTTP_URL, Arc::new(simulator_pool), false).await.unwrap();
+
+        let received_at_ms = utils::current_time_ms() - 1_000;
+        let source = Source::Private {
+            opp_tx_digest: TransactionDigest::random(),
+            received_at_ms,
+        };
+        let error = arb
+            .find_opportunity_before(
+                SuiAddress::from_str(TEST_ATTACKER).unwrap(),
+                "0xce7ff77a83ea0cb6fd39bd8748e2ec89a3f41e8efdc3f4eb123e0ca37b184db2::buck::BUCK",
+                None,
+                vec![],
+                sim_ctx,
+                false,
+                source,
+                Some(received_at_ms + 500),
+            )
+            .await
+            .unwrap_err();
+        assert!(error.is::<DeadlineExceeded>(), "{error:#}");
+    }
 
     #[tokio::test]
     async fn test_find_best_trade_path() {
         mev_logger::init_console_logger_with_directives(None, &["arb=debug"]);
 
-        let simulator_pool = ObjectPool::new(1, move || {
-            tokio::runtime::Runtime::new()
-                .unwrap()
-                .block_on(async { Box::new(HttpSimulator::new(&TEST_HTTP_URL, &None).await) as Box<dyn Simulator> })
+        let simulator_pool = ObjectPool::new_async(1, || async {
+            Box::new(HttpSimulator::new(&TEST_HTTP_URL, &None).await) as Box<dyn Simulator>
         });
 
         let start = Instant::now();
@@ -452,8 +1125,10 @@ mod tests {
         let epoch = get_latest_epoch(&sui).await.unwrap();
         let sim_ctx = SimulateCtx::new(epoch, vec![]);
 
-        let gas_coins = coin::get_gas_coin_refs(&sui, sender, None).await.unwrap();
-        let arb = Arb::new(TEST_HTTP_URL, Arc::new(simulator_pool)).await.unwrap();
+        let gas_coins = coin::get_gas_coin_refs(&sui, sender, &GasCoinFilter::default())
+            .await
+            .unwrap();
+        let arb = Arb::new(TEST_HTTP_URL, Arc::new(simulator_pool), false).await.unwrap();
         let coin_type = "0xce7ff77a83ea0cb6fd39bd8748e2ec89a3f41e8efdc3f4eb123e0ca37b184db2::buck::BUCK";
 
         let arb_res = arb
@@ -483,4 +1158,96 @@ mod tests {
         let db_res = db_sim.simulate(tx_data, sim_ctx).await.unwrap();
         info!(?db_res, "🧀 DB simulation result");
     }
+
+    #[tokio::test]
+    async fn test_http_and_db_simulators_agree_on_transfer() {
+        let sender = SuiAddress::from_str(TEST_ATTACKER).unwrap();
+        let recipient = SuiAddress::random_for_testing_only();
+        let sui = SuiClientBuilder::default().build(TEST_HTTP_URL).await.unwrap();
+        let epoch = get_latest_epoch(&sui).await.unwrap();
+        let sim_ctx = SimulateCtx::new(epoch, vec![]);
+
+        // a transfer out of the mock gas coin
+        let mut builder = ProgrammableTransactionBuilder::new();
+        builder.pay_sui(vec![recipient], vec![1_000]).unwrap();
+        let tx_data = TransactionData::new_programmable(sender, vec![], builder.finish(), 10_000_000, epoch.gas_price);
+
+        let http_sim = HttpSimulator::new(TEST_HTTP_URL, &None).await;
+        let db_sim = DBSimulator::new_default_slow().await;
+        let sort = |mut balance_changes: Vec<BalanceChange>| {
+            balance_changes.sort_by_key(|bc| (bc.owner.to_string(), bc.coin_type.to_string()));
+            balance_changes
+        };
+
+        let http_res = http_sim.simulate(tx_data.clone(), sim_ctx.clone()).await.unwrap();
+        let db_res = db_sim.simulate(tx_data, sim_ctx).await.unwrap();
+        http_res.check_status().unwrap();
+        db_res.check_status().unwrap();
+        assert_eq!(sort(http_res.balance_changes), sort(db_res.balance_changes));
+    }
+
+    #[tokio::test]
+    async fn test_db_simulator_spends_mocked_non_sui_coin() {
+        let usdc = "0xdba34672e30cb065b1f93e3ab55318768fd6fef66c15942c9f7cb846e2f900e7::usdc::USDC";
+        let sender = SuiAddress::from_str(TEST_ATTACKER).unwrap();
+        let recipient = SuiAddress::random_for_testing_only();
+        let sui = SuiClientBuilder::default().build(TEST_HTTP_URL).await.unwrap();
+        let epoch = get_latest_epoch(&sui).await.unwrap();
+
+        // spends a mocked coin of 1000 USDC the way a swap trial spends its coin_in
+        let mocked_usdc = coin::mocked_coin(sender, usdc, 1_000).unwrap();
+        let mut builder = ProgrammableTransactionBuilder::new();
+        let coin = builder
+            .obj(ObjectArg::ImmOrOwnedObject(mocked_usdc.compute_object_reference()))
+            .unwrap();
+        let amount = builder.pure(1_000u64).unwrap();
+        let coin_out = builder.command(Command::SplitCoins(coin, vec![amount]));
+        builder.transfer_arg(recipient, coin_out);
+        let tx_data = TransactionData::new_programmable(sender, vec![], builder.finish(), 10_000_000, epoch.gas_price);
+
+        let mut sim_ctx = SimulateCtx::new(epoch, vec![]);
+        sim_ctx.with_borrowed_coin((mocked_usdc, 1_000));
+        let db_sim = DBSimulator::new_default_slow().await;
+        let res = db_sim.simulate(tx_data, sim_ctx).await.unwrap();
+        res.check_status().unwrap();
+
+        let usdc = TypeTag::from_str(usdc).unwrap();
+        let change = |owner: SuiAddress, coin_type: &TypeTag| {
+            res.balance_changes
+                .iter()
+                .find(|bc| bc.owner == Owner::AddressOwner(owner) && bc.coin_type == *coin_type)
+                .map(|bc| bc.amount)
+        };
+        assert_eq!(change(recipient, &usdc), Some(1_000));
+        assert_eq!(change(sender, &usdc), Some(-1_000));
+    }
+    #[tokio::test]
+    async fn test_http_and_db_simulators_agree_on_objects() {
+        // the Aftermath pool registry has dynamic fields
+        let registry =
+            ObjectID::from_hex_literal("0xfcc774493db2c45c79f688f88d28023a3e7d98e4ee9f48bbf5c7990f651577ae").unwrap();
+        let missing = ObjectID::random();
+        let ids = [SUI_CLOCK_OBJECT_ID, missing, registry];
+
+        let http_sim = HttpSimulator::new(TEST_HTTP_URL, &None).await;
+        let db_sim = DBSimulator::new_default_slow().await;
+
+        let object_ids =
+            |objects: Vec<Option<Object>>| objects.iter().map(|o| o.as_ref().map(|o| o.id())).collect::<Vec<_>>();
+        let expected = vec![Some(SUI_CLOCK_OBJECT_ID), None, Some(registry)];
+        assert_eq!(object_ids(http_sim.multi_get_objects(&ids).await), expected);
+        assert_eq!(object_ids(db_sim.multi_get_objects(&ids).await), expected);
+
+        // the db has no index of dynamic fields
+        assert!(db_sim.get_dynamic_children(&registry).await.is_err());
+        let sui = SuiClientBuilder::default().build(TEST_HTTP_URL).await.unwrap();
+        let db_sim = db_sim.with_dynamic_fields_client(sui);
+
+        let mut http_children = http_sim.get_dynamic_children(&registry).await.unwrap();
+        let mut db_children = db_sim.get_dynamic_children(&registry).await.unwrap();
+        http_children.sort();
+        db_children.sort();
+        assert!(!http_children.is_empty());
+        assert_eq!(http_children, db_children);
+    }
 }
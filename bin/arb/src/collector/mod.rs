@@ -1,32 +1,38 @@
+mod relay;
+
 use burberry::{async_trait, Collector, CollectorStream};
+use dex_indexer::types::SWAP_EVENT_TYPE_PREFIXES;
 use eyre::Result;
-use fastcrypto::encoding::{Base64, Encoding};
-use futures::stream::StreamExt;
 use interprocess::local_socket::{
     tokio::{prelude::*, Stream},
     GenericNamespaced,
 };
-use serde::Deserialize;
+pub use relay::{RelayCollector, RelayFilter};
 use sui_json_rpc_types::{SuiEvent, SuiTransactionBlockEffects};
-use sui_types::{effects::TransactionEffects, transaction::TransactionData};
-use tokio::{io::AsyncReadExt, pin, time};
+use sui_types::effects::TransactionEffects;
+use tokio::{io::AsyncReadExt, time};
 use tracing::{debug, error};
 
 use crate::types::Event;
 
 /*
     是Sui MEV项目中负责交易收集的核心模块，主要实现两种交易收集器
-    1. PrivateTxCollector: 私有交易收集器，用于收集来自Sui节点的私有交易
+    1. RelayCollector: 私有交易收集器，用于收集来自Sui节点的私有交易
     2. PublicTxCollector: 公有交易收集器，用于收集来自Sui节点的公有交易
     该模块为MEV套利系统提供实时交易数据源，是识别套利机会的基础组件。
 */
 pub struct PublicTxCollector {
     path: String,
+    // drop the txs without any swap event, most txs don't touch a DEX at all
+    filter_swap_events: bool,
 }
 
 impl PublicTxCollector {
-    pub fn new(path: &str) -> Self {
-        Self { path: path.to_string() }
+    pub fn new(path: &str, filter_swap_events: bool) -> Self {
+        Self {
+            path: path.to_string(),
+            filter_swap_events,
+        }
     }
 
     async fn connect(&self) -> Result<Stream> {
@@ -99,6 +105,10 @@ impl Collector<Event> for PublicTxCollector {
                             }
                         };
 
+                        if self.filter_swap_events && !has_swap_event(&events) {
+                            continue;
+                        }
+
                         if let Ok(tx_effects) = SuiTransactionBlockEffects::try_from(tx_effects) {
                             yield Event::PublicTx(tx_effects, events);
                         }
@@ -115,70 +125,36 @@ impl Collector<Event> for PublicTxCollector {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
-pub struct TxMessage {
-    tx_bytes: String,
+fn has_swap_event(events: &[SuiEvent]) -> bool {
+    events.iter().any(|event| is_swap_event_type(&event.type_.to_string()))
 }
 
-impl TryFrom<TxMessage> for TransactionData {
-    type Error = eyre::Error;
-
-    fn try_from(tx_message: TxMessage) -> Result<Self> {
-        let tx_bytes = Base64::decode(&tx_message.tx_bytes)?;
-        let tx_data: TransactionData = bcs::from_bytes(&tx_bytes)?;
-        Ok(tx_data)
-    }
+fn is_swap_event_type(event_type: &str) -> bool {
+    SWAP_EVENT_TYPE_PREFIXES
+        .iter()
+        .any(|prefix| event_type.starts_with(prefix))
 }
 
-pub struct PrivateTxCollector {
-    ws_url: String,
-}
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
 
-impl PrivateTxCollector {
-    pub fn new(ws_url: &str) -> Self {
-        Self {
-            ws_url: ws_url.to_string(),
-        }
-    }
-}
+    use move_core_types::language_storage::StructTag;
 
-#[async_trait]
-impl Collector<Event> for PrivateTxCollector {
-    fn name(&self) -> &str {
-        "PrivateTxCollector"
-    }
-
-    async fn get_event_stream(&self) -> Result<CollectorStream<'_, Event>> {
-        let (ws_stream, _) = tokio_tungstenite::connect_async(&self.ws_url)
-            .await
-            .expect("Failed to connect to relay server");
-
-        let (_, read) = ws_stream.split();
+    use super::*;
 
-        let stream = async_stream::stream! {
-            pin!(read);
-            while let Some(message) = read.next().await {
-                let message = match message {
-                    Ok(msg) => msg,
-                    Err(e) => {
-                        error!("Relay websocket error: {:?}", e);
-                        continue;
-                    }
-                };
-
-                let tx_message: TxMessage = serde_json::from_str(message.to_text().unwrap()).unwrap();
-                let tx_data = match TransactionData::try_from(tx_message) {
-                    Ok(tx_data) => tx_data,
-                    Err(e) => {
-                        error!("Invalid tx_message: {:?}", e);
-                        continue;
-                    }
-                };
+    const CETUS_SWAP_EVENT: &str =
+        "0x1eabed72c53feb3805120a081dc15963c204dc8d091542592abaf7a35689b2fb::pool::SwapEvent";
+    const STAKING_REQUEST_EVENT: &str = "0x3::validator::StakingRequestEvent";
 
-                yield Event::PrivateTx(tx_data);
-            }
-        };
+    #[test]
+    fn test_swap_event_filter() {
+        // the filter sees event types formatted from a `StructTag`
+        let swap = StructTag::from_str(CETUS_SWAP_EVENT).unwrap().to_string();
+        let unrelated = StructTag::from_str(STAKING_REQUEST_EVENT).unwrap().to_string();
 
-        Ok(Box::pin(stream))
+        assert!(is_swap_event_type(&swap));
+        assert!(!is_swap_event_type(&unrelated));
+        assert!(!has_swap_event(&[]));
     }
 }
@@ -0,0 +1,479 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use burberry::{async_trait, Collector, CollectorStream};
+use eyre::Result;
+use fastcrypto::encoding::{Base64, Encoding};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use shio::Backoff;
+use sui_types::{
+    base_types::{ObjectID, SuiAddress},
+    transaction::TransactionData,
+};
+use tokio::{net::TcpStream, time};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{error, info, warn};
+
+use crate::types::Event;
+
+// the replay of a reconnect starts this long before the last received transaction, for the clock skew with
+// the relay. The transactions received twice are dropped by their `seq`.
+const REPLAY_MARGIN_MS: u64 = 1_000;
+
+/// The transactions the relay sends, an empty list matches all.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RelayFilter {
+    pub move_packages: Vec<ObjectID>,
+    pub senders: Vec<SuiAddress>,
+}
+
+impl RelayFilter {
+    pub fn is_empty(&self) -> bool {
+        self.move_packages.is_empty() && self.senders.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TxMessage {
+    tx_bytes: String,
+    // from the relays that number their transactions
+    #[serde(default)]
+    seq: Option<u64>,
+    #[serde(default)]
+    replayed: bool,
+}
+
+impl TryFrom<TxMessage> for TransactionData {
+    type Error = eyre::Error;
+
+    fn try_from(tx_message: TxMessage) -> Result<Self> {
+        let tx_bytes = Base64::decode(&tx_message.tx_bytes)?;
+        let tx_data: TransactionData = bcs::from_bytes(&tx_bytes)?;
+        Ok(tx_data)
+    }
+}
+
+// the frames of the relay, the control ones have a `type`
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RelayFrame {
+    Control(ControlFrame),
+    Tx(TxMessage),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ControlFrame {
+    // the `session` of the relays that number their transactions changes when the relay restarts
+    Hello { version: String, session: Option<u64> },
+    Lagged { skipped: u64 },
+    Subscribed,
+    Replayed { count: usize },
+    Error { reason: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SeqCheck {
+    Next,
+    // received before, sent again by a replay
+    Duplicate,
+    // `missed` transactions in between were never received
+    Gap { missed: u64 },
+    // a live transaction numbered before the last one, the relay restarted
+    Restarted,
+}
+
+// the `seq` of the last transaction received, over the reconnects to the same relay session
+#[derive(Debug, Default)]
+struct SeqTracker {
+    last_seq: Option<u64>,
+    session: Option<u64>,
+}
+
+impl SeqTracker {
+    // on the hello of a connection. The numbering restarts with a new session, a relay that doesn't send
+    // one may have restarted as well.
+    fn start_session(&mut self, session: Option<u64>) {
+        if session.is_none() || session != self.session {
+            self.last_seq = None;
+        }
+        self.session = session;
+    }
+
+    fn check(&mut self, seq: u64, replayed: bool) -> SeqCheck {
+        let Some(last_seq) = self.last_seq else {
+            self.last_seq = Some(seq);
+            return SeqCheck::Next;
+        };
+
+        if seq <= last_seq {
+            if replayed {
+                return SeqCheck::Duplicate;
+            }
+            self.last_seq = Some(seq);
+            return SeqCheck::Restarted;
+        }
+
+        self.last_seq = Some(seq);
+        match seq - last_seq - 1 {
+            0 => SeqCheck::Next,
+            missed => SeqCheck::Gap { missed },
+        }
+    }
+}
+
+/// Collects the private transactions of the relay WebSocket feed, see `bin/relay`.
+///
+/// Reconnects with `backoff` whenever the connection drops, the delay grows until a connection delivers a
+/// frame again. The filter, if any, is sent on every connection,
+/// followed by a replay request of what was sent while disconnected. The transactions are numbered by the
+/// relay, so the replayed ones already received are dropped, and the missed ones are logged. With a filter
+/// the numbers of the other transactions are missing, so only the relay's `lagged` frames tell about losses.
+pub struct RelayCollector {
+    ws_url: String,
+    filter: RelayFilter,
+    backoff: Backoff,
+}
+
+impl RelayCollector {
+    pub fn new(ws_url: &str) -> Self {
+        Self {
+            ws_url: ws_url.to_string(),
+            filter: RelayFilter::default(),
+            backoff: Backoff::default(),
+        }
+    }
+
+    pub fn with_filter(mut self, filter: RelayFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    async fn connect(&self, replay_since_ms: Option<u64>) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        let (mut ws_stream, _) = tokio_tungstenite::connect_async(&self.ws_url).await?;
+
+        // answered by `subscribed` and `replayed` frames, in order
+        if !self.filter.is_empty() {
+            let filter = json!({ "filter": self.filter });
+            ws_stream.send(Message::Text(filter.to_string())).await?;
+        }
+        if let Some(since_ms) = replay_since_ms {
+            let replay = json!({ "replay": { "since_ms": since_ms } });
+            ws_stream.send(Message::Text(replay.to_string())).await?;
+        }
+
+        Ok(ws_stream)
+    }
+}
+
+#[async_trait]
+impl Collector<Event> for RelayCollector {
+    fn name(&self) -> &str {
+        "RelayCollector"
+    }
+
+    async fn get_event_stream(&self) -> Result<CollectorStream<'_, Event>> {
+        let stream = async_stream::stream! {
+            let mut seqs = SeqTracker::default();
+            // when the last numbered transaction was received, nothing is replayed without one
+            let mut last_received_ms: Option<u64> = None;
+            let mut attempt = 0;
+
+            loop {
+                if attempt > 0 {
+                    time::sleep(self.backoff.delay(attempt - 1)).await;
+                }
+                let replay_since_ms = last_received_ms.map(|ms| ms.saturating_sub(REPLAY_MARGIN_MS));
+                let ws_stream = match self.connect(replay_since_ms).await {
+                    Ok(ws_stream) => ws_stream,
+                    Err(e) => {
+                        if self.backoff.max_retries.is_some_and(|max_retries| attempt >= max_retries) {
+                            error!(attempt, "Relay unreachable, giving up: {:?}", e);
+                            break;
+                        }
+                        warn!(attempt, "Connect to relay failed: {:?}", e);
+                        attempt += 1;
+                        continue;
+                    }
+                };
+
+                let (_, mut read) = ws_stream.split();
+                while let Some(message) = read.next().await {
+                    let text = match message {
+                        Ok(Message::Text(text)) => text,
+                        Ok(Message::Close(_)) => break,
+                        Ok(_) => continue,
+                        Err(e) => {
+                            warn!("Relay websocket error: {:?}", e);
+                            break;
+                        }
+                    };
+                    // the reaction time to a private tx is measured from here
+                    let received_at_ms = now_ms();
+                    // the relay is up, the backoff starts over
+                    attempt = 0;
+
+                    let tx_message = match serde_json::from_str(&text) {
+                        Ok(RelayFrame::Tx(tx_message)) => tx_message,
+                        Ok(RelayFrame::Control(control_frame)) => {
+                            if let ControlFrame::Hello { session, .. } = &control_frame {
+                                seqs.start_session(*session);
+                            }
+                            log_control_frame(control_frame);
+                            continue;
+                        }
+                        Err(e) => {
+                            error!("Invalid relay frame: {:?}", e);
+                            continue;
+                        }
+                    };
+
+                    if let Some(seq) = tx_message.seq {
+                        match seqs.check(seq, tx_message.replayed) {
+                            SeqCheck::Duplicate => continue,
+                            SeqCheck::Gap { missed } if self.filter.is_empty() => {
+                                warn!(seq, missed, "Relay sequence gap, private txs missed");
+                            }
+                            SeqCheck::Restarted => info!(seq, "Relay sequence restarted"),
+                            SeqCheck::Next | SeqCheck::Gap { .. } => {}
+                        }
+                        last_received_ms = Some(received_at_ms);
+                    }
+
+                    let tx_data = match TransactionData::try_from(tx_message) {
+                        Ok(tx_data) => tx_data,
+                        Err(e) => {
+                            error!("Invalid tx_message: {:?}", e);
+                            continue;
+                        }
+                    };
+
+                    yield Event::PrivateTx(tx_data, received_at_ms);
+                }
+
+                warn!(attempt, "Relay disconnected, reconnecting");
+                attempt += 1;
+            }
+        };
+
+        Ok(Box::pin(stream))
+    }
+}
+
+fn log_control_frame(control_frame: ControlFrame) {
+    match control_frame {
+        ControlFrame::Hello { version, session } => info!(%version, ?session, "Connected to relay"),
+        ControlFrame::Lagged { skipped } => warn!(skipped, "Relay skipped private txs, we fell behind"),
+        ControlFrame::Subscribed => info!("Relay filter set"),
+        ControlFrame::Replayed { count } => info!(count, "Relay replayed private txs"),
+        ControlFrame::Error { reason } => error!(%reason, "Relay rejected a request"),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::Value;
+    use sui_types::base_types::random_object_ref;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn new_tx_data() -> TransactionData {
+        let sender = SuiAddress::random_for_testing_only();
+        TransactionData::new_transfer_sui(sender, sender, None, random_object_ref(), 10_000_000, 750)
+    }
+
+    // as sent by the relay
+    fn tx_frame(tx_data: &TransactionData, seq: u64, replayed: bool) -> String {
+        let mut frame = json!({
+            "tx_bytes": Base64::encode(bcs::to_bytes(tx_data).unwrap()),
+            "signatures": [],
+            "kind": "tx",
+            "seq": seq,
+        });
+        if replayed {
+            frame["replayed"] = json!(true);
+        }
+        frame.to_string()
+    }
+
+    // accepts a connection, reads `requests` messages, then sends `frames` and closes. Returns the messages.
+    async fn mock_relay_conn(listener: &TcpListener, requests: usize, frames: Vec<String>) -> Vec<Value> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws_stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+
+        let mut received = vec![];
+        while received.len() < requests {
+            if let Message::Text(text) = ws_stream.next().await.unwrap().unwrap() {
+                received.push(serde_json::from_str(&text).unwrap());
+            }
+        }
+        for frame in frames {
+            ws_stream.send(Message::Text(frame)).await.unwrap();
+        }
+        ws_stream.close(None).await.unwrap();
+        received
+    }
+
+    #[test]
+    fn test_relay_frames() {
+        let frame: RelayFrame = serde_json::from_str(r#"{"tx_bytes":"AAE=","signatures":[]}"#).unwrap();
+        assert!(matches!(
+            frame,
+            RelayFrame::Tx(TxMessage { tx_bytes, seq: None, replayed: false }) if tx_bytes == "AAE="
+        ));
+
+        let frame: RelayFrame =
+            serde_json::from_str(r#"{"tx_bytes":"AAE=","signatures":[],"seq":7,"replayed":true}"#).unwrap();
+        assert!(matches!(
+            frame,
+            RelayFrame::Tx(TxMessage {
+                seq: Some(7),
+                replayed: true,
+                ..
+            })
+        ));
+
+        let frame: RelayFrame =
+            serde_json::from_str(r#"{"type":"hello","version":"main-1a2b3c4@2024-01-31"}"#).unwrap();
+        assert!(matches!(
+            frame,
+            RelayFrame::Control(ControlFrame::Hello { session: None, .. })
+        ));
+
+        let frame: RelayFrame =
+            serde_json::from_str(r#"{"type":"hello","version":"main-1a2b3c4@2024-01-31","session":1706659200000}"#)
+                .unwrap();
+        assert!(matches!(
+            frame,
+            RelayFrame::Control(ControlFrame::Hello {
+                session: Some(1706659200000),
+                ..
+            })
+        ));
+
+        let frame: RelayFrame = serde_json::from_str(r#"{"type":"lagged","skipped":6}"#).unwrap();
+        assert!(matches!(
+            frame,
+            RelayFrame::Control(ControlFrame::Lagged { skipped: 6 })
+        ));
+
+        let frame: RelayFrame = serde_json::from_str(r#"{"type":"replayed","count":2}"#).unwrap();
+        assert!(matches!(
+            frame,
+            RelayFrame::Control(ControlFrame::Replayed { count: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_seq_tracker() {
+        let mut seqs = SeqTracker::default();
+        assert_eq!(seqs.check(5, false), SeqCheck::Next);
+        assert_eq!(seqs.check(6, false), SeqCheck::Next);
+        assert_eq!(seqs.check(9, false), SeqCheck::Gap { missed: 2 });
+        assert_eq!(seqs.check(8, true), SeqCheck::Duplicate);
+        assert_eq!(seqs.check(9, true), SeqCheck::Duplicate);
+        assert_eq!(seqs.check(10, true), SeqCheck::Next);
+        assert_eq!(seqs.check(1, false), SeqCheck::Restarted);
+        assert_eq!(seqs.check(2, false), SeqCheck::Next);
+    }
+
+    #[test]
+    fn test_seq_tracker_sessions() {
+        let mut seqs = SeqTracker::default();
+        seqs.start_session(Some(1));
+        assert_eq!(seqs.check(5, false), SeqCheck::Next);
+
+        // a reconnect to the same relay replays what was already received
+        seqs.start_session(Some(1));
+        assert_eq!(seqs.check(5, true), SeqCheck::Duplicate);
+        assert_eq!(seqs.check(6, true), SeqCheck::Next);
+
+        // the relay restarted, its replayed transactions are numbered from the start again
+        seqs.start_session(Some(2));
+        assert_eq!(seqs.check(1, true), SeqCheck::Next);
+        assert_eq!(seqs.check(2, false), SeqCheck::Next);
+
+        // without a session every connection may be a new one
+        seqs.start_session(None);
+        assert_eq!(seqs.check(1, true), SeqCheck::Next);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_and_replay() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let txs = (0..4).map(|_| new_tx_data()).collect::<Vec<_>>();
+
+        let hello = json!({ "type": "hello", "version": "test", "session": 1 }).to_string();
+        let conn_frames = [
+            vec![hello.clone(), tx_frame(&txs[0], 1, false), tx_frame(&txs[1], 2, false)],
+            vec![
+                hello,
+                json!({ "type": "subscribed" }).to_string(),
+                tx_frame(&txs[1], 2, true),
+                tx_frame(&txs[2], 3, true),
+                json!({ "type": "replayed", "count": 2 }).to_string(),
+                tx_frame(&txs[3], 4, false),
+            ],
+        ];
+        let [first_frames, second_frames] = conn_frames;
+        let mock_relay = tokio::spawn(async move {
+            let first = mock_relay_conn(&listener, 1, first_frames).await;
+            let second = mock_relay_conn(&listener, 2, second_frames).await;
+            (first, second)
+        });
+
+        let package = ObjectID::random();
+        let backoff = Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+            max_retries: None,
+        };
+        let collector = RelayCollector::new(&format!("ws://{addr}"))
+            .with_filter(RelayFilter {
+                move_packages: vec![package],
+                senders: vec![],
+            })
+            .with_backoff(backoff);
+        let mut events = collector.get_event_stream().await.unwrap();
+
+        for tx in &txs {
+            let event = time::timeout(Duration::from_secs(5), events.next())
+                .await
+                .expect("no private tx")
+                .unwrap();
+            match event {
+                Event::PrivateTx(tx_data, received_at_ms) => {
+                    assert_eq!(&tx_data, tx);
+                    assert!(received_at_ms <= now_ms());
+                }
+                _ => panic!("expected a private tx"),
+            }
+        }
+
+        let (first, second) = mock_relay.await.unwrap();
+        assert_eq!(
+            first,
+            vec![json!({ "filter": { "move_packages": [package], "senders": [] } })]
+        );
+        assert_eq!(second[0], first[0]);
+        // since the first connection, minus the margin
+        assert!(second[1]["replay"]["since_ms"].as_u64().unwrap() <= now_ms() - REPLAY_MARGIN_MS);
+    }
+}
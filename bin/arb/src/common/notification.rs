@@ -1,16 +1,61 @@
-use std::{fmt::Write, time::Duration};
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
-use burberry::executor::telegram_message::{escape, Message, MessageBuilder};
+use burberry::executor::telegram_message::{escape, Message};
 use sui_types::digests::TransactionDigest;
-use utils::{coin, link, telegram};
+use utils::{
+    coin,
+    link::{self, markdown_link},
+    telegram::{self, AlertThread},
+};
 
 use crate::{arb::ArbResult, BUILD_VERSION};
 
-const SUI_ARB_BOT_TOKEN: &str = "";
-const GROUP_SUI_ARB: &str = "";
-const THREAD_LOW_PROFIT: &str = "";
-const THREAD_HIGH_PROFIT: &str = "";
+/// More notifications than this within `COMPACT_WINDOW` are sent in `NotificationMode::Compact`.
+const COMPACT_AFTER: usize = 5;
+const COMPACT_WINDOW: Duration = Duration::from_secs(60);
 
+static RECENT_NOTIFICATIONS: Mutex<RecentNotifications> = Mutex::new(RecentNotifications::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationMode {
+    Full,
+    /// a few lines per arb, while the arbs are frequent
+    Compact,
+}
+
+// the times of the notifications within the last `COMPACT_WINDOW`
+struct RecentNotifications(VecDeque<Instant>);
+
+impl RecentNotifications {
+    const fn new() -> Self {
+        Self(VecDeque::new())
+    }
+
+    // the mode of a notification sent at `now`
+    fn record(&mut self, now: Instant) -> NotificationMode {
+        while self
+            .0
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= COMPACT_WINDOW)
+        {
+            self.0.pop_front();
+        }
+        self.0.push_back(now);
+
+        if self.0.len() > COMPACT_AFTER {
+            NotificationMode::Compact
+        } else {
+            NotificationMode::Full
+        }
+    }
+}
+
+// No message if the alerts are disabled.
 pub fn new_tg_messages(
     digest: TransactionDigest,
     arb_digest: TransactionDigest,
@@ -18,39 +63,80 @@ pub fn new_tg_messages(
     elapsed: Duration,
     simulator_name: &str,
 ) -> Vec<Message> {
+    let Some(alerter) = telegram::alerter() else {
+        return vec![];
+    };
+
+    let mode = RECENT_NOTIFICATIONS.lock().unwrap().record(Instant::now());
+    let msg = arb_message(digest, arb_digest, res, elapsed, simulator_name, mode);
+    vec![alerter.config().message(AlertThread::Notification, msg)]
+}
+
+/// The notification of an arb in MarkdownV2: the opportunity tx `digest` and our `arb_digest`, the amounts, the
+/// bid and the path of `res`, and how long each phase of the search took.
+pub fn arb_message(
+    digest: TransactionDigest,
+    arb_digest: TransactionDigest,
+    res: &ArbResult,
+    elapsed: Duration,
+    simulator_name: &str,
+    mode: NotificationMode,
+) -> String {
     let mut msg = String::with_capacity(4096);
     let trade_res = &res.best_trial_result;
+    let explorer = link::explorer();
 
-    write!(
-        msg,
-        r#"*Profit*: `{profit}`
+    let profit = escape(&coin::format_sui_with_symbol(trade_res.profit));
+    let bid = escape(&coin::format_sui_with_symbol(res.source.bid_amount()));
+    let scan_link = markdown_link(&digest.to_string(), &explorer.tx_url(&digest));
+    let arb_scan_link = markdown_link(&arb_digest.to_string(), &explorer.tx_url(&arb_digest));
 
-"#,
-        profit = escape(&coin::format_sui_with_symbol(trade_res.profit)),
-    )
-    .unwrap();
+    if mode == NotificationMode::Compact {
+        let hops = trade_res
+            .trade_path
+            .path
+            .iter()
+            .map(|dex| hop_label(&dex.protocol().to_string(), &dex.coin_in_type(), &dex.coin_out_type()));
+        write!(
+            msg,
+            r#"*Profit*: `{profit}` *Bid*: `{bid}`
+{path}
+*Opp*: {scan_link}
+*Arb*: {arb_scan_link}"#,
+            path = escape(&hops.collect::<Vec<_>>().join(" | ")),
+        )
+        .unwrap();
+        return msg;
+    }
 
+    // the profit is net of gas
+    let amount_out = trade_res.amount_in as i128 + trade_res.profit as i128 + trade_res.gas_cost as i128;
     write!(
         msg,
-        r#"*Digest*: {scan_link}
+        r#"*Profit*: `{profit}`
+*Bid*: `{bid}`
+*Gas*: `{gas}`
+
+*Digest*: {scan_link}
 *Arb Digest*: {arb_scan_link}
 *Coin*: {coin}
 *Amount In*: {amount_in}
+*Amount Out*: {amount_out}
 *Path*:
 "#,
-        scan_link = link::tx(&digest, None),
-        arb_scan_link = link::tx(&arb_digest, None),
-        coin = link::coin(&trade_res.coin_type, None),
+        gas = escape(&format_signed_sui(trade_res.gas_cost as i128)),
+        coin = markdown_link(&trade_res.coin_type, &explorer.coin_url(&trade_res.coin_type)),
         amount_in = escape(&coin::format_sui_with_symbol(trade_res.amount_in)),
+        amount_out = escape(&format_signed_sui(amount_out)),
     )
     .unwrap();
 
     for (i, dex) in trade_res.trade_path.path.iter().enumerate() {
-        let tag = format!("{}({}-{})", dex.protocol(), dex.coin_in_type(), dex.coin_out_type());
+        let tag = hop_label(&dex.protocol().to_string(), &dex.coin_in_type(), &dex.coin_out_type());
         writeln!(
             msg,
             r#" {i}\. {dex}"#,
-            dex = link::object(dex.object_id(), Some(escape(&tag)))
+            dex = markdown_link(&tag, &explorer.object_url(&dex.object_id()))
         )
         .unwrap();
     }
@@ -68,41 +154,185 @@ pub fn new_tg_messages(
         escape(&format!("{:?}", res.grid_search_duration))
     )
     .unwrap();
-    writeln!(msg, "*Elapsed GSS*: {}", escape(&format!("{:?}", res.gss_duration))).unwrap();
+    let gss = res.gss_duration.map_or("-".to_string(), |gss| format!("{:?}", gss));
+    writeln!(msg, "*Elapsed GSS*: {}", escape(&gss)).unwrap();
     writeln!(msg, "*Cache Misses*: {}", res.cache_misses).unwrap();
-    writeln!(msg, "\n*{}*", simulator_name,).unwrap();
+    writeln!(msg, "\n*{}*", escape(simulator_name)).unwrap();
     writeln!(msg, "*{}*", escape(res.source.to_string().as_str())).unwrap();
-    write!(msg, "*Version*: `{version}`", version = BUILD_VERSION).unwrap();
+    write!(msg, "*Version*: `{version}`", version = escape(BUILD_VERSION)).unwrap();
+
+    msg
+}
 
-    println!("msg: {}", msg);
+// e.g. `Cetus:SUI→OCEAN`, unescaped
+fn hop_label(protocol: &str, coin_in: &str, coin_out: &str) -> String {
+    let short = |coin_type: &str| coin_type.rsplit("::").next().unwrap_or_default().to_string();
+    format!("{}:{}→{}", protocol, short(coin_in), short(coin_out))
+}
 
-    let thread_id = if trade_res.profit > 1000000000 {
-        "125670"
+fn format_signed_sui(value: i128) -> String {
+    let formatted = coin::format_sui_with_symbol(value.unsigned_abs().min(u64::MAX as u128) as u64);
+    if value < 0 {
+        format!("-{formatted}")
     } else {
-        telegram::CHAT_MONEY_PRINTER_THREAD_TEST
+        formatted
+    }
+}
+
+// A follow-up of `new_tg_messages` once a public tx is executed, in MarkdownV2.
+pub fn new_public_tx_message(digest: TransactionDigest, status: &str) -> String {
+    format!(
+        r#"*Public Tx*: {scan_link}
+*Status*: `{status}`"#,
+        scan_link = markdown_link(&digest.to_string(), &link::explorer().tx_url(&digest)),
+        status = escape(status),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use sui_sdk::SUI_COIN_TYPE;
+    use sui_types::{
+        base_types::{ObjectID, SuiAddress},
+        transaction::TransactionData,
     };
 
-    let msg1 = MessageBuilder::new()
-        .bot_token(telegram::R2D2_TELEGRAM_BOT_TOKEN)
-        .chat_id(telegram::CHAT_MONEY_PRINTER)
-        .thread_id(thread_id)
-        .text(msg.clone())
-        .disable_link_preview(true)
-        .build();
-
-    // SUI Arbitrage Group
-    let thread_id = if trade_res.profit > 1000000000 {
-        THREAD_HIGH_PROFIT
-    } else {
-        THREAD_LOW_PROFIT
+    use super::*;
+    use crate::{
+        arb::TrialResult,
+        defi::{Dex, Path, StubDex},
+        types::Source,
     };
-    let msg2 = MessageBuilder::new()
-        .bot_token(SUI_ARB_BOT_TOKEN)
-        .chat_id(GROUP_SUI_ARB)
-        .thread_id(thread_id)
-        .text(msg.clone())
-        .disable_link_preview(true)
-        .build();
-
-    vec![msg1, msg2]
+
+    const OCEAN: &str = "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN";
+
+    fn fixture() -> (TransactionDigest, TransactionDigest, ArbResult, ObjectID, ObjectID) {
+        let (pool1, pool2) = (ObjectID::from_single_byte(1), ObjectID::from_single_byte(2));
+        let path = Path::new(vec![
+            Box::new(StubDex::new(pool1, SUI_COIN_TYPE, OCEAN, 0)) as Box<dyn Dex>,
+            Box::new(StubDex::new(pool2, OCEAN, SUI_COIN_TYPE, 0)),
+        ]);
+
+        let sender = SuiAddress::ZERO;
+        let tx_data = TransactionData::new_transfer_sui(
+            sender,
+            sender,
+            Some(1),
+            sui_types::base_types::random_object_ref(),
+            1,
+            1,
+        );
+        let (opp_digest, arb_digest) = (TransactionDigest::new([1; 32]), TransactionDigest::new([2; 32]));
+        let source = Source::Shio {
+            opp_tx_digest: opp_digest,
+            bid_amount: 0,
+            start: 1_000,
+            arb_found: 1_150,
+            deadline: 1_200,
+        }
+        .with_bid_amount(45_000_000);
+
+        let res = ArbResult {
+            create_trial_ctx_duration: Duration::from_millis(12),
+            grid_search_duration: Duration::from_millis(345),
+            gss_duration: None,
+            best_trial_result: TrialResult::new(OCEAN, 1_500_000_000, 50_000_000, 2_000_000, path, 3, vec![]),
+            cache_misses: 3,
+            source,
+            tx_data,
+        };
+        (opp_digest, arb_digest, res, pool1, pool2)
+    }
+
+    #[test]
+    fn test_full_arb_message() {
+        let (opp_digest, arb_digest, res, pool1, pool2) = fixture();
+        let explorer = link::explorer();
+
+        let msg = arb_message(
+            opp_digest,
+            arb_digest,
+            &res,
+            Duration::from_millis(420),
+            "DBSimulator",
+            NotificationMode::Full,
+        );
+        let expected = format!(
+            r#"*Profit*: `0\.05 SUI`
+*Bid*: `0\.045 SUI`
+*Gas*: `0\.002 SUI`
+
+*Digest*: [{opp_digest}]({opp_url})
+*Arb Digest*: [{arb_digest}]({arb_url})
+*Coin*: [{ocean}]({coin_url})
+*Amount In*: 1\.5 SUI
+*Amount Out*: 1\.552 SUI
+*Path*:
+ 0\. [Cetus:SUI→OCEAN]({pool1_url})
+ 1\. [Cetus:OCEAN→SUI]({pool2_url})
+*Elapsed*: 420ms
+*Elapsed TrialCtx Creation*: 12ms
+*Elapsed Grid Search*: 345ms
+*Elapsed GSS*: \-
+*Cache Misses*: 3
+
+*DBSimulator*
+*Shio\(start\=1000, deadline\=1200, time\_window\=200ms, arb\_found\=1150, early\=50ms\)*
+*Version*: `{version}`"#,
+            opp_url = explorer.tx_url(&opp_digest),
+            arb_url = explorer.tx_url(&arb_digest),
+            ocean = escape(OCEAN),
+            coin_url = explorer.coin_url(OCEAN),
+            pool1_url = explorer.object_url(&pool1),
+            pool2_url = explorer.object_url(&pool2),
+            version = escape(BUILD_VERSION),
+        );
+        assert_eq!(msg, expected);
+    }
+
+    #[test]
+    fn test_compact_arb_message() {
+        let (opp_digest, arb_digest, res, _, _) = fixture();
+        let explorer = link::explorer();
+
+        let msg = arb_message(
+            opp_digest,
+            arb_digest,
+            &res,
+            Duration::ZERO,
+            "DBSimulator",
+            NotificationMode::Compact,
+        );
+        let expected = format!(
+            r#"*Profit*: `0\.05 SUI` *Bid*: `0\.045 SUI`
+Cetus:SUI→OCEAN \| Cetus:OCEAN→SUI
+*Opp*: [{opp_digest}]({opp_url})
+*Arb*: [{arb_digest}]({arb_url})"#,
+            opp_url = explorer.tx_url(&opp_digest),
+            arb_url = explorer.tx_url(&arb_digest),
+        );
+        assert_eq!(msg, expected);
+    }
+
+    #[test]
+    fn test_compact_while_frequent() {
+        let mut recent = RecentNotifications::new();
+        let start = Instant::now();
+        for i in 0..COMPACT_AFTER {
+            assert_eq!(
+                recent.record(start + Duration::from_secs(i as u64)),
+                NotificationMode::Full
+            );
+        }
+        assert_eq!(
+            recent.record(start + Duration::from_secs(10)),
+            NotificationMode::Compact
+        );
+
+        // the first ones are out of the window
+        assert_eq!(
+            recent.record(start + COMPACT_WINDOW + Duration::from_secs(3)),
+            NotificationMode::Full
+        );
+    }
 }
@@ -1,8 +1,36 @@
-use std::collections::HashSet;
+use std::{
+    collections::HashSet,
+    fmt,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
+use dex_indexer::{normalize_coin_type, types::Protocol};
+use eyre::{bail, ensure, Context, Result};
+use serde::Deserialize;
+use shio::{Keepalive, DEFAULT_BID_ACK_TIMEOUT, DEFAULT_MAX_RETRIES, DEFAULT_SIGN_TIMEOUT};
 use sui_sdk::SUI_COIN_TYPE;
+use sui_types::base_types::{ObjectID, SuiAddress};
+use tracing::Level;
+use utils::{
+    link::Explorer,
+    telegram::{AlertConfig, DEFAULT_COALESCE_WINDOW, DEFAULT_MAX_MESSAGES_PER_MINUTE},
+};
+
+use crate::defi::{
+    ProtocolFilter, DEFAULT_DEX_CACHE_TTL, DEFAULT_MAX_POOLS_PER_PROTOCOL, DEFAULT_MAX_SIMULATED_PATHS,
+    DEFAULT_MIN_OUT_TOLERANCE_BPS, DEFAULT_QUARANTINE_ABORTS, DEFAULT_QUARANTINE_COOL_OFF,
+};
 
 pub const GAS_BUDGET: u64 = 10_000_000_000;
+/// the final tx gets the gas cost of its simulation plus this many basis points as budget
+pub const GAS_BUDGET_SAFETY_BPS: u64 = 2_000;
+/// gas coins with a lower balance (0.001 SUI) are dust, not worth the input object
+pub const MIN_GAS_COIN_BALANCE: u64 = 1_000_000;
+/// the gas coins of a tx, the largest ones, well below the input object limit of a tx
+pub const MAX_GAS_COINS: usize = 32;
 pub const MAX_SQRT_PRICE_X64: u128 = 79226673515401279992447579055;
 pub const MIN_SQRT_PRICE_X64: u128 = 4295048016;
 
@@ -14,27 +42,655 @@ pub const MIN_SQRT_PRICE_X64: u128 = 4295048016;
 - 代币白名单等业务规则
 通过集中配置可以避免魔法数字散落在代码各处，提高可维护性。
 */
-pub fn pegged_coin_types() -> HashSet<&'static str> {
-    HashSet::from_iter([
-        SUI_COIN_TYPE,
-        // USDC
-        "0x5d4b302506645c37ff133b98c4b50a5ae14841659738d6d733d59d0d217a93bf::coin::COIN",
-        // USDT
-        "0xc060006111016b8a020ad5b33834984a437aaa7d3c74c18e09a95d48aceab08c::coin::COIN",
-        // WETH
-        "0xaf8cd5edc19c4512f4259f0bee101a40d41ebed738ade5874359610ef8eeced5::coin::COIN",
-        // USDC
-        "0xb231fcda8bbddb31f2ef02e6161444aec64a514e2c89279584ac9806ce9cf037::coin::COIN",
-        // USDC
-        "0xdba34672e30cb065b1f93e3ab55318768fd6fef66c15942c9f7cb846e2f900e7::usdc::USDC",
-        // Bucket USD
-        "0xce7ff77a83ea0cb6fd39bd8748e2ec89a3f41e8efdc3f4eb123e0ca37b184db2::buck::BUCK",
-    ])
+const DEFAULT_PEGGED_COIN_TYPES: &[&str] = &[
+    SUI_COIN_TYPE,
+    // USDC
+    "0x5d4b302506645c37ff133b98c4b50a5ae14841659738d6d733d59d0d217a93bf::coin::COIN",
+    // USDT
+    "0xc060006111016b8a020ad5b33834984a437aaa7d3c74c18e09a95d48aceab08c::coin::COIN",
+    // WETH
+    "0xaf8cd5edc19c4512f4259f0bee101a40d41ebed738ade5874359610ef8eeced5::coin::COIN",
+    // USDC
+    "0xb231fcda8bbddb31f2ef02e6161444aec64a514e2c89279584ac9806ce9cf037::coin::COIN",
+    // USDC
+    "0xdba34672e30cb065b1f93e3ab55318768fd6fef66c15942c9f7cb846e2f900e7::usdc::USDC",
+    // Bucket USD
+    "0xce7ff77a83ea0cb6fd39bd8748e2ec89a3f41e8efdc3f4eb123e0ca37b184db2::buck::BUCK",
+];
+
+const DEFAULT_WARM_UP_COINS: &[&str] = &[
+    // USDC
+    "0xdba34672e30cb065b1f93e3ab55318768fd6fef66c15942c9f7cb846e2f900e7::usdc::USDC",
+    // CETUS
+    "0x06864a6f921804860930db6ddbe2e16acdf8504495ea7481637a1c8b9a8fe54b::cetus::CETUS",
+];
+
+/// Comma-separated pegged coin types, used when the config file doesn't set `pegged_coin_types`.
+const PEGGED_COIN_TYPES_ENV: &str = "SUI_PEGGED_COIN_TYPES";
+
+static PEGGED_COIN_TYPES: OnceLock<Arc<HashSet<String>>> = OnceLock::new();
+
+fn default_pegged_coin_types() -> Vec<String> {
+    match std::env::var(PEGGED_COIN_TYPES_ENV) {
+        Ok(coin_types) => coin_types
+            .split(',')
+            .map(str::trim)
+            .filter(|coin_type| !coin_type.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(_) => DEFAULT_PEGGED_COIN_TYPES.iter().map(|c| c.to_string()).collect(),
+    }
+}
+
+/// The normalized `coin_types`, to be compared with the normalized coin types of the dexes.
+pub fn new_pegged_coin_types(coin_types: &[String]) -> HashSet<String> {
+    coin_types.iter().map(|c| normalize_coin_type(c)).collect()
+}
+
+/// Set the pegged coin types at startup, returns false if they were already initialized.
+pub fn init_pegged_coin_types(coin_types: &[String]) -> bool {
+    PEGGED_COIN_TYPES
+        .set(Arc::new(new_pegged_coin_types(coin_types)))
+        .is_ok()
+}
+
+/// Coins that are treated as directly convertible to SUI when searching sell paths.
+/// Defaults to `SUI_PEGGED_COIN_TYPES` or the builtin list if not initialized.
+pub fn pegged_coin_types() -> Arc<HashSet<String>> {
+    PEGGED_COIN_TYPES
+        .get_or_init(|| Arc::new(new_pegged_coin_types(&default_pegged_coin_types())))
+        .clone()
+}
+
+/// Parameters of `start_bot`, loaded from a TOML file. CLI flags override the values in the file.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BotConfig {
+    pub private_key: Option<String>,
+    /// more attacker keys, each with its own gas coins. The arb txs are sent from `private_key` and these
+    /// in turn.
+    pub extra_private_keys: Vec<String>,
+    pub rpc_url: String,
+    pub ipc_path: Option<String>,
+    pub shio_use_rpc: bool,
+    /// the shio rpc endpoints bids are posted to in order, `shio::SHIO_JSON_RPC_URL` if empty
+    pub shio_rpc_urls: Vec<String>,
+    /// the attempts of a bid after the first one, on the next shio rpc endpoints in turn
+    pub shio_rpc_max_retries: usize,
+    /// in milliseconds, a shio bid the ws server hasn't replied to by then is reported as unacknowledged
+    pub shio_bid_ack_timeout: u64,
+    /// the signing service shio bids are signed by, with `private_key` if not set. It must sign with
+    /// the key of `private_key`, which still signs the other txs, or of `shio_signer_address`.
+    pub shio_signer_url: Option<String>,
+    /// the bid sender whose key is held by `shio_signer_url`, in place of `private_key`. Without a
+    /// `private_key` the bot only bids on shio, no public tx is executed.
+    pub shio_signer_address: Option<SuiAddress>,
+    /// in milliseconds, a bid whose signature takes longer fails
+    pub shio_signer_timeout: u64,
+    pub dry_run: bool,
+    pub dry_run_output: String,
+    /// Opportunities with a lower profit (in MIST) are not executed.
+    pub min_profit: u64,
+    /// Coins that are treated as directly convertible to SUI when searching sell paths.
+    pub pegged_coin_types: Vec<String>,
+    /// Serve the prometheus metrics on `GET /metrics` at this port, disabled if not set.
+    pub metrics_port: Option<u16>,
+    /// Serve the admin commands (see `admin::serve`) on localhost at this port, disabled if not set.
+    pub admin_port: Option<u16>,
+
+    pub collector: CollectorConfig,
+    pub db_sim: DbSimConfig,
+    pub worker: WorkerConfig,
+    pub telegram: TelegramConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectorConfig {
+    /// relay tx collector (should be mutually exclusive with public tx collector)
+    pub relay_ws_url: Option<String>,
+    /// the relay only sends the txs calling these packages, all of them if empty
+    pub relay_move_packages: Vec<ObjectID>,
+    /// shio collector
+    pub shio_ws_url: Option<String>,
+    /// more shio feeds to read from, their items are deduplicated with the ones of `shio_ws_url`.
+    /// Bids fail over to them in order while `shio_ws_url` is down.
+    pub shio_backup_ws_urls: Vec<String>,
+    /// in milliseconds, how often the shio feeds are pinged
+    pub shio_ping_interval: u64,
+    /// in milliseconds, a shio feed that sends no frame (pongs included) for this long is reconnected
+    pub shio_stall_timeout: u64,
+    /// public tx collector
+    pub tx_socket_path: String,
+    /// forward every public tx to the strategy, instead of only the ones with a swap event
+    pub raw_public_txs: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DbSimConfig {
+    pub db_path: String,
+    pub config_path: String,
+    pub update_cache_socket: String,
+    pub preload_path: String,
+    pub use_db_simulator: bool,
+    /// in seconds
+    pub catchup_interval: u64,
+    /// how often the db simulators reload the underlying store, in seconds
+    pub store_catchup_interval: u64,
+    /// warn on every object read that misses the override objects, instead of a debug summary per simulation
+    pub warn_override_misses: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerConfig {
+    pub workers: usize,
+    pub num_simulators: usize,
+    /// in milliseconds
+    pub public_arb_cooldown: u64,
+    /// in milliseconds
+    pub shio_arb_cooldown: u64,
+    /// in milliseconds
+    pub dedicated_short_interval: u64,
+    /// in milliseconds
+    pub dedicated_long_interval: u64,
+    /// the dedicated simulator reloads on the short interval this many times after a submission
+    pub dedicated_short_updates: u32,
+    /// in milliseconds, the final dry run falls back to a pooled simulator if the dedicated simulator
+    /// has not reloaded the store for longer
+    pub max_dedicated_lag: u64,
+    /// split the largest SUI coin into this many gas coins at startup, 0 to keep the coins as is
+    pub split_gas_coins: usize,
+    /// the final dry run must realize at least this percentage of the estimated profit
+    pub min_realized_profit_pct: u64,
+    /// in milliseconds, a shio bid is dropped if less time remains before its deadline
+    pub final_check_margin: u64,
+    /// in milliseconds, an opportunity of a private tx is dropped once the tx was received longer ago
+    pub max_private_age: u64,
+    /// workers search the paths of these coins before reporting ready, so that the lazily
+    /// initialized caches are not built on the first opportunity
+    pub warm_up_coins: Vec<String>,
+    /// also trade through SUI-rooted cycles of up to this many hops, e.g. SUI -> A -> B -> SUI,
+    /// 0 to only trade the paths joined from a buy path and a sell path
+    pub max_cycle_hops: usize,
+    /// in milliseconds, the dexes built from a pool are reused for this long unless a swap on the pool
+    /// is observed, 0 to rebuild them on every search
+    pub dex_cache_ttl: u64,
+    /// the pools of these protocols are ignored, e.g. `["cetus", "turbos"]`
+    pub disable_protocols: Vec<String>,
+    /// only the pools of these protocols are searched, all of them if empty
+    pub only_protocols: Vec<String>,
+    /// of the pools searched per coin, this many of each protocol are kept before the others are ranked
+    /// by liquidity, 0 to only rank them by liquidity
+    pub max_pools_per_protocol: usize,
+    /// of the paths whose pools can all be quoted locally, only this many best estimated ones are simulated,
+    /// 0 to simulate all of them
+    pub max_simulated_paths: usize,
+    /// a pool that aborts all the trials of this many opportunities in a row is left out of the path search for
+    /// `quarantine_cool_off` seconds, 0 to never quarantine a pool
+    pub quarantine_aborts: usize,
+    pub quarantine_cool_off: u64,
+    /// in basis points, the swaps that take a min amount out get their quote minus this tolerance
+    pub min_out_tolerance_bps: u64,
+    /// never flashloan from Navi, the paths whose first hop can't flashloan are rotated or dropped
+    pub disable_navi: bool,
+    /// stack size of each worker thread, in megabytes
+    pub stack_size_mb: usize,
+    /// pin each worker thread to a CPU core, round-robin
+    pub pin_to_cores: bool,
+    /// spawn up to this many workers while the arb_item channel is backlogged, and retire the extra
+    /// ones once they are idle. 0 to keep `workers` workers
+    pub max_workers: usize,
+    /// the arb_item channel is backlogged when at least this many items are waiting in it
+    pub scale_up_backlog: usize,
+    /// in seconds, spawn a worker once the channel stays backlogged for this long
+    pub scale_up_after: u64,
+    /// in seconds, retire a worker once some workers stay idle for this long
+    pub scale_down_idle: u64,
+}
+
+/// Where the panics, the unhealthy components and the executed arbs are reported. Disabled unless both
+/// `bot_token` and `chat_id` are set.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelegramConfig {
+    pub bot_token: Option<String>,
+    pub chat_id: Option<String>,
+    /// panics and unhealthy components, the main thread of the chat if not set
+    pub error_thread_id: Option<String>,
+    /// executed arbs, the main thread of the chat if not set
+    pub notification_thread_id: Option<String>,
+    /// in seconds, the same alert repeated within this window is sent once with the number of repeats
+    pub coalesce_window: u64,
+    /// the messages over this limit are dropped
+    pub max_messages_per_minute: u32,
+    /// where the links of the notifications point to: `suiscan`, `suivision` or the base url of an explorer
+    pub explorer: String,
+    /// the log events at this level or above are sent to the error thread: `error`, `warn` or `off`
+    pub log_alert_level: String,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self {
+            private_key: None,
+            extra_private_keys: vec![],
+            rpc_url: "http://localhost:9000".to_string(),
+            ipc_path: None,
+            shio_use_rpc: false,
+            shio_rpc_urls: vec![],
+            shio_rpc_max_retries: DEFAULT_MAX_RETRIES,
+            shio_bid_ack_timeout: DEFAULT_BID_ACK_TIMEOUT.as_millis() as u64,
+            shio_signer_url: None,
+            shio_signer_address: None,
+            shio_signer_timeout: DEFAULT_SIGN_TIMEOUT.as_millis() as u64,
+            dry_run: false,
+            dry_run_output: "dry_run.jsonl".to_string(),
+            min_profit: 0,
+            pegged_coin_types: default_pegged_coin_types(),
+            metrics_port: None,
+            admin_port: None,
+            collector: CollectorConfig::default(),
+            db_sim: DbSimConfig::default(),
+            worker: WorkerConfig::default(),
+            telegram: TelegramConfig::default(),
+        }
+    }
+}
+
+impl Default for CollectorConfig {
+    fn default() -> Self {
+        Self {
+            relay_ws_url: None,
+            relay_move_packages: vec![],
+            shio_ws_url: None,
+            shio_backup_ws_urls: vec![],
+            shio_ping_interval: Keepalive::default().ping_interval.as_millis() as u64,
+            shio_stall_timeout: Keepalive::default().stall_timeout.as_millis() as u64,
+            tx_socket_path: "/tmp/sui_tx.sock".to_string(),
+            raw_public_txs: false,
+        }
+    }
+}
+
+impl Default for DbSimConfig {
+    fn default() -> Self {
+        Self {
+            db_path: "/home/ubuntu/sui/db/live/store".to_string(),
+            config_path: "/home/ubuntu/sui/fullnode.yaml".to_string(),
+            update_cache_socket: "/tmp/sui_cache_updates.sock".to_string(),
+            preload_path: "/home/ubuntu/suiflow-relay/pool_related_ids.txt".to_string(),
+            use_db_simulator: false,
+            catchup_interval: 60,
+            store_catchup_interval: 3600 * 24,
+            warn_override_misses: false,
+        }
+    }
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            workers: 8,
+            num_simulators: 32,
+            public_arb_cooldown: 3000,
+            shio_arb_cooldown: 0,
+            dedicated_short_interval: 50,
+            dedicated_long_interval: 200,
+            dedicated_short_updates: 50,
+            max_dedicated_lag: 1000,
+            split_gas_coins: 0,
+            min_realized_profit_pct: 80,
+            final_check_margin: 10,
+            max_private_age: 500,
+            warm_up_coins: DEFAULT_WARM_UP_COINS.iter().map(|c| c.to_string()).collect(),
+            max_cycle_hops: 0,
+            dex_cache_ttl: DEFAULT_DEX_CACHE_TTL.as_millis() as u64,
+            disable_protocols: vec![],
+            only_protocols: vec![],
+            max_pools_per_protocol: DEFAULT_MAX_POOLS_PER_PROTOCOL,
+            max_simulated_paths: DEFAULT_MAX_SIMULATED_PATHS,
+            quarantine_aborts: DEFAULT_QUARANTINE_ABORTS,
+            quarantine_cool_off: DEFAULT_QUARANTINE_COOL_OFF.as_secs(),
+            min_out_tolerance_bps: DEFAULT_MIN_OUT_TOLERANCE_BPS,
+            disable_navi: false,
+            stack_size_mb: 128,
+            pin_to_cores: false,
+            max_workers: 0,
+            scale_up_backlog: 10,
+            scale_up_after: 10,
+            scale_down_idle: 60,
+        }
+    }
+}
+
+impl Default for TelegramConfig {
+    fn default() -> Self {
+        Self {
+            bot_token: None,
+            chat_id: None,
+            error_thread_id: None,
+            notification_thread_id: None,
+            coalesce_window: DEFAULT_COALESCE_WINDOW.as_secs(),
+            max_messages_per_minute: DEFAULT_MAX_MESSAGES_PER_MINUTE,
+            explorer: "suiscan".to_string(),
+            log_alert_level: "error".to_string(),
+        }
+    }
+}
+
+impl WorkerConfig {
+    pub fn protocol_filter(&self) -> Result<ProtocolFilter> {
+        let parse = |protocols: &[String]| {
+            protocols
+                .iter()
+                .map(|protocol| Protocol::from_str(protocol.trim()))
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(ProtocolFilter::new(
+            parse(&self.only_protocols).context("invalid `worker.only_protocols`")?,
+            parse(&self.disable_protocols).context("invalid `worker.disable_protocols`")?,
+        ))
+    }
+}
+
+impl TelegramConfig {
+    /// `None` if the alerts are disabled.
+    pub fn alert_config(&self) -> Option<AlertConfig> {
+        let (Some(bot_token), Some(chat_id)) = (&self.bot_token, &self.chat_id) else {
+            return None;
+        };
+
+        let mut config = AlertConfig::new(bot_token.clone(), chat_id.clone());
+        config.error_thread_id = self.error_thread_id.clone();
+        config.notification_thread_id = self.notification_thread_id.clone();
+        config.coalesce_window = Duration::from_secs(self.coalesce_window);
+        config.max_messages_per_minute = self.max_messages_per_minute;
+        Some(config)
+    }
+
+    /// `None` if the log events are not to be sent.
+    pub fn log_alert_level(&self) -> Result<Option<Level>> {
+        match self.log_alert_level.as_str() {
+            "off" => Ok(None),
+            "error" => Ok(Some(Level::ERROR)),
+            "warn" => Ok(Some(Level::WARN)),
+            level => bail!("invalid `telegram.log_alert_level` {level:?}, expected error, warn or off"),
+        }
+    }
+}
+
+impl BotConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).with_context(|| format!("failed to read config {:?}", path))?;
+        Self::from_toml(&content).with_context(|| format!("invalid config {:?}", path))
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self> {
+        Ok(toml::from_str(content)?)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match (&self.private_key, &self.shio_signer_address) {
+            (None, None) => bail!("`private_key` is required, unless `shio_signer_address` is set"),
+            (Some(_), Some(_)) => bail!("`shio_signer_address` is the address of `private_key`, only set one of them"),
+            (None, Some(_)) => {
+                ensure!(
+                    self.shio_signer_url.is_some(),
+                    "`shio_signer_address` requires `shio_signer_url`"
+                );
+                ensure!(
+                    self.collector.shio_ws_url.is_some(),
+                    "`shio_signer_address` requires `collector.shio_ws_url`"
+                );
+                ensure!(
+                    self.extra_private_keys.is_empty(),
+                    "`extra_private_keys` requires `private_key`"
+                );
+            }
+            (Some(_), None) => {}
+        }
+        ensure!(!self.rpc_url.is_empty(), "`rpc_url` must not be empty");
+        ensure!(self.worker.workers > 0, "`worker.workers` must be greater than 0");
+        ensure!(
+            self.worker.num_simulators > 0,
+            "`worker.num_simulators` must be greater than 0"
+        );
+        ensure!(
+            self.worker.dedicated_short_interval <= self.worker.dedicated_long_interval,
+            "`worker.dedicated_short_interval` must not be greater than `worker.dedicated_long_interval`"
+        );
+        ensure!(
+            self.worker.min_realized_profit_pct <= 100,
+            "`worker.min_realized_profit_pct` must not be greater than 100"
+        );
+        ensure!(
+            self.db_sim.store_catchup_interval > 0,
+            "`db_sim.store_catchup_interval` must be greater than 0"
+        );
+        ensure!(
+            self.collector.shio_ping_interval > 0,
+            "`collector.shio_ping_interval` must be greater than 0"
+        );
+        ensure!(
+            self.collector.shio_ping_interval < self.collector.shio_stall_timeout,
+            "`collector.shio_stall_timeout` must be greater than `collector.shio_ping_interval`"
+        );
+        ensure!(
+            self.worker.stack_size_mb > 0,
+            "`worker.stack_size_mb` must be greater than 0"
+        );
+        ensure!(
+            self.worker.max_workers == 0 || self.worker.max_workers >= self.worker.workers,
+            "`worker.max_workers` must be 0 or not less than `worker.workers`"
+        );
+        ensure!(
+            self.telegram.bot_token.is_some() == self.telegram.chat_id.is_some(),
+            "`telegram.bot_token` and `telegram.chat_id` must be set together"
+        );
+        ensure!(
+            self.telegram.max_messages_per_minute > 0,
+            "`telegram.max_messages_per_minute` must be greater than 0"
+        );
+        self.telegram
+            .explorer
+            .parse::<Explorer>()
+            .context("invalid `telegram.explorer`")?;
+        self.telegram.log_alert_level()?;
+        self.worker.protocol_filter()?;
+        for coin_type in &self.pegged_coin_types {
+            ensure!(
+                coin_type.split("::").count() == 3,
+                "`pegged_coin_types` contains an invalid coin type: {:?}",
+                coin_type
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BotConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BotConfig")
+            .field("private_key", &self.private_key.as_ref().map(|_| "<redacted>"))
+            .field("extra_private_keys", &vec!["<redacted>"; self.extra_private_keys.len()])
+            .field("rpc_url", &self.rpc_url)
+            .field("ipc_path", &self.ipc_path)
+            .field("shio_use_rpc", &self.shio_use_rpc)
+            .field("shio_rpc_urls", &self.shio_rpc_urls)
+            .field("shio_rpc_max_retries", &self.shio_rpc_max_retries)
+            .field("shio_bid_ack_timeout", &self.shio_bid_ack_timeout)
+            .field("shio_signer_url", &self.shio_signer_url)
+            .field("shio_signer_address", &self.shio_signer_address)
+            .field("shio_signer_timeout", &self.shio_signer_timeout)
+            .field("dry_run", &self.dry_run)
+            .field("dry_run_output", &self.dry_run_output)
+            .field("min_profit", &self.min_profit)
+            .field("pegged_coin_types", &self.pegged_coin_types)
+            .field("metrics_port", &self.metrics_port)
+            .field("admin_port", &self.admin_port)
+            .field("collector", &self.collector)
+            .field("db_sim", &self.db_sim)
+            .field("worker", &self.worker)
+            .field("telegram", &self.telegram)
+            .finish()
+    }
+}
+
+impl fmt::Debug for TelegramConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TelegramConfig")
+            .field("bot_token", &self.bot_token.as_ref().map(|_| "<redacted>"))
+            .field("chat_id", &self.chat_id)
+            .field("error_thread_id", &self.error_thread_id)
+            .field("notification_thread_id", &self.notification_thread_id)
+            .field("coalesce_window", &self.coalesce_window)
+            .field("max_messages_per_minute", &self.max_messages_per_minute)
+            .field("explorer", &self.explorer)
+            .field("log_alert_level", &self.log_alert_level)
+            .finish()
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
+    use super::*;
 
     pub const TEST_HTTP_URL: &str = "";
     pub const TEST_ATTACKER: &str = "";
+
+    #[test]
+    fn test_partial_config_uses_defaults() {
+        let config = BotConfig::from_toml(
+            r#"
+            rpc_url = "http://10.0.0.1:9000"
+
+            [worker]
+            workers = 4
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.rpc_url, "http://10.0.0.1:9000");
+        assert_eq!(config.worker.workers, 4);
+        assert_eq!(config.worker.num_simulators, WorkerConfig::default().num_simulators);
+        assert_eq!(config.db_sim, DbSimConfig::default());
+    }
+
+    #[test]
+    fn test_unknown_field() {
+        let error = BotConfig::from_toml(
+            r#"
+            [worker]
+            worker = 4
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(format!("{error:#}").contains("unknown field `worker`"), "{error:#}");
+    }
+
+    #[test]
+    fn test_new_pegged_coin_types() {
+        let sui = "0x0000000000000000000000000000000000000000000000000000000000000002::sui::SUI";
+        let coin_types = new_pegged_coin_types(&["0x1::pegged::TEST".to_string(), sui.to_string()]);
+        assert!(coin_types.contains("0x1::pegged::TEST"));
+        assert!(coin_types.contains(SUI_COIN_TYPE));
+        assert!(!coin_types.contains(sui));
+    }
+
+    #[test]
+    fn test_validate_names_field() {
+        let mut config = BotConfig {
+            private_key: Some("key".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        config.worker.num_simulators = 0;
+        let error = config.validate().unwrap_err();
+        assert!(error.to_string().contains("worker.num_simulators"), "{error}");
+
+        config.worker.num_simulators = 1;
+        config.worker.max_workers = config.worker.workers - 1;
+        let error = config.validate().unwrap_err();
+        assert!(error.to_string().contains("worker.max_workers"), "{error}");
+
+        config.worker.max_workers = 0;
+        config.collector.shio_stall_timeout = config.collector.shio_ping_interval;
+        let error = config.validate().unwrap_err();
+        assert!(error.to_string().contains("collector.shio_stall_timeout"), "{error}");
+    }
+
+    #[test]
+    fn test_signer_address_replaces_private_key() {
+        let mut config = BotConfig::default();
+        let error = config.validate().unwrap_err();
+        assert!(error.to_string().contains("private_key"), "{error}");
+
+        config.shio_signer_address = Some(SuiAddress::random_for_testing_only());
+        let error = config.validate().unwrap_err();
+        assert!(error.to_string().contains("shio_signer_url"), "{error}");
+
+        config.shio_signer_url = Some("http://localhost:9100".to_string());
+        config.collector.shio_ws_url = Some("wss://shio".to_string());
+        assert!(config.validate().is_ok());
+
+        config.private_key = Some("key".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_protocol_filter() {
+        let config = BotConfig::from_toml(
+            r#"
+            [worker]
+            disable_protocols = ["cetus", "turbos"]
+            "#,
+        )
+        .unwrap();
+        let filter = config.worker.protocol_filter().unwrap();
+        assert!(!filter.allows(&Protocol::Cetus));
+        assert!(!filter.allows(&Protocol::Turbos));
+        assert!(filter.allows(&Protocol::Aftermath));
+
+        let mut worker = config.worker.clone();
+        worker.only_protocols = vec!["uniswap".to_string()];
+        let error = worker.protocol_filter().unwrap_err();
+        assert!(format!("{error:#}").contains("worker.only_protocols"), "{error:#}");
+    }
+
+    #[test]
+    fn test_telegram_alerts() {
+        let config = BotConfig::default();
+        assert_eq!(config.telegram.alert_config(), None);
+
+        let config = BotConfig::from_toml(
+            r#"
+            [telegram]
+            bot_token = "123:secret"
+            chat_id = "-100"
+            error_thread_id = "7"
+            coalesce_window = 30
+            "#,
+        )
+        .unwrap();
+        let alerts = config.telegram.alert_config().unwrap();
+        assert_eq!(alerts.chat_id, "-100");
+        assert_eq!(alerts.error_thread_id.as_deref(), Some("7"));
+        assert_eq!(alerts.notification_thread_id, None);
+        assert_eq!(alerts.coalesce_window, Duration::from_secs(30));
+        assert_eq!(config.telegram.log_alert_level().unwrap(), Some(Level::ERROR));
+        assert!(!format!("{config:?}").contains("secret"));
+
+        let mut telegram = config.telegram.clone();
+        telegram.log_alert_level = "off".to_string();
+        assert_eq!(telegram.log_alert_level().unwrap(), None);
+        telegram.log_alert_level = "info".to_string();
+        assert!(telegram.log_alert_level().is_err());
+    }
 }
@@ -12,7 +12,11 @@ use sui_types::{
     Identifier, TypeTag,
 };
 use tokio::sync::OnceCell;
-use utils::{coin, new_test_sui_client, object::*};
+use utils::{
+    coin::{self, GasCoinFilter},
+    new_test_sui_client,
+    object::*,
+};
 
 use super::TradeCtx;
 use crate::{config::*, defi::Dex};
@@ -40,26 +44,17 @@ static OBJ_CACHE: OnceCell<ObjectArgs> = OnceCell::const_new();
 async fn get_object_args(simulator: Arc<Box<dyn Simulator>>) -> ObjectArgs {
     OBJ_CACHE
         .get_or_init(|| async {
-            let pool_registry = simulator
-                .get_object(&ObjectID::from_hex_literal(POOL_REGISTRY).unwrap())
-                .await
-                .unwrap();
-            let protocol_fee_vault = simulator
-                .get_object(&ObjectID::from_hex_literal(PROTOCOL_FEE_VAULT).unwrap())
-                .await
-                .unwrap();
-            let treasury = simulator
-                .get_object(&ObjectID::from_hex_literal(TREASURY).unwrap())
-                .await
-                .unwrap();
-            let insurance_fund = simulator
-                .get_object(&ObjectID::from_hex_literal(INSURANCE_FUND).unwrap())
-                .await
-                .unwrap();
-            let referral_vault = simulator
-                .get_object(&ObjectID::from_hex_literal(REFERRAL_VAULT).unwrap())
-                .await
-                .unwrap();
+            let ids = [
+                POOL_REGISTRY,
+                PROTOCOL_FEE_VAULT,
+                TREASURY,
+                INSURANCE_FUND,
+                REFERRAL_VAULT,
+            ]
+            .map(|id| ObjectID::from_hex_literal(id).unwrap());
+            let objects = simulator.multi_get_objects(&ids).await;
+            let [pool_registry, protocol_fee_vault, treasury, insurance_fund, referral_vault] =
+                <[_; 5]>::try_from(objects).unwrap().map(Option::unwrap);
 
             ObjectArgs {
                 pool_registry: shared_obj_arg(&pool_registry, false),
@@ -85,10 +80,10 @@ pub struct Aftermath {
     treasury: ObjectArg,
     insurance_fund: ObjectArg,
     referral_vault: ObjectArg,
-    balances: Vec<u128>,
-    weights: Vec<u64>,
-    swap_fee_in: u64,
-    swap_fee_out: u64,
+    math: PoolMath,
+    // the fees of each coin of the pool, read at `index_in` and `index_out`
+    fees_swap_in: Vec<u64>,
+    fees_swap_out: Vec<u64>,
     index_in: usize,
     index_out: usize,
 }
@@ -116,13 +111,14 @@ impl Aftermath {
             MoveStruct::simple_deserialize(move_obj.contents(), &layout).map_err(|e| eyre!(e))?
         };
 
-        let liquidity = {
-            let lp_supply = extract_struct_from_move_struct(&parsed_pool, "lp_supply")?;
-            extract_u64_from_move_struct(&lp_supply, "value")? as u128
-        };
+        let liquidity = extract_u64_from_move_struct(walk_path(&parsed_pool, &["lp_supply"])?, "value")? as u128;
 
-        let balances = extract_u128_vec_from_move_struct(&parsed_pool, "normalized_balances")?;
-        let weights = extract_u64_vec_from_move_struct(&parsed_pool, "weights")?;
+        let math = PoolMath {
+            balances: extract_u128_vec_from_move_struct(&parsed_pool, "normalized_balances")?,
+            weights: extract_u64_vec_from_move_struct(&parsed_pool, "weights")?,
+            flatness: extract_u128_from_move_struct(&parsed_pool, "flatness")?,
+            decimal_scalars: extract_u128_vec_from_move_struct(&parsed_pool, "decimal_scalars")?,
+        };
         let fees_swap_in = extract_u64_vec_from_move_struct(&parsed_pool, "fees_swap_in")?;
         let fees_swap_out = extract_u64_vec_from_move_struct(&parsed_pool, "fees_swap_out")?;
         let index_in = pool.token_index(coin_in_type).unwrap();
@@ -157,10 +153,9 @@ impl Aftermath {
                 treasury,
                 insurance_fund,
                 referral_vault,
-                balances,
-                weights,
-                swap_fee_in: fees_swap_in[index_in],
-                swap_fee_out: fees_swap_out[index_out],
+                math,
+                fees_swap_in,
+                fees_swap_out,
                 index_in,
                 index_out,
             }]);
@@ -186,10 +181,9 @@ impl Aftermath {
                 treasury: treasury.clone(),
                 insurance_fund: insurance_fund.clone(),
                 referral_vault: referral_vault.clone(),
-                balances: balances.clone(),
-                weights: weights.clone(),
-                swap_fee_in: fees_swap_in[index_in],
-                swap_fee_out: fees_swap_out[index_out],
+                math: math.clone(),
+                fees_swap_in: fees_swap_in.clone(),
+                fees_swap_out: fees_swap_out.clone(),
                 index_in,
                 index_out,
             });
@@ -202,12 +196,12 @@ impl Aftermath {
         &self,
         sender: SuiAddress,
         recipient: SuiAddress,
-        coin_in: ObjectRef,
+        coins_in: &[ObjectRef],
         amount_in: u64,
     ) -> Result<ProgrammableTransaction> {
         let mut ctx = TradeCtx::default();
 
-        let coin_in = ctx.split_coin(coin_in, amount_in)?;
+        let coin_in = ctx.split_coins(coins_in, amount_in)?;
         let coin_out = self.extend_trade_tx(&mut ctx, sender, coin_in, Some(amount_in)).await?;
         ctx.transfer_arg(recipient, coin_out);
 
@@ -249,19 +243,31 @@ impl Aftermath {
         ])
     }
 
+    #[inline]
+    fn swap_fees(&self) -> (u64, u64) {
+        (self.fees_swap_in[self.index_in], self.fees_swap_out[self.index_out])
+    }
+
     #[inline]
     fn expect_amount_out(&self, amount_in: u64) -> Result<u64> {
-        let amount_out = calculate_expected_out(
-            self.balances[self.index_in],
-            self.balances[self.index_out],
-            self.weights[self.index_in],
-            self.weights[self.index_out],
-            self.swap_fee_in,
-            self.swap_fee_out,
-            amount_in,
-        )?;
+        let (fee_in, fee_out) = self.swap_fees();
+        let amount_out = self
+            .math
+            .calc_out_given_in(self.index_in, self.index_out, fee_in, fee_out, amount_in);
+        if let Ok(amount_out) = amount_out {
+            return Ok(amount_out);
+        }
 
-        Ok(amount_out)
+        // the spot price is always defined
+        calculate_expected_out(
+            self.math.balances[self.index_in],
+            self.math.balances[self.index_out],
+            self.math.weights[self.index_in],
+            self.math.weights[self.index_out],
+            fee_in,
+            fee_out,
+            amount_in,
+        )
     }
 }
 
@@ -289,13 +295,13 @@ impl Dex for Aftermath {
 
     async fn swap_tx(&self, sender: SuiAddress, recipient: SuiAddress, amount_in: u64) -> Result<TransactionData> {
         let sui = new_test_sui_client().await;
-        let coin_in = coin::get_coin(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coins_in = coin::get_coins_for_amount(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coin_in_refs = coins_in.iter().map(|coin| coin.object_ref()).collect::<Vec<_>>();
 
-        let pt = self
-            .build_swap_tx(sender, recipient, coin_in.object_ref(), amount_in)
-            .await?;
+        let pt = self.build_swap_tx(sender, recipient, &coin_in_refs, amount_in).await?;
 
-        let gas_coins = coin::get_gas_coin_refs(&sui, sender, Some(coin_in.coin_object_id)).await?;
+        let exclude = coins_in.iter().map(|coin| coin.coin_object_id).collect::<Vec<_>>();
+        let gas_coins = coin::get_gas_coin_refs(&sui, sender, &GasCoinFilter::default().with_exclude(exclude)).await?;
         let gas_price = sui.read_api().get_reference_gas_price().await?;
         let tx_data = TransactionData::new_programmable(sender, gas_coins, pt, GAS_BUDGET, gas_price);
 
@@ -318,12 +324,20 @@ impl Dex for Aftermath {
         self.liquidity
     }
 
+    fn quote(&self, amount_in: u64) -> Option<u64> {
+        self.expect_amount_out(amount_in).ok()
+    }
+
     fn object_id(&self) -> ObjectID {
         self.pool_arg.id()
     }
 
     fn flip(&mut self) {
         std::mem::swap(&mut self.coin_in_type, &mut self.coin_out_type);
+        // the coin types are the last two type params, and the pool and its fees are read by index
+        let len = self.type_params.len();
+        self.type_params.swap(len - 2, len - 1);
+        std::mem::swap(&mut self.index_in, &mut self.index_out);
     }
 
     fn is_a2b(&self) -> bool {
@@ -331,6 +345,83 @@ impl Dex for Aftermath {
     }
 }
 
+const ONE_F64: f64 = 1e18;
+// halvings of the balance out searched by `calc_out_given_in`, enough to reach the precision of a f64
+const MAX_BISECTIONS: usize = 256;
+
+/// The state of a pool read by the swap math: the balances normalized to 18 decimals, the weights and the
+/// flatness in fixed point with 18 decimals, and the scalars that normalize the amounts of each coin.
+#[derive(Debug, Clone)]
+pub struct PoolMath {
+    pub balances: Vec<u128>,
+    pub weights: Vec<u64>,
+    pub flatness: u128,
+    pub decimal_scalars: Vec<u128>,
+}
+
+impl PoolMath {
+    /// The amount out of a swap of `amount_in`, keeping the invariant of the pool
+    /// `flatness * sum(w_i * b_i) + (1 - flatness) * prod(b_i ^ w_i)`: weighted pools have a flatness of 0,
+    /// stable pools of 1. The fee in is taken from the amount in, the fee out from the amount out.
+    pub fn calc_out_given_in(
+        &self,
+        index_in: usize,
+        index_out: usize,
+        swap_fee_in: u64,
+        swap_fee_out: u64,
+        amount_in: u64,
+    ) -> Result<u64> {
+        ensure!(index_in != index_out, "same coin in and out");
+        ensure!(self.flatness as f64 <= ONE_F64, "invalid flatness: {}", self.flatness);
+
+        let balances = self.balances.iter().map(|balance| *balance as f64).collect::<Vec<_>>();
+        let weights = self
+            .weights
+            .iter()
+            .map(|weight| *weight as f64 / ONE_F64)
+            .collect::<Vec<_>>();
+        let flatness = self.flatness as f64 / ONE_F64;
+        let amount_in = amount_in as f64 * self.decimal_scalars[index_in] as f64 * (1.0 - swap_fee_in as f64 / ONE_F64);
+
+        let (balance_in, balance_out) = (balances[index_in], balances[index_out]);
+        ensure!(balance_in > 0.0 && balance_out > 0.0, "empty pool");
+
+        let new_balance_out = if flatness == 0.0 {
+            // weighted: b_out * (b_in / (b_in + a)) ^ (w_in / w_out)
+            balance_out * (balance_in / (balance_in + amount_in)).powf(weights[index_in] / weights[index_out])
+        } else {
+            let invariant = |balances: &[f64]| {
+                let sum = balances.iter().zip(&weights).map(|(b, w)| w * b).sum::<f64>();
+                let product = balances.iter().zip(&weights).map(|(b, w)| b.powf(*w)).product::<f64>();
+                flatness * sum + (1.0 - flatness) * product
+            };
+            let target = invariant(&balances);
+
+            // the invariant grows with the balance out, so the new one is found by bisection, rounded up
+            let mut new_balances = balances.clone();
+            new_balances[index_in] += amount_in;
+            let (mut low, mut high) = (0.0, balance_out);
+            for _ in 0..MAX_BISECTIONS {
+                let mid = (low + high) / 2.0;
+                if mid <= low || mid >= high {
+                    break;
+                }
+                new_balances[index_out] = mid;
+                if invariant(&new_balances) >= target {
+                    high = mid;
+                } else {
+                    low = mid;
+                }
+            }
+            high
+        };
+        ensure!(new_balance_out.is_finite(), "no balance out for {}", amount_in);
+
+        let amount_out = (balance_out - new_balance_out).max(0.0) * (1.0 - swap_fee_out as f64 / ONE_F64);
+        Ok((amount_out / self.decimal_scalars[index_out] as f64) as u64)
+    }
+}
+
 /// Get an estimate for amount_out using the spot price.
 pub fn calculate_expected_out(
     balance_in: u128,
@@ -418,6 +509,7 @@ mod tests {
 
     use object_pool::ObjectPool;
     use simulator::{DBSimulator, Simulator};
+    use sui_types::{base_types::SequenceNumber, object::Owner};
     use tracing::info;
 
     use super::*;
@@ -426,14 +518,224 @@ mod tests {
         defi::{indexer_searcher::IndexerDexSearcher, DexSearcher},
     };
 
+    const E9: u128 = 1_000_000_000;
+    const E12: u128 = 1_000_000_000_000;
+    const HALF: u64 = 500_000_000_000_000_000;
+    const LP: &str = "0xf847c541b3076eea83cbaddcc244d25415b7c6828c1542cae4ab152d809896f6::af_lp::AF_LP";
+    const USDC: &str = "0xdba34672e30cb065b1f93e3ab55318768fd6fef66c15942c9f7cb846e2f900e7::usdc::USDC";
+    const USDT: &str = "0xc060006111016b8a020ad5b33834984a437aaa7d3c74c18e09a95d48aceab08c::coin::COIN";
+
+    fn assert_close(actual: u64, expected: u64) {
+        let tolerance = expected / 1_000_000_000 + 1;
+        assert!(actual.abs_diff(expected) <= tolerance, "{actual} != {expected}");
+    }
+
+    // the closed forms of the invariant, the other quotes are checked against simulated swaps in
+    // `test_quote_matches_simulated_swap`
+    #[test]
+    fn test_calc_out_given_in_weighted() {
+        // 50/50, 1000 coins of 9 decimals each: x * y = k
+        let math = PoolMath {
+            balances: vec![1_000 * E9 * E9, 1_000 * E9 * E9],
+            weights: vec![HALF, HALF],
+            flatness: 0,
+            decimal_scalars: vec![E9, E9],
+        };
+        assert_close(
+            math.calc_out_given_in(0, 1, 0, 0, 10 * E9 as u64).unwrap(),
+            9_900_990_099,
+        );
+
+        // 80/20, 4000 coins in and 1000 coins out
+        let math = PoolMath {
+            balances: vec![4_000 * E9 * E9, 1_000 * E9 * E9],
+            weights: vec![800_000_000_000_000_000, 200_000_000_000_000_000],
+            flatness: 0,
+            decimal_scalars: vec![E9, E9],
+        };
+        assert_close(
+            math.calc_out_given_in(0, 1, 0, 0, 100 * E9 as u64).unwrap(),
+            94_049_355_200,
+        );
+
+        // 1000 coins of 9 decimals in, 3000 coins of 6 decimals out, 0.3% fee in
+        let math = PoolMath {
+            balances: vec![1_000 * E9 * E9, 3_000 * E9 * E12 / 1_000],
+            weights: vec![HALF, HALF],
+            flatness: 0,
+            decimal_scalars: vec![E9, E12],
+        };
+        let fee_in = 3_000_000_000_000_000;
+        assert_close(
+            math.calc_out_given_in(0, 1, fee_in, 0, 10 * E9 as u64).unwrap(),
+            29_614_741,
+        );
+    }
+
+    #[test]
+    fn test_calc_out_given_in_stable() {
+        // 1M coins of 6 decimals each, a swap of 10% of the pool
+        let math = |flatness: u128| PoolMath {
+            balances: vec![1_000_000 * 1_000_000 * E12, 1_000_000 * 1_000_000 * E12],
+            weights: vec![HALF, HALF],
+            flatness,
+            decimal_scalars: vec![E12, E12],
+        };
+        let amount_in = 100_000 * 1_000_000;
+
+        // the weighted sum: 1 for 1, the weighted product: x * y = k
+        assert_close(
+            math(1_000_000_000_000_000_000)
+                .calc_out_given_in(0, 1, 0, 0, amount_in)
+                .unwrap(),
+            amount_in,
+        );
+        assert_close(
+            math(0).calc_out_given_in(0, 1, 0, 0, amount_in).unwrap(),
+            90_909_090_909,
+        );
+
+        // 0.01% fee out
+        assert_close(
+            math(1_000_000_000_000_000_000)
+                .calc_out_given_in(1, 0, 0, 100_000_000_000_000, amount_in)
+                .unwrap(),
+            99_990_000_000,
+        );
+    }
+
+    // a 3 coin pool, trading its first coin for its last one
+    fn aftermath(math: PoolMath, fees_swap_in: Vec<u64>, fees_swap_out: Vec<u64>) -> Aftermath {
+        let coins = ["0x2::sui::SUI", USDC, USDT];
+        let shared_obj = |mutable| ObjectArg::SharedObject {
+            id: ObjectID::random(),
+            initial_shared_version: SequenceNumber::from_u64(1),
+            mutable,
+        };
+        Aftermath {
+            pool_arg: shared_obj(true),
+            liquidity: 1_000_000,
+            coin_in_type: coins[0].to_string(),
+            coin_out_type: coins[2].to_string(),
+            type_params: [LP, coins[0], coins[2]]
+                .iter()
+                .map(|coin| TypeTag::from_str(coin).unwrap())
+                .collect(),
+            pool_registry: shared_obj(false),
+            protocol_fee_vault: shared_obj(false),
+            treasury: shared_obj(true),
+            insurance_fund: shared_obj(true),
+            referral_vault: shared_obj(false),
+            math,
+            fees_swap_in,
+            fees_swap_out,
+            index_in: 0,
+            index_out: 2,
+        }
+    }
+
+    #[test]
+    fn test_flip_reads_the_fees_of_the_new_coins() {
+        let math = PoolMath {
+            balances: vec![1_000 * E9 * E9, 3_000 * E9 * E9, 3_000 * E9 * E9],
+            weights: vec![
+                400_000_000_000_000_000,
+                300_000_000_000_000_000,
+                300_000_000_000_000_000,
+            ],
+            flatness: 0,
+            decimal_scalars: vec![E9, E12, E12],
+        };
+        // 1%, 0.3% and 0.5% in, 0.2%, 0.4% and 0.6% out
+        let fees_swap_in = vec![10_000_000_000_000_000, 3_000_000_000_000_000, 5_000_000_000_000_000];
+        let fees_swap_out = vec![2_000_000_000_000_000, 4_000_000_000_000_000, 6_000_000_000_000_000];
+        let mut dex = aftermath(math.clone(), fees_swap_in.clone(), fees_swap_out.clone());
+
+        let amount_in = 10 * E9 as u64;
+        let quote = math
+            .calc_out_given_in(0, 2, fees_swap_in[0], fees_swap_out[2], amount_in)
+            .unwrap();
+        assert_eq!(dex.quote(amount_in), Some(quote));
+
+        dex.flip();
+        assert_eq!(
+            (dex.coin_in_type(), dex.coin_out_type()),
+            (USDT.to_string(), "0x2::sui::SUI".to_string())
+        );
+        assert_eq!(
+            dex.type_params[1..],
+            [
+                TypeTag::from_str(USDT).unwrap(),
+                TypeTag::from_str("0x2::sui::SUI").unwrap()
+            ]
+        );
+        assert_eq!(dex.swap_fees(), (fees_swap_in[2], fees_swap_out[0]));
+        let amount_in = 10_000_000;
+        let quote = math
+            .calc_out_given_in(2, 0, fees_swap_in[2], fees_swap_out[0], amount_in)
+            .unwrap();
+        assert_eq!(dex.quote(amount_in), Some(quote));
+
+        // and back
+        dex.flip();
+        assert_eq!(dex.swap_fees(), (fees_swap_in[0], fees_swap_out[2]));
+    }
+
+    // the quotes of the pools against the amounts out of their swaps, simulated on the chain state of the test db
+    #[tokio::test]
+    async fn test_quote_matches_simulated_swap() {
+        let simulator_pool = Arc::new(ObjectPool::new_async(1, || async {
+            Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator>
+        }));
+
+        let owner = SuiAddress::from_str(TEST_ATTACKER).unwrap();
+        let recipient =
+            SuiAddress::from_str("0x0cbe287984143ef232336bb39397bd10607fa274707e8d0f91016dceb31bb829").unwrap();
+        let token_out_type = "0x5d4b302506645c37ff133b98c4b50a5ae14841659738d6d733d59d0d217a93bf::coin::COIN";
+        let coin_out = TypeTag::from_str(token_out_type).unwrap();
+
+        let searcher = IndexerDexSearcher::new(TEST_HTTP_URL, simulator_pool.clone())
+            .await
+            .unwrap();
+        let dexes = searcher
+            .find_dexes("0x2::sui::SUI", Some(token_out_type.into()))
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|dex| dex.protocol() == Protocol::Aftermath)
+            .collect::<Vec<_>>();
+        assert!(!dexes.is_empty());
+
+        let simulator = simulator_pool.get();
+        for dex in &dexes {
+            for amount_in in [1_000_000_000, 100_000_000_000] {
+                let quote = dex.quote(amount_in).unwrap();
+                let tx_data = dex.swap_tx(owner, recipient, amount_in).await.unwrap();
+                let response = simulator.simulate(tx_data, Default::default()).await.unwrap();
+                response.check_status().unwrap();
+                let amount_out = response
+                    .balance_changes
+                    .iter()
+                    .find(|bc| bc.owner == Owner::AddressOwner(recipient) && bc.coin_type == coin_out)
+                    .map(|bc| bc.amount as u64)
+                    .unwrap();
+
+                // the package rounds in fixed point
+                assert!(
+                    quote.abs_diff(amount_out) <= amount_out / 10_000 + 1,
+                    "{}: quoted {quote} for {amount_in}, swapped {amount_out}",
+                    dex.object_id()
+                );
+            }
+        }
+    }
+
     #[tokio::test]
     async fn test_aftermath_swap_tx() {
         mev_logger::init_console_logger_with_directives(None, &["arb=debug"]);
 
-        let simulator_pool = Arc::new(ObjectPool::new(1, move || {
-            tokio::runtime::Runtime::new()
-                .unwrap()
-                .block_on(async { Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator> })
+        let simulator_pool = Arc::new(ObjectPool::new_async(1, || async {
+            Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator>
         }));
 
         let owner = SuiAddress::from_str(TEST_ATTACKER).unwrap();
@@ -0,0 +1,136 @@
+use std::{collections::HashMap, fmt};
+
+use dex_indexer::types::Protocol;
+use sui_types::{transaction::Argument, TypeTag};
+
+/// A coarse type of a value of a trade PTB, as tracked by `TradeCtx::with_arg_tracking`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgKind {
+    Coin(TypeTag),
+    Balance(TypeTag),
+    // the hot potato of a flashloan, to be repaid to the protocol
+    Receipt(Protocol),
+    Pure,
+}
+
+impl fmt::Display for ArgKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgKind::Coin(coin_type) => write!(f, "Coin<{}>", coin_type),
+            ArgKind::Balance(coin_type) => write!(f, "Balance<{}>", coin_type),
+            ArgKind::Receipt(protocol) => write!(f, "Receipt({})", protocol),
+            ArgKind::Pure => write!(f, "Pure"),
+        }
+    }
+}
+
+/// A wiring bug of a trade PTB, that would only surface as an abort in simulation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgViolation {
+    /// `arg` is used by command `used_by` after command `consumed_by` took it by value
+    ConsumedReuse {
+        arg: Argument,
+        consumed_by: u16,
+        used_by: u16,
+    },
+    /// command `command` expects a `expected` but `arg` is a `found`
+    KindMismatch {
+        arg: Argument,
+        command: u16,
+        expected: ArgKind,
+        found: ArgKind,
+    },
+}
+
+impl fmt::Display for ArgViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgViolation::ConsumedReuse {
+                arg,
+                consumed_by,
+                used_by,
+            } => write!(
+                f,
+                "{:?} used by command {} after command {} took it by value",
+                arg, used_by, consumed_by
+            ),
+            ArgViolation::KindMismatch {
+                arg,
+                command,
+                expected,
+                found,
+            } => write!(
+                f,
+                "command {} expects a {} but {:?} is a {}",
+                command, expected, arg, found
+            ),
+        }
+    }
+}
+
+// The kinds of the annotated arguments and the command that consumed each, the arguments that were not
+// annotated are not checked.
+#[derive(Debug, Default)]
+pub(super) struct ArgTracker {
+    kinds: HashMap<Argument, ArgKind>,
+    consumed: HashMap<Argument, u16>,
+    violations: Vec<ArgViolation>,
+}
+
+impl ArgTracker {
+    pub fn annotate(&mut self, arg: Argument, kind: ArgKind) {
+        self.kinds.insert(arg, kind);
+    }
+
+    pub fn kind(&self, arg: Argument) -> Option<&ArgKind> {
+        self.kinds.get(&arg)
+    }
+
+    /// `arg` is used by reference by `command`.
+    pub fn borrow(&mut self, arg: Argument, expected: Option<&ArgKind>, command: u16) {
+        self.check_consumed(arg, command, command);
+        self.check_kind(arg, expected, command);
+    }
+
+    /// `arg` is taken by value by one of the commands from `first_command` to `command`, e.g. by the commands
+    /// of a hop, which may already have recorded it.
+    pub fn take(&mut self, arg: Argument, expected: Option<&ArgKind>, first_command: u16, command: u16) {
+        if self.check_consumed(arg, first_command, command) {
+            self.consumed.entry(arg).or_insert(command);
+        }
+        self.check_kind(arg, expected, command);
+    }
+
+    pub fn violations(&self) -> &[ArgViolation] {
+        &self.violations
+    }
+
+    // false if `arg` was consumed before `first_command`
+    fn check_consumed(&mut self, arg: Argument, first_command: u16, command: u16) -> bool {
+        match self.consumed.get(&arg) {
+            Some(&consumed_by) if consumed_by < first_command => {
+                self.violations.push(ArgViolation::ConsumedReuse {
+                    arg,
+                    consumed_by,
+                    used_by: command,
+                });
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn check_kind(&mut self, arg: Argument, expected: Option<&ArgKind>, command: u16) {
+        let (Some(expected), Some(found)) = (expected, self.kinds.get(&arg)) else {
+            return;
+        };
+        if expected != found {
+            self.violations.push(ArgViolation::KindMismatch {
+                arg,
+                command,
+                expected: expected.clone(),
+                found: found.clone(),
+            });
+        }
+    }
+}
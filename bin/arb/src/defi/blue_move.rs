@@ -2,20 +2,31 @@ use std::sync::Arc;
 
 use dex_indexer::types::{Pool, Protocol};
 use eyre::{ensure, eyre, OptionExt, Result};
-use move_core_types::annotated_value::MoveStruct;
+use move_core_types::annotated_value::{MoveStruct, MoveValue};
 use simulator::Simulator;
 use sui_types::{
     base_types::{ObjectID, ObjectRef, SuiAddress},
+    dynamic_field::extract_field_from_move_struct,
     transaction::{Argument, Command, ObjectArg, ProgrammableTransaction, TransactionData},
     Identifier, TypeTag,
 };
 use tokio::sync::OnceCell;
-use utils::{coin, new_test_sui_client, object::*};
+use utils::{
+    coin::{self, GasCoinFilter},
+    new_test_sui_client,
+    object::*,
+};
 
 use super::{TradeCtx, CETUS_AGGREGATOR};
 use crate::{config::*, defi::Dex};
 
+const BLUE_MOVE_DEX: &str = "0xb24b6789e088b876afabca733bed2299fbc9e2d6369be4d1acfa17d8145454d9";
 const DEX_INFO: &str = "0x3f2d9f724f4a1ce5e71676448dc452be9a6243dac9c5b975a588c8c867066e92";
+// the fees of a pool are in basis points
+const FEE_DENOMINATOR: u64 = 10_000;
+// the fields of `swap::Pool`, checked against the deployed package in `test_deployed_pool_fee_fields`
+const DEV_FEE_FIELD: &str = "dev_fee";
+const BURN_FEE_FIELD: &str = "burn_fee";
 
 static OBJ_CACHE: OnceCell<ObjectArgs> = OnceCell::const_new();
 
@@ -38,6 +49,41 @@ pub struct ObjectArgs {
     dex_info: ObjectArg,
 }
 
+/// The dev fee and token burn of the pools created with them, taken from the amount in. The Cetus aggregator
+/// swaps as if they were 0 and aborts on-chain, so these pools are swapped through the BlueMove router.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct FeeConfig {
+    dev_fee: u64,
+    burn_fee: u64,
+}
+
+impl FeeConfig {
+    // the plain pools have both fees at 0, a layout without the fields has no fees
+    fn parse(parsed_pool: &MoveStruct) -> Result<Self> {
+        let read_fee = |field_name: &str| match extract_field_from_move_struct(parsed_pool, field_name) {
+            None => Ok(0),
+            Some(MoveValue::U64(fee)) => Ok(*fee),
+            Some(other) => Err(eyre!("unexpected {}: {:?}", field_name, other)),
+        };
+
+        let fee_config = Self {
+            dev_fee: read_fee(DEV_FEE_FIELD)?,
+            burn_fee: read_fee(BURN_FEE_FIELD)?,
+        };
+        ensure!(
+            fee_config.dev_fee + fee_config.burn_fee < FEE_DENOMINATOR,
+            "pool takes the whole amount in: {:?}",
+            fee_config
+        );
+
+        Ok(fee_config)
+    }
+
+    fn is_plain(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 #[derive(Clone)]
 pub struct BlueMove {
     pool: Pool,
@@ -46,6 +92,7 @@ pub struct BlueMove {
     coin_out_type: String,
     type_params: Vec<TypeTag>,
     dex_info: ObjectArg,
+    fee_config: FeeConfig,
 }
 
 impl BlueMove {
@@ -68,6 +115,7 @@ impl BlueMove {
 
         let is_freeze = extract_bool_from_move_struct(&parsed_pool, "is_freeze")?;
         ensure!(!is_freeze, "pool is frozen");
+        let fee_config = FeeConfig::parse(&parsed_pool)?;
 
         let liquidity = {
             let lsp_supply = extract_struct_from_move_struct(&parsed_pool, "lsp_supply")?;
@@ -91,6 +139,7 @@ impl BlueMove {
             coin_out_type,
             type_params,
             dex_info,
+            fee_config,
         })
     }
 
@@ -98,12 +147,12 @@ impl BlueMove {
         &self,
         sender: SuiAddress,
         recipient: SuiAddress,
-        coin_in: ObjectRef,
+        coins_in: &[ObjectRef],
         amount_in: u64,
     ) -> Result<ProgrammableTransaction> {
         let mut ctx = TradeCtx::default();
 
-        let coin_in = ctx.split_coin(coin_in, amount_in)?;
+        let coin_in = ctx.split_coins(coins_in, amount_in)?;
         let coin_out = self.extend_trade_tx(&mut ctx, sender, coin_in, None).await?;
         ctx.transfer_arg(recipient, coin_out);
 
@@ -122,6 +171,41 @@ impl BlueMove {
 
         Ok(vec![dex_info_arg, coin_in_arg])
     }
+
+    /*
+    public fun swap_exact_input_<X, Y>(
+        amount_x_in: u64,
+        coin_x_in: Coin<X>,
+        amount_y_min_out: u64,
+        dex_info: &mut Dex_Info,
+        ctx: &mut TxContext,
+    ): Coin<Y>
+    */
+    fn build_router_swap_args(
+        &self,
+        ctx: &mut TradeCtx,
+        coin_in_arg: Argument,
+        amount_in: Option<u64>,
+    ) -> Result<Vec<Argument>> {
+        let amount_in_arg = match amount_in {
+            Some(amount_in) => ctx.pure(amount_in).map_err(|e| eyre!(e))?,
+            // the coin out of the previous hop
+            None => ctx.coin_value(coin_in_arg, self.router_type_params()[0].clone())?,
+        };
+        let min_out_arg = ctx.pure(0u64).map_err(|e| eyre!(e))?;
+        let dex_info_arg = ctx.obj(self.dex_info).map_err(|e| eyre!(e))?;
+
+        Ok(vec![amount_in_arg, coin_in_arg, min_out_arg, dex_info_arg])
+    }
+
+    // the router swaps X for Y, whichever the order of the coins in the pool
+    fn router_type_params(&self) -> Vec<TypeTag> {
+        let mut type_params = self.type_params.clone();
+        if !self.is_a2b() {
+            type_params.reverse();
+        }
+        type_params
+    }
 }
 
 #[async_trait::async_trait]
@@ -131,16 +215,25 @@ impl Dex for BlueMove {
         ctx: &mut TradeCtx,
         _sender: SuiAddress,
         coin_in: Argument,
-        _amount_in: Option<u64>,
+        amount_in: Option<u64>,
     ) -> Result<Argument> {
-        let function = if self.is_a2b() { "swap_a2b" } else { "swap_b2a" };
-
-        let package = ObjectID::from_hex_literal(CETUS_AGGREGATOR)?;
-        let module = Identifier::new("bluemove").map_err(|e| eyre!(e))?;
-        let function = Identifier::new(function).map_err(|e| eyre!(e))?;
-        let type_arguments = self.type_params.clone();
-        let arguments = self.build_swap_args(ctx, coin_in)?;
-        ctx.command(Command::move_call(package, module, function, type_arguments, arguments));
+        if self.fee_config.is_plain() {
+            let function = if self.is_a2b() { "swap_a2b" } else { "swap_b2a" };
+
+            let package = ObjectID::from_hex_literal(CETUS_AGGREGATOR)?;
+            let module = Identifier::new("bluemove").map_err(|e| eyre!(e))?;
+            let function = Identifier::new(function).map_err(|e| eyre!(e))?;
+            let type_arguments = self.type_params.clone();
+            let arguments = self.build_swap_args(ctx, coin_in)?;
+            ctx.command(Command::move_call(package, module, function, type_arguments, arguments));
+        } else {
+            let package = ObjectID::from_hex_literal(BLUE_MOVE_DEX)?;
+            let module = Identifier::new("router").map_err(|e| eyre!(e))?;
+            let function = Identifier::new("swap_exact_input_").map_err(|e| eyre!(e))?;
+            let type_arguments = self.router_type_params();
+            let arguments = self.build_router_swap_args(ctx, coin_in, amount_in)?;
+            ctx.command(Command::move_call(package, module, function, type_arguments, arguments));
+        }
 
         let last_idx = ctx.last_command_idx();
         Ok(Argument::Result(last_idx))
@@ -178,13 +271,13 @@ impl Dex for BlueMove {
     async fn swap_tx(&self, sender: SuiAddress, recipient: SuiAddress, amount_in: u64) -> Result<TransactionData> {
         let sui = new_test_sui_client().await;
 
-        let coin_in = coin::get_coin(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coins_in = coin::get_coins_for_amount(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coin_in_refs = coins_in.iter().map(|coin| coin.object_ref()).collect::<Vec<_>>();
 
-        let pt = self
-            .build_swap_tx(sender, recipient, coin_in.object_ref(), amount_in)
-            .await?;
+        let pt = self.build_swap_tx(sender, recipient, &coin_in_refs, amount_in).await?;
 
-        let gas_coins = coin::get_gas_coin_refs(&sui, sender, Some(coin_in.coin_object_id)).await?;
+        let exclude = coins_in.iter().map(|coin| coin.coin_object_id).collect::<Vec<_>>();
+        let gas_coins = coin::get_gas_coin_refs(&sui, sender, &GasCoinFilter::default().with_exclude(exclude)).await?;
         let gas_price = sui.read_api().get_reference_gas_price().await?;
         let tx_data = TransactionData::new_programmable(sender, gas_coins, pt, GAS_BUDGET, gas_price);
 
@@ -196,19 +289,211 @@ impl Dex for BlueMove {
 mod tests {
     use std::str::FromStr;
 
+    use dex_indexer::{
+        types::{PoolExtra, Token},
+        DexIndexer,
+    };
     use itertools::Itertools;
     use object_pool::ObjectPool;
     use simulator::DBSimulator;
     use simulator::HttpSimulator;
     use simulator::Simulator;
+    use sui_json_rpc_types::SuiMoveNormalizedType;
+    use sui_types::transaction::ProgrammableMoveCall;
     use tracing::info;
 
     use super::*;
     use crate::{
         config::tests::{TEST_ATTACKER, TEST_HTTP_URL},
         defi::{indexer_searcher::IndexerDexSearcher, DexSearcher},
+        tests::{move_struct, random_pool, shared_object},
     };
 
+    const COIN_X: &str = "0x2::sui::SUI";
+    const COIN_Y: &str = "0xed4504e791e1dad7bf93b41e089b4733c27f35fde505693e18186c2ba8e2e14b::suib::SUIB";
+
+    // a pool object with the fee fields read by `FeeConfig::parse`
+    fn doctored_pool(fees: &[(&str, u64)]) -> MoveStruct {
+        let mut fields = vec![("is_freeze", MoveValue::Bool(false))];
+        for (name, fee) in fees {
+            fields.push((*name, MoveValue::U64(*fee)));
+        }
+        move_struct("swap", "Pool", fields)
+    }
+
+    fn doctored_blue_move(coin_in_type: &str, coin_out_type: &str, fee_config: FeeConfig) -> BlueMove {
+        let tokens = vec![Token::new(COIN_X, 9), Token::new(COIN_Y, 9)];
+        BlueMove {
+            pool: random_pool(Protocol::BlueMove, tokens, PoolExtra::None),
+            liquidity: 1_000_000,
+            coin_in_type: coin_in_type.to_string(),
+            coin_out_type: coin_out_type.to_string(),
+            type_params: vec![TypeTag::from_str(COIN_X).unwrap(), TypeTag::from_str(COIN_Y).unwrap()],
+            dex_info: shared_object(ObjectID::from_hex_literal(DEX_INFO).unwrap()),
+            fee_config,
+        }
+    }
+
+    async fn swap_call(dex: &BlueMove, amount_in: Option<u64>) -> ProgrammableMoveCall {
+        let mut ctx = TradeCtx::default();
+        let coin_in = ctx.pure(0u64).unwrap();
+        dex.extend_trade_tx(&mut ctx, SuiAddress::ZERO, coin_in, amount_in)
+            .await
+            .unwrap();
+        match ctx.ptb.finish().commands.pop().unwrap() {
+            Command::MoveCall(call) => *call,
+            other => panic!("not a move call: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_fee_config() {
+        assert!(FeeConfig::parse(&doctored_pool(&[])).unwrap().is_plain());
+
+        let fee_config = FeeConfig::parse(&doctored_pool(&[(DEV_FEE_FIELD, 100), (BURN_FEE_FIELD, 200)])).unwrap();
+        assert_eq!(
+            fee_config,
+            FeeConfig {
+                dev_fee: 100,
+                burn_fee: 200
+            }
+        );
+        assert!(!fee_config.is_plain());
+
+        // nothing left to swap
+        assert!(FeeConfig::parse(&doctored_pool(&[(BURN_FEE_FIELD, FEE_DENOMINATOR)])).is_err());
+        assert!(FeeConfig::parse(&doctored_pool(&[(DEV_FEE_FIELD, 5_000), (BURN_FEE_FIELD, 5_000)])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fee_pool_swaps_through_router() {
+        let fee_config = FeeConfig {
+            dev_fee: 0,
+            burn_fee: 100,
+        };
+        let (x, y) = (TypeTag::from_str(COIN_X).unwrap(), TypeTag::from_str(COIN_Y).unwrap());
+
+        // x to y
+        let call = swap_call(&doctored_blue_move(COIN_X, COIN_Y, fee_config), Some(1_000)).await;
+        assert_eq!(call.package, ObjectID::from_hex_literal(BLUE_MOVE_DEX).unwrap());
+        assert_eq!(call.function.as_str(), "swap_exact_input_");
+        assert_eq!(call.type_arguments, vec![x.clone(), y.clone()]);
+        assert_eq!(call.arguments.len(), 4);
+
+        // y to x, the amount in read from the coin of the previous hop
+        let call = swap_call(&doctored_blue_move(COIN_Y, COIN_X, fee_config), None).await;
+        assert_eq!(call.function.as_str(), "swap_exact_input_");
+        assert_eq!(call.type_arguments, vec![y, x]);
+        assert_eq!(call.arguments[0], Argument::Result(0));
+
+        // the plain pools still go through the aggregator
+        let call = swap_call(&doctored_blue_move(COIN_Y, COIN_X, FeeConfig::default()), None).await;
+        assert_eq!(call.package, ObjectID::from_hex_literal(CETUS_AGGREGATOR).unwrap());
+        assert_eq!(call.function.as_str(), "swap_b2a");
+    }
+
+    #[tokio::test]
+    async fn test_deployed_pool_fee_fields() {
+        let sui = new_test_sui_client().await;
+        let package = ObjectID::from_hex_literal(BLUE_MOVE_DEX).unwrap();
+
+        let pool = sui
+            .read_api()
+            .get_normalized_move_struct(package, "swap".to_string(), "Pool".to_string())
+            .await
+            .unwrap();
+        for field_name in [DEV_FEE_FIELD, BURN_FEE_FIELD] {
+            let field = pool.fields.iter().find(|field| field.name == field_name);
+            assert!(
+                matches!(field, Some(field) if matches!(field.type_, SuiMoveNormalizedType::U64)),
+                "{field_name}: {field:?}"
+            );
+        }
+
+        // amount_x_in, coin_x_in, amount_y_min_out, dex_info and the tx context
+        let swap = sui
+            .read_api()
+            .get_normalized_move_function(package, "router".to_string(), "swap_exact_input_".to_string())
+            .await
+            .unwrap();
+        assert_eq!(swap.type_parameters.len(), 2);
+        assert_eq!(swap.parameters.len(), 5);
+        assert!(matches!(swap.parameters[0], SuiMoveNormalizedType::U64));
+        assert!(matches!(swap.parameters[2], SuiMoveNormalizedType::U64));
+    }
+
+    // a SUI pool of the test db with a dev fee or a burn fee
+    async fn fee_pool(simulator: Arc<Box<dyn Simulator>>) -> BlueMove {
+        let indexer = DexIndexer::new_with_protocols(TEST_HTTP_URL, &[Protocol::BlueMove])
+            .await
+            .unwrap();
+        for pool in indexer.get_all_pools(&Protocol::BlueMove).unwrap() {
+            if pool.token_index(COIN_X).is_none() {
+                continue;
+            }
+            match BlueMove::new(simulator.clone(), &pool, COIN_X).await {
+                Ok(dex) if !dex.fee_config.is_plain() => return dex,
+                _ => continue,
+            }
+        }
+        panic!("no fee pool in the test db");
+    }
+
+    // swaps `amount_in` of SUI through `dexes`, the later ones reading their amount in from the coin of the
+    // previous one
+    async fn swap_tx(dexes: &[BlueMove], sender: SuiAddress, amount_in: u64) -> TransactionData {
+        let sui = new_test_sui_client().await;
+        let coins_in = coin::get_coins_for_amount(&sui, sender, COIN_X, amount_in)
+            .await
+            .unwrap();
+        let coin_in_refs = coins_in.iter().map(|coin| coin.object_ref()).collect::<Vec<_>>();
+
+        let mut ctx = TradeCtx::default();
+        let mut coin = ctx.split_coins(&coin_in_refs, amount_in).unwrap();
+        for (i, dex) in dexes.iter().enumerate() {
+            let amount_in = (i == 0).then_some(amount_in);
+            coin = dex.extend_trade_tx(&mut ctx, sender, coin, amount_in).await.unwrap();
+        }
+        ctx.transfer_arg(sender, coin);
+
+        let exclude = coins_in.iter().map(|coin| coin.coin_object_id).collect::<Vec<_>>();
+        let gas_coins = coin::get_gas_coin_refs(&sui, sender, &GasCoinFilter::default().with_exclude(exclude))
+            .await
+            .unwrap();
+        let gas_price = sui.read_api().get_reference_gas_price().await.unwrap();
+        TransactionData::new_programmable(sender, gas_coins, ctx.ptb.finish(), GAS_BUDGET, gas_price)
+    }
+
+    #[tokio::test]
+    async fn test_fee_pool_swap_both_directions() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=debug"]);
+
+        let simulator: Arc<Box<dyn Simulator>> = Arc::new(Box::new(DBSimulator::new_test(true).await));
+        let sender = SuiAddress::from_str(TEST_ATTACKER).unwrap();
+        let amount_in = 1_000_000_000;
+
+        let dex = fee_pool(simulator.clone()).await;
+        info!("🧀 fee pool: {} {:?}", dex.object_id(), dex.fee_config);
+        let mut flipped = dex.clone();
+        flipped.flip();
+
+        // SUI to the other coin, then back to SUI through the flipped pool
+        let swap_event = format!("{BLUE_MOVE_DEX}::swap::Swap_Event");
+        for dexes in [vec![dex.clone()], vec![dex, flipped]] {
+            let tx_data = swap_tx(&dexes, sender, amount_in).await;
+            let response = simulator.simulate(tx_data, Default::default()).await.unwrap();
+            response.check_status().unwrap();
+
+            let swaps = response
+                .events
+                .data
+                .iter()
+                .filter(|event| event.type_.to_string() == swap_event)
+                .count();
+            assert_eq!(swaps, dexes.len(), "{:?}", response.events);
+        }
+    }
+
     #[tokio::test]
     async fn test_flowx_swap_tx() {
         mev_logger::init_console_logger_with_directives(None, &["arb=debug", "dex_indexer=debug"]);
@@ -222,10 +507,8 @@ mod tests {
         let token_out_type = "0x0bffc4f0333fb1256431156395a93fc252432152b0ff732197e8459a365e5a9f::suicat::SUICAT";
         let amount_in = 10000;
 
-        let simulator_pool = Arc::new(ObjectPool::new(1, move || {
-            tokio::runtime::Runtime::new()
-                .unwrap()
-                .block_on(async { Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator> })
+        let simulator_pool = Arc::new(ObjectPool::new_async(1, || async {
+            Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator>
         }));
 
         // find dexes and swap
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use dex_indexer::types::{Pool, Protocol};
-use eyre::{ensure, eyre, OptionExt, Result};
+use eyre::{ensure, eyre, Result};
 use move_core_types::annotated_value::MoveStruct;
 use simulator::Simulator;
 use sui_types::{
@@ -10,10 +10,18 @@ use sui_types::{
     Identifier, TypeTag, SUI_CLOCK_OBJECT_ID,
 };
 use tokio::sync::OnceCell;
-use utils::{coin, new_test_sui_client, object::*};
+use utils::{
+    coin::{self, GasCoinFilter},
+    new_test_sui_client,
+    object::*,
+};
 
-use super::{trade::FlashResult, TradeCtx};
-use crate::{config::*, defi::Dex};
+use super::{
+    trade::FlashResult,
+    utils::{clmm_amount_out, get_pool_object},
+    TradeCtx,
+};
+use crate::{config::*, defi::Dex, types::PoolPaused};
 
 const CETUS_DEX: &str = "0xeffc8ae61f439bb34c9b905ff8f29ec56873dcedf81c7123ff2f1f67c45ec302";
 const CONFIG: &str = "0xdaa46292632c3c4d8f31f23ea0f9b36a28ff3677e9684980e4438403a67a3d8f";
@@ -34,9 +42,10 @@ async fn get_object_args(simulator: Arc<Box<dyn Simulator>>) -> ObjectArgs {
             let config_id = ObjectID::from_hex_literal(CONFIG).unwrap();
             let partner_id = ObjectID::from_hex_literal(PARTNER).unwrap();
 
-            let config = simulator.get_object(&config_id).await.unwrap();
-            let partner = simulator.get_object(&partner_id).await.unwrap();
-            let clock = simulator.get_object(&SUI_CLOCK_OBJECT_ID).await.unwrap();
+            let objects = simulator
+                .multi_get_objects(&[config_id, partner_id, SUI_CLOCK_OBJECT_ID])
+                .await;
+            let [config, partner, clock] = <[_; 3]>::try_from(objects).unwrap().map(Option::unwrap);
 
             ObjectArgs {
                 config: shared_obj_arg(&config, false),
@@ -48,11 +57,21 @@ async fn get_object_args(simulator: Arc<Box<dyn Simulator>>) -> ObjectArgs {
         .clone()
 }
 
+#[cfg(test)]
+pub fn object_args_initialized() -> bool {
+    OBJ_CACHE.initialized()
+}
+
+// `fee_rate` of a pool is in millionths
+const FEE_DENOMINATOR: u64 = 1_000_000;
+
 #[derive(Clone)]
 pub struct Cetus {
     pool: Pool,
     pool_arg: ObjectArg,
     liquidity: u128,
+    sqrt_price: u128,
+    fee_rate: u64,
     coin_in_type: String,
     coin_out_type: String,
     type_params: Vec<TypeTag>,
@@ -65,24 +84,9 @@ impl Cetus {
     pub async fn new(simulator: Arc<Box<dyn Simulator>>, pool: &Pool, coin_in_type: &str) -> Result<Self> {
         ensure!(pool.protocol == Protocol::Cetus, "not a Cetus pool");
 
-        let pool_obj = simulator
-            .get_object(&pool.pool)
-            .await
-            .ok_or_else(|| eyre!("pool not found: {}", pool.pool))?;
-
-        let parsed_pool = {
-            let layout = simulator
-                .get_object_layout(&pool.pool)
-                .ok_or_eyre("pool layout not found")?;
-
-            let move_obj = pool_obj.data.try_as_move().ok_or_eyre("not a move object")?;
-            MoveStruct::simple_deserialize(move_obj.contents(), &layout).map_err(|e| eyre!(e))?
-        };
-
-        let is_pause = extract_bool_from_move_struct(&parsed_pool, "is_pause")?;
-        ensure!(!is_pause, "pool is paused");
-
-        let liquidity = extract_u128_from_move_struct(&parsed_pool, "liquidity")?;
+        let (pool_obj, parsed_pool) = get_pool_object(&**simulator, &pool.pool).await?;
+        let PoolState { liquidity, sqrt_price } = PoolState::parse(pool.pool, &parsed_pool)?;
+        let fee_rate = extract_u64_from_move_struct(&parsed_pool, "fee_rate")?;
 
         let coin_out_type = if pool.token0_type() == coin_in_type {
             pool.token1_type().to_string()
@@ -98,6 +102,8 @@ impl Cetus {
         Ok(Self {
             pool: pool.clone(),
             liquidity,
+            sqrt_price,
+            fee_rate,
             coin_in_type: coin_in_type.to_string(),
             coin_out_type,
             type_params,
@@ -112,12 +118,12 @@ impl Cetus {
         &self,
         sender: SuiAddress,
         recipient: SuiAddress,
-        coin_in: ObjectRef,
+        coins_in: &[ObjectRef],
         amount_in: u64,
     ) -> Result<ProgrammableTransaction> {
         let mut ctx = TradeCtx::default();
 
-        let coin_in = ctx.split_coin(coin_in, amount_in)?;
+        let coin_in = ctx.split_coins(coins_in, amount_in)?;
         let coin_out = self.extend_trade_tx(&mut ctx, sender, coin_in, None).await?;
         ctx.transfer_arg(recipient, coin_out);
 
@@ -187,6 +193,32 @@ impl Cetus {
     }
 }
 
+// the state of a pool that changes between swaps
+#[derive(Debug, PartialEq, Eq)]
+struct PoolState {
+    liquidity: u128,
+    sqrt_price: u128,
+}
+
+impl PoolState {
+    // `PoolPaused` if the pool can't be traded
+    fn parse(pool_id: ObjectID, parsed_pool: &MoveStruct) -> Result<Self> {
+        let is_pause = extract_bool_from_move_struct(parsed_pool, "is_pause")?;
+        if is_pause {
+            return Err(PoolPaused {
+                pool_id,
+                protocol: Protocol::Cetus,
+            }
+            .into());
+        }
+
+        Ok(Self {
+            liquidity: extract_u128_from_move_struct(parsed_pool, "liquidity")?,
+            sqrt_price: extract_u128_from_move_struct(parsed_pool, "current_sqrt_price")?,
+        })
+    }
+}
+
 #[async_trait::async_trait]
 impl Dex for Cetus {
     fn support_flashloan(&self) -> bool {
@@ -270,6 +302,25 @@ impl Dex for Cetus {
         self.liquidity
     }
 
+    async fn refresh(&mut self, simulator: Arc<Box<dyn Simulator>>) -> Result<()> {
+        let (_, parsed_pool) = get_pool_object(&**simulator, &self.pool.pool).await?;
+        let PoolState { liquidity, sqrt_price } = PoolState::parse(self.pool.pool, &parsed_pool)?;
+        self.liquidity = liquidity;
+        self.sqrt_price = sqrt_price;
+        Ok(())
+    }
+
+    fn quote(&self, amount_in: u64) -> Option<u64> {
+        Some(clmm_amount_out(
+            self.sqrt_price,
+            self.liquidity,
+            self.fee_rate,
+            FEE_DENOMINATOR,
+            amount_in,
+            self.is_a2b(),
+        ))
+    }
+
     fn object_id(&self) -> ObjectID {
         self.pool.pool
     }
@@ -286,13 +337,13 @@ impl Dex for Cetus {
     async fn swap_tx(&self, sender: SuiAddress, recipient: SuiAddress, amount_in: u64) -> Result<TransactionData> {
         let sui = new_test_sui_client().await;
 
-        let coin_in = coin::get_coin(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coins_in = coin::get_coins_for_amount(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coin_in_refs = coins_in.iter().map(|coin| coin.object_ref()).collect::<Vec<_>>();
 
-        let pt = self
-            .build_swap_tx(sender, recipient, coin_in.object_ref(), amount_in)
-            .await?;
+        let pt = self.build_swap_tx(sender, recipient, &coin_in_refs, amount_in).await?;
 
-        let gas_coins = coin::get_gas_coin_refs(&sui, sender, Some(coin_in.coin_object_id)).await?;
+        let exclude = coins_in.iter().map(|coin| coin.coin_object_id).collect::<Vec<_>>();
+        let gas_coins = coin::get_gas_coin_refs(&sui, sender, &GasCoinFilter::default().with_exclude(exclude)).await?;
         let gas_price = sui.read_api().get_reference_gas_price().await?;
         let tx_data = TransactionData::new_programmable(sender, gas_coins, pt, GAS_BUDGET, gas_price);
 
@@ -305,6 +356,7 @@ mod tests {
     use std::{str::FromStr, time::Instant};
 
     use itertools::Itertools;
+    use move_core_types::annotated_value::MoveValue;
     use object_pool::ObjectPool;
     use simulator::{DBSimulator, SimulateCtx, Simulator};
     use sui_sdk::SuiClientBuilder;
@@ -315,8 +367,40 @@ mod tests {
         common::get_latest_epoch,
         config::tests::{TEST_ATTACKER, TEST_HTTP_URL},
         defi::{indexer_searcher::IndexerDexSearcher, DexSearcher},
+        tests::move_struct,
     };
 
+    // a pool object with the fields read by `PoolState::parse`
+    fn doctored_pool(is_pause: bool) -> MoveStruct {
+        let fields = vec![
+            ("is_pause", MoveValue::Bool(is_pause)),
+            ("liquidity", MoveValue::U128(1_000_000)),
+            ("current_sqrt_price", MoveValue::U128(1 << 64)),
+        ];
+        move_struct("pool", "Pool", fields)
+    }
+
+    #[test]
+    fn test_paused_pool_state() {
+        let pool_id = ObjectID::random();
+        assert_eq!(
+            PoolState::parse(pool_id, &doctored_pool(false)).unwrap(),
+            PoolState {
+                liquidity: 1_000_000,
+                sqrt_price: 1 << 64
+            }
+        );
+
+        let error = PoolState::parse(pool_id, &doctored_pool(true)).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<PoolPaused>(),
+            Some(&PoolPaused {
+                pool_id,
+                protocol: Protocol::Cetus
+            })
+        );
+    }
+
     // cargo test --package arb --bin arb --all-features -- defi::cetus::tests::test_cetus_swap_tx --exact --show-output
     #[tokio::test]
     async fn test_cetus_swap_tx() {
@@ -329,10 +413,8 @@ mod tests {
         let token_out_type = "0xdeeb7a4662eec9f2f3def03fb937a663dddaa2e215b8078a284d026b7946c270::deep::DEEP";
         let amount_in = 10000;
 
-        let simulator_pool = Arc::new(ObjectPool::new(1, move || {
-            tokio::runtime::Runtime::new()
-                .unwrap()
-                .block_on(async { Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator> })
+        let simulator_pool = Arc::new(ObjectPool::new_async(1, || async {
+            Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator>
         }));
 
         // find dexes and swap
@@ -0,0 +1,260 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use dex_indexer::types::Protocol;
+use eyre::Result;
+use simulator::Simulator;
+use sui_types::{
+    base_types::{ObjectID, SuiAddress},
+    transaction::{Argument, TransactionData},
+};
+
+use super::{trade::FlashResult, Dex, TradeCtx};
+
+pub const DEFAULT_DEX_CACHE_TTL: Duration = Duration::from_secs(5);
+
+static DEX_CACHE: OnceLock<Arc<DexCache>> = OnceLock::new();
+
+/// Set the TTL of the process-wide `DexCache` at startup, returns false if it was already initialized.
+pub fn init_dex_cache(ttl: Duration) -> bool {
+    DEX_CACHE.set(Arc::new(DexCache::new(ttl))).is_ok()
+}
+
+/// The `DexCache` shared by the searchers of all workers, with `DEFAULT_DEX_CACHE_TTL` if not initialized.
+pub fn dex_cache() -> Arc<DexCache> {
+    DEX_CACHE
+        .get_or_init(|| Arc::new(DexCache::new(DEFAULT_DEX_CACHE_TTL)))
+        .clone()
+}
+
+struct CachedDexes {
+    dexes: Vec<Box<dyn Dex>>,
+    cached_at: Instant,
+}
+
+/// The dexes built from a pool, by `(pool_id, coin_in_type)`, for every coin_out_type of the pool.
+///
+/// Building a dex reads the pool through a simulator, which dominates the path search, and a pool rarely
+/// changes within a few seconds. An entry expires after `ttl`, or once a swap on its pool is observed in a
+/// public tx or a shio auction (`invalidate_pool`). The dexes handed out read their liquidity at `cached_at`, see `Dex::liquidity_age`.
+pub struct DexCache {
+    ttl: Duration,
+    // by pool then by coin_in_type, so that a pool is invalidated at once
+    entries: Mutex<HashMap<ObjectID, HashMap<String, CachedDexes>>>,
+}
+
+impl DexCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The cached dexes of `pool_id` from `coin_in_type`, `None` if missing or expired.
+    pub fn get(&self, pool_id: ObjectID, coin_in_type: &str) -> Option<Vec<Box<dyn Dex>>> {
+        let mut entries = self.entries.lock().unwrap();
+        let pool_entries = entries.get_mut(&pool_id)?;
+        let entry = pool_entries.get(coin_in_type)?;
+        if entry.cached_at.elapsed() >= self.ttl {
+            pool_entries.remove(coin_in_type);
+            if pool_entries.is_empty() {
+                entries.remove(&pool_id);
+            }
+            return None;
+        }
+
+        Some(
+            entry
+                .dexes
+                .iter()
+                .map(|dex| CachedDex::new(dex.clone(), entry.cached_at))
+                .collect(),
+        )
+    }
+
+    /// Cache the freshly built `dexes` and hand them out, already marked as cached.
+    pub fn insert(&self, pool_id: ObjectID, coin_in_type: &str, dexes: Vec<Box<dyn Dex>>) -> Vec<Box<dyn Dex>> {
+        let cached_at = Instant::now();
+        let handed_out = dexes.iter().map(|dex| CachedDex::new(dex.clone(), cached_at)).collect();
+
+        let mut entries = self.entries.lock().unwrap();
+        // drop the expired entries of the pools nobody asks for anymore
+        if entries.len() % 1024 == 1023 {
+            let ttl = self.ttl;
+            entries.retain(|_, pool_entries| {
+                pool_entries.retain(|_, entry| entry.cached_at.elapsed() < ttl);
+                !pool_entries.is_empty()
+            });
+        }
+        entries
+            .entry(pool_id)
+            .or_default()
+            .insert(coin_in_type.to_string(), CachedDexes { dexes, cached_at });
+
+        handed_out
+    }
+
+    /// Drop the dexes of `pool_id` in both directions, e.g. after a swap on it.
+    pub fn invalidate_pool(&self, pool_id: ObjectID) {
+        self.entries.lock().unwrap().remove(&pool_id);
+    }
+}
+
+// a dex handed out by the `DexCache`, which knows when its liquidity was read
+#[derive(Clone)]
+struct CachedDex {
+    inner: Box<dyn Dex>,
+    cached_at: Instant,
+}
+
+impl CachedDex {
+    fn new(inner: Box<dyn Dex>, cached_at: Instant) -> Box<dyn Dex> {
+        Box::new(Self { inner, cached_at })
+    }
+}
+
+#[async_trait::async_trait]
+impl Dex for CachedDex {
+    fn support_flashloan(&self) -> bool {
+        self.inner.support_flashloan()
+    }
+
+    async fn extend_flashloan_tx(&self, ctx: &mut TradeCtx, amount: u64) -> Result<FlashResult> {
+        self.inner.extend_flashloan_tx(ctx, amount).await
+    }
+
+    async fn extend_repay_tx(&self, ctx: &mut TradeCtx, coin: Argument, flash_res: FlashResult) -> Result<Argument> {
+        self.inner.extend_repay_tx(ctx, coin, flash_res).await
+    }
+
+    async fn extend_trade_tx(
+        &self,
+        ctx: &mut TradeCtx,
+        sender: SuiAddress,
+        coin_in: Argument,
+        amount_in: Option<u64>,
+    ) -> Result<Argument> {
+        self.inner.extend_trade_tx(ctx, sender, coin_in, amount_in).await
+    }
+
+    fn coin_in_type(&self) -> String {
+        self.inner.coin_in_type()
+    }
+
+    fn coin_out_type(&self) -> String {
+        self.inner.coin_out_type()
+    }
+
+    fn protocol(&self) -> Protocol {
+        self.inner.protocol()
+    }
+
+    fn liquidity(&self) -> u128 {
+        self.inner.liquidity()
+    }
+
+    fn quote(&self, amount_in: u64) -> Option<u64> {
+        self.inner.quote(amount_in)
+    }
+
+    async fn refresh(&mut self, simulator: Arc<Box<dyn Simulator>>) -> Result<()> {
+        self.inner.refresh(simulator).await?;
+        self.cached_at = Instant::now();
+        Ok(())
+    }
+
+    fn liquidity_age(&self) -> Option<Duration> {
+        Some(self.cached_at.elapsed())
+    }
+
+    fn object_id(&self) -> ObjectID {
+        self.inner.object_id()
+    }
+
+    fn flip(&mut self) {
+        self.inner.flip();
+    }
+
+    fn is_a2b(&self) -> bool {
+        self.inner.is_a2b()
+    }
+
+    async fn swap_tx(&self, sender: SuiAddress, recipient: SuiAddress, amount_in: u64) -> Result<TransactionData> {
+        self.inner.swap_tx(sender, recipient, amount_in).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sui_sdk::SUI_COIN_TYPE;
+
+    use super::*;
+    use crate::defi::StubDex;
+
+    const COIN_A: &str = "0x1::a::A";
+
+    fn stub_dexes(pool_id: ObjectID) -> Vec<Box<dyn Dex>> {
+        vec![Box::new(StubDex::new(pool_id, COIN_A, SUI_COIN_TYPE, 1_000_000))]
+    }
+
+    #[test]
+    fn test_hit_within_ttl() {
+        let cache = DexCache::new(Duration::from_secs(60));
+        let pool_id = ObjectID::random();
+        assert!(cache.get(pool_id, COIN_A).is_none());
+
+        let dexes = cache.insert(pool_id, COIN_A, stub_dexes(pool_id));
+        assert_eq!(dexes.len(), 1);
+        assert!(dexes[0].liquidity_age().is_some());
+
+        let dexes = cache.get(pool_id, COIN_A).unwrap();
+        assert_eq!(dexes[0].object_id(), pool_id);
+        assert_eq!(dexes[0].coin_out_type(), SUI_COIN_TYPE);
+        assert_eq!(dexes[0].liquidity(), 1_000_000);
+        // the other direction is a different entry
+        assert!(cache.get(pool_id, SUI_COIN_TYPE).is_none());
+    }
+
+    #[test]
+    fn test_expired_after_ttl() {
+        let cache = DexCache::new(Duration::from_millis(20));
+        let pool_id = ObjectID::random();
+        cache.insert(pool_id, COIN_A, stub_dexes(pool_id));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(cache.get(pool_id, COIN_A).is_none());
+        assert!(cache.entries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalidate_pool() {
+        let cache = DexCache::new(Duration::from_secs(60));
+        let (pool1, pool2) = (ObjectID::random(), ObjectID::random());
+        cache.insert(pool1, COIN_A, stub_dexes(pool1));
+        cache.insert(pool1, SUI_COIN_TYPE, stub_dexes(pool1));
+        cache.insert(pool2, COIN_A, stub_dexes(pool2));
+
+        cache.invalidate_pool(pool1);
+        assert!(cache.get(pool1, COIN_A).is_none());
+        assert!(cache.get(pool1, SUI_COIN_TYPE).is_none());
+        assert!(cache.get(pool2, COIN_A).is_some());
+    }
+
+    #[test]
+    fn test_flip_keeps_cached_entry() {
+        let cache = DexCache::new(Duration::from_secs(60));
+        let pool_id = ObjectID::random();
+        cache.insert(pool_id, COIN_A, stub_dexes(pool_id));
+
+        let mut dex = cache.get(pool_id, COIN_A).unwrap().pop().unwrap();
+        dex.flip();
+        assert_eq!(dex.coin_in_type(), SUI_COIN_TYPE);
+
+        let dex = cache.get(pool_id, COIN_A).unwrap().pop().unwrap();
+        assert_eq!(dex.coin_in_type(), COIN_A);
+    }
+}
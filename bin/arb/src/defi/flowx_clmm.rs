@@ -11,7 +11,8 @@ use sui_types::{
 };
 use tokio::sync::OnceCell;
 use utils::{
-    coin, new_test_sui_client,
+    coin::{self, GasCoinFilter},
+    new_test_sui_client,
     object::{extract_u128_from_move_struct, shared_obj_arg},
 };
 
@@ -124,12 +125,12 @@ impl FlowxClmm {
         &self,
         sender: SuiAddress,
         recipient: SuiAddress,
-        coin_in: ObjectRef,
+        coins_in: &[ObjectRef],
         amount_in: u64,
     ) -> Result<ProgrammableTransaction> {
         let mut ctx = TradeCtx::default();
 
-        let coin_in = ctx.split_coin(coin_in, amount_in)?;
+        let coin_in = ctx.split_coins(coins_in, amount_in)?;
         let coin_out = self.extend_trade_tx(&mut ctx, sender, coin_in, None).await?;
         ctx.transfer_arg(recipient, coin_out);
 
@@ -405,13 +406,13 @@ impl Dex for FlowxClmm {
     async fn swap_tx(&self, sender: SuiAddress, recipient: SuiAddress, amount_in: u64) -> Result<TransactionData> {
         let sui = new_test_sui_client().await;
 
-        let coin_in = coin::get_coin(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coins_in = coin::get_coins_for_amount(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coin_in_refs = coins_in.iter().map(|coin| coin.object_ref()).collect::<Vec<_>>();
 
-        let pt = self
-            .build_swap_tx(sender, recipient, coin_in.object_ref(), amount_in)
-            .await?;
+        let pt = self.build_swap_tx(sender, recipient, &coin_in_refs, amount_in).await?;
 
-        let gas_coins = coin::get_gas_coin_refs(&sui, sender, Some(coin_in.coin_object_id)).await?;
+        let exclude = coins_in.iter().map(|coin| coin.coin_object_id).collect::<Vec<_>>();
+        let gas_coins = coin::get_gas_coin_refs(&sui, sender, &GasCoinFilter::default().with_exclude(exclude)).await?;
         let gas_price = sui.read_api().get_reference_gas_price().await?;
         let tx_data = TransactionData::new_programmable(sender, gas_coins, pt, GAS_BUDGET, gas_price);
 
@@ -449,10 +450,8 @@ mod tests {
         let token_out_type = "0xdeeb7a4662eec9f2f3def03fb937a663dddaa2e215b8078a284d026b7946c270::deep::DEEP";
         let amount_in = 10000;
 
-        let simulator_pool = Arc::new(ObjectPool::new(1, move || {
-            tokio::runtime::Runtime::new()
-                .unwrap()
-                .block_on(async { Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator> })
+        let simulator_pool = Arc::new(ObjectPool::new_async(1, || async {
+            Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator>
         }));
 
         // find dexes and swap
@@ -1,35 +1,62 @@
 use dex_indexer::{
-    types::{Pool, Protocol},
-    DexIndexer,
+    normalize_coin_type, supported_protocols,
+    types::{Pool, Protocol, SwapEvent},
+    DexIndexer, PoolState,
 };
-use eyre::{bail, ensure, OptionExt, Result};
+use eyre::{bail, ensure, eyre, Result};
 use object_pool::ObjectPool;
 use simulator::Simulator;
-use std::sync::Arc;
+use std::{fmt, sync::Arc};
 use sui_sdk::SUI_COIN_TYPE;
 use sui_types::base_types::ObjectID;
 use tokio::sync::OnceCell;
 use tokio::task::JoinSet;
 
 use super::{
-    aftermath::Aftermath, cetus::Cetus, deepbook_v2::DeepbookV2, flowx_clmm::FlowxClmm, turbos::Turbos, Dex,
-    DexSearcher, Path,
+    aftermath::Aftermath, cetus::Cetus, deepbook_v2::DeepbookV2, dex_cache, flowx_clmm::FlowxClmm, turbos::Turbos, Dex,
+    DexCache, DexSearcher, Path, ProtocolFilter,
 };
 use crate::defi::{blue_move::BlueMove, kriya_amm::KriyaAmm, kriya_clmm::KriyaClmm};
 
 static INDEXER: OnceCell<Arc<DexIndexer>> = OnceCell::const_new();
 
+/// Stop syncing the pools of the process-wide `DexIndexer`, if it was started.
+pub async fn shutdown_indexer() {
+    if let Some(indexer) = INDEXER.get() {
+        indexer.shutdown().await;
+    }
+}
+
+/// Record the swap of `swap_event`, executed at `timestamp_ms` if it was, in the pool states of the process-wide
+/// `DexIndexer`, if it was started, see `DexSearcher::pool_state`.
+pub fn record_swap_event(swap_event: &SwapEvent, timestamp_ms: Option<u64>) {
+    if let Some(indexer) = INDEXER.get() {
+        indexer.update_pool_state(swap_event, timestamp_ms);
+    }
+}
+
 #[derive(Clone)]
 pub struct IndexerDexSearcher {
     simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
     indexer: Arc<DexIndexer>,
+    cache: Arc<DexCache>,
 }
 
 impl IndexerDexSearcher {
     pub async fn new(http_url: &str, simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>) -> Result<Self> {
+        Self::new_with_protocols(http_url, simulator_pool, &supported_protocols()).await
+    }
+
+    /// Same as `new`, with only the pools of `protocols` loaded and synced. The process-wide `DexIndexer` is
+    /// started with the `protocols` of the first searcher.
+    pub async fn new_with_protocols(
+        http_url: &str,
+        simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
+        protocols: &[Protocol],
+    ) -> Result<Self> {
         let indexer = INDEXER
             .get_or_init(|| async {
-                let indexer = DexIndexer::new(http_url).await.unwrap();
+                let indexer = DexIndexer::new_with_protocols(http_url, protocols).await.unwrap();
                 Arc::new(indexer)
             })
             .await
@@ -38,16 +65,30 @@ impl IndexerDexSearcher {
         Ok(Self {
             simulator_pool,
             indexer,
+            cache: dex_cache(),
         })
     }
+
+    /// Use `cache` instead of the process-wide `DexCache`.
+    #[cfg(test)]
+    pub fn with_dex_cache(mut self, cache: Arc<DexCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    // the dexes of `pool` from `token_in_type` to any of its other coins
+    async fn dexes(&self, pool: &Pool, token_in_type: &str) -> Result<Vec<Box<dyn Dex>>> {
+        if let Some(dexes) = self.cache.get(pool.pool, token_in_type) {
+            return Ok(dexes);
+        }
+
+        let simulator = self.simulator_pool.get();
+        let dexes = new_dexes(simulator, pool, token_in_type).await?;
+        Ok(self.cache.insert(pool.pool, token_in_type, dexes))
+    }
 }
 
-async fn new_dexes(
-    simulator: Arc<Box<dyn Simulator>>,
-    pool: &Pool,
-    token_in_type: &str,
-    token_out_type: Option<String>,
-) -> Result<Vec<Box<dyn Dex>>> {
+async fn new_dexes(simulator: Arc<Box<dyn Simulator>>, pool: &Pool, token_in_type: &str) -> Result<Vec<Box<dyn Dex>>> {
     let dexes = match pool.protocol {
         Protocol::Turbos => {
             let dex = Turbos::new(simulator, pool, token_in_type).await?;
@@ -59,7 +100,7 @@ async fn new_dexes(
             vec![Box::new(dex) as Box<dyn Dex>]
         }
 
-        Protocol::Aftermath => Aftermath::new(simulator, pool, token_in_type, token_out_type)
+        Protocol::Aftermath => Aftermath::new(simulator, pool, token_in_type, None)
             .await?
             .into_iter()
             .map(|dex| Box::new(dex) as Box<dyn Dex>)
@@ -97,7 +138,12 @@ async fn new_dexes(
 
 #[async_trait::async_trait]
 impl DexSearcher for IndexerDexSearcher {
-    async fn find_dexes(&self, token_in_type: &str, token_out_type: Option<String>) -> Result<Vec<Box<dyn Dex>>> {
+    async fn find_dexes_filtered(
+        &self,
+        token_in_type: &str,
+        token_out_type: Option<String>,
+        filter: &ProtocolFilter,
+    ) -> Result<Vec<Box<dyn Dex>>> {
         let pools = if let Some(token_out_type) = token_out_type.as_ref() {
             self.indexer.get_pools_by_token01(token_in_type, token_out_type)
         } else {
@@ -111,11 +157,11 @@ impl DexSearcher for IndexerDexSearcher {
         );
 
         let mut join_set = JoinSet::new();
-        for pool in pools.unwrap() {
-            let simulator = self.simulator_pool.get();
+        // the denied pools are dropped before their dexes are built
+        for pool in pools.unwrap().into_iter().filter(|pool| filter.allows(&pool.protocol)) {
+            let searcher = self.clone();
             let token_in_type = token_in_type.to_string();
-            let token_out_type = token_out_type.clone();
-            join_set.spawn(async move { new_dexes(simulator, &pool, &token_in_type, token_out_type).await });
+            join_set.spawn(async move { searcher.dexes(&pool, &token_in_type).await });
         }
 
         let mut res = Vec::new();
@@ -128,21 +174,281 @@ impl DexSearcher for IndexerDexSearcher {
             }
         }
 
+        // the dexes are cached for every coin_out_type of their pool, e.g. of an Aftermath pool with 3 coins
+        if let Some(token_out_type) = token_out_type {
+            res.retain(|dex| dex.coin_out_type() == token_out_type);
+        }
+
         Ok(res)
     }
 
-    async fn find_test_path(&self, path: &[ObjectID]) -> Result<Path> {
-        let mut dexes = vec![];
-        let mut coin_in = SUI_COIN_TYPE.to_string();
-
+    async fn find_test_path(&self, path: &[ObjectID], coin_in: Option<&str>, filter: &ProtocolFilter) -> Result<Path> {
+        let mut pools = Vec::with_capacity(path.len());
         for pool_id in path {
-            let simulator = self.simulator_pool.get();
-            let pool = self.indexer.get_pool_by_id(pool_id).ok_or_eyre("pool not found")?;
-            let dex = new_dexes(simulator, &pool, &coin_in, None).await?.pop().unwrap();
-            coin_in = dex.coin_out_type();
+            let pool = self
+                .indexer
+                .get_pool_by_id(pool_id)
+                .ok_or_else(|| eyre!("pool {} not found", pool_id))?;
+            ensure!(
+                filter.allows(&pool.protocol),
+                "pool {} of disabled protocol {}",
+                pool_id,
+                pool.protocol
+            );
+            pools.push(pool);
+        }
+
+        let mut dexes = vec![];
+        for (pool, (coin_in, coin_out)) in pools.iter().zip(chain_coins(&pools, coin_in)?) {
+            let dex = self
+                .dexes(pool, &coin_in)
+                .await?
+                .into_iter()
+                .find(|dex| dex.coin_out_type() == coin_out)
+                .ok_or_else(|| eyre!("no dex of pool {} from {} to {}", pool.pool, coin_in, coin_out))?;
             dexes.push(dex);
         }
 
         Ok(Path { path: dexes })
     }
+
+    fn pool_state(&self, pool_id: &ObjectID) -> Option<PoolState> {
+        self.indexer.pool_state(pool_id)
+    }
+
+    fn pool_states_since_ms(&self) -> Option<u64> {
+        Some(self.indexer.pool_states_since_ms())
+    }
+}
+
+/// The first pool of a test path that can't be entered with the coin out of the previous hop.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BrokenPath {
+    pub pool_id: ObjectID,
+    // the coins the previous hop can swap to, or the starting coin
+    pub expected: Vec<String>,
+    pub available: Vec<String>,
+}
+
+impl BrokenPath {
+    fn new(pool: &Pool, expected: Vec<String>) -> Self {
+        Self {
+            pool_id: pool.pool,
+            expected,
+            available: coin_types(pool),
+        }
+    }
+}
+
+impl fmt::Display for BrokenPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "path breaks at pool {}: expected one of {:?}, available coins {:?}",
+            self.pool_id, self.expected, self.available
+        )
+    }
+}
+
+impl std::error::Error for BrokenPath {}
+
+// The (coin_in, coin_out) of each pool of a test path, each hop swaps to the coin_in of the next one. The path
+// starts with `coin_in`, or the coin of the first pool that leads into the second one, SUI if both do.
+fn chain_coins(pools: &[Pool], coin_in: Option<&str>) -> Result<Vec<(String, String)>> {
+    ensure!(!pools.is_empty(), "empty test path");
+    let mut coin_in = match coin_in {
+        Some(coin_in) => normalize_coin_type(coin_in),
+        None => infer_coin_in(pools)?,
+    };
+    let start = coin_in.clone();
+
+    let mut hops = Vec::with_capacity(pools.len());
+    for (i, pool) in pools.iter().enumerate() {
+        let available = coin_types(pool);
+        if !available.contains(&coin_in) {
+            return Err(BrokenPath::new(pool, vec![coin_in]).into());
+        }
+
+        let coins_out = available
+            .into_iter()
+            .filter(|coin| *coin != coin_in)
+            .collect::<Vec<_>>();
+        let coin_out = match pools.get(i + 1) {
+            Some(next) => {
+                let next_coins = coin_types(next);
+                match coins_out.iter().find(|coin| next_coins.contains(coin)) {
+                    Some(coin_out) => coin_out.clone(),
+                    None => return Err(BrokenPath::new(next, coins_out).into()),
+                }
+            }
+            // back to the starting coin if the pool has it
+            None if coins_out.contains(&start) => start.clone(),
+            None => coins_out
+                .first()
+                .cloned()
+                .ok_or_else(|| eyre!("pool {} has a single coin", pool.pool))?,
+        };
+
+        hops.push((coin_in, coin_out.clone()));
+        coin_in = coin_out;
+    }
+
+    Ok(hops)
+}
+
+fn infer_coin_in(pools: &[Pool]) -> Result<String> {
+    let first = coin_types(&pools[0]);
+    let Some(next) = pools.get(1) else {
+        ensure!(
+            first.iter().any(|coin| coin == SUI_COIN_TYPE),
+            "the starting coin of pool {} is ambiguous among {:?}",
+            pools[0].pool,
+            first
+        );
+        return Ok(SUI_COIN_TYPE.to_string());
+    };
+
+    let next_coins = coin_types(next);
+    let candidates = first
+        .iter()
+        .filter(|coin_in| {
+            first
+                .iter()
+                .any(|coin_out| coin_out != *coin_in && next_coins.contains(coin_out))
+        })
+        .collect::<Vec<_>>();
+    match candidates[..] {
+        [] => Err(BrokenPath::new(next, first.clone()).into()),
+        [coin_in] => Ok(coin_in.to_string()),
+        _ if candidates.iter().any(|coin| *coin == SUI_COIN_TYPE) => Ok(SUI_COIN_TYPE.to_string()),
+        _ => bail!(
+            "the starting coin of pool {} is ambiguous among {:?}",
+            pools[0].pool,
+            candidates
+        ),
+    }
+}
+
+fn coin_types(pool: &Pool) -> Vec<String> {
+    pool.tokens.iter().map(|token| token.token_type.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use dex_indexer::types::{PoolExtra, Token};
+
+    use super::*;
+    use crate::{config::tests::TEST_HTTP_URL, tests::CountingSimulator};
+
+    #[tokio::test]
+    async fn test_find_dexes_hits_cache_within_ttl() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=debug", "dex_indexer=debug"]);
+
+        let (simulator_pool, counts) = CountingSimulator::new_pool(1);
+
+        let cache = Arc::new(DexCache::new(Duration::from_secs(60)));
+        let searcher = IndexerDexSearcher::new(TEST_HTTP_URL, Arc::new(simulator_pool))
+            .await
+            .unwrap()
+            .with_dex_cache(cache.clone());
+
+        let coin_in_type = "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN";
+        let dexes = searcher.find_dexes(coin_in_type, None).await.unwrap();
+        assert!(!dexes.is_empty(), "no dexes found");
+        assert!(counts.get_objects() > 0);
+
+        let fetched = counts.get_objects();
+        let cached = searcher.find_dexes(coin_in_type, None).await.unwrap();
+        assert_eq!(counts.get_objects(), fetched);
+        assert_eq!(cached.len(), dexes.len());
+        assert!(cached.iter().all(|dex| dex.liquidity_age().is_some()));
+
+        // a swap on a pool rebuilds its dexes
+        searcher.invalidate_pool(dexes[0].object_id());
+        searcher.find_dexes(coin_in_type, None).await.unwrap();
+        assert!(counts.get_objects() > fetched);
+    }
+
+    const COIN_A: &str = "0x1::a::A";
+    const COIN_B: &str = "0x1::b::B";
+
+    fn pool(coins: &[&str]) -> Pool {
+        Pool {
+            protocol: Protocol::Cetus,
+            pool: ObjectID::random(),
+            tokens: coins.iter().map(|coin| Token::new(coin, 9)).collect(),
+            extra: PoolExtra::None,
+        }
+    }
+
+    fn hops(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(coin_in, coin_out)| (coin_in.to_string(), coin_out.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_chain_coins_of_3_pools() {
+        let pools = vec![
+            pool(&[SUI_COIN_TYPE, COIN_A]),
+            pool(&[COIN_A, COIN_B]),
+            pool(&[COIN_B, SUI_COIN_TYPE]),
+        ];
+
+        let expected = hops(&[(SUI_COIN_TYPE, COIN_A), (COIN_A, COIN_B), (COIN_B, SUI_COIN_TYPE)]);
+        assert_eq!(chain_coins(&pools, None).unwrap(), expected);
+        assert_eq!(chain_coins(&pools, Some(SUI_COIN_TYPE)).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_chain_coins_flips_pools() {
+        // every pool is listed in the reverse order of the path
+        let pools = vec![
+            pool(&[COIN_A, SUI_COIN_TYPE]),
+            pool(&[COIN_B, COIN_A]),
+            pool(&[SUI_COIN_TYPE, COIN_B]),
+        ];
+        assert_eq!(
+            chain_coins(&pools, None).unwrap(),
+            hops(&[(SUI_COIN_TYPE, COIN_A), (COIN_A, COIN_B), (COIN_B, SUI_COIN_TYPE)])
+        );
+
+        // the starting coin tells the direction of pools of the same coins
+        let pools = vec![pool(&[SUI_COIN_TYPE, COIN_A]), pool(&[COIN_A, SUI_COIN_TYPE])];
+        assert_eq!(
+            chain_coins(&pools, Some(COIN_A)).unwrap(),
+            hops(&[(COIN_A, SUI_COIN_TYPE), (SUI_COIN_TYPE, COIN_A)])
+        );
+        assert_eq!(
+            chain_coins(&pools, None).unwrap(),
+            hops(&[(SUI_COIN_TYPE, COIN_A), (COIN_A, SUI_COIN_TYPE)])
+        );
+    }
+
+    #[test]
+    fn test_chain_coins_of_broken_path() {
+        let pools = vec![
+            pool(&[SUI_COIN_TYPE, COIN_A]),
+            pool(&[COIN_A, COIN_B]),
+            pool(&[SUI_COIN_TYPE, COIN_A]),
+        ];
+        let error = chain_coins(&pools, Some(SUI_COIN_TYPE)).unwrap_err();
+        let broken = error.downcast_ref::<BrokenPath>().unwrap();
+        assert_eq!(
+            broken,
+            &BrokenPath {
+                pool_id: pools[2].pool,
+                expected: vec![COIN_B.to_string()],
+                available: vec![SUI_COIN_TYPE.to_string(), COIN_A.to_string()],
+            }
+        );
+        assert!(error.to_string().contains(&pools[2].pool.to_string()), "{error}");
+
+        // the starting coin isn't in the first pool
+        let error = chain_coins(&pools, Some(COIN_B)).unwrap_err();
+        assert_eq!(error.downcast_ref::<BrokenPath>().unwrap().pool_id, pools[0].pool);
+    }
 }
@@ -1,6 +1,6 @@
-use std::sync::Arc;
+use std::{str::FromStr, sync::Arc};
 
-use dex_indexer::types::{Pool, Protocol};
+use dex_indexer::types::{Pool, PoolExtra, Protocol};
 use eyre::{ensure, eyre, OptionExt, Result};
 use move_core_types::annotated_value::MoveStruct;
 use simulator::Simulator;
@@ -9,11 +9,18 @@ use sui_types::{
     transaction::{Argument, Command, ObjectArg, ProgrammableTransaction, TransactionData},
     Identifier, TypeTag,
 };
-use utils::{coin, new_test_sui_client, object::*};
+use utils::{
+    coin::{self, GasCoinFilter},
+    new_test_sui_client,
+    object::*,
+};
 
-use super::{TradeCtx, CETUS_AGGREGATOR};
+use super::{utils::min_amount_out, TradeCtx};
 use crate::{config::*, defi::Dex};
 
+const KRIYA_AMM: &str = "0xa0eba10b173538c8fecca1dff298e488402cc9ff374f8a12ca7758eebe830b66";
+const FEE_SCALING: u128 = 1_000_000;
+
 #[derive(Clone)]
 pub struct KriyaAmm {
     pool: Pool,
@@ -22,6 +29,11 @@ pub struct KriyaAmm {
     coin_in_type: String,
     coin_out_type: String,
     type_params: Vec<TypeTag>,
+    // reserves of token_x and token_y
+    reserves: [u64; 2],
+    // lp_fee_percent + protocol_fee_percent, scaled by FEE_SCALING, `None` if unknown
+    fee: Option<u64>,
+    is_stable: bool,
 }
 
 impl KriyaAmm {
@@ -45,9 +57,23 @@ impl KriyaAmm {
         let is_swap_enabled = extract_bool_from_move_struct(&parsed_pool, "is_swap_enabled")?;
         ensure!(is_swap_enabled, "swap is not enabled");
 
-        let liquidity = {
-            let lsp_supply = extract_struct_from_move_struct(&parsed_pool, "lsp_supply")?;
-            extract_u64_from_move_struct(&lsp_supply, "value")? as u128
+        let reserves = {
+            let token_x = extract_struct_from_move_struct(&parsed_pool, "token_x")?;
+            let token_y = extract_struct_from_move_struct(&parsed_pool, "token_y")?;
+            [
+                extract_u64_from_move_struct(&token_x, "value")?,
+                extract_u64_from_move_struct(&token_y, "value")?,
+            ]
+        };
+        // the `L` of x * y = L^2, comparable to the liquidity of the concentrated liquidity pools
+        let liquidity = (reserves[0] as f64 * reserves[1] as f64).sqrt() as u128;
+        let is_stable = extract_bool_from_move_struct(&parsed_pool, "is_stable")?;
+        let fee = match pool.extra {
+            PoolExtra::KriyaAmm {
+                lp_fee_percent,
+                protocol_fee_percent,
+            } => Some(lp_fee_percent + protocol_fee_percent),
+            _ => None,
         };
 
         let coin_out_type = if pool.token0_type() == coin_in_type {
@@ -67,6 +93,9 @@ impl KriyaAmm {
             coin_in_type: coin_in_type.to_string(),
             coin_out_type,
             type_params,
+            reserves,
+            fee,
+            is_stable,
         })
     }
 
@@ -74,29 +103,55 @@ impl KriyaAmm {
         &self,
         sender: SuiAddress,
         recipient: SuiAddress,
-        coin_in: ObjectRef,
+        coins_in: &[ObjectRef],
         amount_in: u64,
     ) -> Result<ProgrammableTransaction> {
         let mut ctx = TradeCtx::default();
 
-        let coin_in = ctx.split_coin(coin_in, amount_in)?;
-        let coin_out = self.extend_trade_tx(&mut ctx, sender, coin_in, None).await?;
+        let coin_in = ctx.split_coins(coins_in, amount_in)?;
+        let coin_out = self.extend_trade_tx(&mut ctx, sender, coin_in, Some(amount_in)).await?;
         ctx.transfer_arg(recipient, coin_out);
 
         Ok(ctx.ptb.finish())
     }
 
     /*
-    public fun swap_a2b<CoinA, CoinB>(
-        pool: &mut Pool<CoinA, CoinB>,
-        coin_a: Coin<CoinA>,
+    public fun swap_token_x<X, Y>(
+        pool: &mut Pool<X, Y>,
+        token_x: Coin<X>,
+        amount: u64,
+        min_receive_y: u64,
         ctx: &mut TxContext,
-    ): Coin<CoinB>
+    ): Coin<Y>
     */
-    fn build_swap_args(&self, ctx: &mut TradeCtx, coin_in_arg: Argument) -> Result<Vec<Argument>> {
+    fn build_swap_args(
+        &self,
+        ctx: &mut TradeCtx,
+        coin_in_arg: Argument,
+        amount_in: Option<u64>,
+    ) -> Result<Vec<Argument>> {
         let pool_arg = ctx.obj(self.pool_arg).map_err(|e| eyre!(e))?;
+        let amount_arg = match amount_in {
+            Some(amount_in) => ctx.pure(amount_in).map_err(|e| eyre!(e))?,
+            None => {
+                let coin_in_type = TypeTag::from_str(&self.coin_in_type).map_err(|e| eyre!(e))?;
+                ctx.coin_value(coin_in_arg, coin_in_type)?
+            }
+        };
+        // the amount of a flashloan of the first dex is in the coin in of the path, the hop receives its swap
+        let known_amount_in = amount_in.filter(|_| !ctx.amount_in_of_path());
+        let min_out_arg = ctx.pure(self.min_amount_out(known_amount_in)).map_err(|e| eyre!(e))?;
 
-        Ok(vec![pool_arg, coin_in_arg])
+        Ok(vec![pool_arg, coin_in_arg, amount_arg, min_out_arg])
+    }
+
+    // the quote of `amount_in` minus the tolerance. The amount in of the later hops of a path is only known
+    // on chain, they are left unprotected as the flashloan of the path can't be repaid without the profit.
+    fn min_amount_out(&self, amount_in: Option<u64>) -> u64 {
+        amount_in
+            .and_then(|amount_in| self.quote(amount_in))
+            .map(min_amount_out)
+            .unwrap_or(0)
     }
 }
 
@@ -107,15 +162,15 @@ impl Dex for KriyaAmm {
         ctx: &mut TradeCtx,
         _sender: SuiAddress,
         coin_in: Argument,
-        _amount_in: Option<u64>,
+        amount_in: Option<u64>,
     ) -> Result<Argument> {
-        let function = if self.is_a2b() { "swap_a2b" } else { "swap_b2a" };
+        let function = if self.is_a2b() { "swap_token_x" } else { "swap_token_y" };
 
-        let package = ObjectID::from_hex_literal(CETUS_AGGREGATOR)?;
-        let module = Identifier::new("kriya_amm").map_err(|e| eyre!(e))?;
+        let package = ObjectID::from_hex_literal(KRIYA_AMM)?;
+        let module = Identifier::new("spot_dex").map_err(|e| eyre!(e))?;
         let function = Identifier::new(function).map_err(|e| eyre!(e))?;
         let type_arguments = self.type_params.clone();
-        let arguments = self.build_swap_args(ctx, coin_in)?;
+        let arguments = self.build_swap_args(ctx, coin_in, amount_in)?;
         ctx.command(Command::move_call(package, module, function, type_arguments, arguments));
 
         let last_idx = ctx.last_command_idx();
@@ -138,6 +193,20 @@ impl Dex for KriyaAmm {
         self.liquidity
     }
 
+    // x * y = k, the stable pools have a different curve and aren't quoted
+    fn quote(&self, amount_in: u64) -> Option<u64> {
+        if self.is_stable {
+            return None;
+        }
+
+        let (reserve_in, reserve_out) = if self.is_a2b() {
+            (self.reserves[0], self.reserves[1])
+        } else {
+            (self.reserves[1], self.reserves[0])
+        };
+        Some(xy_amount_out(reserve_in, reserve_out, self.fee?, amount_in))
+    }
+
     fn object_id(&self) -> ObjectID {
         self.pool.pool
     }
@@ -154,13 +223,13 @@ impl Dex for KriyaAmm {
     async fn swap_tx(&self, sender: SuiAddress, recipient: SuiAddress, amount_in: u64) -> Result<TransactionData> {
         let sui = new_test_sui_client().await;
 
-        let coin_in = coin::get_coin(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coins_in = coin::get_coins_for_amount(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coin_in_refs = coins_in.iter().map(|coin| coin.object_ref()).collect::<Vec<_>>();
 
-        let pt = self
-            .build_swap_tx(sender, recipient, coin_in.object_ref(), amount_in)
-            .await?;
+        let pt = self.build_swap_tx(sender, recipient, &coin_in_refs, amount_in).await?;
 
-        let gas_coins = coin::get_gas_coin_refs(&sui, sender, Some(coin_in.coin_object_id)).await?;
+        let exclude = coins_in.iter().map(|coin| coin.coin_object_id).collect::<Vec<_>>();
+        let gas_coins = coin::get_gas_coin_refs(&sui, sender, &GasCoinFilter::default().with_exclude(exclude)).await?;
         let gas_price = sui.read_api().get_reference_gas_price().await?;
         let tx_data = TransactionData::new_programmable(sender, gas_coins, pt, GAS_BUDGET, gas_price);
 
@@ -168,21 +237,139 @@ impl Dex for KriyaAmm {
     }
 }
 
+// the amount out of a constant product pool, the fee is taken from `amount_in`
+fn xy_amount_out(reserve_in: u64, reserve_out: u64, fee: u64, amount_in: u64) -> u64 {
+    let amount_in = amount_in as u128 * FEE_SCALING.saturating_sub(fee as u128) / FEE_SCALING;
+    let denominator = reserve_in as u128 + amount_in;
+    if denominator == 0 {
+        return 0;
+    }
+
+    (reserve_out as u128 * amount_in / denominator) as u64
+}
+
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
-
+    use dex_indexer::types::Token;
     use itertools::Itertools;
     use object_pool::ObjectPool;
     use simulator::{DBSimulator, HttpSimulator, Simulator};
+    use sui_types::transaction::{CallArg, ProgrammableMoveCall};
     use tracing::info;
 
     use super::*;
     use crate::{
         config::tests::{TEST_ATTACKER, TEST_HTTP_URL},
-        defi::{indexer_searcher::IndexerDexSearcher, DexSearcher},
+        defi::{indexer_searcher::IndexerDexSearcher, trade::extend_flashloan_swaps, DexSearcher, Path, StubDex},
+        tests::{random_pool, shared_object},
     };
 
+    #[test]
+    fn test_xy_amount_out() {
+        // no fee: 1000 * 100 / (1000 + 100)
+        assert_eq!(xy_amount_out(1000, 1000, 0, 100), 90);
+        // 0.3%
+        assert_eq!(xy_amount_out(1_000_000, 2_000_000, 3000, 10_000), 19_743);
+        assert_eq!(xy_amount_out(0, 0, 3000, 10_000), 0);
+    }
+
+    const COIN_X: &str = "0x2::sui::SUI";
+    const COIN_Y: &str = "0x5d4b302506645c37ff133b98c4b50a5ae14841659738d6d733d59d0d217a93bf::coin::COIN";
+
+    fn doctored_kriya_amm(coin_in_type: &str, coin_out_type: &str, is_stable: bool) -> KriyaAmm {
+        let tokens = vec![Token::new(COIN_X, 9), Token::new(COIN_Y, 6)];
+        let extra = PoolExtra::KriyaAmm {
+            lp_fee_percent: 2500,
+            protocol_fee_percent: 500,
+        };
+        KriyaAmm {
+            pool: random_pool(Protocol::KriyaAmm, tokens, extra),
+            pool_arg: shared_object(ObjectID::random()),
+            liquidity: 1_000_000,
+            coin_in_type: coin_in_type.to_string(),
+            coin_out_type: coin_out_type.to_string(),
+            type_params: vec![TypeTag::from_str(COIN_X).unwrap(), TypeTag::from_str(COIN_Y).unwrap()],
+            reserves: [1_000_000_000_000, 2_000_000_000],
+            fee: Some(3000),
+            is_stable,
+        }
+    }
+
+    // the swap call and the min amount out passed to it
+    async fn swap_call(dex: &KriyaAmm, amount_in: Option<u64>) -> (ProgrammableMoveCall, u64) {
+        let mut ctx = TradeCtx::default();
+        let coin_in = ctx.pure(0u64).unwrap();
+        dex.extend_trade_tx(&mut ctx, SuiAddress::ZERO, coin_in, amount_in)
+            .await
+            .unwrap();
+
+        last_swap_call(ctx)
+    }
+
+    fn last_swap_call(ctx: TradeCtx) -> (ProgrammableMoveCall, u64) {
+        let mut pt = ctx.ptb.finish();
+        let Command::MoveCall(call) = pt.commands.pop().unwrap() else {
+            panic!("not a move call");
+        };
+        let Argument::Input(idx) = call.arguments[3] else {
+            panic!("min out is not an input");
+        };
+        let CallArg::Pure(bytes) = &pt.inputs[idx as usize] else {
+            panic!("min out is not pure");
+        };
+        let min_out = bcs::from_bytes(bytes).unwrap();
+
+        (*call, min_out)
+    }
+
+    #[tokio::test]
+    async fn test_swap_min_out_from_quote() {
+        let dex = doctored_kriya_amm(COIN_X, COIN_Y, false);
+        let (call, min_out) = swap_call(&dex, Some(1_000_000_000)).await;
+        assert_eq!(call.package, ObjectID::from_hex_literal(KRIYA_AMM).unwrap());
+        assert_eq!(call.function.as_str(), "swap_token_x");
+        assert_eq!(call.arguments.len(), 4);
+
+        let quote = dex.quote(1_000_000_000).unwrap();
+        assert!(min_out > 0);
+        assert!(min_out < quote);
+        assert_eq!(min_out, min_amount_out(quote));
+
+        // y to x with the same type params
+        let (call, min_out) = swap_call(&doctored_kriya_amm(COIN_Y, COIN_X, false), Some(1_000_000)).await;
+        assert_eq!(call.function.as_str(), "swap_token_y");
+        assert_eq!(call.type_arguments[0], TypeTag::from_str(COIN_X).unwrap());
+        assert!(min_out > 0);
+
+        // the amount in of a later hop is read from its coin, and can't be quoted
+        let (call, min_out) = swap_call(&dex, None).await;
+        assert_eq!(call.arguments[2], Argument::Result(0));
+        assert_eq!(min_out, 0);
+    }
+
+    #[tokio::test]
+    async fn test_no_min_out_after_flashloan_of_first_dex() {
+        let dex = doctored_kriya_amm(COIN_X, COIN_Y, false);
+        let amount_in = 1_000_000_000;
+        let flashloan_swaps = |path: Path| async move {
+            let mut ctx = TradeCtx::default();
+            let coin_in = ctx.pure(0u64).unwrap();
+            extend_flashloan_swaps(&mut ctx, &path, SuiAddress::ZERO, coin_in, amount_in)
+                .await
+                .unwrap();
+            last_swap_call(ctx).1
+        };
+
+        // flashloaned by navi, the kriya hop receives the amount in of the path
+        let min_out = flashloan_swaps(Path::new(vec![Box::new(dex.clone())])).await;
+        assert_eq!(min_out, min_amount_out(dex.quote(amount_in).unwrap()));
+
+        // flashloaned by the first dex in COIN_Y, the kriya hop receives the COIN_X of its swap
+        let lender = StubDex::new(ObjectID::random(), COIN_Y, COIN_X, 0).with_flashloan();
+        let min_out = flashloan_swaps(Path::new(vec![Box::new(lender), Box::new(dex)])).await;
+        assert_eq!(min_out, 0);
+    }
+
     #[tokio::test]
     async fn test_kriya_amm_swap_tx() {
         mev_logger::init_console_logger_with_directives(None, &["arb=debug", "dex_indexer=debug"]);
@@ -196,10 +383,8 @@ mod tests {
         let token_out_type = "0x5d4b302506645c37ff133b98c4b50a5ae14841659738d6d733d59d0d217a93bf::coin::COIN";
         let amount_in = 10000;
 
-        let simulator_pool = Arc::new(ObjectPool::new(1, move || {
-            tokio::runtime::Runtime::new()
-                .unwrap()
-                .block_on(async { Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator> })
+        let simulator_pool = Arc::new(ObjectPool::new_async(1, || async {
+            Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator>
         }));
 
         // find dexes and swap
@@ -220,5 +405,6 @@ mod tests {
 
         let response = http_simulator.simulate(tx_data, Default::default()).await.unwrap();
         info!("🧀 {:?}", response);
+        response.check_status().unwrap();
     }
 }
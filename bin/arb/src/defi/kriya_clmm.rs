@@ -11,11 +11,12 @@ use sui_types::{
 };
 use tokio::sync::OnceCell;
 use utils::{
-    coin, new_test_sui_client,
+    coin::{self, GasCoinFilter},
+    new_test_sui_client,
     object::{extract_u128_from_move_struct, shared_obj_arg},
 };
 
-use super::{trade::FlashResult, TradeCtx, CETUS_AGGREGATOR};
+use super::{trade::FlashResult, utils::get_pool_object, TradeCtx, CETUS_AGGREGATOR};
 use crate::{config::*, defi::Dex};
 
 const KRIYA_CLMM: &str = "0xbd8d4489782042c6fafad4de4bc6a5e0b84a43c6c00647ffd7062d1e2bb7549e";
@@ -33,8 +34,8 @@ async fn get_object_args(simulator: Arc<Box<dyn Simulator>>) -> ObjectArgs {
     OBJ_CACHE
         .get_or_init(|| async {
             let version_id = ObjectID::from_hex_literal(VERSION).unwrap();
-            let version = simulator.get_object(&version_id).await.unwrap();
-            let clock = simulator.get_object(&SUI_CLOCK_OBJECT_ID).await.unwrap();
+            let objects = simulator.multi_get_objects(&[version_id, SUI_CLOCK_OBJECT_ID]).await;
+            let [version, clock] = <[_; 2]>::try_from(objects).unwrap().map(Option::unwrap);
 
             ObjectArgs {
                 version: shared_obj_arg(&version, false),
@@ -104,12 +105,12 @@ impl KriyaClmm {
         &self,
         sender: SuiAddress,
         recipient: SuiAddress,
-        coin_in: ObjectRef,
+        coins_in: &[ObjectRef],
         amount_in: u64,
     ) -> Result<ProgrammableTransaction> {
         let mut ctx = TradeCtx::default();
 
-        let coin_in = ctx.split_coin(coin_in, amount_in)?;
+        let coin_in = ctx.split_coins(coins_in, amount_in)?;
         let coin_out = self.extend_trade_tx(&mut ctx, sender, coin_in, None).await?;
         ctx.transfer_arg(recipient, coin_out);
 
@@ -313,6 +314,13 @@ impl Dex for KriyaClmm {
         self.liquidity
     }
 
+    // the pool has no pause flag to check
+    async fn refresh(&mut self, simulator: Arc<Box<dyn Simulator>>) -> Result<()> {
+        let (_, parsed_pool) = get_pool_object(&**simulator, &self.pool.pool).await?;
+        self.liquidity = extract_u128_from_move_struct(&parsed_pool, "liquidity")?;
+        Ok(())
+    }
+
     fn object_id(&self) -> ObjectID {
         self.pool.pool
     }
@@ -329,13 +337,13 @@ impl Dex for KriyaClmm {
     async fn swap_tx(&self, sender: SuiAddress, recipient: SuiAddress, amount_in: u64) -> Result<TransactionData> {
         let sui = new_test_sui_client().await;
 
-        let coin_in = coin::get_coin(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coins_in = coin::get_coins_for_amount(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coin_in_refs = coins_in.iter().map(|coin| coin.object_ref()).collect::<Vec<_>>();
 
-        let pt = self
-            .build_swap_tx(sender, recipient, coin_in.object_ref(), amount_in)
-            .await?;
+        let pt = self.build_swap_tx(sender, recipient, &coin_in_refs, amount_in).await?;
 
-        let gas_coins = coin::get_gas_coin_refs(&sui, sender, Some(coin_in.coin_object_id)).await?;
+        let exclude = coins_in.iter().map(|coin| coin.coin_object_id).collect::<Vec<_>>();
+        let gas_coins = coin::get_gas_coin_refs(&sui, sender, &GasCoinFilter::default().with_exclude(exclude)).await?;
         let gas_price = sui.read_api().get_reference_gas_price().await?;
         let tx_data = TransactionData::new_programmable(sender, gas_coins, pt, GAS_BUDGET, gas_price);
 
@@ -371,10 +379,8 @@ mod tests {
         let token_out_type = "0xdeeb7a4662eec9f2f3def03fb937a663dddaa2e215b8078a284d026b7946c270::deep::DEEP";
         let amount_in = 10000;
 
-        let simulator_pool = Arc::new(ObjectPool::new(1, move || {
-            tokio::runtime::Runtime::new()
-                .unwrap()
-                .block_on(async { Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator> })
+        let simulator_pool = Arc::new(ObjectPool::new_async(1, || async {
+            Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator>
         }));
 
         // find dexes and swap
@@ -1,12 +1,15 @@
 mod aftermath;
+mod arg_tracker;
 mod blue_move;
 mod cetus;
 mod deepbook_v2;
+mod dex_cache;
 mod flowx_clmm;
 mod indexer_searcher;
 mod kriya_amm;
 mod kriya_clmm;
 mod navi;
+mod quarantine;
 mod shio;
 mod trade;
 mod turbos;
@@ -17,38 +20,139 @@ use std::{
     fmt,
     hash::Hash,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 use ::utils::coin;
-use dex_indexer::types::Protocol;
+pub use arg_tracker::{ArgKind, ArgViolation};
+pub use dex_cache::{dex_cache, init_dex_cache, DexCache, DEFAULT_DEX_CACHE_TTL};
+use dex_indexer::{supported_protocols, types::Protocol, PoolState};
 use eyre::{bail, ensure, Result};
-pub use indexer_searcher::IndexerDexSearcher;
+use futures::future::try_join_all;
+pub use indexer_searcher::{record_swap_event, shutdown_indexer, IndexerDexSearcher};
 use object_pool::ObjectPool;
+pub use quarantine::{
+    add_trial, init_pool_quarantine, pool_quarantine, PoolQuarantine, PoolTrial, PoolTrials, DEFAULT_QUARANTINE_ABORTS,
+    DEFAULT_QUARANTINE_COOL_OFF,
+};
+pub use shio::invalidate_shio_global_states_on;
 use simulator::{SimulateCtx, Simulator};
+use sui_json_rpc_types::SuiEvent;
 use sui_sdk::SUI_COIN_TYPE;
 use sui_types::{
     base_types::{ObjectID, ObjectRef, SuiAddress},
     transaction::{Argument, TransactionData},
 };
 use tokio::task::JoinSet;
-use tracing::Instrument;
-use trade::{FlashResult, TradeResult};
-pub use trade::{Path, TradeCtx, TradeType, Trader};
-
-use crate::{config::pegged_coin_types, types::Source};
+use tracing::{debug, warn, Instrument};
+pub use trade::{order_after, HopFill, Path, TradeCtx, TradeType, Trader};
+use trade::{parse_hop_fills, FlashResult, TradeResult};
+pub use utils::{init_min_out_tolerance, DEFAULT_MIN_OUT_TOLERANCE_BPS};
+
+use crate::{
+    config::{new_pegged_coin_types, pegged_coin_types, GAS_BUDGET},
+    types::{DeadlineExceeded, HopAborted, Source},
+};
 
 const MAX_HOP_COUNT: usize = 2;
+const MIN_CYCLE_HOP_COUNT: usize = 3;
 const MAX_POOL_COUNT: usize = 10;
+/// of the `MAX_POOL_COUNT` pools kept per coin, the most liquid ones of each protocol are kept first
+pub const DEFAULT_MAX_POOLS_PER_PROTOCOL: usize = 3;
 const MIN_LIQUIDITY: u128 = 1000;
+/// of the paths fully quoted by their dexes, only the best estimated ones are simulated, see `Dex::quote`
+pub const DEFAULT_MAX_SIMULATED_PATHS: usize = 16;
+// at most this many paths are evaluated by one simulate_many call, fewer when the simulator pool can take all the
+// paths at once, see `simulate_batch_size`
+const SIMULATE_BATCH_SIZE: usize = 8;
+// of the pools of the same liquidity, one that swapped within this is ranked before the others, one that didn't
+// swap for this long after them
+const POOL_ACTIVE_WITHIN: Duration = Duration::from_secs(10 * 60);
+const POOL_SILENT_AFTER: Duration = Duration::from_secs(2 * 60 * 60);
 
 pub const CETUS_AGGREGATOR: &str = "0x11451575c775a3e633437b827ecbc1eb51a5964b0302210b28f5b89880be21a2";
 
+/// The protocols a search goes through: the ones of `only` if not empty, all of them otherwise, minus the
+/// ones of `disabled`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProtocolFilter {
+    pub only: HashSet<Protocol>,
+    pub disabled: HashSet<Protocol>,
+}
+
+impl ProtocolFilter {
+    pub fn new(only: impl IntoIterator<Item = Protocol>, disabled: impl IntoIterator<Item = Protocol>) -> Self {
+        Self {
+            only: only.into_iter().collect(),
+            disabled: disabled.into_iter().collect(),
+        }
+    }
+
+    pub fn allows(&self, protocol: &Protocol) -> bool {
+        (self.only.is_empty() || self.only.contains(protocol)) && !self.disabled.contains(protocol)
+    }
+
+    /// The indexed protocols it allows, the only ones whose pools the `DexIndexer` has to load.
+    pub fn protocols(&self) -> Vec<Protocol> {
+        supported_protocols()
+            .into_iter()
+            .filter(|protocol| self.allows(protocol))
+            .collect()
+    }
+}
+
 #[async_trait::async_trait]
 pub trait DexSearcher: Send + Sync {
     // coin_type: e.g. "0x2::sui::SUI"
-    async fn find_dexes(&self, coin_in_type: &str, coin_out_type: Option<String>) -> Result<Vec<Box<dyn Dex>>>;
+    async fn find_dexes(&self, coin_in_type: &str, coin_out_type: Option<String>) -> Result<Vec<Box<dyn Dex>>> {
+        self.find_dexes_filtered(coin_in_type, coin_out_type, &ProtocolFilter::default())
+            .await
+    }
 
-    async fn find_test_path(&self, path: &[ObjectID]) -> Result<Path>;
+    /// Same as `find_dexes`, without the pools of the protocols `filter` doesn't allow.
+    async fn find_dexes_filtered(
+        &self,
+        coin_in_type: &str,
+        coin_out_type: Option<String>,
+        filter: &ProtocolFilter,
+    ) -> Result<Vec<Box<dyn Dex>>>;
+
+    /// The path through the pools of `path` in order, each hop swapping to a coin of the next pool. It starts
+    /// with `coin_in` if set, otherwise it's inferred from the first pools. Fails if a pool of `path` belongs to
+    /// a protocol `filter` doesn't allow, or can't be entered with the coin out of the previous hop.
+    async fn find_test_path(&self, path: &[ObjectID], coin_in: Option<&str>, filter: &ProtocolFilter) -> Result<Path>;
+
+    /// The last swap seen on `pool_id`, `None` if there was none since the start.
+    fn pool_state(&self, _pool_id: &ObjectID) -> Option<PoolState> {
+        None
+    }
+
+    /// When the searcher started recording the swaps of `pool_state`, `None` if it doesn't.
+    fn pool_states_since_ms(&self) -> Option<u64> {
+        None
+    }
+}
+
+// How recently a pool swapped, to break the ties of liquidity between the pools of a coin: the active ones first
+// and the ones silent for hours last. The pools not seen swapping are in between as their silence is unknown, until
+// the swaps have been recorded for that long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum PoolActivity {
+    Active,
+    Unknown,
+    Silent,
+}
+
+impl PoolActivity {
+    fn of(state: Option<PoolState>, now_ms: u64, since_ms: Option<u64>) -> Self {
+        let recorded_for = since_ms.map(|since_ms| Duration::from_millis(now_ms.saturating_sub(since_ms)));
+        match state.map(|state| state.age(now_ms)) {
+            Some(age) if age <= POOL_ACTIVE_WITHIN => PoolActivity::Active,
+            Some(age) if age > POOL_SILENT_AFTER => PoolActivity::Silent,
+            None if recorded_for.is_some_and(|recorded_for| recorded_for > POOL_SILENT_AFTER) => PoolActivity::Silent,
+            _ => PoolActivity::Unknown,
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -83,6 +187,22 @@ pub trait Dex: Send + Sync + CloneBoxedDex {
     fn coin_out_type(&self) -> String;
     fn protocol(&self) -> Protocol;
     fn liquidity(&self) -> u128;
+    /// The amount out of a swap of `amount_in` estimated without a simulation, from the state the dex was built
+    /// with. `None` if it can't be estimated cheaply. Only used to rank the paths to simulate.
+    fn quote(&self, _amount_in: u64) -> Option<u64> {
+        None
+    }
+
+    /// Re-read the state of the pool that may have changed since the dex was built, e.g. right before a
+    /// trade through it is submitted. `PoolPaused` if the pool can no longer be traded.
+    async fn refresh(&mut self, _simulator: Arc<Box<dyn Simulator>>) -> Result<()> {
+        Ok(())
+    }
+
+    /// How long ago `liquidity` was read, `None` if it was read when the dex was built, see `DexCache`.
+    fn liquidity_age(&self) -> Option<Duration> {
+        None
+    }
     fn object_id(&self) -> ObjectID;
 
     /// flip the coin_in_type and coin_out_type
@@ -143,89 +263,138 @@ impl fmt::Debug for Box<dyn Dex> {
 pub struct Defi {
     dex_searcher: Arc<dyn DexSearcher>,
     trader: Arc<Trader>,
+    simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
+    max_cycle_hops: usize,
+    protocol_filter: ProtocolFilter,
+    max_pools_per_protocol: usize,
+    max_simulated_paths: usize,
+    pegged_coin_types: Arc<HashSet<String>>,
+    quarantine: Arc<PoolQuarantine>,
 }
 
 impl Defi {
-    pub async fn new(http_url: &str, simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>) -> Result<Self> {
-        let dex_searcher = IndexerDexSearcher::new(http_url, simulator_pool.clone()).await?;
-        let trade = Trader::new(simulator_pool).await?;
-
-        Ok(Self {
-            dex_searcher: Arc::new(dex_searcher),
-            trader: Arc::new(trade),
-        })
+    pub async fn new(
+        http_url: &str,
+        simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
+        dry_run: bool,
+    ) -> Result<Self> {
+        Self::new_with_protocol_filter(http_url, simulator_pool, dry_run, ProtocolFilter::default()).await
     }
 
-    #[allow(dead_code)]
-    pub async fn find_dexes(&self, coin_in_type: &str, coin_out_type: Option<String>) -> Result<Vec<Box<dyn Dex>>> {
-        self.dex_searcher.find_dexes(coin_in_type, coin_out_type).await
+    /// Same as `new`, only searching the pools of the protocols `protocol_filter` allows, the only ones the dex
+    /// indexer loads.
+    pub async fn new_with_protocol_filter(
+        http_url: &str,
+        simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
+        dry_run: bool,
+        protocol_filter: ProtocolFilter,
+    ) -> Result<Self> {
+        let dex_searcher =
+            IndexerDexSearcher::new_with_protocols(http_url, simulator_pool.clone(), &protocol_filter.protocols())
+                .await?;
+        let trader = Trader::new(http_url, simulator_pool.clone(), dry_run).await?;
+
+        Ok(Self::new_with_searcher(Arc::new(dex_searcher), trader, simulator_pool)
+            .with_protocol_filter(protocol_filter))
     }
 
-    //查找卖出路径(从指定代币到SUI)
-    pub async fn find_sell_paths(&self, coin_in_type: &str) -> Result<Vec<Path>> {
-        if coin::is_native_coin(coin_in_type) {
-            return Ok(vec![Path::default()]);
+    /// Same as `new`, with the pools found by `dex_searcher` instead of the dex indexer.
+    pub fn new_with_searcher(
+        dex_searcher: Arc<dyn DexSearcher>,
+        trader: Trader,
+        simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
+    ) -> Self {
+        Self {
+            dex_searcher,
+            trader: Arc::new(trader),
+            simulator_pool,
+            max_cycle_hops: 0,
+            protocol_filter: ProtocolFilter::default(),
+            max_pools_per_protocol: DEFAULT_MAX_POOLS_PER_PROTOCOL,
+            max_simulated_paths: DEFAULT_MAX_SIMULATED_PATHS,
+            pegged_coin_types: pegged_coin_types(),
+            quarantine: pool_quarantine(),
         }
+    }
 
-        let mut all_hops = HashMap::new();
-        let mut stack = vec![coin_in_type.to_string()];
-        let mut visited = HashSet::new();
-        let mut visited_dexes = HashSet::new();
+    /// Also search SUI-rooted cycles of up to `max_cycle_hops` hops as trade paths, 0 to disable.
+    pub fn with_max_cycle_hops(mut self, max_cycle_hops: usize) -> Self {
+        self.max_cycle_hops = max_cycle_hops;
+        self
+    }
 
-        for nth_hop in 0..MAX_HOP_COUNT {
-            let is_last_hop = nth_hop == MAX_HOP_COUNT - 1;
-            let mut new_stack = vec![];
+    pub fn max_cycle_hops(&self) -> usize {
+        self.max_cycle_hops
+    }
 
-            while let Some(coin_type) = stack.pop() {
-                if visited.contains(&coin_type) || coin::is_native_coin(&coin_type) {
-                    continue;
-                }
-                visited.insert(coin_type.clone());
-
-                let coin_out_type = if pegged_coin_types().contains(coin_type.as_str()) || is_last_hop {
-                    Some(SUI_COIN_TYPE.to_string())
-                } else {
-                    None
-                };
-                let mut dexes = if let Ok(dexes) = self.dex_searcher.find_dexes(&coin_type, coin_out_type).await {
-                    dexes
-                } else {
-                    continue;
-                };
-
-                dexes.retain(|dex| dex.liquidity() >= MIN_LIQUIDITY);
-
-                if dexes.len() > MAX_POOL_COUNT {
-                    dexes.retain(|dex| !visited_dexes.contains(&dex.object_id()));
-                    dexes.sort_by_key(|dex| std::cmp::Reverse(dex.liquidity()));
-                    dexes.truncate(MAX_POOL_COUNT);
-                }
+    /// Only search the pools of the protocols `protocol_filter` allows.
+    pub fn with_protocol_filter(mut self, protocol_filter: ProtocolFilter) -> Self {
+        self.protocol_filter = protocol_filter;
+        self
+    }
 
-                if dexes.is_empty() {
-                    continue;
-                }
+    /// Of the pools kept per coin, the `max_pools_per_protocol` most liquid ones of each protocol are kept
+    /// before the others, 0 to only rank them by liquidity.
+    pub fn with_max_pools_per_protocol(mut self, max_pools_per_protocol: usize) -> Self {
+        self.max_pools_per_protocol = max_pools_per_protocol;
+        self
+    }
 
-                for dex in &dexes {
-                    let out_coin_type = dex.coin_out_type();
-                    if !visited.contains(&out_coin_type) {
-                        new_stack.push(out_coin_type.clone());
-                    }
-                    visited_dexes.insert(dex.object_id());
-                }
-                all_hops.insert(coin_type.clone(), dexes);
-            }
+    /// Of the paths every dex of which can quote, only simulate the `max_simulated_paths` best estimated ones,
+    /// 0 to simulate all of them.
+    pub fn with_max_simulated_paths(mut self, max_simulated_paths: usize) -> Self {
+        self.max_simulated_paths = max_simulated_paths;
+        self
+    }
 
-            if is_last_hop {
-                break;
-            }
+    /// Sell `coin_types` directly to SUI instead of the process-wide `config::pegged_coin_types`.
+    pub fn with_pegged_coin_types(mut self, coin_types: &[String]) -> Self {
+        self.pegged_coin_types = Arc::new(new_pegged_coin_types(coin_types));
+        self
+    }
 
-            stack = new_stack;
-        }
+    /// Never flashloan from Navi, see `Trader::without_navi` and `flashloan_paths`.
+    pub fn without_navi(mut self) -> Self {
+        self.trader = Arc::new(self.trader.as_ref().clone().without_navi());
+        self
+    }
 
-        let mut routes = vec![];
-        dfs(coin_in_type, &mut vec![], &all_hops, &mut routes);
+    /// The `paths` a flashloan trade can be built for, see `flashloan_paths`.
+    pub fn flashloan_paths(&self, paths: Vec<Path>) -> Vec<Path> {
+        flashloan_paths(paths, self.trader.has_navi())
+    }
 
-        Ok(routes.into_iter().map(Path::new).collect())
+    #[allow(dead_code)]
+    pub async fn find_dexes(&self, coin_in_type: &str, coin_out_type: Option<String>) -> Result<Vec<Box<dyn Dex>>> {
+        self.dex_searcher
+            .find_dexes_filtered(coin_in_type, coin_out_type, &self.protocol_filter)
+            .await
+    }
+
+    //查找卖出路径(从指定代币到SUI)
+    pub async fn find_sell_paths(&self, coin_in_type: &str) -> Result<Vec<Path>> {
+        let paths = find_sell_paths(
+            self.dex_searcher.as_ref(),
+            coin_in_type,
+            &self.protocol_filter,
+            self.max_pools_per_protocol,
+            &self.pegged_coin_types,
+        )
+        .await?;
+
+        Ok(self.without_quarantined(paths))
+    }
+
+    // Resolve the lazily initialized caches (pool indexer, dex object args) ahead of the first opportunity,
+    // by searching the paths of some hot coins.
+    pub async fn warm_up(&self, coin_types: &[String]) {
+        for coin_type in coin_types {
+            let start = Instant::now();
+            match self.find_buy_paths(coin_type).await {
+                Ok(paths) => debug!(%coin_type, paths = paths.len(), elapsed = ?start.elapsed(), "Warmed up"),
+                Err(error) => warn!(%coin_type, ?error, "Warm up failed"),
+            }
+        }
     }
 
     //查找买入路径(从SUI到指定代币)
@@ -241,7 +410,41 @@ impl Defi {
         Ok(paths)
     }
 
+    // Find the cycles that start and end with `base_coin` in 3 to `max_hops` hops, e.g. SUI -> A -> B -> SUI,
+    // which can't be formed by joining a buy path and a sell path around a single coin.
+    pub async fn find_circular_paths(&self, base_coin: &str, max_hops: usize) -> Result<Vec<Path>> {
+        let paths = find_circular_paths(
+            self.dex_searcher.as_ref(),
+            base_coin,
+            max_hops,
+            &self.protocol_filter,
+            self.max_pools_per_protocol,
+        )
+        .await?;
+
+        Ok(self.without_quarantined(paths))
+    }
+
+    // the paths none of the pools of which is quarantined, see `PoolQuarantine`
+    fn without_quarantined(&self, mut paths: Vec<Path>) -> Vec<Path> {
+        paths.retain(|path| {
+            !path
+                .path
+                .iter()
+                .any(|dex| self.quarantine.is_quarantined(&dex.object_id()))
+        });
+        paths
+    }
+
+    /// Count the trials of an opportunity in the quarantine of the pools, see `PoolQuarantine::record_trials`.
+    pub fn record_pool_trials(&self, trials: &PoolTrials) {
+        self.quarantine.record_trials(trials);
+    }
+
     //查找最佳路径(从指定代币到指定代币)
+    /// The paths through `pool_id`, the pool whose swap is backrun, are always simulated, see
+    /// `with_max_simulated_paths`. What the trials did in each pool is added to `pool_trials`.
+    #[allow(clippy::too_many_arguments)]
     pub async fn find_best_path_exact_in(
         &self,
         paths: &[Path],
@@ -250,53 +453,100 @@ impl Defi {
         trade_type: TradeType,
         gas_coins: &[ObjectRef],
         sim_ctx: &SimulateCtx,
+        deadline: Option<u64>,
+        pool_id: Option<ObjectID>,
+        pool_trials: &PoolTrials,
     ) -> Result<PathTradeResult> {
         let mut joinset = JoinSet::new();
-
-        for (idx, path) in paths.iter().enumerate() {
-            if path.is_empty() {
-                continue;
-            }
+        let timer = Instant::now();
+
+        // the paths share the SimulateCtx, so they are simulated in batches, and the batches in parallel over the
+        // simulator pool
+        let indexed_paths = paths_to_simulate(paths, amount_in, self.max_simulated_paths, pool_id)
+            .into_iter()
+            .map(|idx| (idx, &paths[idx]))
+            .collect::<Vec<_>>();
+        let batch_size = simulate_batch_size(indexed_paths.len(), self.simulator_pool.len());
+        for batch in indexed_paths.chunks(batch_size) {
+            // stop spawning new simulations once the deadline has passed
+            DeadlineExceeded::check(deadline)?;
 
             let trade = self.trader.clone();
-            let path = path.clone();
+            let (idxs, batch): (Vec<_>, Vec<_>) = batch.iter().map(|(idx, path)| (*idx, (*path).clone())).unzip();
             let gas_coins = gas_coins.to_vec();
             let sim_ctx = sim_ctx.clone();
 
             joinset.spawn(
                 async move {
-                    let result = trade
-                        .get_trade_result(&path, sender, amount_in, trade_type, gas_coins, sim_ctx)
+                    let results = trade
+                        .get_trade_results(&batch, sender, amount_in, trade_type, &gas_coins, &sim_ctx, deadline)
                         .await;
 
-                    (idx, result)
+                    idxs.into_iter().zip(results).collect::<Vec<_>>()
                 }
                 .in_current_span(),
             );
         }
 
         let (mut best_idx, mut best_trade_res) = (0, TradeResult::default());
-        while let Some(Ok((idx, trade_res))) = joinset.join_next().await {
-            match trade_res {
-                Ok(trade_res) => {
-                    if trade_res > best_trade_res {
-                        best_idx = idx;
-                        best_trade_res = trade_res;
+        let mut trials = HashMap::new();
+        while let Some(Ok(results)) = joinset.join_next().await {
+            for (idx, trade_res) in results {
+                match trade_res {
+                    Ok(trade_res) => {
+                        for dex in &paths[idx].path {
+                            add_trial(&mut trials, dex.object_id(), PoolTrial::WentThrough);
+                        }
+                        // equal results are ranked by path, so the pick doesn't depend on which task finishes first
+                        let better = (&trade_res, paths[idx].tie_break_key())
+                            > (&best_trade_res, paths[best_idx].tie_break_key());
+                        if better {
+                            best_idx = idx;
+                            best_trade_res = trade_res;
+                        }
+                    }
+                    Err(error) => {
+                        // tracing::error!(path = ?paths[idx], ?error, "trade
+                        // error");
+                        if let Some(aborted) = error.downcast_ref::<HopAborted>() {
+                            debug!(%aborted, "Trial aborted by a pool");
+                            add_trial(
+                                &mut trials,
+                                aborted.pool_id,
+                                PoolTrial::Aborted(aborted.protocol.clone()),
+                            );
+                        }
                     }
-                }
-                Err(_error) => {
-                    // tracing::error!(path = ?paths[idx], ?error, "trade
-                    // error");
                 }
             }
         }
+        pool_trials.merge(trials);
+        debug!(paths = indexed_paths.len(), batch_size, elapsed = ?timer.elapsed(), "Paths simulated");
 
         ensure!(best_trade_res.amount_out > 0, "zero amount_out");
 
         Ok(PathTradeResult::new(paths[best_idx].clone(), amount_in, best_trade_res))
     }
 
+    /// The fills of the hops of `path`, parsed from the `events` of a trial. Only done for the chosen trial, the
+    /// swap events of some protocols read their pool.
+    pub async fn hop_fills(&self, path: &Path, events: &[SuiEvent]) -> Vec<HopFill> {
+        parse_hop_fills(path, events, self.simulator_pool.get()).await
+    }
+
+    /// A copy of `path` whose dexes re-read the state of their pool, see `Dex::refresh`. The pools are read
+    /// concurrently, the final tx waits for the slowest one only.
+    pub async fn refresh_path(&self, path: &Path) -> Result<Path> {
+        let mut path = path.clone();
+        try_join_all(path.path.iter_mut().map(|dex| dex.refresh(self.simulator_pool.get()))).await?;
+
+        Ok(path)
+    }
+
     //构建最终交易数据
+    /// `gas_budget` is the budget estimated by a prior simulation of the trade, `GAS_BUDGET` is used
+    /// when it is 0.
+    #[allow(clippy::too_many_arguments)]
     pub async fn build_final_tx_data(
         &self,
         sender: SuiAddress,
@@ -304,15 +554,201 @@ impl Defi {
         path: &Path,
         gas_coins: Vec<ObjectRef>,
         gas_price: u64,
+        gas_budget: u64,
         source: Source,
     ) -> Result<TransactionData> {
-        let (tx_data, _) = self
+        let pt = self
             .trader
-            .get_flashloan_trade_tx(path, sender, amount_in, gas_coins, gas_price, source)
+            .get_flashloan_trade_pt(path, sender, amount_in, &source)
             .await?;
+        let gas_budget = if gas_budget == 0 { GAS_BUDGET } else { gas_budget };
+
+        Ok(trade::new_bid_tx_data(
+            sender,
+            gas_coins,
+            pt,
+            gas_budget,
+            gas_price,
+            source.opp_tx_digest(),
+        ))
+    }
+}
+
+// The `paths` a flashloan trade can be built for: all of them with Navi. Otherwise a path whose first hop can't
+// flashloan is replaced by its first rotation that starts with SUI, like the amount in of the trials, and with a
+// hop able to flashloan, see `Path::flashloan_rotations`. The path is kept as is if it has no such rotation.
+fn flashloan_paths(paths: Vec<Path>, has_navi: bool) -> Vec<Path> {
+    if has_navi {
+        return paths;
+    }
+
+    paths
+        .into_iter()
+        .filter(|path| !path.is_empty())
+        .map(|path| {
+            if path.path[0].support_flashloan() {
+                return path;
+            }
+            path.flashloan_rotations()
+                .into_iter()
+                .find(|rotation| coin::is_native_coin(&rotation.coin_in_type()))
+                .unwrap_or(path)
+        })
+        .collect()
+}
+
+// The indexes of the non-empty `paths` worth simulating: the `max_simulated_paths` best estimated ones of the
+// paths quoted by all their dexes, the ones that can't be quoted, and the ones through `pool_id`.
+fn paths_to_simulate(
+    paths: &[Path],
+    amount_in: u64,
+    max_simulated_paths: usize,
+    pool_id: Option<ObjectID>,
+) -> Vec<usize> {
+    let mut idxs = vec![];
+    let mut quoted = vec![];
+    for (idx, path) in paths.iter().enumerate().filter(|(_, path)| !path.is_empty()) {
+        match path.quote(amount_in) {
+            Some(amount_out) if max_simulated_paths > 0 && !path.contains_pool(pool_id) => {
+                quoted.push((amount_out, idx))
+            }
+            _ => idxs.push(idx),
+        }
+    }
+
+    // the best estimates first, equal ones by index
+    quoted.sort_by(|(a_out, a_idx), (b_out, b_idx)| b_out.cmp(a_out).then(a_idx.cmp(b_idx)));
+    idxs.extend(quoted.into_iter().take(max_simulated_paths).map(|(_, idx)| idx));
+    idxs.sort_unstable();
+    idxs
+}
+
+// the paths of a batch are simulated one after the other, so the batches are as small as needed for every
+// simulator to get one, and no larger than `SIMULATE_BATCH_SIZE`
+fn simulate_batch_size(paths: usize, simulators: usize) -> usize {
+    paths.div_ceil(simulators.max(1)).clamp(1, SIMULATE_BATCH_SIZE)
+}
+
+async fn find_sell_paths(
+    dex_searcher: &dyn DexSearcher,
+    coin_in_type: &str,
+    protocol_filter: &ProtocolFilter,
+    max_pools_per_protocol: usize,
+    pegged_coin_types: &HashSet<String>,
+) -> Result<Vec<Path>> {
+    if coin::is_native_coin(coin_in_type) {
+        return Ok(vec![Path::default()]);
+    }
+
+    let mut all_hops = HashMap::new();
+    let mut stack = vec![coin_in_type.to_string()];
+    let mut visited = HashSet::new();
+    let mut visited_dexes = HashSet::new();
+
+    for nth_hop in 0..MAX_HOP_COUNT {
+        let is_last_hop = nth_hop == MAX_HOP_COUNT - 1;
+        let mut new_stack = vec![];
+
+        while let Some(coin_type) = stack.pop() {
+            if visited.contains(&coin_type) || coin::is_native_coin(&coin_type) {
+                continue;
+            }
+            visited.insert(coin_type.clone());
+
+            let coin_out_type = if pegged_coin_types.contains(coin_type.as_str()) || is_last_hop {
+                Some(SUI_COIN_TYPE.to_string())
+            } else {
+                None
+            };
+            let mut dexes = if let Ok(dexes) = dex_searcher
+                .find_dexes_filtered(&coin_type, coin_out_type, protocol_filter)
+                .await
+            {
+                dexes
+            } else {
+                continue;
+            };
+
+            dexes.retain(|dex| dex.liquidity() >= MIN_LIQUIDITY);
+            let (now_ms, since_ms) = (::utils::current_time_ms(), dex_searcher.pool_states_since_ms());
+            let activity = |pool_id| PoolActivity::of(dex_searcher.pool_state(&pool_id), now_ms, since_ms);
+            let dexes = prune_dexes(dexes, &visited_dexes, max_pools_per_protocol, activity);
+
+            if dexes.is_empty() {
+                continue;
+            }
+
+            for dex in &dexes {
+                let out_coin_type = dex.coin_out_type();
+                if !visited.contains(&out_coin_type) {
+                    new_stack.push(out_coin_type.clone());
+                }
+                visited_dexes.insert(dex.object_id());
+            }
+            all_hops.insert(coin_type.clone(), dexes);
+        }
+
+        if is_last_hop {
+            break;
+        }
 
-        Ok(tx_data)
+        stack = new_stack;
     }
+
+    let mut routes = vec![];
+    dfs(coin_in_type, &mut vec![], &all_hops, &mut routes);
+
+    Ok(routes.into_iter().map(Path::new).collect())
+}
+
+// At most `MAX_POOL_COUNT` of the `dexes` of a coin are kept, the `max_pools_per_protocol` best ranked ones of
+// each protocol first and then the best ranked of the others, so that the single pool of a protocol, which may
+// well have the off-market price, is not crowded out by the deeper pools of another one. The pools are ranked by
+// liquidity, then by their `activity`.
+// The pools already kept for a previous coin are left out, unless they are the only ones of their protocol.
+// With a `max_pools_per_protocol` of 0, the pools are only ranked and the visited ones are always left out.
+fn prune_dexes(
+    mut dexes: Vec<Box<dyn Dex>>,
+    visited_dexes: &HashSet<ObjectID>,
+    max_pools_per_protocol: usize,
+    activity: impl Fn(ObjectID) -> PoolActivity,
+) -> Vec<Box<dyn Dex>> {
+    if dexes.len() <= MAX_POOL_COUNT {
+        return dexes;
+    }
+
+    let rank = |dex: &dyn Dex| (std::cmp::Reverse(dex.liquidity()), activity(dex.object_id()));
+    if max_pools_per_protocol == 0 {
+        dexes.retain(|dex| !visited_dexes.contains(&dex.object_id()));
+        dexes.sort_by_cached_key(|dex| rank(dex.as_ref()));
+        dexes.truncate(MAX_POOL_COUNT);
+        return dexes;
+    }
+
+    let unvisited_protocols = dexes
+        .iter()
+        .filter(|dex| !visited_dexes.contains(&dex.object_id()))
+        .map(|dex| dex.protocol())
+        .collect::<HashSet<_>>();
+    dexes.retain(|dex| !visited_dexes.contains(&dex.object_id()) || !unvisited_protocols.contains(&dex.protocol()));
+    dexes.sort_by_cached_key(|dex| rank(dex.as_ref()));
+
+    let mut protocol_counts = HashMap::new();
+    let (mut kept, mut others) = (vec![], vec![]);
+    for dex in dexes {
+        let count = protocol_counts.entry(dex.protocol()).or_insert(0);
+        if *count < max_pools_per_protocol && kept.len() < MAX_POOL_COUNT {
+            *count += 1;
+            kept.push(dex);
+        } else {
+            others.push(dex);
+        }
+    }
+
+    let room = MAX_POOL_COUNT.saturating_sub(kept.len());
+    kept.extend(others.into_iter().take(room));
+    kept.sort_by_cached_key(|dex| rank(dex.as_ref()));
+    kept
 }
 
 fn dfs(
@@ -338,13 +774,124 @@ fn dfs(
     }
 }
 
+async fn find_circular_paths(
+    dex_searcher: &dyn DexSearcher,
+    base_coin: &str,
+    max_hops: usize,
+    protocol_filter: &ProtocolFilter,
+    max_pools_per_protocol: usize,
+) -> Result<Vec<Path>> {
+    if max_hops < MIN_CYCLE_HOP_COUNT {
+        return Ok(vec![]);
+    }
+
+    let mut all_hops = HashMap::new();
+    let mut stack = vec![base_coin.to_string()];
+    let mut visited = HashSet::new();
+    let mut visited_dexes = HashSet::new();
+
+    for nth_hop in 0..max_hops {
+        let is_last_hop = nth_hop == max_hops - 1;
+        let mut new_stack = vec![];
+
+        while let Some(coin_type) = stack.pop() {
+            if visited.contains(&coin_type) {
+                continue;
+            }
+            visited.insert(coin_type.clone());
+
+            // the last hop has to return to the base coin
+            let coin_out_type = is_last_hop.then(|| base_coin.to_string());
+            let mut dexes = if let Ok(dexes) = dex_searcher
+                .find_dexes_filtered(&coin_type, coin_out_type, protocol_filter)
+                .await
+            {
+                dexes
+            } else {
+                continue;
+            };
+
+            dexes.retain(|dex| dex.liquidity() >= MIN_LIQUIDITY);
+            let (now_ms, since_ms) = (::utils::current_time_ms(), dex_searcher.pool_states_since_ms());
+            let activity = |pool_id| PoolActivity::of(dex_searcher.pool_state(&pool_id), now_ms, since_ms);
+            let dexes = prune_dexes(dexes, &visited_dexes, max_pools_per_protocol, activity);
+
+            if dexes.is_empty() {
+                continue;
+            }
+
+            for dex in &dexes {
+                let out_coin_type = dex.coin_out_type();
+                if out_coin_type != base_coin && !visited.contains(&out_coin_type) {
+                    new_stack.push(out_coin_type);
+                }
+                visited_dexes.insert(dex.object_id());
+            }
+            all_hops.insert(coin_type, dexes);
+        }
+
+        if is_last_hop {
+            break;
+        }
+
+        stack = new_stack;
+    }
+
+    let mut routes = vec![];
+    dfs_cycles(base_coin, base_coin, max_hops, &mut vec![], &all_hops, &mut routes);
+
+    Ok(routes.into_iter().map(Path::new).collect())
+}
+
+// a cycle goes through each pool and each intermediate coin at most once
+fn dfs_cycles(
+    base_coin: &str,
+    coin_type: &str,
+    max_hops: usize,
+    path: &mut Vec<Box<dyn Dex>>,
+    hops: &HashMap<String, Vec<Box<dyn Dex>>>,
+    routes: &mut Vec<Vec<Box<dyn Dex>>>,
+) {
+    if path.len() >= max_hops {
+        return;
+    }
+    let Some(dexes) = hops.get(coin_type) else {
+        return;
+    };
+
+    for dex in dexes {
+        if path.contains(dex) {
+            continue;
+        }
+
+        let coin_out_type = dex.coin_out_type();
+        if coin_out_type == base_coin {
+            if path.len() + 1 >= MIN_CYCLE_HOP_COUNT {
+                let mut route = path.clone();
+                route.push(dex.clone());
+                routes.push(route);
+            }
+            continue;
+        }
+        if path.iter().any(|hop| hop.coin_in_type() == coin_out_type) {
+            continue;
+        }
+
+        path.push(dex.clone());
+        dfs_cycles(base_coin, &coin_out_type, max_hops, path, hops, routes);
+        path.pop();
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PathTradeResult {
     pub path: Path,
     pub amount_in: u64,
     pub amount_out: u64,
     pub gas_cost: i64,
+    pub gas_budget: u64,
     pub cache_misses: u64,
+    pub events: Vec<SuiEvent>,
 }
 
 impl PathTradeResult {
@@ -354,7 +901,9 @@ impl PathTradeResult {
             amount_in,
             amount_out: trade_res.amount_out,
             gas_cost: trade_res.gas_cost,
+            gas_budget: trade_res.gas_budget,
             cache_misses: trade_res.cache_misses,
+            events: trade_res.events,
         }
     }
 
@@ -374,35 +923,540 @@ impl fmt::Display for PathTradeResult {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "PathTradeResult {{ amount_in: {}, amount_out: {}, profit: {}, path: {:?} ... }}",
+            "PathTradeResult {{ amount_in: {}, amount_out: {}, gas_cost: {}, profit: {}, path: {:?} ... }}",
             self.amount_in,
             self.amount_out,
+            self.gas_cost,
             self.profit(),
             self.path
         )
     }
 }
 
+// a pool that can't be traded, for tests that only look at the shape of paths
+#[cfg(test)]
+#[derive(Clone)]
+pub struct StubDex {
+    pool_id: ObjectID,
+    coin_in_type: String,
+    coin_out_type: String,
+    liquidity: u128,
+    protocol: Protocol,
+    // amount out per 10_000 in, `None` if it can't quote
+    quote_rate: Option<u64>,
+    flashloan: bool,
+}
+
+#[cfg(test)]
+impl StubDex {
+    pub fn new(pool_id: ObjectID, coin_in_type: &str, coin_out_type: &str, liquidity: u128) -> Self {
+        Self {
+            pool_id,
+            coin_in_type: coin_in_type.to_string(),
+            coin_out_type: coin_out_type.to_string(),
+            liquidity,
+            protocol: Protocol::Cetus,
+            quote_rate: None,
+            flashloan: false,
+        }
+    }
+
+    pub fn with_protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn with_quote_rate(mut self, quote_rate: u64) -> Self {
+        self.quote_rate = Some(quote_rate);
+        self
+    }
+
+    pub fn with_flashloan(mut self) -> Self {
+        self.flashloan = true;
+        self
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl Dex for StubDex {
+    fn support_flashloan(&self) -> bool {
+        self.flashloan
+    }
+
+    async fn extend_trade_tx(
+        &self,
+        _ctx: &mut TradeCtx,
+        _sender: SuiAddress,
+        _coin_in: Argument,
+        _amount_in: Option<u64>,
+    ) -> Result<Argument> {
+        bail!("stub dex")
+    }
+
+    fn coin_in_type(&self) -> String {
+        self.coin_in_type.clone()
+    }
+
+    fn coin_out_type(&self) -> String {
+        self.coin_out_type.clone()
+    }
+
+    fn protocol(&self) -> Protocol {
+        self.protocol.clone()
+    }
+
+    fn liquidity(&self) -> u128 {
+        self.liquidity
+    }
+
+    fn quote(&self, amount_in: u64) -> Option<u64> {
+        self.quote_rate.map(|rate| amount_in * rate / 10_000)
+    }
+
+    fn object_id(&self) -> ObjectID {
+        self.pool_id
+    }
+
+    fn flip(&mut self) {
+        std::mem::swap(&mut self.coin_in_type, &mut self.coin_out_type);
+    }
+
+    fn is_a2b(&self) -> bool {
+        true
+    }
+
+    async fn swap_tx(&self, _sender: SuiAddress, _recipient: SuiAddress, _amount_in: u64) -> Result<TransactionData> {
+        bail!("stub dex")
+    }
+}
+
+// a path through stub pools, for tests that only look at the shape of a path
+#[cfg(test)]
+pub fn stub_path(pool_ids: &[ObjectID]) -> Path {
+    Path::new(
+        pool_ids
+            .iter()
+            .map(|pool_id| Box::new(StubDex::new(*pool_id, SUI_COIN_TYPE, SUI_COIN_TYPE, 0)) as Box<dyn Dex>)
+            .collect(),
+    )
+}
+
+// protocols whose object args are resolved, see `Defi::warm_up`
+#[cfg(test)]
+pub fn warmed_up_protocols() -> Vec<Protocol> {
+    [
+        (Protocol::Cetus, cetus::object_args_initialized()),
+        (Protocol::Turbos, turbos::object_args_initialized()),
+    ]
+    .into_iter()
+    .filter_map(|(protocol, initialized)| initialized.then_some(protocol))
+    .collect()
+}
+
 #[cfg(test)]
 mod tests {
 
     use simulator::HttpSimulator;
+    use sui_sdk::SuiClientBuilder;
     use tracing::info;
 
+    use dex_indexer::{types::SwapEvent, PoolStateCache};
+
     use super::*;
-    use crate::config::tests::TEST_HTTP_URL;
+    use crate::{common::get_latest_epoch, config::tests::TEST_HTTP_URL, tests::CountingSimulator};
+
+    // serves a fixed set of pools in both directions
+    struct MockDexSearcher(Vec<StubDex>);
+
+    #[async_trait::async_trait]
+    impl DexSearcher for MockDexSearcher {
+        async fn find_dexes_filtered(
+            &self,
+            coin_in_type: &str,
+            coin_out_type: Option<String>,
+            filter: &ProtocolFilter,
+        ) -> Result<Vec<Box<dyn Dex>>> {
+            let dexes = self
+                .0
+                .iter()
+                .filter(|dex| filter.allows(&dex.protocol))
+                .flat_map(|dex| {
+                    let mut flipped = dex.clone();
+                    flipped.flip();
+                    [dex.clone(), flipped]
+                })
+                .filter(|dex| dex.coin_in_type() == coin_in_type)
+                .filter(|dex| match &coin_out_type {
+                    Some(coin_out_type) => dex.coin_out_type() == *coin_out_type,
+                    None => true,
+                })
+                .map(|dex| Box::new(dex) as Box<dyn Dex>)
+                .collect();
+
+            Ok(dexes)
+        }
+
+        async fn find_test_path(
+            &self,
+            _path: &[ObjectID],
+            _coin_in: Option<&str>,
+            _filter: &ProtocolFilter,
+        ) -> Result<Path> {
+            bail!("not supported")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_circular_paths_in_triangle() {
+        let coin_a = "0x1::a::A";
+        let coin_b = "0x1::b::B";
+        let (pool1, pool2, pool3) = (ObjectID::random(), ObjectID::random(), ObjectID::random());
+        let liquidity = MIN_LIQUIDITY * 10;
+        let searcher = MockDexSearcher(vec![
+            StubDex::new(pool1, SUI_COIN_TYPE, coin_a, liquidity),
+            StubDex::new(pool2, coin_a, coin_b, liquidity),
+            StubDex::new(pool3, coin_b, SUI_COIN_TYPE, liquidity),
+            // pruned for its liquidity
+            StubDex::new(ObjectID::random(), coin_a, SUI_COIN_TYPE, MIN_LIQUIDITY - 1),
+        ]);
+
+        // a triangle doesn't fit in 2 hops
+        let paths = find_circular_paths(&searcher, SUI_COIN_TYPE, 2, &ProtocolFilter::default(), 0)
+            .await
+            .unwrap();
+        assert!(paths.is_empty());
+
+        let paths = find_circular_paths(&searcher, SUI_COIN_TYPE, 3, &ProtocolFilter::default(), 0)
+            .await
+            .unwrap();
+        let pool_ids = paths
+            .iter()
+            .map(|path| path.path.iter().map(|dex| dex.object_id()).collect::<Vec<_>>())
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            pool_ids,
+            HashSet::from([vec![pool1, pool2, pool3], vec![pool3, pool2, pool1]])
+        );
+
+        for path in &paths {
+            assert_eq!(path.coin_in_type(), SUI_COIN_TYPE);
+            assert_eq!(path.coin_out_type(), SUI_COIN_TYPE);
+            assert!(path.contains_coin(coin_a) && path.contains_coin(coin_b));
+            for hops in path.path.windows(2) {
+                assert_eq!(hops[0].coin_out_type(), hops[1].coin_in_type());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_denied_protocol_never_in_paths() {
+        let coin_a = "0x1::a::A";
+        let coin_b = "0x1::b::B";
+        let liquidity = MIN_LIQUIDITY * 10;
+        let (cetus_pool, turbos_pool) = (ObjectID::random(), ObjectID::random());
+        let searcher = MockDexSearcher(vec![
+            StubDex::new(cetus_pool, coin_a, SUI_COIN_TYPE, liquidity),
+            StubDex::new(turbos_pool, coin_a, SUI_COIN_TYPE, liquidity).with_protocol(Protocol::Turbos),
+            StubDex::new(ObjectID::random(), coin_a, coin_b, liquidity).with_protocol(Protocol::Turbos),
+            StubDex::new(ObjectID::random(), coin_b, SUI_COIN_TYPE, liquidity),
+            StubDex::new(ObjectID::random(), SUI_COIN_TYPE, coin_b, liquidity).with_protocol(Protocol::Turbos),
+        ]);
+
+        let paths = find_sell_paths(&searcher, coin_a, &ProtocolFilter::default(), 0, &HashSet::new())
+            .await
+            .unwrap();
+        assert!(paths
+            .iter()
+            .any(|path| path.path.iter().any(|dex| dex.protocol() == Protocol::Turbos)));
+
+        let filters = [
+            ProtocolFilter::new([], [Protocol::Turbos]),
+            ProtocolFilter::new([Protocol::Cetus], []),
+        ];
+        for filter in filters {
+            let paths = find_sell_paths(&searcher, coin_a, &filter, 0, &HashSet::new())
+                .await
+                .unwrap();
+            assert!(!paths.is_empty());
+            assert!(paths.iter().any(|path| path.path[0].object_id() == cetus_pool));
+            for path in &paths {
+                assert!(
+                    path.path.iter().all(|dex| dex.protocol() == Protocol::Cetus),
+                    "{path:?}"
+                );
+            }
+
+            let paths = find_circular_paths(&searcher, SUI_COIN_TYPE, 3, &filter, 0)
+                .await
+                .unwrap();
+            for path in &paths {
+                assert!(
+                    path.path.iter().all(|dex| dex.protocol() == Protocol::Cetus),
+                    "{path:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_paths_to_simulate_keeps_best_estimate() {
+        let coin_a = "0x1::a::A";
+        let stub = |pool_id: ObjectID, quote_rate: u64| -> Box<dyn Dex> {
+            Box::new(StubDex::new(pool_id, SUI_COIN_TYPE, coin_a, MIN_LIQUIDITY).with_quote_rate(quote_rate))
+        };
+
+        // 2-hop paths losing 1% to 30%, and the known-best one gaining 5% in the middle
+        let mut paths = (1..=30)
+            .map(|loss| {
+                Path::new(vec![
+                    stub(ObjectID::random(), 10_000 - loss * 100),
+                    stub(ObjectID::random(), 10_000),
+                ])
+            })
+            .collect::<Vec<_>>();
+        paths.insert(
+            17,
+            Path::new(vec![stub(ObjectID::random(), 10_300), stub(ObjectID::random(), 10_194)]),
+        );
+        // can't be estimated
+        paths.push(stub_path(&[ObjectID::random()]));
+        let unquoted = paths.len() - 1;
+        // the worst estimate, but through the fluctuating pool
+        let fluctuating_pool = ObjectID::random();
+        paths.push(Path::new(vec![stub(fluctuating_pool, 1)]));
+        let fluctuating = paths.len() - 1;
+        paths.push(Path::default());
+
+        let amount_in = 1_000_000_000;
+        assert_eq!(paths[17].quote(amount_in), Some(1_049_982_000));
+
+        let idxs = paths_to_simulate(&paths, amount_in, 4, Some(fluctuating_pool));
+        assert_eq!(idxs, vec![0, 1, 2, 17, unquoted, fluctuating]);
+
+        // without pruning all the non-empty paths are simulated
+        let idxs = paths_to_simulate(&paths, amount_in, 0, None);
+        assert_eq!(idxs, (0..paths.len() - 1).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_simulate_batch_size() {
+        // one path per simulator while there are enough of them
+        assert_eq!(simulate_batch_size(16, 32), 1);
+        assert_eq!(simulate_batch_size(16, 16), 1);
+        // then the paths are spread evenly
+        assert_eq!(simulate_batch_size(16, 8), 2);
+        assert_eq!(simulate_batch_size(17, 8), 3);
+        // up to the max batch size, the other batches wait for a simulator
+        assert_eq!(simulate_batch_size(100, 4), SIMULATE_BATCH_SIZE);
+        assert_eq!(simulate_batch_size(0, 4), 1);
+        assert_eq!(simulate_batch_size(3, 0), 3);
+    }
+
+    #[test]
+    fn test_flashloan_paths_rotate_without_navi() {
+        let (coin_a, coin_b) = ("0x1::a::A", "0x1::b::B");
+        let pools = [(); 4].map(|_| ObjectID::random());
+        let hop = |i: usize, coin_in: &str, coin_out: &str, flashloan: bool| -> Box<dyn Dex> {
+            let dex = StubDex::new(pools[i], coin_in, coin_out, MIN_LIQUIDITY);
+            Box::new(if flashloan { dex.with_flashloan() } else { dex })
+        };
+        // SUI -> A -> SUI -> B -> SUI, the third hop can flashloan if `flashloan`
+        let circular = |flashloan: bool| {
+            Path::new(vec![
+                hop(0, SUI_COIN_TYPE, coin_a, false),
+                hop(1, coin_a, SUI_COIN_TYPE, true),
+                hop(2, SUI_COIN_TYPE, coin_b, flashloan),
+                hop(3, coin_b, SUI_COIN_TYPE, false),
+            ])
+        };
+        let ids = |path: &Path| path.path.iter().map(|dex| dex.object_id()).collect::<Vec<_>>();
+
+        // rotated onto the flashloan hop that starts with SUI, each hop in its own direction
+        let paths = flashloan_paths(vec![circular(true)], false);
+        assert_eq!(paths.len(), 1);
+        assert_eq!(ids(&paths[0]), [pools[2], pools[3], pools[0], pools[1]]);
+        assert_eq!(paths[0].coin_in_type(), SUI_COIN_TYPE);
+        assert_eq!(paths[0].coin_out_type(), SUI_COIN_TYPE);
+
+        // the rotation onto the second hop would start with A, the path is kept as is
+        let paths = flashloan_paths(vec![circular(false)], false);
+        assert_eq!(ids(&paths[0]), pools);
+
+        // a flashloanable path doesn't drop the others
+        let flashloanable = Path::new(vec![
+            hop(2, SUI_COIN_TYPE, coin_b, true),
+            hop(3, coin_b, SUI_COIN_TYPE, false),
+        ]);
+        let paths = flashloan_paths(vec![flashloanable, circular(false), Path::default()], false);
+        assert_eq!(paths.len(), 2);
+        assert_eq!(ids(&paths[0]), [pools[2], pools[3]]);
+        assert_eq!(ids(&paths[1]), pools);
+
+        // Navi flashloans for any path
+        let paths = flashloan_paths(vec![circular(true)], true);
+        assert_eq!(ids(&paths[0]), pools);
+    }
+
+    #[test]
+    fn test_prune_dexes_keeps_each_protocol() {
+        let coin_a = "0x1::a::A";
+        let stub = |protocol: Protocol, liquidity: u128| -> Box<dyn Dex> {
+            Box::new(StubDex::new(ObjectID::random(), coin_a, SUI_COIN_TYPE, liquidity).with_protocol(protocol))
+        };
+        let mut dexes = (0..12)
+            .map(|i| stub(Protocol::Cetus, MIN_LIQUIDITY * (100 + i)))
+            .collect::<Vec<_>>();
+        dexes.push(stub(Protocol::Turbos, MIN_LIQUIDITY * 2));
+        dexes.push(stub(Protocol::DeepbookV2, MIN_LIQUIDITY));
+        let deepbook_pool = dexes.last().unwrap().object_id();
+
+        let count =
+            |dexes: &[Box<dyn Dex>], protocol: Protocol| dexes.iter().filter(|dex| dex.protocol() == protocol).count();
+
+        // by liquidity alone, only the cetus pools are kept
+        let pruned = prune_dexes(dexes.clone(), &HashSet::new(), 0, |_| PoolActivity::Unknown);
+        assert_eq!(pruned.len(), MAX_POOL_COUNT);
+        assert_eq!(count(&pruned, Protocol::Cetus), MAX_POOL_COUNT);
+
+        let pruned = prune_dexes(dexes.clone(), &HashSet::new(), 3, |_| PoolActivity::Unknown);
+        assert_eq!(pruned.len(), MAX_POOL_COUNT);
+        assert_eq!(count(&pruned, Protocol::Turbos), 1);
+        assert_eq!(count(&pruned, Protocol::DeepbookV2), 1);
+        assert_eq!(count(&pruned, Protocol::Cetus), MAX_POOL_COUNT - 2);
+        // still sorted by liquidity
+        assert!(pruned.windows(2).all(|w| w[0].liquidity() >= w[1].liquidity()));
+
+        // a visited pool that is the only one of its protocol is not dropped
+        let pruned = prune_dexes(dexes.clone(), &HashSet::from([deepbook_pool]), 3, |_| {
+            PoolActivity::Unknown
+        });
+        assert_eq!(count(&pruned, Protocol::DeepbookV2), 1);
+
+        // the visited cetus pools make room for the other cetus pools
+        let visited = dexes
+            .iter()
+            .filter(|dex| dex.protocol() == Protocol::Cetus)
+            .take(4)
+            .map(|dex| dex.object_id())
+            .collect::<HashSet<_>>();
+        let pruned = prune_dexes(dexes.clone(), &visited, 3, |_| PoolActivity::Unknown);
+        assert_eq!(pruned.len(), MAX_POOL_COUNT);
+        assert!(pruned.iter().all(|dex| !visited.contains(&dex.object_id())));
+
+        // by liquidity alone, the visited pools are dropped, even the only one of its protocol
+        let visited = visited.into_iter().chain([deepbook_pool]).collect::<HashSet<_>>();
+        let unvisited_count = dexes.len() - visited.len();
+        let pruned = prune_dexes(dexes, &visited, 0, |_| PoolActivity::Unknown);
+        assert_eq!(pruned.len(), unvisited_count);
+        assert!(pruned.iter().all(|dex| !visited.contains(&dex.object_id())));
+        assert_eq!(count(&pruned, Protocol::DeepbookV2), 0);
+        assert!(pruned.windows(2).all(|w| w[0].liquidity() >= w[1].liquidity()));
+    }
+
+    #[test]
+    fn test_prune_dexes_by_pool_activity() {
+        // the swaps are recorded since the start, 10 hours ago
+        let cache = PoolStateCache::new_since(0);
+        let now_ms = 10 * 60 * 60 * 1000;
+        // 10 is the most liquid pool, then 8 and 9, 6 and 7 and so on
+        let dexes = (0..11)
+            .map(|i| {
+                let dex = StubDex::new(
+                    ObjectID::random(),
+                    "0x1::a::A",
+                    SUI_COIN_TYPE,
+                    MIN_LIQUIDITY * (100 + i / 2),
+                );
+                Box::new(dex) as Box<dyn Dex>
+            })
+            .collect::<Vec<_>>();
+        let swap = |dex: &dyn Dex, ago: Duration| {
+            let swap_event = SwapEvent {
+                protocol: Protocol::Cetus,
+                pool: Some(dex.object_id()),
+                coins_in: vec![dex.coin_in_type()],
+                coins_out: vec![dex.coin_out_type()],
+                amounts_in: vec![1_000],
+                amounts_out: vec![2_000],
+            };
+            cache.update(&swap_event, now_ms - ago.as_millis() as u64);
+        };
+        // the most liquid pool didn't swap for 3 hours, 1 swapped a minute ago, 9 an hour ago
+        swap(dexes[10].as_ref(), Duration::from_secs(3 * 60 * 60));
+        swap(dexes[1].as_ref(), Duration::from_secs(60));
+        swap(dexes[9].as_ref(), Duration::from_secs(60 * 60));
+
+        let activity = |pool_id| PoolActivity::of(cache.get(&pool_id), now_ms, Some(cache.since_ms()));
+        assert_eq!(activity(dexes[1].object_id()), PoolActivity::Active);
+        assert_eq!(activity(dexes[9].object_id()), PoolActivity::Unknown);
+        assert_eq!(activity(dexes[10].object_id()), PoolActivity::Silent);
+        // not seen swapping in 10 hours
+        assert_eq!(activity(dexes[0].object_id()), PoolActivity::Silent);
+        // nor in the hour since the start, or by a searcher that doesn't record the swaps
+        assert_eq!(
+            PoolActivity::of(None, now_ms, Some(now_ms - 60 * 60 * 1000)),
+            PoolActivity::Unknown
+        );
+        assert_eq!(PoolActivity::of(None, now_ms, None), PoolActivity::Unknown);
+
+        let pruned = prune_dexes(dexes.clone(), &HashSet::new(), 0, activity);
+        let ids = |dexes: &[Box<dyn Dex>]| dexes.iter().map(|dex| dex.object_id()).collect::<Vec<_>>();
+        // by liquidity, the silent pool 10 still first, the activity only breaking the ties of 8 and 9, and of 0
+        // and 1
+        let expected = [10, 9, 8, 6, 7, 4, 5, 2, 3, 1].map(|i| dexes[i].object_id());
+        assert_eq!(ids(&pruned), expected);
+    }
+
+    #[tokio::test]
+    async fn test_find_sell_paths_per_protocol() {
+        let coin_a = "0x1::a::A";
+        let mut pools = (0..12)
+            .map(|i| StubDex::new(ObjectID::random(), coin_a, SUI_COIN_TYPE, MIN_LIQUIDITY * (100 + i)))
+            .collect::<Vec<_>>();
+        let turbos_pool = ObjectID::random();
+        pools.push(StubDex::new(turbos_pool, coin_a, SUI_COIN_TYPE, MIN_LIQUIDITY).with_protocol(Protocol::Turbos));
+        let searcher = MockDexSearcher(pools);
+
+        let paths = find_sell_paths(&searcher, coin_a, &ProtocolFilter::default(), 0, &HashSet::new())
+            .await
+            .unwrap();
+        assert_eq!(paths.len(), MAX_POOL_COUNT);
+        assert!(paths.iter().all(|path| path.path[0].object_id() != turbos_pool));
+
+        let paths = find_sell_paths(
+            &searcher,
+            coin_a,
+            &ProtocolFilter::default(),
+            DEFAULT_MAX_POOLS_PER_PROTOCOL,
+            &HashSet::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(paths.len(), MAX_POOL_COUNT);
+        assert!(paths.iter().any(|path| path.path[0].object_id() == turbos_pool));
+    }
+
+    #[test]
+    fn test_protocol_filter() {
+        assert!(ProtocolFilter::default().allows(&Protocol::Cetus));
+
+        let filter = ProtocolFilter::new([Protocol::Cetus, Protocol::Turbos], [Protocol::Turbos]);
+        assert!(filter.allows(&Protocol::Cetus));
+        // denied wins over allowed
+        assert!(!filter.allows(&Protocol::Turbos));
+        assert!(!filter.allows(&Protocol::Aftermath));
+    }
 
     #[tokio::test]
     async fn test_find_sell_paths() {
         mev_logger::init_console_logger_with_directives(None, &["arb=debug", "dex_indexer=debug"]);
 
-        let simulator_pool = ObjectPool::new(1, move || {
-            tokio::runtime::Runtime::new()
-                .unwrap()
-                .block_on(async { Box::new(HttpSimulator::new(&TEST_HTTP_URL, &None).await) as Box<dyn Simulator> })
+        let simulator_pool = ObjectPool::new_async(1, || async {
+            Box::new(HttpSimulator::new(&TEST_HTTP_URL, &None).await) as Box<dyn Simulator>
         });
 
-        let defi = Defi::new(TEST_HTTP_URL, Arc::new(simulator_pool)).await.unwrap();
+        let defi = Defi::new(TEST_HTTP_URL, Arc::new(simulator_pool), false).await.unwrap();
 
         let coin_in_type = "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN";
         let paths = defi.find_sell_paths(coin_in_type).await.unwrap();
@@ -417,13 +1471,11 @@ mod tests {
     async fn test_find_buy_paths() {
         mev_logger::init_console_logger_with_directives(None, &["arb=debug", "dex_indexer=debug"]);
 
-        let simulator_pool = ObjectPool::new(1, move || {
-            tokio::runtime::Runtime::new()
-                .unwrap()
-                .block_on(async { Box::new(HttpSimulator::new(&TEST_HTTP_URL, &None).await) as Box<dyn Simulator> })
+        let simulator_pool = ObjectPool::new_async(1, || async {
+            Box::new(HttpSimulator::new(&TEST_HTTP_URL, &None).await) as Box<dyn Simulator>
         });
 
-        let defi = Defi::new(TEST_HTTP_URL, Arc::new(simulator_pool)).await.unwrap();
+        let defi = Defi::new(TEST_HTTP_URL, Arc::new(simulator_pool), false).await.unwrap();
 
         let coin_out_type = "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN";
         let paths = defi.find_buy_paths(coin_out_type).await.unwrap();
@@ -432,4 +1484,111 @@ mod tests {
             info!(?path, "buy")
         }
     }
+
+    #[tokio::test]
+    async fn test_pegged_coin_gets_direct_sui_hop() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=debug", "dex_indexer=debug"]);
+
+        let simulator_pool = ObjectPool::new_async(1, || async {
+            Box::new(HttpSimulator::new(&TEST_HTTP_URL, &None).await) as Box<dyn Simulator>
+        });
+
+        let defi = Defi::new(TEST_HTTP_URL, Arc::new(simulator_pool), false).await.unwrap();
+
+        let coin_in_type = "0xdeeb7a4662eec9f2f3def03fb937a663dddaa2e215b8078a284d026b7946c270::deep::DEEP";
+        let paths = defi.find_sell_paths(coin_in_type).await.unwrap();
+        assert!(
+            paths.iter().any(|path| path.path[0].coin_out_type() != SUI_COIN_TYPE),
+            "expected indirect sell paths"
+        );
+
+        // the first level only searches for direct-to-SUI pools now
+        let defi = defi.with_pegged_coin_types(&[coin_in_type.to_string()]);
+        let paths = defi.find_sell_paths(coin_in_type).await.unwrap();
+        assert!(!paths.is_empty(), "No sell paths found");
+        for path in paths {
+            assert_eq!(path.path.len(), 1, "{:?}", path);
+            assert_eq!(path.coin_out_type(), SUI_COIN_TYPE);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_best_path_exact_in_past_deadline() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=debug", "dex_indexer=debug"]);
+
+        let (simulator_pool, counts) = CountingSimulator::new_pool(1);
+
+        let defi = Defi::new(TEST_HTTP_URL, Arc::new(simulator_pool), false).await.unwrap();
+
+        let coin_out_type = "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN";
+        let paths = defi.find_buy_paths(coin_out_type).await.unwrap();
+        assert!(!paths.is_empty(), "No buy paths found");
+
+        let sui = SuiClientBuilder::default().build(TEST_HTTP_URL).await.unwrap();
+        let epoch = get_latest_epoch(&sui).await.unwrap();
+        let sim_ctx = SimulateCtx::new(epoch, vec![]);
+
+        let deadline = Some(::utils::current_time_ms() - 1_000);
+        let error = defi
+            .find_best_path_exact_in(
+                &paths,
+                SuiAddress::ZERO,
+                1_000_000_000,
+                TradeType::Swap,
+                &[],
+                &sim_ctx,
+                deadline,
+                None,
+                &PoolTrials::default(),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(error.is::<DeadlineExceeded>(), "unexpected error: {error:#}");
+        assert_eq!(counts.simulations(), 0);
+    }
+
+    // cargo test -r -p arb bench_find_best_path_exact_in -- --ignored --nocapture
+    #[tokio::test]
+    #[ignore]
+    async fn bench_find_best_path_exact_in() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=info"]);
+
+        let sui = SuiClientBuilder::default().build(TEST_HTTP_URL).await.unwrap();
+        let sim_ctx = SimulateCtx::new(get_latest_epoch(&sui).await.unwrap(), vec![]);
+        let coin_out_type = "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN";
+
+        for simulators in [1, 4, 16] {
+            let simulator_pool = ObjectPool::new_async(simulators, || async {
+                Box::new(HttpSimulator::new(&TEST_HTTP_URL, &None).await) as Box<dyn Simulator>
+            });
+            let defi = Defi::new(TEST_HTTP_URL, Arc::new(simulator_pool), false)
+                .await
+                .unwrap()
+                .with_max_simulated_paths(0);
+            let paths = defi.find_buy_paths(coin_out_type).await.unwrap();
+
+            let timer = Instant::now();
+            defi.find_best_path_exact_in(
+                &paths,
+                SuiAddress::ZERO,
+                1_000_000_000,
+                TradeType::Swap,
+                &[],
+                &sim_ctx,
+                None,
+                None,
+                &PoolTrials::default(),
+            )
+            .await
+            .unwrap();
+            info!(
+                simulators,
+                paths = paths.len(),
+                batch_size = simulate_batch_size(paths.len(), simulators),
+                elapsed = ?timer.elapsed(),
+                "find_best_path_exact_in"
+            );
+        }
+    }
 }
@@ -10,7 +10,7 @@ use sui_types::{
 };
 use utils::object::shared_obj_arg;
 
-use super::{trade::FlashResult, TradeCtx};
+use super::{trade::FlashResult, ArgKind, TradeCtx};
 
 const NAVI_PROTOCOL: &str = "0x834a86970ae93a73faf4fff16ae40bdb72b91c47be585fff19a2af60a19ddca3";
 const NAVI_POOL: &str = "0x96df0fce3c471489f4debaaa762cf960b3d97820bd1f3f025ff8190730e958c5";
@@ -79,6 +79,7 @@ impl Navi {
         let last_idx = ctx.last_command_idx();
 
         let balance_out = Argument::NestedResult(last_idx, 0);
+        ctx.annotate(balance_out, ArgKind::Balance(self.sui_coin_type.clone()));
         let coin_out = ctx.coin_from_balance(balance_out, self.sui_coin_type.clone())?;
 
         Ok(FlashResult {
@@ -117,8 +118,51 @@ impl Navi {
         ctx.command(Command::move_call(package, module, function, type_arguments, arguments));
         let last_idx = ctx.last_command_idx();
         let balance = Argument::Result(last_idx);
+        ctx.annotate(balance, ArgKind::Balance(self.sui_coin_type.clone()));
         let coin = ctx.coin_from_balance(balance, self.sui_coin_type.clone())?;
 
         Ok(coin)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{defi::ArgViolation, tests::shared_object};
+
+    fn doctored_navi() -> Navi {
+        Navi {
+            sui_coin_type: TypeTag::from_str(SUI_COIN_TYPE).unwrap(),
+            pool: shared_object(ObjectID::random()),
+            config: shared_object(ObjectID::random()),
+            storage: shared_object(ObjectID::random()),
+            clock: shared_object(SUI_CLOCK_OBJECT_ID),
+        }
+    }
+
+    #[test]
+    fn test_flashloan_and_repay_are_annotated() {
+        let navi = doctored_navi();
+        let sui = TypeTag::from_str(SUI_COIN_TYPE).unwrap();
+
+        let mut ctx = TradeCtx::with_arg_tracking();
+        let flash_res = navi.extend_flashloan_tx(&mut ctx, 1_000).unwrap();
+        let coin_out = flash_res.coin_out;
+        navi.extend_repay_tx(&mut ctx, coin_out, flash_res).unwrap();
+        assert!(ctx.validate().is_empty(), "{:?}", ctx.validate());
+
+        // the coin out of the loan is a SUI coin, not a balance
+        let mut ctx = TradeCtx::with_arg_tracking();
+        let flash_res = navi.extend_flashloan_tx(&mut ctx, 1_000).unwrap();
+        ctx.balance_destroy_zero(flash_res.coin_out, sui.clone()).unwrap();
+        assert_eq!(
+            ctx.validate(),
+            vec![ArgViolation::KindMismatch {
+                arg: flash_res.coin_out,
+                command: 2,
+                expected: ArgKind::Balance(sui.clone()),
+                found: ArgKind::Coin(sui)
+            }]
+        );
+    }
+}
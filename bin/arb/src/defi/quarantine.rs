@@ -0,0 +1,300 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use dex_indexer::types::Protocol;
+use sui_types::base_types::ObjectID;
+use tracing::{info, warn};
+
+use crate::metrics::metrics;
+
+/// consecutive opportunities whose trials aborted in a pool before it is quarantined
+pub const DEFAULT_QUARANTINE_ABORTS: usize = 5;
+pub const DEFAULT_QUARANTINE_COOL_OFF: Duration = Duration::from_secs(600);
+
+static POOL_QUARANTINE: OnceLock<Arc<PoolQuarantine>> = OnceLock::new();
+
+/// Set up the process-wide `PoolQuarantine` at startup, returns false if it was already initialized.
+pub fn init_pool_quarantine(max_aborts: usize, cool_off: Duration) -> bool {
+    POOL_QUARANTINE
+        .set(Arc::new(PoolQuarantine::new(max_aborts, cool_off)))
+        .is_ok()
+}
+
+/// The `PoolQuarantine` shared by the searchers of all workers, with the defaults if not initialized.
+pub fn pool_quarantine() -> Arc<PoolQuarantine> {
+    POOL_QUARANTINE
+        .get_or_init(|| {
+            Arc::new(PoolQuarantine::new(
+                DEFAULT_QUARANTINE_ABORTS,
+                DEFAULT_QUARANTINE_COOL_OFF,
+            ))
+        })
+        .clone()
+}
+
+/// What the trials of an opportunity did in a pool of their paths.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PoolTrial {
+    /// a trial through the pool went through, at any amount
+    WentThrough,
+    /// the trials through the pool aborted in its hop, see `HopAborted`
+    Aborted(Protocol),
+}
+
+/// The `PoolTrial` of each pool the trials of one opportunity went through, collected apart from the shared
+/// `PoolQuarantine` which only counts them once the opportunity is over, see `PoolQuarantine::record_trials`.
+#[derive(Debug, Default)]
+pub struct PoolTrials {
+    pools: Mutex<HashMap<ObjectID, PoolTrial>>,
+}
+
+impl PoolTrials {
+    /// Add the trials of a batch, see `add_trial`.
+    pub fn merge(&self, trials: HashMap<ObjectID, PoolTrial>) {
+        let mut pools = self.pools.lock().unwrap();
+        for (pool_id, trial) in trials {
+            add_trial(&mut pools, pool_id, trial);
+        }
+    }
+}
+
+/// Add the `trial` of `pool_id` to `trials`. A pool that a trial went through isn't aborted by the others, so
+/// the aborts that depend on the amount, e.g. the slippage of a trial too large for the pool, are not counted.
+pub fn add_trial(trials: &mut HashMap<ObjectID, PoolTrial>, pool_id: ObjectID, trial: PoolTrial) {
+    match trial {
+        PoolTrial::WentThrough => {
+            trials.insert(pool_id, trial);
+        }
+        PoolTrial::Aborted(_) => {
+            trials.entry(pool_id).or_insert(trial);
+        }
+    }
+}
+
+struct PoolAborts {
+    protocol: Protocol,
+    consecutive: usize,
+    quarantined_until: Option<Instant>,
+}
+
+/// The pools that keep aborting the trials through them, e.g. a pool whose adapter is out of date after an
+/// upgrade of its package.
+///
+/// A pool is left out of the path search for `cool_off` once all the trials through it aborted in its hop for
+/// `max_aborts` opportunities in a row, see `PoolTrials`. An opportunity with a trial through the pool that
+/// doesn't abort resets its count.
+pub struct PoolQuarantine {
+    max_aborts: usize,
+    cool_off: Duration,
+    pools: Mutex<HashMap<ObjectID, PoolAborts>>,
+}
+
+impl PoolQuarantine {
+    /// `max_aborts` 0 never quarantines a pool.
+    pub fn new(max_aborts: usize, cool_off: Duration) -> Self {
+        Self {
+            max_aborts,
+            cool_off,
+            pools: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count the `trials` of an opportunity, at most one abort per pool. Returns the pools quarantined because
+    /// of them.
+    pub fn record_trials(&self, trials: &PoolTrials) -> Vec<ObjectID> {
+        let trials = std::mem::take(&mut *trials.pools.lock().unwrap());
+        if trials.is_empty() {
+            return vec![];
+        }
+
+        let mut quarantined = vec![];
+        let mut pools = self.pools.lock().unwrap();
+        for (pool_id, trial) in trials {
+            match trial {
+                PoolTrial::WentThrough => {
+                    // its aborts were not consecutive, unless it is quarantined since by another opportunity
+                    if pools
+                        .get(&pool_id)
+                        .is_some_and(|aborts| aborts.quarantined_until.is_none())
+                    {
+                        pools.remove(&pool_id);
+                    }
+                }
+                PoolTrial::Aborted(protocol) => {
+                    if self.record_abort(&mut pools, pool_id, protocol) {
+                        quarantined.push(pool_id);
+                    }
+                }
+            }
+        }
+        quarantined
+    }
+
+    // returns true if the pool is quarantined because of the abort
+    fn record_abort(&self, pools: &mut HashMap<ObjectID, PoolAborts>, pool_id: ObjectID, protocol: Protocol) -> bool {
+        metrics()
+            .pool_aborts
+            .with_label_values(&[protocol.to_string().as_str()])
+            .inc();
+        if self.max_aborts == 0 {
+            return false;
+        }
+
+        let aborts = pools.entry(pool_id).or_insert(PoolAborts {
+            protocol,
+            consecutive: 0,
+            quarantined_until: None,
+        });
+        aborts.consecutive += 1;
+        if aborts.consecutive < self.max_aborts || aborts.quarantined_until.is_some() {
+            return false;
+        }
+
+        warn!(
+            %pool_id,
+            protocol = %aborts.protocol,
+            aborts = aborts.consecutive,
+            cool_off = ?self.cool_off,
+            "Pool quarantined"
+        );
+        aborts.quarantined_until = Some(Instant::now() + self.cool_off);
+        metrics()
+            .quarantined_pools
+            .with_label_values(&[pool_id.to_string().as_str(), aborts.protocol.to_string().as_str()])
+            .set(1);
+        true
+    }
+
+    /// True while `pool_id` is quarantined, a pool whose cool-off is over is released.
+    pub fn is_quarantined(&self, pool_id: &ObjectID) -> bool {
+        let mut pools = self.pools.lock().unwrap();
+        let Some(until) = pools.get(pool_id).and_then(|aborts| aborts.quarantined_until) else {
+            return false;
+        };
+        if Instant::now() < until {
+            return true;
+        }
+
+        let aborts = pools.remove(pool_id).unwrap();
+        info!(%pool_id, protocol = %aborts.protocol, "Pool released from quarantine");
+        let _ = metrics()
+            .quarantined_pools
+            .remove_label_values(&[pool_id.to_string().as_str(), aborts.protocol.to_string().as_str()]);
+        false
+    }
+
+    /// The pools in quarantine and how long until they are released, the released ones are left out.
+    pub fn quarantined(&self) -> Vec<(ObjectID, Protocol, Duration)> {
+        let now = Instant::now();
+        self.pools
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(pool_id, aborts)| {
+                let until = aborts.quarantined_until.filter(|until| *until > now)?;
+                Some((*pool_id, aborts.protocol.clone(), until - now))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the trials of an opportunity, each pool with a single outcome
+    fn record(quarantine: &PoolQuarantine, trials: &[(ObjectID, PoolTrial)]) -> Vec<ObjectID> {
+        let pool_trials = PoolTrials::default();
+        pool_trials.merge(trials.iter().cloned().collect());
+        quarantine.record_trials(&pool_trials)
+    }
+
+    fn aborted(pool_id: ObjectID) -> (ObjectID, PoolTrial) {
+        (pool_id, PoolTrial::Aborted(Protocol::Cetus))
+    }
+
+    #[test]
+    fn test_quarantine_after_consecutive_aborts() {
+        let quarantine = PoolQuarantine::new(3, Duration::from_secs(60));
+        let (pool1, pool2) = (ObjectID::random(), ObjectID::random());
+
+        assert!(record(&quarantine, &[aborted(pool1)]).is_empty());
+        assert!(record(&quarantine, &[aborted(pool1)]).is_empty());
+        // an opportunity with a trial that went through breaks the streak
+        assert!(record(&quarantine, &[(pool1, PoolTrial::WentThrough)]).is_empty());
+        assert!(record(&quarantine, &[aborted(pool1)]).is_empty());
+        assert!(record(&quarantine, &[aborted(pool1)]).is_empty());
+        assert!(!quarantine.is_quarantined(&pool1));
+
+        assert_eq!(
+            record(&quarantine, &[aborted(pool1), (pool2, PoolTrial::WentThrough)]),
+            [pool1]
+        );
+        assert!(quarantine.is_quarantined(&pool1));
+        assert!(!quarantine.is_quarantined(&pool2));
+        // a success of an older opportunity doesn't release it
+        record(&quarantine, &[(pool1, PoolTrial::WentThrough)]);
+        assert!(quarantine.is_quarantined(&pool1));
+
+        let quarantined = quarantine.quarantined();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].0, pool1);
+        assert_eq!(quarantined[0].1, Protocol::Cetus);
+    }
+
+    #[test]
+    fn test_one_abort_per_opportunity() {
+        let quarantine = PoolQuarantine::new(2, Duration::from_secs(60));
+        let pool_id = ObjectID::random();
+
+        // many aborted trials of an opportunity are counted once
+        let pool_trials = PoolTrials::default();
+        for _ in 0..10 {
+            pool_trials.merge(HashMap::from([aborted(pool_id)]));
+        }
+        assert!(quarantine.record_trials(&pool_trials).is_empty());
+        assert!(!quarantine.is_quarantined(&pool_id));
+
+        // a trial of a smaller amount went through, the aborts of the larger ones are the pool's slippage
+        let mut trials = HashMap::new();
+        add_trial(&mut trials, pool_id, PoolTrial::Aborted(Protocol::Cetus));
+        add_trial(&mut trials, pool_id, PoolTrial::WentThrough);
+        add_trial(&mut trials, pool_id, PoolTrial::Aborted(Protocol::Cetus));
+        assert_eq!(trials[&pool_id], PoolTrial::WentThrough);
+        pool_trials.merge(trials);
+        assert!(quarantine.record_trials(&pool_trials).is_empty());
+
+        // the trials are taken by the quarantine
+        assert!(quarantine.record_trials(&pool_trials).is_empty());
+        assert!(quarantine.pools.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_released_after_cool_off() {
+        let quarantine = PoolQuarantine::new(1, Duration::from_millis(20));
+        let pool_id = ObjectID::random();
+
+        assert_eq!(record(&quarantine, &[aborted(pool_id)]), [pool_id]);
+        assert!(quarantine.is_quarantined(&pool_id));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(quarantine.quarantined().is_empty());
+        assert!(!quarantine.is_quarantined(&pool_id));
+        // released with a clean slate
+        assert!(quarantine.pools.lock().unwrap().is_empty());
+        assert_eq!(record(&quarantine, &[aborted(pool_id)]), [pool_id]);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let quarantine = PoolQuarantine::new(0, Duration::from_secs(60));
+        let pool_id = ObjectID::random();
+        for _ in 0..10 {
+            assert!(record(&quarantine, &[aborted(pool_id)]).is_empty());
+        }
+        assert!(!quarantine.is_quarantined(&pool_id));
+    }
+}
@@ -1,61 +1,68 @@
 use std::{
+    future::Future,
     str::FromStr,
-    sync::{atomic::AtomicUsize, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
 };
 
 use eyre::{ensure, eyre, Result};
 use shio::SHIO_GLOBAL_STATES;
-use sui_sdk::SUI_COIN_TYPE;
+use simulator::SimulatorError;
+use sui_sdk::{
+    rpc_types::{
+        SuiCallArg, SuiObjectArg, SuiObjectDataOptions, SuiObjectResponse, SuiTransactionBlockDataAPI,
+        SuiTransactionBlockKind, SuiTransactionBlockResponse, SuiTransactionBlockResponseOptions,
+        SuiTransactionBlockResponseQuery, TransactionFilter,
+    },
+    SuiClient, SUI_COIN_TYPE,
+};
 use sui_types::{
-    base_types::{ObjectID, SequenceNumber},
+    base_types::{ObjectID, ObjectType, SequenceNumber},
+    object::Owner,
     transaction::{Argument, Command, ObjectArg},
     Identifier, TypeTag,
 };
-use tokio::sync::OnceCell;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
 
 use super::TradeCtx;
 
 const SHIO: &str = "0x1889977f0fb56ae730e7bda8e8e32859ce78874458c74910d36121a81a615123";
-static GLOBAL_STATES: OnceCell<Vec<ObjectArg>> = OnceCell::const_new();
+// the global states are re-read from chain once they are older than this
+const GLOBAL_STATES_TTL: Duration = Duration::from_secs(600);
+// the recent bids whose global states are candidates next to the hardcoded ones, so that a rotated one is found
+const RECENT_BIDS: usize = 50;
+// shared by all `Shio`s
+static GLOBAL_STATES: GlobalStatesCache = GlobalStatesCache::new(GLOBAL_STATES_TTL);
 
 #[derive(Clone)]
 pub struct Shio {
-    global_states: Vec<ObjectArg>,
+    sui: SuiClient,
     state_idx: Arc<AtomicUsize>,
 }
 
 impl Shio {
-    pub async fn new() -> Result<Self> {
-        let global_states = GLOBAL_STATES
-            .get_or_init(|| async {
-                SHIO_GLOBAL_STATES
-                    .iter()
-                    .map(|(id, version)| ObjectArg::SharedObject {
-                        id: ObjectID::from_str(id).unwrap(),
-                        initial_shared_version: SequenceNumber::from_u64(*version),
-                        mutable: true,
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .await
-            .clone();
-
+    pub async fn new(sui: SuiClient) -> Self {
         let state_idx = Arc::new(AtomicUsize::new(0));
 
-        Ok(Self {
-            global_states,
-            state_idx,
-        })
+        let shio = Self { sui, state_idx };
+        // read the global states now, so that the first bid doesn't wait for it
+        shio.global_states().await;
+        shio
     }
 
-    pub fn submit_bid(&self, ctx: &mut TradeCtx, coin_bid: Argument, bid_amount: u64) -> Result<()> {
+    pub async fn submit_bid(&self, ctx: &mut TradeCtx, coin_bid: Argument, bid_amount: u64) -> Result<()> {
         ensure!(bid_amount > 0, "bid_amount must be greater than 0");
 
         let package = ObjectID::from_hex_literal(SHIO)?;
         let module = Identifier::new("auctioneer").map_err(|e| eyre!(e))?;
         let function = Identifier::new("submit_bid").map_err(|e| eyre!(e))?;
 
-        let s = ctx.obj(self.next_state()).map_err(|e| eyre!(e))?;
+        let global_states = self.global_states().await;
+        let s = ctx.obj(self.next_state(&global_states)).map_err(|e| eyre!(e))?;
         let bid_amount = ctx.pure(bid_amount).map_err(|e| eyre!(e))?;
         let coin_type = TypeTag::from_str(SUI_COIN_TYPE).unwrap();
         let fee = ctx.coin_into_balance(coin_bid, coin_type)?;
@@ -66,13 +73,386 @@ impl Shio {
         Ok(())
     }
 
-    fn next_state(&self) -> ObjectArg {
-        let mut idx = self.state_idx.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        if idx >= self.global_states.len() {
+    fn next_state(&self, global_states: &[ObjectArg]) -> ObjectArg {
+        let mut idx = self.state_idx.fetch_add(1, Ordering::Relaxed);
+        if idx >= global_states.len() {
             idx = 0;
-            self.state_idx.store(1, std::sync::atomic::Ordering::Relaxed);
+            self.state_idx.store(1, Ordering::Relaxed);
+        }
+
+        global_states[idx]
+    }
+
+    async fn global_states(&self) -> Arc<Vec<ObjectArg>> {
+        let sui = self.sui.clone();
+        GLOBAL_STATES
+            .get(move || {
+                let sui = sui.clone();
+                async move { fetch_shio_global_states(&sui).await }
+            })
+            .await
+    }
+}
+
+/// Mark the global states for a refresh before the next bid if `error` is about one of them, e.g. a bid
+/// whose dry run failed because a global state was deleted since the last refresh. Returns whether it was.
+pub fn invalidate_shio_global_states_on(error: &eyre::Report) -> bool {
+    GLOBAL_STATES.invalidate_on(error)
+}
+
+// The global states, refreshed from chain once they are older than the TTL or invalidated. Only one refresh runs
+// at a time: the bids meanwhile use the previous global states, and only the first ones wait for a refresh.
+struct GlobalStatesCache {
+    ttl: Duration,
+    // with when they were refreshed
+    cached: RwLock<Option<(Instant, Arc<Vec<ObjectArg>>)>>,
+    invalidated: AtomicBool,
+    refreshing: Mutex<()>,
+}
+
+impl GlobalStatesCache {
+    const fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            cached: RwLock::new(None),
+            invalidated: AtomicBool::new(false),
+            refreshing: Mutex::const_new(()),
+        }
+    }
+
+    async fn get<F, Fut>(&'static self, fetch: F) -> Arc<Vec<ObjectArg>>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Vec<(ObjectID, u64)>>> + Send,
+    {
+        if let Some((fresh, global_states)) = self.cached() {
+            if !fresh {
+                if let Ok(guard) = self.refreshing.try_lock() {
+                    tokio::spawn(async move {
+                        self.refresh(fetch).await;
+                        drop(guard);
+                    });
+                }
+            }
+            return global_states;
+        }
+
+        let _guard = self.refreshing.lock().await;
+        match self.cached() {
+            Some((_, global_states)) => global_states,
+            None => self.refresh(fetch).await,
+        }
+    }
+
+    // the cached global states, with whether they are still fresh
+    fn cached(&self) -> Option<(bool, Arc<Vec<ObjectArg>>)> {
+        let cached = self.cached.read().unwrap();
+        let (refreshed_at, global_states) = cached.as_ref()?;
+        let fresh = refreshed_at.elapsed() < self.ttl && !self.invalidated.load(Ordering::Relaxed);
+        Some((fresh, global_states.clone()))
+    }
+
+    // A failed refresh keeps the previous global states, or the hardcoded `SHIO_GLOBAL_STATES` if there are none
+    // yet, until the next TTL.
+    async fn refresh<F, Fut>(&self, fetch: F) -> Arc<Vec<ObjectArg>>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Vec<(ObjectID, u64)>>>,
+    {
+        self.invalidated.store(false, Ordering::Relaxed);
+        let global_states = match fetch().await {
+            Ok(global_states) => Arc::new(to_object_args(&global_states)),
+            Err(error) => {
+                warn!(?error, "failed to fetch shio global states, keep the previous ones");
+                match self.cached.read().unwrap().as_ref() {
+                    Some((_, global_states)) => global_states.clone(),
+                    None => Arc::new(hardcoded_global_states()),
+                }
+            }
+        };
+
+        *self.cached.write().unwrap() = Some((Instant::now(), global_states.clone()));
+        global_states
+    }
+
+    fn invalidate_on(&self, error: &eyre::Report) -> bool {
+        let Some(SimulatorError::InputObjectMissing { id }) = error.downcast_ref::<SimulatorError>() else {
+            return false;
+        };
+        let is_global_state = self
+            .cached
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|(_, global_states)| global_states.iter().any(|state| state.id() == *id));
+        if is_global_state {
+            info!(%id, "shio global state is gone, refresh before the next bid");
+            self.invalidated.store(true, Ordering::Relaxed);
+        }
+        is_global_state
+    }
+}
+
+/// The shio global states as they are on chain now, as `(id, initial_shared_version)`. The candidates
+/// are the ones of `SHIO_GLOBAL_STATES` and the shared objects of the recent bids, the ones that are no
+/// longer shared `auctioneer` objects (e.g. deleted or wrapped) are dropped, and the versions are the ones
+/// on chain.
+pub async fn fetch_shio_global_states(client: &SuiClient) -> Result<Vec<(ObjectID, u64)>> {
+    let mut ids = SHIO_GLOBAL_STATES
+        .iter()
+        .map(|(id, _)| ObjectID::from_hex_literal(id))
+        .collect::<Result<Vec<_>, _>>()?;
+    match recent_bids(client).await {
+        Ok(bids) => {
+            for id in shared_inputs(&bids) {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        // the hardcoded candidates are still checked
+        Err(error) => warn!(?error, "failed to read the recent shio bids"),
+    }
+
+    let options = SuiObjectDataOptions::new().with_type().with_owner();
+    let resps = client.read_api().multi_get_object_with_options(ids, options).await?;
+
+    let global_states = parse_global_states(&resps);
+    ensure!(!global_states.is_empty(), "no shio global state found on chain");
+    Ok(global_states)
+}
+
+async fn recent_bids(client: &SuiClient) -> Result<Vec<SuiTransactionBlockResponse>> {
+    let filter = TransactionFilter::MoveFunction {
+        package: ObjectID::from_hex_literal(SHIO)?,
+        module: Some("auctioneer".to_string()),
+        function: Some("submit_bid".to_string()),
+    };
+    let query = SuiTransactionBlockResponseQuery::new(
+        Some(filter),
+        Some(SuiTransactionBlockResponseOptions::new().with_input()),
+    );
+    let page = client
+        .read_api()
+        .query_transaction_blocks(query, None, Some(RECENT_BIDS), true)
+        .await?;
+    Ok(page.data)
+}
+
+// the shared objects the txs take as input, in the order they are first seen
+fn shared_inputs(resps: &[SuiTransactionBlockResponse]) -> Vec<ObjectID> {
+    let mut ids = vec![];
+    for resp in resps {
+        let Some(tx) = &resp.transaction else {
+            continue;
+        };
+        let SuiTransactionBlockKind::ProgrammableTransaction(ptb) = tx.data.transaction() else {
+            continue;
+        };
+        for input in &ptb.inputs {
+            if let SuiCallArg::Object(SuiObjectArg::SharedObject { object_id, .. }) = input {
+                if !ids.contains(object_id) {
+                    ids.push(*object_id);
+                }
+            }
         }
+    }
+    ids
+}
+
+fn parse_global_states(resps: &[SuiObjectResponse]) -> Vec<(ObjectID, u64)> {
+    let package = ObjectID::from_hex_literal(SHIO).unwrap();
+    resps
+        .iter()
+        .filter_map(|resp| {
+            let data = resp.data.as_ref()?;
+            match data.type_.as_ref()? {
+                // anyone can publish an `auctioneer` module, only the shared objects of the shio package count
+                ObjectType::Struct(type_)
+                    if ObjectID::from(type_.address()) == package && type_.module().as_str() == "auctioneer" => {}
+                _ => return None,
+            }
+            match data.owner.as_ref()? {
+                Owner::Shared { initial_shared_version } => Some((data.object_id, initial_shared_version.value())),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn to_object_args(global_states: &[(ObjectID, u64)]) -> Vec<ObjectArg> {
+    global_states
+        .iter()
+        .map(|(id, version)| ObjectArg::SharedObject {
+            id: *id,
+            initial_shared_version: SequenceNumber::from_u64(*version),
+            mutable: true,
+        })
+        .collect()
+}
+
+fn hardcoded_global_states() -> Vec<ObjectArg> {
+    let global_states: Vec<_> = SHIO_GLOBAL_STATES
+        .iter()
+        .map(|(id, version)| (ObjectID::from_str(id).unwrap(), *version))
+        .collect();
+    to_object_args(&global_states)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use sui_sdk::SuiClientBuilder;
+    use sui_types::{base_types::SuiAddress, digests::ObjectDigest};
+
+    use super::*;
+    use crate::config::tests::TEST_HTTP_URL;
+
+    fn object_response(id: ObjectID, type_: &str, owner: serde_json::Value) -> SuiObjectResponse {
+        serde_json::from_value(json!({
+            "data": {
+                "objectId": id,
+                "version": "327637290",
+                "digest": ObjectDigest::random(),
+                "type": type_,
+                "owner": owner,
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_global_states() {
+        let global_state_type = format!("{SHIO}::auctioneer::GlobalState");
+        let (shared, owned, other_type) = (ObjectID::random(), ObjectID::random(), ObjectID::random());
+        let deleted = ObjectID::random();
+
+        let resps = vec![
+            object_response(
+                shared,
+                &global_state_type,
+                json!({"Shared": {"initial_shared_version": 327637282}}),
+            ),
+            object_response(owned, &global_state_type, json!({"AddressOwner": SuiAddress::ZERO})),
+            object_response(
+                other_type,
+                "0x2::coin::Coin<0x2::sui::SUI>",
+                json!({"Shared": {"initial_shared_version": 1}}),
+            ),
+            serde_json::from_value(json!({"error": {"code": "notExists", "object_id": deleted}})).unwrap(),
+        ];
+
+        assert_eq!(parse_global_states(&resps), vec![(shared, 327637282)]);
+    }
+
+    #[test]
+    fn test_parse_global_states_of_foreign_package() {
+        let foreign_type = format!("{}::auctioneer::GlobalState", ObjectID::random());
+
+        let resps = vec![object_response(
+            ObjectID::random(),
+            &foreign_type,
+            json!({"Shared": {"initial_shared_version": 327637282}}),
+        )];
+
+        assert!(parse_global_states(&resps).is_empty());
+    }
 
-        self.global_states[idx]
+    fn global_state(id: ObjectID) -> Vec<(ObjectID, u64)> {
+        vec![(id, 327637282)]
+    }
+
+    fn state_ids(global_states: &[ObjectArg]) -> Vec<ObjectID> {
+        global_states.iter().map(|state| state.id()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_global_states_single_flight() {
+        let cache: &'static GlobalStatesCache = Box::leak(Box::new(GlobalStatesCache::new(GLOBAL_STATES_TTL)));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let id = ObjectID::random();
+        let fetch = {
+            let fetches = fetches.clone();
+            move || {
+                let fetches = fetches.clone();
+                async move {
+                    fetches.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok(global_state(id))
+                }
+            }
+        };
+
+        let gets = (0..10).map(|_| cache.get(fetch.clone()));
+        for global_states in futures::future::join_all(gets).await {
+            assert_eq!(state_ids(&global_states), vec![id]);
+        }
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+
+        // fresh, not fetched again
+        cache.get(fetch).await;
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stale_global_states_refreshed_in_background() {
+        let cache: &'static GlobalStatesCache = Box::leak(Box::new(GlobalStatesCache::new(Duration::ZERO)));
+        let (old, new) = (ObjectID::random(), ObjectID::random());
+        let (fetched_sender, fetched) = tokio::sync::oneshot::channel();
+
+        cache.get(move || async move { Ok(global_state(old)) }).await;
+
+        // the stale ones are returned right away, and replaced once fetched
+        let fetched_sender = std::sync::Mutex::new(Some(fetched_sender));
+        let global_states = cache
+            .get(move || {
+                if let Some(sender) = fetched_sender.lock().unwrap().take() {
+                    sender.send(()).unwrap();
+                }
+                async move { Ok(global_state(new)) }
+            })
+            .await;
+        assert_eq!(state_ids(&global_states), vec![old]);
+        fetched.await.unwrap();
+        let _guard = cache.refreshing.lock().await;
+        assert_eq!(state_ids(&cache.cached().unwrap().1), vec![new]);
+    }
+
+    #[tokio::test]
+    async fn test_global_states_invalidated_on_missing_state() {
+        let cache: &'static GlobalStatesCache = Box::leak(Box::new(GlobalStatesCache::new(GLOBAL_STATES_TTL)));
+        let id = ObjectID::random();
+        cache.get(move || async move { Ok(global_state(id)) }).await;
+        assert!(cache.cached().unwrap().0);
+
+        // not about a global state
+        assert!(!cache.invalidate_on(&eyre!("timeout")));
+        let other = SimulatorError::InputObjectMissing { id: ObjectID::random() };
+        assert!(!cache.invalidate_on(&other.into()));
+        assert!(cache.cached().unwrap().0);
+
+        let missing = SimulatorError::InputObjectMissing { id };
+        assert!(cache.invalidate_on(&missing.into()));
+        assert!(!cache.cached().unwrap().0);
+
+        // a failed refresh keeps the previous ones
+        let global_states = cache.refresh(|| async { Err(eyre!("rpc error")) }).await;
+        assert_eq!(state_ids(&global_states), vec![id]);
+        assert!(cache.cached().unwrap().0);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_shio_global_states() {
+        let sui = SuiClientBuilder::default().build(TEST_HTTP_URL).await.unwrap();
+        let global_states = fetch_shio_global_states(&sui).await.unwrap();
+
+        // the hardcoded versions still hold for the states that are still there
+        for (id, version) in &global_states {
+            let hardcoded = SHIO_GLOBAL_STATES
+                .iter()
+                .find(|(hardcoded_id, _)| ObjectID::from_str(hardcoded_id).unwrap() == *id)
+                .unwrap();
+            assert_eq!(hardcoded.1, *version, "{id}");
+        }
     }
 }
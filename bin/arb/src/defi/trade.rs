@@ -1,28 +1,43 @@
 use std::{
+    cmp::{Ordering, Reverse},
     collections::HashSet,
     fmt,
-    ops::{Deref, DerefMut},
+    ops::{Deref, DerefMut, Range},
     str::FromStr,
     sync::Arc,
+    time::Instant,
 };
 
 use ::utils::coin;
+use dex_indexer::types::{Protocol, SwapEvent};
 use eyre::{ensure, eyre, Result};
+use futures::future::join_all;
 use object_pool::ObjectPool;
-use simulator::{SimulateCtx, Simulator};
-use sui_json_rpc_types::SuiExecutionStatus;
-use sui_sdk::rpc_types::SuiTransactionBlockEffectsAPI;
+use serde::{Serialize, Serializer};
+use simulator::{estimate_gas_budget, SimulateCtx, SimulateResult, Simulator, SimulatorError};
+use sui_json_rpc_types::SuiEvent;
+use sui_sdk::{rpc_types::SuiTransactionBlockEffectsAPI, SuiClient, SuiClientBuilder, SUI_COIN_TYPE};
 use sui_types::{
     base_types::{ObjectID, ObjectRef, SuiAddress},
+    digests::TransactionDigest,
     object::{Object, Owner},
     programmable_transaction_builder::ProgrammableTransactionBuilder,
-    transaction::{Argument, Command, ObjectArg, TransactionData},
+    transaction::{Argument, Command, ObjectArg, ProgrammableTransaction, TransactionData, TransactionDataAPI},
     Identifier, TypeTag, SUI_FRAMEWORK_PACKAGE_ID,
 };
-use tracing::instrument;
+use tracing::{debug, instrument};
 
-use super::{navi::Navi, shio::Shio, Dex};
-use crate::{config::*, types::Source};
+use super::{
+    arg_tracker::{ArgKind, ArgTracker, ArgViolation},
+    navi::Navi,
+    shio::Shio,
+    Dex,
+};
+use crate::{
+    config::*,
+    metrics::metrics,
+    types::{acquire_before, HopAborted, Source},
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TradeType {
@@ -41,32 +56,151 @@ pub struct FlashResult {
 pub struct Trader {
     simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
     shio: Arc<Shio>,
-    navi: Arc<Navi>,
+    // flashloans the amount in of the paths whose first hop can't, `None` if disabled
+    navi: Option<Arc<Navi>>,
+    // in dry-run mode, no bid is included in the final tx
+    dry_run: bool,
 }
 
 #[derive(Default)]
 pub struct TradeCtx {
     pub ptb: ProgrammableTransactionBuilder,
     pub command_count: u16,
+    pub hop_commands: HopCommands,
+    // see `with_arg_tracking`
+    arg_tracker: Option<ArgTracker>,
+    // see `amount_in_of_path`
+    amount_in_of_path: bool,
+}
+
+/// The commands added by each hop of a path to a trade tx, to tell which hop a failed command belongs to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HopCommands(Vec<(usize, Range<u16>)>);
+
+impl HopCommands {
+    /// The index in the path of the hop that added `command`, `None` for the commands around the swaps,
+    /// e.g. a Navi flashloan or the transfer of the profit.
+    pub fn hop_of(&self, command: u16) -> Option<usize> {
+        self.0
+            .iter()
+            .find(|(_, commands)| commands.contains(&command))
+            .map(|(hop, _)| *hop)
+    }
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct TradeResult {
     pub amount_out: u64,
     pub gas_cost: i64,
+    // a gas budget for the tx, see `simulator::estimate_gas_budget`
+    pub gas_budget: u64,
     pub cache_misses: u64,
+    // the events of the simulation, only parsed into `HopFill`s for the chosen trial, see `parse_hop_fills`
+    pub events: Vec<SuiEvent>,
+}
+
+/// The observed fill of a single hop, parsed from the swap event emitted by the pool during simulation.
+#[derive(Debug, Clone, Serialize)]
+pub struct HopFill {
+    pub protocol: Protocol,
+    pub pool: ObjectID,
+    pub coin_in: String,
+    pub coin_out: String,
+    pub amount_in: u64,
+    pub amount_out: u64,
+}
+
+impl HopFill {
+    /// Match swap events to the pools of a path in execution order.
+    /// Events of pools that are not part of the path (or are out of order) are skipped.
+    pub fn from_swap_events(pools: &[ObjectID], swap_events: &[SwapEvent]) -> Vec<HopFill> {
+        let mut hop_fills = Vec::with_capacity(pools.len());
+        let mut pools = pools.iter().peekable();
+
+        for swap_event in swap_events {
+            let Some(pool) = pools.peek() else {
+                break;
+            };
+            if swap_event.pool_id() != Some(**pool) {
+                continue;
+            }
+
+            hop_fills.push(HopFill {
+                protocol: swap_event.protocol.clone(),
+                pool: **pool,
+                coin_in: swap_event.coins_in.first().cloned().unwrap_or_default(),
+                coin_out: swap_event.coins_out.first().cloned().unwrap_or_default(),
+                amount_in: swap_event.amounts_in.first().copied().unwrap_or_default(),
+                amount_out: swap_event.amounts_out.first().copied().unwrap_or_default(),
+            });
+            pools.next();
+        }
+
+        hop_fills
+    }
+}
+
+impl fmt::Display for HopFill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}({}) {} {} -> {} {}",
+            self.protocol,
+            self.pool,
+            self.amount_in,
+            self.coin_in.split("::").last().unwrap_or_default(),
+            self.amount_out,
+            self.coin_out.split("::").last().unwrap_or_default()
+        )
+    }
 }
 
 impl Trader {
-    pub async fn new(simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>) -> Result<Self> {
-        let shio = Arc::new(Shio::new().await?);
+    pub async fn new(
+        http_url: &str,
+        simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
+        dry_run: bool,
+    ) -> Result<Self> {
+        let sui = SuiClientBuilder::default().build(http_url).await?;
+        Self::new_with_client(sui, simulator_pool, dry_run).await
+    }
+
+    /// Same as `new`, with the client the shio global states are read from.
+    pub async fn new_with_client(
+        sui: SuiClient,
+        simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
+        dry_run: bool,
+    ) -> Result<Self> {
+        let shio = Arc::new(Shio::new(sui).await);
         let simulator = simulator_pool.get();
         let navi = Arc::new(Navi::new(simulator).await?);
 
         Ok(Self {
             simulator_pool,
             shio,
-            navi,
+            navi: Some(navi),
+            dry_run,
+        })
+    }
+
+    /// Never flashloan from Navi, the paths whose first hop can't flashloan then fail to build, see
+    /// `Path::flashloan_rotations`.
+    pub fn without_navi(mut self) -> Self {
+        self.navi = None;
+        self
+    }
+
+    pub fn has_navi(&self) -> bool {
+        self.navi.is_some()
+    }
+
+    // Navi, to flashloan for a path whose first hop can't
+    fn navi(&self, path: &Path) -> Result<&Navi> {
+        self.navi.as_deref().ok_or_else(|| {
+            eyre!(
+                "the first hop of {:?} can't flashloan and navi is disabled",
+                path.path[0].protocol()
+            )
         })
     }
 
@@ -85,12 +219,96 @@ impl Trader {
         amount_in: u64,
         trade_type: TradeType,
         gas_coins: Vec<ObjectRef>,
-        mut sim_ctx: SimulateCtx,
+        sim_ctx: SimulateCtx,
     ) -> Result<TradeResult> {
+        let (tx_data, sim_ctx, hop_commands) = self
+            .get_trade_tx(path, sender, amount_in, trade_type, gas_coins, sim_ctx)
+            .await?;
+
+        let simulator = self.simulator_pool.get();
+        metrics().simulations.with_label_values(&["trial"]).inc();
+        let resp = simulator.simulate(tx_data, sim_ctx).await?;
+
+        parse_trade_result(path, &hop_commands, sender, amount_in, resp)
+    }
+
+    /// Same as `get_trade_result` for many paths, simulated in one batch. The results are in the order of `paths`.
+    ///
+    /// The batch waits for a free simulator of the pool until `deadline`, the paths fail with
+    /// `DeadlineExceeded` if none is free by then.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn get_trade_results(
+        &self,
+        paths: &[Path],
+        sender: SuiAddress,
+        amount_in: u64,
+        trade_type: TradeType,
+        gas_coins: &[ObjectRef],
+        sim_ctx: &SimulateCtx,
+        deadline: Option<u64>,
+    ) -> Vec<Result<TradeResult>> {
+        let mut results = Vec::with_capacity(paths.len());
+        let (mut txs, mut hop_commands) = (vec![], vec![]);
+        let trade_txs = paths
+            .iter()
+            .map(|path| self.get_trade_tx(path, sender, amount_in, trade_type, gas_coins.to_vec(), sim_ctx.clone()));
+        for tx in join_all(trade_txs).await {
+            match tx {
+                Ok((tx_data, sim_ctx, tx_hop_commands)) => {
+                    results.push(None);
+                    txs.push((tx_data, sim_ctx));
+                    hop_commands.push(tx_hop_commands);
+                }
+                Err(error) => results.push(Some(Err(error))),
+            }
+        }
+
+        // a busy pool makes the trials wait for a simulator rather than pile up on the busy ones
+        let simulator = match acquire_before(&self.simulator_pool, deadline).await {
+            Ok(simulator) => simulator,
+            Err(error) => {
+                return results
+                    .into_iter()
+                    .map(|result| result.unwrap_or_else(|| Err(error.into())))
+                    .collect();
+            }
+        };
+        metrics()
+            .simulations
+            .with_label_values(&["trial"])
+            .inc_by(txs.len() as u64);
+        let (tx_count, timer) = (txs.len(), Instant::now());
+        let mut responses = simulator.simulate_many(txs).await.into_iter().zip(hop_commands);
+        debug!(simulator = %simulator.slot(), tx_count, elapsed = ?timer.elapsed(), "Batch simulated");
+
+        let mut trade_results = Vec::with_capacity(paths.len());
+        for (path, result) in paths.iter().zip(results) {
+            let result = match result {
+                Some(result) => result,
+                None => match responses.next().expect("a response for every tx") {
+                    (Ok(resp), hop_commands) => parse_trade_result(path, &hop_commands, sender, amount_in, resp),
+                    (Err(error), _) => Err(error),
+                },
+            };
+            trade_results.push(result);
+        }
+        trade_results
+    }
+
+    // the tx of a trial, the SimulateCtx to simulate it with and the commands of its hops
+    async fn get_trade_tx(
+        &self,
+        path: &Path,
+        sender: SuiAddress,
+        amount_in: u64,
+        trade_type: TradeType,
+        gas_coins: Vec<ObjectRef>,
+        mut sim_ctx: SimulateCtx,
+    ) -> Result<(TransactionData, SimulateCtx, HopCommands)> {
         ensure!(!path.is_empty(), "empty path");
         let gas_price = sim_ctx.epoch.gas_price;
 
-        let (tx_data, mocked_coin_in) = match trade_type {
+        let (tx_data, mocked_coin_in, hop_commands) = match trade_type {
             TradeType::Swap => {
                 self.get_swap_trade_tx(path, sender, amount_in, gas_coins, gas_price)
                     .await?
@@ -105,45 +323,7 @@ impl Trader {
             sim_ctx.with_borrowed_coin((mocked_coin_in, amount_in));
         }
 
-        let resp = self.simulator_pool.get().simulate(tx_data.clone(), sim_ctx).await?;
-        let status = resp.effects.status();
-
-        match status {
-            SuiExecutionStatus::Success => {}
-            SuiExecutionStatus::Failure { error } => {
-                // ignore "MoveAbort"
-                if !error.contains("MoveAbort") && !error.contains("InsufficientCoinBalance") {
-                    tracing::error!("status: {:?}", status);
-                }
-            }
-        }
-
-        ensure!(status.is_ok(), "{:?}", status);
-
-        let gas_cost = resp.effects.gas_cost_summary().net_gas_usage();
-        let coin_in = TypeTag::from_str(&path.coin_in_type()).map_err(|_| eyre!("invalid coin_in_type"))?;
-        let coin_out = TypeTag::from_str(&path.coin_out_type()).map_err(|_| eyre!("invalid coin_out_type"))?;
-        let out_is_native = coin::is_native_coin(&path.coin_out_type());
-
-        let mut amount_out = i128::MIN;
-        for bc in &resp.balance_changes {
-            if bc.owner == Owner::AddressOwner(sender) && bc.coin_type == coin_out {
-                amount_out = bc.amount;
-                if coin_in == coin_out && out_is_native {
-                    amount_out = amount_out + amount_in as i128 + gas_cost as i128;
-                }
-
-                ensure!(amount_out >= 0, "negative amount_out {}", amount_out);
-                break;
-            }
-        }
-        ensure!(amount_out != i128::MIN, "no balance change for owner: {:?}", sender);
-
-        Ok(TradeResult {
-            amount_out: amount_out as u64,
-            gas_cost,
-            cache_misses: resp.cache_misses,
-        })
+        Ok((tx_data, sim_ctx, hop_commands))
     }
 
     pub async fn get_swap_trade_tx(
@@ -153,28 +333,31 @@ impl Trader {
         amount_in: u64,
         gas_coins: Vec<ObjectRef>,
         gas_price: u64,
-    ) -> Result<(TransactionData, Option<Object>)> {
+    ) -> Result<(TransactionData, Option<Object>, HopCommands)> {
         ensure!(!path.is_empty(), "empty path");
         let mut ctx = TradeCtx::default();
 
         // 1. prepare coin_in
-        let mocked_sui = coin::mocked_sui(sender, amount_in);
-        let coin_in = mocked_sui.compute_object_reference();
+        let mocked_coin_in = coin::mocked_coin(sender, &path.coin_in_type(), amount_in)?;
+        let coin_in = mocked_coin_in.compute_object_reference();
 
         // 2. swap
         let mut coin_in_arg = ctx.split_coin(coin_in, amount_in)?;
         for (i, dex) in path.path.iter().enumerate() {
             let amount_in = if i == 0 { Some(amount_in) } else { None };
+            let first_command = ctx.command_count;
             coin_in_arg = dex.extend_trade_tx(&mut ctx, sender, coin_in_arg, amount_in).await?;
+            ctx.record_hop(i, first_command);
         }
 
         // 3. transfer the coin_out to recipient
         ctx.transfer_arg(sender, coin_in_arg);
+        let hop_commands = std::mem::take(&mut ctx.hop_commands);
         let tx = ctx.ptb.finish();
 
         let tx_data = TransactionData::new_programmable(sender, gas_coins, tx, GAS_BUDGET, gas_price);
 
-        Ok((tx_data, Some(mocked_sui)))
+        Ok((tx_data, Some(mocked_coin_in), hop_commands))
     }
 
     pub async fn get_flashloan_trade_tx(
@@ -185,66 +368,241 @@ impl Trader {
         gas_coins: Vec<ObjectRef>,
         gas_price: u64,
         source: Source,
-    ) -> Result<(TransactionData, Option<Object>)> {
+    ) -> Result<(TransactionData, Option<Object>, HopCommands)> {
+        let mut ctx = self.get_flashloan_trade_ctx(path, sender, amount_in, &source).await?;
+        let hop_commands = std::mem::take(&mut ctx.hop_commands);
+        let pt = ctx.ptb.finish();
+        let tx_data = new_bid_tx_data(sender, gas_coins, pt, GAS_BUDGET, gas_price, source.opp_tx_digest());
+
+        Ok((tx_data, None, hop_commands))
+    }
+
+    pub async fn get_flashloan_trade_pt(
+        &self,
+        path: &Path,
+        sender: SuiAddress,
+        amount_in: u64,
+        source: &Source,
+    ) -> Result<ProgrammableTransaction> {
+        let ctx = self.get_flashloan_trade_ctx(path, sender, amount_in, source).await?;
+        Ok(ctx.ptb.finish())
+    }
+
+    async fn get_flashloan_trade_ctx(
+        &self,
+        path: &Path,
+        sender: SuiAddress,
+        amount_in: u64,
+        source: &Source,
+    ) -> Result<TradeCtx> {
         ensure!(!path.is_empty(), "empty path");
         let first_dex = &path.path[0];
 
-        let mut ctx = TradeCtx::default();
+        // checked by `TradeCtx::validate` once the PTB is built, only in debug builds as it is on the path of
+        // every trial
+        let mut ctx = if cfg!(debug_assertions) {
+            TradeCtx::with_arg_tracking()
+        } else {
+            TradeCtx::new()
+        };
 
-        // 1. flashloan
+        // 1. flashloan, the loan of the first dex belongs to its hop
         let flash_res = if first_dex.support_flashloan() {
-            first_dex.extend_flashloan_tx(&mut ctx, amount_in).await?
+            let flash_res = first_dex.extend_flashloan_tx(&mut ctx, amount_in).await?;
+            ctx.record_hop(0, 0);
+            ctx.annotate_coin(flash_res.coin_out, &first_dex.coin_out_type());
+            ctx.annotate(flash_res.receipt, ArgKind::Receipt(first_dex.protocol()));
+            flash_res
         } else {
-            self.navi.extend_flashloan_tx(&mut ctx, amount_in)?
+            // navi lends SUI, whatever the coin in of the path
+            let flash_res = self.navi(path)?.extend_flashloan_tx(&mut ctx, amount_in)?;
+            ctx.annotate_coin(flash_res.coin_out, SUI_COIN_TYPE);
+            ctx.annotate(flash_res.receipt, ArgKind::Receipt(Protocol::Navi));
+            flash_res
         };
+        let receipt = flash_res.receipt;
 
         // 2. swap
-        let mut coin_in_arg = flash_res.coin_out;
-        let dex_iter: Box<dyn Iterator<Item = &Box<dyn Dex>> + Send> = if first_dex.support_flashloan() {
-            Box::new(path.path.iter().skip(1))
-        } else {
-            Box::new(path.path.iter())
-        };
-        for (i, dex) in dex_iter.enumerate() {
-            let amount_in = if i == 0 { Some(amount_in) } else { None };
-            coin_in_arg = dex.extend_trade_tx(&mut ctx, sender, coin_in_arg, amount_in).await?;
-        }
+        let coin_in_arg = extend_flashloan_swaps(&mut ctx, path, sender, flash_res.coin_out, amount_in).await?;
 
         // 3. repay flashloan
+        let first_command = ctx.command_count;
         let coin_profit = if first_dex.support_flashloan() {
             first_dex.extend_repay_tx(&mut ctx, coin_in_arg, flash_res).await?
         } else {
-            self.navi.extend_repay_tx(&mut ctx, coin_in_arg, flash_res)?
+            self.navi(path)?.extend_repay_tx(&mut ctx, coin_in_arg, flash_res)?
         };
-
-        // 4. submit bid
-        if source.is_shio() {
+        // some repays split the debt out of the coin and return the rest of it as the profit
+        if coin_profit != coin_in_arg {
+            ctx.annotate_taken(coin_in_arg, coin_kind(&first_dex.coin_in_type()), first_command);
+        }
+        ctx.annotate_taken(receipt, None, first_command);
+        ctx.annotate_coin(coin_profit, &path.coin_in_type());
+
+        // 4. submit bid, paid out of the profit
+        if source.is_shio() && !self.dry_run {
+            ensure!(
+                coin::is_native_coin(&path.coin_in_type()),
+                "a bid can't be paid in {}",
+                path.coin_in_type()
+            );
             let amount_arg = ctx.pure(source.bid_amount()).map_err(|e| eyre!(e))?;
+            ctx.annotate(amount_arg, ArgKind::Pure);
             let coin_bid = ctx.split_coin_arg(coin_profit, amount_arg);
-            self.shio.submit_bid(&mut ctx, coin_bid, source.bid_amount())?;
+            self.shio.submit_bid(&mut ctx, coin_bid, source.bid_amount()).await?;
         }
 
         // 5. transfer the profit to recipient
         ctx.transfer_arg(sender, coin_profit);
 
-        let tx = ctx.ptb.finish();
+        if cfg!(debug_assertions) {
+            let violations = ctx.validate();
+            ensure!(
+                violations.is_empty(),
+                "invalid PTB: {}",
+                violations.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+            );
+        }
+
+        Ok(ctx)
+    }
+}
+
+// The swaps of a flashloan trade from the flashloaned `coin_in` of `amount_in`, the first hop is skipped when it
+// lent the coin. Returns the coin out of the last hop.
+pub(super) async fn extend_flashloan_swaps(
+    ctx: &mut TradeCtx,
+    path: &Path,
+    sender: SuiAddress,
+    coin_in: Argument,
+    amount_in: u64,
+) -> Result<Argument> {
+    let mut coin_in_arg = coin_in;
+    let skipped = if path.path[0].support_flashloan() { 1 } else { 0 };
+    for (i, dex) in path.path.iter().enumerate().skip(skipped) {
+        let amount_in = if i == skipped { Some(amount_in) } else { None };
+        let first_command = ctx.command_count;
+        ctx.amount_in_of_path = amount_in.is_some() && skipped == 1;
+        let coin_out_arg = dex.extend_trade_tx(ctx, sender, coin_in_arg, amount_in).await;
+        ctx.amount_in_of_path = false;
+        let coin_out_arg = coin_out_arg?;
+        ctx.record_hop(i, first_command);
+        ctx.annotate_taken(coin_in_arg, coin_kind(&dex.coin_in_type()), first_command);
+        ctx.annotate_coin(coin_out_arg, &dex.coin_out_type());
+        coin_in_arg = coin_out_arg;
+    }
+
+    Ok(coin_in_arg)
+}
+
+// `None` for a coin type that doesn't parse, which is then not checked
+fn coin_kind(coin_type: &str) -> Option<ArgKind> {
+    TypeTag::from_str(coin_type).ok().map(ArgKind::Coin)
+}
+
+/// The tx of `pt` with at least `gas_budget`. A bid on an opportunity tx MUST have a lexicographically
+/// larger digest than the opportunity tx, the budget is bumped until it has.
+pub fn new_bid_tx_data(
+    sender: SuiAddress,
+    gas_coins: Vec<ObjectRef>,
+    pt: ProgrammableTransaction,
+    gas_budget: u64,
+    gas_price: u64,
+    opp_tx_digest: Option<TransactionDigest>,
+) -> TransactionData {
+    let mut tx_data = TransactionData::new_programmable(sender, gas_coins, pt, gas_budget, gas_price);
+    order_after(&mut tx_data, opp_tx_digest);
+
+    tx_data
+}
+
+/// Bumps the gas budget of `tx_data` until its digest is larger than `opp_tx_digest`. To be called again
+/// whenever the tx changes after `new_bid_tx_data`, e.g. its gas payment.
+pub fn order_after(tx_data: &mut TransactionData, opp_tx_digest: Option<TransactionDigest>) {
+    if let Some(opp_tx_digest) = opp_tx_digest {
+        while tx_data.digest() <= opp_tx_digest {
+            tx_data.gas_data_mut().budget += 1;
+        }
+    }
+}
+
+fn parse_trade_result(
+    path: &Path,
+    hop_commands: &HopCommands,
+    sender: SuiAddress,
+    amount_in: u64,
+    resp: SimulateResult,
+) -> Result<TradeResult> {
+    if let Err(error) = resp.check_status() {
+        // aborts and insufficient balances are expected for most trials
+        if !error.is_move_abort() && !error.is_insufficient_balance() {
+            tracing::error!("{error}");
+        }
+        return Err(match hop_aborted(path, hop_commands, &error) {
+            Some(hop_aborted) => eyre::Report::new(error).wrap_err(hop_aborted),
+            None => error.into(),
+        });
+    }
 
-        // 6. finalize
-        let mut tx_data =
-            TransactionData::new_programmable(sender, gas_coins.clone(), tx.clone(), GAS_BUDGET, gas_price);
-
-        if let Some(opp_tx_digest) = source.opp_tx_digest() {
-            // A Bid MUST have a lexicologically larger transaction digest comparing to opportunity transaction's.
-            let mut gas_budget = GAS_BUDGET;
-            while tx_data.digest() <= opp_tx_digest {
-                gas_budget += 1;
-                tx_data =
-                    TransactionData::new_programmable(sender, gas_coins.clone(), tx.clone(), gas_budget, gas_price);
+    let gas_cost = resp.effects.gas_cost_summary().net_gas_usage();
+    let coin_in = TypeTag::from_str(&path.coin_in_type()).map_err(|_| eyre!("invalid coin_in_type"))?;
+    let coin_out = TypeTag::from_str(&path.coin_out_type()).map_err(|_| eyre!("invalid coin_out_type"))?;
+    let out_is_native = coin::is_native_coin(&path.coin_out_type());
+
+    let mut amount_out = i128::MIN;
+    for bc in &resp.balance_changes {
+        if bc.owner == Owner::AddressOwner(sender) && bc.coin_type == coin_out {
+            amount_out = bc.amount;
+            if coin_in == coin_out && out_is_native {
+                amount_out = amount_out + amount_in as i128 + gas_cost as i128;
             }
-        };
 
-        Ok((tx_data, None))
+            ensure!(amount_out >= 0, "negative amount_out {}", amount_out);
+            break;
+        }
     }
+    ensure!(amount_out != i128::MIN, "no balance change for owner: {:?}", sender);
+
+    Ok(TradeResult {
+        amount_out: amount_out as u64,
+        gas_cost,
+        gas_budget: estimate_gas_budget(&resp, GAS_BUDGET_SAFETY_BPS),
+        cache_misses: resp.cache_misses,
+        events: resp.events.data,
+    })
+}
+
+// the hop of `path` whose pool aborted the trial, see `HopAborted`
+fn hop_aborted(path: &Path, hop_commands: &HopCommands, error: &SimulatorError) -> Option<HopAborted> {
+    let location = error
+        .move_abort_location()
+        .filter(|location| !location.is_framework())?;
+    let hop = hop_commands.hop_of(location.command?)?;
+    let dex = path.path.get(hop)?;
+
+    Some(HopAborted {
+        hop,
+        pool_id: dex.object_id(),
+        protocol: dex.protocol(),
+        location,
+    })
+}
+
+/// The fills of the hops of `path`, parsed from the swap `events` of its simulation.
+pub async fn parse_hop_fills(path: &Path, events: &[SuiEvent], simulator: Arc<Box<dyn Simulator>>) -> Vec<HopFill> {
+    let provider: Arc<dyn Simulator> = simulator;
+
+    let mut swap_events = Vec::with_capacity(events.len());
+    for event in events {
+        if let Ok(protocol) = Protocol::try_from(event) {
+            if let Ok(swap_event) = protocol.sui_event_to_swap_event(event, provider.clone()).await {
+                swap_events.push(swap_event);
+            }
+        }
+    }
+
+    let pools = path.path.iter().map(|dex| dex.object_id()).collect::<Vec<_>>();
+    HopFill::from_swap_events(&pools, &swap_events)
 }
 
 impl TradeCtx {
@@ -252,6 +610,62 @@ impl TradeCtx {
         Self::default()
     }
 
+    /// A ctx that tracks the kind of the arguments annotated by its helpers and by `annotate`, and the ones
+    /// taken by value, so that `validate` catches the wiring bugs of a PTB before it is simulated.
+    pub fn with_arg_tracking() -> Self {
+        Self {
+            arg_tracker: Some(ArgTracker::default()),
+            ..Default::default()
+        }
+    }
+
+    /// True while the hop after the flashloan of the first dex is extended: its `amount_in` is then the
+    /// flashloan amount of the path, not the amount of the coin the hop receives.
+    pub fn amount_in_of_path(&self) -> bool {
+        self.amount_in_of_path
+    }
+
+    /// `arg` is a `kind`, no-op unless tracking.
+    pub fn annotate(&mut self, arg: Argument, kind: ArgKind) {
+        if let Some(tracker) = &mut self.arg_tracker {
+            tracker.annotate(arg, kind);
+        }
+    }
+
+    /// `arg` is a coin of `coin_type`, no-op unless tracking or if the type doesn't parse.
+    pub fn annotate_coin(&mut self, arg: Argument, coin_type: &str) {
+        if let Some(kind) = coin_kind(coin_type) {
+            self.annotate(arg, kind);
+        }
+    }
+
+    /// `arg` is taken by value by the commands added since `first_command`, which must expect a `expected`.
+    pub fn annotate_taken(&mut self, arg: Argument, expected: Option<ArgKind>, first_command: u16) {
+        let command = self.last_command_idx();
+        if let Some(tracker) = &mut self.arg_tracker {
+            tracker.take(arg, expected.as_ref(), first_command, command);
+        }
+    }
+
+    /// The violations found so far, always empty unless tracking.
+    pub fn validate(&self) -> Vec<ArgViolation> {
+        self.arg_tracker
+            .as_ref()
+            .map_or_else(Vec::new, |tracker| tracker.violations().to_vec())
+    }
+
+    // `arg` is used by the last command, by value if `taken`
+    fn track_arg(&mut self, arg: Argument, expected: Option<ArgKind>, taken: bool) {
+        let command = self.last_command_idx();
+        if let Some(tracker) = &mut self.arg_tracker {
+            if taken {
+                tracker.take(arg, expected.as_ref(), command, command);
+            } else {
+                tracker.borrow(arg, expected.as_ref(), command);
+            }
+        }
+    }
+
     pub fn command(&mut self, cmd: Command) {
         self.ptb.command(cmd);
         self.command_count += 1;
@@ -260,6 +674,14 @@ impl TradeCtx {
     pub fn transfer_arg(&mut self, recipient: SuiAddress, coin_arg: Argument) {
         self.ptb.transfer_arg(recipient, coin_arg);
         self.command_count += 1;
+        self.track_arg(coin_arg, None, true);
+    }
+
+    /// The commands added since `first_command` belong to `hop` of the path, see `HopCommands`.
+    pub fn record_hop(&mut self, hop: usize, first_command: u16) {
+        if first_command < self.command_count {
+            self.hop_commands.0.push((hop, first_command..self.command_count));
+        }
     }
 
     pub fn last_command_idx(&self) -> u16 {
@@ -269,13 +691,52 @@ impl TradeCtx {
     pub fn split_coin(&mut self, coin: ObjectRef, amount: u64) -> Result<Argument> {
         let coin_arg = self.obj(ObjectArg::ImmOrOwnedObject(coin)).map_err(|e| eyre!(e))?;
         let amount_arg = self.pure(amount).map_err(|e| eyre!(e))?;
+        self.annotate(amount_arg, ArgKind::Pure);
 
         Ok(self.split_coin_arg(coin_arg, amount_arg))
     }
 
+    /// Merges the coins into the first one, which is returned. A single coin is used as is.
+    pub fn merge_coins_objrefs(&mut self, coins: &[ObjectRef]) -> Result<Argument> {
+        let (first, rest) = coins.split_first().ok_or_else(|| eyre!("no coin to merge"))?;
+        let coin_arg = self.obj(ObjectArg::ImmOrOwnedObject(*first)).map_err(|e| eyre!(e))?;
+        if rest.is_empty() {
+            return Ok(coin_arg);
+        }
+
+        let rest = rest
+            .iter()
+            .map(|coin| self.obj(ObjectArg::ImmOrOwnedObject(*coin)).map_err(|e| eyre!(e)))
+            .collect::<Result<Vec<_>>>()?;
+        self.command(Command::MergeCoins(coin_arg, rest));
+
+        Ok(coin_arg)
+    }
+
+    /// `split_coin` out of the coins merged together, see `coin::get_coins_for_amount`.
+    pub fn split_coins(&mut self, coins: &[ObjectRef], amount: u64) -> Result<Argument> {
+        let coin_arg = self.merge_coins_objrefs(coins)?;
+        let amount_arg = self.pure(amount).map_err(|e| eyre!(e))?;
+        self.annotate(amount_arg, ArgKind::Pure);
+
+        Ok(self.split_coin_arg(coin_arg, amount_arg))
+    }
+
+    /// The split coin is of the type of `coin`, if known.
     pub fn split_coin_arg(&mut self, coin: Argument, amount: Argument) -> Argument {
         self.command(Command::SplitCoins(coin, vec![amount]));
         let last_idx = self.last_command_idx();
+
+        // a balance of the coin type is caught, the coin type itself can't be checked
+        let coin_kind = match self.arg_kind(coin) {
+            Some(ArgKind::Coin(coin_type) | ArgKind::Balance(coin_type)) => Some(ArgKind::Coin(coin_type)),
+            _ => None,
+        };
+        self.track_arg(coin, coin_kind.clone(), false);
+        if let Some(ArgKind::Coin(coin_type)) = coin_kind {
+            self.annotate(Argument::Result(last_idx), ArgKind::Coin(coin_type));
+        }
+
         Argument::Result(last_idx)
     }
 
@@ -285,18 +746,26 @@ impl TradeCtx {
             SUI_FRAMEWORK_PACKAGE_ID,
             "balance",
             "destroy_zero",
-            vec![coin_type],
+            vec![coin_type.clone()],
             vec![balance],
         )?;
+        self.track_arg(balance, Some(ArgKind::Balance(coin_type)), true);
 
         Ok(())
     }
 
     // sui::balance::zero<CoinTypeTag>();
     pub fn balance_zero(&mut self, coin_type: TypeTag) -> Result<Argument> {
-        self.build_command(SUI_FRAMEWORK_PACKAGE_ID, "balance", "zero", vec![coin_type], vec![])?;
+        self.build_command(
+            SUI_FRAMEWORK_PACKAGE_ID,
+            "balance",
+            "zero",
+            vec![coin_type.clone()],
+            vec![],
+        )?;
 
         let last_idx = self.last_command_idx();
+        self.annotate(Argument::Result(last_idx), ArgKind::Balance(coin_type));
         Ok(Argument::Result(last_idx))
     }
 
@@ -306,11 +775,13 @@ impl TradeCtx {
             SUI_FRAMEWORK_PACKAGE_ID,
             "coin",
             "from_balance",
-            vec![coin_type],
+            vec![coin_type.clone()],
             vec![balance],
         )?;
+        self.track_arg(balance, Some(ArgKind::Balance(coin_type.clone())), true);
 
         let last_idx = self.last_command_idx();
+        self.annotate(Argument::Result(last_idx), ArgKind::Coin(coin_type));
         Ok(Argument::Result(last_idx))
     }
 
@@ -320,14 +791,35 @@ impl TradeCtx {
             SUI_FRAMEWORK_PACKAGE_ID,
             "coin",
             "into_balance",
-            vec![coin_type],
+            vec![coin_type.clone()],
+            vec![coin],
+        )?;
+        self.track_arg(coin, Some(ArgKind::Coin(coin_type.clone())), true);
+
+        let last_idx = self.last_command_idx();
+        self.annotate(Argument::Result(last_idx), ArgKind::Balance(coin_type));
+        Ok(Argument::Result(last_idx))
+    }
+
+    // sui::coin::value(&coin), for the swaps that take the amount in along with the coin
+    pub fn coin_value(&mut self, coin: Argument, coin_type: TypeTag) -> Result<Argument> {
+        self.build_command(
+            SUI_FRAMEWORK_PACKAGE_ID,
+            "coin",
+            "value",
+            vec![coin_type.clone()],
             vec![coin],
         )?;
+        self.track_arg(coin, Some(ArgKind::Coin(coin_type)), false);
 
         let last_idx = self.last_command_idx();
         Ok(Argument::Result(last_idx))
     }
 
+    fn arg_kind(&self, arg: Argument) -> Option<ArgKind> {
+        self.arg_tracker.as_ref()?.kind(arg).cloned()
+    }
+
     #[inline]
     fn build_command(
         &mut self,
@@ -359,18 +851,29 @@ impl DerefMut for TradeCtx {
     }
 }
 
-impl PartialEq for TradeResult {
-    fn eq(&self, other: &Self) -> bool {
-        self.amount_out == other.amount_out
+/// The greater, the better: a larger amount_out, then a lower gas cost.
+impl Ord for TradeResult {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.amount_out
+            .cmp(&other.amount_out)
+            .then_with(|| other.gas_cost.cmp(&self.gas_cost))
     }
 }
 
 impl PartialOrd for TradeResult {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.amount_out.partial_cmp(&other.amount_out)
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
+impl PartialEq for TradeResult {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TradeResult {}
+
 #[derive(Default, Clone)]
 pub struct Path {
     pub path: Vec<Box<dyn Dex>>,
@@ -385,6 +888,13 @@ impl Path {
         self.path.is_empty()
     }
 
+    /// Ranks paths with the same trade result, the greater, the better: fewer hops, then the
+    /// smaller pool ids so that the choice is reproducible.
+    pub fn tie_break_key(&self) -> (Reverse<usize>, Reverse<Vec<ObjectID>>) {
+        let pool_ids = self.path.iter().map(|dex| dex.object_id()).collect();
+        (Reverse(self.path.len()), Reverse(pool_ids))
+    }
+
     pub fn is_disjoint(&self, other: &Self) -> bool {
         let a = self.path.iter().collect::<HashSet<_>>();
         let b = other.path.iter().collect::<HashSet<_>>();
@@ -406,6 +916,64 @@ impl Path {
             false
         }
     }
+
+    pub fn contains_coin(&self, coin_type: &str) -> bool {
+        self.path.iter().any(|dex| dex.coin_out_type() == coin_type)
+    }
+
+    /// The rotations of a circular path that start with a hop able to flashloan, for when the first hop of the
+    /// path can't and Navi is disabled. E.g. `A -> SUI -> A` for `SUI -> A -> SUI`, the hops keep their
+    /// directions. The amount in and out of a rotation are in the coin it starts with.
+    pub fn flashloan_rotations(&self) -> Vec<Path> {
+        if self.is_empty() || self.coin_in_type() != self.coin_out_type() {
+            return vec![];
+        }
+
+        (1..self.path.len())
+            .filter(|&i| self.path[i].support_flashloan())
+            .map(|i| {
+                let mut path = self.path.clone();
+                path.rotate_left(i);
+                Path::new(path)
+            })
+            .collect()
+    }
+
+    /// The amount out of the path estimated from the `Dex::quote` of each hop, `None` if a hop can't be quoted.
+    pub fn quote(&self, amount_in: u64) -> Option<u64> {
+        self.path
+            .iter()
+            .try_fold(amount_in, |amount_in, dex| dex.quote(amount_in))
+    }
+}
+
+/// A serializable view of a hop in a `Path`, since `Box<dyn Dex>` itself can't be serialized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HopSummary {
+    pub protocol: Protocol,
+    pub pool_id: ObjectID,
+    pub coin_in: String,
+    pub coin_out: String,
+}
+
+impl Path {
+    pub fn to_summary(&self) -> Vec<HopSummary> {
+        self.path
+            .iter()
+            .map(|dex| HopSummary {
+                protocol: dex.protocol(),
+                pool_id: dex.object_id(),
+                coin_in: dex.coin_in_type(),
+                coin_out: dex.coin_out_type(),
+            })
+            .collect()
+    }
+}
+
+impl Serialize for Path {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_summary().serialize(serializer)
+    }
 }
 
 impl fmt::Debug for Path {
@@ -414,3 +982,323 @@ impl fmt::Debug for Path {
         write!(f, "[{}]", path_str.join(", "))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use simulator::HttpSimulator;
+    use sui_sdk::SuiClientBuilder;
+    use sui_types::base_types::random_object_ref;
+
+    use super::*;
+    use crate::{
+        common::get_latest_epoch,
+        config::tests::TEST_HTTP_URL,
+        defi::{stub_path, Defi},
+    };
+
+    fn swap_event(pool: ObjectID, coin_in: &str, coin_out: &str, amount_in: u64, amount_out: u64) -> SwapEvent {
+        SwapEvent {
+            protocol: Protocol::Cetus,
+            pool: Some(pool),
+            coins_in: vec![coin_in.to_string()],
+            coins_out: vec![coin_out.to_string()],
+            amounts_in: vec![amount_in],
+            amounts_out: vec![amount_out],
+        }
+    }
+
+    #[test]
+    fn test_hop_fills_from_two_hop_path() {
+        let pool1 = ObjectID::random();
+        let pool2 = ObjectID::random();
+        let unrelated = ObjectID::random();
+        let coin = "0x1::ocean::OCEAN";
+
+        let swap_events = vec![
+            swap_event(pool1, "0x2::sui::SUI", coin, 1_000, 500),
+            swap_event(unrelated, coin, "0x2::sui::SUI", 7, 7),
+            swap_event(pool2, coin, "0x2::sui::SUI", 500, 1_010),
+        ];
+
+        let hop_fills = HopFill::from_swap_events(&[pool1, pool2], &swap_events);
+        assert_eq!(hop_fills.len(), 2);
+        assert_eq!(hop_fills[0].pool, pool1);
+        assert_eq!(hop_fills[1].pool, pool2);
+        assert_eq!(hop_fills[0].amount_out, hop_fills[1].amount_in);
+        assert_eq!(hop_fills[0].coin_out, hop_fills[1].coin_in);
+    }
+
+    #[test]
+    fn test_bid_tx_digest_is_larger_than_opp_tx_digest() {
+        let sender = SuiAddress::random_for_testing_only();
+        let gas_coins = vec![random_object_ref()];
+        let mut ptb = ProgrammableTransactionBuilder::new();
+        ptb.transfer_sui(sender, Some(1));
+        let pt = ptb.finish();
+
+        let tx_data = new_bid_tx_data(sender, gas_coins.clone(), pt.clone(), 1_000_000, 1000, None);
+        assert_eq!(tx_data.gas_budget(), 1_000_000);
+
+        let opp_tx_digest = TransactionDigest::new([0xf0; 32]);
+        let tx_data = new_bid_tx_data(sender, gas_coins, pt, 1_000_000, 1000, Some(opp_tx_digest));
+        assert!(tx_data.digest() > opp_tx_digest);
+        assert!(tx_data.gas_budget() >= 1_000_000);
+    }
+
+    fn sui() -> TypeTag {
+        TypeTag::from_str(SUI_COIN_TYPE).unwrap()
+    }
+
+    // a ctx tracking the arguments with a SUI coin split at command 0
+    fn tracking_ctx() -> (TradeCtx, Argument) {
+        let mut ctx = TradeCtx::with_arg_tracking();
+        let coin = ctx.split_coin(random_object_ref(), 100).unwrap();
+        ctx.annotate_coin(coin, SUI_COIN_TYPE);
+        (ctx, coin)
+    }
+
+    #[test]
+    fn test_arg_tracking_off_by_default() {
+        let mut ctx = TradeCtx::new();
+        let coin = ctx.split_coin(random_object_ref(), 100).unwrap();
+        ctx.coin_into_balance(coin, sui()).unwrap();
+        ctx.coin_into_balance(coin, sui()).unwrap();
+        assert!(ctx.validate().is_empty());
+    }
+
+    #[test]
+    fn test_consumed_arg_reuse() {
+        let (mut ctx, coin) = tracking_ctx();
+        let balance = ctx.coin_into_balance(coin, sui()).unwrap();
+        ctx.coin_value(coin, sui()).unwrap();
+        ctx.balance_destroy_zero(balance, sui()).unwrap();
+        ctx.balance_destroy_zero(balance, sui()).unwrap();
+
+        assert_eq!(
+            ctx.validate(),
+            vec![
+                ArgViolation::ConsumedReuse {
+                    arg: coin,
+                    consumed_by: 1,
+                    used_by: 2
+                },
+                ArgViolation::ConsumedReuse {
+                    arg: balance,
+                    consumed_by: 3,
+                    used_by: 4
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hop_takes_its_coin_in_once() {
+        let (mut ctx, coin) = tracking_ctx();
+
+        // a hop that consumes its coin in with a helper
+        let first_command = ctx.command_count;
+        let balance = ctx.coin_into_balance(coin, sui()).unwrap();
+        let coin_out = ctx.coin_from_balance(balance, sui()).unwrap();
+        ctx.annotate_taken(coin, coin_kind(SUI_COIN_TYPE), first_command);
+        assert!(ctx.validate().is_empty());
+
+        // the next hop wired to the coin in of the previous one
+        let first_command = ctx.command_count;
+        ctx.transfer_arg(SuiAddress::ZERO, coin_out);
+        ctx.annotate_taken(coin, coin_kind(SUI_COIN_TYPE), first_command);
+        assert_eq!(
+            ctx.validate(),
+            vec![ArgViolation::ConsumedReuse {
+                arg: coin,
+                consumed_by: 1,
+                used_by: 3
+            }]
+        );
+    }
+
+    #[test]
+    fn test_arg_kind_mismatch() {
+        let usdc = TypeTag::from_str("0xdba34672e30cb065b1f93e3ab55318768fd6fef66c15942c9f7cb846e2f900e7::usdc::USDC")
+            .unwrap();
+
+        // a coin where a balance is expected
+        let (mut ctx, coin) = tracking_ctx();
+        ctx.balance_destroy_zero(coin, sui()).unwrap();
+        // a balance where a coin is expected
+        let balance = ctx.balance_zero(sui()).unwrap();
+        let amount = ctx.pure(1u64).unwrap();
+        ctx.split_coin_arg(balance, amount);
+        // a coin of another type
+        let (mut other_ctx, other_coin) = tracking_ctx();
+        other_ctx.coin_into_balance(other_coin, usdc.clone()).unwrap();
+
+        assert_eq!(
+            ctx.validate(),
+            vec![
+                ArgViolation::KindMismatch {
+                    arg: coin,
+                    command: 1,
+                    expected: ArgKind::Balance(sui()),
+                    found: ArgKind::Coin(sui())
+                },
+                ArgViolation::KindMismatch {
+                    arg: balance,
+                    command: 3,
+                    expected: ArgKind::Coin(sui()),
+                    found: ArgKind::Balance(sui())
+                },
+            ]
+        );
+        assert_eq!(
+            other_ctx.validate(),
+            vec![ArgViolation::KindMismatch {
+                arg: other_coin,
+                command: 1,
+                expected: ArgKind::Coin(usdc),
+                found: ArgKind::Coin(sui())
+            }]
+        );
+    }
+
+    #[test]
+    fn test_split_coins_merges_only_fragmented_coins() {
+        let mut ctx = TradeCtx::new();
+        ctx.split_coins(&[random_object_ref()], 100).unwrap();
+        let pt = ctx.ptb.finish();
+        assert_eq!(pt.commands.len(), 1);
+        assert!(matches!(pt.commands[0], Command::SplitCoins(..)));
+
+        let mut ctx = TradeCtx::new();
+        ctx.split_coins(&[random_object_ref(), random_object_ref(), random_object_ref()], 100)
+            .unwrap();
+        let pt = ctx.ptb.finish();
+        assert_eq!(pt.commands.len(), 2);
+        assert!(matches!(&pt.commands[0], Command::MergeCoins(_, coins) if coins.len() == 2));
+        assert!(matches!(pt.commands[1], Command::SplitCoins(..)));
+
+        assert!(TradeCtx::new().split_coins(&[], 100).is_err());
+    }
+
+    #[test]
+    fn test_abort_attributed_to_its_hop() {
+        let (pool1, pool2) = (ObjectID::random(), ObjectID::random());
+        let path = stub_path(&[pool1, pool2]);
+
+        // a split, then 2 commands for each hop, then the transfer
+        let mut ctx = TradeCtx::new();
+        ctx.split_coins(&[random_object_ref()], 100).unwrap();
+        for hop in 0..2 {
+            let first_command = ctx.command_count;
+            ctx.balance_zero(TypeTag::Bool).unwrap();
+            ctx.balance_zero(TypeTag::Bool).unwrap();
+            ctx.record_hop(hop, first_command);
+        }
+        assert_eq!(ctx.hop_commands.hop_of(0), None);
+        assert_eq!(ctx.hop_commands.hop_of(2), Some(0));
+        assert_eq!(ctx.hop_commands.hop_of(3), Some(1));
+        assert_eq!(ctx.hop_commands.hop_of(5), None);
+
+        let abort = |address: &str, command: u16| {
+            SimulatorError::ExecutionFailure {
+            status: sui_json_rpc_types::SuiExecutionStatus::Failure {
+                error: format!("MoveAbort(MoveLocation {{ module: ModuleId {{ address: {address}, name: Identifier(\"pool\") }}, function: 5, instruction: 29, function_name: Some(\"swap\") }}, 7) in command {command}"),
+            },
+        }
+        };
+        let aborted = hop_aborted(&path, &ctx.hop_commands, &abort("0xabc", 4)).unwrap();
+        assert_eq!(aborted.hop, 1);
+        assert_eq!(aborted.pool_id, pool2);
+        assert_eq!(aborted.location.abort_code, 7);
+
+        // aborts in the framework or outside of the swaps are not the pool's doing
+        assert!(hop_aborted(&path, &ctx.hop_commands, &abort("0x2", 4)).is_none());
+        assert!(hop_aborted(&path, &ctx.hop_commands, &abort("0xabc", 0)).is_none());
+
+        let report = eyre::Report::new(abort("0xabc", 1))
+            .wrap_err(hop_aborted(&path, &ctx.hop_commands, &abort("0xabc", 1)).unwrap());
+        assert_eq!(report.downcast_ref::<HopAborted>().unwrap().pool_id, pool1);
+        assert!(report.downcast_ref::<SimulatorError>().unwrap().is_move_abort());
+    }
+
+    #[test]
+    fn test_trade_result_tie_break_by_gas_cost() {
+        let trade_result = |amount_out, gas_cost| TradeResult {
+            amount_out,
+            gas_cost,
+            ..Default::default()
+        };
+
+        assert!(trade_result(101, 10) > trade_result(100, 1));
+        assert!(trade_result(100, 1) > trade_result(100, 10));
+        assert_eq!(trade_result(100, 1), trade_result(100, 1));
+    }
+
+    #[test]
+    fn test_path_tie_break_key() {
+        let (pool1, pool2, pool3) = (
+            ObjectID::from_single_byte(1),
+            ObjectID::from_single_byte(2),
+            ObjectID::from_single_byte(3),
+        );
+
+        let short = stub_path(&[pool3, pool2]);
+        let long = stub_path(&[pool1, pool2, pool3]);
+        assert!(short.tie_break_key() > long.tie_break_key());
+
+        let smaller_ids = stub_path(&[pool1, pool3]);
+        assert!(smaller_ids.tie_break_key() > short.tie_break_key());
+    }
+
+    #[tokio::test]
+    async fn test_batch_results_in_path_order() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=debug"]);
+
+        let simulator_pool = Arc::new(ObjectPool::new_async(1, || async {
+            Box::new(HttpSimulator::new(TEST_HTTP_URL, &None).await) as Box<dyn Simulator>
+        }));
+        let defi = Defi::new(TEST_HTTP_URL, simulator_pool.clone(), false).await.unwrap();
+        let trader = Trader::new(TEST_HTTP_URL, simulator_pool.clone(), false).await.unwrap();
+
+        let coin_out_type = "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN";
+        let paths = defi.find_buy_paths(coin_out_type).await.unwrap();
+        assert!(paths.len() >= 2, "not enough buy paths");
+
+        let sui = SuiClientBuilder::default().build(TEST_HTTP_URL).await.unwrap();
+        let sim_ctx = SimulateCtx::new(get_latest_epoch(&sui).await.unwrap(), vec![]);
+        let (sender, amount_in) = (SuiAddress::ZERO, 1_000_000_000);
+
+        // the tx in the middle pays with a gas coin that doesn't exist
+        let mut txs = vec![];
+        for (path, gas_coins) in [
+            (&paths[0], vec![]),
+            (&paths[0], vec![random_object_ref()]),
+            (&paths[1], vec![]),
+        ] {
+            let tx = trader
+                .get_trade_tx(path, sender, amount_in, TradeType::Swap, gas_coins, sim_ctx.clone())
+                .await
+                .unwrap();
+            txs.push(tx);
+        }
+        let txs = txs
+            .into_iter()
+            .map(|(tx, sim_ctx, _)| (tx, sim_ctx))
+            .collect::<Vec<_>>();
+        let digests = txs.iter().map(|(tx, _)| tx.digest()).collect::<Vec<_>>();
+
+        let results = simulator_pool.get().simulate_many(txs).await;
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().effects.transaction_digest(), &digests[0]);
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().unwrap().effects.transaction_digest(), &digests[2]);
+
+        // an empty path fails to build its tx, without failing the others
+        let batch = [paths[0].clone(), Path::default(), paths[1].clone()];
+        let results = trader
+            .get_trade_results(&batch, sender, amount_in, TradeType::Swap, &[], &sim_ctx, None)
+            .await;
+        assert_eq!(results.len(), 3);
+        assert!(results[1].as_ref().unwrap_err().to_string().contains("empty path"));
+        assert!(results[0].is_ok() || results[2].is_ok(), "{results:?}");
+    }
+}
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use dex_indexer::types::{Pool, Protocol};
+use dex_indexer::types::{Pool, PoolExtra, Protocol};
 use eyre::{ensure, eyre, OptionExt, Result};
 use move_core_types::annotated_value::MoveStruct;
 use simulator::Simulator;
@@ -10,10 +10,17 @@ use sui_types::{
     Identifier, TypeTag, SUI_CLOCK_OBJECT_ID,
 };
 use tokio::sync::OnceCell;
-use utils::{coin, new_test_sui_client, object::*};
+use utils::{
+    coin::{self, GasCoinFilter},
+    new_test_sui_client,
+    object::*,
+};
 
-use super::{TradeCtx, CETUS_AGGREGATOR};
-use crate::{config::*, defi::Dex};
+use super::{
+    utils::{clmm_amount_out, get_pool_object},
+    TradeCtx, CETUS_AGGREGATOR,
+};
+use crate::{config::*, defi::Dex, types::PoolPaused};
 
 const VERSIONED: &str = "0xf1cf0e81048df168ebeb1b8030fad24b3e0b53ae827c25053fff0779c1445b6f";
 
@@ -41,11 +48,22 @@ async fn get_object_args(simulator: Arc<Box<dyn Simulator>>) -> ObjectArgs {
         .clone()
 }
 
+#[cfg(test)]
+pub fn object_args_initialized() -> bool {
+    OBJ_CACHE.initialized()
+}
+
+// the fee tier of a pool is in millionths, e.g. 500 or 3000
+const FEE_DENOMINATOR: u64 = 1_000_000;
+
 #[derive(Clone)]
 pub struct Turbos {
     pool: Pool,
     pool_arg: ObjectArg,
     liquidity: u128,
+    sqrt_price: u128,
+    // the pools of a pair differ by their fee tier, which `quote` accounts for
+    fee: u64,
     coin_in_type: String,
     coin_out_type: String,
     type_params: Vec<TypeTag>,
@@ -71,10 +89,11 @@ impl Turbos {
             MoveStruct::simple_deserialize(move_obj.contents(), &layout).map_err(|e| eyre!(e))?
         };
 
-        let unlocked = extract_bool_from_move_struct(&parsed_pool, "unlocked")?;
-        ensure!(unlocked, "pool is locked");
-
-        let liquidity = extract_u128_from_move_struct(&parsed_pool, "liquidity")?;
+        let PoolState { liquidity, sqrt_price } = PoolState::parse(pool.pool, &parsed_pool)?;
+        let fee = match pool.extra {
+            PoolExtra::Turbos { fee } => fee as u64,
+            _ => extract_u32_from_move_struct(&parsed_pool, "fee")? as u64,
+        };
 
         let coin_out_type = if pool.token0_type() == coin_in_type {
             pool.token1_type().to_string()
@@ -90,6 +109,8 @@ impl Turbos {
         Ok(Self {
             pool: pool.clone(),
             liquidity,
+            sqrt_price,
+            fee,
             coin_in_type: coin_in_type.to_string(),
             coin_out_type,
             type_params,
@@ -103,12 +124,12 @@ impl Turbos {
         &self,
         sender: SuiAddress,
         recipient: SuiAddress,
-        coin_in: ObjectRef,
+        coins_in: &[ObjectRef],
         amount_in: u64,
     ) -> Result<ProgrammableTransaction> {
         let mut ctx = TradeCtx::default();
 
-        let coin_in = ctx.split_coin(coin_in, amount_in)?;
+        let coin_in = ctx.split_coins(coins_in, amount_in)?;
         let coin_out = self.extend_trade_tx(&mut ctx, sender, coin_in, None).await?;
         ctx.transfer_arg(recipient, coin_out);
 
@@ -133,6 +154,32 @@ impl Turbos {
     }
 }
 
+// the state of a pool that changes between swaps
+#[derive(Debug, PartialEq, Eq)]
+struct PoolState {
+    liquidity: u128,
+    sqrt_price: u128,
+}
+
+impl PoolState {
+    // `PoolPaused` if the pool is locked
+    fn parse(pool_id: ObjectID, parsed_pool: &MoveStruct) -> Result<Self> {
+        let unlocked = extract_bool_from_move_struct(parsed_pool, "unlocked")?;
+        if !unlocked {
+            return Err(PoolPaused {
+                pool_id,
+                protocol: Protocol::Turbos,
+            }
+            .into());
+        }
+
+        Ok(Self {
+            liquidity: extract_u128_from_move_struct(parsed_pool, "liquidity")?,
+            sqrt_price: extract_u128_from_move_struct(parsed_pool, "sqrt_price")?,
+        })
+    }
+}
+
 #[async_trait::async_trait]
 impl Dex for Turbos {
     async fn extend_trade_tx(
@@ -172,6 +219,25 @@ impl Dex for Turbos {
         self.liquidity
     }
 
+    async fn refresh(&mut self, simulator: Arc<Box<dyn Simulator>>) -> Result<()> {
+        let (_, parsed_pool) = get_pool_object(&**simulator, &self.pool.pool).await?;
+        let PoolState { liquidity, sqrt_price } = PoolState::parse(self.pool.pool, &parsed_pool)?;
+        self.liquidity = liquidity;
+        self.sqrt_price = sqrt_price;
+        Ok(())
+    }
+
+    fn quote(&self, amount_in: u64) -> Option<u64> {
+        Some(clmm_amount_out(
+            self.sqrt_price,
+            self.liquidity,
+            self.fee,
+            FEE_DENOMINATOR,
+            amount_in,
+            self.is_a2b(),
+        ))
+    }
+
     fn object_id(&self) -> ObjectID {
         self.pool.pool
     }
@@ -188,11 +254,13 @@ impl Dex for Turbos {
     async fn swap_tx(&self, sender: SuiAddress, recipient: SuiAddress, amount_in: u64) -> Result<TransactionData> {
         let sui = new_test_sui_client().await;
 
-        let coin_in = coin::get_coin(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coins_in = coin::get_coins_for_amount(&sui, sender, &self.coin_in_type, amount_in).await?;
+        let coin_in_refs = coins_in.iter().map(|coin| coin.object_ref()).collect::<Vec<_>>();
 
-        let pt = self.swap_tx(sender, recipient, coin_in.object_ref(), amount_in).await?;
+        let pt = self.swap_tx(sender, recipient, &coin_in_refs, amount_in).await?;
 
-        let gas_coins = coin::get_gas_coin_refs(&sui, sender, Some(coin_in.coin_object_id)).await?;
+        let exclude = coins_in.iter().map(|coin| coin.coin_object_id).collect::<Vec<_>>();
+        let gas_coins = coin::get_gas_coin_refs(&sui, sender, &GasCoinFilter::default().with_exclude(exclude)).await?;
         let gas_price = sui.read_api().get_reference_gas_price().await?;
         let tx_data = TransactionData::new_programmable(sender, gas_coins, pt, GAS_BUDGET, gas_price);
 
@@ -204,17 +272,52 @@ impl Dex for Turbos {
 mod tests {
     use std::str::FromStr;
 
+    use dex_indexer::types::Token;
     use itertools::Itertools;
     use object_pool::ObjectPool;
-    use simulator::{DBSimulator, HttpSimulator, Simulator};
+    use simulator::{DBSimulator, HttpSimulator, SimulateCtx, Simulator};
     use tracing::info;
 
     use super::*;
     use crate::{
+        common::get_latest_epoch,
         config::tests::{TEST_ATTACKER, TEST_HTTP_URL},
-        defi::{indexer_searcher::IndexerDexSearcher, DexSearcher},
+        defi::{indexer_searcher::IndexerDexSearcher, paths_to_simulate, DexSearcher, Path},
+        tests::{random_pool, shared_object},
     };
 
+    // a SUI/DEEP pool of the `fee` tier, with the same state for every tier
+    fn doctored_turbos(fee: u32) -> Turbos {
+        let tokens = vec![Token::new("0x2::sui::SUI", 9), Token::new("0xdee::deep::DEEP", 9)];
+        let pool = random_pool(Protocol::Turbos, tokens, PoolExtra::Turbos { fee });
+        Turbos {
+            pool_arg: shared_object(pool.pool),
+            pool,
+            liquidity: 1_000_000_000_000,
+            sqrt_price: 1 << 64,
+            fee: fee as u64,
+            coin_in_type: "0x2::sui::SUI".to_string(),
+            coin_out_type: "0xdee::deep::DEEP".to_string(),
+            type_params: vec![],
+            versioned: shared_object(ObjectID::random()),
+            clock: shared_object(ObjectID::random()),
+        }
+    }
+
+    #[test]
+    fn test_quote_prefers_lower_fee_tier() {
+        let paths = vec![
+            Path::new(vec![Box::new(doctored_turbos(3000)) as Box<dyn Dex>]),
+            Path::new(vec![Box::new(doctored_turbos(500))]),
+        ];
+        let amount_in = 1_000_000;
+
+        let high_fee = paths[0].quote(amount_in).unwrap();
+        let low_fee = paths[1].quote(amount_in).unwrap();
+        assert!(low_fee > high_fee, "{low_fee} <= {high_fee}");
+        assert_eq!(paths_to_simulate(&paths, amount_in, 1, None), vec![1]);
+    }
+
     #[tokio::test]
     async fn test_turbos_swap_tx() {
         mev_logger::init_console_logger_with_directives(None, &["arb=debug", "dex_indexer=debug"]);
@@ -228,10 +331,8 @@ mod tests {
         let token_out_type = "0xdeeb7a4662eec9f2f3def03fb937a663dddaa2e215b8078a284d026b7946c270::deep::DEEP";
         let amount_in = 10000;
 
-        let simulator_pool = Arc::new(ObjectPool::new(1, move || {
-            tokio::runtime::Runtime::new()
-                .unwrap()
-                .block_on(async { Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator> })
+        let simulator_pool = Arc::new(ObjectPool::new_async(1, || async {
+            Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator>
         }));
 
         // find dexes and swap
@@ -253,4 +354,50 @@ mod tests {
         let response = http_simulator.simulate(tx_data, Default::default()).await.unwrap();
         info!("🧀 {:?}", response);
     }
+
+    #[tokio::test]
+    async fn test_turbos_swap_reads_no_reward_vault() {
+        let owner = SuiAddress::from_str(TEST_ATTACKER).unwrap();
+        let token_in_type = "0x2::sui::SUI";
+        let token_out_type = "0xdeeb7a4662eec9f2f3def03fb937a663dddaa2e215b8078a284d026b7946c270::deep::DEEP";
+
+        let simulator_pool = Arc::new(ObjectPool::new_async(1, || async {
+            Box::new(HttpSimulator::new(TEST_HTTP_URL, &None).await) as Box<dyn Simulator>
+        }));
+        let searcher = IndexerDexSearcher::new(TEST_HTTP_URL, simulator_pool.clone())
+            .await
+            .unwrap();
+        let dex = searcher
+            .find_dexes(token_in_type, Some(token_out_type.into()))
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|dex| dex.protocol() == Protocol::Turbos)
+            .max_by_key(|dex| dex.liquidity())
+            .unwrap();
+        let tx_data = dex.swap_tx(owner, owner, 10000).await.unwrap();
+
+        let simulator = simulator_pool.get();
+        let (_, parsed_pool) = get_pool_object(&**simulator, &dex.object_id()).await.unwrap();
+        let vaults = extract_vec_struct_from_move_struct(&parsed_pool, "reward_infos")
+            .unwrap()
+            .iter()
+            .map(|reward_info| extract_object_id_from_move_struct(reward_info, "vault").unwrap())
+            .collect::<Vec<_>>();
+
+        // without override objects, every object read by the swap is a miss
+        let sui = new_test_sui_client().await;
+        let epoch = get_latest_epoch(&sui).await.unwrap();
+        let db_simulator = DBSimulator::new_test(true).await;
+        let response = db_simulator
+            .simulate(tx_data, SimulateCtx::new(epoch, vec![]))
+            .await
+            .unwrap();
+        response.check_status().unwrap();
+
+        let read = db_simulator.top_misses(usize::MAX);
+        assert!(read.iter().any(|(id, _)| *id == dex.object_id()), "{read:?}");
+        assert!(!vaults.is_empty());
+        assert!(read.iter().all(|(id, _)| !vaults.contains(id)), "{read:?}");
+    }
 }
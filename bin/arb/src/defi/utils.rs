@@ -1,10 +1,15 @@
+use std::sync::OnceLock;
+
 use cached::proc_macro::cached;
-use eyre::Result;
+use eyre::{eyre, OptionExt, Result};
+use move_core_types::annotated_value::MoveStruct;
+use primitive_types::U512;
+use simulator::Simulator;
 use sui_sdk::{
     rpc_types::{SuiObjectData, SuiObjectDataOptions},
     SuiClient,
 };
-use sui_types::base_types::ObjectID;
+use sui_types::{base_types::ObjectID, object::Object};
 
 #[cached(key = "String", convert = r##"{ obj_id.to_string() }"##, result = true)]
 pub async fn get_object_cache(sui: &SuiClient, obj_id: &str) -> Result<SuiObjectData> {
@@ -22,3 +27,110 @@ pub async fn get_object(sui: &SuiClient, obj_id: &str) -> Result<SuiObjectData>
 
     Ok(obj)
 }
+
+/// The pool object `pool_id` as read by `simulator`, and its parsed Move struct.
+pub async fn get_pool_object(simulator: &dyn Simulator, pool_id: &ObjectID) -> Result<(Object, MoveStruct)> {
+    let pool_obj = simulator
+        .get_object(pool_id)
+        .await
+        .ok_or_else(|| eyre!("pool not found: {}", pool_id))?;
+
+    let parsed_pool = {
+        let layout = simulator
+            .get_object_layout(pool_id)
+            .ok_or_eyre("pool layout not found")?;
+
+        let move_obj = pool_obj.data.try_as_move().ok_or_eyre("not a move object")?;
+        MoveStruct::simple_deserialize(move_obj.contents(), &layout).map_err(|e| eyre!(e))?
+    };
+
+    Ok((pool_obj, parsed_pool))
+}
+
+/// the min amount out of a swap is its quote minus this many basis points
+pub const DEFAULT_MIN_OUT_TOLERANCE_BPS: u64 = 100;
+
+static MIN_OUT_TOLERANCE_BPS: OnceLock<u64> = OnceLock::new();
+
+/// Set the min-out tolerance at startup, returns false if it was already initialized.
+pub fn init_min_out_tolerance(bps: u64) -> bool {
+    MIN_OUT_TOLERANCE_BPS.set(bps.min(10_000)).is_ok()
+}
+
+/// The min amount out of a swap quoted at `quote`, so that a swap executed publicly can't be sandwiched
+/// beyond the tolerance set by `init_min_out_tolerance`.
+pub fn min_amount_out(quote: u64) -> u64 {
+    let bps = *MIN_OUT_TOLERANCE_BPS.get_or_init(|| DEFAULT_MIN_OUT_TOLERANCE_BPS);
+    (quote as u128 * (10_000 - bps) as u128 / 10_000) as u64
+}
+
+const Q64: u128 = 1 << 64;
+
+/// The amount out of a swap on a concentrated liquidity pool at `sqrt_price` (Q64.64) with `liquidity`, as if
+/// the swap stayed within the current tick range. Overestimates the swaps that cross a tick, only use to rank.
+pub fn clmm_amount_out(
+    sqrt_price: u128,
+    liquidity: u128,
+    fee_rate: u64,
+    fee_denominator: u64,
+    amount_in: u64,
+    a2b: bool,
+) -> u64 {
+    if sqrt_price == 0 || liquidity == 0 || fee_rate >= fee_denominator {
+        return 0;
+    }
+
+    let amount_in = U512::from(amount_in) * U512::from(fee_denominator - fee_rate) / U512::from(fee_denominator);
+    let (s, l, q) = (U512::from(sqrt_price), U512::from(liquidity), U512::from(Q64));
+
+    let amount_out = if a2b {
+        // the price goes down: s' = L * s * Q / (L * Q + dx * s), dy = L * (s - s') / Q
+        let next = l * s * q / (l * q + amount_in * s);
+        l * (s - next) / q
+    } else {
+        // the price goes up: s' = s + dy * Q / L, dx = L * Q * (s' - s) / (s * s')
+        let next = s + amount_in * q / l;
+        l * q * (next - s) / (s * next)
+    };
+
+    if amount_out > U512::from(u64::MAX) {
+        u64::MAX
+    } else {
+        amount_out.low_u64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clmm_amount_out() {
+        // price 1, deep liquidity: ~amount_in minus the fee, both ways
+        let (sqrt_price, liquidity) = (Q64, 1_000_000_000_000_000);
+        let a2b = clmm_amount_out(sqrt_price, liquidity, 2500, 1_000_000, 1_000_000, true);
+        let b2a = clmm_amount_out(sqrt_price, liquidity, 2500, 1_000_000, 1_000_000, false);
+        assert!((997_000..=997_500).contains(&a2b), "a2b: {}", a2b);
+        assert!((997_000..=997_500).contains(&b2a), "b2a: {}", b2a);
+
+        // price 4 (sqrt 2): 1 token0 buys ~4 token1, 4 token1 buy ~1 token0
+        let a2b = clmm_amount_out(2 * Q64, liquidity, 0, 1_000_000, 1_000_000, true);
+        let b2a = clmm_amount_out(2 * Q64, liquidity, 0, 1_000_000, 4_000_000, false);
+        assert!((3_999_000..=4_000_000).contains(&a2b), "a2b: {}", a2b);
+        assert!((999_000..=1_000_000).contains(&b2a), "b2a: {}", b2a);
+
+        // the price impact grows with the amount in
+        let small = clmm_amount_out(Q64, 1_000_000, 0, 1_000_000, 1_000, true);
+        let large = clmm_amount_out(Q64, 1_000_000, 0, 1_000_000, 1_000_000, true);
+        assert!(large < 1_000 * small);
+
+        assert_eq!(clmm_amount_out(Q64, 0, 2500, 1_000_000, 1_000_000, true), 0);
+    }
+
+    #[test]
+    fn test_min_amount_out() {
+        assert_eq!(min_amount_out(1_000_000), 990_000);
+        assert_eq!(min_amount_out(u64::MAX), (u64::MAX as u128 * 99 / 100) as u64);
+        assert_eq!(min_amount_out(0), 0);
+    }
+}
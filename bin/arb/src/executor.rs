@@ -1,16 +1,38 @@
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
 use async_trait::async_trait;
 use burberry::Executor;
-use eyre::Result;
+use eyre::{eyre, Result};
 use fastcrypto::hash::HashFunction;
+use serde::Serialize;
 use shared_crypto::intent::{Intent, IntentMessage};
-use sui_json_rpc_types::{SuiTransactionBlockResponse, SuiTransactionBlockResponseOptions};
-use sui_sdk::{SuiClient, SuiClientBuilder};
+use simulator::rescale_gas_budget;
+use sui_json_rpc_types::{
+    SuiTransactionBlockEffectsAPI, SuiTransactionBlockResponse, SuiTransactionBlockResponseOptions,
+};
+use sui_sdk::{error::Error as SuiSdkError, SuiClient, SuiClientBuilder};
 use sui_types::{
+    base_types::SuiAddress,
     crypto::{Signer, SuiKeyPair},
+    digests::TransactionDigest,
     signature::GenericSignature,
-    transaction::{Transaction, TransactionData},
+    transaction::{Transaction, TransactionData, TransactionDataAPI},
+};
+use tracing::{info, warn};
+use utils::telegram::{AlertThread, Alerter};
+
+use crate::{
+    arb::ArbResult, common::notification::new_public_tx_message, gas_coin::SenderPool, metrics::metrics, types::Action,
 };
-use tracing::info;
 
 /*
 PublicTxExecutor 是Sui MEV项目的交易执行器，主要功能包括：
@@ -23,17 +45,130 @@ PublicTxExecutor 是Sui MEV项目的交易执行器，主要功能包括：
 该执行器是MEV套利流水线的最后环节，负责将模拟验证通过的交易实际提交到区块链。   
 */
 pub struct PublicTxExecutor {
-    sui: SuiClient,
-    keypair: SuiKeyPair,
+    client: Arc<dyn QuorumDriver>,
+    // a tx is signed by the key of its sender, the first key signs the txs of any other sender
+    keypairs: Vec<(SuiAddress, SuiKeyPair)>,
+    retry_policy: RetryPolicy,
+    // rebuild the tx with the latest reference gas price if it has been raised since the tx was built
+    bump_gas_price: bool,
+    // the gas coins leased by workers are released with their new versions after execution
+    gas_coins: Option<Arc<SenderPool>>,
+    notifier: Option<Alerter>,
+    stats: PublicTxStats,
 }
 
 impl PublicTxExecutor {
     pub async fn new(rpc_url: &str, keypair: SuiKeyPair) -> Result<Self> {
         let sui = SuiClientBuilder::default().build(rpc_url).await?;
-        Ok(Self { sui, keypair })
+        Ok(Self::new_with_client(Arc::new(sui), keypair))
+    }
+
+    pub fn new_with_client(client: Arc<dyn QuorumDriver>, keypair: SuiKeyPair) -> Self {
+        Self {
+            client,
+            keypairs: vec![(SuiAddress::from(&keypair.public()), keypair)],
+            retry_policy: RetryPolicy::default(),
+            bump_gas_price: false,
+            gas_coins: None,
+            notifier: None,
+            stats: PublicTxStats::default(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_gas_price_bump(mut self, bump_gas_price: bool) -> Self {
+        self.bump_gas_price = bump_gas_price;
+        self
+    }
+
+    /// Also sign the txs of the senders of `keypairs`.
+    pub fn with_extra_keypairs(mut self, keypairs: Vec<SuiKeyPair>) -> Self {
+        let keypairs = keypairs
+            .into_iter()
+            .map(|keypair| (SuiAddress::from(&keypair.public()), keypair));
+        self.keypairs.extend(keypairs);
+        self
+    }
+
+    pub fn with_gas_coin_managers(mut self, gas_coins: Arc<SenderPool>) -> Self {
+        self.gas_coins = Some(gas_coins);
+        self
+    }
+
+    /// Send a follow-up notification with the digest and status of every executed tx.
+    pub fn with_notifier(mut self, notifier: Alerter) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    pub fn stats(&self) -> &PublicTxStats {
+        &self.stats
     }
 
+    /// Sign and submit the tx, at the latest reference gas price if it has been raised, see `bump_gas_price`.
+    /// Transient RPC failures are retried with a capped backoff.
     pub async fn execute_tx(&self, tx_data: TransactionData) -> Result<SuiTransactionBlockResponse> {
+        let tx_data = self.bump_gas_price(tx_data).await?;
+        self.submit(tx_data).await
+    }
+
+    /// The tx rebuilt with the latest reference gas price if it has been raised since the tx was built, which
+    /// changes its digest.
+    ///
+    /// The gas price is only bumped before the first submission: once the tx may have reached a
+    /// validator, submitting a different tx with the same gas coin would equivocate it. The gas budget
+    /// was estimated at the old price, so it's scaled up with it.
+    async fn bump_gas_price(&self, mut tx_data: TransactionData) -> Result<TransactionData> {
+        if self.bump_gas_price {
+            let reference_gas_price = self.client.reference_gas_price().await?;
+            if tx_data.gas_price() < reference_gas_price {
+                let gas_budget = rescale_gas_budget(tx_data.gas_budget(), tx_data.gas_price(), reference_gas_price);
+                info!(
+                    from = tx_data.gas_price(),
+                    to = reference_gas_price,
+                    gas_budget,
+                    "Reference gas price changed, bump gas price"
+                );
+                let gas_data = tx_data.gas_data_mut();
+                gas_data.price = reference_gas_price;
+                gas_data.budget = gas_budget;
+            }
+        }
+
+        Ok(tx_data)
+    }
+
+    async fn submit(&self, tx_data: TransactionData) -> Result<SuiTransactionBlockResponse> {
+        let tx = self.sign(tx_data)?;
+
+        let mut attempt = 0;
+        loop {
+            match self.client.execute_transaction_block(tx.clone()).await {
+                Ok(resp) => return Ok(resp),
+                Err(error) if error.is::<TransientRpcError>() && attempt + 1 < self.retry_policy.max_attempts => {
+                    let backoff = self.retry_policy.backoff(attempt);
+                    warn!(digest = %tx.digest(), attempt, ?backoff, "Execute tx failed, retrying: {error}");
+                    self.stats.retries.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn sign(&self, tx_data: TransactionData) -> Result<Transaction> {
+        let sender = tx_data.sender();
+        // a tx signed with another key is rejected anyway, after a round trip
+        let (_, keypair) = self
+            .keypairs
+            .iter()
+            .find(|(address, _)| *address == sender)
+            .ok_or_else(|| eyre!("no keypair for sender {}", sender))?;
         let intent_msg = IntentMessage::new(Intent::sui_transaction(), tx_data);
         let raw_tx = bcs::to_bytes(&intent_msg)?;
 
@@ -43,17 +178,19 @@ impl PublicTxExecutor {
             hasher.finalize().digest
         };
 
-        let sig = self.keypair.sign(&digest);
-        let tx = Transaction::from_generic_sig_data(intent_msg.value, vec![GenericSignature::Signature(sig)]);
-
-        let options = SuiTransactionBlockResponseOptions::default();
-        let tx_resp = self
-            .sui
-            .quorum_driver_api()
-            .execute_transaction_block(tx, options, None)
-            .await?;
+        let sig = keypair.sign(&digest);
+        Ok(Transaction::from_generic_sig_data(
+            intent_msg.value,
+            vec![GenericSignature::Signature(sig)],
+        ))
+    }
 
-        Ok(tx_resp)
+    async fn notify(&self, digest: TransactionDigest, status: String) {
+        if let Some(notifier) = &self.notifier {
+            notifier
+                .send(AlertThread::Notification, &new_public_tx_message(digest, &status))
+                .await;
+        }
     }
 }
 
@@ -64,10 +201,496 @@ impl Executor<TransactionData> for PublicTxExecutor {
     }
 
     async fn execute(&self, action: TransactionData) -> Result<()> {
-        let resp = self.execute_tx(action).await?;
-        let digest = resp.digest.base58_encode();
+        let sender = action.sender();
+        let gas_coins = action.gas().to_vec();
+        // the digest of the submitted tx, which changes with the gas price
+        let mut digest = action.digest();
+        let resp = match self.bump_gas_price(action).await {
+            Ok(tx_data) => {
+                digest = tx_data.digest();
+                self.submit(tx_data).await
+            }
+            Err(error) => Err(error),
+        };
+
+        if let Some(manager) = self.gas_coins.as_ref().and_then(|senders| senders.get(&sender)) {
+            match resp.as_ref().ok().and_then(|resp| resp.effects.as_ref()) {
+                Some(effects) => manager.release_executed(&gas_coins, effects),
+                None => manager.release_unknown(&gas_coins),
+            }
+        }
+
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(error) => {
+                self.stats.failed.fetch_add(1, Ordering::Relaxed);
+                self.notify(digest, format!("error: {error:#}")).await;
+                return Err(error);
+            }
+        };
+
+        let status = match resp.effects.as_ref().map(|effects| effects.status()) {
+            Some(status) if status.is_ok() => {
+                self.stats.succeeded.fetch_add(1, Ordering::Relaxed);
+                metrics().wins.with_label_values(&[self.name()]).inc();
+                "success".to_string()
+            }
+            Some(status) => {
+                self.stats.failed.fetch_add(1, Ordering::Relaxed);
+                format!("{status:?}")
+            }
+            None => "unknown".to_string(),
+        };
+
+        info!(digest = %resp.digest, %status, stats = %self.stats, "Executed tx");
+        self.notify(resp.digest, status).await;
+        Ok(())
+    }
+}
+
+/// Counts the actions executed by the inner executor in the `arb_submissions_total` metric.
+pub struct MeteredExecutor<E> {
+    inner: E,
+}
+
+impl<E> MeteredExecutor<E> {
+    pub fn new(inner: E) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl<A, E> Executor<A> for MeteredExecutor<E>
+where
+    A: Send + 'static,
+    E: Executor<A>,
+{
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn execute(&self, action: A) -> Result<()> {
+        let result = self.inner.execute(action).await;
+        metrics().record_submission(self.inner.name(), result.is_ok());
+        result
+    }
+}
+
+/// Submits signed txs to the network, abstracted so that `PublicTxExecutor` can be tested without a fullnode.
+#[async_trait]
+pub trait QuorumDriver: Send + Sync {
+    /// A failure that is worth retrying must be a `TransientRpcError`.
+    async fn execute_transaction_block(&self, tx: Transaction) -> Result<SuiTransactionBlockResponse>;
+
+    async fn reference_gas_price(&self) -> Result<u64>;
+}
+
+#[async_trait]
+impl QuorumDriver for SuiClient {
+    async fn execute_transaction_block(&self, tx: Transaction) -> Result<SuiTransactionBlockResponse> {
+        let options = SuiTransactionBlockResponseOptions::new().with_effects();
+        self.quorum_driver_api()
+            .execute_transaction_block(tx, options, None)
+            .await
+            .map_err(|error| match error {
+                // resubmitting the same signed tx is idempotent
+                SuiSdkError::RpcError(_) | SuiSdkError::FailToConfirmTransactionStatus(..) => {
+                    eyre::Report::new(TransientRpcError(error.to_string()))
+                }
+                error => eyre!(error),
+            })
+    }
+
+    async fn reference_gas_price(&self) -> Result<u64> {
+        Ok(self.read_api().get_reference_gas_price().await?)
+    }
+}
+
+#[derive(Debug)]
+pub struct TransientRpcError(pub String);
+
+impl fmt::Display for TransientRpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "transient rpc error: {}", self.0)
+    }
+}
+
+impl std::error::Error for TransientRpcError {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// including the first attempt
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // the backoff after the `attempt`-th (0-based) failed attempt, doubled each time up to `max_backoff`
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_backoff)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PublicTxStats {
+    pub succeeded: AtomicU64,
+    pub failed: AtomicU64,
+    pub retries: AtomicU64,
+}
+
+impl fmt::Display for PublicTxStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "succeeded={}, failed={}, retries={}",
+            self.succeeded.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed),
+            self.retries.load(Ordering::Relaxed)
+        )
+    }
+}
+
+/// What the bot would have done for an opportunity, recorded in dry-run mode.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DryRunRecord {
+    pub timestamp_ms: u64,
+    pub action: String,
+    pub tx_digest: String,
+    pub arb_tx_digest: String,
+    pub source: String,
+    pub coin_type: String,
+    pub amount_in: u64,
+    pub profit: u64,
+    pub bid_amount: u64,
+    pub path: Vec<String>,
+    pub cache_misses: u64,
+    pub elapsed_ms: u64,
+    pub create_trial_ctx_ms: u64,
+    pub grid_search_ms: u64,
+    pub gss_ms: Option<u64>,
+}
+
+impl DryRunRecord {
+    pub fn new(
+        action: &Action,
+        tx_digest: TransactionDigest,
+        arb_tx_digest: TransactionDigest,
+        res: &ArbResult,
+        elapsed: Duration,
+    ) -> Self {
+        let (action, bid_amount) = match action {
+            Action::NotifyViaTelegram(_) => ("NotifyViaTelegram", 0),
+            Action::ExecutePublicTx(_) => ("ExecutePublicTx", 0),
+            Action::ShioSubmitBid((_, bid_amount, ..)) => ("ShioSubmitBid", *bid_amount),
+        };
+        let trial_res = &res.best_trial_result;
 
-        info!(?digest, status_ok = ?resp.status_ok(), "Executed tx");
+        Self {
+            timestamp_ms: utils::current_time_ms(),
+            action: action.to_string(),
+            tx_digest: tx_digest.to_string(),
+            arb_tx_digest: arb_tx_digest.to_string(),
+            source: res.source.to_string(),
+            coin_type: trial_res.coin_type.clone(),
+            amount_in: trial_res.amount_in,
+            profit: trial_res.profit,
+            bid_amount,
+            path: trial_res
+                .trade_path
+                .path
+                .iter()
+                .map(|dex| {
+                    format!(
+                        "{}({}):{}:{}",
+                        dex.protocol(),
+                        dex.object_id(),
+                        dex.coin_in_type(),
+                        dex.coin_out_type()
+                    )
+                })
+                .collect(),
+            cache_misses: res.cache_misses,
+            elapsed_ms: elapsed.as_millis() as u64,
+            create_trial_ctx_ms: res.create_trial_ctx_duration.as_millis() as u64,
+            grid_search_ms: res.grid_search_duration.as_millis() as u64,
+            gss_ms: res.gss_duration.map(|d| d.as_millis() as u64),
+        }
+    }
+}
+
+/// Replaces the real executors in dry-run mode: nothing is submitted, the would-be actions are
+/// appended to a JSONL file instead.
+pub struct RecordingExecutor {
+    file: Mutex<File>,
+}
+
+impl RecordingExecutor {
+    pub fn new(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+
+    pub fn record(&self, record: &DryRunRecord) -> Result<()> {
+        info!(
+            action = %record.action,
+            tx = %record.tx_digest,
+            coin = %record.coin_type,
+            profit = record.profit,
+            bid_amount = record.bid_amount,
+            elapsed_ms = record.elapsed_ms,
+            "📝 Dry run, recorded: {:?}",
+            record.path
+        );
+
+        let line = serde_json::to_string(record)?;
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|e| eyre!("poisoned dry-run file lock: {}", e))?;
+        writeln!(file, "{}", line)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::atomic::AtomicUsize};
+
+    use burberry::executor::telegram_message::Message;
+    use sui_types::{
+        base_types::{random_object_ref, SuiAddress},
+        crypto::{get_key_pair, AccountKeyPair, PublicKey, SuiSignature},
+    };
+    use utils::telegram::AlertConfig;
+
+    use super::*;
+
+    struct MockQuorumDriver {
+        responses: Mutex<VecDeque<Result<()>>>,
+        reference_gas_price: u64,
+        submitted: Mutex<Vec<Transaction>>,
+    }
+
+    impl MockQuorumDriver {
+        fn new(responses: Vec<Result<()>>, reference_gas_price: u64) -> Arc<Self> {
+            Arc::new(Self {
+                responses: Mutex::new(responses.into()),
+                reference_gas_price,
+                submitted: Mutex::new(vec![]),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl QuorumDriver for MockQuorumDriver {
+        async fn execute_transaction_block(&self, tx: Transaction) -> Result<SuiTransactionBlockResponse> {
+            let digest = *tx.digest();
+            self.submitted.lock().unwrap().push(tx);
+            self.responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("unexpected submission")
+                .map(|_| SuiTransactionBlockResponse::new(digest))
+        }
+
+        async fn reference_gas_price(&self) -> Result<u64> {
+            Ok(self.reference_gas_price)
+        }
+    }
+
+    #[derive(Default)]
+    struct CountingNotifier {
+        notified: AtomicUsize,
+        sent: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Executor<Message> for CountingNotifier {
+        fn name(&self) -> &str {
+            "CountingNotifier"
+        }
+
+        async fn execute(&self, action: Message) -> Result<()> {
+            self.notified.fetch_add(1, Ordering::Relaxed);
+            self.sent.lock().unwrap().push(format!("{action:?}"));
+            Ok(())
+        }
+    }
+
+    fn new_executor(client: Arc<MockQuorumDriver>, notifier: Arc<CountingNotifier>) -> PublicTxExecutor {
+        let (_, keypair): (_, AccountKeyPair) = get_key_pair();
+        PublicTxExecutor::new_with_client(client, SuiKeyPair::Ed25519(keypair))
+            .with_retry_policy(RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(2),
+            })
+            .with_notifier(Alerter::new(
+                AlertConfig::new("token".to_string(), "chat".to_string()),
+                notifier,
+            ))
+    }
+
+    // a tx of the sender of the main keypair of `executor`
+    fn new_tx_data(executor: &PublicTxExecutor, gas_price: u64) -> TransactionData {
+        let sender = executor.keypairs[0].0;
+        TransactionData::new_transfer_sui(sender, sender, Some(1), random_object_ref(), 1_000_000, gas_price)
+    }
+
+    fn transient() -> Result<()> {
+        Err(TransientRpcError("request timeout".to_string()).into())
+    }
+
+    // the address of the key that signed the submitted `tx`
+    fn signed_by(tx: &Transaction) -> SuiAddress {
+        let GenericSignature::Signature(sig) = &tx.data().tx_signatures()[0] else {
+            panic!("not a simple signature");
+        };
+        SuiAddress::from(&PublicKey::try_from_bytes(sig.scheme(), sig.public_key_bytes()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_sign_with_key_of_sender() {
+        let client = MockQuorumDriver::new(vec![Ok(()), Ok(())], 750);
+        let (first, keypair): (_, AccountKeyPair) = get_key_pair();
+        let (second, extra_keypair): (_, AccountKeyPair) = get_key_pair();
+        let executor = PublicTxExecutor::new_with_client(client.clone(), SuiKeyPair::Ed25519(keypair))
+            .with_extra_keypairs(vec![SuiKeyPair::Ed25519(extra_keypair)]);
+
+        for sender in [second, first] {
+            let tx_data =
+                TransactionData::new_transfer_sui(sender, sender, Some(1), random_object_ref(), 1_000_000, 750);
+            executor.execute_tx(tx_data).await.unwrap();
+        }
+
+        let submitted = client.submitted.lock().unwrap();
+        assert_eq!(signed_by(&submitted[0]), second);
+        assert_eq!(signed_by(&submitted[1]), first);
+        drop(submitted);
+
+        // no key of the sender, not submitted
+        let sender = SuiAddress::random_for_testing_only();
+        let tx_data = TransactionData::new_transfer_sui(sender, sender, Some(1), random_object_ref(), 1_000_000, 750);
+        let error = executor.execute_tx(tx_data).await.unwrap_err();
+        assert!(error.to_string().contains("no keypair for sender"), "{error}");
+        assert_eq!(client.submitted.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_execute_success() {
+        let client = MockQuorumDriver::new(vec![Ok(())], 750);
+        let notifier = Arc::new(CountingNotifier::default());
+        let executor = new_executor(client.clone(), notifier.clone());
+
+        executor.execute(new_tx_data(&executor, 750)).await.unwrap();
+
+        assert_eq!(client.submitted.lock().unwrap().len(), 1);
+        assert_eq!(executor.stats().retries.load(Ordering::Relaxed), 0);
+        assert_eq!(notifier.notified.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_execute_retries_transient_failure() {
+        let client = MockQuorumDriver::new(vec![transient(), transient(), Ok(())], 750);
+        let notifier = Arc::new(CountingNotifier::default());
+        let executor = new_executor(client.clone(), notifier.clone());
+
+        executor.execute(new_tx_data(&executor, 750)).await.unwrap();
+
+        // the same signed tx is resubmitted
+        let submitted = client.submitted.lock().unwrap();
+        assert_eq!(submitted.len(), 3);
+        assert!(submitted.iter().all(|tx| tx.digest() == submitted[0].digest()));
+        assert_eq!(executor.stats().retries.load(Ordering::Relaxed), 2);
+        assert_eq!(notifier.notified.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_execute_gives_up_after_max_attempts() {
+        let client = MockQuorumDriver::new(vec![transient(), transient(), transient()], 750);
+        let notifier = Arc::new(CountingNotifier::default());
+        let executor = new_executor(client.clone(), notifier.clone());
+
+        let error = executor.execute(new_tx_data(&executor, 750)).await.unwrap_err();
+
+        assert!(error.is::<TransientRpcError>());
+        assert_eq!(client.submitted.lock().unwrap().len(), 3);
+        assert_eq!(executor.stats().failed.load(Ordering::Relaxed), 1);
+        assert_eq!(notifier.notified.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_execute_permanent_failure_not_retried() {
+        let client = MockQuorumDriver::new(vec![Err(eyre!("invalid signature"))], 750);
+        let notifier = Arc::new(CountingNotifier::default());
+        let executor = new_executor(client.clone(), notifier.clone());
+
+        let error = executor.execute(new_tx_data(&executor, 750)).await.unwrap_err();
+
+        assert!(!error.is::<TransientRpcError>());
+        assert_eq!(client.submitted.lock().unwrap().len(), 1);
+        assert_eq!(executor.stats().failed.load(Ordering::Relaxed), 1);
+        assert_eq!(notifier.notified.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_gas_price_bump() {
+        let client = MockQuorumDriver::new(vec![Ok(()), Ok(())], 1000);
+        let notifier = Arc::new(CountingNotifier::default());
+
+        let executor = new_executor(client.clone(), notifier.clone());
+        executor.execute(new_tx_data(&executor, 750)).await.unwrap();
+
+        let executor = new_executor(client.clone(), notifier).with_gas_price_bump(true);
+        executor.execute(new_tx_data(&executor, 750)).await.unwrap();
+
+        let gas_data = client
+            .submitted
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|tx| (tx.transaction_data().gas_price(), tx.transaction_data().gas_budget()))
+            .collect::<Vec<_>>();
+        // the budget grows with the price
+        assert_eq!(gas_data, vec![(750, 1_000_000), (1000, 1_333_334)]);
+    }
+
+    #[tokio::test]
+    async fn test_failure_notifies_digest_of_bumped_tx() {
+        let client = MockQuorumDriver::new(vec![Err(eyre!("invalid signature"))], 1000);
+        let notifier = Arc::new(CountingNotifier::default());
+        let executor = new_executor(client.clone(), notifier.clone()).with_gas_price_bump(true);
+
+        let tx_data = new_tx_data(&executor, 750);
+        let built = tx_data.digest();
+        executor.execute(tx_data).await.unwrap_err();
+
+        let submitted = *client.submitted.lock().unwrap()[0].digest();
+        assert_ne!(submitted, built);
+        let sent = notifier.sent.lock().unwrap();
+        assert!(sent[0].contains(&submitted.to_string()), "{sent:?}");
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(10), Duration::from_secs(1));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,336 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use eyre::{ensure, OptionExt, Result};
+use sui_json_rpc_types::{SuiTransactionBlockEffects, SuiTransactionBlockEffectsAPI};
+use sui_sdk::{SuiClient, SUI_COIN_TYPE};
+use sui_types::{
+    base_types::{ObjectID, ObjectRef, SuiAddress},
+    programmable_transaction_builder::ProgrammableTransactionBuilder,
+    transaction::TransactionData,
+};
+use tracing::{info, warn};
+use utils::coin;
+
+use crate::{config::GAS_BUDGET, executor::PublicTxExecutor};
+
+/// A lease that is not released within this duration is considered lost, and the coin is
+/// re-fetched on the next refresh.
+const LEASE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The bookkeeping of gas coins, separated from `GasCoinManager` so that it doesn't need a `SuiClient`.
+#[derive(Debug, Default)]
+struct GasCoinPool {
+    available: VecDeque<ObjectRef>,
+    leased: HashMap<ObjectID, Instant>,
+}
+
+impl GasCoinPool {
+    fn lease(&mut self) -> Option<ObjectRef> {
+        let coin = self.available.pop_front()?;
+        self.leased.insert(coin.0, Instant::now());
+        Some(coin)
+    }
+
+    /// Put the coin back to the pool. `None` means the latest version is unknown, so the coin
+    /// stays out of the pool until the next refresh.
+    fn release(&mut self, coin_id: ObjectID, latest: Option<ObjectRef>) {
+        if self.leased.remove(&coin_id).is_none() {
+            return;
+        }
+
+        if let Some(latest) = latest {
+            self.available.push_back(latest);
+        }
+    }
+
+    /// Replace the available coins with the ones fetched from chain, except the leased ones.
+    fn replace(&mut self, coins: Vec<ObjectRef>, now: Instant) {
+        self.leased
+            .retain(|_, leased_at| now.saturating_duration_since(*leased_at) < LEASE_TIMEOUT);
+
+        let leased = &self.leased;
+        self.available = coins.into_iter().filter(|coin| !leased.contains_key(&coin.0)).collect();
+    }
+}
+
+/// Leases the gas coins of a sender so that concurrent transactions never use the same gas object.
+///
+/// A coin is leased to a worker for one transaction, and released with its new object ref taken
+/// from the transaction effects, or without one if the outcome is unknown (e.g. a shio bid).
+pub struct GasCoinManager {
+    sui: SuiClient,
+    owner: SuiAddress,
+    pool: Mutex<GasCoinPool>,
+}
+
+impl GasCoinManager {
+    pub async fn new(sui: SuiClient, owner: SuiAddress) -> Result<Self> {
+        let manager = Self {
+            sui,
+            owner,
+            pool: Mutex::new(GasCoinPool::default()),
+        };
+        manager.refresh().await?;
+
+        Ok(manager)
+    }
+
+    pub fn owner(&self) -> SuiAddress {
+        self.owner
+    }
+
+    /// True if a gas coin can be leased without a refresh.
+    pub fn has_available(&self) -> bool {
+        !self.pool.lock().unwrap().available.is_empty()
+    }
+
+    /// Re-fetch the gas coins with enough balance from chain.
+    pub async fn refresh(&self) -> Result<()> {
+        let coins = coin::get_coins(&self.sui, self.owner, SUI_COIN_TYPE, GAS_BUDGET).await?;
+        let coins = coins.into_iter().map(|c| c.object_ref()).collect::<Vec<_>>();
+        if coins.is_empty() {
+            warn!(owner = %self.owner, "no gas coin with balance >= {}", GAS_BUDGET);
+        }
+
+        self.pool.lock().unwrap().replace(coins, Instant::now());
+        Ok(())
+    }
+
+    /// Lease a gas coin, refreshing the pool if all coins are in use.
+    pub async fn acquire(&self) -> Result<ObjectRef> {
+        if let Some(coin) = self.pool.lock().unwrap().lease() {
+            return Ok(coin);
+        }
+
+        self.refresh().await?;
+        self.pool.lock().unwrap().lease().ok_or_eyre("all gas coins are in use")
+    }
+
+    /// Release the gas coins of a tx that was not executed, they can be reused as is.
+    pub fn release_unused(&self, gas_coins: &[ObjectRef]) {
+        let mut pool = self.pool.lock().unwrap();
+        for coin in gas_coins {
+            pool.release(coin.0, Some(*coin));
+        }
+    }
+
+    /// Release the gas coins of a tx whose outcome is unknown, they are re-fetched on the next refresh.
+    pub fn release_unknown(&self, gas_coins: &[ObjectRef]) {
+        let mut pool = self.pool.lock().unwrap();
+        for coin in gas_coins {
+            pool.release(coin.0, None);
+        }
+    }
+
+    /// Release the gas coin of an executed tx with its new version.
+    pub fn release_executed(&self, gas_coins: &[ObjectRef], effects: &SuiTransactionBlockEffects) {
+        let gas_object = effects.gas_object().reference.to_object_ref();
+
+        let mut pool = self.pool.lock().unwrap();
+        for coin in gas_coins {
+            let latest = (coin.0 == gas_object.0).then_some(gas_object);
+            pool.release(coin.0, latest);
+        }
+    }
+
+    /// Split the largest gas coin into `num_coins` coins so that workers can run in parallel.
+    pub async fn split(&self, executor: &PublicTxExecutor, num_coins: usize) -> Result<()> {
+        ensure!(num_coins > 0, "num_coins must be greater than 0");
+
+        let coins = coin::get_coins(&self.sui, self.owner, SUI_COIN_TYPE, GAS_BUDGET).await?;
+        let largest = coins
+            .into_iter()
+            .max_by_key(|c| c.balance)
+            .ok_or_eyre("no gas coin to split")?;
+
+        let amount = largest.balance / (num_coins as u64 + 1);
+        ensure!(
+            amount >= GAS_BUDGET,
+            "gas coin {} with balance {} is too small to split into {} coins",
+            largest.coin_object_id,
+            largest.balance,
+            num_coins
+        );
+
+        let mut ptb = ProgrammableTransactionBuilder::new();
+        ptb.pay_sui(vec![self.owner; num_coins], vec![amount; num_coins])?;
+        let gas_price = self.sui.read_api().get_reference_gas_price().await?;
+        let tx_data = TransactionData::new_programmable(
+            self.owner,
+            vec![largest.object_ref()],
+            ptb.finish(),
+            GAS_BUDGET,
+            gas_price,
+        );
+
+        let resp = executor.execute_tx(tx_data).await?;
+        ensure!(
+            resp.status_ok() == Some(true),
+            "split gas coin failed: {:?}",
+            resp.errors
+        );
+        info!(digest = %resp.digest, num_coins, amount, "gas coin split");
+
+        self.refresh().await
+    }
+}
+
+/// The attacker addresses, each with its own gas coins, so that our txs neither come from a single address
+/// nor serialize on the same gas objects.
+pub struct SenderPool {
+    senders: Vec<Arc<GasCoinManager>>,
+    next: RoundRobin,
+}
+
+impl SenderPool {
+    pub fn new(senders: Vec<Arc<GasCoinManager>>) -> Self {
+        assert!(!senders.is_empty(), "no sender");
+        Self {
+            senders,
+            next: RoundRobin::default(),
+        }
+    }
+
+    /// The sender of the next opportunity, round-robin over the senders with a gas coin available.
+    pub fn pick(&self) -> Arc<GasCoinManager> {
+        let i = self.next.pick(self.senders.len(), |i| self.senders[i].has_available());
+        self.senders[i].clone()
+    }
+
+    /// The gas coins of `owner`, `None` if it's not one of our senders.
+    pub fn get(&self, owner: &SuiAddress) -> Option<&Arc<GasCoinManager>> {
+        self.senders.iter().find(|sender| sender.owner == *owner)
+    }
+
+    pub fn addresses(&self) -> Vec<SuiAddress> {
+        self.senders.iter().map(|sender| sender.owner).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<GasCoinManager>> {
+        self.senders.iter()
+    }
+}
+
+/// The index of the next sender, separated from `SenderPool` so that it doesn't need a `SuiClient`.
+#[derive(Debug, Default)]
+struct RoundRobin(AtomicUsize);
+
+impl RoundRobin {
+    // the next of `len` senders that is `usable`. If none is, the next one, which refreshes its gas coins.
+    fn pick(&self, len: usize, usable: impl Fn(usize) -> bool) -> usize {
+        let start = self.0.fetch_add(1, Ordering::Relaxed);
+        (0..len)
+            .map(|offset| (start + offset) % len)
+            .find(|i| usable(*i))
+            .unwrap_or(start % len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Barrier},
+    };
+
+    use sui_types::base_types::{random_object_ref, SequenceNumber};
+
+    use super::*;
+
+    fn pool_with(coins: &[ObjectRef]) -> GasCoinPool {
+        let mut pool = GasCoinPool::default();
+        pool.replace(coins.to_vec(), Instant::now());
+        pool
+    }
+
+    #[test]
+    fn test_concurrent_leases_are_distinct() {
+        let coins = (0..2).map(|_| random_object_ref()).collect::<Vec<_>>();
+        let pool = Arc::new(Mutex::new(pool_with(&coins)));
+        let barrier = Arc::new(Barrier::new(2));
+
+        let handles = (0..2)
+            .map(|_| {
+                let pool = pool.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    pool.lock().unwrap().lease()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let leased = handles
+            .into_iter()
+            .map(|h| h.join().unwrap().unwrap())
+            .collect::<HashSet<_>>();
+        assert_eq!(leased.len(), 2);
+
+        // no coin is left until one is released
+        assert!(pool.lock().unwrap().lease().is_none());
+    }
+
+    #[test]
+    fn test_release_with_new_version() {
+        let coin = random_object_ref();
+        let mut pool = pool_with(&[coin]);
+
+        let leased = pool.lease().unwrap();
+        assert_eq!(leased, coin);
+
+        let latest = (coin.0, SequenceNumber::from_u64(coin.1.value() + 1), coin.2);
+        pool.release(coin.0, Some(latest));
+        assert_eq!(pool.lease(), Some(latest));
+
+        // unknown outcome, the coin is not reused until refreshed
+        pool.release(coin.0, None);
+        assert!(pool.lease().is_none());
+
+        // releasing a coin that is not leased is a no-op
+        pool.release(coin.0, Some(latest));
+        assert!(pool.lease().is_none());
+    }
+
+    #[test]
+    fn test_replace_skips_leased_coins() {
+        let coins = (0..2).map(|_| random_object_ref()).collect::<Vec<_>>();
+        let mut pool = pool_with(&coins);
+
+        let leased = pool.lease().unwrap();
+        pool.replace(coins.clone(), Instant::now());
+        assert_eq!(pool.available.len(), 1);
+        assert_ne!(pool.lease(), Some(leased));
+
+        // an expired lease is reclaimed
+        let mut pool = pool_with(&coins[..1]);
+        pool.lease().unwrap();
+        pool.replace(coins[..1].to_vec(), Instant::now() + LEASE_TIMEOUT);
+        assert_eq!(pool.lease(), Some(coins[0]));
+    }
+
+    #[test]
+    fn test_round_robin_alternates() {
+        let next = RoundRobin::default();
+        let picked = (0..4).map(|_| next.pick(2, |_| true)).collect::<Vec<_>>();
+        assert_eq!(picked, vec![0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_round_robin_skips_sender_without_gas_coins() {
+        let next = RoundRobin::default();
+        let picked = (0..3).map(|_| next.pick(2, |i| i != 0)).collect::<Vec<_>>();
+        assert_eq!(picked, vec![1, 1, 1]);
+
+        // none is usable, the next one refreshes its coins
+        let next = RoundRobin::default();
+        let picked = (0..2).map(|_| next.pick(2, |_| false)).collect::<Vec<_>>();
+        assert_eq!(picked, vec![0, 1]);
+    }
+}
@@ -0,0 +1,71 @@
+//! The arbitrage engine of the `arb` binary, to embed the opportunity finder with other collectors and
+//! executors. `Arb` finds the most profitable trade of a coin, `strategy::ArbStrategy` runs it on the events
+//! of a burberry engine.
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! use arb_core::{
+//!     arb::Arb,
+//!     common::get_latest_epoch,
+//!     defi::{Defi, DexSearcher, IndexerDexSearcher, Trader},
+//!     types::Source,
+//! };
+//! use object_pool::ObjectPool;
+//! use simulator::{HttpSimulator, SimulateCtx, Simulator};
+//! use sui_sdk::SuiClientBuilder;
+//! use sui_types::base_types::SuiAddress;
+//! use utils::coin::{self, GasCoinFilter};
+//!
+//! # async fn example(sender: SuiAddress) -> eyre::Result<()> {
+//! let rpc_url = "http://localhost:9000";
+//! let simulator_pool = Arc::new(ObjectPool::new_async(4, || async {
+//!     Box::new(HttpSimulator::new(rpc_url, &None).await) as Box<dyn Simulator>
+//! }));
+//! let sui = SuiClientBuilder::default().build(rpc_url).await?;
+//!
+//! // any `DexSearcher`, e.g. over your own index of the pools
+//! let dex_searcher: Arc<dyn DexSearcher> =
+//!     Arc::new(IndexerDexSearcher::new(rpc_url, simulator_pool.clone()).await?);
+//! let trader = Trader::new_with_client(sui.clone(), simulator_pool.clone(), false).await?;
+//! let arb = Arb::new_with_defi(Defi::new_with_searcher(dex_searcher, trader, simulator_pool));
+//!
+//! let gas_coins = coin::get_gas_coin_refs(&sui, sender, &GasCoinFilter::default()).await?;
+//! let sim_ctx = SimulateCtx::new(get_latest_epoch(&sui).await?, vec![]);
+//! let coin_type = "0xdba34672e30cb065b1f93e3ab55318768fd6fef66c15942c9f7cb846e2f900e7::usdc::USDC";
+//! let result = arb
+//!     .find_opportunity(sender, coin_type, None, gas_coins, sim_ctx, true, Source::Public)
+//!     .await?;
+//! println!("profit: {}", result.best_trial_result.profit);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod arb;
+pub mod collector;
+pub mod common;
+pub mod config;
+pub mod defi;
+pub mod executor;
+pub mod gas_coin;
+pub mod metrics;
+pub mod pool_ids;
+pub mod start_bot;
+pub mod strategy;
+pub mod types;
+
+mod admin;
+#[cfg(test)]
+mod tests;
+
+pub const BUILD_VERSION: &str = version::build_version!();
+
+#[derive(Clone, Debug, clap::Parser)]
+#[command(about = "Common configuration")]
+pub struct HttpConfig {
+    #[arg(long, env = "SUI_RPC_URL", default_value = "http://localhost:9000")]
+    pub rpc_url: String,
+
+    #[arg(long, help = "deprecated")]
+    pub ipc_path: Option<String>,
+}
@@ -0,0 +1,255 @@
+use std::sync::OnceLock;
+
+use ::utils::metrics_server::register;
+use prometheus::{HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry};
+
+static METRICS: OnceLock<ArbMetrics> = OnceLock::new();
+
+/// The process-wide metrics, shared by the strategy, the worker threads and the executors.
+pub fn metrics() -> &'static ArbMetrics {
+    METRICS.get_or_init(ArbMetrics::new)
+}
+
+pub struct ArbMetrics {
+    registry: Registry,
+    /// events received by the strategy, by source
+    pub events: IntCounterVec,
+    /// `(coin, pool)` opportunities inserted into the ArbCache, by source
+    pub opportunities: IntCounterVec,
+    pub arb_cache_depth: IntGauge,
+    /// arb cache entries dropped because workers were backlogged
+    pub arb_cache_shed: IntCounter,
+    /// time a worker spends searching an opportunity, by worker and source
+    pub trial_duration: HistogramVec,
+    /// how the arb items end up in workers, by worker, source and outcome (e.g. `deadline_exceeded`)
+    pub worker_results: IntCounterVec,
+    /// time from receiving an opportunity to building its final tx, by source. Public txs have no receive time.
+    pub reaction_latency: HistogramVec,
+    /// simulations run by workers, by stage (`trial` or `final`)
+    pub simulations: IntCounterVec,
+    /// trials aborted by a pool of their path, by protocol, see `HopAborted`
+    pub pool_aborts: IntCounterVec,
+    /// 1 for each pool in quarantine, by pool and protocol, see `PoolQuarantine`
+    pub quarantined_pools: IntGaugeVec,
+    /// actions executed by the executors, by executor and result (`ok` or `error`)
+    pub submissions: IntCounterVec,
+    /// txs that are known to have succeeded on chain, by executor. Shio bids are counted in `shio_auctions`.
+    pub wins: IntCounterVec,
+    /// settled shio auctions that we took part in, by outcome (`won` or `lost`)
+    pub shio_auctions: IntCounterVec,
+    /// bids submitted through the shio ws server since start, by result (e.g. `acked`, `timed_out`),
+    /// copied from the counters of the shio executor
+    pub shio_bids: IntGaugeVec,
+    /// time until a shio rpc endpoint accepted a bid, retries included, by endpoint
+    pub shio_rpc_latency: HistogramVec,
+    /// time since the shio collector last received an item
+    pub shio_last_item_age_ms: IntGauge,
+    /// 1 while the update socket of the db simulator is connected
+    pub db_sim_updates_healthy: IntGauge,
+    /// when the db simulator last applied an update batch, in ms since the unix epoch
+    pub db_sim_last_update_ms: IntGauge,
+}
+
+impl ArbMetrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        Self {
+            events: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("arb_events_total", "Events received by the strategy"),
+                    &["source"],
+                ),
+            ),
+            opportunities: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("arb_opportunities_total", "Opportunities inserted into the arb cache"),
+                    &["source"],
+                ),
+            ),
+            arb_cache_depth: register(
+                &registry,
+                IntGauge::new("arb_cache_depth", "Opportunities waiting in the arb cache"),
+            ),
+            arb_cache_shed: register(
+                &registry,
+                IntCounter::new(
+                    "arb_cache_shed_total",
+                    "Arb cache entries dropped because workers were backlogged",
+                ),
+            ),
+            trial_duration: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("arb_trial_duration_seconds", "Time spent searching an opportunity"),
+                    &["worker", "source"],
+                ),
+            ),
+            worker_results: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "arb_worker_results_total",
+                        "Outcomes of the arb items handled by workers",
+                    ),
+                    &["worker", "source", "outcome"],
+                ),
+            ),
+            reaction_latency: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "arb_reaction_latency_seconds",
+                        "Time from receiving an opportunity to building its final tx",
+                    )
+                    .buckets(vec![0.01, 0.02, 0.05, 0.1, 0.2, 0.3, 0.5, 1.0, 2.0]),
+                    &["source"],
+                ),
+            ),
+            simulations: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("arb_simulations_total", "Simulations run by workers"),
+                    &["stage"],
+                ),
+            ),
+            pool_aborts: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("arb_pool_aborts_total", "Trials aborted by a pool of their path"),
+                    &["protocol"],
+                ),
+            ),
+            quarantined_pools: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new("arb_quarantined_pools", "Pools left out of the path search after repeated aborts"),
+                    &["pool", "protocol"],
+                ),
+            ),
+            submissions: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("arb_submissions_total", "Actions executed by the executors"),
+                    &["executor", "result"],
+                ),
+            ),
+            wins: register(
+                &registry,
+                IntCounterVec::new(Opts::new("arb_wins_total", "Txs succeeded on chain"), &["executor"]),
+            ),
+            shio_auctions: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("arb_shio_auctions_total", "Settled shio auctions that we took part in"),
+                    &["outcome"],
+                ),
+            ),
+            shio_bids: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new("arb_shio_bids", "Bids submitted through the shio ws server since start"),
+                    &["result"],
+                ),
+            ),
+            shio_rpc_latency: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "arb_shio_rpc_latency_seconds",
+                        "Time until a shio rpc endpoint accepted a bid",
+                    )
+                    .buckets(vec![0.005, 0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0]),
+                    &["endpoint"],
+                ),
+            ),
+            shio_last_item_age_ms: register(
+                &registry,
+                IntGauge::new(
+                    "arb_shio_last_item_age_ms",
+                    "Time since the shio collector last received an item",
+                ),
+            ),
+            db_sim_updates_healthy: register(
+                &registry,
+                IntGauge::new(
+                    "arb_db_sim_updates_healthy",
+                    "Whether the update socket of the db simulator is connected",
+                ),
+            ),
+            db_sim_last_update_ms: register(
+                &registry,
+                IntGauge::new(
+                    "arb_db_sim_last_update_ms",
+                    "When the db simulator last applied an update batch",
+                ),
+            ),
+            registry,
+        }
+    }
+
+    pub fn record_worker_result(&self, worker: usize, source: &str, outcome: &str) {
+        let worker = worker.to_string();
+        self.worker_results
+            .with_label_values(&[worker.as_str(), source, outcome])
+            .inc();
+    }
+
+    pub fn record_reaction_latency(&self, source: &str, received_at_ms: u64) {
+        let latency_ms = utils::current_time_ms().saturating_sub(received_at_ms);
+        self.reaction_latency
+            .with_label_values(&[source])
+            .observe(latency_ms as f64 / 1000.0);
+    }
+
+    pub fn record_submission(&self, executor: &str, ok: bool) {
+        let result = if ok { "ok" } else { "error" };
+        self.submissions.with_label_values(&[executor, result]).inc();
+    }
+
+    /// The registry to serve, see `utils::metrics_server::serve`.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::net::SocketAddr;
+
+    use ::utils::metrics_server::{sample_value, scrape, serve};
+
+    use super::*;
+
+    /// Serve the process-wide metrics on a random port.
+    pub async fn serve_metrics() -> SocketAddr {
+        serve("127.0.0.1:0".parse().unwrap(), metrics().registry().clone())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_scrape_metrics() {
+        let addr = serve_metrics().await;
+        let before = scrape(addr).await;
+
+        metrics().record_submission("TestExecutor", true);
+        metrics().record_submission("TestExecutor", false);
+        metrics().wins.with_label_values(&["TestExecutor"]).inc();
+
+        let after = scrape(addr).await;
+        for sample in [
+            r#"arb_submissions_total{executor="TestExecutor",result="ok"}"#,
+            r#"arb_submissions_total{executor="TestExecutor",result="error"}"#,
+            r#"arb_wins_total{executor="TestExecutor"}"#,
+        ] {
+            assert_eq!(
+                sample_value(&after, sample),
+                sample_value(&before, sample) + 1.0,
+                "{sample}"
+            );
+        }
+    }
+}
@@ -1,16 +1,21 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fs;
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
 
-use clap::Parser;
-use dex_indexer::{types::Protocol, DexIndexer};
-use eyre::Result;
+use clap::{Parser, ValueEnum};
+use dex_indexer::{
+    types::{Pool, Protocol},
+    DexIndexer,
+};
+use eyre::{Context, Result};
 use mev_logger::LevelFilter;
 use object_pool::ObjectPool;
+use serde::{Deserialize, Serialize};
 use simulator::{DBSimulator, SimulateCtx, Simulator};
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufRead, BufReader};
 use sui_sdk::types::{
     BRIDGE_PACKAGE_ID, DEEPBOOK_PACKAGE_ID, MOVE_STDLIB_PACKAGE_ID, SUI_AUTHENTICATOR_STATE_OBJECT_ID,
     SUI_BRIDGE_OBJECT_ID, SUI_CLOCK_OBJECT_ID, SUI_DENY_LIST_OBJECT_ID, SUI_FRAMEWORK_PACKAGE_ID,
@@ -20,10 +25,10 @@ use sui_sdk::SuiClientBuilder;
 use sui_types::base_types::{ObjectID, SuiAddress};
 use sui_types::object::{Object, Owner};
 use sui_types::transaction::{InputObjectKind, ObjectReadResult};
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::common::get_latest_epoch;
-use crate::defi::{DexSearcher, IndexerDexSearcher, TradeType, Trader};
+use crate::defi::{DexSearcher, IndexerDexSearcher, ProtocolFilter, TradeType, Trader};
 use crate::HttpConfig;
 
 /*
@@ -53,8 +58,38 @@ pub struct Args {
     )]
     pub path: String,
 
+    #[clap(
+        long,
+        help = "The coin the test path starts with, inferred from its first pools if not set"
+    )]
+    pub coin_in: Option<String>,
+
     #[clap(long, help = "Delete objects before simulation")]
     pub delete_objects: Option<String>,
+
+    #[clap(
+        long,
+        help = "Only re-enumerate the pools whose version changed since the manifest of the previous run"
+    )]
+    pub incremental: bool,
+
+    #[clap(
+        long,
+        value_delimiter = ',',
+        help = "Comma-separated protocols to collect, all supported ones if empty"
+    )]
+    pub protocols: Vec<Protocol>,
+
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output_format: OutputFormat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// one id per line, what `--test` and the simulators read
+    Text,
+    /// the ids grouped by protocol and by pool
+    Json,
 }
 
 fn supported_protocols() -> Vec<Protocol> {
@@ -70,7 +105,8 @@ fn supported_protocols() -> Vec<Protocol> {
     ]
 }
 
-/// Write all pool and related object ids to the `args.result_path`.
+/// Write all pool and related object ids to the `args.result_path`. An incremental run writes those of the
+/// protocols of the manifest too, re-collected for the selected protocols and kept as they were for the others.
 pub async fn run(args: Args) -> Result<()> {
     mev_logger::init_console_logger_with_directives(
         Some(LevelFilter::INFO),
@@ -89,45 +125,171 @@ pub async fn run(args: Args) -> Result<()> {
 
     let result_path = args.result_path;
     let rpc_url = args.http_config.rpc_url;
+    let protocols = if args.protocols.is_empty() {
+        supported_protocols()
+    } else {
+        args.protocols
+    };
+
+    // Navi pools are not indexed
+    let indexed_protocols = protocols
+        .iter()
+        .filter(|protocol| **protocol != Protocol::Navi)
+        .cloned()
+        .collect::<Vec<_>>();
+    let dex_indexer = DexIndexer::new_with_protocols(&rpc_url, &indexed_protocols).await?;
+    let sui = SuiClientBuilder::default().build(&rpc_url).await?;
+    let simulator: Arc<dyn Simulator> = Arc::new(DBSimulator::new_default_slow().await.with_dynamic_fields_client(sui));
 
-    let dex_indexer = DexIndexer::new(&rpc_url).await?;
-    let simulator: Arc<dyn Simulator> = Arc::new(DBSimulator::new_default_slow().await);
-
-    let _ = fs::remove_file(&result_path);
-    let file = File::create(&result_path)?;
-    let mut writer = BufWriter::new(file);
-
-    // load existing ids
-    let mut object_ids: HashSet<String> = fs::read_to_string(&result_path)?
-        .lines()
-        .map(|line| line.to_string())
-        .collect();
+    let manifest_path = manifest_path(&result_path);
+    let mut manifest = if args.incremental {
+        PoolManifest::load(&manifest_path)?
+    } else {
+        PoolManifest::default()
+    };
 
-    // add new ids
-    for protocol in supported_protocols() {
+    for protocol in protocols {
         // protocol related ids
-        object_ids.extend(protocol.related_object_ids().await?);
+        let related_ids = protocol.related_object_ids(simulator.clone()).await?;
+        manifest.update_protocol(&protocol, related_ids);
         if protocol == Protocol::Navi {
             // Navi pools are not indexed
             continue;
         }
 
         // pool related ids
-        for pool in dex_indexer.get_all_pools(&protocol)? {
-            object_ids.extend(pool.related_object_ids(simulator.clone()).await);
+        let pools = dex_indexer.get_all_pools(&protocol)?;
+        let enumerated = manifest.update(&protocol, pools, simulator.clone()).await;
+        info!(%protocol, enumerated, "pool related ids collected");
+    }
+
+    let output = PoolIds {
+        global: global_ids().into_iter().collect(),
+        protocols: &manifest.protocols,
+        pools: &manifest.pools,
+    };
+    let content = match args.output_format {
+        OutputFormat::Text => output.all_ids().into_iter().collect::<Vec<_>>().join("\n"),
+        OutputFormat::Json => serde_json::to_string_pretty(&output)?,
+    };
+    fs::write(&result_path, content)?;
+    manifest.save(&manifest_path)?;
+
+    info!("🎉 write pool and related object ids to {}", result_path);
+
+    Ok(())
+}
+
+// the manifest is kept next to the result
+fn manifest_path(result_path: &str) -> String {
+    format!("{}.manifest.json", result_path)
+}
+
+/// The related ids of a pool, and the version of the pool they were enumerated at.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolEntry {
+    pub protocol: Protocol,
+    /// `None` if the pool object couldn't be read, it is enumerated again on the next run
+    pub version: Option<u64>,
+    /// how many of the `ids` are dynamic children of the pool, `None` if they couldn't be enumerated, then
+    /// the pool is enumerated again on the next run
+    pub children: Option<usize>,
+    pub ids: BTreeSet<String>,
+}
+
+/// The pools of the previous run by pool id, so that an incremental run only enumerates the dynamic fields
+/// of the pools that changed since, and the related ids of their protocols.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PoolManifest {
+    #[serde(default)]
+    protocols: BTreeMap<String, BTreeSet<String>>,
+    pools: BTreeMap<String, PoolEntry>,
+}
+
+impl PoolManifest {
+    /// An empty manifest if there is none at `path` yet.
+    pub fn load(path: &str) -> Result<Self> {
+        if !Path::new(path).exists() {
+            info!(path, "no pool manifest, all pools are enumerated");
+            return Ok(Self::default());
         }
+
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content).with_context(|| format!("invalid pool manifest {}", path))
     }
 
-    object_ids.extend(global_ids());
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
 
-    let all_ids: Vec<String> = object_ids.into_iter().collect();
-    writeln!(writer, "{}", all_ids.join("\n"))?;
+    /// Replace the related ids of `protocol`, those of the other protocols are kept.
+    pub fn update_protocol(&mut self, protocol: &Protocol, ids: impl IntoIterator<Item = String>) {
+        self.protocols.insert(protocol.to_string(), ids.into_iter().collect());
+    }
 
-    writer.flush()?;
+    /// Refresh the entries of the `pools` of `protocol`, returns how many were enumerated. A pool whose version
+    /// is the one of its entry keeps its ids, the pools of `protocol` that are gone are dropped.
+    pub async fn update(&mut self, protocol: &Protocol, pools: Vec<Pool>, simulator: Arc<dyn Simulator>) -> usize {
+        let pool_ids = pools.iter().map(|pool| pool.pool).collect::<Vec<_>>();
+        let versions = simulator.multi_get_objects(&pool_ids).await;
+
+        // the pools of the other protocols are out of scope
+        let (mut previous, others): (BTreeMap<_, _>, BTreeMap<_, _>) = std::mem::take(&mut self.pools)
+            .into_iter()
+            .partition(|(_, entry)| entry.protocol == *protocol);
+        self.pools = others;
+
+        let mut enumerated = 0;
+        for (pool, object) in pools.into_iter().zip(versions) {
+            let pool_id = pool.pool.to_string();
+            let version = object.map(|object| object.version().value());
+            let entry = match previous.remove(&pool_id) {
+                Some(entry) if version.is_some() && entry.version == version && entry.children.is_some() => entry,
+                _ => {
+                    enumerated += 1;
+                    let mut ids = pool.own_object_ids().into_iter().collect::<BTreeSet<_>>();
+                    let children = match pool.children_ids(simulator.clone()).await {
+                        Ok(children_ids) => {
+                            let children = children_ids.len();
+                            ids.extend(children_ids);
+                            Some(children)
+                        }
+                        Err(error) => {
+                            warn!(%pool_id, ?error, "fail to enumerate the pool children, retried on the next run");
+                            None
+                        }
+                    };
+                    PoolEntry {
+                        protocol: protocol.clone(),
+                        version,
+                        children,
+                        ids,
+                    }
+                }
+            };
+            self.pools.insert(pool_id, entry);
+        }
 
-    info!("🎉 write pool and related object ids to {}", result_path);
+        enumerated
+    }
+}
 
-    Ok(())
+/// What is written to the `result_path`, the protocols and the pools of the same manifest.
+#[derive(Serialize)]
+struct PoolIds<'a> {
+    global: BTreeSet<String>,
+    protocols: &'a BTreeMap<String, BTreeSet<String>>,
+    pools: &'a BTreeMap<String, PoolEntry>,
+}
+
+impl PoolIds<'_> {
+    fn all_ids(&self) -> BTreeSet<String> {
+        let mut ids = self.global.clone();
+        ids.extend(self.protocols.values().flatten().cloned());
+        ids.extend(self.pools.values().flat_map(|entry| entry.ids.iter().cloned()));
+        ids
+    }
 }
 
 fn global_ids() -> HashSet<String> {
@@ -170,14 +332,14 @@ async fn test_pool_related_objects(args: Args) -> Result<()> {
     let with_fallback = args.with_fallback;
     let rpc_url = args.http_config.rpc_url;
 
-    let simulator_pool = Arc::new(ObjectPool::new(1, move || {
-        tokio::runtime::Runtime::new()
-            .unwrap()
-            .block_on(async { Box::new(DBSimulator::new_test(with_fallback).await) as Box<dyn Simulator> })
+    let simulator_pool = Arc::new(ObjectPool::new_async(1, move || async move {
+        Box::new(DBSimulator::new_test(with_fallback).await) as Box<dyn Simulator>
     }));
 
     let dex_searcher: Arc<dyn DexSearcher> = Arc::new(IndexerDexSearcher::new(&rpc_url, simulator_pool.clone()).await?);
-    let path = dex_searcher.find_test_path(&path).await?;
+    let path = dex_searcher
+        .find_test_path(&path, args.coin_in.as_deref(), &ProtocolFilter::default())
+        .await?;
     info!(?with_fallback, ?amount_in, ?path, ?args.delete_objects, "test data");
     // Test Data ==================================
 
@@ -196,7 +358,7 @@ async fn test_pool_related_objects(args: Args) -> Result<()> {
 
     let sim_ctx = SimulateCtx::new(epoch, override_objects);
 
-    let trader = Trader::new(simulator_pool).await?;
+    let trader = Trader::new(&rpc_url, simulator_pool, false).await?;
     let result = trader
         .get_trade_result(&path, sender, amount_in, TradeType::Flashloan, vec![], sim_ctx)
         .await?;
@@ -234,3 +396,174 @@ async fn pool_related_objects(file_path: &str) -> Result<Vec<ObjectReadResult>>
 
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use dex_indexer::types::{PoolExtra, Token};
+
+    use super::*;
+    use crate::tests::MockSimulator;
+
+    fn blue_move_pool(pool_id: ObjectID) -> Pool {
+        Pool {
+            protocol: Protocol::BlueMove,
+            pool: pool_id,
+            tokens: vec![
+                Token::new("0x2::sui::SUI", 9),
+                Token::new(
+                    "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN",
+                    9,
+                ),
+            ],
+            extra: PoolExtra::None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_incremental_update_skips_unchanged_pools() {
+        let (pool1, pool2, pool3) = (ObjectID::random(), ObjectID::random(), ObjectID::random());
+        let pools = || vec![blue_move_pool(pool1), blue_move_pool(pool2)];
+
+        // the first run enumerates every pool
+        let simulator = Arc::new(MockSimulator::default().with_pool(pool1, 10).with_pool(pool2, 20));
+        let mut manifest = PoolManifest::default();
+        assert_eq!(
+            manifest.update(&Protocol::BlueMove, pools(), simulator.clone()).await,
+            2
+        );
+        assert!(!simulator.enumerated_reads.lock().unwrap().is_empty());
+        let pool1_entry = manifest.pools[&pool1.to_string()].clone();
+        assert_eq!(pool1_entry.version, Some(10));
+        assert!(pool1_entry.ids.contains(&pool1.to_string()));
+        // the dynamic child of the pool, its grandson isn't there
+        assert_eq!(pool1_entry.children, Some(1));
+
+        // nothing changed, nothing is enumerated
+        let simulator = Arc::new(MockSimulator::default().with_pool(pool1, 10).with_pool(pool2, 20));
+        assert_eq!(
+            manifest.update(&Protocol::BlueMove, pools(), simulator.clone()).await,
+            0
+        );
+        assert!(simulator.enumerated_reads.lock().unwrap().is_empty());
+        assert_eq!(manifest.pools[&pool1.to_string()], pool1_entry);
+
+        // only the pool with a new version and the new pool are enumerated
+        let simulator = Arc::new(
+            MockSimulator::default()
+                .with_pool(pool1, 10)
+                .with_pool(pool2, 21)
+                .with_pool(pool3, 30),
+        );
+        let mut pools = pools();
+        pools.push(blue_move_pool(pool3));
+        assert_eq!(manifest.update(&Protocol::BlueMove, pools, simulator.clone()).await, 2);
+        assert_eq!(manifest.pools[&pool1.to_string()], pool1_entry);
+        assert_eq!(manifest.pools[&pool2.to_string()].version, Some(21));
+        assert_eq!(manifest.pools[&pool3.to_string()].version, Some(30));
+    }
+
+    #[tokio::test]
+    async fn test_update_keeps_other_protocols() {
+        let (pool1, pool2) = (ObjectID::random(), ObjectID::random());
+        let simulator = Arc::new(MockSimulator::default().with_pool(pool1, 1).with_pool(pool2, 1));
+
+        let mut manifest = PoolManifest::default();
+        manifest.pools.insert(
+            pool1.to_string(),
+            PoolEntry {
+                protocol: Protocol::Cetus,
+                version: Some(1),
+                children: Some(0),
+                ids: BTreeSet::from([pool1.to_string()]),
+            },
+        );
+        // a pool of the protocol that is gone
+        manifest.pools.insert(
+            ObjectID::random().to_string(),
+            PoolEntry {
+                protocol: Protocol::BlueMove,
+                version: Some(1),
+                children: Some(0),
+                ids: BTreeSet::new(),
+            },
+        );
+
+        manifest.update_protocol(&Protocol::Cetus, ["0xc".to_string()]);
+        manifest.update_protocol(&Protocol::BlueMove, ["0xb1".to_string()]);
+
+        // an incremental run of BlueMove only
+        manifest.update_protocol(&Protocol::BlueMove, ["0xb2".to_string()]);
+        manifest
+            .update(&Protocol::BlueMove, vec![blue_move_pool(pool2)], simulator)
+            .await;
+        assert_eq!(manifest.pools.len(), 2);
+        assert_eq!(manifest.pools[&pool1.to_string()].protocol, Protocol::Cetus);
+        assert_eq!(manifest.pools[&pool2.to_string()].protocol, Protocol::BlueMove);
+
+        // both the pool ids and the related ids of Cetus are kept
+        let output = PoolIds {
+            global: BTreeSet::new(),
+            protocols: &manifest.protocols,
+            pools: &manifest.pools,
+        };
+        let all_ids = output.all_ids();
+        assert!(all_ids.contains(&pool1.to_string()));
+        assert!(all_ids.contains("0xc"));
+        assert!(all_ids.contains(&pool2.to_string()));
+        assert!(all_ids.contains("0xb2"));
+        assert!(!all_ids.contains("0xb1"));
+    }
+
+    #[tokio::test]
+    async fn test_pool_with_unreadable_children_enumerated_again() {
+        let pool_id = ObjectID::random();
+        // the Cetus children are read from the pool object, which the simulator doesn't serve
+        let cetus_pool = || Pool {
+            protocol: Protocol::Cetus,
+            ..blue_move_pool(pool_id)
+        };
+        let simulator = Arc::new(MockSimulator::default().with_pool(pool_id, 10));
+
+        let mut manifest = PoolManifest::default();
+        let enumerated = manifest
+            .update(&Protocol::Cetus, vec![cetus_pool()], simulator.clone())
+            .await;
+        assert_eq!(enumerated, 1);
+        let entry = &manifest.pools[&pool_id.to_string()];
+        assert_eq!((entry.version, entry.children), (Some(10), None));
+        assert!(entry.ids.contains(&pool_id.to_string()));
+
+        // same version, but the children are still missing
+        assert_eq!(
+            manifest.update(&Protocol::Cetus, vec![cetus_pool()], simulator).await,
+            1
+        );
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let pool_id = ObjectID::random();
+        let mut manifest = PoolManifest::default();
+        manifest.pools.insert(
+            pool_id.to_string(),
+            PoolEntry {
+                protocol: Protocol::Turbos,
+                version: None,
+                children: None,
+                ids: BTreeSet::from([pool_id.to_string(), "0x2".to_string()]),
+            },
+        );
+
+        manifest.update_protocol(&Protocol::Turbos, ["0x3".to_string()]);
+
+        let path = std::env::temp_dir().join(format!("pool_ids_{}.manifest.json", pool_id));
+        let path = path.to_str().unwrap();
+        manifest.save(path).unwrap();
+        let loaded = PoolManifest::load(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(loaded.pools, manifest.pools);
+        assert_eq!(loaded.protocols, manifest.protocols);
+
+        assert!(PoolManifest::load(path).unwrap().pools.is_empty());
+    }
+}
@@ -1,24 +1,51 @@
 use std::{
-    sync::Arc,
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
     time::{Duration, Instant},
 };
 
-use ::utils::heartbeat;
-use burberry::{executor::telegram_message::TelegramMessageDispatcher, map_collector, map_executor, Engine};
+use ::utils::{
+    heartbeat::{self, HealthStatus},
+    link, metrics_server,
+    telegram::{self, AlertThread},
+    telegram_layer::TelegramLayer,
+};
+use burberry::{executor::telegram_message::escape, map_collector, map_executor, Engine};
 use clap::Parser;
 use eyre::Result;
+use mev_logger::LoggerConfig;
 use object_pool::ObjectPool;
-use shio::{new_shio_collector_and_executor, ShioRPCExecutor};
-use simulator::{DBSimulator, HttpSimulator, ReplaySimulator, Simulator};
-use sui_types::{base_types::SuiAddress, crypto::SuiKeyPair};
-use tracing::{info, warn};
+use shio::{
+    new_shio_collector_and_executor, BidSigner, BidStats, ConnState, HttpBidSigner, ItemClock, Keepalive,
+    KeypairSigner, SenderBidSigner, ShioRPCExecutor,
+};
+use simulator::{
+    DBSimulator, DBSimulatorBuilder, HttpSimulator, ReplayIntervals, ReplaySimulator, Simulator, UpdateHealth,
+};
+use sui_sdk::SuiClientBuilder;
+use sui_types::{
+    base_types::{ObjectID, SuiAddress},
+    crypto::SuiKeyPair,
+};
+use tokio::{
+    runtime::Handle,
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+    task::JoinSet,
+};
+use tracing::{error, info, warn};
 
 use crate::{
-    collector::{PrivateTxCollector, PublicTxCollector},
-    executor::PublicTxExecutor,
-    strategy::ArbStrategy,
+    admin,
+    collector::{PublicTxCollector, RelayCollector, RelayFilter},
+    config::{init_pegged_coin_types, BotConfig},
+    defi::{init_dex_cache, init_min_out_tolerance, init_pool_quarantine, shutdown_indexer},
+    executor::{MeteredExecutor, PublicTxExecutor, RecordingExecutor},
+    gas_coin::{GasCoinManager, SenderPool},
+    metrics,
+    strategy::{ArbStrategy, AutoscaleConfig, FinalCheck, WorkerShutdown, WorkerThreads},
     types::{Action, Event},
-    HttpConfig,
+    BUILD_VERSION,
 };
 
 /*
@@ -27,230 +54,1136 @@ use crate::{
 
 #[derive(Clone, Debug, Parser)]
 pub struct Args {
+    /// TOML file with the bot parameters, see `BotConfig`.
+    /// Flags given on the command line (or via env) override the values in the file.
+    #[arg(long)]
+    pub config: Option<String>,
+
     #[arg(long, env = "SUI_PRIVATE_KEY")]
-    pub private_key: String,
+    pub private_key: Option<String>,
+
+    /// Comma-separated attacker keys besides `private_key`, the arb txs are sent from each key in turn
+    #[arg(long, value_delimiter = ',', env = "SUI_EXTRA_PRIVATE_KEYS")]
+    pub extra_private_keys: Option<Vec<String>>,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "true",
+        help = "shio executor uses RPC to submit bid, `--shio-use-rpc false` turns it off"
+    )]
+    pub shio_use_rpc: Option<bool>,
+
+    /// Comma-separated shio rpc endpoints, a bid goes to the next one when an endpoint fails
+    /// [default: shio::SHIO_JSON_RPC_URL]
+    #[arg(long, value_delimiter = ',')]
+    pub shio_rpc_urls: Option<Vec<String>>,
+
+    /// The attempts of a bid after the first one, on the next shio rpc endpoints in turn
+    /// [default: shio::DEFAULT_MAX_RETRIES]
+    #[arg(long)]
+    pub shio_rpc_max_retries: Option<usize>,
+
+    /// A shio bid the ws server hasn't replied to within this many milliseconds is reported as
+    /// unacknowledged [default: 150]
+    #[arg(long)]
+    pub shio_bid_ack_timeout: Option<u64>,
+
+    /// Sign the shio bids with this signing service instead of `private_key`, it must hold the same keys
+    #[arg(long)]
+    pub shio_signer_url: Option<String>,
+
+    /// The bid sender whose key is held by `shio_signer_url`, in place of `private_key`.
+    /// Without a `private_key` only the shio bids are executed
+    #[arg(long)]
+    pub shio_signer_address: Option<SuiAddress>,
+
+    /// A shio bid whose signature takes longer than this many milliseconds fails [default: 100]
+    #[arg(long)]
+    pub shio_signer_timeout: Option<u64>,
+
+    /// Simulate-only mode: never submit a bid or a public tx,
+    /// the would-be actions are recorded to `dry_run_output` instead.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub dry_run: Option<bool>,
+
+    /// [default: dry_run.jsonl]
+    #[arg(long)]
+    pub dry_run_output: Option<String>,
+
+    /// Opportunities with a lower profit (in MIST) are not executed [default: 0]
+    #[arg(long)]
+    pub min_profit: Option<u64>,
 
-    #[arg(long, help = "shio executor uses RPC to submit bid")]
-    pub shio_use_rpc: bool,
+    /// [default: http://localhost:9000]
+    #[arg(long, env = "SUI_RPC_URL")]
+    pub rpc_url: Option<String>,
+
+    #[arg(long, help = "deprecated")]
+    pub ipc_path: Option<String>,
+
+    /// Serve the prometheus metrics on `GET /metrics` at this port
+    #[arg(long)]
+    pub metrics_port: Option<u16>,
+
+    /// Serve the admin commands on localhost at this port, e.g. `echo 'log arb=trace' | nc localhost <port>`,
+    /// or `quarantine` for the quarantined pools
+    #[arg(long)]
+    pub admin_port: Option<u16>,
 
     #[command(flatten)]
-    pub http_config: HttpConfig,
+    collector_args: CollectorArgs,
 
     #[command(flatten)]
-    collector_config: CollectorConfig,
+    db_sim_args: DbSimArgs,
 
     #[command(flatten)]
-    db_sim_config: DbSimConfig,
+    worker_args: WorkerArgs,
 
     #[command(flatten)]
-    worker_config: WorkerConfig,
+    telegram_args: TelegramArgs,
 }
 
 #[derive(Clone, Debug, Parser)]
-struct CollectorConfig {
+struct CollectorArgs {
     /// relay tx collector (should be mutually exclusive with public tx collector)
     #[arg(long)]
     pub relay_ws_url: Option<String>,
 
+    /// Comma-separated packages, the relay only sends the txs calling them [default: all txs]
+    #[arg(long, value_delimiter = ',')]
+    pub relay_move_packages: Option<Vec<ObjectID>>,
+
     /// shio collector
     #[arg(long)]
     pub shio_ws_url: Option<String>,
 
-    /// public tx collector
-    #[arg(long, env = "SUI_TX_SOCKET_PATH", default_value = "/tmp/sui_tx.sock")]
-    pub tx_socket_path: String,
+    /// Comma-separated shio feeds to also read from, bids fail over to them in order while
+    /// `shio_ws_url` is down
+    #[arg(long, value_delimiter = ',')]
+    pub shio_backup_ws_urls: Option<Vec<String>>,
+
+    /// Ping the shio feeds every this many milliseconds [default: 5000]
+    #[arg(long)]
+    pub shio_ping_interval: Option<u64>,
+
+    /// Reconnect a shio feed that sends nothing, pongs included, for this many milliseconds [default: 15000]
+    #[arg(long)]
+    pub shio_stall_timeout: Option<u64>,
+
+    /// public tx collector [default: /tmp/sui_tx.sock]
+    #[arg(long, env = "SUI_TX_SOCKET_PATH")]
+    pub tx_socket_path: Option<String>,
+
+    /// Forward every public tx to the strategy, by default txs without any swap event are dropped
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub raw_public_txs: Option<bool>,
 }
 
 #[derive(Clone, Debug, Parser)]
-struct DbSimConfig {
-    /// needed for db simulator
-    #[arg(long, env = "SUI_DB_PATH", default_value = "/home/ubuntu/sui/db/live/store")]
-    pub db_path: String,
+struct DbSimArgs {
+    /// needed for db simulator [default: /home/ubuntu/sui/db/live/store]
+    #[arg(long, env = "SUI_DB_PATH")]
+    pub db_path: Option<String>,
 
-    /// needed for db simulator
-    #[arg(long, env = "SUI_CONFIG_PATH", default_value = "/home/ubuntu/sui/fullnode.yaml")]
-    pub config_path: String,
+    /// needed for db simulator [default: /home/ubuntu/sui/fullnode.yaml]
+    #[arg(long, env = "SUI_CONFIG_PATH")]
+    pub config_path: Option<String>,
 
-    /// db simulator listens to this socket
-    /// sui node will update object changes
-    #[arg(long, env = "SUI_UPDATE_CACHE_SOCKET", default_value = "/tmp/sui_cache_updates.sock")]
-    pub update_cache_socket: String,
+    /// db simulator listens to this feed, `unix:///path` or `tcp://host:port`, with an optional
+    /// `?compression=zstd`. sui node will update object changes [default: /tmp/sui_cache_updates.sock]
+    #[arg(long, env = "SUI_UPDATE_CACHE_SOCKET")]
+    pub update_cache_socket: Option<String>,
 
-    /// pool related objects path
-    #[arg(
-        long,
-        env = "SUI_PRELOAD_PATH",
-        default_value = "/home/ubuntu/suiflow-relay/pool_related_ids.txt"
-    )]
-    pub preload_path: String,
+    /// pool related objects path [default: /home/ubuntu/suiflow-relay/pool_related_ids.txt]
+    #[arg(long, env = "SUI_PRELOAD_PATH")]
+    pub preload_path: Option<String>,
 
     /// use db simulator or not
-    #[arg(long, default_value_t = false)]
-    pub use_db_simulator: bool,
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub use_db_simulator: Option<bool>,
+
+    /// catchup interval in seconds [default: 60]
+    #[arg(long)]
+    pub catchup_interval: Option<u64>,
 
-    /// catchup interval in seconds
-    #[arg(long, default_value_t = 60)]
-    pub catchup_interval: u64,
+    /// how often the db simulators reload the underlying store, in seconds [default: 86400]
+    #[arg(long)]
+    pub store_catchup_interval: Option<u64>,
+
+    /// Warn on every object read that misses the override objects, instead of a debug summary per
+    /// simulation
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub warn_override_misses: Option<bool>,
 }
 
 #[derive(Clone, Debug, Parser)]
-struct WorkerConfig {
+struct WorkerArgs {
     /// Number of workers to process events (public tx, private tx, shio)
-    /// 8 is usually enough
-    #[arg(long, default_value_t = 8)]
-    pub workers: usize,
+    /// 8 is usually enough [default: 8]
+    #[arg(long)]
+    pub workers: Option<usize>,
 
-    /// Number of simulator in simulator pool.
-    #[arg(long, default_value_t = 32)]
-    pub num_simulators: usize,
+    /// Number of simulator in simulator pool [default: 32]
+    #[arg(long)]
+    pub num_simulators: Option<usize>,
 
-    /// If a new coin comes in and it has been processed within the last `max_recent_arbs` times,
-    /// it will be ignored.
-    #[arg(long, default_value_t = 20)]
-    pub max_recent_arbs: usize,
+    /// If a (coin, pool) pair from a public tx has been sent to workers within this cooldown (in milliseconds),
+    /// it will be ignored [default: 3000]
+    #[arg(long)]
+    pub public_arb_cooldown: Option<u64>,
+
+    /// Same as `public_arb_cooldown` but for shio opportunities, which are already gated by their deadline
+    /// [default: 0]
+    #[arg(long)]
+    pub shio_arb_cooldown: Option<u64>,
 
     /// short and long interval for dedicated simulator (in milliseconds)
     /// short: 50ms
-    #[arg(long, default_value_t = 50)]
-    pub dedicated_short_interval: u64,
+    #[arg(long)]
+    pub dedicated_short_interval: Option<u64>,
 
     /// long: 200ms
-    #[arg(long, default_value_t = 200)]
-    pub dedicated_long_interval: u64,
+    #[arg(long)]
+    pub dedicated_long_interval: Option<u64>,
+
+    /// How many times the dedicated simulator reloads on the short interval after a submission
+    /// [default: 50]
+    #[arg(long)]
+    pub dedicated_short_updates: Option<u32>,
+
+    /// The final dry run falls back to a pooled simulator if the dedicated simulator has not reloaded
+    /// the store for this many milliseconds [default: 1000]
+    #[arg(long)]
+    pub max_dedicated_lag: Option<u64>,
+
+    /// Split the largest SUI coin into this many gas coins at startup, so that workers don't
+    /// wait for each other's gas coin [default: 0, no split]
+    #[arg(long)]
+    pub split_gas_coins: Option<usize>,
+
+    /// The final dry run right before submission must realize at least this percentage of the
+    /// estimated profit [default: 80]
+    #[arg(long)]
+    pub min_realized_profit_pct: Option<u64>,
+
+    /// A shio bid is dropped if less than this many milliseconds remain before its deadline when
+    /// the final dry run starts [default: 10]
+    #[arg(long)]
+    pub final_check_margin: Option<u64>,
+
+    /// An opportunity of a private tx is dropped once the tx was received longer than this many
+    /// milliseconds ago, it's likely executed by then [default: 500]
+    #[arg(long)]
+    pub max_private_age: Option<u64>,
+
+    /// Comma-separated coin types whose paths are searched by every worker before it's ready
+    /// [default: USDC,CETUS]
+    #[arg(long, value_delimiter = ',')]
+    pub warm_up_coins: Option<Vec<String>>,
+
+    /// Also trade through SUI-rooted cycles of up to this many hops, e.g. SUI -> A -> B -> SUI
+    /// [default: 0, disabled]
+    #[arg(long)]
+    pub max_cycle_hops: Option<usize>,
+
+    /// The dexes built from a pool are reused for this many milliseconds unless a swap on the pool is
+    /// observed, 0 to rebuild them on every search [default: 5000]
+    #[arg(long)]
+    pub dex_cache_ttl: Option<u64>,
+
+    /// Comma-separated protocols whose pools are ignored, e.g. `cetus,turbos`
+    #[arg(long, value_delimiter = ',')]
+    pub disable_protocols: Option<Vec<String>>,
+
+    /// Comma-separated protocols whose pools are the only ones searched [default: all]
+    #[arg(long, value_delimiter = ',')]
+    pub only_protocols: Option<Vec<String>>,
+
+    /// Of the pools searched per coin, keep this many of each protocol before ranking the others by
+    /// liquidity, 0 to only rank them by liquidity [default: 3]
+    #[arg(long)]
+    pub max_pools_per_protocol: Option<usize>,
+
+    /// Of the paths whose pools can all be quoted locally, only simulate this many best estimated ones, 0 to
+    /// simulate all of them [default: 16]
+    #[arg(long)]
+    pub max_simulated_paths: Option<usize>,
+
+    /// Leave a pool out of the path search once it aborted all the trials of this many opportunities in a
+    /// row, 0 to never quarantine a pool [default: 5]
+    #[arg(long)]
+    pub quarantine_aborts: Option<usize>,
+
+    /// How long a pool stays quarantined, in seconds [default: 600]
+    #[arg(long)]
+    pub quarantine_cool_off: Option<u64>,
+
+    /// The swaps that take a min amount out get their quote minus this many basis points [default: 100]
+    #[arg(long)]
+    pub min_out_tolerance_bps: Option<u64>,
+
+    /// Stack size of each worker thread in megabytes [default: 128]
+    #[arg(long)]
+    pub stack_size_mb: Option<usize>,
+
+    /// Pin each worker thread to a CPU core, round-robin
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub pin_to_cores: Option<bool>,
+
+    /// Never flashloan from Navi, the paths whose first hop can't flashloan are rotated or dropped
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub disable_navi: Option<bool>,
+
+    /// Spawn up to this many workers while the arb_item channel is backlogged, and retire the
+    /// extra ones once they are idle [default: 0, disabled]
+    #[arg(long)]
+    pub max_workers: Option<usize>,
+
+    /// The arb_item channel is backlogged when at least this many items are waiting [default: 10]
+    #[arg(long)]
+    pub scale_up_backlog: Option<usize>,
+
+    /// Spawn a worker once the channel stays backlogged for this many seconds [default: 10]
+    #[arg(long)]
+    pub scale_up_after: Option<u64>,
+
+    /// Retire a worker once some workers stay idle for this many seconds [default: 60]
+    #[arg(long)]
+    pub scale_down_idle: Option<u64>,
+}
+
+#[derive(Clone, Debug, Parser)]
+struct TelegramArgs {
+    /// Report panics, unhealthy components and executed arbs with this telegram bot, disabled if not set
+    #[arg(long, env = "TELEGRAM_BOT_TOKEN")]
+    pub telegram_bot_token: Option<String>,
+
+    /// The chat the bot reports to, required with `telegram_bot_token`
+    #[arg(long, env = "TELEGRAM_CHAT_ID")]
+    pub telegram_chat_id: Option<String>,
+
+    /// Thread of the chat for panics and unhealthy components [default: main thread]
+    #[arg(long, env = "TELEGRAM_ERROR_THREAD_ID")]
+    pub telegram_error_thread_id: Option<String>,
+
+    /// Thread of the chat for executed arbs [default: main thread]
+    #[arg(long, env = "TELEGRAM_NOTIFICATION_THREAD_ID")]
+    pub telegram_notification_thread_id: Option<String>,
+
+    /// Where the links of the notifications point to: suiscan, suivision or the base url of an explorer
+    /// [default: suiscan]
+    #[arg(long)]
+    pub explorer: Option<String>,
+}
+
+impl Args {
+    /// CLI flags > config file > defaults
+    pub fn into_bot_config(self) -> Result<BotConfig> {
+        let file_config = match &self.config {
+            Some(path) => BotConfig::from_file(path)?,
+            None => BotConfig::default(),
+        };
+
+        let config = self.override_config(file_config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn override_config(self, mut config: BotConfig) -> BotConfig {
+        fn set<T>(field: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+
+        // a bare boolean flag turns it on, `--flag false` turns it off
+        set(&mut config.shio_use_rpc, self.shio_use_rpc);
+        set(&mut config.dry_run, self.dry_run);
+        set(&mut config.db_sim.use_db_simulator, self.db_sim_args.use_db_simulator);
+        set(
+            &mut config.db_sim.warn_override_misses,
+            self.db_sim_args.warn_override_misses,
+        );
+        set(&mut config.collector.raw_public_txs, self.collector_args.raw_public_txs);
+        set(&mut config.worker.pin_to_cores, self.worker_args.pin_to_cores);
+        set(&mut config.worker.disable_navi, self.worker_args.disable_navi);
+
+        config.private_key = self.private_key.or(config.private_key);
+        config.ipc_path = self.ipc_path.or(config.ipc_path);
+        config.metrics_port = self.metrics_port.or(config.metrics_port);
+        config.admin_port = self.admin_port.or(config.admin_port);
+        set(&mut config.extra_private_keys, self.extra_private_keys);
+        set(&mut config.rpc_url, self.rpc_url);
+        set(&mut config.dry_run_output, self.dry_run_output);
+        set(&mut config.min_profit, self.min_profit);
+        set(&mut config.shio_bid_ack_timeout, self.shio_bid_ack_timeout);
+        config.shio_signer_url = self.shio_signer_url.or(config.shio_signer_url);
+        config.shio_signer_address = self.shio_signer_address.or(config.shio_signer_address);
+        set(&mut config.shio_signer_timeout, self.shio_signer_timeout);
+        set(&mut config.shio_rpc_urls, self.shio_rpc_urls);
+        set(&mut config.shio_rpc_max_retries, self.shio_rpc_max_retries);
+
+        let collector = &mut config.collector;
+        collector.relay_ws_url = self.collector_args.relay_ws_url.or(collector.relay_ws_url.take());
+        set(
+            &mut collector.relay_move_packages,
+            self.collector_args.relay_move_packages,
+        );
+        collector.shio_ws_url = self.collector_args.shio_ws_url.or(collector.shio_ws_url.take());
+        set(
+            &mut collector.shio_backup_ws_urls,
+            self.collector_args.shio_backup_ws_urls,
+        );
+        set(
+            &mut collector.shio_ping_interval,
+            self.collector_args.shio_ping_interval,
+        );
+        set(
+            &mut collector.shio_stall_timeout,
+            self.collector_args.shio_stall_timeout,
+        );
+        set(&mut collector.tx_socket_path, self.collector_args.tx_socket_path);
+
+        let db_sim = &mut config.db_sim;
+        set(&mut db_sim.db_path, self.db_sim_args.db_path);
+        set(&mut db_sim.config_path, self.db_sim_args.config_path);
+        set(&mut db_sim.update_cache_socket, self.db_sim_args.update_cache_socket);
+        set(&mut db_sim.preload_path, self.db_sim_args.preload_path);
+        set(&mut db_sim.catchup_interval, self.db_sim_args.catchup_interval);
+        set(
+            &mut db_sim.store_catchup_interval,
+            self.db_sim_args.store_catchup_interval,
+        );
+
+        let worker = &mut config.worker;
+        set(&mut worker.workers, self.worker_args.workers);
+        set(&mut worker.num_simulators, self.worker_args.num_simulators);
+        set(&mut worker.public_arb_cooldown, self.worker_args.public_arb_cooldown);
+        set(&mut worker.shio_arb_cooldown, self.worker_args.shio_arb_cooldown);
+        set(
+            &mut worker.dedicated_short_interval,
+            self.worker_args.dedicated_short_interval,
+        );
+        set(
+            &mut worker.dedicated_long_interval,
+            self.worker_args.dedicated_long_interval,
+        );
+        set(
+            &mut worker.dedicated_short_updates,
+            self.worker_args.dedicated_short_updates,
+        );
+        set(&mut worker.max_dedicated_lag, self.worker_args.max_dedicated_lag);
+        set(&mut worker.split_gas_coins, self.worker_args.split_gas_coins);
+        set(
+            &mut worker.min_realized_profit_pct,
+            self.worker_args.min_realized_profit_pct,
+        );
+        set(&mut worker.final_check_margin, self.worker_args.final_check_margin);
+        set(&mut worker.max_private_age, self.worker_args.max_private_age);
+        set(&mut worker.warm_up_coins, self.worker_args.warm_up_coins);
+        set(&mut worker.max_cycle_hops, self.worker_args.max_cycle_hops);
+        set(&mut worker.dex_cache_ttl, self.worker_args.dex_cache_ttl);
+        set(&mut worker.disable_protocols, self.worker_args.disable_protocols);
+        set(&mut worker.only_protocols, self.worker_args.only_protocols);
+        set(
+            &mut worker.max_pools_per_protocol,
+            self.worker_args.max_pools_per_protocol,
+        );
+        set(&mut worker.max_simulated_paths, self.worker_args.max_simulated_paths);
+        set(&mut worker.quarantine_aborts, self.worker_args.quarantine_aborts);
+        set(&mut worker.quarantine_cool_off, self.worker_args.quarantine_cool_off);
+        set(
+            &mut worker.min_out_tolerance_bps,
+            self.worker_args.min_out_tolerance_bps,
+        );
+        set(&mut worker.stack_size_mb, self.worker_args.stack_size_mb);
+        set(&mut worker.max_workers, self.worker_args.max_workers);
+        set(&mut worker.scale_up_backlog, self.worker_args.scale_up_backlog);
+        set(&mut worker.scale_up_after, self.worker_args.scale_up_after);
+        set(&mut worker.scale_down_idle, self.worker_args.scale_down_idle);
+
+        let tg = &mut config.telegram;
+        tg.bot_token = self.telegram_args.telegram_bot_token.or(tg.bot_token.take());
+        tg.chat_id = self.telegram_args.telegram_chat_id.or(tg.chat_id.take());
+        tg.error_thread_id = self
+            .telegram_args
+            .telegram_error_thread_id
+            .or(tg.error_thread_id.take());
+        tg.notification_thread_id = self
+            .telegram_args
+            .telegram_notification_thread_id
+            .or(tg.notification_thread_id.take());
+        set(&mut tg.explorer, self.telegram_args.explorer);
+
+        config
+    }
 }
 
 pub async fn run(args: Args) -> Result<()> {
-    utils::set_panic_hook();
-    mev_logger::init_with_whitelisted_modules(
-        "mainnet",
-        "sui-arb".to_string(),
-        &["arb", "utils", "shio", "cache_metrics=debug"],
-    );
-
-    let keypair = SuiKeyPair::decode(&args.private_key)?;
-    let pubkey = keypair.public();
-    let attacker = SuiAddress::from(&pubkey);
-
-    info!(
-        "start_bot with attacker: {}, http_config: {:#?}, collector_config: {:#?}, db_sim_config: {:#?}, worker_config: {:#?}",
-        attacker, args.http_config, args.collector_config, args.db_sim_config, args.worker_config
-    );
-
-    let rpc_url = args.http_config.rpc_url;
-    let db_path = args.db_sim_config.db_path;
-    let tx_socket_path = args.collector_config.tx_socket_path;
-    let config_path = args.db_sim_config.config_path;
-    let update_cache_socket = args.db_sim_config.update_cache_socket;
-    let preload_path = args.db_sim_config.preload_path;
+    let config = args.into_bot_config()?;
+    utils::set_panic_hook(config.telegram.alert_config());
+    // checked by `BotConfig::validate`
+    link::set_explorer(config.telegram.explorer.parse()?);
+    let mut logger_config =
+        LoggerConfig::new("sui-arb-mainnet").with_whitelisted_modules(&["arb", "utils", "shio", "cache_metrics=debug"]);
+    if let (Some(alerter), Some(level)) = (telegram::alerter(), config.telegram.log_alert_level()?) {
+        // only the modules logged, the panics and the heartbeat are alerted by themselves
+        let modules = logger_config.whitelisted_modules.clone().unwrap_or_default();
+        let layer = TelegramLayer::new(alerter, Handle::current())
+            .with_level(level)
+            .with_targets(&modules);
+        logger_config = logger_config.with_alert_layer(layer.filtered());
+    }
+    mev_logger::init_with_config(logger_config);
+
+    // either a `private_key` or a `shio_signer_address`, checked by `BotConfig::validate`
+    let private_keys: Vec<_> = config
+        .private_key
+        .iter()
+        .chain(config.extra_private_keys.iter())
+        .collect();
+    let attackers = match config.shio_signer_address {
+        Some(address) => vec![address],
+        None => private_keys
+            .iter()
+            .map(|key| Ok(SuiAddress::from(&SuiKeyPair::decode(key)?.public())))
+            .collect::<Result<Vec<_>>>()?,
+    };
+
+    info!("start_bot with attackers: {:?}, config: {:#?}", attackers, config);
+
+    if !init_pegged_coin_types(&config.pegged_coin_types) {
+        warn!("pegged coin types already initialized");
+    }
+    if !init_dex_cache(Duration::from_millis(config.worker.dex_cache_ttl)) {
+        warn!("dex cache already initialized");
+    }
+    let quarantine_cool_off = Duration::from_secs(config.worker.quarantine_cool_off);
+    if !init_pool_quarantine(config.worker.quarantine_aborts, quarantine_cool_off) {
+        warn!("pool quarantine already initialized");
+    }
+    if !init_min_out_tolerance(config.worker.min_out_tolerance_bps) {
+        warn!("min out tolerance already initialized");
+    }
+
+    if let Some(port) = config.metrics_port {
+        metrics_server::serve(
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            metrics::metrics().registry().clone(),
+        )
+        .await?;
+    }
+    if let Some(port) = config.admin_port {
+        admin::serve(SocketAddr::from(([127, 0, 0, 1], port))).await?;
+    }
+
+    let rpc_url = config.rpc_url;
+    let db_path = config.db_sim.db_path;
+    let tx_socket_path = config.collector.tx_socket_path;
+    let config_path = config.db_sim.config_path;
+    let update_cache_socket = config.db_sim.update_cache_socket;
+    let preload_path = config.db_sim.preload_path;
     let mut engine = Engine::default();
 
-    if let Some(ref ws_url) = args.collector_config.shio_ws_url {
+    // in dry-run mode, the real executors are replaced by the recorder used by workers
+    let recorder = if config.dry_run {
+        warn!(output = %config.dry_run_output, "dry-run mode, no bid or tx will be submitted");
+        Some(Arc::new(RecordingExecutor::new(&config.dry_run_output)?))
+    } else {
+        None
+    };
+
+    if let Some(ref ws_url) = config.collector.shio_ws_url {
+        let ws_urls = std::iter::once(ws_url.clone())
+            .chain(config.collector.shio_backup_ws_urls.iter().cloned())
+            .collect();
+        let keepalive = Keepalive {
+            ping_interval: Duration::from_millis(config.collector.shio_ping_interval),
+            stall_timeout: Duration::from_millis(config.collector.shio_stall_timeout),
+        };
+        let mut signers = Vec::with_capacity(attackers.len());
+        for (i, attacker) in attackers.iter().enumerate() {
+            let signer: Arc<dyn BidSigner> = match config.shio_signer_url {
+                Some(ref url) => Arc::new(
+                    HttpBidSigner::new(url.clone(), *attacker)
+                        .with_timeout(Duration::from_millis(config.shio_signer_timeout)),
+                ),
+                // without a signing service, the attackers are the addresses of the keys
+                None => Arc::new(KeypairSigner::new(SuiKeyPair::decode(private_keys[i])?)),
+            };
+            signers.push(signer);
+        }
+        let bid_signer: Arc<dyn BidSigner> = Arc::new(SenderBidSigner::new(signers));
         let (shio_collector, shio_executor) =
-            new_shio_collector_and_executor(keypair, Some(ws_url.clone()), None).await;
-        engine.add_collector(map_collector!(shio_collector, Event::Shio));
-
-        if args.shio_use_rpc {
-            let shio_rpc_executor = ShioRPCExecutor::new(SuiKeyPair::decode(&args.private_key)?);
-            engine.add_executor(map_executor!(shio_rpc_executor, Action::ShioSubmitBid));
-        } else {
-            engine.add_executor(map_executor!(shio_executor, Action::ShioSubmitBid));
+            new_shio_collector_and_executor(bid_signer.clone(), ws_urls, None, keepalive).await;
+        report_shio_conn_state(shio_collector.conn_state());
+        report_shio_last_item_age(shio_collector.item_clock(), keepalive.stall_timeout);
+        heartbeat::register_probe("shio", {
+            let (conn_state, item_clock) = (shio_collector.conn_state(), shio_collector.item_clock());
+            Box::new(move || {
+                shio_health(
+                    *conn_state.borrow(),
+                    item_clock.last_item_age(),
+                    keepalive.stall_timeout,
+                )
+            })
+        });
+        engine.add_collector(map_collector!(shio_collector, Event::from));
+
+        if !config.dry_run {
+            if config.shio_use_rpc {
+                let mut shio_rpc_executor = ShioRPCExecutor::new(bid_signer)
+                    .with_max_retries(config.shio_rpc_max_retries)
+                    .with_latency_observer(Arc::new(|endpoint: &str, latency: Duration| {
+                        metrics::metrics()
+                            .shio_rpc_latency
+                            .with_label_values(&[endpoint])
+                            .observe(latency.as_secs_f64())
+                    }));
+                if !config.shio_rpc_urls.is_empty() {
+                    shio_rpc_executor = shio_rpc_executor.with_endpoints(config.shio_rpc_urls.clone());
+                }
+                engine.add_executor(map_executor!(
+                    MeteredExecutor::new(shio_rpc_executor),
+                    Action::ShioSubmitBid
+                ));
+            } else {
+                let shio_executor = shio_executor.with_ack_timeout(Duration::from_millis(config.shio_bid_ack_timeout));
+                report_shio_bid_stats(shio_executor.stats());
+                engine.add_executor(map_executor!(
+                    MeteredExecutor::new(shio_executor),
+                    Action::ShioSubmitBid
+                ));
+            }
         }
     } else {
-        let public_tx_collector = PublicTxCollector::new(&tx_socket_path);
+        let public_tx_collector = PublicTxCollector::new(&tx_socket_path, !config.collector.raw_public_txs);
         engine.add_collector(Box::new(public_tx_collector));
     }
 
-    engine.add_executor(map_executor!(
-        PublicTxExecutor::new(&rpc_url, SuiKeyPair::decode(&args.private_key)?).await?,
-        Action::ExecutePublicTx
-    ));
+    let sui = SuiClientBuilder::default().build(&rpc_url).await?;
+    let mut gas_coins = Vec::with_capacity(attackers.len());
+    for attacker in &attackers {
+        gas_coins.push(Arc::new(GasCoinManager::new(sui.clone(), *attacker).await?));
+    }
+    let senders = Arc::new(SenderPool::new(gas_coins));
 
-    if let Some(ref relay_ws_url) = args.collector_config.relay_ws_url {
-        let private_tx_collector = PrivateTxCollector::new(relay_ws_url);
-        engine.add_collector(Box::new(private_tx_collector));
+    if let (false, Some(private_key)) = (config.dry_run, &config.private_key) {
+        let extra_keypairs = config
+            .extra_private_keys
+            .iter()
+            .map(|key| SuiKeyPair::decode(key))
+            .collect::<Result<Vec<_>>>()?;
+        let mut public_tx_executor = PublicTxExecutor::new(&rpc_url, SuiKeyPair::decode(private_key)?)
+            .await?
+            .with_gas_price_bump(true)
+            .with_extra_keypairs(extra_keypairs)
+            .with_gas_coin_managers(senders.clone());
+        if let Some(alerter) = telegram::alerter() {
+            public_tx_executor = public_tx_executor.with_notifier(alerter);
+        }
+        if config.worker.split_gas_coins > 0 {
+            for gas_coins in senders.iter() {
+                gas_coins
+                    .split(&public_tx_executor, config.worker.split_gas_coins)
+                    .await?;
+            }
+        }
+        engine.add_executor(map_executor!(
+            MeteredExecutor::new(public_tx_executor),
+            Action::ExecutePublicTx
+        ));
+    } else if !config.dry_run {
+        warn!("no `private_key`, only the shio bids are executed");
+    }
+
+    if let Some(ref relay_ws_url) = config.collector.relay_ws_url {
+        let filter = RelayFilter {
+            move_packages: config.collector.relay_move_packages.clone(),
+            senders: vec![],
+        };
+        let relay_collector = RelayCollector::new(relay_ws_url).with_filter(filter);
+        engine.add_collector(Box::new(relay_collector));
     }
 
-    let simulator_pool: ObjectPool<Box<dyn Simulator>> = match args.db_sim_config.use_db_simulator {
+    let warn_override_misses = config.db_sim.warn_override_misses;
+    let db_sim_builder = DBSimulatorBuilder::new(&db_path, &config_path)
+        .with_update_source(update_cache_socket.parse()?)
+        .with_catch_up_interval(Duration::from_secs(config.db_sim.store_catchup_interval))
+        .with_preload_ids_path(&preload_path);
+    if config.db_sim.use_db_simulator {
+        // fail with the missing paths before the simulator pool panics on them
+        db_sim_builder.check_paths()?;
+    }
+
+    let simulator_pool: ObjectPool<Box<dyn Simulator>> = match config.db_sim.use_db_simulator {
         true => {
-            let db_path = db_path.to_string();
-            let config_path = config_path.to_string();
-            let update_cache_socket = update_cache_socket.to_string();
-            let preload_path = preload_path.to_string();
-            ObjectPool::new(args.worker_config.num_simulators, move || {
-                tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let db_sim_builder = db_sim_builder.clone();
+            ObjectPool::new_async(config.worker.num_simulators, move || {
+                let db_sim_builder = db_sim_builder.clone();
+                async move {
                     let start = Instant::now();
                     let simulator = Box::new(
-                        DBSimulator::new_slow(&db_path, &config_path, Some(&update_cache_socket), Some(&preload_path))
-                            .await,
+                        db_sim_builder
+                            .build()
+                            .await
+                            .expect("failed to build DBSimulator")
+                            .with_override_miss_warnings(warn_override_misses),
                     ) as Box<dyn Simulator>;
                     info!(elapsed = ?start.elapsed(), "DBSimulator initialized");
                     simulator
-                })
+                }
             })
         }
         false => {
             warn!("http simulator is deprecated. use only for testing");
 
             let rpc_url = rpc_url.to_string();
-            let ipc_path = args.http_config.ipc_path.clone();
+            let ipc_path = config.ipc_path.clone();
 
-            ObjectPool::new(args.worker_config.num_simulators, move || {
+            ObjectPool::new_async(config.worker.num_simulators, move || {
                 let rpc_url = rpc_url.clone();
                 let ipc_path = ipc_path.clone();
 
-                tokio::runtime::Runtime::new()
-                    .unwrap()
-                    .block_on(async { Box::new(HttpSimulator::new(&rpc_url, &ipc_path).await) as Box<dyn Simulator> })
+                async move { Box::new(HttpSimulator::new(&rpc_url, &ipc_path).await) as Box<dyn Simulator> }
             })
         }
     };
 
     // TODO: when we have relay (tons of un-executed txs), maybe we should use a simulator pool
-    let own_simulator = if args.db_sim_config.use_db_simulator {
-        Arc::new(DBSimulator::new_slow(&db_path, &config_path, Some(&update_cache_socket), Some(&preload_path)).await)
-            as Arc<dyn Simulator>
+    let own_simulator = if config.db_sim.use_db_simulator {
+        let simulator = db_sim_builder
+            .clone()
+            .build()
+            .await?
+            .with_override_miss_warnings(warn_override_misses);
+        if let Some(health) = simulator.update_health() {
+            heartbeat::register_probe("db_sim", {
+                let health = health.clone();
+                Box::new(move || db_sim_health(&health, ::utils::current_time_ms()))
+            });
+            report_update_health(health);
+        }
+        let simulator = Arc::new(simulator);
+        report_top_misses(simulator.clone());
+        simulator as Arc<dyn Simulator>
     } else {
         warn!("http simulator is deprecated. use only for testing");
-        let ipc_path = args.http_config.ipc_path;
+        let ipc_path = config.ipc_path;
         Arc::new(HttpSimulator::new(&rpc_url, &ipc_path).await) as Arc<dyn Simulator>
     };
 
-    let dedicated_simulator = if args.db_sim_config.use_db_simulator {
+    let dedicated_simulator = if config.db_sim.use_db_simulator {
         Some(Arc::new(
-            ReplaySimulator::new_slow(
-                &db_path,
-                &config_path,
-                Duration::from_millis(args.worker_config.dedicated_long_interval),
-                Duration::from_millis(args.worker_config.dedicated_short_interval),
+            ReplaySimulator::from_builder(
+                DBSimulatorBuilder::new(&db_path, &config_path),
+                ReplayIntervals::new(
+                    Duration::from_millis(config.worker.dedicated_long_interval),
+                    Duration::from_millis(config.worker.dedicated_short_interval),
+                )
+                .with_short_updates(config.worker.dedicated_short_updates),
             )
-            .await,
+            .await?
+            .with_override_miss_warnings(warn_override_misses),
         ))
     } else {
         None
     };
 
     info!("simulator_pool initialized: {:?}", simulator_pool);
+    let simulator_pool = Arc::new(simulator_pool);
+    heartbeat::register_probe("simulator_pool", {
+        let simulator_pool = simulator_pool.clone();
+        Box::new(move || {
+            simulator_pool_health(
+                simulator_pool.len(),
+                simulator_pool.replacing(),
+                simulator_pool.failing_replacements(),
+            )
+        })
+    });
 
+    let worker_threads = WorkerThreads {
+        stack_size: config.worker.stack_size_mb * 1024 * 1024,
+        pin_to_cores: config.worker.pin_to_cores,
+        autoscale: (config.worker.max_workers > config.worker.workers).then(|| AutoscaleConfig {
+            min_workers: config.worker.workers,
+            max_workers: config.worker.max_workers,
+            backlog_threshold: config.worker.scale_up_backlog,
+            scale_up_after: Duration::from_secs(config.worker.scale_up_after),
+            scale_down_idle: Duration::from_secs(config.worker.scale_down_idle),
+        }),
+    };
+    // checked by `BotConfig::validate`
+    let protocol_filter = config.worker.protocol_filter()?;
     let arb_strategy = ArbStrategy::new(
-        attacker,
-        Arc::new(simulator_pool),
+        senders,
+        simulator_pool,
         own_simulator,
-        args.worker_config.max_recent_arbs,
+        Duration::from_millis(config.worker.public_arb_cooldown),
+        Duration::from_millis(config.worker.shio_arb_cooldown),
         &rpc_url,
-        args.worker_config.workers,
+        config.worker.workers,
         dedicated_simulator,
+        recorder,
+        config.min_profit,
+        FinalCheck {
+            min_profit_pct: config.worker.min_realized_profit_pct,
+            deadline_margin_ms: config.worker.final_check_margin,
+            max_dedicated_lag: Duration::from_millis(config.worker.max_dedicated_lag),
+            max_private_age: Duration::from_millis(config.worker.max_private_age),
+        },
+        config.worker.warm_up_coins,
+        config.worker.max_cycle_hops,
+        protocol_filter,
+        config.worker.max_pools_per_protocol,
+        config.worker.max_simulated_paths,
+        config.worker.disable_navi,
+        worker_threads,
     )
-    .await;
+    .await
+    // dry runs record the public txs, they are otherwise executed with `private_key`
+    .with_public_txs(config.dry_run || config.private_key.is_some());
+    let worker_shutdown = arb_strategy.worker_shutdown();
     engine.add_strategy(Box::new(arb_strategy));
 
-    engine.add_executor(map_executor!(
-        TelegramMessageDispatcher::new_without_error_report(),
-        Action::NotifyViaTelegram
-    ));
+    if let Some(alerter) = telegram::alerter() {
+        engine.add_executor(map_executor!(alerter, Action::NotifyViaTelegram));
+    }
 
-    heartbeat::start("sui-arb", Duration::from_secs(30));
+    heartbeat::start("sui-arb", Duration::from_secs(30), heartbeat::DEFAULT_ESCALATE_AFTER);
 
-    engine.run_and_join().await.unwrap();
+    let mut engine_tasks = engine.run().await.expect("Burberry engine run failed");
+    tokio::select! {
+        _ = async { while engine_tasks.join_next().await.is_some() {} } => {}
+        signal = shutdown_signal() => {
+            info!(signal, "shutting down, signal again to force exit");
+            tokio::spawn(async {
+                let signal = shutdown_signal().await;
+                warn!(signal, "forced exit");
+                std::process::exit(130);
+            });
+            shutdown(worker_shutdown, engine_tasks, signal).await;
+        }
+    }
 
     Ok(())
 }
+
+// the queued arb_items are handled for this long before the others are dropped
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+// a worker exits once its current arb_item is handled
+const SHUTDOWN_EXIT_TIMEOUT: Duration = Duration::from_secs(30);
+// for the executors to submit the actions of the last arb_items, e.g. their notifications
+const SHUTDOWN_EXECUTOR_GRACE: Duration = Duration::from_secs(2);
+
+// The name of the first SIGINT or SIGTERM received.
+async fn shutdown_signal() -> &'static str {
+    let mut sigterm = signal(SignalKind::terminate()).expect("failed to listen to SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+        _ = sigterm.recv() => "SIGTERM",
+    }
+}
+
+// Stops the workers, then the collectors and executors of the engine, and flushes the dex indexer.
+async fn shutdown(worker_shutdown: WorkerShutdown, mut engine_tasks: JoinSet<()>, signal: &str) {
+    // events are ignored from now on, so the collectors have nothing to feed
+    if !worker_shutdown
+        .shutdown(SHUTDOWN_DRAIN_TIMEOUT, SHUTDOWN_EXIT_TIMEOUT)
+        .await
+    {
+        warn!(
+            "workers still busy after {:?}, stop without them",
+            SHUTDOWN_EXIT_TIMEOUT
+        );
+    }
+
+    tokio::time::sleep(SHUTDOWN_EXECUTOR_GRACE).await;
+    engine_tasks.abort_all();
+    while engine_tasks.join_next().await.is_some() {}
+
+    shutdown_indexer().await;
+
+    if let Some(alerter) = telegram::alerter() {
+        let msg = format!(
+            "🛑 *Bot stopped* by {}\n*Version*: `{}`",
+            escape(signal),
+            escape(BUILD_VERSION)
+        );
+        alerter.send(AlertThread::Notification, &msg).await;
+    }
+    info!("bot stopped");
+}
+
+// Exposes the update socket health of the own db simulator in the metrics, the simulators of the pool
+// connect to the same socket.
+fn report_update_health(health: Arc<UpdateHealth>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+        let mut was_healthy = true;
+        loop {
+            interval.tick().await;
+            let healthy = health.is_healthy();
+            metrics::metrics().db_sim_updates_healthy.set(healthy as i64);
+            if let Some(last_update_ms) = health.last_update_ms() {
+                metrics::metrics().db_sim_last_update_ms.set(last_update_ms as i64);
+            }
+
+            if was_healthy && !healthy {
+                warn!(
+                    reconnects = health.reconnects(),
+                    "db simulator updates are down, objects are stale"
+                );
+            } else if !was_healthy && healthy {
+                info!(reconnects = health.reconnects(), "db simulator updates are back");
+            }
+            was_healthy = healthy;
+        }
+    });
+}
+
+// updates come with every checkpoint, a few per second
+const DB_SIM_MAX_UPDATE_LAG: Duration = Duration::from_secs(10);
+
+fn db_sim_health(health: &UpdateHealth, now_ms: u64) -> HealthStatus {
+    if !health.is_healthy() {
+        return HealthStatus::Unhealthy("update socket disconnected".to_string());
+    }
+    match health.last_update_ms() {
+        Some(last_update_ms) if now_ms.saturating_sub(last_update_ms) > DB_SIM_MAX_UPDATE_LAG.as_millis() as u64 => {
+            HealthStatus::Unhealthy(format!("no update for {}s", (now_ms - last_update_ms) / 1000))
+        }
+        _ => HealthStatus::Healthy,
+    }
+}
+
+// the ejected simulators are replaced in the background, the pool is only unhealthy while a replacement keeps
+// failing or none is left
+fn simulator_pool_health(len: usize, replacing: usize, failing: usize) -> HealthStatus {
+    if failing > 0 {
+        return HealthStatus::Unhealthy(format!("{failing} of {len} simulators fail to initialize"));
+    }
+    if replacing == len {
+        return HealthStatus::Unhealthy("all simulators are being replaced".to_string());
+    }
+    HealthStatus::Healthy
+}
+
+// Logs the objects the own db simulator keeps reading from the store, to keep the preload ids up to date.
+fn report_top_misses(simulator: Arc<DBSimulator>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        // the first tick completes immediately
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            info!("db simulator top misses, {}", simulator.dump_top_misses(50));
+        }
+    });
+}
+
+fn report_shio_conn_state(mut conn_state: watch::Receiver<ConnState>) {
+    tokio::spawn(async move {
+        while conn_state.changed().await.is_ok() {
+            let state = *conn_state.borrow_and_update();
+            match state {
+                ConnState::Connected => info!("shio feed connected"),
+                ConnState::Connecting { attempt: 0 } => {}
+                ConnState::Connecting { attempt } => warn!(attempt, "shio feed reconnecting"),
+                ConnState::Disconnected => warn!("shio feed disconnected, bids fail until it reconnects"),
+                ConnState::GaveUp => error!("shio feed gave up reconnecting"),
+            }
+        }
+    });
+}
+
+fn shio_health(conn_state: ConnState, last_item_age: Option<Duration>, stall_timeout: Duration) -> HealthStatus {
+    if conn_state != ConnState::Connected {
+        return HealthStatus::Unhealthy(format!("{conn_state:?}"));
+    }
+    match last_item_age {
+        Some(age) if age > stall_timeout => HealthStatus::Unhealthy(format!("no item for {}s", age.as_secs())),
+        _ => HealthStatus::Healthy,
+    }
+}
+
+// A connected feed can still go quiet, e.g. when shio stops publishing, which the keepalive doesn't catch.
+fn report_shio_last_item_age(item_clock: ItemClock, stall_timeout: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+        let mut was_quiet = false;
+        loop {
+            interval.tick().await;
+            let Some(age) = item_clock.last_item_age() else {
+                continue;
+            };
+            metrics::metrics().shio_last_item_age_ms.set(age.as_millis() as i64);
+
+            let quiet = age > stall_timeout;
+            if quiet && !was_quiet {
+                warn!(?age, "no shio item received lately");
+            } else if !quiet && was_quiet {
+                info!("shio items are back");
+            }
+            was_quiet = quiet;
+        }
+    });
+}
+
+// the bid counters of the shio executor only live in the shio crate, so they are copied into the metrics
+fn report_shio_bid_stats(stats: Arc<BidStats>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+        loop {
+            interval.tick().await;
+            let shio_bids = &metrics::metrics().shio_bids;
+            for (result, counter) in [
+                ("submitted", &stats.submitted),
+                ("acked", &stats.acked),
+                ("rejected", &stats.rejected),
+                ("timed_out", &stats.timed_out),
+                ("failed", &stats.failed),
+            ] {
+                shio_bids
+                    .with_label_values(&[result])
+                    .set(counter.load(Ordering::Relaxed) as i64);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Args {
+        Args::try_parse_from(["start-bot"].iter().chain(args)).unwrap()
+    }
+
+    #[test]
+    fn test_cli_overrides_file_overrides_default() {
+        let file_config = BotConfig::from_toml(
+            r#"
+            private_key = "file-key"
+            min_profit = 1000
+
+            [worker]
+            workers = 4
+            num_simulators = 16
+            "#,
+        )
+        .unwrap();
+
+        let args = parse_args(&["--private-key", "cli-key", "--workers", "2", "--use-db-simulator"]);
+        let config = args.override_config(file_config);
+
+        // cli
+        assert_eq!(config.private_key.as_deref(), Some("cli-key"));
+        assert_eq!(config.worker.workers, 2);
+        assert!(config.db_sim.use_db_simulator);
+        // file
+        assert_eq!(config.worker.num_simulators, 16);
+        assert_eq!(config.min_profit, 1000);
+        // default
+        assert_eq!(config.worker.public_arb_cooldown, 3000);
+        assert_eq!(config.rpc_url, BotConfig::default().rpc_url);
+        assert!(!config.dry_run);
+    }
+
+    #[test]
+    fn test_cli_turns_off_file_booleans() {
+        let file_config = BotConfig::from_toml(
+            r#"
+            dry_run = true
+            shio_use_rpc = true
+
+            [worker]
+            pin_to_cores = true
+            "#,
+        )
+        .unwrap();
+
+        let args = parse_args(&["--dry-run", "false", "--shio-use-rpc=false", "--disable-navi"]);
+        let config = args.override_config(file_config);
+
+        assert!(!config.dry_run);
+        assert!(!config.shio_use_rpc);
+        assert!(config.worker.disable_navi);
+        // not on the command line
+        assert!(config.worker.pin_to_cores);
+    }
+
+    #[test]
+    fn test_shio_backup_ws_urls() {
+        let args = parse_args(&[
+            "--shio-ws-url",
+            "wss://primary",
+            "--shio-backup-ws-urls",
+            "wss://backup1,wss://backup2",
+        ]);
+        let config = args.override_config(BotConfig::default());
+
+        assert_eq!(config.collector.shio_ws_url.as_deref(), Some("wss://primary"));
+        assert_eq!(
+            config.collector.shio_backup_ws_urls,
+            vec!["wss://backup1", "wss://backup2"]
+        );
+    }
+
+    #[test]
+    fn test_relay_move_packages() {
+        let args = parse_args(&[
+            "--relay-ws-url",
+            "ws://relay:9001",
+            "--relay-move-packages",
+            "0x2,0xdee9",
+        ]);
+        let config = args.override_config(BotConfig::default());
+
+        assert_eq!(config.collector.relay_ws_url.as_deref(), Some("ws://relay:9001"));
+        assert_eq!(
+            config.collector.relay_move_packages,
+            vec![
+                ObjectID::from_hex_literal("0x2").unwrap(),
+                ObjectID::from_hex_literal("0xdee9").unwrap()
+            ]
+        );
+        assert!(BotConfig::default().collector.relay_move_packages.is_empty());
+    }
+
+    #[test]
+    fn test_telegram_args() {
+        let args = parse_args(&["--telegram-bot-token", "123:secret", "--telegram-chat-id", "-100"]);
+        let config = args.override_config(BotConfig::default());
+
+        let alerts = config.telegram.alert_config().unwrap();
+        assert_eq!(alerts.bot_token, "123:secret");
+        assert_eq!(alerts.chat_id, "-100");
+        assert_eq!(alerts.notification_thread_id, None);
+    }
+
+    #[test]
+    fn test_simulator_pool_health() {
+        assert_eq!(simulator_pool_health(4, 0, 0), HealthStatus::Healthy);
+        assert_eq!(simulator_pool_health(4, 1, 0), HealthStatus::Healthy);
+        assert!(matches!(simulator_pool_health(4, 1, 1), HealthStatus::Unhealthy(_)));
+        assert!(matches!(simulator_pool_health(4, 4, 0), HealthStatus::Unhealthy(_)));
+    }
+
+    #[test]
+    fn test_shio_health() {
+        let stall_timeout = Duration::from_secs(15);
+        let healthy = |state, age| shio_health(state, age, stall_timeout) == HealthStatus::Healthy;
+
+        assert!(healthy(ConnState::Connected, None));
+        assert!(healthy(ConnState::Connected, Some(Duration::from_secs(1))));
+        assert!(!healthy(ConnState::Connected, Some(Duration::from_secs(16))));
+        assert!(!healthy(
+            ConnState::Connecting { attempt: 3 },
+            Some(Duration::from_secs(1))
+        ));
+        assert!(!healthy(ConnState::GaveUp, None));
+    }
+}
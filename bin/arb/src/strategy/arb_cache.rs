@@ -1,6 +1,7 @@
 use std::{
-    cmp::Ordering,
+    cmp::{Ordering, Reverse},
     collections::{BinaryHeap, HashMap},
+    fmt,
     time::{Duration, Instant},
 };
 
@@ -38,22 +39,40 @@ pub struct ArbEntry {
     source: Source,
 }
 
+/// Pop order of an ArbItem, the greatest one is popped first:
+/// 1. shio items before others, because they have a deadline to meet.
+/// 2. among shio items, the one with the nearest deadline.
+/// 3. the larger expected profit hint.
+/// 4. the most recently inserted one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Priority {
+    is_shio: bool,
+    deadline: Reverse<u64>,
+    profit_hint: u64,
+    generation: u64,
+}
+
+impl Priority {
+    fn new(source: &Source, profit_hint: Option<u64>, generation: u64) -> Self {
+        Self {
+            is_shio: source.is_shio(),
+            deadline: Reverse(source.deadline().unwrap_or(u64::MAX)),
+            profit_hint: profit_hint.unwrap_or_default(),
+            generation,
+        }
+    }
+}
+
 #[derive(Eq, PartialEq)]
 struct HeapItem {
-    expires_at: Instant,
-    generation: u64,
+    priority: Priority,
     coin: String,
     pool_id: Option<ObjectID>,
 }
 
 impl Ord for HeapItem {
     fn cmp(&self, other: &Self) -> Ordering {
-        // Default BinaryHeap is a max-heap, so we invert ordering:
-        // We want the earliest expiration at the front, so we compare timestamps reversed.
-        self.expires_at
-            .cmp(&other.expires_at)
-            .then(self.generation.cmp(&other.generation))
-            .reverse()
+        self.priority.cmp(&other.priority)
     }
 }
 
@@ -63,12 +82,51 @@ impl PartialOrd for HeapItem {
     }
 }
 
-/// A structure to manage ArbItems with uniqueness, reordering, and timed expiration.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ArbCacheMetrics {
+    pub public_inserted: u64,
+    pub shio_inserted: u64,
+    pub public_popped: u64,
+    pub shio_popped: u64,
+    pub expired: u64,
+    pub shed: u64,
+}
+
+impl ArbCacheMetrics {
+    fn record_insert(&mut self, source: &Source) {
+        if source.is_shio() {
+            self.shio_inserted += 1;
+        } else {
+            self.public_inserted += 1;
+        }
+    }
+
+    fn record_pop(&mut self, source: &Source) {
+        if source.is_shio() {
+            self.shio_popped += 1;
+        } else {
+            self.public_popped += 1;
+        }
+    }
+}
+
+impl fmt::Display for ArbCacheMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "inserted(public={}, shio={}), popped(public={}, shio={}), expired={}, shed={}",
+            self.public_inserted, self.shio_inserted, self.public_popped, self.shio_popped, self.expired, self.shed
+        )
+    }
+}
+
+/// A structure to manage ArbItems with uniqueness, prioritization, and timed expiration.
 pub struct ArbCache {
     map: HashMap<String, ArbEntry>,
     heap: BinaryHeap<HeapItem>,
     generation_counter: u64,
     expiration_duration: Duration,
+    metrics: ArbCacheMetrics,
 }
 
 impl ArbCache {
@@ -78,11 +136,13 @@ impl ArbCache {
             heap: BinaryHeap::new(),
             generation_counter: 0,
             expiration_duration,
+            metrics: ArbCacheMetrics::default(),
         }
     }
 
     /// Insert or update an ArbItem.
     /// If the coin already exists, this updates it with a new generation and expiration time.
+    /// `profit_hint` is a coarse estimation of the opportunity's value, used to prioritize items.
     pub fn insert(
         &mut self,
         coin: String,
@@ -90,15 +150,25 @@ impl ArbCache {
         digest: TransactionDigest,
         sim_ctx: SimulateCtx,
         source: Source,
+        profit_hint: Option<u64>,
     ) {
         let now = Instant::now();
         self.generation_counter += 1;
         let generation = self.generation_counter;
         let expires_at = now + self.expiration_duration;
 
+        self.metrics.record_insert(&source);
+
+        // Insert into the heap
+        self.heap.push(HeapItem {
+            priority: Priority::new(&source, profit_hint, generation),
+            coin: coin.clone(),
+            pool_id,
+        });
+
         // Insert into the map
         self.map.insert(
-            coin.clone(),
+            coin,
             ArbEntry {
                 digest,
                 sim_ctx,
@@ -107,14 +177,6 @@ impl ArbCache {
                 source,
             },
         );
-
-        // Insert into the heap
-        self.heap.push(HeapItem {
-            expires_at,
-            generation,
-            coin,
-            pool_id,
-        });
     }
 
     /// Attempt to get an ArbItem by coin.
@@ -123,62 +185,280 @@ impl ArbCache {
         self.map.get(coin).map(|entry| (entry.digest, entry.sim_ctx.clone()))
     }
 
+    /// Number of live (possibly expired but not yet removed) entries.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Total number of entries dropped because they expired before being popped.
+    pub fn expired_count(&self) -> u64 {
+        self.metrics.expired
+    }
+
+    pub fn metrics(&self) -> ArbCacheMetrics {
+        self.metrics
+    }
+
     /// Periodically call this to remove expired entries.
-    /// This will pop from the heap until it finds an entry that is not stale and not expired.
+    /// Stale heap items are dropped lazily by `pop_one`, and the heap is compacted once it
+    /// grows much larger than the map.
     pub fn remove_expired(&mut self) -> Vec<String> {
-        let mut expired_coins = Vec::new();
         let now = Instant::now();
-        while let Some(top) = self.heap.peek() {
-            // If top is outdated (stale) or expired, pop it and remove from map if needed
-            if let Some(entry) = self.map.get(&top.coin) {
-                if entry.generation != top.generation {
-                    // Stale entry, just discard from heap
-                    self.heap.pop();
-                    continue;
-                }
-                // Matching generation
-                if entry.expires_at <= now {
-                    // It's actually expired
-                    expired_coins.push(top.coin.clone());
-                    self.map.remove(&top.coin);
-                    self.heap.pop();
-                } else {
-                    // The top is not expired and not stale. We can break now.
-                    break;
-                }
+        let mut expired_coins = Vec::new();
+        self.map.retain(|coin, entry| {
+            if entry.expires_at <= now {
+                expired_coins.push(coin.clone());
+                false
             } else {
-                // Coin not in map means stale in heap
-                self.heap.pop();
+                true
             }
+        });
+        self.metrics.expired += expired_coins.len() as u64;
+
+        if self.heap.len() > 2 * self.map.len() + 64 {
+            let map = &self.map;
+            self.heap.retain(
+                |item| matches!(map.get(&item.coin), Some(entry) if entry.generation == item.priority.generation),
+            );
         }
+
         expired_coins
     }
 
+    /// Drop the entries that are no longer worth handling when workers are backlogged: the expired ones,
+    /// the shio ones whose deadline has passed, and then the lowest-priority ones until at most `keep`
+    /// remain. Returns the number of dropped entries.
+    pub fn shed(&mut self, keep: usize) -> usize {
+        self.shed_at(keep, Instant::now(), utils::current_time_ms())
+    }
+
+    fn shed_at(&mut self, keep: usize, now: Instant, now_ms: u64) -> usize {
+        let len_before = self.map.len();
+        self.map.retain(|_, entry| {
+            entry.expires_at > now && entry.source.deadline().map_or(true, |deadline| deadline > now_ms)
+        });
+
+        if self.map.len() > keep {
+            let map = &self.map;
+            let mut current = self
+                .heap
+                .drain()
+                .filter(
+                    |item| matches!(map.get(&item.coin), Some(entry) if entry.generation == item.priority.generation),
+                )
+                .collect::<Vec<_>>();
+            current.sort_unstable_by(|a, b| b.cmp(a));
+
+            for item in current.split_off(keep) {
+                self.map.remove(&item.coin);
+            }
+            self.heap = current.into_iter().collect();
+        }
+
+        let shed = len_before - self.map.len();
+        self.metrics.shed += shed as u64;
+        shed
+    }
+
     pub fn pop_one(&mut self) -> Option<ArbItem> {
         let now = Instant::now();
         // Keep popping until we find a valid, current entry that's not expired.
         while let Some(top) = self.heap.pop() {
-            if let Some(entry) = self.map.get(&top.coin) {
-                if entry.generation == top.generation {
-                    // It's the current entry for this coin
-                    if entry.expires_at > now {
-                        // It's valid and not expired. We can remove it and return.
-                        let entry = self.map.remove(&top.coin).unwrap();
-                        return Some(ArbItem::new(top.coin, top.pool_id, entry));
-                    } else {
-                        // It's current but expired, remove it from map and continue.
-                        self.map.remove(&top.coin);
-                    }
-                } else {
-                    // Stale entry, just continue without touching the map.
-                    // Because a newer entry for this coin exists.
-                }
-            } else {
+            let Some(entry) = self.map.get(&top.coin) else {
                 // The map no longer has this coin, meaning it's stale.
                 continue;
+            };
+
+            if entry.generation != top.priority.generation {
+                // Stale entry, just continue without touching the map.
+                // Because a newer entry for this coin exists.
+                continue;
             }
+
+            // It's the current entry for this coin
+            let entry = self.map.remove(&top.coin).unwrap();
+            if entry.expires_at > now {
+                // It's valid and not expired.
+                self.metrics.record_pop(&entry.source);
+                return Some(ArbItem::new(top.coin, top.pool_id, entry));
+            }
+
+            // It's current but expired, drop it and continue.
+            self.metrics.expired += 1;
         }
         // No valid entries were found
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shio_source(deadline: u64) -> Source {
+        Source::Shio {
+            opp_tx_digest: TransactionDigest::random(),
+            bid_amount: 0,
+            start: 0,
+            arb_found: 0,
+            deadline,
+        }
+    }
+
+    #[test]
+    fn test_shio_with_imminent_deadline_popped_first() {
+        let mut cache = ArbCache::new(Duration::from_secs(5));
+        let digest = TransactionDigest::random();
+
+        cache.insert(
+            "0x1::a::A".to_string(),
+            None,
+            digest,
+            SimulateCtx::default(),
+            Source::Public,
+            None,
+        );
+        cache.insert(
+            "0x1::b::B".to_string(),
+            None,
+            digest,
+            SimulateCtx::default(),
+            Source::Public,
+            Some(100),
+        );
+        cache.insert(
+            "0x1::c::C".to_string(),
+            None,
+            digest,
+            SimulateCtx::default(),
+            shio_source(2_000),
+            None,
+        );
+        cache.insert(
+            "0x1::d::D".to_string(),
+            None,
+            digest,
+            SimulateCtx::default(),
+            shio_source(1_000),
+            None,
+        );
+        assert_eq!(cache.len(), 4);
+
+        let popped = std::iter::from_fn(|| cache.pop_one())
+            .map(|item| item.coin)
+            .collect::<Vec<_>>();
+        assert_eq!(popped, vec!["0x1::d::D", "0x1::c::C", "0x1::b::B", "0x1::a::A"]);
+
+        let metrics = cache.metrics();
+        assert_eq!(metrics.shio_popped, 2);
+        assert_eq!(metrics.public_popped, 2);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_reinsert_replaces_entry() {
+        let mut cache = ArbCache::new(Duration::from_secs(5));
+        let digest = TransactionDigest::random();
+
+        cache.insert(
+            "0x1::a::A".to_string(),
+            None,
+            digest,
+            SimulateCtx::default(),
+            Source::Public,
+            None,
+        );
+        cache.insert(
+            "0x1::a::A".to_string(),
+            None,
+            digest,
+            SimulateCtx::default(),
+            shio_source(1_000),
+            None,
+        );
+        assert_eq!(cache.len(), 1);
+
+        let item = cache.pop_one().unwrap();
+        assert!(item.source.is_shio());
+        assert!(cache.pop_one().is_none());
+    }
+
+    #[test]
+    fn test_shed_keeps_reachable_shio_items() {
+        let mut cache = ArbCache::new(Duration::from_secs(5));
+        let digest = TransactionDigest::random();
+        let now_ms = 10_000;
+
+        for coin in ["0x1::a::A", "0x1::b::B", "0x1::c::C"] {
+            cache.insert(
+                coin.to_string(),
+                None,
+                digest,
+                SimulateCtx::default(),
+                Source::Public,
+                Some(100),
+            );
+        }
+        // the deadline has passed, shed even though shio items are popped first
+        cache.insert(
+            "0x1::d::D".to_string(),
+            None,
+            digest,
+            SimulateCtx::default(),
+            shio_source(now_ms - 1),
+            None,
+        );
+        cache.insert(
+            "0x1::e::E".to_string(),
+            None,
+            digest,
+            SimulateCtx::default(),
+            shio_source(now_ms + 50),
+            None,
+        );
+
+        assert_eq!(cache.shed_at(2, Instant::now(), now_ms), 3);
+        assert_eq!(cache.metrics().shed, 3);
+        assert_eq!(cache.len(), 2);
+
+        let popped = std::iter::from_fn(|| cache.pop_one())
+            .map(|item| item.coin)
+            .collect::<Vec<_>>();
+        assert_eq!(popped, vec!["0x1::e::E", "0x1::c::C"]);
+    }
+
+    #[test]
+    fn test_remove_expired() {
+        let mut cache = ArbCache::new(Duration::ZERO);
+        let digest = TransactionDigest::random();
+
+        cache.insert(
+            "0x1::a::A".to_string(),
+            None,
+            digest,
+            SimulateCtx::default(),
+            Source::Public,
+            None,
+        );
+        cache.insert(
+            "0x1::b::B".to_string(),
+            None,
+            digest,
+            SimulateCtx::default(),
+            shio_source(1_000),
+            None,
+        );
+
+        let mut expired = cache.remove_expired();
+        expired.sort();
+        assert_eq!(expired, vec!["0x1::a::A", "0x1::b::B"]);
+        assert_eq!(cache.expired_count(), 2);
+        assert_eq!(cache.len(), 0);
+        assert!(cache.pop_one().is_none());
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use sui_types::digests::TransactionDigest;
+
+use crate::types::Source;
+
+type OppKey = (TransactionDigest, String);
+
+/// Remembers recently seen `(tx_digest, coin)` opportunities so that the same transaction
+/// arriving from both the public tx stream and the shio feed is only handled once.
+///
+/// A shio opportunity is preferred over a public one, because it carries the override objects
+/// and the gas price of the opportunity transaction.
+pub struct OpportunityDedup {
+    seen: HashMap<OppKey, (Instant, bool)>,
+    order: VecDeque<(Instant, OppKey)>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl OpportunityDedup {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            ttl,
+            capacity,
+        }
+    }
+
+    /// Returns true if the opportunity should be inserted into the ArbCache, and records it.
+    pub fn check_and_record(&mut self, tx_digest: TransactionDigest, coin: &str, source: &Source) -> bool {
+        let now = Instant::now();
+        self.evict(now);
+
+        let is_shio = source.is_shio();
+        let key = (tx_digest, coin.to_string());
+        match self.seen.get_mut(&key) {
+            // the shio variant wins over the public one
+            Some((seen_at, seen_shio)) if !*seen_shio && is_shio => {
+                *seen_at = now;
+                *seen_shio = true;
+            }
+            Some(_) => return false,
+            None => {
+                self.seen.insert(key.clone(), (now, is_shio));
+            }
+        }
+
+        self.order.push_back((now, key));
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    fn evict(&mut self, now: Instant) {
+        while let Some((inserted_at, key)) = self.order.front() {
+            if now.duration_since(*inserted_at) < self.ttl && self.seen.len() <= self.capacity {
+                break;
+            }
+
+            // only remove the map entry if it was not refreshed afterwards
+            if matches!(self.seen.get(key), Some((seen_at, _)) if seen_at == inserted_at) {
+                self.seen.remove(key);
+            }
+            self.order.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COIN: &str = "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN";
+
+    #[test]
+    fn test_entries_expire() {
+        let mut dedup = OpportunityDedup::new(Duration::ZERO, 100);
+        let digest = TransactionDigest::random();
+
+        assert!(dedup.check_and_record(digest, COIN, &Source::Public));
+        assert!(dedup.check_and_record(digest, COIN, &Source::Public));
+        assert_eq!(dedup.len(), 1);
+    }
+}
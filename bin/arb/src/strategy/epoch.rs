@@ -0,0 +1,203 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use eyre::Result;
+use simulator::{SimEpoch, EPOCH_STALE_MARGIN_MS};
+use sui_sdk::SuiClient;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::common::get_latest_epoch;
+
+// the reference gas price can change within an epoch
+const GAS_PRICE_REFRESH_INTERVAL: Duration = Duration::from_secs(600);
+// after a failed refresh, or until the next epoch has started
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where the latest epoch is read from, the governance api of a `SuiClient` in production.
+#[async_trait]
+pub(super) trait EpochSource: Send + Sync {
+    async fn latest_epoch(&self) -> Result<SimEpoch>;
+}
+
+#[async_trait]
+impl EpochSource for SuiClient {
+    async fn latest_epoch(&self) -> Result<SimEpoch> {
+        get_latest_epoch(self).await
+    }
+}
+
+/// Keeps the epoch of an `ArbStrategy` up to date in the background, so that the event handlers read it
+/// without a round trip. The refresh starts `EPOCH_STALE_MARGIN_MS` before the predicted end of the epoch,
+/// and is retried until the next epoch is returned.
+pub(super) struct EpochRefresher {
+    source: Arc<dyn EpochSource>,
+    sender: watch::Sender<SimEpoch>,
+    gas_price_interval: Duration,
+    retry_interval: Duration,
+}
+
+impl EpochRefresher {
+    pub fn new(source: Arc<dyn EpochSource>, epoch: SimEpoch) -> (Self, watch::Receiver<SimEpoch>) {
+        let (sender, receiver) = watch::channel(epoch);
+        let refresher = Self {
+            source,
+            sender,
+            gas_price_interval: GAS_PRICE_REFRESH_INTERVAL,
+            retry_interval: RETRY_INTERVAL,
+        };
+        (refresher, receiver)
+    }
+
+    /// Runs until every receiver is dropped.
+    pub fn spawn(self) {
+        tokio::spawn(self.run());
+    }
+
+    async fn run(self) {
+        while !self.sender.is_closed() {
+            let epoch = *self.sender.borrow();
+            tokio::time::sleep(self.next_refresh_in(&epoch, utils::current_time_ms())).await;
+
+            match self.source.latest_epoch().await {
+                Ok(latest) => self.publish(latest),
+                Err(error) => {
+                    warn!(?error, "failed to refresh epoch");
+                    tokio::time::sleep(self.retry_interval).await;
+                }
+            }
+        }
+    }
+
+    fn next_refresh_in(&self, epoch: &SimEpoch, now_ms: u64) -> Duration {
+        if epoch.is_stale_at(now_ms) {
+            return self.retry_interval;
+        }
+        let stale_in = Duration::from_millis(epoch.end_timestamp_ms() - EPOCH_STALE_MARGIN_MS - now_ms);
+        stale_in.min(self.gas_price_interval)
+    }
+
+    fn publish(&self, latest: SimEpoch) {
+        self.sender.send_if_modified(|epoch| {
+            if latest.epoch_id == epoch.epoch_id && latest.gas_price == epoch.gas_price {
+                return false;
+            }
+            info!(
+                epoch = latest.epoch_id,
+                gas_price = latest.gas_price,
+                previous_gas_price = epoch.gas_price,
+                "epoch refreshed"
+            );
+            *epoch = latest;
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    use super::*;
+
+    // the governance api, returns the epochs of `next` in turn and then the last one
+    struct MockEpochSource {
+        next: Mutex<Vec<SimEpoch>>,
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl EpochSource for MockEpochSource {
+        async fn latest_epoch(&self) -> Result<SimEpoch> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            let mut next = self.next.lock().unwrap();
+            if next.len() > 1 {
+                Ok(next.remove(0))
+            } else {
+                Ok(next[0])
+            }
+        }
+    }
+
+    // an epoch that becomes stale in `stale_in_ms`
+    fn epoch(epoch_id: u64, gas_price: u64, stale_in_ms: u64) -> SimEpoch {
+        let duration_ms = 86_400_000;
+        SimEpoch {
+            epoch_id,
+            epoch_start_timestamp: utils::current_time_ms() + stale_in_ms + EPOCH_STALE_MARGIN_MS - duration_ms,
+            epoch_duration_ms: duration_ms,
+            gas_price,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_across_epoch_boundary() {
+        let current = epoch(500, 750, 200);
+        // the epoch is still ongoing for a refresh within the margin
+        let source = Arc::new(MockEpochSource {
+            next: Mutex::new(vec![current, current, epoch(501, 800, 86_000_000)]),
+            calls: AtomicUsize::new(0),
+        });
+        let (mut refresher, mut receiver) = EpochRefresher::new(source.clone(), current);
+        refresher.retry_interval = Duration::from_millis(10);
+        refresher.spawn();
+
+        // read by the handlers without a fetch
+        assert_eq!(receiver.borrow().epoch_id, 500);
+        assert_eq!(source.calls.load(Ordering::Relaxed), 0);
+
+        tokio::time::timeout(Duration::from_secs(5), receiver.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        let latest = *receiver.borrow_and_update();
+        assert_eq!((latest.epoch_id, latest.gas_price), (501, 800));
+        assert_eq!(source.calls.load(Ordering::Relaxed), 3);
+
+        // the next refresh is far away
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(source.calls.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_gas_price_within_epoch() {
+        let current = epoch(500, 750, 86_000_000);
+        let source = Arc::new(MockEpochSource {
+            next: Mutex::new(vec![epoch(500, 780, 86_000_000)]),
+            calls: AtomicUsize::new(0),
+        });
+        let (mut refresher, mut receiver) = EpochRefresher::new(source.clone(), current);
+        refresher.gas_price_interval = Duration::from_millis(50);
+        refresher.spawn();
+
+        tokio::time::timeout(Duration::from_secs(5), receiver.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(receiver.borrow().gas_price, 780);
+    }
+
+    #[test]
+    fn test_next_refresh_in() {
+        let (refresher, _receiver) = EpochRefresher::new(
+            Arc::new(MockEpochSource {
+                next: Mutex::new(vec![]),
+                calls: AtomicUsize::new(0),
+            }),
+            SimEpoch::default(),
+        );
+        let epoch = SimEpoch {
+            epoch_start_timestamp: 1_000_000,
+            epoch_duration_ms: 1_000_000,
+            ..Default::default()
+        };
+
+        assert_eq!(refresher.next_refresh_in(&epoch, 1_000_000), GAS_PRICE_REFRESH_INTERVAL);
+        let now_ms = 2_000_000 - EPOCH_STALE_MARGIN_MS - 5_000;
+        assert_eq!(refresher.next_refresh_in(&epoch, now_ms), Duration::from_secs(5));
+        assert_eq!(refresher.next_refresh_in(&epoch, 2_000_000), RETRY_INTERVAL);
+    }
+}
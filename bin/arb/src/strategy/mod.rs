@@ -1,27 +1,41 @@
 mod arb_cache;
+mod dedup;
+mod epoch;
+mod recent_arbs;
+mod scaler;
+mod shutdown;
 mod worker;
 
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::HashSet,
     str::FromStr,
-    sync::Arc,
-    time::Duration,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
-use arb_cache::{ArbCache, ArbItem};
-use async_channel::Sender;
+use arb_cache::ArbCache;
+use async_channel::{Receiver, Sender};
 use burberry::ActionSubmitter;
+use dedup::OpportunityDedup;
 use dex_indexer::types::Protocol;
-use eyre::{ensure, eyre, Result};
+use epoch::EpochRefresher;
+use eyre::{ensure, eyre, OptionExt, Result};
 use fastcrypto::encoding::{Base64, Encoding};
 use object_pool::ObjectPool;
 use rayon::prelude::*;
+use recent_arbs::{RecentArbs, ShioBids};
+pub use scaler::AutoscaleConfig;
+use scaler::{ScaleAction, WorkerScaler};
 use shio::{ShioItem, ShioObject};
+pub use shutdown::WorkerShutdown;
 use simulator::{ReplaySimulator, SimEpoch, SimulateCtx, Simulator};
 use sui_json_rpc_types::{SuiEvent, SuiTransactionBlockEffects, SuiTransactionBlockEffectsAPI};
 use sui_sdk::{SuiClient, SuiClientBuilder};
 use sui_types::{
-    base_types::{MoveObjectType, ObjectID, SuiAddress},
+    base_types::{MoveObjectType, ObjectID},
     committee::ProtocolVersion,
     digests::TransactionDigest,
     object::{MoveObject, Object, Owner, OBJECT_START_VERSION},
@@ -30,85 +44,352 @@ use sui_types::{
 };
 use tokio::{
     runtime::{Builder, Handle, RuntimeFlavor},
+    sync::watch,
     task::JoinSet,
 };
 use tracing::{debug, error, info, instrument, warn};
-use worker::Worker;
+use utils::heartbeat::{self, HealthStatus};
+pub use worker::FinalCheck;
+use worker::{Worker, WorkerMessage};
 
 use crate::{
     arb::Arb,
     common::get_latest_epoch,
+    defi::{dex_cache, record_swap_event, DexCache, ProtocolFilter},
+    executor::RecordingExecutor,
+    gas_coin::SenderPool,
+    metrics::metrics,
     types::{Action, Event, Source},
 };
 
+const METRICS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+// workers are considered backlogged once this many arb_items are waiting in the channel
+const ARB_ITEM_CHANNEL_HIGH_WATER_MARK: usize = 10;
+// the number of the best entries kept in the arb_cache while workers are backlogged
+const BACKLOGGED_ARB_CACHE_SIZE: usize = 10;
+
 pub struct ArbStrategy {
-    sender: SuiAddress,
-    arb_item_sender: Option<Sender<ArbItem>>,
+    // the attackers the workers take turns sending from
+    senders: Arc<SenderPool>,
+    arb_item_sender: Option<Sender<WorkerMessage>>,
     arb_cache: ArbCache,
+    opp_dedup: OpportunityDedup,
+    last_metrics_log: Instant,
+    deadline_exceeded: Arc<AtomicU64>,
+    profit_regressed: Arc<AtomicU64>,
 
-    recent_arbs: VecDeque<String>,
-    max_recent_arbs: usize,
+    recent_arbs: RecentArbs,
+    // the bids of the workers, to tell a won auction from a lost one
+    shio_bids: Arc<ShioBids>,
+    // settled shio auctions that we took part in
+    shio_won: u64,
+    shio_lost: u64,
 
     simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
     own_simulator: Arc<dyn Simulator>, // only for execution of pending txs
     rpc_url: String,
     workers: usize,
-    sui: SuiClient,
-    epoch: Option<SimEpoch>,
+    // published by the `EpochRefresher`, which is spawned in `sync_state`
+    epoch: watch::Receiver<SimEpoch>,
+    epoch_refresher: Option<EpochRefresher>,
     dedicated_simulator: Option<Arc<ReplaySimulator>>,
+    recorder: Option<Arc<RecordingExecutor>>, // dry-run mode if set
+    min_profit: u64,
+    final_check: FinalCheck,
+    warm_up_coins: Arc<Vec<String>>,
+    max_cycle_hops: usize,
+    protocol_filter: ProtocolFilter,
+    max_pools_per_protocol: usize,
+    max_simulated_paths: usize,
+    disable_navi: bool,
+    // see `with_public_txs`
+    public_txs: bool,
+    // shared with the dex searchers of the workers
+    dex_cache: Arc<DexCache>,
+
+    worker_threads: WorkerThreads,
+    scaler: Option<WorkerScaler>,
+    // set by `sync_state`, to spawn more workers later
+    arb_item_receiver: Option<Receiver<WorkerMessage>>,
+    submitter: Option<Arc<dyn ActionSubmitter<Action>>>,
+    next_worker_id: usize,
+    live_workers: Arc<AtomicUsize>,
+    busy_workers: Arc<AtomicUsize>,
+    shutdown: WorkerShutdown,
+}
+
+/// How the worker threads are spawned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerThreads {
+    /// in bytes
+    pub stack_size: usize,
+    /// pin each worker thread to a core, round-robin by worker id
+    pub pin_to_cores: bool,
+    /// spawn and retire workers with the backlog of the arb_item channel
+    pub autoscale: Option<AutoscaleConfig>,
+}
+
+impl Default for WorkerThreads {
+    fn default() -> Self {
+        Self {
+            stack_size: 128 * 1024 * 1024, // 128 MB
+            pin_to_cores: false,
+            autoscale: None,
+        }
+    }
 }
 
 impl ArbStrategy {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
-        attacker: SuiAddress,
+        senders: Arc<SenderPool>,
         simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
         own_simulator: Arc<dyn Simulator>,
-        recent_arbs: usize,
+        public_arb_cooldown: Duration,
+        shio_arb_cooldown: Duration,
         rpc_url: &str,
         workers: usize,
         dedicated_simulator: Option<Arc<ReplaySimulator>>,
+        recorder: Option<Arc<RecordingExecutor>>,
+        min_profit: u64,
+        final_check: FinalCheck,
+        warm_up_coins: Vec<String>,
+        max_cycle_hops: usize,
+        protocol_filter: ProtocolFilter,
+        max_pools_per_protocol: usize,
+        max_simulated_paths: usize,
+        disable_navi: bool,
+        worker_threads: WorkerThreads,
     ) -> Self {
         let sui = SuiClientBuilder::default().build(&rpc_url).await.unwrap();
         let epoch = get_latest_epoch(&sui).await.unwrap();
+        let (epoch_refresher, epoch) = EpochRefresher::new(Arc::new(sui), epoch);
+        let live_workers = Arc::new(AtomicUsize::new(0));
 
         Self {
-            sender: attacker,
+            senders,
             arb_item_sender: None,
             arb_cache: ArbCache::new(Duration::from_secs(5)),
-            recent_arbs: VecDeque::with_capacity(recent_arbs),
-            max_recent_arbs: recent_arbs,
+            opp_dedup: OpportunityDedup::new(Duration::from_secs(10), 10_000),
+            last_metrics_log: Instant::now(),
+            deadline_exceeded: Arc::new(AtomicU64::new(0)),
+            profit_regressed: Arc::new(AtomicU64::new(0)),
+            recent_arbs: RecentArbs::new(public_arb_cooldown, shio_arb_cooldown),
+            shio_bids: Arc::new(ShioBids::default()),
+            shio_won: 0,
+            shio_lost: 0,
             simulator_pool,
             own_simulator,
             rpc_url: rpc_url.to_string(),
             workers,
-            sui,
-            epoch: Some(epoch),
+            epoch,
+            epoch_refresher: Some(epoch_refresher),
             dedicated_simulator,
+            recorder,
+            min_profit,
+            final_check,
+            warm_up_coins: Arc::new(warm_up_coins),
+            max_cycle_hops,
+            protocol_filter,
+            max_pools_per_protocol,
+            max_simulated_paths,
+            disable_navi,
+            public_txs: true,
+            dex_cache: dex_cache(),
+            worker_threads,
+            scaler: worker_threads.autoscale.map(WorkerScaler::new),
+            arb_item_receiver: None,
+            submitter: None,
+            next_worker_id: 0,
+            live_workers: live_workers.clone(),
+            busy_workers: Arc::new(AtomicUsize::new(0)),
+            shutdown: WorkerShutdown::new(live_workers),
+        }
+    }
+
+    /// Whether the public txs are executed. Without a public tx executor, the lost shio auctions are not retried
+    /// as public txs, and the workers drop the opportunities of the other sources.
+    pub fn with_public_txs(mut self, public_txs: bool) -> Self {
+        self.public_txs = public_txs;
+        self
+    }
+
+    /// Stops the workers once the strategy is moved into the engine.
+    pub fn worker_shutdown(&self) -> WorkerShutdown {
+        self.shutdown.clone()
+    }
+
+    // Spawn a worker thread, `init_tx` is notified once the worker is ready to receive arb_items.
+    fn spawn_worker(&mut self, init_tx: Option<tokio::sync::mpsc::Sender<()>>) {
+        let id = self.next_worker_id;
+        self.next_worker_id += 1;
+        debug!(worker.id = id, "spawning worker...");
+
+        let senders = self.senders.clone();
+        let arb_item_receiver = self.arb_item_receiver.clone().expect("spawn worker before sync_state");
+        let submitter = self.submitter.clone().expect("spawn worker before sync_state");
+
+        let rpc_url = self.rpc_url.clone();
+        let simulator_pool_arb = self.simulator_pool.clone();
+        let simulator_pool_worker = self.simulator_pool.clone();
+        let simulator_name = simulator_pool_arb.get().name().to_string();
+        let dedicated_simulator = self.dedicated_simulator.clone();
+        let deadline_exceeded = self.deadline_exceeded.clone();
+        let recorder = self.recorder.clone();
+        let dry_run = recorder.is_some();
+        let min_profit = self.min_profit;
+        let final_check = self.final_check;
+        let profit_regressed = self.profit_regressed.clone();
+        let shio_bids = self.shio_bids.clone();
+        let warm_up_coins = self.warm_up_coins.clone();
+        let max_cycle_hops = self.max_cycle_hops;
+        let protocol_filter = self.protocol_filter.clone();
+        let max_pools_per_protocol = self.max_pools_per_protocol;
+        let max_simulated_paths = self.max_simulated_paths;
+        let disable_navi = self.disable_navi;
+        let public_txs = self.public_txs;
+        let pin_to_cores = self.worker_threads.pin_to_cores;
+        let busy_workers = self.busy_workers.clone();
+        let live_workers = self.live_workers.clone();
+        live_workers.fetch_add(1, Ordering::Relaxed);
+
+        let spawned = std::thread::Builder::new()
+            .stack_size(self.worker_threads.stack_size)
+            .name(format!("worker-{id}"))
+            .spawn(move || {
+                // the worker is live until its thread exits, whether it retires, fails or panics
+                let _live = LiveWorker(live_workers);
+                if pin_to_cores {
+                    pin_to_core(id);
+                }
+
+                let arb = run_in_tokio!({
+                    Arb::new_with_protocol_filter(&rpc_url, simulator_pool_arb, dry_run, protocol_filter)
+                });
+                let arb = match arb {
+                    Ok(arb) => arb,
+                    Err(error) => {
+                        error!(worker.id = id, ?error, "Worker initialization failed");
+                        return;
+                    }
+                };
+                let arb = Arc::new(
+                    arb.with_max_cycle_hops(max_cycle_hops)
+                        .with_max_pools_per_protocol(max_pools_per_protocol)
+                        .with_max_simulated_paths(max_simulated_paths)
+                        .with_navi(!disable_navi),
+                );
+                // build the lazy caches now, otherwise the first opportunity times out
+                let arb_to_warm_up = arb.clone();
+                run_in_tokio!(arb_to_warm_up.warm_up(&warm_up_coins));
+
+                // Signal that this worker is initialized
+                if let Some(init_tx) = init_tx {
+                    run_in_tokio!(init_tx.send(())).unwrap();
+                }
+
+                let worker = Worker {
+                    id,
+                    senders,
+                    arb_item_receiver,
+                    busy_workers,
+                    simulator_pool: simulator_pool_worker,
+                    simulator_name,
+                    submitter,
+                    arb,
+                    dedicated_simulator,
+                    deadline_exceeded,
+                    recorder,
+                    min_profit,
+                    final_check,
+                    profit_regressed,
+                    shio_bids,
+                    public_txs,
+                };
+                if let Err(error) = worker.run() {
+                    error!(worker.id = id, ?error, "Worker failed");
+                }
+            });
+        if let Err(error) = spawned {
+            self.live_workers.fetch_sub(1, Ordering::Relaxed);
+            error!(worker.id = id, ?error, "Spawn worker thread failed");
+        }
+    }
+
+    fn autoscale(&mut self) {
+        let Some(scaler) = &mut self.scaler else {
+            return;
+        };
+
+        let backlog = self.arb_item_sender.as_ref().unwrap().len();
+        let workers = self.live_workers.load(Ordering::Relaxed);
+        let idle_workers = workers.saturating_sub(self.busy_workers.load(Ordering::Relaxed));
+        let action = scaler.observe(backlog, workers, idle_workers, Instant::now());
+        self.scale(action);
+    }
+
+    fn scale(&mut self, action: ScaleAction) {
+        match action {
+            ScaleAction::Up => {
+                info!(
+                    workers = self.live_workers.load(Ordering::Relaxed),
+                    "arb_item channel backlogged, spawn a worker"
+                );
+                self.spawn_worker(None);
+            }
+            ScaleAction::Down => {
+                info!(
+                    workers = self.live_workers.load(Ordering::Relaxed),
+                    "workers idle, retire a worker"
+                );
+                // whichever idle worker receives it exits, the channel is unbounded so this never blocks
+                let _ = self.arb_item_sender.as_ref().unwrap().try_send(WorkerMessage::Shutdown);
+            }
+            ScaleAction::Hold => {}
         }
     }
 
     #[instrument(name = "on-new-tx", skip_all, fields(tx = %tx.digest()))]
-    async fn on_new_tx(&self, tx: TransactionData) -> Result<()> {
-        // 1. simulate
-        // 2. parse tx_effects/simulate_result
-        // 3. enqueue arb_item
+    async fn on_new_tx(&mut self, tx: TransactionData, received_at_ms: u64) -> Result<()> {
+        let tx_digest = tx.digest();
+        let epoch = self.latest_epoch();
+
+        let (coin_pools, override_objects) = simulate_private_tx(self.own_simulator.clone(), tx, epoch).await?;
+        if coin_pools.is_empty() {
+            return Ok(());
+        }
+
+        // the private tx is not on chain yet, so arbs are simulated on top of the objects it mutated.
+        let sim_ctx = SimulateCtx::new(epoch, override_objects);
+        let source = Source::Private {
+            opp_tx_digest: tx_digest,
+            received_at_ms,
+        };
+
+        for (coin, pool_id) in coin_pools {
+            self.insert_opportunity(coin, pool_id, tx_digest, &sim_ctx, source);
+        }
+
         Ok(())
     }
 
     #[instrument(name = "on-new-tx-effects", skip_all, fields(tx = %tx_effects.transaction_digest()))]
     async fn on_new_tx_effects(&mut self, tx_effects: SuiTransactionBlockEffects, events: Vec<SuiEvent>) -> Result<()> {
-        let coin_pools = self.parse_involved_coin_pools(events).await;
+        let coin_pools = parse_involved_coin_pools(events, self.own_simulator.clone()).await;
+        // the swapped pools have changed on chain, their cached dexes are stale
+        for pool_id in coin_pools.iter().filter_map(|(_, pool_id)| *pool_id) {
+            self.dex_cache.invalidate_pool(pool_id);
+        }
         if coin_pools.is_empty() {
             return Ok(());
         }
 
         let tx_digest = tx_effects.transaction_digest();
-        let epoch = self.get_latest_epoch().await?;
+        let epoch = self.latest_epoch();
         let sim_ctx = SimulateCtx::new(epoch, vec![]);
 
         for (coin, pool_id) in coin_pools {
-            self.arb_cache
-                .insert(coin, pool_id, *tx_digest, sim_ctx.clone(), Source::Public);
+            self.insert_opportunity(coin, pool_id, *tx_digest, &sim_ctx, Source::Public);
         }
 
         Ok(())
@@ -120,9 +401,13 @@ impl ArbStrategy {
             Some(potential_opportunity) => potential_opportunity,
             None => return Ok(()),
         };
+        // the arbs are simulated after the swaps of the opportunity tx, the cached dexes of its pools predate them
+        for pool_id in coin_pools.iter().filter_map(|(_, pool_id)| *pool_id) {
+            self.dex_cache.invalidate_pool(pool_id);
+        }
 
         let tx_digest = TransactionDigest::from_str(shio_item.tx_digest()).map_err(|e| eyre!(e))?;
-        let epoch = self.get_latest_epoch().await?;
+        let epoch = self.latest_epoch();
         let mut sim_ctx = SimulateCtx::new(epoch, override_objects);
         // A bid must has the exact gas_price as the opportunity transaction's.
         sim_ctx.with_gas_price(shio_item.gas_price());
@@ -137,35 +422,67 @@ impl ArbStrategy {
         };
 
         for (coin, pool_id) in coin_pools {
-            self.arb_cache.insert(coin, pool_id, tx_digest, sim_ctx.clone(), source);
+            self.insert_opportunity(coin, pool_id, tx_digest, &sim_ctx, source);
         }
 
         Ok(())
     }
 
-    async fn parse_involved_coin_pools(&self, events: Vec<SuiEvent>) -> HashSet<(String, Option<ObjectID>)> {
-        let mut join_set = JoinSet::new();
-
-        for event in events {
-            let own_simulator = self.own_simulator.clone();
-            join_set.spawn(async move {
-                if let Ok(protocol) = Protocol::try_from(&event) {
-                    if let Ok(swap_event) = protocol.sui_event_to_swap_event(&event, own_simulator).await {
-                        return Some((swap_event.involved_coin_one_side(), swap_event.pool_id()));
-                    }
-                }
-                None
-            });
+    // The auction end doesn't name the winner, so an auction counts as won if our highest bid reached the
+    // winning bid, and as lost if its opportunity was sent to workers but it didn't, whether or not a bid was
+    // actually submitted. The pairs of a lost auction leave their cooldown and are retried right away on the
+    // latest state as public txs if they are executed, workers drop them if nothing is left after the winner.
+    #[instrument(name = "on-shio-result", skip_all, fields(tx = %opp_tx_digest))]
+    async fn on_shio_result(&mut self, opp_tx_digest: TransactionDigest, winning_bid_amount: u64) -> Result<()> {
+        let coin_pools = self.recent_arbs.take_shio_auction(&opp_tx_digest);
+        let our_bid = self.shio_bids.take(&opp_tx_digest);
+        if our_bid.is_some_and(|bid_amount| bid_amount >= winning_bid_amount) {
+            self.shio_won += 1;
+            metrics().shio_auctions.with_label_values(&["won"]).inc();
+            info!(winning_bid_amount, "won shio auction");
+            return Ok(());
+        }
+        if coin_pools.is_empty() && our_bid.is_none() {
+            // not our auction
+            return Ok(());
         }
 
-        let mut coin_pools = HashSet::new();
-        while let Some(result) = join_set.join_next().await {
-            if let Ok(Some((coin, pool_id))) = result {
-                coin_pools.insert((coin, pool_id));
+        self.shio_lost += 1;
+        metrics().shio_auctions.with_label_values(&["lost"]).inc();
+        debug!(?our_bid, winning_bid_amount, "lost shio auction");
+
+        let epoch = self.latest_epoch();
+        let sim_ctx = SimulateCtx::new(epoch, vec![]);
+        let source = Source::Public;
+        for (coin, pool_id) in coin_pools {
+            self.recent_arbs.forget(&coin, pool_id);
+            if !self.public_txs {
+                continue;
             }
+            // not through `opp_dedup`, which has already seen the opportunity tx
+            metrics().opportunities.with_label_values(&[source.name()]).inc();
+            self.arb_cache
+                .insert(coin, pool_id, opp_tx_digest, sim_ctx.clone(), source, None);
+        }
+
+        Ok(())
+    }
+
+    fn insert_opportunity(
+        &mut self,
+        coin: String,
+        pool_id: Option<ObjectID>,
+        tx_digest: TransactionDigest,
+        sim_ctx: &SimulateCtx,
+        source: Source,
+    ) {
+        if !self.opp_dedup.check_and_record(tx_digest, &coin, &source) {
+            return;
         }
 
-        coin_pools
+        metrics().opportunities.with_label_values(&[source.name()]).inc();
+        self.arb_cache
+            .insert(coin, pool_id, tx_digest, sim_ctx.clone(), source, None);
     }
 
     // returns (involved_coin_pools, override_objects) if there are swap events.
@@ -185,6 +502,8 @@ impl ArbStrategy {
             join_set.spawn(async move {
                 if let Ok(protocol) = Protocol::try_from(&event) {
                     if let Ok(swap_event) = protocol.shio_event_to_swap_event(&event, own_simulator).await {
+                        // the tx is pending, the swap is seen now
+                        record_swap_event(&swap_event, None);
                         return Some((swap_event.involved_coin_one_side(), swap_event.pool_id()));
                     }
                 }
@@ -214,19 +533,106 @@ impl ArbStrategy {
         Some((involved_coin_pools, override_objects))
     }
 
-    async fn get_latest_epoch(&mut self) -> Result<SimEpoch> {
-        if let Some(epoch) = self.epoch {
-            if !epoch.is_stale() {
-                return Ok(epoch);
+    fn latest_epoch(&self) -> SimEpoch {
+        *self.epoch.borrow()
+    }
+}
+
+// Send the best arb_items to workers while the channel has room. Otherwise workers are backlogged, and
+// the arb_cache is shed to its best entries, so that workers don't catch up on stale items afterwards.
+async fn dispatch_arb_items(
+    arb_cache: &mut ArbCache,
+    recent_arbs: &mut RecentArbs,
+    arb_item_sender: &Sender<WorkerMessage>,
+) {
+    let channel_len = arb_item_sender.len();
+    if channel_len < ARB_ITEM_CHANNEL_HIGH_WATER_MARK {
+        let num_to_send = ARB_ITEM_CHANNEL_HIGH_WATER_MARK - channel_len;
+        for _ in 0..num_to_send {
+            if let Some(item) = arb_cache.pop_one() {
+                if recent_arbs.try_record(&item.coin, item.pool_id, &item.source) {
+                    arb_item_sender.send(WorkerMessage::ArbItem(item)).await.unwrap();
+                }
             } else {
-                self.epoch = None;
+                // no more arb_item to send
+                break;
             }
         }
+    } else {
+        let shed = arb_cache.shed(BACKLOGGED_ARB_CACHE_SIZE);
+        metrics().arb_cache_shed.inc_by(shed as u64);
+        warn!(
+            shed,
+            arb_cache.len = arb_cache.len(),
+            "arb_item channel stash {}",
+            channel_len
+        );
+    }
+}
+
+// pin the current thread to a core, round-robin by worker id
+fn pin_to_core(id: usize) {
+    match core_affinity::get_core_ids() {
+        Some(core_ids) if !core_ids.is_empty() => {
+            let core_id = core_ids[id % core_ids.len()];
+            if !core_affinity::set_for_current(core_id) {
+                warn!(worker.id = id, ?core_id, "failed to pin worker to core");
+            }
+        }
+        _ => warn!(worker.id = id, "failed to get core ids, worker is not pinned"),
+    }
+}
+
+// counted in `live_workers` while held by a worker thread
+struct LiveWorker(Arc<AtomicUsize>);
+
+impl Drop for LiveWorker {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+async fn parse_involved_coin_pools(
+    events: Vec<SuiEvent>,
+    simulator: Arc<dyn Simulator>,
+) -> HashSet<(String, Option<ObjectID>)> {
+    let mut join_set = JoinSet::new();
+
+    for event in events {
+        let simulator = simulator.clone();
+        join_set.spawn(async move {
+            if let Ok(protocol) = Protocol::try_from(&event) {
+                if let Ok(swap_event) = protocol.sui_event_to_swap_event(&event, simulator).await {
+                    record_swap_event(&swap_event, event.timestamp_ms);
+                    return Some((swap_event.involved_coin_one_side(), swap_event.pool_id()));
+                }
+            }
+            None
+        });
+    }
 
-        let epoch = get_latest_epoch(&self.sui).await?;
-        self.epoch = Some(epoch);
-        Ok(epoch)
+    let mut coin_pools = HashSet::new();
+    while let Some(result) = join_set.join_next().await {
+        if let Ok(Some((coin, pool_id))) = result {
+            coin_pools.insert((coin, pool_id));
+        }
     }
+
+    coin_pools
+}
+
+// returns (involved_coin_pools, override_objects) of a private tx by simulating it.
+async fn simulate_private_tx(
+    simulator: Arc<dyn Simulator>,
+    tx: TransactionData,
+    epoch: SimEpoch,
+) -> Result<(HashSet<(String, Option<ObjectID>)>, Vec<ObjectReadResult>)> {
+    let resp = simulator.simulate(tx, SimulateCtx::new(epoch, vec![])).await?;
+    let status = resp.effects.status();
+    ensure!(status.is_ok(), "private tx failed in simulation: {:?}", status);
+
+    let coin_pools = parse_involved_coin_pools(resp.events.data, simulator).await;
+    Ok((coin_pools, resp.object_changes))
 }
 
 fn new_object_read_result(tx_digest: TransactionDigest, shio_obj: &ShioObject) -> Result<ObjectReadResult> {
@@ -237,11 +643,12 @@ fn new_object_read_result(tx_digest: TransactionDigest, shio_obj: &ShioObject) -
     );
 
     let id = ObjectID::from_hex_literal(&shio_obj.id)?;
+    let version_and_digest = shio_obj.version_and_digest();
 
     let move_obj = {
         let type_: MoveObjectType = serde_json::from_str(&shio_obj.object_type)?;
         let has_public_transfer = shio_obj.has_public_transfer();
-        let version = OBJECT_START_VERSION;
+        let version = version_and_digest.map_or(OBJECT_START_VERSION, |(version, _)| version);
         let contents = Base64::decode(&shio_obj.object_bcs)?;
         let protocol_config = ProtocolConfig::get_for_version(ProtocolVersion::MAX, Chain::Mainnet);
         unsafe { MoveObject::new_from_execution(type_, has_public_transfer, version, contents, &protocol_config)? }
@@ -257,7 +664,11 @@ fn new_object_read_result(tx_digest: TransactionDigest, shio_obj: &ShioObject) -
             initial_shared_version,
             mutable: true,
         },
-        _ => InputObjectKind::ImmOrOwnedMoveObject(object.compute_object_reference()),
+        // keep the real ref if known, so that our gas coins mutated by the opportunity tx can be spent after it
+        _ => match version_and_digest {
+            Some((version, digest)) => InputObjectKind::ImmOrOwnedMoveObject((id, version, digest)),
+            None => InputObjectKind::ImmOrOwnedMoveObject(object.compute_object_reference()),
+        },
     };
 
     Ok(ObjectReadResult::new(input_object_kind, object.into()))
@@ -302,57 +713,32 @@ impl burberry::Strategy<Event, Action> for ArbStrategy {
         }
 
         let (arb_item_sender, arb_item_receiver) = async_channel::unbounded();
+        heartbeat::register_probe("arb_channel", {
+            let arb_item_sender = arb_item_sender.clone();
+            Box::new(move || arb_channel_health(arb_item_sender.len()))
+        });
+        self.shutdown
+            .set_channel(arb_item_sender.clone(), arb_item_receiver.clone());
         self.arb_item_sender = Some(arb_item_sender);
-
-        let sender = self.sender;
-        let rpc_url = self.rpc_url.clone();
+        self.arb_item_receiver = Some(arb_item_receiver);
+        self.submitter = Some(submitter);
+        if let Some(epoch_refresher) = self.epoch_refresher.take() {
+            epoch_refresher.spawn();
+        }
 
         let workers_to_spawn = self.workers;
         info!("spawning {} workers to process messages", workers_to_spawn);
 
         let (init_tx, mut init_rx) = tokio::sync::mpsc::channel(workers_to_spawn);
-
-        for id in 0..workers_to_spawn {
-            debug!(worker.id = id, "spawning worker...");
-
-            let arb_item_receiver = arb_item_receiver.clone();
-            let submitter = submitter.clone();
-
-            let sui = SuiClientBuilder::default().build(&rpc_url).await?;
-            let rpc_url = rpc_url.clone();
-            let init_tx = init_tx.clone();
-            let simulator_pool_arb = self.simulator_pool.clone();
-            let simulator_pool_worker = self.simulator_pool.clone();
-            let simulator_name = simulator_pool_arb.get().name().to_string();
-            let dedicated_simulator = self.dedicated_simulator.clone();
-
-            let _ = std::thread::Builder::new()
-                .stack_size(128 * 1024 * 1024) // 128 MB
-                .name(format!("worker-{id}"))
-                .spawn(move || {
-                    let arb = Arc::new(run_in_tokio!({ Arb::new(&rpc_url, simulator_pool_arb) }).unwrap());
-
-                    // Signal that this worker is initialized
-                    run_in_tokio!(init_tx.send(())).unwrap();
-
-                    let worker = Worker {
-                        _id: id,
-                        sender,
-                        arb_item_receiver,
-                        simulator_pool: simulator_pool_worker,
-                        simulator_name,
-                        submitter,
-                        sui,
-                        arb,
-                        dedicated_simulator,
-                    };
-                    worker.run().unwrap_or_else(|e| panic!("worker {id} panicked: {e:?}"));
-                });
+        for _ in 0..workers_to_spawn {
+            self.spawn_worker(Some(init_tx.clone()));
         }
+        // a worker that fails to initialize drops its sender without a message
+        drop(init_tx);
 
         // Wait for all workers to initialize
         for _ in 0..workers_to_spawn {
-            init_rx.recv().await.expect("worker initialization failed");
+            init_rx.recv().await.ok_or_eyre("worker initialization failed")?;
         }
 
         info!("workers all spawned!");
@@ -360,45 +746,467 @@ impl burberry::Strategy<Event, Action> for ArbStrategy {
     }
 
     async fn process_event(&mut self, event: Event, _submitter: Arc<dyn ActionSubmitter<Action>>) {
+        if self.shutdown.is_stopping() {
+            return;
+        }
+        metrics().events.with_label_values(&[event.source_name()]).inc();
+
         let result = match event {
             Event::PublicTx(tx_effects, events) => self.on_new_tx_effects(tx_effects, events).await,
-            Event::PrivateTx(tx_data) => self.on_new_tx(tx_data).await,
+            Event::PrivateTx(tx_data, received_at_ms) => self.on_new_tx(tx_data, received_at_ms).await,
             Event::Shio(shio_item) => self.on_new_shio_item(shio_item).await,
+            Event::ShioResult {
+                opp_tx_digest,
+                winning_bid_amount,
+            } => self.on_shio_result(opp_tx_digest, winning_bid_amount).await,
         };
         if let Err(error) = result {
             error!(?error, "failed to process event");
             return;
         }
 
-        // send arb_item to workers if channel is < 10
-        let channel_len = self.arb_item_sender.as_ref().unwrap().len();
-        if channel_len < 10 {
-            let num_to_send = 10 - channel_len;
-            for _ in 0..num_to_send {
-                if let Some(item) = self.arb_cache.pop_one() {
-                    if !self.recent_arbs.contains(&item.coin) || item.source.is_shio() {
-                        let coin = item.coin.clone();
-                        self.arb_item_sender.as_ref().unwrap().send(item).await.unwrap();
-
-                        self.recent_arbs.push_back(coin);
-                        if self.recent_arbs.len() > self.max_recent_arbs {
-                            self.recent_arbs.pop_front();
-                        }
-                    }
-                } else {
-                    // no more arb_item to send
-                    break;
-                }
-            }
-        } else {
-            warn!("arb_item channel stash {}", channel_len);
+        dispatch_arb_items(
+            &mut self.arb_cache,
+            &mut self.recent_arbs,
+            self.arb_item_sender.as_ref().unwrap(),
+        )
+        .await;
+
+        self.autoscale();
+
+        self.arb_cache.remove_expired();
+        self.recent_arbs.remove_expired();
+        self.shio_bids.remove_expired();
+        metrics().arb_cache_depth.set(self.arb_cache.len() as i64);
+
+        if self.last_metrics_log.elapsed() > METRICS_LOG_INTERVAL {
+            info!(
+                arb_cache.len = self.arb_cache.len(),
+                arb_cache.expired = self.arb_cache.expired_count(),
+                opp_dedup.len = self.opp_dedup.len(),
+                recent_arbs.len = self.recent_arbs.len(),
+                shio.won = self.shio_won,
+                shio.lost = self.shio_lost,
+                workers = self.live_workers.load(Ordering::Relaxed),
+                deadline_exceeded = self.deadline_exceeded.load(Ordering::Relaxed),
+                profit_regressed = self.profit_regressed.load(Ordering::Relaxed),
+                "arb_cache metrics: {}",
+                self.arb_cache.metrics()
+            );
+            self.last_metrics_log = Instant::now();
         }
+    }
+}
 
-        let expired_coins = self.arb_cache.remove_expired();
-        for coin in expired_coins {
-            if let Some(pos) = self.recent_arbs.iter().position(|x| x == &coin) {
-                self.recent_arbs.remove(pos);
-            }
+// the channel is only filled up to the high water mark, it stays there while the workers can't keep up
+fn arb_channel_health(depth: usize) -> HealthStatus {
+    if depth < ARB_ITEM_CHANNEL_HIGH_WATER_MARK {
+        HealthStatus::Healthy
+    } else {
+        HealthStatus::Unhealthy(format!("{depth} arb items waiting for workers"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use move_core_types::language_storage::StructTag;
+    use serde_json::json;
+    use simulator::{DBSimulator, HttpSimulator};
+    use sui_types::base_types::SuiAddress;
+    use utils::{
+        coin::{self, GasCoinFilter},
+        metrics_server::{sample_value, scrape},
+    };
+
+    use super::*;
+    use crate::{
+        config::tests::{TEST_ATTACKER, TEST_HTTP_URL},
+        defi::{
+            warmed_up_protocols, Dex, DexSearcher, IndexerDexSearcher, StubDex, DEFAULT_MAX_POOLS_PER_PROTOCOL,
+            DEFAULT_MAX_SIMULATED_PATHS,
+        },
+        gas_coin::GasCoinManager,
+        metrics::tests::serve_metrics,
+    };
+
+    struct NoopSubmitter;
+
+    impl ActionSubmitter<Action> for NoopSubmitter {
+        fn submit(&self, _action: Action) {}
+    }
+
+    #[tokio::test]
+    async fn test_simulate_private_tx() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=debug", "dex_indexer=debug"]);
+
+        let sender = SuiAddress::from_str(TEST_ATTACKER).unwrap();
+        let coin_in_type = "0x2::sui::SUI";
+        let coin_out_type = "0xdeeb7a4662eec9f2f3def03fb937a663dddaa2e215b8078a284d026b7946c270::deep::DEEP";
+
+        let simulator_pool = Arc::new(ObjectPool::new_async(1, || async {
+            Box::new(DBSimulator::new_test(true).await) as Box<dyn Simulator>
+        }));
+
+        // a swap tx that hasn't been executed on chain
+        let searcher = IndexerDexSearcher::new(TEST_HTTP_URL, simulator_pool.clone())
+            .await
+            .unwrap();
+        let dex = searcher
+            .find_dexes(coin_in_type, Some(coin_out_type.into()))
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|dex| dex.protocol() == Protocol::Cetus)
+            .sorted_by(|a, b| a.liquidity().cmp(&b.liquidity()))
+            .last()
+            .unwrap();
+        let pool_id = dex.object_id();
+        let tx_data = dex.swap_tx(sender, sender, 10000).await.unwrap();
+
+        let sui = SuiClientBuilder::default().build(TEST_HTTP_URL).await.unwrap();
+        let epoch = get_latest_epoch(&sui).await.unwrap();
+
+        let simulator: Arc<dyn Simulator> = simulator_pool.get();
+        let (coin_pools, override_objects) = simulate_private_tx(simulator, tx_data, epoch).await.unwrap();
+        info!(?coin_pools, override_objects.len = override_objects.len(), "private tx");
+
+        assert!(coin_pools.contains(&(coin_out_type.to_string(), Some(pool_id))));
+        assert!(override_objects.iter().any(|obj| obj.id() == pool_id));
+    }
+
+    #[tokio::test]
+    async fn test_backlogged_workers_shed_public_items() {
+        let (arb_item_sender, arb_item_receiver) = async_channel::unbounded();
+        let mut arb_cache = ArbCache::new(Duration::from_secs(5));
+        let mut recent_arbs = RecentArbs::new(Duration::ZERO, Duration::ZERO);
+        let digest = TransactionDigest::random();
+
+        // workers are blocked, so the channel fills up to the high-water mark
+        for i in 0..ARB_ITEM_CHANNEL_HIGH_WATER_MARK {
+            let coin = format!("0x1::c{i}::C");
+            arb_cache.insert(coin, None, digest, SimulateCtx::default(), Source::Public, None);
+        }
+        dispatch_arb_items(&mut arb_cache, &mut recent_arbs, &arb_item_sender).await;
+        assert_eq!(arb_item_sender.len(), ARB_ITEM_CHANNEL_HIGH_WATER_MARK);
+
+        let num_public = 2 * BACKLOGGED_ARB_CACHE_SIZE;
+        for i in 0..num_public {
+            let coin = format!("0x1::p{i}::P");
+            arb_cache.insert(coin, None, digest, SimulateCtx::default(), Source::Public, None);
         }
+        let shio = Source::Shio {
+            opp_tx_digest: digest,
+            bid_amount: 0,
+            start: 0,
+            arb_found: 0,
+            deadline: utils::current_time_ms() + 1_000,
+        };
+        let coin = "0x1::s::S".to_string();
+        arb_cache.insert(coin, None, digest, SimulateCtx::default(), shio, None);
+
+        let shed_before = metrics().arb_cache_shed.get();
+        dispatch_arb_items(&mut arb_cache, &mut recent_arbs, &arb_item_sender).await;
+        let shed = (num_public + 1 - BACKLOGGED_ARB_CACHE_SIZE) as u64;
+        assert_eq!(arb_cache.len(), BACKLOGGED_ARB_CACHE_SIZE);
+        assert_eq!(arb_cache.metrics().shed, shed);
+        assert!(metrics().arb_cache_shed.get() >= shed_before + shed);
+
+        // once workers catch up, the shio item is the first one dispatched
+        while arb_item_receiver.try_recv().is_ok() {}
+        dispatch_arb_items(&mut arb_cache, &mut recent_arbs, &arb_item_sender).await;
+        let Ok(WorkerMessage::ArbItem(item)) = arb_item_receiver.try_recv() else {
+            panic!("expected an arb item");
+        };
+        assert!(item.source.is_shio());
+    }
+
+    async fn new_test_strategy(warm_up_coins: Vec<String>) -> ArbStrategy {
+        let sender = SuiAddress::from_str(TEST_ATTACKER).unwrap();
+        let simulator_pool = Arc::new(ObjectPool::new_async(1, || async {
+            Box::new(HttpSimulator::new(TEST_HTTP_URL, &None).await) as Box<dyn Simulator>
+        }));
+        let own_simulator = Arc::new(HttpSimulator::new(TEST_HTTP_URL, &None).await) as Arc<dyn Simulator>;
+        let sui = SuiClientBuilder::default().build(TEST_HTTP_URL).await.unwrap();
+        let gas_coins = Arc::new(GasCoinManager::new(sui, sender).await.unwrap());
+
+        ArbStrategy::new(
+            Arc::new(SenderPool::new(vec![gas_coins])),
+            simulator_pool,
+            own_simulator,
+            Duration::ZERO,
+            Duration::ZERO,
+            TEST_HTTP_URL,
+            1,
+            None,
+            None,
+            0,
+            FinalCheck::default(),
+            warm_up_coins,
+            0,
+            ProtocolFilter::default(),
+            DEFAULT_MAX_POOLS_PER_PROTOCOL,
+            DEFAULT_MAX_SIMULATED_PATHS,
+            false,
+            WorkerThreads::default(),
+        )
+        .await
+    }
+
+    const AFTERMATH_SWAP_EVENT: &str =
+        "0xc4049b2d1cc0f6e017fda8260e4377cecd236bd7f56a54fee120816e72e2e0dd::events::SwapEventV2";
+    // the SUI/BUCK pool of the aftermath pools, its swaps don't read any object
+    const AFTERMATH_POOL: &str = "0xdeacf7ab460385d4bcb567f183f916367f7d43666a2c72323013822eb3c57026";
+    const BUCK: &str = "ce7ff77a83ea0cb6fd39bd8748e2ec89a3f41e8efdc3f4eb123e0ca37b184db2::buck::BUCK";
+
+    fn aftermath_swap_json() -> serde_json::Value {
+        json!({
+            "pool_id": AFTERMATH_POOL,
+            "issuer": TEST_ATTACKER,
+            "referrer": null,
+            "types_in": ["0000000000000000000000000000000000000000000000000000000000000002::sui::SUI"],
+            "amounts_in": ["1000000000"],
+            "types_out": [BUCK],
+            "amounts_out": ["3500000000"],
+        })
+    }
+
+    // the effects and the swap event of a public tx, the effects are those of a transfer simulated on
+    // the latest state
+    async fn public_swap_tx(strategy: &ArbStrategy) -> (SuiTransactionBlockEffects, SuiEvent) {
+        let sender = SuiAddress::from_str(TEST_ATTACKER).unwrap();
+        let sui = SuiClientBuilder::default().build(TEST_HTTP_URL).await.unwrap();
+        let gas_coin = coin::get_gas_coin_refs(&sui, sender, &GasCoinFilter::default())
+            .await
+            .unwrap()[0];
+        let gas_price = sui.read_api().get_reference_gas_price().await.unwrap();
+        let tx_data = TransactionData::new_transfer_sui(sender, sender, Some(1), gas_coin, 10_000_000, gas_price);
+        let sim_ctx = SimulateCtx::new(strategy.latest_epoch(), vec![]);
+        let effects = strategy.own_simulator.simulate(tx_data, sim_ctx).await.unwrap().effects;
+
+        let mut event = SuiEvent::random_for_testing();
+        event.id.tx_digest = *effects.transaction_digest();
+        event.type_ = AFTERMATH_SWAP_EVENT.parse::<StructTag>().unwrap();
+        event.parsed_json = aftermath_swap_json();
+        (effects, event)
+    }
+
+    // the auction of the same swap, sent by shio before the tx is executed
+    fn shio_swap_item(tx_digest: TransactionDigest) -> ShioItem {
+        ShioItem::from(json!({"auctionStarted": {
+            "txDigest": tx_digest.to_string(),
+            "gasPrice": 1000,
+            "deadlineTimestampMs": utils::current_time_ms() + 10_000,
+            "sideEffects": {
+                "gasUsage": 0,
+                "events": [{
+                    "type": AFTERMATH_SWAP_EVENT,
+                    "bcs": "",
+                    "id": {"eventSeq": 0, "txDigest": tx_digest.to_string()},
+                    "packageId": AFTERMATH_SWAP_EVENT.split("::").next().unwrap(),
+                    "parsedJson": aftermath_swap_json(),
+                    "sender": TEST_ATTACKER,
+                    "transactionModule": "swap",
+                }],
+            },
+        }}))
+    }
+
+    #[tokio::test]
+    async fn test_public_tx_after_shio_item_is_skipped() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=debug"]);
+
+        let mut strategy = new_test_strategy(vec![]).await;
+        let (effects, event) = public_swap_tx(&strategy).await;
+        let tx_digest = *effects.transaction_digest();
+
+        strategy.on_new_shio_item(shio_swap_item(tx_digest)).await.unwrap();
+        strategy.on_new_tx_effects(effects, vec![event]).await.unwrap();
+
+        let item = strategy.arb_cache.pop_one().unwrap();
+        assert!(item.source.is_shio());
+        assert_eq!(item.source.opp_tx_digest(), Some(tx_digest));
+        assert!(strategy.arb_cache.pop_one().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shio_item_after_public_tx_wins() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=debug"]);
+
+        let mut strategy = new_test_strategy(vec![]).await;
+        let (effects, event) = public_swap_tx(&strategy).await;
+        let tx_digest = *effects.transaction_digest();
+
+        strategy
+            .on_new_tx_effects(effects.clone(), vec![event.clone()])
+            .await
+            .unwrap();
+        assert_eq!(strategy.arb_cache.len(), 1);
+        strategy.on_new_shio_item(shio_swap_item(tx_digest)).await.unwrap();
+        // both are seen again, e.g. when the stream reconnects
+        strategy.on_new_tx_effects(effects, vec![event]).await.unwrap();
+        strategy.on_new_shio_item(shio_swap_item(tx_digest)).await.unwrap();
+
+        // the shio opportunity replaced the public one of the same coin
+        let item = strategy.arb_cache.pop_one().unwrap();
+        assert!(item.source.is_shio());
+        assert!(strategy.arb_cache.pop_one().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shio_item_invalidates_cached_dexes() {
+        let mut strategy = new_test_strategy(vec![]).await;
+        let cache = Arc::new(DexCache::new(Duration::from_secs(60)));
+        strategy.dex_cache = cache.clone();
+        let pool_id = ObjectID::from_hex_literal(AFTERMATH_POOL).unwrap();
+        let coin_in = "0x2::sui::SUI";
+        let dex: Box<dyn Dex> = Box::new(StubDex::new(pool_id, coin_in, BUCK, 1_000_000));
+        cache.insert(pool_id, coin_in, vec![dex]);
+
+        let tx_digest = TransactionDigest::random();
+        strategy.on_new_shio_item(shio_swap_item(tx_digest)).await.unwrap();
+        assert!(cache.get(pool_id, coin_in).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sync_state_warms_up_workers() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=debug"]);
+
+        let usdc = "0xdba34672e30cb065b1f93e3ab55318768fd6fef66c15942c9f7cb846e2f900e7::usdc::USDC";
+        let mut strategy = new_test_strategy(vec![usdc.to_string()]).await;
+        strategy.sync_state(Arc::new(NoopSubmitter)).await.unwrap();
+
+        let protocols = warmed_up_protocols();
+        assert!(protocols.contains(&Protocol::Cetus), "{protocols:?}");
+        assert!(protocols.contains(&Protocol::Turbos), "{protocols:?}");
+    }
+
+    #[tokio::test]
+    async fn test_process_event_moves_metrics() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=debug"]);
+
+        let mut strategy = new_test_strategy(vec![]).await;
+        strategy.sync_state(Arc::new(NoopSubmitter)).await.unwrap();
+
+        let addr = serve_metrics().await;
+        let before = scrape(addr).await;
+
+        // an auction without swap events, it's received but yields no opportunity
+        for _ in 0..2 {
+            let event = Event::Shio(ShioItem::Dummy(serde_json::Value::Null));
+            strategy.process_event(event, Arc::new(NoopSubmitter)).await;
+        }
+
+        let after = scrape(addr).await;
+        let events = r#"arb_events_total{source="shio"}"#;
+        assert_eq!(sample_value(&after, events), sample_value(&before, events) + 2.0);
+        assert!(
+            after.lines().any(|line| line.starts_with("arb_cache_depth ")),
+            "{after}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shio_results_update_counters() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=debug"]);
+
+        let mut strategy = new_test_strategy(vec![]).await;
+        let coin = "0x1::c::C";
+        let pool_id = Some(ObjectID::random());
+        let shio = |opp_tx_digest| Source::Shio {
+            opp_tx_digest,
+            bid_amount: 0,
+            start: 0,
+            arb_found: 0,
+            deadline: 0,
+        };
+
+        // won, our bid is the winning one
+        let won = TransactionDigest::random();
+        assert!(strategy.recent_arbs.try_record(coin, pool_id, &shio(won)));
+        strategy.shio_bids.record(won, 100);
+        strategy.on_shio_result(won, 100).await.unwrap();
+        assert_eq!((strategy.shio_won, strategy.shio_lost), (1, 0));
+        assert_eq!(strategy.arb_cache.len(), 0);
+
+        // someone else's auction
+        strategy.on_shio_result(TransactionDigest::random(), 100).await.unwrap();
+        assert_eq!((strategy.shio_won, strategy.shio_lost), (1, 0));
+
+        // lost, our bid was outbid and the pair is retried right away
+        let lost = TransactionDigest::random();
+        assert!(strategy.recent_arbs.try_record(coin, pool_id, &shio(lost)));
+        strategy.shio_bids.record(lost, 100);
+        strategy.on_shio_result(lost, 150).await.unwrap();
+        assert_eq!((strategy.shio_won, strategy.shio_lost), (1, 1));
+        assert_eq!(strategy.arb_cache.len(), 1);
+        assert_eq!(strategy.recent_arbs.shio_auctions_len(), 0);
+        assert_eq!(strategy.shio_bids.len(), 0);
+
+        // without a public tx executor, a lost auction is not retried
+        let mut strategy = strategy.with_public_txs(false);
+        let lost = TransactionDigest::random();
+        assert!(strategy.recent_arbs.try_record("0x1::d::D", pool_id, &shio(lost)));
+        strategy.shio_bids.record(lost, 100);
+        strategy.on_shio_result(lost, 150).await.unwrap();
+        assert_eq!((strategy.shio_won, strategy.shio_lost), (1, 2));
+        assert_eq!(strategy.arb_cache.len(), 1);
+    }
+
+    #[test]
+    fn test_panicked_worker_is_no_longer_live() {
+        let live_workers = Arc::new(AtomicUsize::new(1));
+        let worker = std::thread::spawn({
+            let live_workers = live_workers.clone();
+            move || {
+                let _live = LiveWorker(live_workers);
+                panic!("worker failed");
+            }
+        });
+
+        assert!(worker.join().is_err());
+        assert_eq!(live_workers.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_scale_workers_up_and_down() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=debug"]);
+
+        let mut strategy = new_test_strategy(vec![]).await;
+        strategy.sync_state(Arc::new(NoopSubmitter)).await.unwrap();
+        assert_eq!(strategy.live_workers.load(Ordering::Relaxed), 1);
+
+        strategy.scale(ScaleAction::Up);
+        assert_eq!(strategy.live_workers.load(Ordering::Relaxed), 2);
+
+        // the retired worker exits once it receives the shutdown message
+        strategy.scale(ScaleAction::Down);
+        tokio::time::timeout(Duration::from_secs(30), async {
+            while strategy.live_workers.load(Ordering::Relaxed) != 1 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_retires_all_workers() {
+        mev_logger::init_console_logger_with_directives(None, &["arb=debug"]);
+
+        let mut strategy = new_test_strategy(vec![]).await;
+        strategy.sync_state(Arc::new(NoopSubmitter)).await.unwrap();
+        strategy.scale(ScaleAction::Up);
+        assert_eq!(strategy.live_workers.load(Ordering::Relaxed), 2);
+
+        let shutdown = strategy.worker_shutdown();
+        assert!(shutdown.shutdown(Duration::from_secs(1), Duration::from_secs(30)).await);
+        assert!(shutdown.is_stopping());
+        assert_eq!(strategy.live_workers.load(Ordering::Relaxed), 0);
+
+        // the events received while stopping are ignored
+        let event = Event::Shio(ShioItem::Dummy(serde_json::Value::Null));
+        strategy.process_event(event, Arc::new(NoopSubmitter)).await;
+        assert!(strategy.arb_item_sender.as_ref().unwrap().is_empty());
     }
 }
@@ -0,0 +1,254 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use sui_types::{base_types::ObjectID, digests::TransactionDigest};
+
+use crate::types::Source;
+
+/// Tracks when each `(coin, pool)` pair was last sent to workers, so that the same pair
+/// doesn't churn workers on every event.
+///
+/// The cooldown depends on the source of the incoming item: shio items are already gated by
+/// their deadline, so they usually have a shorter (or zero) cooldown than public ones.
+///
+/// The pairs sent for a shio opportunity are also kept by its tx digest until the auction result
+/// arrives, so that a lost auction can release them right away.
+pub struct RecentArbs {
+    last_sent: HashMap<(String, Option<ObjectID>), Instant>,
+    shio_auctions: HashMap<TransactionDigest, (Instant, Vec<(String, Option<ObjectID>)>)>,
+    public_cooldown: Duration,
+    shio_cooldown: Duration,
+}
+
+// auctions whose result never arrives are forgotten after this long
+const SHIO_AUCTION_TTL: Duration = Duration::from_secs(30);
+
+impl RecentArbs {
+    pub fn new(public_cooldown: Duration, shio_cooldown: Duration) -> Self {
+        Self {
+            last_sent: HashMap::new(),
+            shio_auctions: HashMap::new(),
+            public_cooldown,
+            shio_cooldown,
+        }
+    }
+
+    /// Returns true (and records the pair) if the pair is not within its cooldown window.
+    pub fn try_record(&mut self, coin: &str, pool_id: Option<ObjectID>, source: &Source) -> bool {
+        self.try_record_at(coin, pool_id, source, Instant::now())
+    }
+
+    fn try_record_at(&mut self, coin: &str, pool_id: Option<ObjectID>, source: &Source, now: Instant) -> bool {
+        let key = (coin.to_string(), pool_id);
+        let cooldown = self.cooldown(source);
+        if let Some(last_sent) = self.last_sent.get(&key) {
+            if now.saturating_duration_since(*last_sent) < cooldown {
+                return false;
+            }
+        }
+
+        if let Source::Shio { opp_tx_digest, .. } = source {
+            let (_, pairs) = self.shio_auctions.entry(*opp_tx_digest).or_insert((now, vec![]));
+            pairs.push(key.clone());
+        }
+        self.last_sent.insert(key, now);
+        true
+    }
+
+    /// Returns the pairs sent for the auction of `opp_tx_digest`, and stops tracking it.
+    pub fn take_shio_auction(&mut self, opp_tx_digest: &TransactionDigest) -> Vec<(String, Option<ObjectID>)> {
+        self.shio_auctions
+            .remove(opp_tx_digest)
+            .map(|(_, pairs)| pairs)
+            .unwrap_or_default()
+    }
+
+    /// Ends the cooldown of the pair, the next item of it is sent whatever its source.
+    pub fn forget(&mut self, coin: &str, pool_id: Option<ObjectID>) {
+        self.last_sent.remove(&(coin.to_string(), pool_id));
+    }
+
+    /// Remove the pairs whose cooldown has elapsed for every source.
+    pub fn remove_expired(&mut self) {
+        self.remove_expired_at(Instant::now());
+    }
+
+    fn remove_expired_at(&mut self, now: Instant) {
+        let max_cooldown = self.public_cooldown.max(self.shio_cooldown);
+        self.last_sent
+            .retain(|_, last_sent| now.saturating_duration_since(*last_sent) < max_cooldown);
+        self.shio_auctions
+            .retain(|_, (sent, _)| now.saturating_duration_since(*sent) < SHIO_AUCTION_TTL);
+    }
+
+    pub fn len(&self) -> usize {
+        self.last_sent.len()
+    }
+
+    /// The number of shio auctions waiting for their result.
+    pub fn shio_auctions_len(&self) -> usize {
+        self.shio_auctions.len()
+    }
+
+    fn cooldown(&self, source: &Source) -> Duration {
+        if source.is_shio() {
+            self.shio_cooldown
+        } else {
+            self.public_cooldown
+        }
+    }
+}
+
+/// The highest bid the workers submitted for each shio opportunity, shared with the strategy. The `auctionEnded`
+/// item only carries the winning bid amount, so an auction is won if our bid reached it.
+#[derive(Default)]
+pub struct ShioBids {
+    bids: Mutex<HashMap<TransactionDigest, (Instant, u64)>>,
+}
+
+impl ShioBids {
+    pub fn record(&self, opp_tx_digest: TransactionDigest, bid_amount: u64) {
+        let mut bids = self.bids.lock().unwrap();
+        let (_, highest) = bids.entry(opp_tx_digest).or_insert((Instant::now(), 0));
+        *highest = (*highest).max(bid_amount);
+    }
+
+    /// Returns our highest bid for the auction of `opp_tx_digest`, and stops tracking it.
+    pub fn take(&self, opp_tx_digest: &TransactionDigest) -> Option<u64> {
+        self.bids.lock().unwrap().remove(opp_tx_digest).map(|(_, bid)| bid)
+    }
+
+    /// Forget the bids whose auction ended without an `auctionEnded` item.
+    pub fn remove_expired(&self) {
+        self.remove_expired_at(Instant::now());
+    }
+
+    fn remove_expired_at(&self, now: Instant) {
+        self.bids
+            .lock()
+            .unwrap()
+            .retain(|_, (sent, _)| now.saturating_duration_since(*sent) < SHIO_AUCTION_TTL);
+    }
+
+    pub fn len(&self) -> usize {
+        self.bids.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COIN: &str = "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN";
+
+    #[test]
+    fn test_same_coin_different_pool_accepted() {
+        let mut recent_arbs = RecentArbs::new(Duration::from_secs(3), Duration::ZERO);
+
+        assert!(recent_arbs.try_record(COIN, Some(ObjectID::random()), &Source::Public));
+        assert!(recent_arbs.try_record(COIN, Some(ObjectID::random()), &Source::Public));
+        assert_eq!(recent_arbs.len(), 2);
+    }
+
+    #[test]
+    fn test_cooldown_window() {
+        let mut recent_arbs = RecentArbs::new(Duration::from_secs(3), Duration::ZERO);
+        let pool_id = Some(ObjectID::random());
+        let now = Instant::now();
+
+        assert!(recent_arbs.try_record_at(COIN, pool_id, &Source::Public, now));
+        assert!(!recent_arbs.try_record_at(COIN, pool_id, &Source::Public, now + Duration::from_secs(1)));
+
+        // shio items have no cooldown
+        let shio = Source::Shio {
+            opp_tx_digest: TransactionDigest::random(),
+            bid_amount: 0,
+            start: 0,
+            arb_found: 0,
+            deadline: 0,
+        };
+        assert!(recent_arbs.try_record_at(COIN, pool_id, &shio, now + Duration::from_secs(2)));
+
+        assert!(recent_arbs.try_record_at(COIN, pool_id, &Source::Public, now + Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn test_remove_expired() {
+        let mut recent_arbs = RecentArbs::new(Duration::from_secs(3), Duration::ZERO);
+        let now = Instant::now();
+
+        assert!(recent_arbs.try_record_at(COIN, None, &Source::Public, now));
+        recent_arbs.remove_expired_at(now + Duration::from_secs(1));
+        assert_eq!(recent_arbs.len(), 1);
+
+        recent_arbs.remove_expired_at(now + Duration::from_secs(3));
+        assert_eq!(recent_arbs.len(), 0);
+    }
+
+    #[test]
+    fn test_lost_shio_auction_releases_pairs() {
+        let mut recent_arbs = RecentArbs::new(Duration::from_secs(3), Duration::from_secs(3));
+        let pool_id = Some(ObjectID::random());
+        let opp_tx_digest = TransactionDigest::random();
+        let shio = Source::Shio {
+            opp_tx_digest,
+            bid_amount: 0,
+            start: 0,
+            arb_found: 0,
+            deadline: 0,
+        };
+        let now = Instant::now();
+
+        assert!(recent_arbs.try_record_at(COIN, pool_id, &shio, now));
+        assert!(!recent_arbs.try_record_at(COIN, pool_id, &Source::Public, now));
+        assert_eq!(recent_arbs.shio_auctions_len(), 1);
+
+        assert!(recent_arbs.take_shio_auction(&TransactionDigest::random()).is_empty());
+        let pairs = recent_arbs.take_shio_auction(&opp_tx_digest);
+        assert_eq!(pairs, vec![(COIN.to_string(), pool_id)]);
+        assert_eq!(recent_arbs.shio_auctions_len(), 0);
+
+        recent_arbs.forget(COIN, pool_id);
+        assert!(recent_arbs.try_record_at(COIN, pool_id, &Source::Public, now));
+    }
+
+    #[test]
+    fn test_shio_auction_expires() {
+        let mut recent_arbs = RecentArbs::new(Duration::ZERO, Duration::ZERO);
+        let shio = Source::Shio {
+            opp_tx_digest: TransactionDigest::random(),
+            bid_amount: 0,
+            start: 0,
+            arb_found: 0,
+            deadline: 0,
+        };
+        let now = Instant::now();
+
+        assert!(recent_arbs.try_record_at(COIN, None, &shio, now));
+        recent_arbs.remove_expired_at(now + Duration::from_secs(1));
+        assert_eq!(recent_arbs.shio_auctions_len(), 1);
+
+        recent_arbs.remove_expired_at(now + SHIO_AUCTION_TTL);
+        assert_eq!(recent_arbs.shio_auctions_len(), 0);
+    }
+
+    #[test]
+    fn test_shio_bids() {
+        let shio_bids = ShioBids::default();
+        let opp_tx_digest = TransactionDigest::random();
+
+        // the highest bid is kept
+        shio_bids.record(opp_tx_digest, 100);
+        shio_bids.record(opp_tx_digest, 300);
+        shio_bids.record(opp_tx_digest, 200);
+        shio_bids.record(TransactionDigest::random(), 50);
+        assert_eq!(shio_bids.take(&opp_tx_digest), Some(300));
+        assert_eq!(shio_bids.take(&opp_tx_digest), None);
+
+        shio_bids.remove_expired_at(Instant::now() + SHIO_AUCTION_TTL);
+        assert_eq!(shio_bids.len(), 0);
+    }
+}
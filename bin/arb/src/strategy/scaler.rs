@@ -0,0 +1,144 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoscaleConfig {
+    /// workers are never retired below this count
+    pub min_workers: usize,
+    pub max_workers: usize,
+    /// the channel is backlogged when at least this many arb_items are waiting in it
+    pub backlog_threshold: usize,
+    /// spawn a worker once the channel stays backlogged for this long
+    pub scale_up_after: Duration,
+    /// retire a worker once some workers stay idle for this long
+    pub scale_down_idle: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleAction {
+    Up,
+    Down,
+    Hold,
+}
+
+/// Decides when to spawn or retire a worker, from the backlog of the arb_item channel and the idle workers.
+///
+/// Workers are added or retired one at a time, and each step needs another full period of backlog (or
+/// idleness), so that a retired worker has exited before the next decision.
+pub struct WorkerScaler {
+    config: AutoscaleConfig,
+    backlogged_since: Option<Instant>,
+    idle_since: Option<Instant>,
+}
+
+impl WorkerScaler {
+    pub fn new(config: AutoscaleConfig) -> Self {
+        Self {
+            config,
+            backlogged_since: None,
+            idle_since: None,
+        }
+    }
+
+    pub fn observe(&mut self, backlog: usize, workers: usize, idle_workers: usize, now: Instant) -> ScaleAction {
+        if backlog >= self.config.backlog_threshold {
+            self.idle_since = None;
+            let backlogged_since = *self.backlogged_since.get_or_insert(now);
+            if now.saturating_duration_since(backlogged_since) >= self.config.scale_up_after
+                && workers < self.config.max_workers
+            {
+                self.backlogged_since = Some(now);
+                return ScaleAction::Up;
+            }
+            return ScaleAction::Hold;
+        }
+        self.backlogged_since = None;
+
+        if backlog == 0 && idle_workers > 0 {
+            let idle_since = *self.idle_since.get_or_insert(now);
+            if now.saturating_duration_since(idle_since) >= self.config.scale_down_idle
+                && workers > self.config.min_workers
+            {
+                self.idle_since = Some(now);
+                return ScaleAction::Down;
+            }
+            return ScaleAction::Hold;
+        }
+        self.idle_since = None;
+
+        ScaleAction::Hold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scaler() -> WorkerScaler {
+        WorkerScaler::new(AutoscaleConfig {
+            min_workers: 2,
+            max_workers: 3,
+            backlog_threshold: 10,
+            scale_up_after: Duration::from_secs(5),
+            scale_down_idle: Duration::from_secs(60),
+        })
+    }
+
+    #[test]
+    fn test_scale_up_on_sustained_backlog() {
+        let mut scaler = scaler();
+        let now = Instant::now();
+
+        assert_eq!(scaler.observe(10, 2, 0, now), ScaleAction::Hold);
+        assert_eq!(
+            scaler.observe(10, 2, 0, now + Duration::from_secs(4)),
+            ScaleAction::Hold
+        );
+        assert_eq!(scaler.observe(10, 2, 0, now + Duration::from_secs(5)), ScaleAction::Up);
+
+        // the next worker needs another period of backlog, and no more than max_workers are spawned
+        assert_eq!(
+            scaler.observe(10, 3, 0, now + Duration::from_secs(6)),
+            ScaleAction::Hold
+        );
+        assert_eq!(
+            scaler.observe(10, 3, 0, now + Duration::from_secs(60)),
+            ScaleAction::Hold
+        );
+    }
+
+    #[test]
+    fn test_transient_backlog_is_ignored() {
+        let mut scaler = scaler();
+        let now = Instant::now();
+
+        assert_eq!(scaler.observe(10, 2, 0, now), ScaleAction::Hold);
+        assert_eq!(scaler.observe(3, 2, 0, now + Duration::from_secs(3)), ScaleAction::Hold);
+        assert_eq!(
+            scaler.observe(10, 2, 0, now + Duration::from_secs(6)),
+            ScaleAction::Hold
+        );
+        assert_eq!(scaler.observe(10, 2, 0, now + Duration::from_secs(11)), ScaleAction::Up);
+    }
+
+    #[test]
+    fn test_scale_down_idle_workers() {
+        let mut scaler = scaler();
+        let now = Instant::now();
+
+        assert_eq!(scaler.observe(0, 3, 1, now), ScaleAction::Hold);
+        assert_eq!(
+            scaler.observe(0, 3, 1, now + Duration::from_secs(60)),
+            ScaleAction::Down
+        );
+
+        // never below min_workers
+        assert_eq!(
+            scaler.observe(0, 2, 2, now + Duration::from_secs(120)),
+            ScaleAction::Hold
+        );
+        assert_eq!(
+            scaler.observe(0, 2, 2, now + Duration::from_secs(600)),
+            ScaleAction::Hold
+        );
+    }
+}
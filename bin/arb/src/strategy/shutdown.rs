@@ -0,0 +1,79 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+use async_channel::{Receiver, Sender};
+use tracing::{info, warn};
+
+use super::worker::WorkerMessage;
+
+/// Stops the workers of an `ArbStrategy` from outside of the engine that owns it, see `shutdown`.
+#[derive(Clone)]
+pub struct WorkerShutdown {
+    stopping: Arc<AtomicBool>,
+    // set by `sync_state`
+    channel: Arc<OnceLock<(Sender<WorkerMessage>, Receiver<WorkerMessage>)>>,
+    live_workers: Arc<AtomicUsize>,
+}
+
+impl WorkerShutdown {
+    pub(super) fn new(live_workers: Arc<AtomicUsize>) -> Self {
+        Self {
+            stopping: Arc::new(AtomicBool::new(false)),
+            channel: Arc::new(OnceLock::new()),
+            live_workers,
+        }
+    }
+
+    pub(super) fn set_channel(&self, sender: Sender<WorkerMessage>, receiver: Receiver<WorkerMessage>) {
+        let _ = self.channel.set((sender, receiver));
+    }
+
+    /// True once `shutdown` is called, the strategy no longer dispatches arb_items.
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::Relaxed)
+    }
+
+    /// Stop dispatching arb_items, give the queued ones `drain_timeout` to be handled and drop the others,
+    /// then ask every worker to exit after its current arb_item. Returns false if some workers are still
+    /// running after `exit_timeout`.
+    pub async fn shutdown(&self, drain_timeout: Duration, exit_timeout: Duration) -> bool {
+        self.stopping.store(true, Ordering::Relaxed);
+        let Some((sender, receiver)) = self.channel.get() else {
+            // never synced, no worker was spawned
+            return true;
+        };
+
+        if !wait_until(drain_timeout, || sender.is_empty()).await {
+            let mut dropped = 0;
+            while let Ok(msg) = receiver.try_recv() {
+                dropped += matches!(msg, WorkerMessage::ArbItem(_)) as usize;
+            }
+            warn!(dropped, "arb_items dropped at shutdown");
+        }
+
+        let workers = self.live_workers.load(Ordering::Relaxed);
+        info!(workers, "asking workers to exit");
+        for _ in 0..workers {
+            // the channel is unbounded so this never blocks
+            let _ = sender.try_send(WorkerMessage::Shutdown);
+        }
+
+        wait_until(exit_timeout, || self.live_workers.load(Ordering::Relaxed) == 0).await
+    }
+}
+
+// false if `done` is still false after `timeout`
+async fn wait_until(timeout: Duration, done: impl Fn() -> bool) -> bool {
+    tokio::time::timeout(timeout, async {
+        while !done() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .is_ok()
+}
@@ -1,35 +1,46 @@
 use std::{
-    sync::Arc,
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
-use burberry::ActionSubmitter;
-use eyre::{bail, ensure, Context, OptionExt, Result};
+use burberry::{executor::telegram_message::Message, ActionSubmitter};
+use eyre::{bail, Context, OptionExt, Result};
 use object_pool::ObjectPool;
-use simulator::{ReplaySimulator, SimulateCtx, Simulator};
-use sui_json_rpc_types::SuiTransactionBlockEffectsAPI;
-use sui_sdk::SuiClient;
+use simulator::{ReplaySimulator, SimulateCtx, Simulator, SimulatorError};
+use sui_json_rpc_types::BalanceChange;
 use sui_types::{
-    base_types::{ObjectID, SuiAddress},
+    base_types::{ObjectID, ObjectRef, SuiAddress},
+    digests::TransactionDigest,
     object::Owner,
-    transaction::{GasData, TransactionData, TransactionDataAPI},
+    transaction::{GasData, InputObjectKind, TransactionData, TransactionDataAPI},
 };
-use tracing::{error, info, instrument};
-use utils::coin;
+use tracing::{debug, error, info, instrument, warn};
 
 use crate::{
     arb::{Arb, ArbResult},
     common::notification::new_tg_messages,
-    types::{Action, Source},
+    defi::{dex_cache, invalidate_shio_global_states_on, order_after},
+    executor::{DryRunRecord, RecordingExecutor},
+    gas_coin::{GasCoinManager, SenderPool},
+    metrics::metrics,
+    types::{acquire_before, Action, DeadlineExceeded, PoolPaused, Source},
 };
 
-use super::arb_cache::ArbItem;
+use super::{arb_cache::ArbItem, recent_arbs::ShioBids};
 
 pub struct Worker {
-    pub _id: usize,
-    pub sender: SuiAddress,
+    pub id: usize,
+    // each arb_item is sent from the next attacker with an available gas coin
+    pub senders: Arc<SenderPool>,
 
-    pub arb_item_receiver: async_channel::Receiver<ArbItem>,
+    pub arb_item_receiver: async_channel::Receiver<WorkerMessage>,
+    // number of workers handling an arb_item, for the autoscaler to find idle workers
+    pub busy_workers: Arc<AtomicUsize>,
 
     pub simulator_pool: Arc<ObjectPool<Box<dyn Simulator>>>,
     pub simulator_name: String,
@@ -37,8 +48,106 @@ pub struct Worker {
     pub dedicated_simulator: Option<Arc<ReplaySimulator>>,
 
     pub submitter: Arc<dyn ActionSubmitter<Action>>,
-    pub sui: SuiClient,
     pub arb: Arc<Arb>,
+
+    // number of arb_items dropped because their shio deadline had passed, or their private tx was too old
+    pub deadline_exceeded: Arc<AtomicU64>,
+
+    // dry-run mode: record the actions instead of submitting them
+    pub recorder: Option<Arc<RecordingExecutor>>,
+
+    // opportunities with a lower profit are not executed
+    pub min_profit: u64,
+
+    pub final_check: FinalCheck,
+    // number of final txs dropped because their re-simulated profit regressed
+    pub profit_regressed: Arc<AtomicU64>,
+    // the submitted bids, by opportunity tx
+    pub shio_bids: Arc<ShioBids>,
+    // false without a public tx executor, the opportunities can then only be bid on shio
+    pub public_txs: bool,
+}
+
+/// Thresholds of the last-moment dry run of the final tx, right before it is submitted.
+#[derive(Debug, Clone, Copy)]
+pub struct FinalCheck {
+    /// the realized profit must be at least this percentage of the estimated one
+    pub min_profit_pct: u64,
+    /// a shio bid is dropped if less than this many milliseconds remain before its deadline
+    pub deadline_margin_ms: u64,
+    /// the dedicated simulator is not trusted if it has not reloaded the store for longer
+    pub max_dedicated_lag: Duration,
+    /// an arb_item of a private tx is dropped once the tx was received longer ago, it's likely executed by then
+    pub max_private_age: Duration,
+}
+
+impl Default for FinalCheck {
+    fn default() -> Self {
+        Self {
+            min_profit_pct: 80,
+            deadline_margin_ms: 10,
+            max_dedicated_lag: Duration::from_secs(1),
+            max_private_age: Duration::from_millis(500),
+        }
+    }
+}
+
+impl FinalCheck {
+    // when an arb_item of `source` is no longer worth submitting, `None` if never
+    fn deadline(&self, source: &Source) -> Option<u64> {
+        match source {
+            Source::Private { received_at_ms, .. } => Some(received_at_ms + self.max_private_age.as_millis() as u64),
+            _ => source.deadline(),
+        }
+    }
+
+    // returns the realized profit of the final tx, or `ProfitRegressed` if it's too far below the estimated one.
+    // the final tx of a shio bid pays `bid_amount` out of the profit, so it's added back to the balance change.
+    fn check_profit(
+        &self,
+        balance_changes: &[BalanceChange],
+        sender: SuiAddress,
+        estimated: u64,
+        bid_amount: u64,
+        min_profit: u64,
+    ) -> Result<u64> {
+        let bc = balance_changes
+            .iter()
+            .find(|bc| bc.owner == Owner::AddressOwner(sender))
+            .ok_or_eyre("No balance change for attacker")?;
+
+        let realized = bc.amount + bid_amount as i128;
+        let min_realized = (estimated as u128 * self.min_profit_pct as u128 / 100).max(min_profit as u128);
+        if realized <= 0 || (realized as u128) < min_realized {
+            return Err(ProfitRegressed { estimated, realized }.into());
+        }
+
+        Ok(realized as u64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfitRegressed {
+    pub estimated: u64,
+    pub realized: i128,
+}
+
+impl fmt::Display for ProfitRegressed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "realized profit {} regressed from estimated {}",
+            self.realized, self.estimated
+        )
+    }
+}
+
+impl std::error::Error for ProfitRegressed {}
+
+pub enum WorkerMessage {
+    ArbItem(ArbItem),
+    /// the worker that receives it exits after its current arb_item
+    Shutdown,
 }
 
 impl Worker {
@@ -46,8 +155,19 @@ impl Worker {
     pub async fn run(mut self) -> Result<()> {
         loop {
             tokio::select! {
-                arb_item = self.arb_item_receiver.recv() => {
-                    if let Err(error) = self.handle_arb_item(arb_item.context("arb_item channel error")?).await {
+                msg = self.arb_item_receiver.recv() => {
+                    let arb_item = match msg.context("arb_item channel error")? {
+                        WorkerMessage::ArbItem(arb_item) => arb_item,
+                        WorkerMessage::Shutdown => {
+                            info!(worker.id = self.id, "Worker retired");
+                            return Ok(());
+                        }
+                    };
+
+                    self.busy_workers.fetch_add(1, Ordering::Relaxed);
+                    let result = self.handle_arb_item(arb_item).await;
+                    self.busy_workers.fetch_sub(1, Ordering::Relaxed);
+                    if let Err(error) = result {
                         error!(?error, "Handle arb_item failed");
                     }
                 }
@@ -62,44 +182,141 @@ impl Worker {
             coin,
             pool_id,
             tx_digest,
-            sim_ctx,
+            mut sim_ctx,
             source,
         } = arb_item;
 
+        // the trials and the final dry run of an opportunity see the same Clock, so deadline checks in Move
+        // code don't flip between them
+        if let Source::Shio { start, .. } = source {
+            sim_ctx.with_clock_timestamp_ms(start);
+        }
+
+        // private flow is only worth a short while, don't search the opportunities of a stale private tx, nor
+        // keep searching once it is
+        let deadline = self.final_check.deadline(&source);
+        if let (Source::Private { .. }, Err(error)) = (source, DeadlineExceeded::check(deadline)) {
+            debug!("⏰ Drop private arb_item: {error}");
+            self.deadline_exceeded.fetch_add(1, Ordering::Relaxed);
+            metrics().record_worker_result(self.id, source.name(), "deadline_exceeded");
+            return Ok(());
+        }
+
+        // the tx would be dropped, with its gas coin leased until the lease times out
+        if !source.is_shio() && !self.public_txs {
+            debug!("Drop arb_item without a public tx executor");
+            metrics().record_worker_result(self.id, source.name(), "no_public_executor");
+            return Ok(());
+        }
+
+        // every final tx leases its own gas coin from its sender, so that concurrent txs never use the same one
+        let gas_coins = self.senders.pick();
         if let Some((arb_result, elapsed)) = arbitrage_one_coin(
+            self.id,
             self.arb.clone(),
-            self.sender,
+            gas_coins.owner(),
             &coin,
             pool_id,
             sim_ctx.clone(),
             false,
             source,
+            deadline,
+            &self.deadline_exceeded,
         )
         .await
         {
-            let tx_data = match self.dry_run_tx_data(arb_result.tx_data.clone(), sim_ctx.clone()).await {
-                Ok(tx_data) => tx_data,
+            if arb_result.best_trial_result.profit < self.min_profit {
+                info!(
+                    profit = arb_result.best_trial_result.profit,
+                    min_profit = self.min_profit,
+                    "Profit below min_profit, skip"
+                );
+                metrics().record_worker_result(self.id, source.name(), "below_min_profit");
+                return Ok(());
+            }
+
+            let (tx_data, leased) = match self
+                .dry_run_tx_data(
+                    &gas_coins,
+                    arb_result.tx_data.clone(),
+                    sim_ctx.clone(),
+                    deadline,
+                    arb_result.best_trial_result.profit,
+                    arb_result.source,
+                )
+                .await
+            {
+                Ok(final_tx) => final_tx,
+                Err(error) if error.is::<DeadlineExceeded>() => {
+                    debug!(?arb_result, "⏰ Skip dry run: {error}");
+                    self.deadline_exceeded.fetch_add(1, Ordering::Relaxed);
+                    metrics().record_worker_result(self.id, source.name(), "deadline_exceeded");
+                    return Ok(());
+                }
+                Err(error) if error.is::<ProfitRegressed>() => {
+                    info!(?arb_result, "📉 Drop final tx: {error}");
+                    self.profit_regressed.fetch_add(1, Ordering::Relaxed);
+                    metrics().record_worker_result(self.id, source.name(), "profit_regressed");
+                    return Ok(());
+                }
+                Err(error)
+                    if error
+                        .downcast_ref::<SimulatorError>()
+                        .is_some_and(|e| matches!(e, SimulatorError::ExecutionFailure { .. })) =>
+                {
+                    // the state moved since the trials, e.g. the opportunity was taken
+                    info!(?arb_result, "Final tx failed in dry run: {error}");
+                    metrics().record_worker_result(self.id, source.name(), "dry_run_aborted");
+                    return Ok(());
+                }
                 Err(error) => {
+                    // e.g. the bid is on a shio global state that is gone since the last refresh
+                    if source.is_shio() {
+                        invalidate_shio_global_states_on(&error);
+                    }
                     error!(?arb_result, ?error, "Dry run final tx_data failed");
+                    metrics().record_worker_result(self.id, source.name(), "dry_run_failed");
                     return Ok(());
                 }
             };
 
+            if let Some(received_at_ms) = source.received_at_ms() {
+                metrics().record_reaction_latency(source.name(), received_at_ms);
+            }
+
             let arb_tx_digest = tx_data.digest();
             let action = match arb_result.source {
-                Source::Shio { bid_amount, .. } => Action::ShioSubmitBid((tx_data, bid_amount, tx_digest)),
+                Source::Shio {
+                    bid_amount, deadline, ..
+                } => Action::ShioSubmitBid((tx_data, bid_amount, tx_digest, deadline)),
                 _ => Action::ExecutePublicTx(tx_data),
             };
+            let shio_bid = match &action {
+                Action::ShioSubmitBid((_, bid_amount, opp_tx_digest, _)) => Some((*opp_tx_digest, *bid_amount)),
+                _ => None,
+            };
 
-            self.submitter.submit(action);
+            let submitted = submit_or_record(
+                self.submitter.as_ref(),
+                self.recorder.as_deref(),
+                action,
+                |action| DryRunRecord::new(action, tx_digest, arb_tx_digest, &arb_result, elapsed),
+                || new_tg_messages(tx_digest, arb_tx_digest, &arb_result, elapsed, &self.simulator_name),
+            );
+            let outcome = if submitted { "submitted" } else { "recorded" };
+            metrics().record_worker_result(self.id, source.name(), outcome);
 
-            let tg_msgs = new_tg_messages(tx_digest, arb_tx_digest, &arb_result, elapsed, &self.simulator_name);
-            for tg_msg in tg_msgs {
-                self.submitter.submit(tg_msg.into());
+            // a submitted public tx releases its gas coin in PublicTxExecutor, with the version from effects.
+            // we never know whether a bid is executed, so its gas coin is re-fetched on the next refresh.
+            if !submitted {
+                gas_coins.release_unused(&[leased]);
+            } else if let Some((opp_tx_digest, bid_amount)) = shio_bid {
+                gas_coins.release_unknown(&[leased]);
+                self.shio_bids.record(opp_tx_digest, bid_amount);
             }
 
             // notify dedicated simulator to update more frequently
-            if let Some(dedicated_sim) = &self.dedicated_simulator {
+            if let (true, Some(dedicated_sim)) = (submitted, &self.dedicated_simulator) {
                 dedicated_sim.update_notifier.send(()).await.unwrap();
             }
         }
@@ -107,43 +324,178 @@ impl Worker {
         Ok(())
     }
 
-    // return a final tx_data with latest versions
-    async fn dry_run_tx_data(&self, tx_data: TransactionData, sim_ctx: SimulateCtx) -> Result<TransactionData> {
-        let tx_data: TransactionData = self.fix_object_refs(tx_data).await?;
+    // return a final tx_data with latest versions, and the ref of the gas coin leased for it
+    async fn dry_run_tx_data(
+        &self,
+        gas_coins: &GasCoinManager,
+        tx_data: TransactionData,
+        sim_ctx: SimulateCtx,
+        deadline: Option<u64>,
+        estimated_profit: u64,
+        source: Source,
+    ) -> Result<(TransactionData, ObjectRef)> {
+        let (tx_data, leased) = self
+            .fix_object_refs(gas_coins, tx_data, &sim_ctx, source.opp_tx_digest())
+            .await?;
 
-        let resp = if let Some(dedicated_sim) = &self.dedicated_simulator {
-            dedicated_sim.simulate(tx_data.clone(), sim_ctx).await?
-        } else {
-            self.simulator_pool.get().simulate(tx_data.clone(), sim_ctx).await?
+        match self
+            .check_final_tx_data(&tx_data, sim_ctx, deadline, estimated_profit, source.bid_amount())
+            .await
+        {
+            Ok(()) => Ok((tx_data, leased)),
+            Err(error) => {
+                gas_coins.release_unused(&[leased]);
+                Err(error)
+            }
+        }
+    }
+
+    async fn check_final_tx_data(
+        &self,
+        tx_data: &TransactionData,
+        sim_ctx: SimulateCtx,
+        deadline: Option<u64>,
+        estimated_profit: u64,
+        bid_amount: u64,
+    ) -> Result<()> {
+        // the dedicated simulator is a shared and busy one, don't waste it on a bid that can't make it in time
+        let margin = self.final_check.deadline_margin_ms;
+        DeadlineExceeded::check(deadline.map(|deadline| deadline.saturating_sub(margin)))?;
+
+        // a lagging dedicated simulator runs on stale pools, the pooled simulators receive the object updates
+        let dedicated_sim = self.dedicated_simulator.as_ref().filter(|dedicated_sim| {
+            let lag = dedicated_sim.lag();
+            let lagging = lag > self.final_check.max_dedicated_lag;
+            if lagging {
+                warn!(
+                    worker.id = self.id,
+                    ?lag,
+                    "dedicated simulator is lagging, dry run on a pooled simulator"
+                );
+            }
+            !lagging
+        });
+
+        let pooled_simulator;
+        let simulator: &dyn Simulator = match dedicated_sim {
+            Some(dedicated_sim) => &**dedicated_sim,
+            None => {
+                // waits no longer than the margin before the deadline, like the check above
+                let deadline = deadline.map(|deadline| deadline.saturating_sub(margin));
+                pooled_simulator = acquire_before(&self.simulator_pool, deadline).await?;
+                &**pooled_simulator
+            }
         };
 
-        let status = &resp.effects.status();
-        ensure!(status.is_ok(), "Dry run result: {:?}", status);
+        final_dry_run(
+            simulator,
+            tx_data,
+            sim_ctx,
+            tx_data.sender(),
+            estimated_profit,
+            bid_amount,
+            self.min_profit,
+            &self.final_check,
+        )
+        .await
+        .map(|_| ())
+    }
 
-        let bc = &resp
-            .balance_changes
-            .into_iter()
-            .find(|bc| bc.owner == Owner::AddressOwner(self.sender))
-            .ok_or_eyre("No balance change for attacker")?;
-        ensure!(bc.amount > 0, "Attacker's balance not increased {:?}", bc);
+    // Lease a gas coin with its latest object ref.
+    // otherwise we need to wait until the index api to return the correct gas coins,
+    // and concurrent txs would equivocate on the same gas coin.
+    // The leased ref is returned as is, the payment may hold the version after the opportunity tx instead,
+    // which is not on chain yet and must not go back to the pool.
+    async fn fix_object_refs(
+        &self,
+        gas_coins: &GasCoinManager,
+        tx_data: TransactionData,
+        sim_ctx: &SimulateCtx,
+        opp_tx_digest: Option<TransactionDigest>,
+    ) -> Result<(TransactionData, ObjectRef)> {
+        let gas_coin = gas_coins.acquire().await?;
 
-        Ok(tx_data)
+        let tx_data = with_gas_payment(tx_data, latest_gas_coins(vec![gas_coin], sim_ctx), opp_tx_digest);
+        Ok((tx_data, gas_coin))
     }
+}
+
+// The payment is part of the digest, so a bid is ordered after its opportunity tx again once it is set.
+fn with_gas_payment(
+    mut tx_data: TransactionData,
+    payment: Vec<ObjectRef>,
+    opp_tx_digest: Option<TransactionDigest>,
+) -> TransactionData {
+    let gas_data: &mut GasData = tx_data.gas_data_mut();
+    gas_data.payment = payment;
+    order_after(&mut tx_data, opp_tx_digest);
 
-    // Fetch the latest object ref for gas coins.
-    // otherwise we need to wait until the index api to return the correct gas coins
-    async fn fix_object_refs(&self, tx_data: TransactionData) -> Result<TransactionData> {
-        let gas_coins = coin::get_gas_coin_refs(&self.sui, self.sender, None).await?;
+    tx_data
+}
 
-        let mut tx_data = tx_data;
-        let gas_data: &mut GasData = tx_data.gas_data_mut();
-        gas_data.payment = gas_coins;
+// Our tx is executed right after the opportunity tx (e.g. a shio bid), so a gas coin mutated by it
+// must be spent with its version after the opportunity, which is kept in the override objects.
+fn latest_gas_coins(gas_coins: Vec<ObjectRef>, sim_ctx: &SimulateCtx) -> Vec<ObjectRef> {
+    let latest_refs = sim_ctx
+        .override_objects
+        .iter()
+        .filter_map(|obj| match obj.input_object_kind {
+            InputObjectKind::ImmOrOwnedMoveObject(obj_ref) => Some((obj_ref.0, obj_ref)),
+            _ => None,
+        })
+        .collect::<HashMap<ObjectID, ObjectRef>>();
+
+    gas_coins
+        .into_iter()
+        .map(|coin| latest_refs.get(&coin.0).copied().unwrap_or(coin))
+        .collect()
+}
 
-        Ok(tx_data)
+// Re-simulates the final tx and returns its realized profit.
+async fn final_dry_run(
+    simulator: &dyn Simulator,
+    tx_data: &TransactionData,
+    sim_ctx: SimulateCtx,
+    sender: SuiAddress,
+    estimated_profit: u64,
+    bid_amount: u64,
+    min_profit: u64,
+    final_check: &FinalCheck,
+) -> Result<u64> {
+    metrics().simulations.with_label_values(&["final"]).inc();
+    let resp = simulator.simulate(tx_data.clone(), sim_ctx).await?;
+
+    resp.check_status()?;
+
+    final_check.check_profit(&resp.balance_changes, sender, estimated_profit, bid_amount, min_profit)
+}
+
+// Submits the action and its telegram notifications, or only records the action in dry-run mode.
+// Returns true if the action was submitted.
+fn submit_or_record(
+    submitter: &dyn ActionSubmitter<Action>,
+    recorder: Option<&RecordingExecutor>,
+    action: Action,
+    record: impl FnOnce(&Action) -> DryRunRecord,
+    tg_msgs: impl FnOnce() -> Vec<Message>,
+) -> bool {
+    if let Some(recorder) = recorder {
+        if let Err(error) = recorder.record(&record(&action)) {
+            error!(?error, "Record dry run action failed");
+        }
+        return false;
+    }
+
+    submitter.submit(action);
+    for tg_msg in tg_msgs() {
+        submitter.submit(tg_msg.into());
     }
+    true
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn arbitrage_one_coin(
+    worker: usize,
     arb: Arc<Arb>,
     attacker: SuiAddress,
     coin_type: &str,
@@ -151,14 +503,37 @@ async fn arbitrage_one_coin(
     sim_ctx: SimulateCtx,
     use_gss: bool,
     source: Source,
+    deadline: Option<u64>,
+    deadline_exceeded: &AtomicU64,
 ) -> Option<(ArbResult, Duration)> {
     let start = Instant::now();
-    let arb_result = match arb
-        .find_opportunity(attacker, coin_type, pool_id, vec![], sim_ctx, use_gss, source)
-        .await
-    {
+    let arb_result = arb
+        .find_opportunity_before(attacker, coin_type, pool_id, vec![], sim_ctx, use_gss, source, deadline)
+        .await;
+    metrics()
+        .trial_duration
+        .with_label_values(&[worker.to_string().as_str(), source.name()])
+        .observe(start.elapsed().as_secs_f64());
+
+    let arb_result = match arb_result {
         Ok(r) => r,
+        Err(error) if error.is::<DeadlineExceeded>() => {
+            debug!(elapsed = ?start.elapsed(), %coin_type, "⏰ Trial aborted: {error}");
+            deadline_exceeded.fetch_add(1, Ordering::Relaxed);
+            metrics().record_worker_result(worker, source.name(), "deadline_exceeded");
+            return None;
+        }
+        Err(error) if error.is::<PoolPaused>() => {
+            info!(elapsed = ?start.elapsed(), %coin_type, "⏸️ Skip opportunity: {error}");
+            // don't hand out the paused pool again until it is rebuilt
+            if let Some(paused) = error.downcast_ref::<PoolPaused>() {
+                dex_cache().invalidate_pool(paused.pool_id);
+            }
+            metrics().record_worker_result(worker, source.name(), "pool_paused");
+            return None;
+        }
         Err(error) => {
+            metrics().record_worker_result(worker, source.name(), "no_opportunity");
             let elapsed = start.elapsed();
             if elapsed > Duration::from_secs(1) {
                 info!(elapsed = ?elapsed, %coin_type, "🥱 \x1b[31mNo opportunity: {error:#}\x1b[0m");
@@ -182,3 +557,315 @@ async fn arbitrage_one_coin(
 
     Some((arb_result, start.elapsed()))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::atomic::AtomicUsize};
+
+    use fastcrypto::encoding::{Base64, Encoding};
+    use shio::ShioItem;
+    use simulator::{HttpSimulator, SimulateResult};
+    use sui_sdk::SuiClientBuilder;
+    use sui_types::{
+        base_types::{random_object_ref, SequenceNumber},
+        digests::ObjectDigest,
+        gas_coin::GAS,
+        object::Object,
+        programmable_transaction_builder::ProgrammableTransactionBuilder,
+    };
+    use utils::coin::{self, GasCoinFilter};
+
+    use super::*;
+    use crate::{
+        common::get_latest_epoch,
+        config::tests::{TEST_ATTACKER, TEST_HTTP_URL},
+        strategy::new_object_read_result,
+    };
+
+    // simulates the tx with the inner simulator, but reports a fixed profit for the sender
+    struct DegradedSimulator {
+        inner: HttpSimulator,
+        sender: SuiAddress,
+        realized_profit: i128,
+    }
+
+    #[async_trait::async_trait]
+    impl Simulator for DegradedSimulator {
+        async fn simulate(&self, tx: TransactionData, ctx: SimulateCtx) -> Result<SimulateResult> {
+            let mut resp = self.inner.simulate(tx, ctx).await?;
+            resp.balance_changes = vec![BalanceChange {
+                owner: Owner::AddressOwner(self.sender),
+                coin_type: GAS::type_tag(),
+                amount: self.realized_profit,
+            }];
+            Ok(resp)
+        }
+
+        async fn get_object(&self, obj_id: &ObjectID) -> Option<Object> {
+            self.inner.get_object(obj_id).await
+        }
+
+        fn name(&self) -> &str {
+            "DegradedSimulator"
+        }
+    }
+
+    fn balance_change(owner: SuiAddress, amount: i128) -> BalanceChange {
+        BalanceChange {
+            owner: Owner::AddressOwner(owner),
+            coin_type: GAS::type_tag(),
+            amount,
+        }
+    }
+
+    #[derive(Default)]
+    struct CountingSubmitter {
+        submitted: AtomicUsize,
+    }
+
+    impl ActionSubmitter<Action> for CountingSubmitter {
+        fn submit(&self, _action: Action) {
+            self.submitted.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_dry_run_submits_nothing() {
+        let path = std::env::temp_dir().join(format!("arb-dry-run-{}.jsonl", utils::current_time_ms()));
+        let recorder = RecordingExecutor::new(path.to_str().unwrap()).unwrap();
+        let submitter = CountingSubmitter::default();
+
+        let sender = SuiAddress::random_for_testing_only();
+        let tx_data = TransactionData::new_transfer_sui(sender, sender, Some(1), random_object_ref(), 1_000_000, 750);
+        let action = Action::ShioSubmitBid((tx_data, 100, TransactionDigest::random(), 0));
+
+        let submitted = submit_or_record(
+            &submitter,
+            Some(&recorder),
+            action,
+            |_| DryRunRecord {
+                action: "ShioSubmitBid".to_string(),
+                bid_amount: 100,
+                ..Default::default()
+            },
+            Vec::new,
+        );
+
+        assert!(!submitted);
+        assert_eq!(submitter.submitted.load(Ordering::Relaxed), 0);
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines.lines().count(), 1);
+        assert!(lines.contains(r#""bid_amount":100"#));
+    }
+
+    #[test]
+    fn test_check_profit() {
+        let sender = SuiAddress::random_for_testing_only();
+        let final_check = FinalCheck::default();
+        let estimated = 500_000_000;
+
+        let check = |amount: i128, min_profit: u64| {
+            final_check.check_profit(&[balance_change(sender, amount)], sender, estimated, 0, min_profit)
+        };
+
+        assert_eq!(check(450_000_000, 0).unwrap(), 450_000_000);
+        assert_eq!(check(400_000_000, 0).unwrap(), 400_000_000);
+
+        let error = check(399_999_999, 0).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ProfitRegressed>(),
+            Some(&ProfitRegressed {
+                estimated,
+                realized: 399_999_999
+            })
+        );
+        assert!(check(-1_000, 0).unwrap_err().is::<ProfitRegressed>());
+        // above the ratio, but below min_profit
+        assert!(check(450_000_000, 460_000_000).unwrap_err().is::<ProfitRegressed>());
+
+        let other = SuiAddress::random_for_testing_only();
+        let error = final_check
+            .check_profit(&[balance_change(other, 450_000_000)], sender, estimated, 0, 0)
+            .unwrap_err();
+        assert!(!error.is::<ProfitRegressed>());
+    }
+
+    #[test]
+    fn test_check_profit_of_shio_bid() {
+        let sender = SuiAddress::random_for_testing_only();
+        let final_check = FinalCheck::default();
+        let estimated = 500_000_000;
+        let shio = Source::Shio {
+            opp_tx_digest: TransactionDigest::random(),
+            bid_amount: 0,
+            start: 1_000,
+            arb_found: 0,
+            deadline: 1_200,
+        }
+        .with_bid_amount(estimated / 10 * 9);
+
+        // the final tx pays the bid, only the rest of the profit is left to the sender
+        let check = |amount: i128| {
+            final_check.check_profit(
+                &[balance_change(sender, amount)],
+                sender,
+                estimated,
+                shio.bid_amount(),
+                0,
+            )
+        };
+        assert_eq!(check(50_000_000).unwrap(), estimated);
+        assert_eq!(check(0).unwrap(), 450_000_000);
+
+        let error = check(-60_000_000).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ProfitRegressed>(),
+            Some(&ProfitRegressed {
+                estimated,
+                realized: 390_000_000
+            })
+        );
+    }
+
+    #[test]
+    fn test_private_arb_item_dropped_by_age() {
+        let final_check = FinalCheck {
+            max_private_age: Duration::from_millis(300),
+            ..Default::default()
+        };
+        let private = Source::Private {
+            opp_tx_digest: TransactionDigest::random(),
+            received_at_ms: 1_000,
+        };
+        let check_at = |source: &Source, now: u64| DeadlineExceeded::check_at(final_check.deadline(source), now);
+
+        assert!(check_at(&private, 1_000).is_ok());
+        assert!(check_at(&private, 1_299).is_ok());
+        assert_eq!(
+            check_at(&private, 1_300),
+            Err(DeadlineExceeded {
+                deadline: 1_300,
+                now: 1_300
+            })
+        );
+
+        // the other sources keep their own deadline, if any
+        assert!(check_at(&Source::Public, u64::MAX).is_ok());
+        let shio = Source::Shio {
+            opp_tx_digest: TransactionDigest::random(),
+            bid_amount: 0,
+            start: 1_000,
+            arb_found: 0,
+            deadline: 1_200,
+        };
+        assert_eq!(final_check.deadline(&shio), Some(1_200));
+    }
+
+    #[test]
+    fn test_gas_coin_mutated_by_shio_opportunity() {
+        let owner = SuiAddress::random_for_testing_only();
+        let mutated = Object::new_gas_with_balance_and_owner_for_testing(1_000_000_000, owner);
+        let gas_coins = vec![mutated.compute_object_reference(), random_object_ref()];
+
+        let version = SequenceNumber::from_u64(mutated.version().value() + 1);
+        let digest = ObjectDigest::random();
+        let move_obj = mutated.data.try_as_move().unwrap();
+        let shio_item = ShioItem::from(serde_json::json!({
+            "auctionStarted": {
+                "txDigest": TransactionDigest::random().to_string(),
+                "gasPrice": 750,
+                "deadlineTimestampMs": 0,
+                "sideEffects": {
+                    "mutatedObjects": [{
+                        "id": mutated.id().to_string(),
+                        "objectType": serde_json::to_string(move_obj.type_()).unwrap(),
+                        "owner": serde_json::to_value(Owner::AddressOwner(owner)).unwrap(),
+                        "content": { "dataType": "moveObject", "hasPublicTransfer": true },
+                        "objectBcs": Base64::encode(move_obj.contents()),
+                        "version": version.value().to_string(),
+                        "digest": digest.to_string(),
+                    }],
+                    "gasUsage": 0,
+                },
+            },
+        }));
+        assert_eq!(shio_item.type_name(), "auctionStarted");
+
+        let tx_digest = TransactionDigest::from_str(shio_item.tx_digest()).unwrap();
+        let sim_ctx = SimulateCtx {
+            override_objects: shio_item
+                .created_mutated_objects()
+                .into_iter()
+                .map(|obj| new_object_read_result(tx_digest, obj))
+                .collect::<Result<_>>()
+                .unwrap(),
+            ..Default::default()
+        };
+
+        let latest = latest_gas_coins(gas_coins.clone(), &sim_ctx);
+        assert_eq!(latest[0], (mutated.id(), version, digest));
+        assert_eq!(latest[1], gas_coins[1]);
+    }
+
+    #[test]
+    fn test_final_bid_is_ordered_after_opportunity() {
+        let sender = SuiAddress::random_for_testing_only();
+        let mut ptb = ProgrammableTransactionBuilder::new();
+        ptb.transfer_sui(sender, Some(1));
+        let pt = ptb.finish();
+
+        // the bids are built without gas coins, the leased one is only set on the final tx
+        let opp_tx_digest = TransactionDigest::new([0xf0; 32]);
+        for _ in 0..8 {
+            let mut tx_data = TransactionData::new_programmable(sender, vec![], pt.clone(), 1_000_000, 1000);
+            order_after(&mut tx_data, Some(opp_tx_digest));
+            let gas_coin = random_object_ref();
+
+            let tx_data = with_gas_payment(tx_data, vec![gas_coin], Some(opp_tx_digest));
+            assert_eq!(tx_data.gas(), &[gas_coin]);
+            assert!(tx_data.digest() > opp_tx_digest);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_final_dry_run_with_degraded_profit() {
+        let sender = SuiAddress::from_str(TEST_ATTACKER).unwrap();
+        let sui = SuiClientBuilder::default().build(TEST_HTTP_URL).await.unwrap();
+        let epoch = get_latest_epoch(&sui).await.unwrap();
+        let gas_coin = coin::get_gas_coin_refs(&sui, sender, &GasCoinFilter::default())
+            .await
+            .unwrap()[0];
+
+        let gas_price = sui.read_api().get_reference_gas_price().await.unwrap();
+        let tx_data = TransactionData::new_transfer_sui(sender, sender, Some(1), gas_coin, 10_000_000, gas_price);
+        let estimated = 500_000_000;
+
+        for (realized_profit, accepted) in [(450_000_000, true), (300_000_000, false)] {
+            let simulator = DegradedSimulator {
+                inner: HttpSimulator::new(TEST_HTTP_URL, &None).await,
+                sender,
+                realized_profit,
+            };
+
+            let result = final_dry_run(
+                &simulator,
+                &tx_data,
+                SimulateCtx::new(epoch, vec![]),
+                sender,
+                estimated,
+                0,
+                0,
+                &FinalCheck::default(),
+            )
+            .await;
+
+            if accepted {
+                assert_eq!(result.unwrap(), realized_profit as u64);
+            } else {
+                assert!(result.unwrap_err().is::<ProfitRegressed>());
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Helpers shared by the tests of the crate: doctored pools and objects, and simulators that count or mock
+//! what is read.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use dex_indexer::types::{Pool, PoolExtra, Protocol, Token};
+use eyre::Result;
+use move_core_types::{
+    account_address::AccountAddress,
+    annotated_value::{MoveStruct, MoveStructLayout, MoveValue},
+    identifier::Identifier,
+    language_storage::StructTag,
+};
+use object_pool::ObjectPool;
+use simulator::{HttpSimulator, SimulateCtx, SimulateResult, Simulator};
+use sui_types::{
+    base_types::{ObjectID, SequenceNumber, SuiAddress},
+    object::Object,
+    transaction::{ObjectArg, TransactionData},
+};
+
+use crate::config::tests::TEST_HTTP_URL;
+
+/// A `module::name` Move struct with `fields`, as parsed from a doctored object.
+pub fn move_struct(module: &str, name: &str, fields: Vec<(&str, MoveValue)>) -> MoveStruct {
+    let type_ = StructTag {
+        address: AccountAddress::ONE,
+        module: Identifier::new(module).unwrap(),
+        name: Identifier::new(name).unwrap(),
+        type_params: vec![],
+    };
+    let fields = fields
+        .into_iter()
+        .map(|(name, value)| (Identifier::new(name).unwrap(), value))
+        .collect();
+    MoveStruct::new(type_, fields)
+}
+
+/// A mutable shared object argument of `id`.
+pub fn shared_object(id: ObjectID) -> ObjectArg {
+    ObjectArg::SharedObject {
+        id,
+        initial_shared_version: SequenceNumber::from_u64(1),
+        mutable: true,
+    }
+}
+
+/// A `protocol` pool of `tokens` with a random id.
+pub fn random_pool(protocol: Protocol, tokens: Vec<Token>, extra: PoolExtra) -> Pool {
+    Pool {
+        protocol,
+        pool: ObjectID::random(),
+        tokens,
+        extra,
+    }
+}
+
+/// The simulations and the object fetches of the `CountingSimulator`s of a pool.
+#[derive(Debug, Clone, Default)]
+pub struct SimulatorCounts {
+    simulations: Arc<AtomicUsize>,
+    get_objects: Arc<AtomicUsize>,
+}
+
+impl SimulatorCounts {
+    pub fn simulations(&self) -> usize {
+        self.simulations.load(Ordering::Relaxed)
+    }
+
+    pub fn get_objects(&self) -> usize {
+        self.get_objects.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts the simulations and the object fetches of an `HttpSimulator` over `TEST_HTTP_URL`.
+pub struct CountingSimulator {
+    inner: HttpSimulator,
+    counts: SimulatorCounts,
+}
+
+impl CountingSimulator {
+    /// A pool of `num_simulators`, which share their counts.
+    pub fn new_pool(num_simulators: usize) -> (ObjectPool<Box<dyn Simulator>>, SimulatorCounts) {
+        let counts = SimulatorCounts::default();
+        let simulator_pool = ObjectPool::new_async(num_simulators, {
+            let counts = counts.clone();
+            move || {
+                let counts = counts.clone();
+                async move {
+                    let inner = HttpSimulator::new(TEST_HTTP_URL, &None).await;
+                    Box::new(CountingSimulator { inner, counts }) as Box<dyn Simulator>
+                }
+            }
+        });
+        (simulator_pool, counts)
+    }
+}
+
+#[async_trait::async_trait]
+impl Simulator for CountingSimulator {
+    async fn simulate(&self, tx: TransactionData, ctx: SimulateCtx) -> Result<SimulateResult> {
+        self.counts.simulations.fetch_add(1, Ordering::Relaxed);
+        self.inner.simulate(tx, ctx).await
+    }
+
+    async fn get_object(&self, obj_id: &ObjectID) -> Option<Object> {
+        self.counts.get_objects.fetch_add(1, Ordering::Relaxed);
+        self.inner.get_object(obj_id).await
+    }
+
+    fn name(&self) -> &str {
+        "CountingSimulator"
+    }
+
+    fn get_object_layout(&self, obj_id: &ObjectID) -> Option<MoveStructLayout> {
+        self.inner.get_object_layout(obj_id)
+    }
+}
+
+/// Serves the pool objects at their versions to `multi_get_objects`, and records the objects read one by one,
+/// as when enumerating the children of a pool, which it never finds.
+#[derive(Default)]
+pub struct MockSimulator {
+    pools: HashMap<ObjectID, Object>,
+    pub enumerated_reads: Mutex<Vec<ObjectID>>,
+}
+
+impl MockSimulator {
+    pub fn with_pool(mut self, pool_id: ObjectID, version: u64) -> Self {
+        let object =
+            Object::with_id_owner_version_for_testing(pool_id, SequenceNumber::from_u64(version), SuiAddress::ZERO);
+        self.pools.insert(pool_id, object);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Simulator for MockSimulator {
+    async fn simulate(&self, _: TransactionData, _: SimulateCtx) -> Result<SimulateResult> {
+        eyre::bail!("MockSimulator can't simulate")
+    }
+
+    async fn get_object(&self, obj_id: &ObjectID) -> Option<Object> {
+        self.enumerated_reads.lock().unwrap().push(*obj_id);
+        None
+    }
+
+    async fn multi_get_objects(&self, ids: &[ObjectID]) -> Vec<Option<Object>> {
+        ids.iter().map(|id| self.pools.get(id).cloned()).collect()
+    }
+
+    fn name(&self) -> &str {
+        "MockSimulator"
+    }
+}
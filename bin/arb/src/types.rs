@@ -1,9 +1,14 @@
-use std::fmt;
+use std::{fmt, str::FromStr, time::Duration};
 
 use burberry::executor::telegram_message::Message;
+use dex_indexer::types::Protocol;
+use object_pool::{ObjectPool, PoolGuard};
+use serde::Serialize;
 use shio::ShioItem;
+use simulator::MoveAbortLocation;
 use sui_json_rpc_types::{SuiEvent, SuiTransactionBlockEffects};
-use sui_types::{digests::TransactionDigest, transaction::TransactionData};
+use sui_types::{base_types::ObjectID, digests::TransactionDigest, transaction::TransactionData};
+use tracing::warn;
 
 /*
 Action 枚举定义了执行器可以执行的操作，包括通知、执行公共交易和提交Shio出价。
@@ -13,7 +18,8 @@ Action 枚举定义了执行器可以执行的操作，包括通知、执行公
 pub enum Action {
     NotifyViaTelegram(Message),
     ExecutePublicTx(TransactionData),
-    ShioSubmitBid((TransactionData, u64, TransactionDigest)),
+    // (tx_data, bid_amount, opp_tx_digest, deadline_ms)
+    ShioSubmitBid((TransactionData, u64, TransactionDigest, u64)),
 }
 
 impl From<Message> for Action {
@@ -28,9 +34,9 @@ impl From<TransactionData> for Action {
     }
 }
 
-impl From<(TransactionData, u64, TransactionDigest)> for Action {
-    fn from((tx_data, bid_amount, opp_tx_digest): (TransactionData, u64, TransactionDigest)) -> Self {
-        Self::ShioSubmitBid((tx_data, bid_amount, opp_tx_digest))
+impl From<(TransactionData, u64, TransactionDigest, u64)> for Action {
+    fn from(bid: (TransactionData, u64, TransactionDigest, u64)) -> Self {
+        Self::ShioSubmitBid(bid)
     }
 }
 
@@ -38,13 +44,58 @@ impl From<(TransactionData, u64, TransactionDigest)> for Action {
 #[derive(Clone, Debug)]
 pub enum Event {
     PublicTx(SuiTransactionBlockEffects, Vec<SuiEvent>),
-    PrivateTx(TransactionData),
+    // (tx_data, received_at_ms), as received from the relay
+    PrivateTx(TransactionData, u64),
     Shio(ShioItem),
+    // the settled auction of a shio opportunity
+    ShioResult {
+        opp_tx_digest: TransactionDigest,
+        winning_bid_amount: u64,
+    },
+}
+
+impl From<ShioItem> for Event {
+    fn from(item: ShioItem) -> Self {
+        if let ShioItem::AuctionEnded {
+            tx_digest,
+            winning_bid_amount,
+        } = &item
+        {
+            match TransactionDigest::from_str(tx_digest) {
+                Ok(opp_tx_digest) => {
+                    return Self::ShioResult {
+                        opp_tx_digest,
+                        winning_bid_amount: *winning_bid_amount,
+                    }
+                }
+                Err(error) => warn!(?item, ?error, "invalid auction end"),
+            }
+        }
+
+        Self::Shio(item)
+    }
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+impl Event {
+    /// The source of the event, e.g. for metric labels.
+    pub fn source_name(&self) -> &'static str {
+        match self {
+            Event::PublicTx(..) => "public",
+            Event::PrivateTx(..) => "private",
+            Event::Shio(_) => "shio",
+            Event::ShioResult { .. } => "shio_result",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize)]
 pub enum Source {
     Public,
+    // a transaction received privately (e.g. from the relay), not yet executed on chain
+    Private {
+        opp_tx_digest: TransactionDigest,
+        received_at_ms: u64,
+    },
     Shio {
         opp_tx_digest: TransactionDigest,
         bid_amount: u64,
@@ -63,6 +114,10 @@ impl fmt::Display for Source {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Source::Public => write!(f, "Public"),
+            Source::Private {
+                opp_tx_digest,
+                received_at_ms,
+            } => write!(f, "Private(tx={}, received={})", opp_tx_digest, *received_at_ms),
             Source::Shio {
                 start,
                 arb_found,
@@ -94,11 +149,121 @@ impl fmt::Display for Source {
     }
 }
 
+/// Returned when the search for an opportunity is aborted because the shio deadline has passed,
+/// so a bid could no longer be submitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineExceeded {
+    pub deadline: u64,
+    pub now: u64,
+}
+
+impl DeadlineExceeded {
+    /// `deadline` is a timestamp in ms, `None` means no deadline.
+    pub fn check(deadline: Option<u64>) -> Result<(), Self> {
+        Self::check_at(deadline, utils::current_time_ms())
+    }
+
+    pub(crate) fn check_at(deadline: Option<u64>, now: u64) -> Result<(), Self> {
+        match deadline {
+            Some(deadline) if now >= deadline => Err(Self { deadline, now }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "deadline exceeded by {}ms (deadline={}, now={})",
+            self.now - self.deadline,
+            self.deadline,
+            self.now
+        )
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// Returned when a pool of a trade path is found paused (or locked) while its state is read, so a trade
+/// through it would abort.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolPaused {
+    pub pool_id: ObjectID,
+    pub protocol: Protocol,
+}
+
+impl fmt::Display for PoolPaused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} pool {} is paused", self.protocol, self.pool_id)
+    }
+}
+
+impl std::error::Error for PoolPaused {}
+
+/// Wraps the `SimulatorError` of a trial that aborted in the commands of a hop of its path, outside of the
+/// Move and Sui frameworks, so the abort is the pool's own doing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HopAborted {
+    pub hop: usize,
+    pub pool_id: ObjectID,
+    pub protocol: Protocol,
+    pub location: MoveAbortLocation,
+}
+
+impl fmt::Display for HopAborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hop {} through {} pool {} aborted in {}::{}::{} with code {}",
+            self.hop,
+            self.protocol,
+            self.pool_id,
+            self.location.package,
+            self.location.module,
+            self.location.function.as_deref().unwrap_or("?"),
+            self.location.abort_code
+        )
+    }
+}
+
+impl std::error::Error for HopAborted {}
+
+/// Leases an object of `pool`, waiting in line for one until `deadline`, without limit if `None`.
+pub async fn acquire_before<T>(pool: &ObjectPool<T>, deadline: Option<u64>) -> Result<PoolGuard<T>, DeadlineExceeded>
+where
+    T: Send + Sync + 'static,
+{
+    let Some(deadline) = deadline else {
+        return Ok(pool.acquire().await);
+    };
+
+    let wait = Duration::from_millis(deadline.saturating_sub(utils::current_time_ms()));
+    match pool.acquire_timeout(wait).await {
+        Some(guard) => Ok(guard),
+        None => Err(DeadlineExceeded {
+            deadline,
+            now: utils::current_time_ms().max(deadline),
+        }),
+    }
+}
+
 impl Source {
+    /// A short name of the source, e.g. for metric labels.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Source::Public => "public",
+            Source::Private { .. } => "private",
+            Source::Shio { .. } => "shio",
+            Source::ShioDeadlineMissed { .. } => "shio_deadline_missed",
+        }
+    }
+
     pub fn is_shio(&self) -> bool {
         matches!(self, Source::Shio { .. })
     }
 
+    /// The shio opportunity a bid must be ordered after.
     pub fn opp_tx_digest(&self) -> Option<TransactionDigest> {
         match self {
             Source::Shio { opp_tx_digest, .. } => Some(*opp_tx_digest),
@@ -106,6 +271,15 @@ impl Source {
         }
     }
 
+    /// When the opportunity was received, in ms. Public txs are only seen once executed, so they have none.
+    pub fn received_at_ms(&self) -> Option<u64> {
+        match self {
+            Source::Public => None,
+            Source::Private { received_at_ms, .. } => Some(*received_at_ms),
+            Source::Shio { start, .. } | Source::ShioDeadlineMissed { start, .. } => Some(*start),
+        }
+    }
+
     pub fn deadline(&self) -> Option<u64> {
         match self {
             Source::Shio { deadline, .. } => Some(*deadline),
@@ -168,3 +342,59 @@ impl Source {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deadline_exceeded() {
+        assert!(DeadlineExceeded::check_at(None, 1_000).is_ok());
+        assert!(DeadlineExceeded::check_at(Some(1_001), 1_000).is_ok());
+        assert_eq!(
+            DeadlineExceeded::check_at(Some(1_000), 1_000),
+            Err(DeadlineExceeded {
+                deadline: 1_000,
+                now: 1_000
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_acquire_before() {
+        let pool = ObjectPool::new(1, || ());
+        let guard = acquire_before(&pool, None).await.unwrap();
+
+        // the only object is leased until after the deadline
+        let deadline = utils::current_time_ms() + 50;
+        let error = acquire_before(&pool, Some(deadline)).await.unwrap_err();
+        assert_eq!(error.deadline, deadline);
+        assert!(error.now >= deadline);
+
+        drop(guard);
+        assert!(acquire_before(&pool, Some(utils::current_time_ms() + 50)).await.is_ok());
+    }
+
+    #[test]
+    fn test_auction_ended_to_event() {
+        let opp_tx_digest = TransactionDigest::random();
+        let item = ShioItem::from(serde_json::json!({
+            "auctionEnded": {"txDigest": opp_tx_digest.to_string(), "winningBidAmount": 42}
+        }));
+
+        let Event::ShioResult {
+            opp_tx_digest: digest,
+            winning_bid_amount,
+        } = Event::from(item)
+        else {
+            panic!("expected a shio result");
+        };
+        assert_eq!((digest, winning_bid_amount), (opp_tx_digest, 42));
+
+        // an auction end with an invalid digest is passed through as is
+        let item = ShioItem::from(serde_json::json!({
+            "auctionEnded": {"txDigest": "nothing", "winningBidAmount": 42}
+        }));
+        assert!(matches!(Event::from(item), Event::Shio(ShioItem::AuctionEnded { .. })));
+    }
+}
@@ -1,9 +1,21 @@
+mod metrics;
+mod replay;
+mod subscription;
+
 use async_trait::async_trait;
+use clap::Parser;
+use eyre::{bail, ensure, eyre, Result, WrapErr};
 use fastcrypto::encoding::Base64;
-use futures::SinkExt;
+use futures::{Sink, SinkExt};
 use futures_util::stream::StreamExt;
+use mysten_network::Multiaddr;
 use serde::Serialize;
-use sui_network::api::{Validator, ValidatorServer};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use sui_network::api::{Validator, ValidatorClient, ValidatorServer};
 use sui_types::{
     crypto::ToFromBytes,
     messages_checkpoint::{CheckpointRequest, CheckpointRequestV2, CheckpointResponse, CheckpointResponseV2},
@@ -14,48 +26,364 @@ use sui_types::{
         SubmitCertificateResponse, SystemStateRequest, TransactionInfoRequest, TransactionInfoResponse,
     },
     sui_system_state::SuiSystemState,
-    transaction::{CertifiedTransaction, Transaction},
+    transaction::{CertifiedTransaction, SenderSignedData, Transaction},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{
+        broadcast::{self, error::RecvError},
+        OwnedSemaphorePermit, Semaphore,
+    },
+};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        self,
+        handshake::server::{ErrorResponse, Request, Response},
+        http::StatusCode,
+        protocol::{frame::coding::CloseCode, CloseFrame, Message},
+    },
+};
+use tonic::transport::{Channel, Endpoint};
+use tracing::{debug, error, info, warn};
+use utils::metrics_server;
+
+use crate::{
+    metrics::{ClientGuard, RelayMetrics},
+    replay::ReplayBuffer,
+    subscription::{SubscriberMessage, TxFilter},
 };
-use tokio::{net::TcpListener, sync::watch};
-use tokio_tungstenite::{accept_async, tungstenite::protocol::Message};
-use tracing::{debug, error, info};
 
-const RELAY_SERVER_URL: &str = "/ip4/0.0.0.0/tcp/9000/http";
-const WS_SERVER_URL: &str = "0.0.0.0:9001";
+pub const BUILD_VERSION: &str = version::build_version!();
+
+const DEFAULT_GRPC_BIND: &str = "/ip4/0.0.0.0/tcp/9000/http";
+const DEFAULT_WS_BIND: &str = "0.0.0.0:9001";
+
+/// How many transactions a subscriber can fall behind before it misses some.
+const DEFAULT_BROADCAST_CAPACITY: usize = 1024;
+const DEFAULT_MAX_WS_CLIENTS: usize = 256;
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// Where the validator gRPC service listens, as a multiaddr.
+    #[arg(long, env = "RELAY_GRPC_BIND", default_value = DEFAULT_GRPC_BIND, value_parser = parse_multiaddr)]
+    grpc_bind: Multiaddr,
+
+    /// Where the WebSocket subscribers connect, e.g. `127.0.0.1:9001` behind a proxy.
+    #[arg(long, env = "RELAY_WS_BIND", default_value = DEFAULT_WS_BIND)]
+    ws_bind: SocketAddr,
+
+    /// The subscribers past this number are turned away.
+    #[arg(long, env = "RELAY_MAX_WS_CLIENTS", default_value_t = DEFAULT_MAX_WS_CLIENTS)]
+    max_ws_clients: usize,
+
+    #[arg(long, alias = "ws-capacity", env = "RELAY_BROADCAST_CAPACITY", default_value_t = DEFAULT_BROADCAST_CAPACITY)]
+    broadcast_capacity: usize,
+
+    /// How many recent transactions are kept for the replay requests, none with 0.
+    #[arg(long, env = "RELAY_REPLAY_MAX_MESSAGES", default_value_t = replay::DEFAULT_REPLAY_MAX_MESSAGES)]
+    replay_max_messages: usize,
+
+    #[arg(long, env = "RELAY_REPLAY_MAX_BYTES", default_value_t = replay::DEFAULT_REPLAY_MAX_BYTES)]
+    replay_max_bytes: usize,
+
+    #[arg(long, env = "RELAY_REPLAY_MAX_AGE_SECS", default_value_t = replay::DEFAULT_REPLAY_MAX_AGE.as_secs())]
+    replay_max_age_secs: u64,
+
+    /// The token the WebSocket subscribers must give, as `?token=` or `Authorization: Bearer`, none if unset.
+    #[arg(long, env = "RELAY_WS_TOKEN")]
+    ws_token: Option<String>,
+
+    /// The gRPC url of a validator or fullnode, e.g. `http://localhost:8080`, to forward the requests to.
+    #[arg(long, env = "RELAY_UPSTREAM")]
+    upstream: Option<String>,
+
+    /// Serve the prometheus metrics on `GET /metrics` at this address, e.g. `0.0.0.0:9184`.
+    #[arg(long, env = "RELAY_METRICS_BIND")]
+    metrics_bind: Option<SocketAddr>,
+}
+
+impl Args {
+    fn validate(&self) -> Result<()> {
+        ensure!(self.broadcast_capacity > 0, "--broadcast-capacity must be positive");
+        ensure!(self.max_ws_clients > 0, "--max-ws-clients must be positive");
+        Ok(())
+    }
+}
+
+fn parse_multiaddr(addr: &str) -> Result<Multiaddr, String> {
+    addr.parse().map_err(|e| format!("invalid multiaddr: {e}"))
+}
+
+/// Who may subscribe to the WebSocket server.
+#[derive(Debug, Clone)]
+pub struct WsConfig {
+    pub token: Option<String>,
+    pub max_clients: usize,
+}
 
-#[derive(Debug, Clone, Serialize, Default)]
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            token: None,
+            max_clients: DEFAULT_MAX_WS_CLIENTS,
+        }
+    }
+}
+
+/// How a transaction was submitted to the relay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxKind {
+    Tx,
+    Cert,
+    Bundle,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct TxMessage {
     tx_bytes: String,
     signatures: Vec<String>,
+    kind: TxKind,
+    /// The position of the transaction in its soft bundle.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bundle_index: Option<usize>,
+    /// Increases with every broadcast transaction, for the subscribers to drop the ones they already got.
+    seq: u64,
+    /// Sent again for a replay request.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    replayed: bool,
 }
 
+impl TxMessage {
+    pub fn new(data: &SenderSignedData, kind: TxKind) -> Self {
+        let tx_bytes = Base64::from_bytes(&bcs::to_bytes(data.transaction_data()).unwrap()).encoded();
+        let signatures: Vec<String> = data
+            .tx_signatures()
+            .iter()
+            .map(|s| Base64::from_bytes(s.as_bytes()).encoded())
+            .collect();
+
+        TxMessage {
+            tx_bytes,
+            signatures,
+            kind,
+            bundle_index: None,
+            seq: 0,
+            replayed: false,
+        }
+    }
+
+    /// One message per certificate of the bundle, in order.
+    pub fn from_bundle(certificates: &[CertifiedTransaction]) -> Vec<Self> {
+        certificates
+            .iter()
+            .enumerate()
+            .map(|(i, certificate)| TxMessage {
+                bundle_index: Some(i),
+                ..TxMessage::new(certificate.data(), TxKind::Bundle)
+            })
+            .collect()
+    }
+}
+
+/// The frames sent to the subscribers besides the transactions, told apart by their `type`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlFrame {
+    /// The first frame of every connection, a new one means the subscriber reconnected. The `session` changes
+    /// when the relay restarts, and with it the numbering of the transactions.
+    Hello { version: String, session: u64 },
+    /// The subscriber fell behind, the `skipped` transactions before the next one are lost.
+    Lagged { skipped: u64 },
+    /// The filter of a `filter` message applies to the next transactions.
+    Subscribed,
+    /// The `count` buffered transactions of a `replay` message were sent, the live ones follow.
+    Replayed { count: usize },
+    /// The message of the subscriber is invalid, it's ignored.
+    Error { reason: String },
+}
+
+/// Cheap to clone, the clones share the channel, the replay buffer and the metrics.
+#[derive(Clone)]
 pub struct Relay {
-    tx_sender: watch::Sender<TxMessage>,
+    tx_sender: broadcast::Sender<TxMessage>,
+    replay: Arc<Mutex<ReplayBuffer>>,
+    metrics: Arc<RelayMetrics>,
+    // the transactions and queries are forwarded to it, without one they fail
+    upstream: Option<ValidatorClient<Channel>>,
+    // when the relay started, the transactions are numbered from it
+    session: u64,
 }
 
 impl Relay {
-    pub fn new(tx_sender: watch::Sender<TxMessage>) -> Self {
-        Relay { tx_sender }
+    pub fn new(tx_sender: broadcast::Sender<TxMessage>) -> Self {
+        Relay {
+            tx_sender,
+            replay: Arc::new(Mutex::new(ReplayBuffer::default())),
+            metrics: Arc::new(RelayMetrics::new()),
+            upstream: None,
+            session: replay::now_ms(),
+        }
+    }
+
+    pub fn with_replay(mut self, replay: ReplayBuffer) -> Self {
+        self.replay = Arc::new(Mutex::new(replay));
+        self
+    }
+
+    pub fn with_upstream(mut self, upstream: ValidatorClient<Channel>) -> Self {
+        self.upstream = Some(upstream);
+        self
+    }
+
+    pub fn metrics(&self) -> Arc<RelayMetrics> {
+        self.metrics.clone()
     }
 
-    async fn start_websocket_server(tx_sender: watch::Sender<TxMessage>) {
-        info!("WebSocket Server running on {}", WS_SERVER_URL);
-        let listener = TcpListener::bind(WS_SERVER_URL).await.unwrap();
-        while let Ok((stream, _)) = listener.accept().await {
-            let tx_sender = tx_sender.clone();
+    fn upstream(&self) -> Result<ValidatorClient<Channel>, tonic::Status> {
+        self.upstream
+            .clone()
+            .ok_or_else(|| tonic::Status::internal("Not implemented"))
+    }
+
+    fn broadcast(&self, tx_message: TxMessage) {
+        // numbered and sent under the lock, so the subscribers get them in order
+        let mut buffer = self.replay.lock().unwrap();
+        let tx_message = buffer.push(tx_message, replay::now_ms());
+        self.metrics.broadcast.inc();
+        if self.tx_sender.send(tx_message).is_err() {
+            debug!("💤 No subscriber");
+        }
+    }
+
+    async fn serve_websocket(self, listener: TcpListener, config: WsConfig) {
+        let clients = Arc::new(Semaphore::new(config.max_clients));
+        while let Ok((stream, addr)) = listener.accept().await {
+            // subscribed before the hello, which is then followed by every transaction
+            let tx_receiver = self.tx_sender.subscribe();
+            // held until the subscriber is gone, whichever way
+            let client = clients.clone().try_acquire_owned().ok();
+            let ws_token = config.token.clone();
+            let relay = self.clone();
             tokio::spawn(async move {
-                let ws_stream = accept_async(stream).await.unwrap();
-                let (mut write, _) = ws_stream.split();
-                let mut tx_receiver = tx_sender.subscribe();
-                while tx_receiver.changed().await.is_ok() {
-                    let tx_message = tx_receiver.borrow().clone();
-                    let msg = Message::Text(serde_json::to_string(&tx_message).unwrap());
-                    info!("🔥 Relay send {:?}", msg);
-                    write.send(msg).await.unwrap();
+                let connected_at = Instant::now();
+                let result = relay
+                    .serve_subscriber(stream, addr, tx_receiver, client, ws_token)
+                    .await;
+                let duration_ms = connected_at.elapsed().as_millis() as u64;
+                match result {
+                    Ok(()) => info!(%addr, duration_ms, "Subscriber disconnected"),
+                    Err(e) => info!(%addr, duration_ms, error = %e, "Subscriber disconnected"),
                 }
             });
         }
     }
+
+    async fn serve_subscriber(
+        &self,
+        stream: TcpStream,
+        addr: SocketAddr,
+        mut tx_receiver: broadcast::Receiver<TxMessage>,
+        client: Option<OwnedSemaphorePermit>,
+        ws_token: Option<String>,
+    ) -> Result<()> {
+        let mut token = None;
+        let capture_token = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+            if client.is_none() {
+                let mut response = ErrorResponse::new(Some("too many subscribers".to_string()));
+                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                return Err(response);
+            }
+            token = subscription::request_token(request);
+            Ok(response)
+        };
+        let ws_stream = accept_hdr_async(stream, capture_token).await?;
+        // a failed send means the subscriber is gone, the errors end the task
+        let (mut write, mut read) = ws_stream.split();
+
+        if let Some(ws_token) = ws_token {
+            if !token.is_some_and(|token| subscription::token_matches(&token, &ws_token)) {
+                let close_frame = CloseFrame {
+                    code: CloseCode::Policy,
+                    reason: "unauthorized".into(),
+                };
+                write.send(Message::Close(Some(close_frame))).await?;
+                bail!("unauthorized");
+            }
+        }
+
+        let _client_guard = ClientGuard::new(self.metrics.clone(), addr);
+        info!(%addr, "Subscriber connected");
+        let hello = ControlFrame::Hello {
+            version: BUILD_VERSION.to_string(),
+            session: self.session,
+        };
+        self.send(&mut write, Message::Text(serde_json::to_string(&hello)?))
+            .await?;
+
+        let mut filter = TxFilter::default();
+        // the live transactions up to it were already replayed
+        let mut replayed_seq = None;
+        loop {
+            let msg = tokio::select! {
+                tx_message = tx_receiver.recv() => match tx_message {
+                    Ok(tx_message) if replayed_seq.is_some_and(|seq| tx_message.seq <= seq) => continue,
+                    Ok(tx_message) if !filter.matches_message(&tx_message) => continue,
+                    Ok(tx_message) => Message::Text(serde_json::to_string(&tx_message)?),
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!(%addr, skipped, "🐢 Subscriber lagged, transactions skipped");
+                        self.metrics.record_lag(addr, skipped);
+                        Message::Text(serde_json::to_string(&ControlFrame::Lagged { skipped })?)
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                frame = read.next() => match frame {
+                    Some(Ok(Message::Text(text))) => {
+                        let reply = match serde_json::from_str::<SubscriberMessage>(&text) {
+                            Ok(SubscriberMessage::Filter(new_filter)) => {
+                                info!(%addr, filter = ?new_filter, "Subscriber filter set");
+                                filter = new_filter;
+                                ControlFrame::Subscribed
+                            }
+                            Ok(SubscriberMessage::Replay(request)) => {
+                                let tx_messages = self.replay.lock().unwrap().since(request.since_ms, replay::now_ms());
+                                replayed_seq = replayed_seq.max(tx_messages.last().map(|tx_message| tx_message.seq));
+
+                                let mut count = 0;
+                                for tx_message in tx_messages.iter().filter(|m| filter.matches_message(m)) {
+                                    self.send(&mut write, Message::Text(serde_json::to_string(tx_message)?)).await?;
+                                    count += 1;
+                                }
+                                info!(%addr, since_ms = request.since_ms, count, "Subscriber replayed");
+                                ControlFrame::Replayed { count }
+                            }
+                            Err(e) => ControlFrame::Error {
+                                reason: format!("invalid subscription: {e}"),
+                            },
+                        };
+                        Message::Text(serde_json::to_string(&reply)?)
+                    }
+                    Some(Ok(Message::Close(_))) | None => return Ok(()),
+                    // the pings are answered by tungstenite
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                },
+            };
+            info!("🔥 Relay send {:?}", msg);
+            self.send(&mut write, msg).await?;
+        }
+    }
+
+    // sends to a subscriber, counted in the metrics
+    async fn send<S>(&self, write: &mut S, msg: Message) -> Result<()>
+    where
+        S: Sink<Message, Error = tungstenite::Error> + Unpin,
+    {
+        self.metrics.ws_bytes_sent.inc_by(msg.len() as u64);
+        write.send(msg).await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -65,23 +393,13 @@ impl Validator for Relay {
         request: tonic::Request<Transaction>,
     ) -> Result<tonic::Response<HandleTransactionResponse>, tonic::Status> {
         info!("🧀 Relay receive {:?}", request);
+        self.metrics.received.with_label_values(&["transaction"]).inc();
 
         let tx = request.into_inner();
-        let tx_bytes = Base64::from_bytes(&bcs::to_bytes(tx.data().transaction_data()).unwrap()).encoded();
-        let signatures: Vec<String> = tx
-            .data()
-            .tx_signatures()
-            .iter()
-            .map(|s| Base64::from_bytes(s.as_bytes()).encoded())
-            .collect();
-
-        let tx_message = TxMessage { tx_bytes, signatures };
+        self.broadcast(TxMessage::new(tx.data(), TxKind::Tx));
 
-        if self.tx_sender.send(tx_message).is_err() {
-            debug!("💤 No subscriber");
-        }
-
-        Err(tonic::Status::internal("Not implemented"))
+        // the sender gets the genuine response
+        self.upstream()?.transaction(tx).await
     }
 
     async fn transaction_v2(
@@ -107,64 +425,109 @@ impl Validator for Relay {
 
     async fn handle_certificate_v3(
         &self,
-        _request: tonic::Request<HandleCertificateRequestV3>,
+        request: tonic::Request<HandleCertificateRequestV3>,
     ) -> Result<tonic::Response<HandleCertificateResponseV3>, tonic::Status> {
-        Err(tonic::Status::internal("Not implemented"))
+        info!("🧀 Relay receive certificate {:?}", request);
+        self.metrics
+            .received
+            .with_label_values(&["handle_certificate_v3"])
+            .inc();
+
+        let request = request.into_inner();
+        self.broadcast(TxMessage::new(request.certificate.data(), TxKind::Cert));
+
+        self.upstream()?.handle_certificate_v3(request).await
     }
 
     async fn handle_soft_bundle_certificates_v3(
         &self,
-        _request: tonic::Request<HandleSoftBundleCertificatesRequestV3>,
+        request: tonic::Request<HandleSoftBundleCertificatesRequestV3>,
     ) -> Result<tonic::Response<HandleSoftBundleCertificatesResponseV3>, tonic::Status> {
-        Err(tonic::Status::internal("Not implemented"))
+        info!("🧀 Relay receive soft bundle {:?}", request);
+
+        let request = request.into_inner();
+        self.metrics
+            .received
+            .with_label_values(&["handle_soft_bundle_certificates_v3"])
+            .inc_by(request.certificates.len() as u64);
+        for tx_message in TxMessage::from_bundle(&request.certificates) {
+            self.broadcast(tx_message);
+        }
+
+        self.upstream()?.handle_soft_bundle_certificates_v3(request).await
     }
 
     async fn object_info(
         &self,
-        _request: tonic::Request<ObjectInfoRequest>,
+        request: tonic::Request<ObjectInfoRequest>,
     ) -> Result<tonic::Response<ObjectInfoResponse>, tonic::Status> {
-        Err(tonic::Status::internal("Not implemented"))
+        self.upstream()?.object_info(request.into_inner()).await
     }
 
     async fn transaction_info(
         &self,
-        _request: tonic::Request<TransactionInfoRequest>,
+        request: tonic::Request<TransactionInfoRequest>,
     ) -> Result<tonic::Response<TransactionInfoResponse>, tonic::Status> {
-        Err(tonic::Status::internal("Not implemented"))
+        self.upstream()?.transaction_info(request.into_inner()).await
     }
 
     async fn checkpoint(
         &self,
-        _request: tonic::Request<CheckpointRequest>,
+        request: tonic::Request<CheckpointRequest>,
     ) -> Result<tonic::Response<CheckpointResponse>, tonic::Status> {
-        Err(tonic::Status::internal("Not implemented"))
+        self.upstream()?.checkpoint(request.into_inner()).await
     }
 
     async fn checkpoint_v2(
         &self,
-        _request: tonic::Request<CheckpointRequestV2>,
+        request: tonic::Request<CheckpointRequestV2>,
     ) -> Result<tonic::Response<CheckpointResponseV2>, tonic::Status> {
-        Err(tonic::Status::internal("Not implemented"))
+        self.upstream()?.checkpoint_v2(request.into_inner()).await
     }
 
     async fn get_system_state_object(
         &self,
-        _request: tonic::Request<SystemStateRequest>,
+        request: tonic::Request<SystemStateRequest>,
     ) -> Result<tonic::Response<SuiSystemState>, tonic::Status> {
-        Err(tonic::Status::internal("Not implemented"))
+        self.upstream()?.get_system_state_object(request.into_inner()).await
     }
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    args.validate()?;
     mev_logger::init_console_logger_with_directives(None, &["relay=debug"]);
 
-    let (sender, _) = watch::channel(TxMessage::default());
-    let relay = Relay::new(sender.clone());
+    let (sender, _) = broadcast::channel(args.broadcast_capacity);
+    let replay = ReplayBuffer::new(
+        args.replay_max_messages,
+        args.replay_max_bytes,
+        Duration::from_secs(args.replay_max_age_secs),
+    );
+    let mut relay = Relay::new(sender).with_replay(replay);
+    if let Some(upstream) = args.upstream {
+        let channel = Endpoint::from_shared(upstream.clone())
+            .expect("invalid upstream url")
+            .connect_lazy();
+        relay = relay.with_upstream(ValidatorClient::new(channel));
+        info!("Forwarding to upstream {}", upstream);
+    }
+    if let Some(metrics_bind) = args.metrics_bind {
+        metrics_server::serve(metrics_bind, relay.metrics().registry().clone())
+            .await
+            .wrap_err_with(|| format!("fail to bind the metrics server to {metrics_bind}"))?;
+    }
 
-    tokio::spawn(async move {
-        Relay::start_websocket_server(sender).await;
-    });
+    let listener = TcpListener::bind(args.ws_bind)
+        .await
+        .wrap_err_with(|| format!("fail to bind the WebSocket server to {}", args.ws_bind))?;
+    info!("WebSocket Server running on {}", args.ws_bind);
+    let ws_config = WsConfig {
+        token: args.ws_token,
+        max_clients: args.max_ws_clients,
+    };
+    tokio::spawn(relay.clone().serve_websocket(listener, ws_config));
 
     // test code
     // tokio::spawn(async move {
@@ -174,12 +537,12 @@ async fn main() {
     let server = mysten_network::config::Config::new()
         .server_builder()
         .add_service(ValidatorServer::new(relay))
-        .bind(&RELAY_SERVER_URL.parse().unwrap(), None)
+        .bind(&args.grpc_bind, None)
         .await
-        .unwrap();
+        .map_err(|e| eyre!("fail to bind the gRPC server to {}: {e}", args.grpc_bind))?;
 
     info!("Server running on {}", server.local_addr());
-    server.serve().await.unwrap();
+    server.serve().await.map_err(|e| eyre!("gRPC server failed: {e}"))
 }
 
 #[allow(dead_code)]
@@ -196,3 +559,552 @@ async fn subscribe_websocket_messages() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::Stream;
+    use serde_json::Value;
+
+    use serde_json::json;
+    use sui_types::{
+        base_types::{random_object_ref, ObjectID, SuiAddress},
+        committee::Committee,
+        crypto::{get_key_pair, AccountKeyPair, KeypairTraits},
+        digests::TransactionDigest,
+        programmable_transaction_builder::ProgrammableTransactionBuilder,
+        transaction::{SignedTransaction, TransactionData},
+        Identifier,
+    };
+    use tokio_tungstenite::{
+        connect_async,
+        tungstenite::{self, client::IntoClientRequest},
+    };
+
+    use super::*;
+
+    // records the requests it gets, answers the checkpoints and fails the rest with `not_found`
+    #[derive(Default, Clone)]
+    struct MockUpstream {
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MockUpstream {
+        fn record<T>(&self, request: String) -> Result<tonic::Response<T>, tonic::Status> {
+            self.requests.lock().unwrap().push(request.clone());
+            Err(tonic::Status::not_found(format!("mock {request}")))
+        }
+    }
+
+    #[async_trait]
+    impl Validator for MockUpstream {
+        async fn transaction(
+            &self,
+            request: tonic::Request<Transaction>,
+        ) -> Result<tonic::Response<HandleTransactionResponse>, tonic::Status> {
+            self.record(format!("transaction {}", request.into_inner().digest()))
+        }
+
+        async fn transaction_v2(
+            &self,
+            _request: tonic::Request<HandleTransactionRequestV2>,
+        ) -> Result<tonic::Response<HandleTransactionResponseV2>, tonic::Status> {
+            self.record("transaction_v2".to_string())
+        }
+
+        async fn submit_certificate(
+            &self,
+            _request: tonic::Request<CertifiedTransaction>,
+        ) -> Result<tonic::Response<SubmitCertificateResponse>, tonic::Status> {
+            self.record("submit_certificate".to_string())
+        }
+
+        async fn handle_certificate_v2(
+            &self,
+            _request: tonic::Request<CertifiedTransaction>,
+        ) -> Result<tonic::Response<HandleCertificateResponseV2>, tonic::Status> {
+            self.record("handle_certificate_v2".to_string())
+        }
+
+        async fn handle_certificate_v3(
+            &self,
+            _request: tonic::Request<HandleCertificateRequestV3>,
+        ) -> Result<tonic::Response<HandleCertificateResponseV3>, tonic::Status> {
+            self.record("handle_certificate_v3".to_string())
+        }
+
+        async fn handle_soft_bundle_certificates_v3(
+            &self,
+            _request: tonic::Request<HandleSoftBundleCertificatesRequestV3>,
+        ) -> Result<tonic::Response<HandleSoftBundleCertificatesResponseV3>, tonic::Status> {
+            self.record("handle_soft_bundle_certificates_v3".to_string())
+        }
+
+        async fn object_info(
+            &self,
+            _request: tonic::Request<ObjectInfoRequest>,
+        ) -> Result<tonic::Response<ObjectInfoResponse>, tonic::Status> {
+            self.record("object_info".to_string())
+        }
+
+        async fn transaction_info(
+            &self,
+            request: tonic::Request<TransactionInfoRequest>,
+        ) -> Result<tonic::Response<TransactionInfoResponse>, tonic::Status> {
+            self.record(format!("transaction_info {}", request.into_inner().transaction_digest))
+        }
+
+        async fn checkpoint(
+            &self,
+            request: tonic::Request<CheckpointRequest>,
+        ) -> Result<tonic::Response<CheckpointResponse>, tonic::Status> {
+            let sequence_number = request.into_inner().sequence_number;
+            self.requests
+                .lock()
+                .unwrap()
+                .push(format!("checkpoint {sequence_number:?}"));
+            Ok(tonic::Response::new(CheckpointResponse {
+                checkpoint: None,
+                contents: None,
+            }))
+        }
+
+        async fn checkpoint_v2(
+            &self,
+            _request: tonic::Request<CheckpointRequestV2>,
+        ) -> Result<tonic::Response<CheckpointResponseV2>, tonic::Status> {
+            self.record("checkpoint_v2".to_string())
+        }
+
+        async fn get_system_state_object(
+            &self,
+            _request: tonic::Request<SystemStateRequest>,
+        ) -> Result<tonic::Response<SuiSystemState>, tonic::Status> {
+            self.record("get_system_state_object".to_string())
+        }
+    }
+
+    async fn start_mock_upstream(mock: MockUpstream) -> ValidatorClient<Channel> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let incoming = futures::stream::unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(ValidatorServer::new(mock))
+                .serve_with_incoming(incoming),
+        );
+
+        ValidatorClient::new(Endpoint::from_shared(url).unwrap().connect_lazy())
+    }
+
+    fn new_tx() -> Transaction {
+        let (sender, keypair): (SuiAddress, AccountKeyPair) = get_key_pair();
+        let data = TransactionData::new_transfer_sui(sender, sender, None, random_object_ref(), 10_000_000, 750);
+        Transaction::from_data_and_signer(data, vec![&keypair])
+    }
+
+    fn new_move_call_tx(package: ObjectID) -> Transaction {
+        let (sender, keypair): (SuiAddress, AccountKeyPair) = get_key_pair();
+        let mut builder = ProgrammableTransactionBuilder::new();
+        builder.programmable_move_call(
+            package,
+            Identifier::new("pool").unwrap(),
+            Identifier::new("swap").unwrap(),
+            vec![],
+            vec![],
+        );
+        let data =
+            TransactionData::new_programmable(sender, vec![random_object_ref()], builder.finish(), 10_000_000, 750);
+        Transaction::from_data_and_signer(data, vec![&keypair])
+    }
+
+    // the relay broadcasts to the server
+    async fn start_ws_server(config: WsConfig) -> (Relay, SocketAddr) {
+        let (sender, _) = broadcast::channel(16);
+        let relay = Relay::new(sender);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(relay.clone().serve_websocket(listener, config));
+        (relay, addr)
+    }
+
+    // certified by every member of a test committee
+    fn new_certificate(tx: Transaction) -> CertifiedTransaction {
+        let (committee, keypairs) = Committee::new_simple_test_committee();
+        let signatures = keypairs
+            .iter()
+            .map(|keypair| {
+                SignedTransaction::new(committee.epoch(), tx.data().clone(), keypair, keypair.public().into())
+                    .into_sig()
+            })
+            .collect();
+        CertifiedTransaction::new(tx.into_data(), signatures, &committee).unwrap()
+    }
+
+    #[test]
+    fn test_certificate_messages() {
+        let tx = new_tx();
+        let tx_message = TxMessage::new(tx.data(), TxKind::Tx);
+
+        let certificate = new_certificate(tx.clone());
+        let cert_message = TxMessage::new(certificate.data(), TxKind::Cert);
+        assert_eq!(cert_message.kind, TxKind::Cert);
+        assert_eq!(cert_message.bundle_index, None);
+        // the transaction and its sender signature, not the ones of the validators
+        assert_eq!(cert_message.tx_bytes, tx_message.tx_bytes);
+        assert_eq!(cert_message.signatures, tx_message.signatures);
+        assert_eq!(cert_message.signatures.len(), 1);
+
+        let json = serde_json::to_value(&cert_message).unwrap();
+        assert_eq!(json["kind"], "cert");
+        assert!(json.get("bundle_index").is_none());
+    }
+
+    #[test]
+    fn test_bundle_messages() {
+        let txs = [new_tx(), new_tx(), new_tx()];
+        let certificates = txs.iter().cloned().map(new_certificate).collect::<Vec<_>>();
+
+        let bundle_messages = TxMessage::from_bundle(&certificates);
+        assert_eq!(bundle_messages.len(), 3);
+        for (i, (bundle_message, tx)) in bundle_messages.iter().zip(&txs).enumerate() {
+            assert_eq!(bundle_message.kind, TxKind::Bundle);
+            assert_eq!(bundle_message.bundle_index, Some(i));
+            assert_eq!(bundle_message.tx_bytes, TxMessage::new(tx.data(), TxKind::Tx).tx_bytes);
+        }
+
+        let json = serde_json::to_value(&bundle_messages[2]).unwrap();
+        assert_eq!(json["kind"], "bundle");
+        assert_eq!(json["bundle_index"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_forward_to_upstream() {
+        let mock = MockUpstream::default();
+        let (sender, mut tx_receiver) = broadcast::channel(4);
+        let relay = Relay::new(sender).with_upstream(start_mock_upstream(mock.clone()).await);
+
+        let response = relay
+            .checkpoint(tonic::Request::new(CheckpointRequest {
+                sequence_number: Some(42),
+                request_content: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.checkpoint.is_none());
+
+        // the errors of the upstream too
+        let digest = TransactionDigest::random();
+        let status = relay
+            .transaction_info(tonic::Request::new(TransactionInfoRequest {
+                transaction_digest: digest,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert_eq!(status.message(), format!("mock transaction_info {digest}"));
+
+        // captured, then forwarded
+        let tx = new_tx();
+        let status = relay.transaction(tonic::Request::new(tx.clone())).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        let tx_message = tx_receiver.try_recv().unwrap();
+        assert_eq!(tx_message.kind, TxKind::Tx);
+        assert_eq!(
+            tx_message.tx_bytes,
+            Base64::from_bytes(&bcs::to_bytes(tx.data().transaction_data()).unwrap()).encoded()
+        );
+
+        assert_eq!(
+            *mock.requests.lock().unwrap(),
+            vec![
+                "checkpoint Some(42)".to_string(),
+                format!("transaction_info {digest}"),
+                format!("transaction {}", tx.digest()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_without_upstream() {
+        let (sender, mut tx_receiver) = broadcast::channel(4);
+        let relay = Relay::new(sender);
+
+        let status = relay.transaction(tonic::Request::new(new_tx())).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+        assert!(tx_receiver.try_recv().is_ok());
+
+        let status = relay
+            .checkpoint(tonic::Request::new(CheckpointRequest {
+                sequence_number: None,
+                request_content: false,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+    }
+
+    fn tx_message(i: usize) -> TxMessage {
+        TxMessage {
+            tx_bytes: format!("tx-{i}"),
+            signatures: vec![],
+            kind: TxKind::Tx,
+            bundle_index: None,
+            seq: i as u64,
+            replayed: false,
+        }
+    }
+
+    async fn next_frame<S>(read: &mut S) -> Value
+    where
+        S: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+    {
+        let frame = read.next().await.unwrap().unwrap();
+        serde_json::from_str(frame.to_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_slow_subscriber_lag_reported() {
+        let (sender, _) = broadcast::channel(4);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Relay::new(sender.clone()).serve_websocket(listener, WsConfig::default()));
+
+        let (ws_stream, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+        let (_, mut read) = ws_stream.split();
+
+        let hello = next_frame(&mut read).await;
+        assert_eq!(hello["type"], "hello");
+        assert_eq!(hello["version"], BUILD_VERSION);
+        assert!(hello["session"].as_u64().unwrap() <= replay::now_ms());
+
+        // a burst, sent before the subscriber task gets to run
+        for i in 0..10 {
+            sender.send(tx_message(i)).unwrap();
+        }
+
+        let lagged = next_frame(&mut read).await;
+        assert_eq!(lagged["type"], "lagged");
+        assert_eq!(lagged["skipped"], 6);
+        for i in 6..10 {
+            assert_eq!(next_frame(&mut read).await["tx_bytes"], format!("tx-{i}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_subscriber_closed() {
+        let config = WsConfig {
+            token: Some("s3cret".to_string()),
+            ..Default::default()
+        };
+        let (_relay, addr) = start_ws_server(config).await;
+
+        for url in [format!("ws://{addr}"), format!("ws://{addr}/?token=s3cres")] {
+            let (ws_stream, _) = connect_async(url).await.unwrap();
+            let (_, mut read) = ws_stream.split();
+            match read.next().await.unwrap().unwrap() {
+                Message::Close(Some(close_frame)) => {
+                    assert_eq!(close_frame.code, CloseCode::Policy);
+                    assert_eq!(close_frame.reason, "unauthorized");
+                }
+                msg => panic!("expected a close frame, got {msg:?}"),
+            }
+        }
+
+        let (ws_stream, _) = connect_async(format!("ws://{addr}/?token=s3cret")).await.unwrap();
+        let (_, mut read) = ws_stream.split();
+        assert_eq!(next_frame(&mut read).await["type"], "hello");
+
+        let mut request = format!("ws://{addr}").into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("authorization", "Bearer s3cret".parse().unwrap());
+        let (ws_stream, _) = connect_async(request).await.unwrap();
+        let (_, mut read) = ws_stream.split();
+        assert_eq!(next_frame(&mut read).await["type"], "hello");
+    }
+
+    #[tokio::test]
+    async fn test_package_filter() {
+        let (relay, addr) = start_ws_server(WsConfig::default()).await;
+        let (ws_stream, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+        let (mut write, mut read) = ws_stream.split();
+        assert_eq!(next_frame(&mut read).await["type"], "hello");
+
+        write
+            .send(Message::Text(r#"{"filter": 42}"#.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(next_frame(&mut read).await["type"], "error");
+
+        let (cetus, turbos) = (ObjectID::random(), ObjectID::random());
+        let subscribe = json!({ "filter": { "move_packages": [cetus.to_string()] } });
+        write.send(Message::Text(subscribe.to_string())).await.unwrap();
+        assert_eq!(next_frame(&mut read).await["type"], "subscribed");
+
+        let cetus_tx = TxMessage::new(new_move_call_tx(cetus).data(), TxKind::Tx);
+        for tx_message in [
+            TxMessage::new(new_tx().data(), TxKind::Tx),
+            TxMessage::new(new_move_call_tx(turbos).data(), TxKind::Tx),
+            cetus_tx.clone(),
+        ] {
+            relay.broadcast(tx_message);
+        }
+        // the others are never sent
+        assert_eq!(next_frame(&mut read).await["tx_bytes"], cetus_tx.tx_bytes);
+    }
+
+    #[test]
+    fn test_args() {
+        let args = Args::try_parse_from(["relay"]).unwrap();
+        assert_eq!(args.grpc_bind.to_string(), DEFAULT_GRPC_BIND);
+        assert_eq!(args.ws_bind.to_string(), DEFAULT_WS_BIND);
+        assert_eq!(args.max_ws_clients, DEFAULT_MAX_WS_CLIENTS);
+        assert_eq!(args.broadcast_capacity, DEFAULT_BROADCAST_CAPACITY);
+
+        let args = Args::try_parse_from([
+            "relay",
+            "--grpc-bind",
+            "/ip4/127.0.0.1/tcp/9100/http",
+            "--ws-bind",
+            "127.0.0.1:9101",
+            "--max-ws-clients",
+            "8",
+            "--ws-capacity",
+            "64",
+        ])
+        .unwrap();
+        assert_eq!(args.grpc_bind.to_string(), "/ip4/127.0.0.1/tcp/9100/http");
+        assert_eq!(args.ws_bind, SocketAddr::from(([127, 0, 0, 1], 9101)));
+        assert_eq!(args.max_ws_clients, 8);
+        assert_eq!(args.broadcast_capacity, 64);
+        args.validate().unwrap();
+
+        let args = Args::try_parse_from(["relay", "--broadcast-capacity", "0"]).unwrap();
+        assert!(args.validate().is_err());
+
+        for invalid in [
+            ["--ws-bind", "localhost"],
+            ["--grpc-bind", "0.0.0.0:9000"],
+            ["--max-ws-clients", "many"],
+        ] {
+            let error = Args::try_parse_from(["relay", invalid[0], invalid[1]]).unwrap_err();
+            assert!(error.to_string().contains(invalid[0]), "{error}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_ws_clients() {
+        let config = WsConfig {
+            max_clients: 1,
+            ..Default::default()
+        };
+        let (relay, addr) = start_ws_server(config).await;
+
+        let (ws_stream, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+        let (_, mut read) = ws_stream.split();
+        assert_eq!(next_frame(&mut read).await["type"], "hello");
+
+        match connect_async(format!("ws://{addr}")).await {
+            Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE),
+            result => panic!("expected a 503, got {result:?}"),
+        }
+
+        // gone without a close frame, the next send fails and frees its place
+        drop(read);
+        relay.broadcast(TxMessage::new(new_tx().data(), TxKind::Tx));
+
+        let reconnect = async {
+            loop {
+                if let Ok((ws_stream, _)) = connect_async(format!("ws://{addr}")).await {
+                    return ws_stream;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let ws_stream = tokio::time::timeout(Duration::from_secs(5), reconnect)
+            .await
+            .expect("the place of the gone subscriber is never freed");
+        let (_, mut read) = ws_stream.split();
+        assert_eq!(next_frame(&mut read).await["type"], "hello");
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let (relay, addr) = start_ws_server(WsConfig::default()).await;
+        // before the subscriber connects
+        let tx_messages = (0..3)
+            .map(|_| TxMessage::new(new_tx().data(), TxKind::Tx))
+            .collect::<Vec<_>>();
+        for tx_message in &tx_messages {
+            relay.broadcast(tx_message.clone());
+        }
+
+        let (ws_stream, _) = connect_async(format!("ws://{addr}")).await.unwrap();
+        let (mut write, mut read) = ws_stream.split();
+        assert_eq!(next_frame(&mut read).await["type"], "hello");
+
+        let replay = json!({ "replay": { "since_ms": 0 } });
+        write.send(Message::Text(replay.to_string())).await.unwrap();
+        for (i, tx_message) in tx_messages.iter().enumerate() {
+            let frame = next_frame(&mut read).await;
+            assert_eq!(frame["tx_bytes"], tx_message.tx_bytes);
+            assert_eq!(frame["seq"], i + 1);
+            assert_eq!(frame["replayed"], true);
+        }
+        let replayed = next_frame(&mut read).await;
+        assert_eq!(replayed["type"], "replayed");
+        assert_eq!(replayed["count"], 3);
+
+        // then the live ones
+        relay.broadcast(TxMessage::new(new_tx().data(), TxKind::Tx));
+        let frame = next_frame(&mut read).await;
+        assert_eq!(frame["seq"], 4);
+        assert!(frame.get("replayed").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let (relay, ws_addr) = start_ws_server(WsConfig::default()).await;
+        let metrics_addr = metrics_server::serve("127.0.0.1:0".parse().unwrap(), relay.metrics().registry().clone())
+            .await
+            .unwrap();
+
+        let (ws_stream, _) = connect_async(format!("ws://{ws_addr}")).await.unwrap();
+        let (_, mut read) = ws_stream.split();
+        let hello = read.next().await.unwrap().unwrap();
+
+        for _ in 0..3 {
+            relay.transaction(tonic::Request::new(new_tx())).await.unwrap_err();
+        }
+        let mut bytes_sent = hello.len();
+        for _ in 0..3 {
+            bytes_sent += read.next().await.unwrap().unwrap().len();
+        }
+
+        let scraped = metrics_server::scrape(metrics_addr).await;
+        for (sample, value) in [
+            (r#"relay_transactions_received_total{method="transaction"}"#, 3),
+            ("relay_messages_broadcast_total", 3),
+            ("relay_ws_clients", 1),
+            ("relay_ws_bytes_sent_total", bytes_sent),
+        ] {
+            assert_eq!(metrics_server::sample_value(&scraped, sample), value as f64, "{sample}");
+        }
+
+        drop(read);
+        // the subscriber task ends on its next send
+        relay.broadcast(TxMessage::new(new_tx().data(), TxKind::Tx));
+        let disconnected = async {
+            while relay.metrics().ws_clients.get() != 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), disconnected)
+            .await
+            .expect("the subscriber is still counted");
+    }
+}
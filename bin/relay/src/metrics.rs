@@ -0,0 +1,150 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use prometheus::{IntCounter, IntCounterVec, IntGauge, Opts, Registry};
+use utils::metrics_server::register;
+
+/// The metrics of a relay, each relay has its own registry.
+pub struct RelayMetrics {
+    registry: Registry,
+    /// transactions received, by gRPC method
+    pub received: IntCounterVec,
+    /// transactions sent to the broadcast channel, whether or not anyone subscribed
+    pub broadcast: IntCounter,
+    /// the WebSocket subscribers past the handshake
+    pub ws_clients: IntGauge,
+    /// times a subscriber fell behind
+    pub ws_lagged: IntCounter,
+    /// transactions lost by the subscribers that fell behind
+    pub ws_skipped: IntCounter,
+    /// transactions lost by each connected subscriber, by peer address. Removed when it disconnects.
+    pub ws_client_skipped: IntCounterVec,
+    /// the payload of the frames sent to the subscribers
+    pub ws_bytes_sent: IntCounter,
+}
+
+impl Default for RelayMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RelayMetrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        Self {
+            received: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("relay_transactions_received_total", "Transactions received"),
+                    &["method"],
+                ),
+            ),
+            broadcast: register(
+                &registry,
+                IntCounter::new("relay_messages_broadcast_total", "Transactions sent to the subscribers"),
+            ),
+            ws_clients: register(
+                &registry,
+                IntGauge::new("relay_ws_clients", "Connected WebSocket subscribers"),
+            ),
+            ws_lagged: register(
+                &registry,
+                IntCounter::new("relay_ws_lagged_total", "Times a subscriber fell behind"),
+            ),
+            ws_skipped: register(
+                &registry,
+                IntCounter::new(
+                    "relay_ws_skipped_total",
+                    "Transactions lost by the subscribers that fell behind",
+                ),
+            ),
+            ws_client_skipped: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "relay_ws_client_skipped_total",
+                        "Transactions lost by each connected subscriber",
+                    ),
+                    &["client"],
+                ),
+            ),
+            ws_bytes_sent: register(
+                &registry,
+                IntCounter::new("relay_ws_bytes_sent_total", "Bytes sent to the subscribers"),
+            ),
+            registry,
+        }
+    }
+
+    pub fn record_lag(&self, client: SocketAddr, skipped: u64) {
+        self.ws_lagged.inc();
+        self.ws_skipped.inc_by(skipped);
+        self.ws_client_skipped
+            .with_label_values(&[client.to_string().as_str()])
+            .inc_by(skipped);
+    }
+
+    /// The registry to serve, see `utils::metrics_server::serve`.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+/// A connected subscriber, counted in `ws_clients` until dropped, whichever way it disconnects.
+pub struct ClientGuard {
+    metrics: Arc<RelayMetrics>,
+    client: SocketAddr,
+}
+
+impl ClientGuard {
+    pub fn new(metrics: Arc<RelayMetrics>, client: SocketAddr) -> Self {
+        metrics.ws_clients.inc();
+        Self { metrics, client }
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.metrics.ws_clients.dec();
+        // the label is gone with the subscriber, it never lagged otherwise
+        let _ = self
+            .metrics
+            .ws_client_skipped
+            .remove_label_values(&[self.client.to_string().as_str()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::metrics_server::{sample_value, scrape, serve};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_client_guard() {
+        let metrics = Arc::new(RelayMetrics::new());
+        let addr = serve("127.0.0.1:0".parse().unwrap(), metrics.registry().clone())
+            .await
+            .unwrap();
+        let client = SocketAddr::from(([10, 0, 0, 1], 4242));
+
+        let guard = ClientGuard::new(metrics.clone(), client);
+        metrics.record_lag(client, 6);
+        metrics.record_lag(client, 2);
+
+        let scraped = scrape(addr).await;
+        assert_eq!(sample_value(&scraped, "relay_ws_clients"), 1.0);
+        assert_eq!(sample_value(&scraped, "relay_ws_lagged_total"), 2.0);
+        assert_eq!(
+            sample_value(&scraped, r#"relay_ws_client_skipped_total{client="10.0.0.1:4242"}"#),
+            8.0
+        );
+
+        drop(guard);
+        let scraped = scrape(addr).await;
+        assert_eq!(sample_value(&scraped, "relay_ws_clients"), 0.0);
+        assert_eq!(sample_value(&scraped, "relay_ws_skipped_total"), 8.0);
+        assert!(!scraped.contains("relay_ws_client_skipped_total{"), "{scraped}");
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    collections::VecDeque,
+    mem,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::TxMessage;
+
+pub const DEFAULT_REPLAY_MAX_MESSAGES: usize = 4096;
+pub const DEFAULT_REPLAY_MAX_BYTES: usize = 16 * 1024 * 1024;
+pub const DEFAULT_REPLAY_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// The recent transactions, for the subscribers that reconnect. Every transaction is numbered when pushed, the
+/// oldest ones are dropped past any of the limits.
+#[derive(Debug)]
+pub struct ReplayBuffer {
+    max_messages: usize,
+    max_bytes: usize,
+    max_age: Duration,
+    // with when they were pushed, in ms since the epoch, oldest first
+    messages: VecDeque<(u64, TxMessage)>,
+    bytes: usize,
+    next_seq: u64,
+}
+
+impl Default for ReplayBuffer {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_REPLAY_MAX_MESSAGES,
+            DEFAULT_REPLAY_MAX_BYTES,
+            DEFAULT_REPLAY_MAX_AGE,
+        )
+    }
+}
+
+impl ReplayBuffer {
+    /// No message is kept with `max_messages` 0, they are still numbered.
+    pub fn new(max_messages: usize, max_bytes: usize, max_age: Duration) -> Self {
+        Self {
+            max_messages,
+            max_bytes,
+            max_age,
+            messages: VecDeque::new(),
+            bytes: 0,
+            next_seq: 1,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// The estimated memory of the kept messages.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Numbers `tx_message`, from 1, and keeps it. Returns the numbered message.
+    pub fn push(&mut self, mut tx_message: TxMessage, now_ms: u64) -> TxMessage {
+        tx_message.seq = self.next_seq;
+        self.next_seq += 1;
+
+        self.bytes += message_bytes(&tx_message);
+        self.messages.push_back((now_ms, tx_message.clone()));
+        self.evict(now_ms);
+        tx_message
+    }
+
+    /// The kept messages pushed at `since_ms` or later, oldest first, tagged as replayed.
+    pub fn since(&mut self, since_ms: u64, now_ms: u64) -> Vec<TxMessage> {
+        self.evict(now_ms);
+        self.messages
+            .iter()
+            .filter(|(pushed_ms, _)| *pushed_ms >= since_ms)
+            .map(|(_, tx_message)| TxMessage {
+                replayed: true,
+                ..tx_message.clone()
+            })
+            .collect()
+    }
+
+    fn evict(&mut self, now_ms: u64) {
+        let oldest_ms = now_ms.saturating_sub(self.max_age.as_millis() as u64);
+        while let Some((pushed_ms, tx_message)) = self.messages.front() {
+            let within_limits =
+                self.messages.len() <= self.max_messages && self.bytes <= self.max_bytes && *pushed_ms >= oldest_ms;
+            if within_limits {
+                break;
+            }
+
+            self.bytes -= message_bytes(tx_message);
+            self.messages.pop_front();
+        }
+    }
+}
+
+/// The ms since the epoch, as `since_ms` of a replay request.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn message_bytes(tx_message: &TxMessage) -> usize {
+    mem::size_of::<TxMessage>()
+        + tx_message.tx_bytes.len()
+        + tx_message.signatures.iter().map(String::len).sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TxKind;
+
+    fn tx_message(i: usize, len: usize) -> TxMessage {
+        TxMessage {
+            tx_bytes: format!("tx-{i}-{}", "x".repeat(len)),
+            signatures: vec![],
+            kind: TxKind::Tx,
+            bundle_index: None,
+            seq: 0,
+            replayed: false,
+        }
+    }
+
+    fn seqs(tx_messages: &[TxMessage]) -> Vec<u64> {
+        tx_messages.iter().map(|tx_message| tx_message.seq).collect()
+    }
+
+    #[test]
+    fn test_replay_order() {
+        let mut buffer = ReplayBuffer::default();
+        let pushed = (0..5)
+            .map(|i| buffer.push(tx_message(i, 0), 1_000 + i as u64))
+            .collect::<Vec<_>>();
+        assert_eq!(seqs(&pushed), vec![1, 2, 3, 4, 5]);
+        assert!(pushed.iter().all(|tx_message| !tx_message.replayed));
+
+        let replayed = buffer.since(1_002, 1_010);
+        assert_eq!(seqs(&replayed), vec![3, 4, 5]);
+        assert_eq!(replayed[0].tx_bytes, "tx-2-");
+        assert!(replayed.iter().all(|tx_message| tx_message.replayed));
+        assert!(buffer.since(1_010, 1_010).is_empty());
+    }
+
+    #[test]
+    fn test_max_messages() {
+        let mut buffer = ReplayBuffer::new(3, usize::MAX, DEFAULT_REPLAY_MAX_AGE);
+        for i in 0..5 {
+            buffer.push(tx_message(i, 0), 1_000);
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(seqs(&buffer.since(0, 1_000)), vec![3, 4, 5]);
+
+        // still numbered, never kept
+        let mut buffer = ReplayBuffer::new(0, usize::MAX, DEFAULT_REPLAY_MAX_AGE);
+        assert_eq!(buffer.push(tx_message(0, 0), 1_000).seq, 1);
+        assert_eq!(buffer.push(tx_message(1, 0), 1_000).seq, 2);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.bytes(), 0);
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let message_len = message_bytes(&tx_message(0, 1_000));
+        let mut buffer = ReplayBuffer::new(100, 2 * message_len, DEFAULT_REPLAY_MAX_AGE);
+        for i in 0..5 {
+            buffer.push(tx_message(i, 1_000), 1_000);
+            assert!(buffer.bytes() <= 2 * message_len);
+        }
+        assert_eq!(seqs(&buffer.since(0, 1_000)), vec![4, 5]);
+
+        // a message over the limit by itself is never kept
+        buffer.push(tx_message(5, 10_000), 1_000);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.bytes(), 0);
+    }
+
+    #[test]
+    fn test_max_age() {
+        let mut buffer = ReplayBuffer::new(100, usize::MAX, Duration::from_secs(10));
+        buffer.push(tx_message(0, 0), 1_000);
+        buffer.push(tx_message(1, 0), 6_000);
+        buffer.push(tx_message(2, 0), 11_000);
+        assert_eq!(seqs(&buffer.since(0, 11_000)), vec![1, 2, 3]);
+
+        assert_eq!(seqs(&buffer.since(0, 16_000)), vec![2, 3]);
+        assert_eq!(buffer.len(), 2);
+    }
+}
@@ -0,0 +1,198 @@
+use fastcrypto::encoding::{Base64, Encoding};
+use serde::Deserialize;
+use sui_types::{
+    base_types::{ObjectID, SuiAddress},
+    transaction::{TransactionData, TransactionDataAPI},
+};
+use tokio_tungstenite::tungstenite::handshake::server::Request;
+use tracing::warn;
+
+use crate::TxMessage;
+
+/// What a subscriber sends, either
+/// - `{"filter": {"move_packages": ["0x2"], "senders": []}}` to only get some of the transactions, a new filter
+///   replaces the previous one.
+/// - `{"replay": {"since_ms": 1700000000000}}` to get the buffered transactions since then, before the live ones.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriberMessage {
+    Filter(TxFilter),
+    Replay(ReplayRequest),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct ReplayRequest {
+    /// In ms since the epoch.
+    pub since_ms: u64,
+}
+
+/// The transactions calling any of `move_packages` and sent by any of `senders`, an empty list matches all.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct TxFilter {
+    #[serde(default)]
+    pub move_packages: Vec<ObjectID>,
+    #[serde(default)]
+    pub senders: Vec<SuiAddress>,
+}
+
+impl TxFilter {
+    pub fn is_empty(&self) -> bool {
+        self.move_packages.is_empty() && self.senders.is_empty()
+    }
+
+    pub fn matches(&self, tx_data: &TransactionData) -> bool {
+        let sender_matches = self.senders.is_empty() || self.senders.contains(&tx_data.sender());
+        let package_matches = self.move_packages.is_empty()
+            || tx_data
+                .move_calls()
+                .into_iter()
+                .any(|(package, _, _)| self.move_packages.contains(package));
+
+        sender_matches && package_matches
+    }
+
+    /// Same as `matches`, on the encoded transaction of `tx_message`. An undecodable one never matches.
+    pub fn matches_message(&self, tx_message: &TxMessage) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        let tx_data = Base64::decode(&tx_message.tx_bytes)
+            .map_err(|e| e.to_string())
+            .and_then(|tx_bytes| bcs::from_bytes::<TransactionData>(&tx_bytes).map_err(|e| e.to_string()));
+        match tx_data {
+            Ok(tx_data) => self.matches(&tx_data),
+            Err(e) => {
+                warn!("Undecodable tx_bytes: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// The token of the WebSocket upgrade `request`, either the `token` query parameter or the bearer token of the
+/// `Authorization` header.
+pub fn request_token(request: &Request) -> Option<String> {
+    let query_token = request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| *name == "token")
+            .map(|(_, token)| token.to_string())
+    });
+
+    query_token.or_else(|| {
+        let authorization = request.headers().get("authorization")?.to_str().ok()?;
+        authorization
+            .strip_prefix("Bearer ")
+            .map(|token| token.trim().to_string())
+    })
+}
+
+/// Compares the tokens in a time that only depends on their length, not on where they differ.
+pub fn token_matches(token: &str, expected: &str) -> bool {
+    if token.len() != expected.len() {
+        return false;
+    }
+
+    token
+        .bytes()
+        .zip(expected.bytes())
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use sui_types::{
+        base_types::random_object_ref, programmable_transaction_builder::ProgrammableTransactionBuilder, Identifier,
+    };
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn new_move_call(sender: SuiAddress, package: ObjectID) -> TransactionData {
+        let mut builder = ProgrammableTransactionBuilder::new();
+        builder.programmable_move_call(
+            package,
+            Identifier::new("pool").unwrap(),
+            Identifier::new("swap").unwrap(),
+            vec![],
+            vec![],
+        );
+        TransactionData::new_programmable(sender, vec![random_object_ref()], builder.finish(), 10_000_000, 750)
+    }
+
+    #[test]
+    fn test_filter() {
+        let (alice, bob) = (
+            SuiAddress::random_for_testing_only(),
+            SuiAddress::random_for_testing_only(),
+        );
+        let (cetus, turbos) = (ObjectID::random(), ObjectID::random());
+        let transfer = TransactionData::new_transfer_sui(alice, alice, None, random_object_ref(), 10_000_000, 750);
+
+        assert!(TxFilter::default().matches(&transfer));
+
+        let filter: TxFilter = serde_json::from_value(json!({ "move_packages": [cetus.to_string()] })).unwrap();
+        assert!(filter.matches(&new_move_call(alice, cetus)));
+        assert!(!filter.matches(&new_move_call(alice, turbos)));
+        assert!(!filter.matches(&transfer));
+
+        let filter = TxFilter {
+            move_packages: vec![cetus],
+            senders: vec![bob],
+        };
+        assert!(filter.matches(&new_move_call(bob, cetus)));
+        assert!(!filter.matches(&new_move_call(alice, cetus)));
+    }
+
+    #[test]
+    fn test_subscriber_message() {
+        let package = ObjectID::random();
+        let message = json!({ "filter": { "move_packages": [package.to_string()] } });
+        match serde_json::from_value(message).unwrap() {
+            SubscriberMessage::Filter(filter) => assert_eq!(filter.move_packages, vec![package]),
+            message => panic!("expected a filter, got {message:?}"),
+        }
+
+        let message = json!({ "replay": { "since_ms": 1_700_000_000_000u64 } });
+        match serde_json::from_value(message).unwrap() {
+            SubscriberMessage::Replay(request) => assert_eq!(request.since_ms, 1_700_000_000_000),
+            message => panic!("expected a replay, got {message:?}"),
+        }
+
+        assert!(serde_json::from_value::<SubscriberMessage>(json!({ "replay": {} })).is_err());
+    }
+
+    #[test]
+    fn test_token_matches() {
+        assert!(token_matches("s3cret", "s3cret"));
+        assert!(!token_matches("s3cres", "s3cret"));
+        assert!(!token_matches("s3cre", "s3cret"));
+        assert!(!token_matches("", "s3cret"));
+    }
+
+    #[test]
+    fn test_request_token() {
+        let request = |uri: &str, authorization: Option<&str>| {
+            let mut builder = Request::builder().uri(uri);
+            if let Some(authorization) = authorization {
+                builder = builder.header("authorization", authorization);
+            }
+            builder.body(()).unwrap()
+        };
+
+        assert_eq!(
+            request_token(&request("/?a=1&token=s3cret", None)).as_deref(),
+            Some("s3cret")
+        );
+        assert_eq!(
+            request_token(&request("/", Some("Bearer s3cret"))).as_deref(),
+            Some("s3cret")
+        );
+        assert_eq!(request_token(&request("/", Some("Basic s3cret"))), None);
+        assert_eq!(request_token(&request("/?tokens=s3cret", None)), None);
+    }
+}
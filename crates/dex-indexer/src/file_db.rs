@@ -2,21 +2,25 @@ use std::{
     collections::HashMap,
     fs::{File, OpenOptions},
     io::{BufRead, BufReader, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
 use dashmap::DashMap;
 use eyre::{eyre, Result};
 use sui_sdk::types::event::EventID;
-use tracing::{debug, error};
+use tracing::{debug, info};
 
 use crate::{
     token01_key,
     types::{PoolCache, Token01Pools, TokenPools},
-    Pool, Protocol, DB,
+    Pool, PoolStream, Protocol, DB,
 };
 
+// the pool files are read in chunks of this size, the lines are split from the buffer
+const READ_CHUNK_SIZE: usize = 1 << 20;
+
 #[derive(Debug, Clone)]
 pub struct FileDB {
     inner: Arc<Mutex<Inner>>,
@@ -27,6 +31,9 @@ struct Inner {
     pools_paths: HashMap<Protocol, PathBuf>,
     cursors_path: PathBuf,
     processed_cursors: HashMap<Protocol, Option<EventID>>,
+    // the cursors of the swap events, apart from those of the pool created events
+    swap_cursors_path: PathBuf,
+    swap_cursors: HashMap<Protocol, Option<EventID>>,
 }
 
 impl FileDB {
@@ -41,23 +48,36 @@ impl FileDB {
             .collect();
 
         let cursors_path = base_path.join("processed_cursors.json");
-        let mut processed_cursors = HashMap::new();
-        if cursors_path.exists() {
-            let cursors_file = File::open(&cursors_path)?;
-            let reader = BufReader::new(cursors_file);
-            processed_cursors = serde_json::from_reader(reader)?;
-        }
+        let processed_cursors = read_cursors(&cursors_path)?;
+        let swap_cursors_path = base_path.join("swap_cursors.json");
+        let swap_cursors = read_cursors(&swap_cursors_path)?;
 
         Ok(Self {
             inner: Arc::new(Mutex::new(Inner {
                 pools_paths,
                 cursors_path,
                 processed_cursors,
+                swap_cursors_path,
+                swap_cursors,
             })),
         })
     }
 }
 
+fn read_cursors(path: &Path) -> Result<HashMap<Protocol, Option<EventID>>> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+fn write_cursors(path: &Path, cursors: &HashMap<Protocol, Option<EventID>>) -> Result<()> {
+    let cursors_file = OpenOptions::new().create(true).write(true).truncate(true).open(path)?;
+    serde_json::to_writer(cursors_file, cursors)?;
+    Ok(())
+}
+
 impl DB for FileDB {
     fn flush(&self, protocol: &Protocol, pools: &[Pool], cursor: Option<EventID>) -> Result<()> {
         let mut inner = self.inner.lock().unwrap();
@@ -72,14 +92,13 @@ impl DB for FileDB {
         }
 
         inner.processed_cursors.insert(protocol.clone(), cursor);
-        let cursors_file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&inner.cursors_path)?;
-        serde_json::to_writer(cursors_file, &inner.processed_cursors)?;
+        write_cursors(&inner.cursors_path, &inner.processed_cursors)
+    }
 
-        Ok(())
+    fn flush_swap_cursor(&self, protocol: &Protocol, cursor: Option<EventID>) -> Result<()> {
+        let mut inner = self.inner.lock().map_err(|_| eyre!("Mutex poisoned"))?;
+        inner.swap_cursors.insert(protocol.clone(), cursor);
+        write_cursors(&inner.swap_cursors_path, &inner.swap_cursors)
     }
 
     fn load_token_pools(&self, protocols: &[Protocol]) -> Result<PoolCache> {
@@ -101,17 +120,21 @@ impl DB for FileDB {
                     continue;
                 }
             };
-            let reader = BufReader::new(pool_file);
+            let mut reader = BufReader::with_capacity(READ_CHUNK_SIZE, pool_file);
 
+            let timer = Instant::now();
             let mut count = 0;
-            for line in reader.lines() {
-                count += 1;
-                if line.is_err() {
-                    error!("Error reading line: {:?}", line);
-                    continue;
+            let mut line = String::new();
+            loop {
+                line.clear();
+                match reader.read_line(&mut line) {
+                    Ok(0) => break,
+                    Ok(_) => count += 1,
+                    // the error would repeat on the next line
+                    Err(e) => return Err(eyre!("Error reading the pools of {:?}: {:?}", protocol, e)),
                 }
 
-                let pool = Pool::try_from(line?.as_str())?;
+                let pool = Pool::try_from(line.trim_end_matches(['\n', '\r']))?;
                 // token_pools
                 for token in &pool.tokens {
                     let key = token.token_type.clone();
@@ -125,7 +148,9 @@ impl DB for FileDB {
                 // pool_map
                 pool_map.insert(pool.pool, pool);
             }
-            debug!(?protocol, pools_count = %count, "token pools loaded");
+            let elapsed = timer.elapsed();
+            let pools_per_sec = (count as f64 / elapsed.as_secs_f64()) as u64;
+            info!(?protocol, pools_count = %count, ?elapsed, %pools_per_sec, "token pools loaded");
         }
 
         Ok(PoolCache::new(token_pools, token01_pools, pool_map))
@@ -136,6 +161,11 @@ impl DB for FileDB {
         Ok(inner.processed_cursors.clone())
     }
 
+    fn get_swap_cursors(&self) -> Result<HashMap<Protocol, Option<EventID>>> {
+        let inner = self.inner.lock().map_err(|_| eyre!("Mutex poisoned"))?;
+        Ok(inner.swap_cursors.clone())
+    }
+
     fn pool_count(&self, protocol: &Protocol) -> Result<usize> {
         let inner = self.inner.lock().map_err(|_| eyre!("Mutex poisoned"))?;
         let pool_path = inner
@@ -148,19 +178,169 @@ impl DB for FileDB {
     }
 
     fn get_all_pools(&self, protocol: &Protocol) -> Result<Vec<Pool>> {
-        let inner = self.inner.lock().map_err(|_| eyre!("Mutex poisoned"))?;
-        let pool_path = inner
-            .pools_paths
-            .get(protocol)
-            .ok_or_else(|| eyre!("Protocol not supported: {:?}", protocol))?;
-        let pool_file = File::open(pool_path)?;
-        let reader = BufReader::new(pool_file);
+        self.stream_pools(protocol)?.collect()
+    }
+
+    // the file is read line by line as the pools are pulled, without holding the lock
+    fn stream_pools(&self, protocol: &Protocol) -> Result<PoolStream> {
+        let pool_file = {
+            let inner = self.inner.lock().map_err(|_| eyre!("Mutex poisoned"))?;
+            let pool_path = inner
+                .pools_paths
+                .get(protocol)
+                .ok_or_else(|| eyre!("Protocol not supported: {:?}", protocol))?;
+            File::open(pool_path)?
+        };
+
+        let pools = BufReader::new(pool_file)
+            .lines()
+            .map(|line| Pool::try_from(line?.as_str()));
+        Ok(Box::new(pools))
+    }
+}
 
-        let mut pools = vec![];
-        for line in reader.lines() {
-            pools.push(Pool::try_from(line?.as_str())?);
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use sui_sdk::types::base_types::ObjectID;
+
+    use super::*;
+    use crate::types::{PoolExtra, Token};
+
+    const SUI: &str = "0x2::sui::SUI";
+    const USDC: &str = "0xdba34672e30cb065b1f93e3ab55318768fd6fef66c15942c9f7cb846e2f900e7::usdc::USDC";
+
+    // a file db of `count` pools of each protocol, over a few tokens, generic lp coins and extras
+    fn fixture_db(count: usize) -> (FileDB, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("file_db-{}", ObjectID::random()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = FileDB::new(&dir, &[Protocol::Cetus, Protocol::Aftermath]).unwrap();
+
+        let lp = format!("0x1::lp::LP<{SUI}, {USDC}>");
+        let tokens = [SUI, USDC, "0x1::ocean::OCEAN", lp.as_str()];
+        let pools = |protocol: Protocol| {
+            (0..count)
+                .map(|i| Pool {
+                    protocol: protocol.clone(),
+                    pool: ObjectID::random(),
+                    tokens: (0..2 + i % 2)
+                        .map(|j| Token::new(tokens[(i + j) % 4], 6 + j as u8))
+                        .collect(),
+                    extra: match i % 3 {
+                        0 => PoolExtra::None,
+                        1 => PoolExtra::Cetus { fee_rate: i as u64 },
+                        _ => PoolExtra::DeepbookV2 {
+                            taker_fee_rate: 1,
+                            maker_rebate_rate: 2,
+                            tick_size: 3,
+                            lot_size: 4,
+                        },
+                    },
+                })
+                .collect::<Vec<_>>()
+        };
+        db.flush(&Protocol::Cetus, &pools(Protocol::Cetus), None).unwrap();
+        db.flush(&Protocol::Aftermath, &pools(Protocol::Aftermath), None)
+            .unwrap();
+
+        (db, dir)
+    }
+
+    // `load_token_pools` as it was, with serde_json for the tokens and extra of each line
+    fn load_token_pools_with_serde(path: PathBuf) -> PoolCache {
+        let (token_pools, token01_pools, pool_map) = (TokenPools::new(), Token01Pools::new(), DashMap::new());
+        for line in BufReader::new(File::open(path).unwrap()).lines() {
+            let line = line.unwrap();
+            let parts: Vec<&str> = line.split('|').collect();
+            let pool = Pool {
+                protocol: Protocol::try_from(parts[0]).unwrap(),
+                pool: parts[1].parse().unwrap(),
+                tokens: serde_json::from_str(parts[2]).unwrap(),
+                extra: serde_json::from_str(parts[3]).unwrap(),
+            };
+            for token in &pool.tokens {
+                token_pools
+                    .entry(token.token_type.clone())
+                    .or_default()
+                    .insert(pool.clone());
+            }
+            for (token0_type, token1_type) in pool.token01_pairs() {
+                let key = token01_key(&token0_type, &token1_type);
+                token01_pools.entry(key).or_default().insert(pool.clone());
+            }
+            pool_map.insert(pool.pool, pool);
+        }
+
+        PoolCache::new(token_pools, token01_pools, pool_map)
+    }
+
+    fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+        let timer = Instant::now();
+        let result = f();
+        (result, timer.elapsed())
+    }
+
+    #[test]
+    fn test_load_token_pools_of_requested_protocols() {
+        let (db, dir) = fixture_db(10);
+        let cache = db.load_token_pools(&[Protocol::Aftermath]).unwrap();
+        assert_eq!(cache.pool_map.len(), 10);
+        assert!(cache.pool_map.iter().all(|pool| pool.protocol == Protocol::Aftermath));
+
+        // only the requested protocols are known to a lazy db
+        let lazy_db = FileDB::new(&dir, &[Protocol::Cetus]).unwrap();
+        assert!(lazy_db.load_token_pools(&[Protocol::Aftermath]).is_err());
+        assert_eq!(lazy_db.load_token_pools(&[Protocol::Cetus]).unwrap().pool_map.len(), 10);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_token_pools_as_with_serde() {
+        let (db, dir) = fixture_db(1_000);
+        let cache = db.load_token_pools(&[Protocol::Cetus]).unwrap();
+        let expected = load_token_pools_with_serde(dir.join("cetus_pools.txt"));
+
+        // the same pools, tokens and extras in the same entries
+        let pools = |cache: &PoolCache| {
+            let mut pools = cache.pool_map.iter().map(|pool| pool.to_string()).collect::<Vec<_>>();
+            pools.sort();
+            pools
+        };
+        assert_eq!(cache.pool_map.len(), 1_000);
+        assert_eq!(pools(&cache), pools(&expected));
+
+        let ids = |pools: &HashSet<Pool>| {
+            let mut ids = pools.iter().map(|pool| pool.pool).collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        assert_eq!(cache.token_pools.len(), expected.token_pools.len());
+        for entry in expected.token_pools.iter() {
+            assert_eq!(ids(&cache.token_pools.get(entry.key()).unwrap()), ids(entry.value()));
         }
+        assert_eq!(cache.token01_pools.len(), expected.token01_pools.len());
+        for entry in expected.token01_pools.iter() {
+            assert_eq!(ids(&cache.token01_pools.get(entry.key()).unwrap()), ids(entry.value()));
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // cargo test --release -p dex-indexer bench_load_token_pools -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_load_token_pools() {
+        mev_logger::init_console_logger(None);
+        let (db, dir) = fixture_db(100_000);
+
+        let (cache, elapsed) = timed(|| db.load_token_pools(&[Protocol::Cetus]).unwrap());
+        let (expected, serde_elapsed) = timed(|| load_token_pools_with_serde(dir.join("cetus_pools.txt")));
+        std::fs::remove_dir_all(dir).unwrap();
 
-        Ok(pools)
+        info!(?elapsed, ?serde_elapsed, "100k pools loaded");
+        assert_eq!(cache.pool_map.len(), expected.pool_map.len());
+        assert!(elapsed < serde_elapsed);
     }
 }
@@ -4,31 +4,43 @@
 mod blockberry;
 mod collector;
 mod file_db;
+mod pool_state;
 mod protocols;
 mod strategy;
+mod swap_events;
 pub mod types;
 
 use std::{
     collections::{HashMap, HashSet},
     fmt::Debug,
+    ops::ControlFlow,
     sync::Arc,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use burberry::Engine;
 use collector::QueryEventCollector;
+use dashmap::DashMap;
 use eyre::Result;
+use simulator::Simulator;
 use strategy::PoolCreatedStrategy;
 use sui_sdk::{
     types::{base_types::ObjectID, event::EventID},
-    SuiClientBuilder, SUI_COIN_TYPE,
+    SuiClient, SuiClientBuilder, SUI_COIN_TYPE,
 };
-use tokio::task::JoinSet;
+use tokio::{sync::Mutex, task::JoinSet};
 use tracing::info;
-use types::{DummyExecutor, Event, NoAction, Pool, PoolCache, Protocol};
+use types::{DummyExecutor, Event, NoAction, Pool, PoolCache, Protocol, SwapEvent};
+use utils::heartbeat::{self, HealthStatus};
+
+pub use pool_state::{PoolState, PoolStateCache};
+pub use swap_events::SwapEventCollector;
 
 pub const FILE_DB_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data");
 
+// the cursors catch up every 10s, a protocol that hasn't for this long is reported unhealthy
+const CURSOR_STALE_AFTER: Duration = Duration::from_secs(60);
+
 pub fn supported_protocols() -> Vec<Protocol> {
     vec![
         Protocol::Cetus,
@@ -42,26 +54,58 @@ pub fn supported_protocols() -> Vec<Protocol> {
     ]
 }
 
+/// The protocols whose swap events are streamed by a `SwapEventCollector`.
+pub fn swap_protocols() -> Vec<Protocol> {
+    vec![
+        Protocol::Cetus,
+        Protocol::Turbos,
+        Protocol::Aftermath,
+        Protocol::KriyaAmm,
+        Protocol::KriyaClmm,
+        Protocol::FlowxAmm,
+        Protocol::FlowxClmm,
+        Protocol::BlueMove,
+        Protocol::SuiSwap,
+        Protocol::Interest,
+        Protocol::Abex,
+        Protocol::BabySwap,
+    ]
+}
+
 #[derive(Clone)]
 pub struct DexIndexer {
     pool_cache: PoolCache,
+    protocols: Vec<Protocol>,
+    pool_states: PoolStateCache,
 
     db: Arc<dyn DB>,
+    synced_at: Arc<DashMap<Protocol, Instant>>,
+    stopped: Arc<Mutex<bool>>,
     _live_indexer_tasks: Arc<JoinSet<()>>,
 }
 
 impl DexIndexer {
     pub async fn new(http_url: &str) -> Result<Self> {
+        Self::new_with_protocols(http_url, &supported_protocols()).await
+    }
+
+    /// Only the pools of `protocols` are loaded from the db and synced, e.g. the protocols that the bot trades.
+    pub async fn new_with_protocols(http_url: &str, protocols: &[Protocol]) -> Result<Self> {
         let sui = SuiClientBuilder::default().build(http_url).await?;
-        let db = Arc::new(file_db::FileDB::new(FILE_DB_DIR, &supported_protocols())?);
+        let db = Arc::new(file_db::FileDB::new(FILE_DB_DIR, protocols)?);
 
         let timer = Instant::now();
-        info!("loading token pools...");
-        let pool_cache = db.load_token_pools(&supported_protocols())?;
-        info!(elapsed = ?timer.elapsed(), token_pools_count = %pool_cache.token_pools.len(), token01_pools_count = %pool_cache.token01_pools.len(), "token pools loaded");
+        info!(?protocols, "loading token pools...");
+        let pool_cache = db.load_token_pools(protocols)?;
+        let elapsed = timer.elapsed();
+        let pools_count = pool_cache.pool_map.len();
+        let pools_per_sec = (pools_count as f64 / elapsed.as_secs_f64()) as u64;
+        info!(?elapsed, %pools_count, %pools_per_sec, token_pools_count = %pool_cache.token_pools.len(), token01_pools_count = %pool_cache.token01_pools.len(), "token pools loaded");
 
-        let strategy = PoolCreatedStrategy::new(db.clone(), sui.clone(), pool_cache.clone())?;
+        let strategy = PoolCreatedStrategy::new(db.clone(), sui.clone(), pool_cache.clone(), protocols.to_vec())?;
         strategy.backfill_pools().await?;
+        let synced_at = strategy.synced_at();
+        let stopped = strategy.stopped();
 
         // Build the bubbery engine
         let mut engine = Engine::<Event, NoAction>::new();
@@ -72,11 +116,77 @@ impl DexIndexer {
 
         let join_set = engine.run().await.expect("Burberry engine run failed");
 
-        Ok(Self {
+        let indexer = Self {
             pool_cache,
+            protocols: protocols.to_vec(),
+            pool_states: PoolStateCache::new(),
             db,
+            synced_at,
+            stopped,
             _live_indexer_tasks: Arc::new(join_set),
-        })
+        };
+        heartbeat::register_probe("dex_indexer", {
+            let indexer = indexer.clone();
+            Box::new(move || indexer.cursor_health())
+        });
+
+        Ok(indexer)
+    }
+
+    /// Stop syncing the pools, once the sync in progress has flushed its pools and cursors to the db.
+    pub async fn shutdown(&self) {
+        *self.stopped.lock().await = true;
+        info!("dex indexer stopped");
+    }
+
+    /// The time since the cursor of each synced protocol last caught up with the chain, `None` if it never did.
+    pub fn cursor_ages(&self) -> Vec<(Protocol, Option<Duration>)> {
+        self.protocols
+            .iter()
+            .map(|protocol| {
+                let age = self.synced_at.get(protocol).map(|synced_at| synced_at.elapsed());
+                (protocol.clone(), age)
+            })
+            .collect()
+    }
+
+    fn cursor_health(&self) -> HealthStatus {
+        let stale: Vec<_> = self
+            .cursor_ages()
+            .into_iter()
+            .filter_map(|(protocol, age)| match age {
+                Some(age) if age <= CURSOR_STALE_AFTER => None,
+                Some(age) => Some(format!("{protocol} synced {}s ago", age.as_secs())),
+                None => Some(format!("{protocol} never synced")),
+            })
+            .collect();
+
+        if stale.is_empty() {
+            HealthStatus::Healthy
+        } else {
+            HealthStatus::Unhealthy(stale.join(", "))
+        }
+    }
+
+    /// A `SwapEventCollector` whose cursors are persisted in the db of the indexer.
+    pub fn swap_event_collector(&self, sui: SuiClient, simulator: Arc<dyn Simulator>) -> SwapEventCollector {
+        SwapEventCollector::new(sui, self.db.clone(), simulator)
+    }
+
+    /// The last swap of the given pool, as fed with `update_pool_state`.
+    pub fn pool_state(&self, pool_id: &ObjectID) -> Option<PoolState> {
+        self.pool_states.get(pool_id)
+    }
+
+    /// Record the swap of `swap_event`, executed at `timestamp_ms`, or seen now if it's not executed yet.
+    pub fn update_pool_state(&self, swap_event: &SwapEvent, timestamp_ms: Option<u64>) {
+        let at_ms = timestamp_ms.unwrap_or_else(utils::current_time_ms);
+        self.pool_states.update(swap_event, at_ms);
+    }
+
+    /// When the pool states started being recorded, see `PoolStateCache::since_ms`.
+    pub fn pool_states_since_ms(&self) -> u64 {
+        self.pool_states.since_ms()
     }
 
     /// Get the pools by the given token type.
@@ -104,6 +214,31 @@ impl DexIndexer {
     pub fn get_all_pools(&self, protocol: &Protocol) -> Result<Vec<Pool>> {
         self.db.get_all_pools(protocol)
     }
+
+    /// Call `f` on the pools of the given protocol as they are read from the db, until it breaks. Unlike
+    /// `get_all_pools`, the pools are never all in memory.
+    pub fn for_each_pool(&self, protocol: &Protocol, mut f: impl FnMut(&Pool) -> ControlFlow<()>) -> Result<()> {
+        for pool in self.db.stream_pools(protocol)? {
+            if f(&pool?).is_break() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the pools of the given protocol that match `predicate`, e.g. by token or liquidity.
+    pub fn get_pools_filtered(&self, protocol: &Protocol, mut predicate: impl FnMut(&Pool) -> bool) -> Result<Vec<Pool>> {
+        let mut pools = vec![];
+        self.for_each_pool(protocol, |pool| {
+            if predicate(pool) {
+                pools.push(pool.clone());
+            }
+            ControlFlow::Continue(())
+        })?;
+
+        Ok(pools)
+    }
 }
 
 #[inline]
@@ -128,19 +263,97 @@ pub trait DB: Debug + Send + Sync {
     fn flush(&self, protocol: &Protocol, pools: &[Pool], cursor: Option<EventID>) -> Result<()>;
     fn load_token_pools(&self, protocols: &[Protocol]) -> Result<PoolCache>;
     fn get_processed_cursors(&self) -> Result<HashMap<Protocol, Option<EventID>>>;
+    /// The cursors of the swap events streamed by a `SwapEventCollector`, apart from the processed cursors.
+    fn flush_swap_cursor(&self, protocol: &Protocol, cursor: Option<EventID>) -> Result<()>;
+    fn get_swap_cursors(&self) -> Result<HashMap<Protocol, Option<EventID>>>;
     fn pool_count(&self, protocol: &Protocol) -> Result<usize>;
     fn get_all_pools(&self, protocol: &Protocol) -> Result<Vec<Pool>>;
+    /// The pools of `protocol`, read lazily as the stream is consumed.
+    fn stream_pools(&self, protocol: &Protocol) -> Result<PoolStream>;
 }
 
+pub type PoolStream = Box<dyn Iterator<Item = Result<Pool>> + Send>;
+
 #[cfg(test)]
 mod tests {
 
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use burberry::async_trait;
+    use move_core_types::{
+        account_address::AccountAddress,
+        annotated_value::{MoveStruct, MoveValue},
+        identifier::Identifier,
+        language_storage::StructTag,
+    };
+    use simulator::{SimulateCtx, SimulateResult};
+    use sui_sdk::types::{object::Object, transaction::TransactionData};
+
     use super::*;
+    use crate::{
+        file_db::FileDB,
+        types::{PoolExtra, Token, Token01Pools, TokenPools},
+    };
 
     pub const TEST_HTTP_URL: &str = "";
     const TOKEN0_TYPE: &str = "";
     const TOKEN1_TYPE: &str = "";
 
+    /// A test `DBSimulator` that enumerates dynamic fields over `TEST_HTTP_URL`.
+    pub async fn new_test_db_simulator() -> simulator::DBSimulator {
+        let client = SuiClientBuilder::default().build(TEST_HTTP_URL).await.unwrap();
+        simulator::DBSimulator::new_test(true)
+            .await
+            .with_dynamic_fields_client(client)
+    }
+
+    /// A simulator serving the dynamic fields of each parent, and no object.
+    #[derive(Default)]
+    pub struct MockSimulator {
+        children: HashMap<ObjectID, Vec<ObjectID>>,
+    }
+
+    impl MockSimulator {
+        pub fn with_children(mut self, parent: ObjectID, children: Vec<ObjectID>) -> Self {
+            self.children.insert(parent, children);
+            self
+        }
+    }
+
+    #[async_trait]
+    impl Simulator for MockSimulator {
+        async fn simulate(&self, _: TransactionData, _: SimulateCtx) -> Result<SimulateResult> {
+            eyre::bail!("MockSimulator can't simulate")
+        }
+
+        async fn get_object(&self, _: &ObjectID) -> Option<Object> {
+            None
+        }
+
+        async fn get_dynamic_children(&self, parent: &ObjectID) -> Result<Vec<ObjectID>> {
+            Ok(self.children.get(parent).cloned().unwrap_or_default())
+        }
+
+        fn name(&self) -> &str {
+            "MockSimulator"
+        }
+    }
+
+    /// A `module::name` Move struct with `fields`, as parsed from a doctored object.
+    pub fn move_struct(module: &str, name: &str, fields: Vec<(&str, MoveValue)>) -> MoveStruct {
+        let type_ = StructTag {
+            address: AccountAddress::ONE,
+            module: Identifier::new(module).unwrap(),
+            name: Identifier::new(name).unwrap(),
+            type_params: vec![],
+        };
+        let fields = fields
+            .into_iter()
+            .map(|(name, value)| (Identifier::new(name).unwrap(), value))
+            .collect();
+        MoveStruct::new(type_, fields)
+    }
+
     #[tokio::test]
     async fn test_get_pools() {
         // `DexIndexer::new` will backfill pools first.
@@ -167,6 +380,129 @@ mod tests {
         assert_eq!(normalize_coin_type(TOKEN1_TYPE), TOKEN1_TYPE.to_string());
     }
 
+    const USDC: &str = "0xdba34672e30cb065b1f93e3ab55318768fd6fef66c15942c9f7cb846e2f900e7::usdc::USDC";
+
+    // counts the pools pulled from the stream of the inner db
+    #[derive(Debug)]
+    struct CountingDB {
+        inner: FileDB,
+        read: Arc<AtomicUsize>,
+    }
+
+    impl DB for CountingDB {
+        fn flush(&self, protocol: &Protocol, pools: &[Pool], cursor: Option<EventID>) -> Result<()> {
+            self.inner.flush(protocol, pools, cursor)
+        }
+
+        fn load_token_pools(&self, protocols: &[Protocol]) -> Result<PoolCache> {
+            self.inner.load_token_pools(protocols)
+        }
+
+        fn get_processed_cursors(&self) -> Result<HashMap<Protocol, Option<EventID>>> {
+            self.inner.get_processed_cursors()
+        }
+
+        fn flush_swap_cursor(&self, protocol: &Protocol, cursor: Option<EventID>) -> Result<()> {
+            self.inner.flush_swap_cursor(protocol, cursor)
+        }
+
+        fn get_swap_cursors(&self) -> Result<HashMap<Protocol, Option<EventID>>> {
+            self.inner.get_swap_cursors()
+        }
+
+        fn pool_count(&self, protocol: &Protocol) -> Result<usize> {
+            self.inner.pool_count(protocol)
+        }
+
+        fn get_all_pools(&self, protocol: &Protocol) -> Result<Vec<Pool>> {
+            self.inner.get_all_pools(protocol)
+        }
+
+        fn stream_pools(&self, protocol: &Protocol) -> Result<PoolStream> {
+            let read = self.read.clone();
+            let pools = self.inner.stream_pools(protocol)?.inspect(move |_| {
+                read.fetch_add(1, Ordering::Relaxed);
+            });
+            Ok(Box::new(pools))
+        }
+    }
+
+    // an indexer over a file db of 10 Cetus pools, every third one is a SUI/USDC pool
+    fn fixture_indexer() -> (DexIndexer, Arc<AtomicUsize>) {
+        let dir = std::env::temp_dir().join(format!("dex_indexer-{}", ObjectID::random()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file_db = FileDB::new(&dir, &[Protocol::Cetus]).unwrap();
+
+        let pools = (0..10)
+            .map(|i| {
+                let token1_type = if i % 3 == 0 { USDC } else { "0x1::ocean::OCEAN" };
+                Pool {
+                    protocol: Protocol::Cetus,
+                    pool: ObjectID::random(),
+                    tokens: vec![Token::new(SUI_COIN_TYPE, 9), Token::new(token1_type, 6)],
+                    extra: PoolExtra::None,
+                }
+            })
+            .collect::<Vec<_>>();
+        file_db.flush(&Protocol::Cetus, &pools, None).unwrap();
+
+        let read = Arc::new(AtomicUsize::new(0));
+        let db = CountingDB {
+            inner: file_db,
+            read: read.clone(),
+        };
+        let indexer = DexIndexer {
+            pool_cache: PoolCache::new(TokenPools::new(), Token01Pools::new(), DashMap::new()),
+            protocols: vec![Protocol::Cetus],
+            pool_states: PoolStateCache::new(),
+            db: Arc::new(db),
+            synced_at: Arc::new(DashMap::new()),
+            stopped: Arc::new(Mutex::new(false)),
+            _live_indexer_tasks: Arc::new(JoinSet::new()),
+        };
+        (indexer, read)
+    }
+
+    #[test]
+    fn test_for_each_pool_stops_reading_on_break() {
+        let (indexer, read) = fixture_indexer();
+
+        let mut seen = 0;
+        indexer
+            .for_each_pool(&Protocol::Cetus, |_| {
+                seen += 1;
+                if seen == 3 {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })
+            .unwrap();
+        assert_eq!(seen, 3);
+        assert_eq!(read.load(Ordering::Relaxed), 3);
+
+        indexer.for_each_pool(&Protocol::Cetus, |_| ControlFlow::Continue(())).unwrap();
+        assert_eq!(read.load(Ordering::Relaxed), 3 + 10);
+    }
+
+    #[test]
+    fn test_pools_filtered_match_all_pools() {
+        let (indexer, _) = fixture_indexer();
+        let ids = |pools: Vec<Pool>| pools.into_iter().map(|pool| pool.pool).collect::<Vec<_>>();
+
+        let all_pools = indexer.get_all_pools(&Protocol::Cetus).unwrap();
+        assert_eq!(all_pools.len(), 10);
+        assert_eq!(
+            ids(indexer.get_pools_filtered(&Protocol::Cetus, |_| true).unwrap()),
+            ids(all_pools.clone())
+        );
+
+        let has_usdc = |pool: &Pool| pool.tokens.iter().any(|token| token.token_type == USDC);
+        let usdc_pools = indexer.get_pools_filtered(&Protocol::Cetus, has_usdc).unwrap();
+        assert_eq!(usdc_pools.len(), 4);
+        assert_eq!(ids(usdc_pools), ids(all_pools.into_iter().filter(has_usdc).collect()));
+    }
+
     #[tokio::test]
     async fn test_pools_count() {
         let indexer = DexIndexer::new(TEST_HTTP_URL).await.unwrap();
@@ -0,0 +1,155 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use sui_sdk::types::base_types::ObjectID;
+
+use crate::types::SwapEvent;
+
+/// The last swap of a pool, as seen in its swap events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolState {
+    pub coin_in: String,
+    pub coin_out: String,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    /// `amount_out / amount_in`, in the raw units of the coins
+    pub price: f64,
+    pub updated_at_ms: u64,
+}
+
+impl PoolState {
+    /// The time since the last swap, at `now_ms`.
+    pub fn age(&self, now_ms: u64) -> Duration {
+        Duration::from_millis(now_ms.saturating_sub(self.updated_at_ms))
+    }
+}
+
+/// The `PoolState` of each pool, updated from the swap events so that the pools can be ranked without a
+/// simulation. Cheap to clone, the clones share the states.
+#[derive(Debug, Clone)]
+pub struct PoolStateCache {
+    states: Arc<DashMap<ObjectID, PoolState>>,
+    since_ms: u64,
+}
+
+impl Default for PoolStateCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PoolStateCache {
+    pub fn new() -> Self {
+        Self::new_since(utils::current_time_ms())
+    }
+
+    pub fn new_since(since_ms: u64) -> Self {
+        Self {
+            states: Arc::new(DashMap::new()),
+            since_ms,
+        }
+    }
+
+    /// When the states started being recorded: the pools without one didn't swap since.
+    pub fn since_ms(&self) -> u64 {
+        self.since_ms
+    }
+
+    /// Record the swap of `swap_event` at `at_ms`. The events without a pool or an amount, and the ones older
+    /// than the state of their pool, are ignored.
+    pub fn update(&self, swap_event: &SwapEvent, at_ms: u64) {
+        let Some(pool_id) = swap_event.pool_id() else {
+            return;
+        };
+        let (Some(coin_in), Some(coin_out)) = (swap_event.coins_in.first(), swap_event.coins_out.first()) else {
+            return;
+        };
+        let (Some(&amount_in), Some(&amount_out)) = (swap_event.amounts_in.first(), swap_event.amounts_out.first())
+        else {
+            return;
+        };
+        if amount_in == 0 {
+            return;
+        }
+
+        let state = PoolState {
+            coin_in: coin_in.clone(),
+            coin_out: coin_out.clone(),
+            amount_in,
+            amount_out,
+            price: amount_out as f64 / amount_in as f64,
+            updated_at_ms: at_ms,
+        };
+        self.states
+            .entry(pool_id)
+            .and_modify(|last| {
+                if last.updated_at_ms <= at_ms {
+                    *last = state.clone();
+                }
+            })
+            .or_insert(state);
+    }
+
+    pub fn get(&self, pool_id: &ObjectID) -> Option<PoolState> {
+        self.states.get(pool_id).map(|state| state.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Protocol;
+
+    const USDC: &str = "0xdba34672e30cb065b1f93e3ab55318768fd6fef66c15942c9f7cb846e2f900e7::usdc::USDC";
+
+    fn swap_event(pool: Option<ObjectID>, amount_in: u64, amount_out: u64) -> SwapEvent {
+        SwapEvent {
+            protocol: Protocol::Cetus,
+            pool,
+            coins_in: vec!["0x2::sui::SUI".to_string()],
+            coins_out: vec![USDC.to_string()],
+            amounts_in: vec![amount_in],
+            amounts_out: vec![amount_out],
+        }
+    }
+
+    #[test]
+    fn test_pool_state_reflects_latest_swap() {
+        let cache = PoolStateCache::new();
+        let (pool, other) = (ObjectID::random(), ObjectID::random());
+
+        cache.update(&swap_event(Some(pool), 1_000, 3_500), 1_000);
+        cache.update(&swap_event(Some(other), 1_000, 3_400), 1_500);
+        cache.update(&swap_event(Some(pool), 2_000, 7_200), 2_000);
+        let state = cache.get(&pool).unwrap();
+        assert_eq!(
+            (state.amount_in, state.amount_out, state.updated_at_ms),
+            (2_000, 7_200, 2_000)
+        );
+        assert_eq!(state.price, 3.6);
+        assert_eq!(
+            (state.coin_in.as_str(), state.coin_out.as_str()),
+            ("0x2::sui::SUI", USDC)
+        );
+        assert_eq!(state.age(62_000), Duration::from_secs(60));
+
+        // a late event doesn't override a newer swap
+        cache.update(&swap_event(Some(pool), 1_000, 3_000), 1_800);
+        assert_eq!(cache.get(&pool).unwrap().updated_at_ms, 2_000);
+
+        // nor do the events without a pool or an amount in
+        cache.update(&swap_event(None, 1_000, 3_000), 3_000);
+        cache.update(&swap_event(Some(pool), 0, 0), 3_000);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&pool).unwrap().updated_at_ms, 2_000);
+        assert_eq!(cache.get(&other).unwrap().price, 3.4);
+    }
+}
@@ -13,7 +13,7 @@ use sui_sdk::{
 };
 use sui_types::TypeTag;
 
-use super::{get_children_ids, get_coin_decimals};
+use super::get_coin_decimals;
 use crate::{
     normalize_coin_type,
     types::{Pool, PoolExtra, Protocol, SwapEvent, Token},
@@ -258,7 +258,7 @@ impl AftermathSwapEvent {
     }
 }
 
-pub async fn aftermath_related_object_ids() -> Vec<String> {
+pub async fn aftermath_related_object_ids(simulator: Arc<dyn Simulator>) -> Vec<String> {
     let mut res = vec![
         "0xc4049b2d1cc0f6e017fda8260e4377cecd236bd7f56a54fee120816e72e2e0dd", // Aftermath AmmV2
         "0xfcc774493db2c45c79f688f88d28023a3e7d98e4ee9f48bbf5c7990f651577ae", // PoolRegistry
@@ -307,8 +307,8 @@ pub async fn aftermath_related_object_ids() -> Vec<String> {
         .collect::<Vec<_>>();
 
     for id in parent_ids {
-        if let Ok(children) = get_children_ids(id).await {
-            res.extend(children);
+        if let Ok(children) = simulator.get_dynamic_children(&id).await {
+            res.extend(children.iter().map(|id| id.to_string()));
         }
     }
 
@@ -340,8 +340,8 @@ pub async fn aftermath_pool_children_ids(pool: &Pool, simulator: Arc<dyn Simulat
         result.push(object_id.to_string());
     }
 
-    if let Ok(children_ids) = get_children_ids(pool.pool).await {
-        result.extend(children_ids);
+    if let Ok(children_ids) = simulator.get_dynamic_children(&pool.pool).await {
+        result.extend(children_ids.iter().map(|id| id.to_string()));
     }
 
     Ok(result)
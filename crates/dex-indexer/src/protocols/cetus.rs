@@ -14,14 +14,14 @@ use simulator::{SimulateCtx, Simulator};
 use sui_sdk::{
     rpc_types::{EventFilter, SuiData, SuiEvent, SuiObjectDataOptions},
     types::{base_types::ObjectID, TypeTag},
-    SuiClient, SuiClientBuilder,
+    SuiClient,
 };
 use sui_types::{
     base_types::SuiAddress, dynamic_field::derive_dynamic_field_id, object::Object, programmable_transaction_builder::ProgrammableTransactionBuilder, transaction::{Command, TransactionData}, Identifier
 };
 use utils::object::*;
 
-use super::{get_coin_decimals, get_pool_coins_type, SUI_RPC_NODE};
+use super::{get_coin_decimals, get_pool_coins_type};
 use crate::{
     get_coin_in_out_v2,
     types::{Pool, PoolExtra, Protocol, SwapEvent, Token},
@@ -251,26 +251,9 @@ pub async fn cetus_pool_children_ids(pool: &Pool, simulator: Arc<dyn Simulator>)
 
         id
     };
-    let sui_client = SuiClientBuilder::default()
-    .build(SUI_RPC_NODE)
-    .await
-    .unwrap();
-
-    let mut next_cursor = None;
-    let mut tick_vec = Vec::new();
-
-    loop {
-        let ret = sui_client.read_api().get_dynamic_fields(positions_id, next_cursor, None).await?;
-        next_cursor = ret.next_cursor;
-        tick_vec.extend(ret.data); 
-        if !ret.has_next_page {
-            break;
-        }
-    }
 
-    let tick_vec: Vec<String> = tick_vec.iter().map(|field_info| {
-        field_info.object_id.to_string()
-    }).collect();
+    let tick_vec = simulator.get_dynamic_children(&positions_id).await?;
+    let tick_vec = tick_vec.iter().map(|id| id.to_string()).collect::<Vec<_>>();
    
     result.extend(tick_vec);
 
@@ -289,21 +272,8 @@ pub async fn cetus_pool_children_ids(pool: &Pool, simulator: Arc<dyn Simulator>)
             id
         };
 
-        let mut next_cursor = None;
-        let mut tick_vec = Vec::new();
-    
-        loop {
-            let ret = sui_client.read_api().get_dynamic_fields(id, next_cursor, None).await?;
-            next_cursor = ret.next_cursor;
-            tick_vec.extend(ret.data); 
-            if !ret.has_next_page {
-                break;
-            }
-        }
-
-        let tick_vec: Vec<String> = tick_vec.iter().map(|field_info| {
-            field_info.object_id.to_string()
-        }).collect();
+        let tick_vec = simulator.get_dynamic_children(&id).await?;
+        let tick_vec = tick_vec.iter().map(|id| id.to_string()).collect::<Vec<_>>();
     
         result.extend(tick_vec);
 
@@ -395,6 +365,7 @@ fn pool_layout(pool_id: ObjectID, simulator: Arc<dyn Simulator>) -> MoveStructLa
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tests::new_test_db_simulator;
     use mev_logger::LevelFilter;
     use simulator::{DBSimulator, HttpSimulator};
     use tokio::time::Instant;
@@ -455,7 +426,7 @@ mod tests {
             extra: PoolExtra::None,
         };
 
-        let simulator: Arc<dyn Simulator> = Arc::new(DBSimulator::new_test(true).await);
+        let simulator: Arc<dyn Simulator> = Arc::new(new_test_db_simulator().await);
 
         let start = Instant::now();
         let children_ids = cetus_pool_children_ids(&pool, simulator).await.unwrap();
@@ -479,7 +450,7 @@ mod tests {
             extra: PoolExtra::None,
         };
 
-        let simulator: Arc<dyn Simulator> = Arc::new(DBSimulator::new_test(true).await);
+        let simulator: Arc<dyn Simulator> = Arc::new(new_test_db_simulator().await);
 
         // let start = Instant::now();
         let children_ids = cetus_pool_children_ids(&pool, simulator).await.unwrap();
@@ -9,14 +9,14 @@ use simulator::Simulator;
 use sui_sdk::{
     rpc_types::{EventFilter, SuiEvent},
     types::base_types::ObjectID,
-    SuiClient, SuiClientBuilder,
+    SuiClient,
 };
 use sui_types::{dynamic_field::derive_dynamic_field_id, TypeTag};
 use utils::object::{
     extract_object_id_from_move_struct, extract_struct_from_move_struct,
 };
 
-use super::{get_coin_decimals, get_pool_coins_type, SUI_RPC_NODE};
+use super::{get_coin_decimals, get_pool_coins_type};
 use crate::{
     get_coin_in_out_v2,
     types::{Pool, PoolExtra, Protocol, SwapEvent, Token},
@@ -230,16 +230,12 @@ pub async fn kriya_clmm_pool_children_ids(pool: &Pool, simulator: Arc<dyn Simula
             .await
             .ok_or_else(|| eyre!("KriyaClmm pool not found: {}", pool.pool))?;
 
-        let layout = pool_layout(pool.pool, simulator);
+        let layout = pool_layout(pool.pool, simulator.clone());
 
         let move_obj = pool_obj.data.try_as_move().ok_or_eyre("Not a Move object")?;
         MoveStruct::simple_deserialize(move_obj.contents(), &layout).map_err(|e| eyre!(e))?
     };
 
-    let sui_client = SuiClientBuilder::default()
-    .build(SUI_RPC_NODE)
-    .await
-    .unwrap();
 
     // tick ID
     {
@@ -250,20 +246,8 @@ pub async fn kriya_clmm_pool_children_ids(pool: &Pool, simulator: Arc<dyn Simula
             extract_object_id_from_move_struct(&id, "bytes")?
         };
 
-        let mut next_cursor = None;
-        let mut tick_vec = Vec::new();
-
-        loop {
-            let ret = sui_client.read_api().get_dynamic_fields(ticks_id, next_cursor, None).await?;
-            next_cursor = ret.next_cursor;
-            tick_vec.extend(ret.data); 
-            if !ret.has_next_page {
-                break;
-            }
-        }
-        let tick_vec: Vec<String> = tick_vec.iter().map(|field_info| {
-            field_info.object_id.to_string()
-        }).collect();
+        let tick_vec = simulator.get_dynamic_children(&ticks_id).await?;
+        let tick_vec = tick_vec.iter().map(|id| id.to_string()).collect::<Vec<_>>();
         res.extend(tick_vec);
     }
 
@@ -279,20 +263,8 @@ pub async fn kriya_clmm_pool_children_ids(pool: &Pool, simulator: Arc<dyn Simula
             extract_object_id_from_move_struct(&id, "bytes")?
         };
 
-        let mut next_cursor = None;
-        let mut tick_vec = Vec::new();
-    
-        loop {
-            let ret = sui_client.read_api().get_dynamic_fields(tick_bitmap_id, next_cursor, None).await?;
-            next_cursor = ret.next_cursor;
-            tick_vec.extend(ret.data); 
-            if !ret.has_next_page {
-                break;
-            }
-        }
-        let tick_vec: Vec<String> = tick_vec.iter().map(|field_info| {
-            field_info.object_id.to_string()
-        }).collect();
+        let tick_vec = simulator.get_dynamic_children(&tick_bitmap_id).await?;
+        let tick_vec = tick_vec.iter().map(|id| id.to_string()).collect::<Vec<_>>();
         res.extend(tick_vec);
     }
 
@@ -316,6 +288,7 @@ mod tests {
     use std::str::FromStr;
 
     use super::*;
+    use crate::{protocols::SUI_RPC_NODE, tests::new_test_db_simulator};
     use mev_logger::LevelFilter;
     use simulator::DBSimulator;
     use simulator::HttpSimulator;
@@ -376,7 +349,7 @@ mod tests {
             extra: PoolExtra::None,
         };
 
-        let simulator: Arc<dyn Simulator> = Arc::new(DBSimulator::new_test(true).await);
+        let simulator: Arc<dyn Simulator> = Arc::new(new_test_db_simulator().await);
         let start = Instant::now();
         let children_ids = kriya_clmm_pool_children_ids(&pool, simulator).await.unwrap();
         println!("Took ==============> : {} ms", start.elapsed().as_millis());
@@ -21,7 +21,7 @@ use eyre::{bail, ensure, eyre, Result};
 use sui_sdk::{
     rpc_types::SuiObjectDataOptions,
     types::{base_types::ObjectID, TypeTag},
-    SuiClient, SuiClientBuilder,
+    SuiClient,
 };
 
 use crate::{blockberry, normalize_coin_type};
@@ -111,25 +111,6 @@ macro_rules! get_coin_in_out_v2 {
     }};
 }
 
-// For generating pool_related_ids.txt only, using HttpClient is acceptable.
-pub async fn get_children_ids(id: ObjectID) -> Result<Vec<String>> {
-    let sui_client = SuiClientBuilder::default().build(SUI_RPC_NODE).await.unwrap();
-    let mut next_cursor = None;
-    let mut children = vec![];
-
-    loop {
-        let ret = sui_client.read_api().get_dynamic_fields(id, next_cursor, None).await?;
-        next_cursor = ret.next_cursor;
-        let children_ids = ret.data.iter().map(|field_info| field_info.object_id.to_string());
-        children.extend(children_ids);
-        if !ret.has_next_page {
-            break;
-        }
-    }
-
-    Ok(children)
-}
-
 #[macro_export]
 macro_rules! move_field_layout {
     ($name:literal, $layout:expr) => {
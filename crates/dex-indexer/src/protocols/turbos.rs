@@ -9,7 +9,7 @@ use simulator::Simulator;
 use sui_sdk::{
     rpc_types::{EventFilter, SuiEvent},
     types::base_types::ObjectID,
-    SuiClient, SuiClientBuilder,
+    SuiClient,
 };
 // use sui_types::{dynamic_field::derive_dynamic_field_id, TypeTag};
 
@@ -17,7 +17,7 @@ use utils::object::{
     extract_object_id_from_move_struct, extract_struct_from_move_struct,
 };
 
-use super::{get_coin_decimals, get_pool_coins_type, SUI_RPC_NODE};
+use super::{get_coin_decimals, get_pool_coins_type};
 use crate::{
     get_coin_in_out_v2,
     types::{Pool, PoolExtra, Protocol, SwapEvent, Token},
@@ -192,13 +192,24 @@ pub async fn turbos_pool_children_ids(pool: &Pool, simulator: Arc<dyn Simulator>
             .await
             .ok_or_else(|| eyre!("Turbos pool not found: {}", pool.pool))?;
 
-        let layout = pool_layout(pool.pool, simulator);
+        let layout = pool_layout(pool.pool, simulator.clone());
 
         let move_obj = pool_obj.data.try_as_move().ok_or_eyre("Not a Move object")?;
         MoveStruct::simple_deserialize(move_obj.contents(), &layout).map_err(|e| eyre!(e))?
     };
 
-    let tick_map = extract_struct_from_move_struct(&parsed_pool, "tick_map")?;
+    pool_children_ids(pool.pool, &parsed_pool, &*simulator).await
+}
+
+// the objects read by a swap besides the pool: the words of the tick bitmap, and the initialized ticks with
+// their fee and reward growth outside, which are dynamic fields of the pool. The reward vaults are only
+// read when collecting rewards, not by a swap.
+async fn pool_children_ids(
+    pool_id: ObjectID,
+    parsed_pool: &MoveStruct,
+    simulator: &dyn Simulator,
+) -> Result<Vec<String>> {
+    let tick_map = extract_struct_from_move_struct(parsed_pool, "tick_map")?;
 
     let tickmap_id = {
         let id = extract_struct_from_move_struct(&tick_map, "id")?;
@@ -206,31 +217,10 @@ pub async fn turbos_pool_children_ids(pool: &Pool, simulator: Arc<dyn Simulator>
         extract_object_id_from_move_struct(&id, "bytes")?
     };
 
-    let sui_client = SuiClientBuilder::default()
-    .build(SUI_RPC_NODE)
-    .await
-    .unwrap();
-
-    let mut next_cursor = None;
-    let mut tick_vec = Vec::new();
-
-    loop {
-        let ret = sui_client.read_api().get_dynamic_fields(tickmap_id, next_cursor, None).await?;
-        next_cursor = ret.next_cursor;
-        tick_vec.extend(ret.data); 
-        if !ret.has_next_page {
-            break;
-        }
-    }
-
-    let tick_vec: Vec<String> = tick_vec.iter().map(|field_info| {
-        field_info.object_id.to_string()
-    }).collect();
-
-    // println!("tick_vec ======> {:?}", tick_vec);
-
-    Ok(tick_vec)
+    let mut children = simulator.get_dynamic_children(&tickmap_id).await?;
+    children.extend(simulator.get_dynamic_children(&pool_id).await?);
 
+    Ok(children.iter().map(|id| id.to_string()).collect())
 }
 
 
@@ -249,9 +239,56 @@ mod tests {
     use std::str::FromStr;
 
     use super::*;
+    use crate::tests::{move_struct, new_test_db_simulator, MockSimulator};
     use mev_logger::LevelFilter;
-    use simulator::DBSimulator;
-    use simulator::HttpSimulator;
+    use move_core_types::annotated_value::MoveValue;
+    use simulator::{DBSimulator, HttpSimulator};
+
+    // a pool object with the fields read by `pool_children_ids`, and reward vaults which it leaves out
+    fn doctored_pool(tick_map_id: ObjectID, vaults: &[ObjectID]) -> MoveStruct {
+        let id = move_struct("pool", "ID", vec![("bytes", MoveValue::Address(tick_map_id.into()))]);
+        let uid = move_struct("pool", "UID", vec![("id", MoveValue::Struct(id))]);
+        let tick_map = move_struct("pool", "Table", vec![("id", MoveValue::Struct(uid))]);
+        let reward_infos = vaults
+            .iter()
+            .map(|vault| {
+                MoveValue::Struct(move_struct(
+                    "pool",
+                    "PoolRewardInfo",
+                    vec![("vault", MoveValue::Address((*vault).into()))],
+                ))
+            })
+            .collect();
+
+        move_struct(
+            "pool",
+            "Pool",
+            vec![
+                ("tick_map", MoveValue::Struct(tick_map)),
+                ("reward_infos", MoveValue::Vector(reward_infos)),
+            ],
+        )
+    }
+
+    #[tokio::test]
+    async fn test_pool_children_ids_with_ticks_without_reward_vaults() {
+        let (pool_id, tick_map_id) = (ObjectID::random(), ObjectID::random());
+        let words = vec![ObjectID::random(), ObjectID::random()];
+        let ticks = vec![ObjectID::random(), ObjectID::random(), ObjectID::random()];
+        let vaults = vec![ObjectID::random(), ObjectID::random()];
+        let simulator = MockSimulator::default()
+            .with_children(tick_map_id, words.clone())
+            .with_children(pool_id, ticks.clone());
+
+        let parsed_pool = doctored_pool(tick_map_id, &vaults);
+        let children_ids = pool_children_ids(pool_id, &parsed_pool, &simulator).await.unwrap();
+
+        let expected = [words, ticks].concat();
+        assert_eq!(
+            children_ids,
+            expected.iter().map(|id| id.to_string()).collect::<Vec<_>>()
+        );
+    }
 
     #[tokio::test]
     async fn test_swap_event_http() {
@@ -308,7 +345,7 @@ mod tests {
             extra: PoolExtra::None,
         };
 
-        let simulator: Arc<dyn Simulator> = Arc::new(DBSimulator::new_test(true).await);
+        let simulator: Arc<dyn Simulator> = Arc::new(new_test_db_simulator().await);
 
         let children_ids = turbos_pool_children_ids(&pool, simulator).await.unwrap();
         println!("{:?}", children_ids);
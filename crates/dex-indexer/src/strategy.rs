@@ -1,44 +1,112 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use burberry::{async_trait, ActionSubmitter, Strategy};
+use dashmap::DashMap;
 use eyre::Result;
-use sui_sdk::{types::event::EventID, SuiClient};
-use tokio::task::JoinSet;
-use tracing::{debug, error, info};
+use sui_sdk::{
+    rpc_types::{EventFilter, EventPage, SuiEvent},
+    types::event::EventID,
+    SuiClient,
+};
+use tokio::{sync::Mutex, task::JoinSet};
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    supported_protocols, token01_key,
-    types::{Event, NoAction, PoolCache, Protocol},
+    token01_key,
+    types::{Event, MalformedEvent, NoAction, Pool, PoolCache, Protocol},
     DB,
 };
 
+// a page of events, or the pool of an event, is queried again after a transient rpc error, the delay doubles after
+// each attempt
+const QUERY_RETRIES: usize = 4;
+const QUERY_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Where the pool created and swap events are read from, the event api of a `SuiClient` in production.
+#[async_trait]
+pub trait EventSource: Send + Sync {
+    async fn query_events(&self, filter: EventFilter, cursor: Option<EventID>) -> Result<EventPage>;
+    /// The id of the latest event that matches `filter`, `None` if there is none.
+    async fn latest_event(&self, filter: EventFilter) -> Result<Option<EventID>>;
+    async fn event_to_pool(&self, protocol: &Protocol, event: &SuiEvent) -> Result<Pool>;
+}
+
+#[async_trait]
+impl EventSource for SuiClient {
+    async fn query_events(&self, filter: EventFilter, cursor: Option<EventID>) -> Result<EventPage> {
+        Ok(self.event_api().query_events(filter, cursor, None, false).await?)
+    }
+
+    async fn latest_event(&self, filter: EventFilter) -> Result<Option<EventID>> {
+        let page = self.event_api().query_events(filter, None, Some(1), true).await?;
+        Ok(page.data.first().map(|event| event.id))
+    }
+
+    async fn event_to_pool(&self, protocol: &Protocol, event: &SuiEvent) -> Result<Pool> {
+        protocol.sui_event_to_pool(event, self).await
+    }
+}
+
 #[derive(Clone)]
 pub struct PoolCreatedStrategy {
     pool_cache: PoolCache,
+    // the protocols whose pools are synced
+    protocols: Vec<Protocol>,
 
     db: Arc<dyn DB>,
-    sui: SuiClient,
+    events: Arc<dyn EventSource>,
+    // when the cursor of each protocol last caught up with the chain
+    synced_at: Arc<DashMap<Protocol, Instant>>,
+    // held while the pools are synced, true once the indexer is shut down
+    stopped: Arc<Mutex<bool>>,
 }
 
 impl PoolCreatedStrategy {
-    pub fn new(db: Arc<dyn DB>, sui: SuiClient, pool_cache: PoolCache) -> Result<Self> {
-        Ok(Self { pool_cache, db, sui })
+    pub fn new(db: Arc<dyn DB>, sui: SuiClient, pool_cache: PoolCache, protocols: Vec<Protocol>) -> Result<Self> {
+        Ok(Self {
+            pool_cache,
+            protocols,
+            db,
+            events: Arc::new(sui),
+            synced_at: Arc::new(DashMap::new()),
+            stopped: Arc::new(Mutex::new(false)),
+        })
+    }
+
+    /// Shared with the strategy, so that it can be read after the strategy is moved into the engine.
+    pub fn synced_at(&self) -> Arc<DashMap<Protocol, Instant>> {
+        self.synced_at.clone()
+    }
+
+    /// Shared with the indexer, which sets it to stop syncing the pools once the sync in progress is flushed.
+    pub fn stopped(&self) -> Arc<Mutex<bool>> {
+        self.stopped.clone()
     }
 
     pub async fn backfill_pools(&self) -> Result<()> {
         let mut joinset = JoinSet::new();
         let cursors = self.db.get_processed_cursors()?;
-        for protocol in supported_protocols() {
-            let (sui, db) = (self.sui.clone(), self.db.clone());
+        for protocol in self.protocols.clone() {
+            let (events, db) = (self.events.clone(), self.db.clone());
             let pool_cache = self.pool_cache.clone();
             let cursor = cursors.get(&protocol).cloned().flatten();
 
-            joinset.spawn(async move { backfill_pools_for_protocol(sui, db, protocol, cursor, pool_cache).await });
+            joinset.spawn(async move {
+                let result = backfill_pools_for_protocol(events, db, protocol.clone(), cursor, pool_cache).await;
+                (protocol, result)
+            });
         }
 
         while let Some(res) = joinset.join_next().await {
-            if let Err(e) = res {
-                error!("backfill_pools error: {:?}", e);
+            match res {
+                Ok((protocol, Ok(()))) => {
+                    self.synced_at.insert(protocol, Instant::now());
+                }
+                Ok((protocol, Err(e))) => error!("backfill_pools error for {}: {:?}", protocol, e),
+                Err(e) => error!("backfill_pools error: {:?}", e),
             }
         }
 
@@ -58,14 +126,22 @@ impl Strategy<Event, NoAction> for PoolCreatedStrategy {
     }
 
     async fn process_event(&mut self, _event: Event, _: Arc<dyn ActionSubmitter<NoAction>>) {
+        let stopped = self.stopped.clone();
+        let stopped = stopped.lock().await;
+        if *stopped {
+            return;
+        }
+
         if let Err(error) = self.backfill_pools().await {
             error!("backfill_pools error: {:?}", error);
         }
     }
 }
 
+// The pools created since `cursor`, a page at a time. An event that isn't a valid pool is skipped, so that the
+// rest of its page is flushed and the cursor moves past it.
 async fn backfill_pools_for_protocol(
-    sui: SuiClient,
+    events: Arc<dyn EventSource>,
     db: Arc<dyn DB>,
     protocol: Protocol,
     cursor: Option<EventID>,
@@ -75,10 +151,7 @@ async fn backfill_pools_for_protocol(
     let mut cursor = cursor;
 
     debug!(%protocol, ?filter, ?cursor, "querying events");
-    let mut page = sui
-        .event_api()
-        .query_events(filter.clone(), cursor, None, false)
-        .await?;
+    let mut page = query_events_with_retry(events.as_ref(), &protocol, &filter, cursor).await?;
     debug!(%protocol, ?page, "events queried");
 
     let PoolCache {
@@ -90,7 +163,7 @@ async fn backfill_pools_for_protocol(
     while !page.data.is_empty() {
         let mut pools = vec![];
         for event in &page.data {
-            match protocol.sui_event_to_pool(event, &sui).await {
+            match event_to_pool_with_retry(events.as_ref(), &protocol, event).await {
                 Ok(pool) => {
                     // token_pools
                     for token in &pool.tokens {
@@ -107,9 +180,11 @@ async fn backfill_pools_for_protocol(
 
                     pools.push(pool)
                 }
-                Err(e) => {
-                    error!("invalid {:?}: {:?}", event, e);
+                Err(error) if error.downcast_ref::<MalformedEvent>().is_some() => {
+                    error!(%protocol, event_id = ?event.id, ?error, "invalid pool created event skipped");
                 }
+                // the cursor stays before the page, so that the pool is read again on the next backfill
+                Err(error) => return Err(error),
             }
         }
         debug!("{}: {} pools found at cursor {:?}", protocol, pools.len(), cursor);
@@ -120,11 +195,7 @@ async fn backfill_pools_for_protocol(
         };
         db.flush(&protocol, &pools, cursor)?;
 
-        // thread::sleep(Duration::from_secs(1));
-        page = sui
-            .event_api()
-            .query_events(filter.clone(), cursor, None, false)
-            .await?;
+        page = query_events_with_retry(events.as_ref(), &protocol, &filter, cursor).await?;
     }
 
     info!(
@@ -135,3 +206,211 @@ async fn backfill_pools_for_protocol(
 
     Ok(())
 }
+
+pub(crate) async fn query_events_with_retry(
+    events: &dyn EventSource,
+    protocol: &Protocol,
+    filter: &EventFilter,
+    cursor: Option<EventID>,
+) -> Result<EventPage> {
+    let mut delay = QUERY_RETRY_DELAY;
+    for _ in 0..QUERY_RETRIES {
+        match events.query_events(filter.clone(), cursor).await {
+            Ok(page) => return Ok(page),
+            Err(error) => {
+                warn!(%protocol, ?cursor, ?error, ?delay, "query_events failed, retrying");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+    }
+
+    events.query_events(filter.clone(), cursor).await
+}
+
+// only the rpc errors are retried, a `MalformedEvent` fails the same way every time
+async fn event_to_pool_with_retry(events: &dyn EventSource, protocol: &Protocol, event: &SuiEvent) -> Result<Pool> {
+    let mut delay = QUERY_RETRY_DELAY;
+    for _ in 0..QUERY_RETRIES {
+        match events.event_to_pool(protocol, event).await {
+            Err(error) if error.downcast_ref::<MalformedEvent>().is_none() => {
+                warn!(%protocol, event_id = ?event.id, ?error, ?delay, "event_to_pool failed, retrying");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+
+    events.event_to_pool(protocol, event).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use sui_sdk::{rpc_types::Page, types::base_types::ObjectID};
+
+    use super::*;
+    use crate::{
+        file_db::FileDB,
+        types::{PoolExtra, Token, Token01Pools, TokenPools},
+    };
+
+    const PAGE_SIZE: usize = 2;
+
+    // the pool created events of a protocol, `fail_next` queries fail before the next one succeeds, and the pools
+    // of the events in `fail_pool` are read after as many failures
+    struct MockEventSource {
+        events: Vec<SuiEvent>,
+        fail_next: AtomicUsize,
+        queries: AtomicUsize,
+        fail_pool: Vec<(u64, AtomicUsize)>,
+        pool_reads: AtomicUsize,
+    }
+
+    impl MockEventSource {
+        // `count` events, the ones in `poisoned` can't be parsed into a pool
+        fn new(count: u64, poisoned: &[u64], fail_next: usize) -> Self {
+            let events = (0..count)
+                .map(|seq| {
+                    let mut event = SuiEvent::random_for_testing();
+                    event.id.event_seq = seq;
+                    event.parsed_json = serde_json::json!({ "poisoned": poisoned.contains(&seq) });
+                    event
+                })
+                .collect();
+            Self {
+                events,
+                fail_next: AtomicUsize::new(fail_next),
+                queries: AtomicUsize::new(0),
+                fail_pool: vec![],
+                pool_reads: AtomicUsize::new(0),
+            }
+        }
+
+        fn with_failing_pool(mut self, seq: u64, failures: usize) -> Self {
+            self.fail_pool.push((seq, AtomicUsize::new(failures)));
+            self
+        }
+    }
+
+    #[async_trait]
+    impl EventSource for MockEventSource {
+        async fn query_events(&self, _filter: EventFilter, cursor: Option<EventID>) -> Result<EventPage> {
+            self.queries.fetch_add(1, Ordering::Relaxed);
+            let fail = self
+                .fail_next
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                .is_ok();
+            eyre::ensure!(!fail, "transient rpc error");
+
+            let start = match cursor {
+                Some(cursor) => self.events.iter().position(|e| e.id == cursor).unwrap() + 1,
+                None => 0,
+            };
+            let end = (start + PAGE_SIZE).min(self.events.len());
+            let data = self.events[start..end].to_vec();
+            Ok(Page {
+                next_cursor: data.last().map(|e| e.id),
+                has_next_page: end < self.events.len(),
+                data,
+            })
+        }
+
+        async fn latest_event(&self, _filter: EventFilter) -> Result<Option<EventID>> {
+            Ok(self.events.last().map(|e| e.id))
+        }
+
+        async fn event_to_pool(&self, protocol: &Protocol, event: &SuiEvent) -> Result<Pool> {
+            self.pool_reads.fetch_add(1, Ordering::Relaxed);
+            if event.parsed_json["poisoned"].as_bool().unwrap() {
+                return Err(eyre::eyre!("missing pool_id").wrap_err(MalformedEvent));
+            }
+            let fail = self
+                .fail_pool
+                .iter()
+                .filter(|(seq, _)| *seq == event.id.event_seq)
+                .any(|(_, failures)| {
+                    failures
+                        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                        .is_ok()
+                });
+            eyre::ensure!(!fail, "transient rpc error");
+
+            Ok(Pool {
+                protocol: protocol.clone(),
+                pool: ObjectID::random(),
+                tokens: vec![Token::new("0x2::sui::SUI", 9), Token::new("0x1::ocean::OCEAN", 6)],
+                extra: PoolExtra::None,
+            })
+        }
+    }
+
+    async fn backfill(events: Arc<MockEventSource>) -> (Result<()>, FileDB) {
+        let dir = std::env::temp_dir().join(format!("pool_created-{}", ObjectID::random()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = FileDB::new(&dir, &[Protocol::Cetus]).unwrap();
+        let pool_cache = PoolCache::new(TokenPools::new(), Token01Pools::new(), DashMap::new());
+
+        let result = backfill_pools_for_protocol(events, Arc::new(db.clone()), Protocol::Cetus, None, pool_cache).await;
+        (result, db)
+    }
+
+    #[tokio::test]
+    async fn test_backfill_retries_transient_query_errors() {
+        let events = Arc::new(MockEventSource::new(5, &[], 2));
+        let (result, db) = backfill(events.clone()).await;
+        result.unwrap();
+
+        assert_eq!(db.pool_count(&Protocol::Cetus).unwrap(), 5);
+        let cursors = db.get_processed_cursors().unwrap();
+        assert_eq!(cursors[&Protocol::Cetus], Some(events.events[4].id));
+        // 3 pages and the empty one, after 2 failures
+        assert_eq!(events.queries.load(Ordering::Relaxed), 2 + 3 + 1);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_gives_up_after_retries() {
+        let events = Arc::new(MockEventSource::new(5, &[], QUERY_RETRIES + 1));
+        let (result, db) = backfill(events.clone()).await;
+
+        assert!(result.is_err());
+        assert_eq!(events.queries.load(Ordering::Relaxed), QUERY_RETRIES + 1);
+        assert!(db.get_processed_cursors().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_backfill_skips_poisoned_event() {
+        let events = Arc::new(MockEventSource::new(5, &[2], 0));
+        let (result, db) = backfill(events.clone()).await;
+        result.unwrap();
+
+        // the rest of the page of the poisoned event is flushed, and the cursor moves past it
+        assert_eq!(db.pool_count(&Protocol::Cetus).unwrap(), 4);
+        let cursors = db.get_processed_cursors().unwrap();
+        assert_eq!(cursors[&Protocol::Cetus], Some(events.events[4].id));
+    }
+
+    #[tokio::test]
+    async fn test_backfill_retries_transient_pool_errors() {
+        let events = Arc::new(MockEventSource::new(5, &[], 0).with_failing_pool(3, 2));
+        let (result, db) = backfill(events.clone()).await;
+        result.unwrap();
+
+        assert_eq!(db.pool_count(&Protocol::Cetus).unwrap(), 5);
+        assert_eq!(events.pool_reads.load(Ordering::Relaxed), 5 + 2);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_keeps_cursor_before_unreadable_pool() {
+        let events = Arc::new(MockEventSource::new(5, &[], 0).with_failing_pool(3, QUERY_RETRIES + 1));
+        let (result, db) = backfill(events.clone()).await;
+
+        // the page of the pool is not flushed, the cursor stays at the end of the previous one
+        assert!(result.is_err());
+        assert_eq!(db.pool_count(&Protocol::Cetus).unwrap(), 2);
+        let cursors = db.get_processed_cursors().unwrap();
+        assert_eq!(cursors[&Protocol::Cetus], Some(events.events[1].id));
+    }
+}
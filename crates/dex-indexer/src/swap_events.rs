@@ -0,0 +1,330 @@
+use std::{sync::Arc, time::Duration};
+
+use eyre::Result;
+use simulator::Simulator;
+use sui_sdk::{
+    rpc_types::{EventFilter, SuiEvent},
+    types::event::EventID,
+    SuiClient,
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    strategy::{query_events_with_retry, EventSource},
+    swap_protocols,
+    types::{Protocol, SwapEvent},
+    DB,
+};
+
+// a protocol that caught up with the chain is polled again after this long
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Streams the swap events of the protocols as normalized `SwapEvent`s, e.g. for analytics or a price cache.
+/// The cursor of each protocol is persisted as a swap cursor of the db, a protocol without one starts from
+/// its latest event.
+pub struct SwapEventCollector {
+    events: Arc<dyn EventSource>,
+    db: Arc<dyn DB>,
+    simulator: Arc<dyn Simulator>,
+    protocols: Vec<Protocol>,
+    poll_interval: Duration,
+}
+
+impl SwapEventCollector {
+    pub fn new(sui: SuiClient, db: Arc<dyn DB>, simulator: Arc<dyn Simulator>) -> Self {
+        Self::new_with_source(Arc::new(sui), db, simulator)
+    }
+
+    pub(crate) fn new_with_source(
+        events: Arc<dyn EventSource>,
+        db: Arc<dyn DB>,
+        simulator: Arc<dyn Simulator>,
+    ) -> Self {
+        Self {
+            events,
+            db,
+            simulator,
+            protocols: swap_protocols(),
+            poll_interval: POLL_INTERVAL,
+        }
+    }
+
+    /// Only stream the swap events of `protocols`.
+    pub fn with_protocols(mut self, protocols: &[Protocol]) -> Self {
+        self.protocols = protocols.to_vec();
+        self
+    }
+
+    /// Polls the swap events of each protocol in the background, until the receiver is dropped.
+    pub fn spawn(self) -> Result<async_channel::Receiver<SwapEvent>> {
+        let cursors = self.db.get_swap_cursors()?;
+        let (sender, receiver) = async_channel::bounded(CHANNEL_CAPACITY);
+
+        for protocol in self.protocols {
+            let stream = ProtocolSwapEvents {
+                filter: protocol.swap_event_filter()?,
+                cursor: cursors.get(&protocol).cloned().flatten(),
+                protocol,
+                events: self.events.clone(),
+                db: self.db.clone(),
+                simulator: self.simulator.clone(),
+                sender: sender.clone(),
+                poll_interval: self.poll_interval,
+            };
+            tokio::spawn(stream.run());
+        }
+
+        Ok(receiver)
+    }
+}
+
+struct ProtocolSwapEvents {
+    protocol: Protocol,
+    filter: EventFilter,
+    cursor: Option<EventID>,
+
+    events: Arc<dyn EventSource>,
+    db: Arc<dyn DB>,
+    simulator: Arc<dyn Simulator>,
+    sender: async_channel::Sender<SwapEvent>,
+    poll_interval: Duration,
+}
+
+impl ProtocolSwapEvents {
+    async fn run(mut self) {
+        if self.cursor.is_none() {
+            match self.events.latest_event(self.filter.clone()).await {
+                Ok(latest) => self.cursor = latest,
+                Err(error) => warn!(protocol = %self.protocol, ?error, "failed to get the latest swap event"),
+            }
+        }
+        info!(protocol = %self.protocol, cursor = ?self.cursor, "streaming swap events");
+
+        while !self.sender.is_closed() {
+            match self.poll().await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(error) => error!(protocol = %self.protocol, ?error, "failed to poll swap events"),
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    // sends the swap events of the next page, true if there are more pages
+    async fn poll(&mut self) -> Result<bool> {
+        let page = query_events_with_retry(self.events.as_ref(), &self.protocol, &self.filter, self.cursor).await?;
+        let Some(last) = page.data.last() else {
+            return Ok(false);
+        };
+
+        for event in &page.data {
+            let Some(swap_event) = self.to_swap_event(event).await else {
+                continue;
+            };
+            if self.sender.send(swap_event).await.is_err() {
+                // the receiver is dropped
+                return Ok(false);
+            }
+        }
+
+        self.cursor = if page.has_next_page {
+            page.next_cursor
+        } else {
+            Some(last.id)
+        };
+        self.db.flush_swap_cursor(&self.protocol, self.cursor)?;
+
+        Ok(page.has_next_page)
+    }
+
+    // `None` for the other events of the module, or a swap event that can't be parsed
+    async fn to_swap_event(&self, event: &SuiEvent) -> Option<SwapEvent> {
+        if !Protocol::try_from(event).is_ok_and(|protocol| protocol == self.protocol) {
+            debug!(protocol = %self.protocol, event_type = %event.type_, "not a swap event");
+            return None;
+        }
+
+        match self
+            .protocol
+            .sui_event_to_swap_event(event, self.simulator.clone())
+            .await
+        {
+            Ok(swap_event) => Some(swap_event),
+            Err(error) => {
+                error!(protocol = %self.protocol, event_id = ?event.id, ?error, "invalid swap event skipped");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use burberry::async_trait;
+    use move_core_types::language_storage::StructTag;
+    use serde_json::json;
+    use sui_sdk::{
+        rpc_types::{EventPage, Page},
+        types::base_types::ObjectID,
+    };
+
+    use super::*;
+    use crate::{file_db::FileDB, protocols::aftermath::AFTERMATH_SWAP_EVENT, tests::MockSimulator, types::Pool};
+
+    // the SUI/BUCK pool of data/aftermath_pools.txt
+    const POOL: &str = "0xdeacf7ab460385d4bcb567f183f916367f7d43666a2c72323013822eb3c57026";
+    const SUI: &str = "0000000000000000000000000000000000000000000000000000000000000002::sui::SUI";
+    const BUCK: &str = "ce7ff77a83ea0cb6fd39bd8748e2ec89a3f41e8efdc3f4eb123e0ca37b184db2::buck::BUCK";
+
+    // the events of a module, the ones matching the filter are returned in one page, and the cursors
+    // queried with
+    struct MockEventSource {
+        events: Vec<SuiEvent>,
+        cursors: Mutex<Vec<Option<EventID>>>,
+    }
+
+    #[async_trait]
+    impl EventSource for MockEventSource {
+        async fn query_events(&self, filter: EventFilter, cursor: Option<EventID>) -> Result<EventPage> {
+            self.cursors.lock().unwrap().push(cursor);
+            let start = match cursor {
+                Some(cursor) => self.events.iter().position(|e| e.id == cursor).unwrap() + 1,
+                None => 0,
+            };
+            let EventFilter::MoveEventType(event_type) = filter else {
+                eyre::bail!("unexpected filter: {filter:?}");
+            };
+            Ok(Page {
+                data: self.events[start..]
+                    .iter()
+                    .filter(|e| e.type_ == event_type)
+                    .cloned()
+                    .collect(),
+                next_cursor: self.events.last().map(|e| e.id),
+                has_next_page: false,
+            })
+        }
+
+        async fn latest_event(&self, _filter: EventFilter) -> Result<Option<EventID>> {
+            Ok(None)
+        }
+
+        async fn event_to_pool(&self, protocol: &Protocol, _event: &SuiEvent) -> Result<Pool> {
+            eyre::bail!("the swap events of {protocol} don't create pools")
+        }
+    }
+
+    fn captured_event(seq: u64, event_type: &str, parsed_json: serde_json::Value) -> SuiEvent {
+        let mut event = SuiEvent::random_for_testing();
+        event.id.event_seq = seq;
+        event.type_ = event_type.parse::<StructTag>().unwrap();
+        event.parsed_json = parsed_json;
+        event
+    }
+
+    // the parsed json of a `SwapEventV2`, which doesn't prefix the coin types with `0x`
+    fn aftermath_swap(seq: u64, amount_in: u64, amount_out: u64) -> SuiEvent {
+        let parsed_json = json!({
+            "pool_id": POOL,
+            "issuer": "0xb25c34dbd1b5f5e7a3d8fb2a5d1c93c5bd70b1fc8f3c6fce4e3e6cc3ba3f8a39",
+            "referrer": null,
+            "types_in": [SUI],
+            "amounts_in": [amount_in.to_string()],
+            "types_out": [BUCK],
+            "amounts_out": [amount_out.to_string()],
+        });
+        captured_event(seq, AFTERMATH_SWAP_EVENT, parsed_json)
+    }
+
+    async fn wait_for(description: &str, condition: impl Fn() -> bool) {
+        let waiting = async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), waiting)
+            .await
+            .unwrap_or_else(|_| panic!("timed out waiting for {description}"));
+    }
+
+    #[test]
+    fn test_swap_event_filters() {
+        let filter = Protocol::Aftermath.swap_event_filter().unwrap();
+        let EventFilter::MoveEventType(event_type) = filter else {
+            panic!("expected an event type filter: {filter:?}");
+        };
+        assert_eq!(event_type.to_string(), AFTERMATH_SWAP_EVENT);
+
+        // the kriya amm swap events are generic over the coins of the pool
+        let filter = Protocol::KriyaAmm.swap_event_filter().unwrap();
+        assert!(matches!(filter, EventFilter::MoveEventModule { module, .. } if module.as_str() == "spot_dex"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_swap_events() {
+        let pool = ObjectID::from_hex_literal(POOL).unwrap();
+        let deposit_event = AFTERMATH_SWAP_EVENT.replace("SwapEventV2", "DepositEventV2");
+        let events = Arc::new(MockEventSource {
+            events: vec![
+                aftermath_swap(0, 1_000_000_000, 3_500_000_000),
+                // another event of the module, not matched by the filter
+                captured_event(1, &deposit_event, json!({ "pool_id": POOL })),
+                // a swap event without its amounts
+                captured_event(2, AFTERMATH_SWAP_EVENT, json!({ "pool_id": POOL })),
+                aftermath_swap(3, 2_000_000_000, 7_000_000_000),
+            ],
+            cursors: Mutex::new(vec![]),
+        });
+
+        let dir = std::env::temp_dir().join(format!("swap_events-{}", ObjectID::random()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Arc::new(FileDB::new(&dir, &[Protocol::Aftermath]).unwrap());
+        // the aftermath swaps don't read any object
+        let simulator = Arc::new(MockSimulator::default());
+        let mut collector = SwapEventCollector::new_with_source(events.clone(), db.clone(), simulator.clone())
+            .with_protocols(&[Protocol::Aftermath]);
+        collector.poll_interval = Duration::from_millis(10);
+        let receiver = collector.spawn().unwrap();
+
+        for (amount_in, amount_out) in [(1_000_000_000, 3_500_000_000), (2_000_000_000, 7_000_000_000)] {
+            let swap_event = tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await;
+            let swap_event = swap_event.expect("no swap event").unwrap();
+            assert_eq!(swap_event.protocol, Protocol::Aftermath);
+            assert_eq!(swap_event.pool, Some(pool));
+            assert_eq!(swap_event.coins_in, vec!["0x2::sui::SUI".to_string()]);
+            assert_eq!(swap_event.coins_out, vec![format!("0x{BUCK}")]);
+            assert_eq!(
+                (swap_event.amounts_in[0], swap_event.amounts_out[0]),
+                (amount_in, amount_out)
+            );
+        }
+
+        // the cursor moves past the page, and is persisted apart from the processed cursors
+        let last = Some(events.events[3].id);
+        wait_for("the persisted cursor", || {
+            db.get_swap_cursors().unwrap().get(&Protocol::Aftermath) == Some(&last)
+        })
+        .await;
+        assert!(db.get_processed_cursors().unwrap().is_empty());
+        assert_eq!(events.cursors.lock().unwrap()[0], None);
+
+        // a new collector resumes from the persisted cursor
+        drop(receiver);
+        let queried = events.cursors.lock().unwrap().len();
+        let receiver = SwapEventCollector::new_with_source(events.clone(), db.clone(), simulator)
+            .with_protocols(&[Protocol::Aftermath])
+            .spawn()
+            .unwrap();
+        wait_for("a query of the new collector", || {
+            events.cursors.lock().unwrap().len() > queried
+        })
+        .await;
+        assert_eq!(events.cursors.lock().unwrap()[queried], last);
+        assert!(receiver.is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
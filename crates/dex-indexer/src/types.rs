@@ -2,6 +2,7 @@ use std::{
     collections::HashSet,
     fmt,
     hash::{Hash, Hasher},
+    str::FromStr,
     sync::Arc,
 };
 
@@ -13,7 +14,7 @@ use shio::ShioEvent;
 use simulator::Simulator;
 use sui_sdk::{
     rpc_types::{EventFilter, SuiEvent},
-    types::base_types::ObjectID,
+    types::{base_types::ObjectID, Identifier},
     SuiClient, SUI_COIN_TYPE,
 };
 use tracing::error;
@@ -26,6 +27,23 @@ use crate::{
     },
 };
 
+/// Swap event types of the supported protocols. Some of them are generic, so an event type
+/// should be matched by prefix.
+pub const SWAP_EVENT_TYPE_PREFIXES: &[&str] = &[
+    CETUS_SWAP_EVENT,
+    TURBOS_SWAP_EVENT,
+    AFTERMATH_SWAP_EVENT,
+    KRIYA_AMM_SWAP_EVENT,
+    KRIYA_CLMM_SWAP_EVENT,
+    FLOWX_AMM_SWAP_EVENT,
+    FLOWX_CLMM_SWAP_EVENT,
+    BLUE_MOVE_SWAP_EVENT,
+    SUISWAP_SWAP_EVENT,
+    INTEREST_SWAP_EVENT,
+    ABEX_SWAP_EVENT,
+    BABY_SWAP_EVENT,
+];
+
 // token_type -> pools
 pub type TokenPools = DashMap<String, HashSet<Pool>>;
 // (token0_type, token1_type) -> pools
@@ -136,8 +154,14 @@ impl TryFrom<&str> for Pool {
 
         let protocol = Protocol::try_from(parts[0])?;
         let pool = parts[1].parse()?;
-        let tokens: Vec<Token> = serde_json::from_str(parts[2])?;
-        let extra: PoolExtra = serde_json::from_str(parts[3])?;
+        let tokens = match parse_tokens(parts[2]) {
+            Some(tokens) => tokens,
+            None => serde_json::from_str(parts[2])?,
+        };
+        let extra: PoolExtra = match parts[3] {
+            r#""None""# => PoolExtra::None,
+            extra => serde_json::from_str(extra)?,
+        };
 
         Ok(Pool {
             protocol,
@@ -148,6 +172,35 @@ impl TryFrom<&str> for Pool {
     }
 }
 
+// The tokens as written by `Display`, e.g. `[{"token_type":"0x2::sui::SUI","decimals":9}]`, split by hand as
+// serde_json is the bottleneck of loading the pools. `None` if not in that exact shape, to be parsed by serde_json.
+fn parse_tokens(s: &str) -> Option<Vec<Token>> {
+    let mut rest = s.strip_prefix('[')?.strip_suffix(']')?;
+    let mut tokens = vec![];
+    while !rest.is_empty() {
+        let (token_type, r) = rest
+            .strip_prefix(r#"{"token_type":""#)?
+            .split_once(r#"","decimals":"#)?;
+        let (decimals, r) = r.split_once('}')?;
+        // escaped strings are left to serde_json
+        if token_type.contains(['"', '\\']) {
+            return None;
+        }
+        tokens.push(Token {
+            token_type: token_type.to_string(),
+            decimals: decimals.parse().ok()?,
+        });
+
+        rest = match r.strip_prefix(',') {
+            Some(r) if !r.is_empty() => r,
+            None if r.is_empty() => r,
+            _ => return None,
+        };
+    }
+
+    Some(tokens)
+}
+
 impl Pool {
     pub fn token0_type(&self) -> String {
         self.tokens[0].token_type.clone()
@@ -182,6 +235,17 @@ impl Pool {
     }
 
     pub async fn related_object_ids(&self, simulator: Arc<dyn Simulator>) -> HashSet<String> {
+        let mut res = self.own_object_ids();
+        match self.children_ids(simulator).await {
+            Ok(children_ids) => res.extend(children_ids),
+            Err(e) => error!("Failed to get pool children ids: {}, pool: {}", e, self.pool),
+        }
+
+        res
+    }
+
+    /// The pool and the packages of its tokens.
+    pub fn own_object_ids(&self) -> HashSet<String> {
         let mut res = HashSet::new();
 
         // Pool
@@ -195,8 +259,12 @@ impl Pool {
             .collect::<Vec<_>>();
         res.extend(token_object_ids);
 
-        // Children
-        let children_ids = match self.protocol {
+        res
+    }
+
+    /// The dynamic children of the pool a swap may read, e.g. its ticks.
+    pub async fn children_ids(&self, simulator: Arc<dyn Simulator>) -> Result<Vec<String>> {
+        match self.protocol {
             Protocol::Cetus => cetus_pool_children_ids(self, simulator).await,
             Protocol::BlueMove => blue_move_pool_children_ids(self, simulator).await,
             Protocol::Turbos => turbos_pool_children_ids(self, simulator).await,
@@ -204,13 +272,7 @@ impl Pool {
             Protocol::FlowxClmm => flowx_clmm_pool_children_ids(self, simulator).await,
             Protocol::Aftermath => aftermath_pool_children_ids(self, simulator).await,
             _ => Ok(vec![]),
-        };
-        match children_ids {
-            Ok(children_ids) => res.extend(children_ids),
-            Err(e) => error!("Failed to get pool children ids: {}, pool: {}", e, self.pool),
         }
-
-        res
     }
 }
 
@@ -223,6 +285,17 @@ impl Token {
     }
 }
 
+/// The error of an event that can't be parsed, which no retry will fix, as opposed to the rpc errors of
+/// reading what it refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MalformedEvent;
+
+impl fmt::Display for MalformedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed event")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SwapEvent {
     pub protocol: Protocol,
@@ -316,6 +389,14 @@ impl TryFrom<&str> for Protocol {
     }
 }
 
+impl FromStr for Protocol {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::try_from(s)
+    }
+}
+
 impl TryFrom<&SuiEvent> for Protocol {
     type Error = eyre::Error;
 
@@ -366,17 +447,60 @@ impl Protocol {
         }
     }
 
+    /// The filter of the swap events of the protocol. The generic ones are only known up to their type
+    /// arguments, which vary by pool, so they are filtered by the module that emits them instead.
+    pub fn swap_event_filter(&self) -> Result<EventFilter> {
+        let (event_type, generic) = match self {
+            Protocol::Cetus => (CETUS_SWAP_EVENT, false),
+            Protocol::Turbos => (TURBOS_SWAP_EVENT, false),
+            Protocol::Aftermath => (AFTERMATH_SWAP_EVENT, false),
+            Protocol::KriyaAmm => (KRIYA_AMM_SWAP_EVENT, true),
+            Protocol::KriyaClmm => (KRIYA_CLMM_SWAP_EVENT, false),
+            Protocol::FlowxAmm => (FLOWX_AMM_SWAP_EVENT, false),
+            Protocol::FlowxClmm => (FLOWX_CLMM_SWAP_EVENT, false),
+            Protocol::BlueMove => (BLUE_MOVE_SWAP_EVENT, true),
+            Protocol::SuiSwap => (SUISWAP_SWAP_EVENT, true),
+            Protocol::Interest => (INTEREST_SWAP_EVENT, true),
+            Protocol::Abex => (ABEX_SWAP_EVENT, true),
+            Protocol::BabySwap => (BABY_SWAP_EVENT, true),
+            _ => bail!("No swap event for {}", self),
+        };
+        if !generic {
+            return Ok(EventFilter::MoveEventType(event_type.parse()?));
+        }
+
+        let mut parts = event_type.split("::");
+        let (Some(package), Some(module)) = (parts.next(), parts.next()) else {
+            bail!("Invalid event type: {}", event_type);
+        };
+        Ok(EventFilter::MoveEventModule {
+            package: package.parse()?,
+            module: Identifier::new(module)?,
+        })
+    }
+
+    /// The pool created by `event`. The errors of parsing the event are wrapped in `MalformedEvent`, the other
+    /// ones are the rpc errors of reading the pool.
     pub async fn sui_event_to_pool(&self, event: &SuiEvent, sui: &SuiClient) -> Result<Pool> {
+        macro_rules! to_pool {
+            ($pool_created:ty) => {
+                <$pool_created>::try_from(event)
+                    .map_err(|error| error.wrap_err(MalformedEvent))?
+                    .to_pool(sui)
+                    .await
+            };
+        }
+
         match self {
-            Protocol::Cetus => CetusPoolCreated::try_from(event)?.to_pool(sui).await,
-            Protocol::Turbos => TurbosPoolCreated::try_from(event)?.to_pool(sui).await,
-            Protocol::Aftermath => AftermathPoolCreated::try_from(event)?.to_pool(sui).await,
-            Protocol::KriyaAmm => KriyaAmmPoolCreated::try_from(event)?.to_pool(sui).await,
-            Protocol::KriyaClmm => KriyaClmmPoolCreated::try_from(event)?.to_pool(sui).await,
-            Protocol::FlowxAmm => FlowxAmmPoolCreated::try_from(event)?.to_pool(sui).await,
-            Protocol::FlowxClmm => FlowxClmmPoolCreated::try_from(event)?.to_pool(sui).await,
-            Protocol::DeepbookV2 => DeepbookV2PoolCreated::try_from(event)?.to_pool(sui).await,
-            Protocol::BlueMove => BlueMovePoolCreated::try_from(event)?.to_pool(sui).await,
+            Protocol::Cetus => to_pool!(CetusPoolCreated),
+            Protocol::Turbos => to_pool!(TurbosPoolCreated),
+            Protocol::Aftermath => to_pool!(AftermathPoolCreated),
+            Protocol::KriyaAmm => to_pool!(KriyaAmmPoolCreated),
+            Protocol::KriyaClmm => to_pool!(KriyaClmmPoolCreated),
+            Protocol::FlowxAmm => to_pool!(FlowxAmmPoolCreated),
+            Protocol::FlowxClmm => to_pool!(FlowxClmmPoolCreated),
+            Protocol::DeepbookV2 => to_pool!(DeepbookV2PoolCreated),
+            Protocol::BlueMove => to_pool!(BlueMovePoolCreated),
             _ => todo!(),
         }
     }
@@ -417,7 +541,9 @@ impl Protocol {
         }
     }
 
-    pub async fn related_object_ids(&self) -> Result<HashSet<String>> {
+    /// The objects of the protocol that its swaps may read, the children of its shared objects are enumerated
+    /// through `simulator`.
+    pub async fn related_object_ids(&self, simulator: Arc<dyn Simulator>) -> Result<HashSet<String>> {
         let res = match self {
             Protocol::Cetus => cetus_related_object_ids(),
             Protocol::BlueMove => blue_move_related_object_ids(),
@@ -426,7 +552,7 @@ impl Protocol {
             Protocol::KriyaClmm => kriya_clmm_related_object_ids(),
             Protocol::FlowxClmm => flowx_clmm_related_object_ids(),
             Protocol::Navi => navi_related_object_ids(),
-            Protocol::Aftermath => aftermath_related_object_ids().await,
+            Protocol::Aftermath => aftermath_related_object_ids(simulator).await,
             _ => bail!("Not interesting"),
         }
         .into_iter()
@@ -1,22 +1,221 @@
-use std::fmt::Display;
+mod reload;
+mod retention;
+mod size_rolling;
 
+use std::{fmt::Display, path::PathBuf, sync::Mutex};
+
+use reload::{add_directives, NewFilter, ReloadableFilter};
+pub use reload::{set_directives, SetDirectivesError};
+pub use retention::{Retention, DEFAULT_MAX_LOG_FILES};
+pub use size_rolling::SizeRollingFile;
 pub use tracing::level_filters::LevelFilter;
-use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+use tracing_subscriber::{
+    fmt::{self, writer::BoxMakeWriter, MakeWriter},
+    layer::{Filter, SubscriberExt},
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
 
-pub fn init<T: Into<String>>(name: T) {
-    let console_layer = fmt::layer().with_target(false).with_filter(EnvFilter::new("info"));
+// always logged by the whitelisted loggers
+const DEFAULT_WHITELISTED_MODULES: [&str; 4] = ["burberry", "reconstruct", "mev_core::flashloan", "panic_hook"];
 
-    let file_appender = tracing_appender::rolling::hourly("./logs/", format!("{}.log", name.into()));
-    let file_layer = fmt::layer()
-        .with_ansi(false)
-        .with_writer(file_appender)
-        .with_target(true)
-        .with_filter(EnvFilter::new("info"));
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
 
-    tracing_subscriber::registry()
-        .with(console_layer)
-        .with(file_layer)
-        .init();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// a json object per line, with the target, the thread name and the fields of the spans
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    #[default]
+    Hourly,
+    Daily,
+    /// rolled over once the file reaches this many bytes, see `SizeRollingFile`
+    Size(u64),
+}
+
+/// Where and how the logs are written, to the console and to `{dir}/{file_prefix}-{instance_id}.log`.
+pub struct LoggerConfig {
+    pub format: LogFormat,
+    pub dir: PathBuf,
+    pub file_prefix: String,
+    /// tells apart the files of the instances running on the same host [default: the pid]
+    pub instance_id: Option<String>,
+    pub rotation: Rotation,
+    /// off by default, applies to the file of this instance and its rotations, see `Retention::prune`. Set a
+    /// stable `instance_id` for it to apply to the files of the previous runs too
+    pub retention: Retention,
+    /// only these modules are logged, on top of the default ones, at info to the console and at trace to
+    /// the file [default: every module at info]
+    pub whitelisted_modules: Option<Vec<String>>,
+    /// gets the events on top of the console and the file, with its own filter, e.g. to send the errors to
+    /// telegram, see `utils::telegram_layer::TelegramLayer`
+    pub alert_layer: Option<BoxedLayer>,
+}
+
+impl LoggerConfig {
+    pub fn new<T: Into<String>>(file_prefix: T) -> Self {
+        Self {
+            format: LogFormat::default(),
+            dir: PathBuf::from("./logs/"),
+            file_prefix: file_prefix.into(),
+            instance_id: None,
+            rotation: Rotation::default(),
+            retention: Retention::default(),
+            whitelisted_modules: None,
+            alert_layer: None,
+        }
+    }
+
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    pub fn with_instance_id<T: Into<String>>(mut self, instance_id: T) -> Self {
+        self.instance_id = Some(instance_id.into());
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn with_alert_layer<L: Layer<Registry> + Send + Sync + 'static>(mut self, layer: L) -> Self {
+        self.alert_layer = Some(layer.boxed());
+        self
+    }
+
+    pub fn with_whitelisted_modules(mut self, modules: &[&str]) -> Self {
+        self.whitelisted_modules = Some(modules.iter().map(|module| module.to_string()).collect());
+        self
+    }
+
+    // e.g. `sui-arb-mainnet-4242.log`, the hourly and daily rotations suffix it with the date
+    pub fn file_name(&self) -> String {
+        let instance_id = match &self.instance_id {
+            Some(instance_id) => instance_id.clone(),
+            None => std::process::id().to_string(),
+        };
+        format!("{}-{}.log", self.file_prefix, instance_id)
+    }
+
+    fn file_writer(&self) -> BoxMakeWriter {
+        let file_name = self.file_name();
+        match self.rotation {
+            Rotation::Hourly => BoxMakeWriter::new(tracing_appender::rolling::hourly(&self.dir, file_name)),
+            Rotation::Daily => BoxMakeWriter::new(tracing_appender::rolling::daily(&self.dir, file_name)),
+            Rotation::Size(max_bytes) => {
+                let file = SizeRollingFile::new(&self.dir, &file_name, max_bytes)
+                    .unwrap_or_else(|e| panic!("fail to open log file {file_name} in {:?}: {e}", self.dir));
+                BoxMakeWriter::new(Mutex::new(file))
+            }
+        }
+    }
+
+    // the filters can be changed at runtime with `set_directives`
+    fn layers(&self) -> (Vec<BoxedLayer>, Vec<ReloadableFilter>) {
+        let modules = self.whitelisted_modules.as_ref().map(|modules| {
+            DEFAULT_WHITELISTED_MODULES
+                .iter()
+                .map(|module| module.to_string())
+                .chain(modules.iter().cloned())
+                .collect::<Vec<_>>()
+        });
+        let new_filter = |level: LevelFilter| -> NewFilter {
+            let modules = modules.clone();
+            Box::new(move |directives| {
+                let filter = match &modules {
+                    Some(modules) => {
+                        let modules = modules.iter().map(String::as_str).collect::<Vec<_>>();
+                        new_whitelist_mode_env_filter(&modules, level)
+                    }
+                    None => EnvFilter::new("info"),
+                };
+                add_directives(filter, directives)
+            })
+        };
+
+        // without directives on top, building the filters can't fail
+        let (console_filter, console_reloadable) = ReloadableFilter::new(new_filter(LevelFilter::INFO)).unwrap();
+        let (file_filter, file_reloadable) = ReloadableFilter::new(new_filter(LevelFilter::TRACE)).unwrap();
+        let console_target = self.whitelisted_modules.is_some();
+
+        (
+            vec![
+                new_layer(self.format, std::io::stdout, true, console_target, console_filter),
+                new_layer(self.format, self.file_writer(), false, true, file_filter),
+            ],
+            vec![console_reloadable, file_reloadable],
+        )
+    }
+}
+
+impl std::fmt::Debug for LoggerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoggerConfig")
+            .field("format", &self.format)
+            .field("dir", &self.dir)
+            .field("file_prefix", &self.file_prefix)
+            .field("instance_id", &self.instance_id)
+            .field("rotation", &self.rotation)
+            .field("retention", &self.retention)
+            .field("whitelisted_modules", &self.whitelisted_modules)
+            .field("alert_layer", &self.alert_layer.is_some())
+            .finish()
+    }
+}
+
+fn new_layer<W, F>(format: LogFormat, writer: W, ansi: bool, target: bool, filter: F) -> BoxedLayer
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    F: Filter<Registry> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => fmt::layer()
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .with_target(target)
+            .with_filter(filter)
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_writer(writer)
+            .with_target(true)
+            .with_thread_names(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_filter(filter)
+            .boxed(),
+    }
+}
+
+pub fn init_with_config(mut config: LoggerConfig) {
+    let (mut layers, filters) = config.layers();
+    layers.extend(config.alert_layer.take());
+    tracing_subscriber::registry().with(layers).init();
+    filters.into_iter().for_each(ReloadableFilter::register);
+    let file_name = config.file_name();
+    config.retention.start(config.dir, file_name);
+}
+
+pub fn init<T: Into<String>>(name: T) {
+    init_with_config(LoggerConfig::new(name));
 }
 
 pub fn init_with_chain<T: Display>(chain: T, name: String) {
@@ -43,46 +242,197 @@ pub fn new_whitelist_mode_env_filter(allowed_modules: &[&str], level: LevelFilte
 }
 
 pub fn init_with_whitelisted_modules<T: Display>(chain: T, name: String, modules: &[&str]) {
-    let modules = ["burberry", "reconstruct", "mev_core::flashloan", "panic_hook"]
-        .iter()
-        .chain(modules.iter())
-        .cloned()
-        .collect::<Vec<_>>();
-
-    let console_layer = fmt::layer()
-        .with_target(true)
-        .with_filter(new_whitelist_mode_env_filter(&modules, LevelFilter::INFO));
+    init_with_config(LoggerConfig::new(format!("{name}-{chain}")).with_whitelisted_modules(modules));
+}
 
-    let file_appender = tracing_appender::rolling::hourly("./logs/", format!("{name}-{chain}.log"));
+pub fn init_console_logger(level: Option<LevelFilter>) {
+    init_console_logger_with_directives(level, &[]);
+}
 
-    let file_layer = fmt::layer()
-        .with_ansi(false)
-        .with_writer(file_appender)
-        .with_target(true)
-        .with_filter(new_whitelist_mode_env_filter(&modules, LevelFilter::TRACE));
+/// Logs at `level` [default: info], `RUST_LOG` and then `directives` taking precedence.
+pub fn init_console_logger_with_directives(level: Option<LevelFilter>, directives: &[&str]) {
+    let level = level.unwrap_or(LevelFilter::INFO);
+    let env_directives = std::env::var(EnvFilter::DEFAULT_ENV).unwrap_or_default();
+    let directives = directives.join(",");
+    let (filter, reloadable) = ReloadableFilter::new(Box::new(move |extra| {
+        let filter = EnvFilter::builder()
+            .with_default_directive(level.into())
+            .parse(&env_directives)?;
+        add_directives(add_directives(filter, &directives)?, extra)
+    }))
+    .unwrap();
 
     tracing_subscriber::registry()
-        .with(file_layer)
-        .with(console_layer)
+        .with(fmt::layer().with_timer(fmt::time::SystemTime).with_filter(filter))
         .init();
+    reloadable.register();
 }
 
-pub fn init_console_logger(level: Option<LevelFilter>) {
-    init_console_logger_with_directives(level, &[]);
-}
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{self, Write},
+        sync::Arc,
+        time::SystemTime,
+    };
 
-pub fn init_console_logger_with_directives(level: Option<LevelFilter>, directives: &[&str]) {
-    let mut env_filter = EnvFilter::builder()
-        .with_default_directive(level.unwrap_or(LevelFilter::INFO).into())
-        .from_env()
+    use serde_json::Value;
+    use tracing::{debug, info, info_span};
+
+    use super::*;
+
+    fn new_temp_dir(name: &str) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!("mev_logger-{name}-{}-{nanos}", std::process::id()))
+    }
+
+    #[test]
+    fn test_file_name() {
+        let config = LoggerConfig::new("sui-arb-mainnet");
+        assert_eq!(
+            config.file_name(),
+            format!("sui-arb-mainnet-{}.log", std::process::id())
+        );
+
+        let config = config.with_instance_id("bot-2");
+        assert_eq!(config.file_name(), "sui-arb-mainnet-bot-2.log");
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let dir = new_temp_dir("json");
+        let config = LoggerConfig::new("sui-arb")
+            .with_format(LogFormat::Json)
+            .with_dir(&dir)
+            .with_instance_id("test")
+            .with_rotation(Rotation::Daily);
+
+        let (layers, _) = config.layers();
+        let subscriber = tracing_subscriber::registry().with(layers);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!("arb", coin = "0x2::sui::SUI");
+            let _guard = span.enter();
+            info!(profit = 42, "arb found");
+            info!("arb sent");
+        });
+
+        let files = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), 1, "{files:?}");
+        let file_name = files[0].file_name().unwrap().to_str().unwrap();
+        assert!(file_name.starts_with("sui-arb-test.log"), "{file_name}");
+
+        let logs = fs::read_to_string(&files[0]).unwrap();
+        let lines = logs
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{logs}");
+
+        let line = &lines[0];
+        for key in ["timestamp", "level", "fields", "target", "threadName", "span", "spans"] {
+            assert!(line.get(key).is_some(), "no {key} in {line}");
+        }
+        assert_eq!(line["fields"]["message"], "arb found");
+        assert_eq!(line["fields"]["profit"], 42);
+        assert_eq!(line["target"], module_path!());
+        assert_eq!(line["span"]["name"], "arb");
+        assert_eq!(line["span"]["coin"], "0x2::sui::SUI");
+        assert_eq!(lines[1]["fields"]["message"], "arb sent");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // collects the logs of the test
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Captured {
+        fn take(&self) -> String {
+            String::from_utf8(std::mem::take(&mut *self.0.lock().unwrap())).unwrap()
+        }
+    }
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // the only test to register a filter, `set_directives` applies to the registered filters of every test
+    #[test]
+    fn test_set_directives_at_runtime() {
+        let (filter, reloadable) = ReloadableFilter::new(Box::new(|directives| {
+            add_directives(EnvFilter::new("info"), directives)
+        }))
         .unwrap();
+        reloadable.register();
 
-    for directive in directives {
-        env_filter = env_filter.add_directive(directive.parse().unwrap());
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            fmt::layer()
+                .with_ansi(false)
+                .with_writer(move || writer.clone())
+                .with_filter(filter),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            debug!("before");
+            info!("before");
+            let logs = captured.take();
+            assert!(logs.contains("INFO") && !logs.contains("DEBUG"), "{logs}");
+
+            set_directives("mev_logger=debug").unwrap();
+            debug!("after");
+            assert!(captured.take().contains("DEBUG"));
+
+            // an invalid directive changes nothing
+            let error = set_directives("mev_logger=debug,mev_logger=loud").unwrap_err();
+            assert!(matches!(error, SetDirectivesError::Parse(_)), "{error}");
+            debug!("after an invalid directive");
+            assert!(captured.take().contains("DEBUG"));
+
+            // back to the initial directives
+            set_directives("").unwrap();
+            debug!("reset");
+            info!("reset");
+            let logs = captured.take();
+            assert!(logs.contains("INFO") && !logs.contains("DEBUG"), "{logs}");
+        });
     }
 
-    tracing_subscriber::registry()
-        .with(fmt::layer().with_timer(fmt::time::SystemTime))
-        .with(env_filter)
-        .init();
+    #[test]
+    fn test_size_rolling_file() {
+        let dir = new_temp_dir("size");
+        let mut file = SizeRollingFile::new(&dir, "sui-arb-test.log", 10).unwrap();
+        for line in ["12345\n", "67890\n", "abc\n", "0123456789abc\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        drop(file);
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("sui-arb-test.log.1"), "12345\n");
+        assert_eq!(read("sui-arb-test.log.2"), "67890\nabc\n");
+        assert_eq!(read("sui-arb-test.log"), "0123456789abc\n");
+
+        // a restart appends to the current file and keeps the rolled ones
+        let mut file = SizeRollingFile::new(&dir, "sui-arb-test.log", 10).unwrap();
+        file.write_all(b"xyz\n").unwrap();
+        assert_eq!(read("sui-arb-test.log.3"), "0123456789abc\n");
+        assert_eq!(read("sui-arb-test.log"), "xyz\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,75 @@
+use std::{fmt, sync::Mutex};
+
+use tracing_subscriber::{filter::ParseError, reload, EnvFilter, Registry};
+
+// builds the filter of a layer, with the directives of `set_directives` on top of the initial ones
+pub(crate) type NewFilter = Box<dyn Fn(&str) -> Result<EnvFilter, ParseError> + Send + Sync>;
+
+static RELOADABLE_FILTERS: Mutex<Vec<ReloadableFilter>> = Mutex::new(Vec::new());
+
+pub(crate) struct ReloadableFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    new_filter: NewFilter,
+}
+
+impl ReloadableFilter {
+    /// The filter to put on a layer, and what `register` makes `set_directives` reload it with.
+    pub(crate) fn new(new_filter: NewFilter) -> Result<(reload::Layer<EnvFilter, Registry>, Self), ParseError> {
+        let (filter, handle) = reload::Layer::new(new_filter("")?);
+        Ok((filter, Self { handle, new_filter }))
+    }
+
+    pub(crate) fn register(self) {
+        RELOADABLE_FILTERS.lock().unwrap().push(self);
+    }
+}
+
+#[derive(Debug)]
+pub enum SetDirectivesError {
+    /// none of the initialized loggers has a reloadable filter
+    NoLogger,
+    Parse(ParseError),
+    Reload(reload::Error),
+}
+
+impl fmt::Display for SetDirectivesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoLogger => write!(f, "no logger to set the directives of"),
+            Self::Parse(e) => write!(f, "invalid directives: {e}"),
+            Self::Reload(e) => write!(f, "fail to reload the log filter: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SetDirectivesError {}
+
+/// Adds `directives`, e.g. `arb=trace,shio=debug`, on top of the directives the loggers were initialized
+/// with, replacing the ones given to a previous call. An empty string goes back to the initial directives.
+///
+/// Either every filter is reloaded or none is, an invalid directive leaves the filters as they are.
+pub fn set_directives(directives: &str) -> Result<(), SetDirectivesError> {
+    let filters = RELOADABLE_FILTERS.lock().unwrap();
+    if filters.is_empty() {
+        return Err(SetDirectivesError::NoLogger);
+    }
+
+    let new_filters = filters
+        .iter()
+        .map(|filter| (filter.new_filter)(directives))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(SetDirectivesError::Parse)?;
+    for (filter, new_filter) in filters.iter().zip(new_filters) {
+        filter.handle.reload(new_filter).map_err(SetDirectivesError::Reload)?;
+    }
+
+    Ok(())
+}
+
+/// Adds the comma-separated `directives` to `filter`, they take precedence over the ones of the same target.
+pub(crate) fn add_directives(mut filter: EnvFilter, directives: &str) -> Result<EnvFilter, ParseError> {
+    for directive in directives.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        filter = filter.add_directive(directive.parse()?);
+    }
+    Ok(filter)
+}
@@ -0,0 +1,220 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use tracing::{info, warn};
+
+/// A week of hourly files, e.g. `Retention::default().with_max_files(DEFAULT_MAX_LOG_FILES)`.
+pub const DEFAULT_MAX_LOG_FILES: usize = 24 * 7;
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many log files are kept, the oldest ones are deleted first. No limit keeps them all, which is the
+/// default: pruning is opt-in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Retention {
+    pub max_files: Option<usize>,
+    pub max_total_bytes: Option<u64>,
+}
+
+impl Retention {
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = Some(max_files);
+        self
+    }
+
+    pub fn with_max_total_bytes(mut self, max_total_bytes: u64) -> Self {
+        self.max_total_bytes = Some(max_total_bytes);
+        self
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.max_files.is_none() && self.max_total_bytes.is_none()
+    }
+
+    /// Deletes the oldest of the files of `dir` written under `file_name`, i.e. `file_name` and its rotations
+    /// such as `{file_name}.2024-01-01-00` or `{file_name}.1`, until the limits are met. The files of other
+    /// instances sharing the prefix are never deleted, those of the previous runs of the same instance id are.
+    /// The newest file is always kept. Returns the deleted files.
+    pub fn prune<P: AsRef<Path>>(&self, dir: P, file_name: &str) -> io::Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() && is_rotation_of(&entry.file_name().to_string_lossy(), file_name) {
+                files.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        // newest first
+        files.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| b.2.cmp(&a.2)));
+
+        let mut deleted = vec![];
+        let mut total_bytes = 0;
+        for (i, (_, len, path)) in files.into_iter().enumerate() {
+            total_bytes += len;
+            let over_files = self.max_files.is_some_and(|max_files| i >= max_files);
+            let over_bytes = self.max_total_bytes.is_some_and(|max_bytes| total_bytes > max_bytes);
+            if i > 0 && (over_files || over_bytes) {
+                fs::remove_file(&path)?;
+                deleted.push(path);
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// Prunes the files every minute, in a background thread, see `prune`.
+    pub(crate) fn start(self, dir: PathBuf, file_name: String) {
+        if self.is_unlimited() {
+            return;
+        }
+
+        let worker = move || loop {
+            match self.prune(&dir, &file_name) {
+                Ok(deleted) if !deleted.is_empty() => info!(?deleted, "log files pruned"),
+                Ok(_) => {}
+                Err(error) => warn!(?error, ?dir, "fail to prune log files"),
+            }
+            thread::sleep(PRUNE_INTERVAL);
+        };
+        thread::Builder::new()
+            .name("log-retention".to_string())
+            .spawn(worker)
+            .expect("fail to spawn the log retention thread");
+    }
+}
+
+// `name` is `file_name`, or `file_name` with the date or index suffix of a rotation
+fn is_rotation_of(name: &str, file_name: &str) -> bool {
+    match name.strip_prefix(file_name) {
+        Some("") => true,
+        Some(suffix) => suffix
+            .strip_prefix('.')
+            .is_some_and(|suffix| !suffix.is_empty() && suffix.chars().all(|c| c.is_ascii_digit() || c == '-')),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    fn new_temp_dir() -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("mev_logger-retention-{}-{nanos}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // `name` with `len` bytes, last modified `age_secs` ago
+    fn new_file(dir: &Path, name: &str, len: usize, age_secs: u64) {
+        let path = dir.join(name);
+        fs::write(&path, vec![b'x'; len]).unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(age_secs))
+            .unwrap();
+    }
+
+    fn names(paths: &[PathBuf]) -> Vec<String> {
+        paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    fn remaining(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_prune_max_files() {
+        let dir = new_temp_dir();
+        new_file(&dir, "sui-arb-1.log.2024-01-01-00", 10, 4 * 3600);
+        new_file(&dir, "sui-arb-1.log.2024-01-01-01", 10, 3 * 3600);
+        new_file(&dir, "sui-arb-1.log.2024-01-01-02", 10, 2 * 3600);
+        new_file(&dir, "sui-arb-1.log.2024-01-01-03", 10, 3600);
+        // the files of the other instances sharing the prefix, or of another prefix, are never pruned
+        new_file(&dir, "sui-arb-2.log.2024-01-01-00", 10, 5 * 3600);
+        new_file(&dir, "sui-arb-11.log.2024-01-01-00", 10, 5 * 3600);
+        new_file(&dir, "sui-arb-1.log-backup", 10, 5 * 3600);
+        new_file(&dir, "relay-1.log.2024-01-01-00", 10, 5 * 3600);
+
+        let deleted = Retention::unlimited()
+            .with_max_files(2)
+            .prune(&dir, "sui-arb-1.log")
+            .unwrap();
+        assert_eq!(
+            names(&deleted),
+            vec!["sui-arb-1.log.2024-01-01-01", "sui-arb-1.log.2024-01-01-00"]
+        );
+        assert_eq!(
+            remaining(&dir),
+            vec![
+                "relay-1.log.2024-01-01-00",
+                "sui-arb-1.log-backup",
+                "sui-arb-1.log.2024-01-01-02",
+                "sui-arb-1.log.2024-01-01-03",
+                "sui-arb-11.log.2024-01-01-00",
+                "sui-arb-2.log.2024-01-01-00",
+            ]
+        );
+
+        // within the limits
+        assert!(Retention::unlimited()
+            .with_max_files(2)
+            .prune(&dir, "sui-arb-1.log")
+            .unwrap()
+            .is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_retention_is_opt_in() {
+        assert_eq!(Retention::default(), Retention::unlimited());
+        assert!(Retention::default().is_unlimited());
+    }
+
+    #[test]
+    fn test_prune_max_total_bytes() {
+        let dir = new_temp_dir();
+        new_file(&dir, "sui-arb-1.log.1", 40, 300);
+        new_file(&dir, "sui-arb-1.log.2", 30, 200);
+        new_file(&dir, "sui-arb-1.log.3", 20, 100);
+        new_file(&dir, "sui-arb-1.log", 10, 0);
+
+        // 10 + 20 + 30 fit, the oldest file doesn't
+        let retention = Retention::unlimited().with_max_total_bytes(60);
+        assert_eq!(
+            names(&retention.prune(&dir, "sui-arb-1.log").unwrap()),
+            vec!["sui-arb-1.log.1"]
+        );
+
+        // the newest file is kept, even over the limit
+        let retention = Retention::unlimited().with_max_total_bytes(5).with_max_files(10);
+        assert_eq!(
+            names(&retention.prune(&dir, "sui-arb-1.log").unwrap()),
+            vec!["sui-arb-1.log.3", "sui-arb-1.log.2"]
+        );
+        assert_eq!(remaining(&dir), vec!["sui-arb-1.log"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// A log file rolled over once it reaches `max_bytes`: `{file_name}` is renamed to `{file_name}.{n}`, with
+/// `n` increasing from 1, and a new `{file_name}` is started.
+///
+/// An event is never split across files, a file may then exceed `max_bytes` by the size of one event.
+pub struct SizeRollingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    file: File,
+    written: u64,
+    next_index: u32,
+}
+
+impl SizeRollingFile {
+    pub fn new<P: AsRef<Path>>(dir: P, file_name: &str, max_bytes: u64) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        let path = dir.as_ref().join(file_name);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+
+        // carries on after the files rolled over by a previous run
+        let mut next_index = 1;
+        while rolled_path(&path, next_index).exists() {
+            next_index += 1;
+        }
+
+        Ok(Self {
+            path,
+            max_bytes,
+            file,
+            written,
+            next_index,
+        })
+    }
+
+    fn roll_over(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        fs::rename(&self.path, rolled_path(&self.path, self.next_index))?;
+        self.next_index += 1;
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+fn rolled_path(path: &Path, index: u32) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{index}"));
+    PathBuf::from(path)
+}
+
+impl Write for SizeRollingFile {
+    // the fmt layers write an event at once
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.roll_over()?;
+        }
+
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -1,53 +1,813 @@
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    fmt::Debug,
+    future::Future,
+    ops::Deref,
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
 
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+};
+
+// a replacement whose `init_fn` panics is tried again after this, doubled on every failure
+const REPLACE_RETRY_INITIAL: Duration = Duration::from_millis(100);
+const REPLACE_RETRY_MAX: Duration = Duration::from_secs(10);
+
+type InitFn<T> = Arc<dyn Fn() -> T + Send + Sync>;
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+type AsyncInitFn<T> = Arc<dyn Fn() -> BoxFuture<T> + Send + Sync>;
+type HealthFn<T> = Arc<dyn Fn(&T) -> bool + Send + Sync>;
+
+/// A fixed set of objects, handed out as leases.
+///
+/// A lease (`PoolGuard`) is exclusive and returns its slot when dropped. A lease takes a permit of a
+/// semaphore sized to the pool, so the demand in excess waits in line instead of oversubscribing the objects.
+/// The free slots are found with an atomic flag per slot, starting from a slot that rotates with every
+/// acquisition, so the objects are used evenly.
+///
+/// A broken object is ejected, by `mark_unhealthy` or when the health check of `new_with_health` fails
+/// on lease, and replaced by a new one from `init_fn`, on a background thread or on the runtime of an async
+/// pool. The pool runs with one object less until then. A replacement whose `init_fn` panics is retried with
+/// a growing delay, `failing_replacements` tells how many are stuck that way.
 pub struct ObjectPool<T> {
-    pub objects: Vec<Arc<T>>,
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    objects: Vec<RwLock<Arc<T>>>,
+    // a slot stays leased while its object is being replaced
+    leased: Vec<AtomicBool>,
+    replacing: Vec<AtomicBool>,
+    // the last replacement attempt of the slot panicked, it's retried
+    failing: Vec<AtomicBool>,
+    in_use: AtomicUsize,
+    // where the next acquisition starts looking for a free slot
+    next: AtomicUsize,
+    // as many as the free slots, or fewer while a slot is being returned
+    permits: Arc<Semaphore>,
+    replacer: Replacer<T>,
+    health_fn: Option<HealthFn<T>>,
+    ejections: AtomicU64,
+    replacements: AtomicU64,
+}
+
+// how the ejected objects are replaced
+enum Replacer<T> {
+    // on a background thread
+    Sync(InitFn<T>),
+    // on the runtime the pool was created on, or on a temporary one if there was none
+    Async {
+        init_fn: AsyncInitFn<T>,
+        handle: Option<Handle>,
+    },
+}
+
+impl<T> Inner<T> {
+    // puts the replacement of an ejected object in its slot, and returns the slot to the pool
+    fn replace(&self, slot: usize, object: T) {
+        *self.objects[slot].write().unwrap() = Arc::new(object);
+
+        self.failing[slot].store(false, Ordering::Release);
+        self.replacing[slot].store(false, Ordering::Release);
+        self.leased[slot].store(false, Ordering::Release);
+        self.replacements.fetch_add(1, Ordering::AcqRel);
+        self.permits.add_permits(1);
+    }
+
+    // records a panicked replacement attempt, returns how long to wait before the next one
+    fn replace_failed(&self, slot: usize, attempt: u32) -> Duration {
+        self.failing[slot].store(true, Ordering::Release);
+        REPLACE_RETRY_INITIAL
+            .saturating_mul(1 << attempt.min(16))
+            .min(REPLACE_RETRY_MAX)
+    }
 }
 
-impl<T> ObjectPool<T> {
+impl<T: Send + Sync + 'static> ObjectPool<T> {
     pub fn new<F>(num_objects: usize, init_fn: F) -> Self
     where
         F: Fn() -> T + Send + Sync + 'static,
-        T: Send + Sync + 'static,
     {
-        let init_fn = Arc::new(init_fn);
+        Self::new_inner(num_objects, Arc::new(init_fn), None)
+    }
+
+    /// Same as `new`, an object for which `health_fn` returns false is ejected instead of leased.
+    /// `health_fn` runs on every lease, it must be cheap.
+    pub fn new_with_health<F, H>(num_objects: usize, init_fn: F, health_fn: H) -> Self
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+        H: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        Self::new_inner(num_objects, Arc::new(init_fn), Some(Arc::new(health_fn)))
+    }
+
+    /// Same as `new`, with an async `init_fn`, the objects are initialized concurrently on the ambient runtime.
+    ///
+    /// Without an ambient runtime, or with a current-thread one, which the caller blocks, they are initialized on
+    /// a temporary multi-thread runtime instead, so the tasks the objects spawn die with it. The replacements of
+    /// the ejected objects are spawned on the ambient runtime, whatever its flavor, and only fall back to a
+    /// temporary runtime without one.
+    pub fn new_async<F, Fut>(num_objects: usize, init_fn: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = T> + Send + 'static,
+    {
+        let init_fn: AsyncInitFn<T> = Arc::new(move || Box::pin(init_fn()) as BoxFuture<T>);
+        let init_all = {
+            let init_fn = init_fn.clone();
+            async move {
+                let mut set = JoinSet::new();
+                for slot in 0..num_objects {
+                    let object = init_fn();
+                    set.spawn(async move { (slot, object.await) });
+                }
+
+                let mut objects = Vec::with_capacity(num_objects);
+                while let Some(result) = set.join_next().await {
+                    objects.push(result.expect("object initialization panicked"));
+                }
+                objects.sort_by_key(|(slot, _)| *slot);
+                objects.into_iter().map(|(_, object)| object).collect::<Vec<_>>()
+            }
+        };
+
+        let objects = block_on(init_all);
+        let replacer = Replacer::Async {
+            init_fn,
+            handle: Handle::try_current().ok(),
+        };
+        Self::with_objects(objects, replacer, None)
+    }
+
+    fn new_inner(num_objects: usize, init_fn: InitFn<T>, health_fn: Option<HealthFn<T>>) -> Self {
         let mut handles = Vec::with_capacity(num_objects);
 
         // Spawn threads to initialize objects in parallel
         for _ in 0..num_objects {
             let init_fn = init_fn.clone();
-            handles.push(std::thread::spawn(move || Arc::new((init_fn)())));
+            handles.push(std::thread::spawn(move || (init_fn)()));
         }
 
         // Collect results from all threads
-        let objects = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        let objects: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+
+        Self::with_objects(objects, Replacer::Sync(init_fn), health_fn)
+    }
+
+    fn with_objects(objects: Vec<T>, replacer: Replacer<T>, health_fn: Option<HealthFn<T>>) -> Self {
+        let objects: Vec<_> = objects
+            .into_iter()
+            .map(|object| RwLock::new(Arc::new(object)))
+            .collect();
+
+        Self {
+            inner: Arc::new(Inner {
+                leased: objects.iter().map(|_| AtomicBool::new(false)).collect(),
+                replacing: objects.iter().map(|_| AtomicBool::new(false)).collect(),
+                failing: objects.iter().map(|_| AtomicBool::new(false)).collect(),
+                permits: Arc::new(Semaphore::new(objects.len())),
+                objects,
+                in_use: AtomicUsize::new(0),
+                next: AtomicUsize::new(0),
+                replacer,
+                health_fn,
+                ejections: AtomicU64::new(0),
+                replacements: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.objects.is_empty()
+    }
+
+    /// The number of leased objects.
+    pub fn in_use(&self) -> usize {
+        self.inner.in_use.load(Ordering::Acquire)
+    }
+
+    /// The number of objects ejected so far.
+    pub fn ejections(&self) -> u64 {
+        self.inner.ejections.load(Ordering::Acquire)
+    }
+
+    /// The number of ejected objects replaced so far, the others are still being replaced.
+    pub fn replacements(&self) -> u64 {
+        self.inner.replacements.load(Ordering::Acquire)
+    }
 
-        Self { objects }
+    /// The number of ejected objects being replaced.
+    pub fn replacing(&self) -> usize {
+        count_set(&self.inner.replacing)
     }
 
-    // get the one with the least refcount
+    /// The number of ejected objects whose last replacement attempt panicked, they are retried.
+    pub fn failing_replacements(&self) -> usize {
+        count_set(&self.inner.failing)
+    }
+
+    /// Leases a free object, `None` if they are all leased.
+    pub fn try_get_owned(&self) -> Option<PoolGuard<T>> {
+        loop {
+            let permit = self.inner.permits.clone().try_acquire_owned().ok()?;
+            if let Some(guard) = self.lease(permit) {
+                return Some(guard);
+            }
+        }
+    }
+
+    /// Leases a free object, waiting in line for one to be returned if they are all leased.
+    pub async fn acquire(&self) -> PoolGuard<T> {
+        loop {
+            // the semaphore is never closed
+            let permit = self.inner.permits.clone().acquire_owned().await.unwrap();
+            if let Some(guard) = self.lease(permit) {
+                return guard;
+            }
+        }
+    }
+
+    /// Same as `acquire`, `None` if no object is returned within `timeout`.
+    pub async fn acquire_timeout(&self, timeout: Duration) -> Option<PoolGuard<T>> {
+        tokio::time::timeout(timeout, self.acquire()).await.ok()
+    }
+
+    /// Same as `acquire`.
+    pub async fn get_owned(&self) -> PoolGuard<T> {
+        self.acquire().await
+    }
+
+    // a permit guarantees a free slot, `None` if its object fails the health check and is ejected
+    fn lease(&self, permit: OwnedSemaphorePermit) -> Option<PoolGuard<T>> {
+        let inner = &self.inner;
+        let len = inner.objects.len();
+        let start = inner.next.fetch_add(1, Ordering::Relaxed);
+
+        let slot = (0..len)
+            .map(|i| (start + i) % len)
+            .find(|slot| {
+                inner.leased[*slot]
+                    .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            })
+            .expect("a free slot for every permit");
+        inner.in_use.fetch_add(1, Ordering::AcqRel);
+
+        let guard = PoolGuard {
+            inner: inner.clone(),
+            slot,
+            object: inner.objects[slot].read().unwrap().clone(),
+            permit: Some(permit),
+        };
+        match &inner.health_fn {
+            Some(health_fn) if !health_fn(&guard) => {
+                self.mark_unhealthy(guard);
+                None
+            }
+            _ => Some(guard),
+        }
+    }
+
+    /// Ejects the object of `guard`, e.g. after it failed repeatedly, a new one is made in the background.
+    pub fn mark_unhealthy(&self, mut guard: PoolGuard<T>) {
+        let inner = guard.inner.clone();
+        let slot = guard.slot;
+
+        // the slot stays leased, and the pool one permit short, until the object is replaced
+        guard.permit.take().unwrap().forget();
+        drop(guard);
+        inner.in_use.fetch_sub(1, Ordering::AcqRel);
+        inner.replacing[slot].store(true, Ordering::Release);
+        inner.ejections.fetch_add(1, Ordering::AcqRel);
+
+        match &inner.replacer {
+            Replacer::Sync(init_fn) => {
+                let init_fn = init_fn.clone();
+                std::thread::spawn(move || replace_blocking(&inner, slot, || init_fn()));
+            }
+            Replacer::Async { init_fn, handle } => {
+                let init_fn = init_fn.clone();
+                match handle.clone() {
+                    // the panics of a task end up in its JoinError
+                    Some(handle) => {
+                        handle.spawn(async move {
+                            let mut attempt = 0;
+                            loop {
+                                let init_fn = init_fn.clone();
+                                match tokio::spawn(async move { init_fn().await }).await {
+                                    Ok(object) => break inner.replace(slot, object),
+                                    Err(_) => tokio::time::sleep(inner.replace_failed(slot, attempt)).await,
+                                }
+                                attempt += 1;
+                            }
+                        });
+                    }
+                    None => {
+                        std::thread::spawn(move || replace_blocking(&inner, slot, || block_on(init_fn())));
+                    }
+                }
+            }
+        }
+    }
+
+    /// An object, shared with the other callers: a free one if any, the next one in turn otherwise.
+    /// Unlike `get_owned`, it doesn't hold a lease, for the callers that keep the object around.
     pub fn get(&self) -> Arc<T> {
-        self.objects
-            .iter()
-            .min_by_key(|obj| Arc::strong_count(obj))
-            .unwrap()
-            .clone()
+        if let Some(guard) = self.try_get_owned() {
+            return guard.object().clone();
+        }
+
+        let inner = &self.inner;
+        let len = inner.objects.len();
+        assert!(len > 0, "get from an empty ObjectPool");
+        let start = inner.next.fetch_add(1, Ordering::Relaxed);
+        // the ejected objects are avoided, unless they all are
+        let slot = (0..len)
+            .map(|i| (start + i) % len)
+            .find(|slot| !inner.replacing[*slot].load(Ordering::Acquire))
+            .unwrap_or(start % len);
+        inner.objects[slot].read().unwrap().clone()
+    }
+}
+
+// replaces the object of `slot` on the current thread, until `init_fn` returns without panicking
+fn replace_blocking<T>(inner: &Inner<T>, slot: usize, init_fn: impl Fn() -> T) {
+    let mut attempt = 0;
+    loop {
+        match catch_unwind(AssertUnwindSafe(&init_fn)) {
+            Ok(object) => return inner.replace(slot, object),
+            Err(_) => std::thread::sleep(inner.replace_failed(slot, attempt)),
+        }
+        attempt += 1;
+    }
+}
+
+fn count_set(flags: &[AtomicBool]) -> usize {
+    flags.iter().filter(|flag| flag.load(Ordering::Acquire)).count()
+}
+
+// runs `future` to completion from sync code, on the ambient runtime if it's a multi-thread one
+fn block_on<Fut>(future: Fut) -> Fut::Output
+where
+    Fut: Future + Send,
+    Fut::Output: Send,
+{
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| handle.block_on(future))
+        }
+        // a runtime can neither be started nor dropped within another one, hence the thread
+        _ => std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    tokio::runtime::Runtime::new()
+                        .expect("fail to build a runtime")
+                        .block_on(future)
+                })
+                .join()
+                .unwrap()
+        }),
     }
 }
 
 impl<T> Debug for ObjectPool<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let len = self.objects.len();
-        let ref_counts: Vec<_> = self.objects.iter().map(|obj| Arc::strong_count(obj)).collect();
-        let max_ref = ref_counts.iter().max().unwrap_or(&0);
-        let min_ref = ref_counts.iter().min().unwrap_or(&0);
-
-        write!(f, "ObjectPool(len={}, max_ref={}, min_ref={}", len, max_ref, min_ref)?;
+        let inner = &self.inner;
+        let len = inner.objects.len();
+        let replacing = count_set(&inner.replacing);
+        write!(
+            f,
+            "ObjectPool(len={}, in_use={}, replacing={}",
+            len,
+            inner.in_use.load(Ordering::Relaxed),
+            replacing
+        )?;
 
         if len < 32 {
-            write!(f, ", ref_counts={:?}", ref_counts)?;
+            let leased: Vec<_> = inner
+                .leased
+                .iter()
+                .map(|leased| leased.load(Ordering::Relaxed) as u8)
+                .collect();
+            write!(f, ", leased={:?}", leased)?;
         }
 
         write!(f, ")")
     }
 }
+
+/// The lease of an object of an `ObjectPool`, the object goes back to the pool on drop.
+pub struct PoolGuard<T> {
+    inner: Arc<Inner<T>>,
+    slot: usize,
+    object: Arc<T>,
+    // released after the slot, see `Drop`, `None` once the object is ejected
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<T> PoolGuard<T> {
+    /// The index of the object in the pool.
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    pub fn object(&self) -> &Arc<T> {
+        &self.object
+    }
+}
+
+impl<T> Deref for PoolGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.object
+    }
+}
+
+impl<T> Drop for PoolGuard<T> {
+    fn drop(&mut self) {
+        // an ejected slot is returned once its object is replaced
+        if self.permit.is_none() {
+            return;
+        }
+
+        self.inner.in_use.fetch_sub(1, Ordering::AcqRel);
+        // the permit, dropped after this, lets the next lease in
+        self.inner.leased[self.slot].store(false, Ordering::Release);
+    }
+}
+
+impl<T> Debug for PoolGuard<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PoolGuard(slot={})", self.slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an object that counts its concurrent and total users
+    #[derive(Default)]
+    struct Counted {
+        users: AtomicUsize,
+        uses: AtomicUsize,
+    }
+
+    #[test]
+    fn test_leases() {
+        let pool = ObjectPool::new(2, Counted::default);
+        assert_eq!((pool.len(), pool.in_use()), (2, 0));
+
+        let a = pool.try_get_owned().unwrap();
+        let b = pool.try_get_owned().unwrap();
+        assert_ne!(a.slot(), b.slot());
+        assert_eq!(pool.in_use(), 2);
+        assert!(pool.try_get_owned().is_none());
+        assert_eq!(
+            format!("{pool:?}"),
+            "ObjectPool(len=2, in_use=2, replacing=0, leased=[1, 1])"
+        );
+
+        // still shared while all are leased
+        let _shared = pool.get();
+        assert_eq!(pool.in_use(), 2);
+
+        drop(a);
+        assert_eq!(pool.in_use(), 1);
+        let c = pool.try_get_owned().unwrap();
+        assert_ne!(c.slot(), b.slot());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_waits_for_a_lease() {
+        let pool = Arc::new(ObjectPool::new(2, Counted::default));
+        let a = pool.acquire().await;
+        let _b = pool.acquire().await;
+
+        let third = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.acquire().await.slot() }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!third.is_finished());
+
+        let slot = a.slot();
+        drop(a);
+        let third = tokio::time::timeout(Duration::from_secs(1), third)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(third, slot);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_timeout() {
+        let pool = ObjectPool::new(2, Counted::default);
+        let _a = pool.acquire_timeout(Duration::from_millis(10)).await.unwrap();
+        let _b = pool.acquire_timeout(Duration::from_millis(10)).await.unwrap();
+
+        let start = std::time::Instant::now();
+        assert!(pool.acquire_timeout(Duration::from_millis(50)).await.is_none());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(pool.in_use(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_owned_waits_for_a_lease() {
+        let pool = Arc::new(ObjectPool::new(1, Counted::default));
+        let guard = pool.try_get_owned().unwrap();
+
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.get_owned().await.slot() }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        drop(guard);
+        let slot = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(slot, 0);
+        assert_eq!(pool.in_use(), 0);
+    }
+
+    // an object numbered in order of creation, which breaks when told to
+    struct Numbered {
+        id: usize,
+        healthy: AtomicBool,
+    }
+
+    fn numbered_pool(num_objects: usize) -> ObjectPool<Numbered> {
+        let created = AtomicUsize::new(0);
+        ObjectPool::new_with_health(
+            num_objects,
+            move || Numbered {
+                id: created.fetch_add(1, Ordering::SeqCst),
+                healthy: AtomicBool::new(true),
+            },
+            |object| object.healthy.load(Ordering::SeqCst),
+        )
+    }
+
+    async fn wait_for_replacements(pool: &ObjectPool<Numbered>, replacements: u64) {
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while pool.replacements() < replacements {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("replaced in time");
+    }
+
+    #[tokio::test]
+    async fn test_unhealthy_object_replaced_on_lease() {
+        let pool = numbered_pool(2);
+        let broken = pool.acquire().await;
+        let broken_slot = broken.slot();
+        broken.healthy.store(false, Ordering::SeqCst);
+        drop(broken);
+
+        // the broken object is ejected on lease, and leased again once replaced
+        let a = pool.acquire().await;
+        let b = pool.acquire_timeout(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(pool.ejections(), 1);
+        assert!(a.healthy.load(Ordering::SeqCst) && b.healthy.load(Ordering::SeqCst));
+
+        wait_for_replacements(&pool, 1).await;
+        let replaced = [a, b].into_iter().find(|guard| guard.slot() == broken_slot).unwrap();
+        assert_eq!(replaced.id, 2);
+        assert_eq!(pool.in_use(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_mark_unhealthy() {
+        let pool = numbered_pool(2);
+        let a = pool.acquire().await;
+        let slot = a.slot();
+        pool.mark_unhealthy(a);
+        assert_eq!((pool.in_use(), pool.ejections()), (0, 1));
+
+        wait_for_replacements(&pool, 1).await;
+        let a = pool.acquire().await;
+        let b = pool.acquire().await;
+        let replaced = if a.slot() == slot { a } else { b };
+        assert_eq!(replaced.id, 2);
+        assert!(pool.try_get_owned().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reduced_capacity_while_replacing() {
+        // the replacements take a while
+        let created = Arc::new(AtomicUsize::new(0));
+        let pool = ObjectPool::new(2, {
+            let created = created.clone();
+            move || {
+                if created.fetch_add(1, Ordering::SeqCst) >= 2 {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Counted::default()
+            }
+        });
+
+        let a = pool.acquire().await;
+        pool.mark_unhealthy(a);
+        let b = pool.acquire().await;
+        assert!(pool.try_get_owned().is_none());
+        assert!(format!("{pool:?}").contains("replacing=1"), "{pool:?}");
+        // the shared objects avoid the one being replaced
+        assert!(Arc::ptr_eq(&pool.get(), b.object()));
+
+        let c = pool.acquire_timeout(Duration::from_secs(1)).await.unwrap();
+        assert_ne!(c.slot(), b.slot());
+        assert_eq!((pool.replacements(), created.load(Ordering::SeqCst)), (1, 3));
+    }
+
+    // an init_fn that panics on its second call, the first replacement
+    fn panics_once<T>(init: impl Fn() -> T) -> impl Fn() -> T {
+        let created = AtomicUsize::new(0);
+        move || {
+            if created.fetch_add(1, Ordering::SeqCst) == 1 {
+                panic!("initialization failed");
+            }
+            init()
+        }
+    }
+
+    async fn assert_replaced_after_panic<T: Send + Sync + 'static>(pool: ObjectPool<T>) {
+        pool.mark_unhealthy(pool.acquire().await);
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while pool.failing_replacements() == 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the replacement panicked");
+        assert_eq!((pool.replacing(), pool.replacements()), (1, 0));
+
+        // retried
+        let _guard = pool.acquire_timeout(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(
+            (pool.replacing(), pool.failing_replacements(), pool.replacements()),
+            (0, 0, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_panicking_replacement_retried() {
+        assert_replaced_after_panic(ObjectPool::new(1, panics_once(Counted::default))).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_panicking_async_replacement_retried() {
+        let init_fn = panics_once(|| async { Counted::default() });
+        assert_replaced_after_panic(ObjectPool::new_async(1, init_fn)).await;
+    }
+
+    // objects that take a while to initialize, numbered in order of creation
+    fn slow_async_pool(num_objects: usize) -> ObjectPool<usize> {
+        let created = AtomicUsize::new(0);
+        ObjectPool::new_async(num_objects, move || {
+            let id = created.fetch_add(1, Ordering::SeqCst);
+            async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                id
+            }
+        })
+    }
+
+    fn assert_initialized_concurrently(pool: &ObjectPool<usize>, start: std::time::Instant) {
+        assert!(start.elapsed() < Duration::from_millis(300), "{:?}", start.elapsed());
+        let mut ids = pool
+            .inner
+            .objects
+            .iter()
+            .map(|object| **object.read().unwrap())
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_new_async_without_runtime() {
+        let start = std::time::Instant::now();
+        let pool = slow_async_pool(4);
+        assert_initialized_concurrently(&pool, start);
+    }
+
+    #[tokio::test]
+    async fn test_new_async_in_current_thread_runtime() {
+        let start = std::time::Instant::now();
+        let pool = slow_async_pool(4);
+        assert_initialized_concurrently(&pool, start);
+
+        // the replacements too
+        let guard = pool.acquire().await;
+        let slot = guard.slot();
+        pool.mark_unhealthy(guard);
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while pool.replacements() < 1 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(**pool.inner.objects[slot].read().unwrap(), 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_new_async_on_ambient_runtime() {
+        let start = std::time::Instant::now();
+        let pool = slow_async_pool(4);
+        assert_initialized_concurrently(&pool, start);
+
+        // the tasks spawned by the objects outlive the initialization
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let tx = std::sync::Mutex::new(Some(tx));
+        let _pool = ObjectPool::new_async(1, move || {
+            let tx = tx.lock().unwrap().take();
+            async move {
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    tx.unwrap().send(()).unwrap();
+                });
+            }
+        });
+        tokio::time::timeout(Duration::from_secs(1), rx).await.unwrap().unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_new_async_replaced_on_ambient_runtime() {
+        // the second object spawns a task, which must outlive its initialization
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let tx = std::sync::Mutex::new(Some(tx));
+        let created = AtomicUsize::new(0);
+        let pool = ObjectPool::new_async(1, move || {
+            let tx = (created.fetch_add(1, Ordering::SeqCst) == 1).then(|| tx.lock().unwrap().take().unwrap());
+            async move {
+                if let Some(tx) = tx {
+                    tokio::spawn(async move {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        tx.send(()).unwrap();
+                    });
+                }
+            }
+        });
+
+        pool.mark_unhealthy(pool.acquire().await);
+        tokio::time::timeout(Duration::from_secs(1), rx).await.unwrap().unwrap();
+        assert_eq!(pool.replacements(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_stress() {
+        const OBJECTS: usize = 4;
+        const TASKS: usize = 64;
+        const ROUNDS: usize = 100;
+
+        let pool = Arc::new(ObjectPool::new(OBJECTS, Counted::default));
+        let tasks = (0..TASKS)
+            .map(|_| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    for _ in 0..ROUNDS {
+                        let guard = pool.get_owned().await;
+                        assert!(pool.in_use() <= OBJECTS);
+                        assert_eq!(guard.users.fetch_add(1, Ordering::SeqCst), 0, "shared lease");
+                        tokio::task::yield_now().await;
+                        guard.users.fetch_sub(1, Ordering::SeqCst);
+                        guard.uses.fetch_add(1, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(pool.in_use(), 0);
+        let uses = pool
+            .inner
+            .objects
+            .iter()
+            .map(|object| object.read().unwrap().uses.load(Ordering::SeqCst))
+            .collect::<Vec<_>>();
+        assert_eq!(uses.iter().sum::<usize>(), TASKS * ROUNDS);
+        let even = TASKS * ROUNDS / OBJECTS;
+        assert!(uses.iter().all(|uses| *uses > even / 2 && *uses < even * 2), "{uses:?}");
+    }
+}
@@ -0,0 +1,288 @@
+use std::{sync::Arc, time::Duration};
+
+use burberry::async_trait;
+use eyre::{ensure, eyre, OptionExt, Result};
+use fastcrypto::{
+    encoding::{Base64, Encoding},
+    hash::HashFunction,
+    traits::ToFromBytes,
+};
+use serde::Deserialize;
+use serde_json::json;
+use shared_crypto::intent::Intent;
+use sui_types::{
+    base_types::SuiAddress,
+    crypto::{DefaultHash, PublicKey, Signature, Signer, SuiKeyPair, SuiSignature},
+    transaction::{TransactionData, TransactionDataAPI},
+};
+
+// the signature is on the critical path of a bid, a slower signer makes it miss the auction anyway
+pub const DEFAULT_SIGN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Signs the bid txs, the address is the sender of the txs.
+#[async_trait]
+pub trait BidSigner: Send + Sync {
+    /// `tx_bytes` is the bcs encoded `TransactionData`, the signature is over its sui transaction intent.
+    async fn sign(&self, tx_bytes: &[u8]) -> Result<Signature>;
+
+    fn address(&self) -> SuiAddress;
+}
+
+/// Signs with a key held in memory.
+pub struct KeypairSigner {
+    keypair: SuiKeyPair,
+    address: SuiAddress,
+}
+
+impl KeypairSigner {
+    pub fn new(keypair: SuiKeyPair) -> Self {
+        let address = SuiAddress::from(&keypair.public());
+        Self { keypair, address }
+    }
+}
+
+#[async_trait]
+impl BidSigner for KeypairSigner {
+    async fn sign(&self, tx_bytes: &[u8]) -> Result<Signature> {
+        let digest = {
+            let mut hasher = DefaultHash::default();
+            hasher.update(bcs::to_bytes(&Intent::sui_transaction())?);
+            hasher.update(tx_bytes);
+            hasher.finalize().digest
+        };
+
+        Ok(self.keypair.sign(&digest))
+    }
+
+    fn address(&self) -> SuiAddress {
+        self.address
+    }
+}
+
+/// Asks a signing service to sign, so that the key doesn't live in the bot.
+///
+/// The service gets `POST {"address": "0x..", "txBytes": "<base64>"}` and answers
+/// `{"signature": "<base64>"}`, in the serialized sui signature format (flag || signature || public key).
+/// A signature of another key than `address` is refused.
+pub struct HttpBidSigner {
+    client: reqwest::Client,
+    url: String,
+    address: SuiAddress,
+    timeout: Duration,
+}
+
+#[derive(Deserialize)]
+struct SignResponse {
+    signature: String,
+}
+
+impl HttpBidSigner {
+    pub fn new(url: String, address: SuiAddress) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            address,
+            timeout: DEFAULT_SIGN_TIMEOUT,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[async_trait]
+impl BidSigner for HttpBidSigner {
+    async fn sign(&self, tx_bytes: &[u8]) -> Result<Signature> {
+        let request = json!({
+            "address": self.address,
+            "txBytes": Base64::encode(tx_bytes),
+        });
+        let resp = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(|e| eyre!(e).wrap_err(format!("signer {} failed, timeout {:?}", self.url, self.timeout)))?;
+        ensure!(
+            resp.status().is_success(),
+            "signer {} answered {}",
+            self.url,
+            resp.status()
+        );
+
+        let resp: SignResponse = resp.json().await?;
+        let signature =
+            Signature::from_bytes(&Base64::decode(&resp.signature).map_err(|e| eyre!(e))?).map_err(|e| eyre!(e))?;
+
+        let public_key =
+            PublicKey::try_from_bytes(signature.scheme(), signature.public_key_bytes()).map_err(|e| eyre!(e))?;
+        let signed_by = SuiAddress::from(&public_key);
+        ensure!(
+            signed_by == self.address,
+            "signer signed with {signed_by}, expected {}",
+            self.address
+        );
+
+        Ok(signature)
+    }
+
+    fn address(&self) -> SuiAddress {
+        self.address
+    }
+}
+
+/// Signs each bid with the signer of its sender, for bids sent from several addresses.
+pub struct SenderBidSigner {
+    signers: Vec<Arc<dyn BidSigner>>,
+}
+
+impl SenderBidSigner {
+    pub fn new(signers: Vec<Arc<dyn BidSigner>>) -> Self {
+        assert!(!signers.is_empty(), "no bid signer");
+        Self { signers }
+    }
+}
+
+#[async_trait]
+impl BidSigner for SenderBidSigner {
+    async fn sign(&self, tx_bytes: &[u8]) -> Result<Signature> {
+        let sender = bcs::from_bytes::<TransactionData>(tx_bytes)?.sender();
+        let signer = self
+            .signers
+            .iter()
+            .find(|signer| signer.address() == sender)
+            .ok_or_eyre(format!("no bid signer for sender {sender}"))?;
+        signer.sign(tx_bytes).await
+    }
+
+    /// The address of the first signer.
+    fn address(&self) -> SuiAddress {
+        self.signers[0].address()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use shared_crypto::intent::IntentMessage;
+    use sui_types::{
+        base_types::random_object_ref,
+        crypto::{get_key_pair, AccountKeyPair},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    fn new_keypair() -> SuiKeyPair {
+        let (_, keypair): (_, AccountKeyPair) = get_key_pair();
+        SuiKeyPair::Ed25519(keypair)
+    }
+
+    fn new_tx(sender: SuiAddress) -> TransactionData {
+        TransactionData::new_transfer_sui(sender, sender, None, random_object_ref(), 10_000_000, 750)
+    }
+
+    // signs every request with `signer` after `delay`
+    async fn new_mock_signer(signer: Arc<KeypairSigner>, delay: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let signer = signer.clone();
+                tokio::spawn(async move {
+                    let mut request = vec![];
+                    let mut buf = [0u8; 4096];
+                    // the body is a single json object
+                    while !request.ends_with(b"}") {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                    }
+                    let request = String::from_utf8(request).unwrap();
+                    let body: Value = serde_json::from_str(&request[request.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+                    let tx_bytes = Base64::decode(body["txBytes"].as_str().unwrap()).unwrap();
+
+                    let signature = signer.sign(&tx_bytes).await.unwrap();
+                    let body = json!({"signature": Base64::encode(signature.as_ref())}).to_string();
+                    tokio::time::sleep(delay).await;
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+
+        url
+    }
+
+    #[tokio::test]
+    async fn test_keypair_signer_signs_the_tx_intent() {
+        let keypair = new_keypair();
+        let expected_address = SuiAddress::from(&keypair.public());
+        let tx_data = new_tx(expected_address);
+        let expected = Signature::new_secure(
+            &IntentMessage::new(Intent::sui_transaction(), tx_data.clone()),
+            &keypair,
+        );
+
+        let signer = KeypairSigner::new(keypair);
+        assert_eq!(signer.address(), expected_address);
+        let signature = signer.sign(&bcs::to_bytes(&tx_data).unwrap()).await.unwrap();
+        assert_eq!(signature, expected);
+    }
+
+    #[tokio::test]
+    async fn test_http_signer_round_trip() {
+        let service_signer = Arc::new(KeypairSigner::new(new_keypair()));
+        let url = new_mock_signer(service_signer.clone(), Duration::ZERO).await;
+
+        let tx_bytes = bcs::to_bytes(&new_tx(service_signer.address())).unwrap();
+        let signer = HttpBidSigner::new(url, service_signer.address());
+        assert_eq!(
+            signer.sign(&tx_bytes).await.unwrap(),
+            service_signer.sign(&tx_bytes).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_signer_refuses_slow_or_wrong_signatures() {
+        let service_signer = Arc::new(KeypairSigner::new(new_keypair()));
+        let tx_bytes = bcs::to_bytes(&new_tx(service_signer.address())).unwrap();
+
+        let slow_url = new_mock_signer(service_signer.clone(), Duration::from_millis(500)).await;
+        let signer = HttpBidSigner::new(slow_url, service_signer.address()).with_timeout(Duration::from_millis(50));
+        assert!(signer.sign(&tx_bytes).await.is_err());
+
+        // the service holds another key than the one of the bids
+        let url = new_mock_signer(service_signer, Duration::ZERO).await;
+        let signer = HttpBidSigner::new(url, SuiAddress::random_for_testing_only());
+        let error = signer.sign(&tx_bytes).await.unwrap_err();
+        assert!(error.to_string().contains("expected"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_sender_signer_picks_the_key_of_the_sender() {
+        let (first, second) = (KeypairSigner::new(new_keypair()), KeypairSigner::new(new_keypair()));
+        let (first_address, second_address) = (first.address(), second.address());
+
+        let signer = SenderBidSigner::new(vec![Arc::new(first), Arc::new(second)]);
+        assert_eq!(signer.address(), first_address);
+        let tx_bytes = bcs::to_bytes(&new_tx(second_address)).unwrap();
+        let signature = signer.sign(&tx_bytes).await.unwrap();
+        let public_key = PublicKey::try_from_bytes(signature.scheme(), signature.public_key_bytes()).unwrap();
+        assert_eq!(SuiAddress::from(&public_key), second_address);
+
+        let tx_bytes = bcs::to_bytes(&new_tx(SuiAddress::random_for_testing_only())).unwrap();
+        assert!(signer.sign(&tx_bytes).await.is_err());
+    }
+}
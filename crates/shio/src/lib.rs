@@ -1,3 +1,5 @@
+mod bid_signer;
+mod multi_conn;
 mod shio_collector;
 mod shio_conn;
 mod shio_executor;
@@ -139,24 +141,37 @@ pub const SHIO_GLOBAL_STATES: [(&str, u64); 32] = [
 pub const SHIO_FEED_URL: &str = "wss://rpc.getshio.com/feed";
 pub const SHIO_JSON_RPC_URL: &str = "https://rpc.getshio.com";
 
-pub use shio_collector::ShioCollector;
-pub use shio_executor::ShioExecutor;
-pub use shio_rpc_executor::ShioRPCExecutor;
+pub use bid_signer::{BidSigner, HttpBidSigner, KeypairSigner, SenderBidSigner, DEFAULT_SIGN_TIMEOUT};
+pub use shio_collector::{ItemClock, ShioCollector};
+pub use shio_conn::{Backoff, BidReply, BidRequest, ConnState, Keepalive};
+pub use shio_executor::{BidStats, ReplyCallback, ShioExecutor, DEFAULT_BID_ACK_TIMEOUT};
+pub use shio_rpc_executor::{LatencyObserver, ShioRPCExecutor, DEFAULT_MAX_ATTEMPT_TIMEOUT, DEFAULT_MAX_RETRIES};
 pub use types::*;
 
+// The bids are signed by `signer`, see `KeypairSigner` and `HttpBidSigner`.
+// `num_retries` bounds the reconnects in a row, `None` reconnects forever.
+// Bids go out on the first connected feed of `shio_feed_urls`, `SHIO_FEED_URL` if empty.
+// Every feed is pinged and reconnected on a stall as set in `keepalive`.
 pub async fn new_shio_collector_and_executor(
-    keypair: sui_types::crypto::SuiKeyPair,
-    shio_feed_url: Option<String>,
+    signer: std::sync::Arc<dyn BidSigner>,
+    shio_feed_urls: Vec<String>,
     num_retries: Option<u32>,
+    keepalive: Keepalive,
 ) -> (ShioCollector, ShioExecutor) {
-    let (bid_sender, shio_item_receiver) = shio_conn::new_shio_conn(
-        shio_feed_url.unwrap_or(SHIO_FEED_URL.to_string()),
-        num_retries.unwrap_or(3),
+    let shio_feed_urls = if shio_feed_urls.is_empty() {
+        vec![SHIO_FEED_URL.to_string()]
+    } else {
+        shio_feed_urls
+    };
+    let (bid_sender, shio_item_receiver, conn_state) = multi_conn::new_multi_shio_conn(
+        shio_feed_urls,
+        Backoff::default().with_max_retries(num_retries),
+        keepalive,
     )
     .await;
 
-    let executor = ShioExecutor::new(keypair, bid_sender).await;
-    let collector = ShioCollector::new(shio_item_receiver);
+    let executor = ShioExecutor::new(signer, bid_sender).await;
+    let collector = ShioCollector::new(shio_item_receiver, conn_state);
 
     (collector, executor)
 }
@@ -0,0 +1,301 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_channel::{Receiver, Sender};
+use eyre::eyre;
+use tokio::sync::watch;
+use tracing::warn;
+
+use crate::{
+    shio_conn::{new_shio_conn, Backoff, BidRequest, ConnState, Keepalive},
+    ShioItem,
+};
+
+// an item received again from another endpoint within this window is dropped
+const DEDUP_WINDOW: Duration = Duration::from_secs(10);
+
+/// Connects to every feed endpoint, see `new_shio_conn`, and merges them into a single connection:
+/// - the items of all endpoints go to one receiver, an item already received from another endpoint
+///   is dropped;
+/// - a bid goes out on the first connected endpoint in the order of `wss_urls`, the first one is the
+///   primary and the others its failovers;
+/// - the state is `Connected` while any endpoint is connected.
+pub async fn new_multi_shio_conn(
+    wss_urls: Vec<String>,
+    backoff: Backoff,
+    keepalive: Keepalive,
+) -> (Sender<BidRequest>, Receiver<ShioItem>, watch::Receiver<ConnState>) {
+    assert!(!wss_urls.is_empty(), "no shio feed url");
+
+    let (bid_sender, bid_receiver) = async_channel::unbounded();
+    let (shio_item_sender, shio_item_receiver) = async_channel::unbounded();
+    let (state_sender, state_receiver) = watch::channel(ConnState::Connecting { attempt: 0 });
+    let state_sender = Arc::new(state_sender);
+    let dedup = Arc::new(Mutex::new(ItemDedup::new(DEDUP_WINDOW)));
+
+    let mut conns = vec![];
+    for wss_url in wss_urls {
+        conns.push(new_shio_conn(wss_url, backoff, keepalive).await);
+    }
+    let states: Vec<_> = conns.iter().map(|(_, _, state)| state.clone()).collect();
+
+    for (_, items, mut state) in conns.iter().cloned() {
+        let shio_item_sender: Sender<ShioItem> = shio_item_sender.clone();
+        let dedup = dedup.clone();
+        tokio::spawn(async move {
+            while let Ok(item) = items.recv().await {
+                if !dedup.lock().unwrap().check_and_record(&item) {
+                    continue;
+                }
+                if shio_item_sender.send(item).await.is_err() {
+                    // nobody reads the feed anymore
+                    return;
+                }
+            }
+        });
+
+        let states = states.clone();
+        let state_sender = state_sender.clone();
+        tokio::spawn(async move {
+            loop {
+                state_sender.send_replace(merge_states(&states));
+                if state.changed().await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    let conns: Vec<_> = conns
+        .into_iter()
+        .map(|(bid_sender, _, state)| (bid_sender, state))
+        .collect();
+    tokio::spawn(async move {
+        let bid_receiver: Receiver<BidRequest> = bid_receiver;
+        while let Ok((bid, result)) = bid_receiver.recv().await {
+            match conns.iter().find(|(_, state)| *state.borrow() == ConnState::Connected) {
+                Some((bid_sender, _)) => {
+                    if bid_sender.send((bid, result)).await.is_err() {
+                        warn!("shio connection is gone, bid dropped");
+                    }
+                }
+                None => {
+                    let _ = result.send(Err(eyre!("no shio feed is connected")));
+                }
+            }
+        }
+    });
+
+    (bid_sender, shio_item_receiver, state_receiver)
+}
+
+fn merge_states(states: &[watch::Receiver<ConnState>]) -> ConnState {
+    let states: Vec<ConnState> = states.iter().map(|state| *state.borrow()).collect();
+    if states.contains(&ConnState::Connected) {
+        return ConnState::Connected;
+    }
+
+    // the most preferred endpoint that is still retried
+    states
+        .into_iter()
+        .find(|state| *state != ConnState::GaveUp)
+        .unwrap_or(ConnState::GaveUp)
+}
+
+type ItemKey = (String, String);
+
+/// Remembers the recently received items by type and tx digest. Items without a tx digest always pass.
+struct ItemDedup {
+    seen: HashMap<ItemKey, Instant>,
+    order: VecDeque<(Instant, ItemKey)>,
+    ttl: Duration,
+}
+
+impl ItemDedup {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            ttl,
+        }
+    }
+
+    /// Returns true if the item wasn't received within the window, and records it.
+    fn check_and_record(&mut self, item: &ShioItem) -> bool {
+        self.check_and_record_at(item, Instant::now())
+    }
+
+    fn check_and_record_at(&mut self, item: &ShioItem, now: Instant) -> bool {
+        let tx_digest = match item {
+            ShioItem::AuctionStarted { tx_digest, .. } | ShioItem::AuctionEnded { tx_digest, .. } => tx_digest,
+            ShioItem::Other { .. } | ShioItem::Dummy(_) => return true,
+        };
+
+        while let Some((seen_at, _)) = self.order.front() {
+            if now.saturating_duration_since(*seen_at) < self.ttl {
+                break;
+            }
+            let (_, key) = self.order.pop_front().unwrap();
+            self.seen.remove(&key);
+        }
+
+        let key = (item.type_name().to_string(), tx_digest.clone());
+        if self.seen.contains_key(&key) {
+            return false;
+        }
+        self.seen.insert(key.clone(), now);
+        self.order.push_back((now, key));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use tokio::{net::TcpListener, sync::oneshot};
+    use tokio_tungstenite::tungstenite::Message;
+
+    use super::*;
+
+    fn auction_started(tx_digest: &str) -> Value {
+        json!({"auctionStarted": {
+            "txDigest": tx_digest,
+            "gasPrice": 750,
+            "deadlineTimestampMs": 0,
+            "sideEffects": {"gasUsage": 0},
+        }})
+    }
+
+    // sends the items to the first connection and reports the numbers of the bids it reads.
+    // the connection is not accepted before `accept_after` fires, if given.
+    async fn new_mock_server(
+        items: Vec<Value>,
+        accept_after: Option<oneshot::Receiver<()>>,
+    ) -> (String, oneshot::Sender<()>, Receiver<u64>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (shutdown_sender, mut shutdown) = oneshot::channel();
+        let (bid_sender, bid_receiver) = async_channel::unbounded();
+
+        tokio::spawn(async move {
+            if let Some(accept_after) = accept_after {
+                accept_after.await.unwrap();
+            }
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            for item in items {
+                ws.send(Message::Text(item.to_string())).await.unwrap();
+            }
+            loop {
+                tokio::select! {
+                    _ = &mut shutdown => {
+                        ws.close(None).await.unwrap();
+                        return;
+                    }
+                    Some(Ok(Message::Text(text))) = ws.next() => {
+                        let bid = serde_json::from_str::<Value>(&text).unwrap()["bid"].as_u64().unwrap();
+                        bid_sender.send(bid).await.unwrap();
+                    }
+                }
+            }
+        });
+
+        (url, shutdown_sender, bid_receiver)
+    }
+
+    async fn send_bid(bid_sender: &Sender<BidRequest>, bid: u64) -> eyre::Result<()> {
+        let (result_sender, result) = oneshot::channel();
+        bid_sender.send((json!({"bid": bid}), result_sender)).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), result)
+            .await
+            .unwrap()
+            .unwrap()
+            .map(drop)
+    }
+
+    async fn recv_bid(bids: &Receiver<u64>) -> u64 {
+        tokio::time::timeout(Duration::from_secs(5), bids.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    async fn wait_for(state: &mut watch::Receiver<ConnState>, f: impl FnMut(&ConnState) -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), state.wait_for(f))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_items_are_deduplicated() {
+        let (url1, _shutdown1, _) = new_mock_server(vec![auction_started("a"), auction_started("b")], None).await;
+        let (url2, _shutdown2, _) = new_mock_server(vec![auction_started("b"), auction_started("c")], None).await;
+
+        let (_bid_sender, items, _state) =
+            new_multi_shio_conn(vec![url1, url2], Backoff::default(), Keepalive::default()).await;
+
+        let mut tx_digests = vec![];
+        for _ in 0..3 {
+            let item = tokio::time::timeout(Duration::from_secs(5), items.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            tx_digests.push(item.tx_digest().to_string());
+        }
+        tx_digests.sort();
+        assert_eq!(tx_digests, vec!["a", "b", "c"]);
+
+        // the duplicate of "b" never shows up
+        assert!(tokio::time::timeout(Duration::from_millis(200), items.recv())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bids_fail_over_to_the_next_endpoint() {
+        // the secondary only connects once the primary got its bid
+        let (start_secondary, accept_after) = oneshot::channel();
+        let (primary, shutdown_primary, primary_bids) = new_mock_server(vec![], None).await;
+        let (secondary, _shutdown_secondary, secondary_bids) = new_mock_server(vec![], Some(accept_after)).await;
+
+        let (bid_sender, _items, mut state) =
+            new_multi_shio_conn(vec![primary, secondary], Backoff::default(), Keepalive::default()).await;
+        wait_for(&mut state, |s| *s == ConnState::Connected).await;
+
+        send_bid(&bid_sender, 1).await.unwrap();
+        assert_eq!(recv_bid(&primary_bids).await, 1);
+
+        // the primary goes away, and no bid can go out until the secondary is connected
+        shutdown_primary.send(()).unwrap();
+        wait_for(&mut state, |s| *s != ConnState::Connected).await;
+        assert!(send_bid(&bid_sender, 2).await.is_err());
+
+        start_secondary.send(()).unwrap();
+        wait_for(&mut state, |s| *s == ConnState::Connected).await;
+        send_bid(&bid_sender, 3).await.unwrap();
+        assert_eq!(recv_bid(&secondary_bids).await, 3);
+        assert!(primary_bids.is_empty());
+    }
+
+    #[test]
+    fn test_item_dedup_window() {
+        let mut dedup = ItemDedup::new(Duration::from_secs(10));
+        let now = Instant::now();
+        let item = ShioItem::from(auction_started("a"));
+        assert!(matches!(item, ShioItem::AuctionStarted { .. }));
+
+        assert!(dedup.check_and_record_at(&item, now));
+        assert!(!dedup.check_and_record_at(&item, now + Duration::from_secs(1)));
+        assert!(dedup.check_and_record_at(&item, now + Duration::from_secs(10)));
+
+        // items without a tx digest are never deduplicated
+        let dummy = ShioItem::Dummy(json!({"hello": 1}));
+        assert!(dedup.check_and_record_at(&dummy, now));
+        assert!(dedup.check_and_record_at(&dummy, now));
+    }
+}
@@ -1,25 +1,69 @@
-use crate::shio_conn::new_shio_conn;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::shio_conn::{new_shio_conn, Backoff, ConnState, Keepalive};
 use crate::types::ShioItem;
 use async_channel::Receiver;
 use burberry::{async_trait, Collector, CollectorStream};
 use eyre::Result;
+use tokio::sync::watch;
 use tracing::warn;
 
 pub struct ShioCollector {
     receiver: Receiver<ShioItem>,
+    conn_state: watch::Receiver<ConnState>,
+    item_clock: ItemClock,
+}
+
+/// When the collector last received an item, shared with whoever reports on the feed.
+#[derive(Debug, Clone, Default)]
+pub struct ItemClock(Arc<Mutex<Option<Instant>>>);
+
+impl ItemClock {
+    fn tick(&self) {
+        *self.0.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// `None` until the first item.
+    pub fn last_item_age(&self) -> Option<Duration> {
+        self.0.lock().unwrap().map(|last_item| last_item.elapsed())
+    }
 }
 
 // Only one connection to the ws server
 impl ShioCollector {
     // Only one connection to the ws server
+    // `num_retries` bounds the reconnects in a row, `None` reconnects forever
     pub async fn new_without_executor(wss_url: String, num_retries: Option<u32>) -> Self {
         warn!("only reading from shio feed, not sending any bids");
-        let (_, receiver) = new_shio_conn(wss_url, num_retries.unwrap_or(3)).await;
-        Self { receiver }
+        let backoff = Backoff::default().with_max_retries(num_retries);
+        let (_, receiver, conn_state) = new_shio_conn(wss_url, backoff, Keepalive::default()).await;
+        Self::new(receiver, conn_state)
+    }
+
+    pub fn new(receiver: Receiver<ShioItem>, conn_state: watch::Receiver<ConnState>) -> Self {
+        Self {
+            receiver,
+            conn_state,
+            item_clock: ItemClock::default(),
+        }
+    }
+
+    /// The state of the connection to the ws server, to log and alert on disconnects.
+    pub fn conn_state(&self) -> watch::Receiver<ConnState> {
+        self.conn_state.clone()
+    }
+
+    /// The time since the last item, `None` before the first one.
+    pub fn last_item_age(&self) -> Option<Duration> {
+        self.item_clock.last_item_age()
     }
 
-    pub fn new(receiver: Receiver<ShioItem>) -> Self {
-        Self { receiver }
+    /// To read `last_item_age` after the collector is moved into the engine.
+    pub fn item_clock(&self) -> ItemClock {
+        self.item_clock.clone()
     }
 }
 
@@ -32,6 +76,7 @@ impl Collector<ShioItem> for ShioCollector {
     async fn get_event_stream(&self) -> Result<CollectorStream<'_, ShioItem>> {
         let stream = async_stream::stream! {
             while let Ok(item) = self.receiver.clone().recv().await {
+                self.item_clock.tick();
                 yield item;
             }
 
@@ -66,7 +111,7 @@ mod tests {
                 ShioItem::AuctionEnded { .. } => {
                     println!("{:#?}", item.type_name());
                 }
-                ShioItem::Dummy(_) => {
+                ShioItem::Other { .. } | ShioItem::Dummy(_) => {
                     println!("{:#?}", item);
                 }
             }
@@ -1,53 +1,180 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
 use async_channel::{Receiver, Sender};
+use eyre::{eyre, Result};
 use futures::{SinkExt, StreamExt};
 use serde_json::Value;
+use tokio::sync::{oneshot, watch};
 use tokio_tungstenite::tungstenite::Message;
-use tracing::error;
+use tracing::{debug, error, info, warn};
 
 use crate::ShioItem;
 
-pub async fn new_shio_conn(wss_url: String, num_retries: u32) -> (Sender<Value>, Receiver<ShioItem>) {
+/// A bid, and where to report whether it was written to the ws server. A written bid comes with the
+/// receiver of the server's reply to it, see `BidReply`.
+pub type BidRequest = (Value, oneshot::Sender<Result<oneshot::Receiver<BidReply>>>);
+
+/// The reply of the ws server to a bid. Bids carry no id of their own, so a reply is matched to a bid by
+/// the `oppTxDigest` it names, and an error that names none to the oldest bid not replied to yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BidReply {
+    Acked,
+    // the reason the server gave
+    Rejected(String),
+}
+
+// the written bids not replied to yet, with their `oppTxDigest`, oldest first
+type PendingBids = VecDeque<(Option<String>, oneshot::Sender<BidReply>)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnState {
+    // `attempt` counts the failed connects since the last connection, 0 for the first try
+    Connecting { attempt: u32 },
+    Connected,
+    Disconnected,
+    // `Backoff::max_retries` was exceeded, the connection is not retried anymore
+    GaveUp,
+}
+
+/// Exponential backoff with jitter between reconnects.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    // `None` retries forever
+    pub max_retries: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+            max_retries: None,
+        }
+    }
+}
+
+impl Backoff {
+    pub fn with_max_retries(mut self, max_retries: Option<u32>) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// `initial * 2^attempt`, capped at `max`, of which up to a quarter is randomly taken off so that
+    /// clients don't reconnect in lockstep.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.initial.saturating_mul(1 << attempt.min(16)).min(self.max);
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        delay - delay.mul_f64((nanos % 1000) as f64 / 4000.0)
+    }
+}
+
+/// Pings the ws server, and recycles a connection on which nothing arrives for too long. A connection can
+/// go quiet without the TCP connection dropping, and would then wait for the next frame forever.
+#[derive(Debug, Clone, Copy)]
+pub struct Keepalive {
+    pub ping_interval: Duration,
+    // the connection is recycled when no frame (items, pongs, ...) arrives for this long
+    pub stall_timeout: Duration,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(5),
+            stall_timeout: Duration::from_secs(15),
+        }
+    }
+}
+
+/// Connects to the shio feed, and keeps reconnecting when the connection drops or stalls. The feed needs no
+/// authentication or subscription, a new connection streams items right away.
+///
+/// Bids are only written while connected, a bid sent while disconnected fails. A bid is done once written,
+/// the replies of the server are handed to the bids as they come, and bids still pending when the
+/// connection drops never get one. The task ends when all receivers of items are dropped, or when
+/// `backoff.max_retries` is exceeded.
+pub async fn new_shio_conn(
+    wss_url: String,
+    backoff: Backoff,
+    keepalive: Keepalive,
+) -> (Sender<BidRequest>, Receiver<ShioItem>, watch::Receiver<ConnState>) {
     let (bid_sender, bid_receiver) = async_channel::unbounded();
     let (shio_item_sender, shio_item_receiver) = async_channel::unbounded();
+    let (state_sender, state_receiver) = watch::channel(ConnState::Connecting { attempt: 0 });
 
     tokio::spawn(async move {
-        let bid_receiver: Receiver<Value> = bid_receiver;
+        let bid_receiver: Receiver<BidRequest> = bid_receiver;
         let shio_item_sender: Sender<ShioItem> = shio_item_sender;
         let wss_url = wss_url;
 
-        let mut retry_count = 0;
+        let mut attempt = 0;
+        let mut pending = PendingBids::new();
 
         loop {
+            state_sender.send_replace(ConnState::Connecting { attempt });
             let (mut wss_stream, _) = match tokio_tungstenite::connect_async(&wss_url).await {
                 Ok(r) => {
-                    retry_count = 0;
+                    attempt = 0;
                     r
                 }
                 Err(e) => {
                     error!("fail to connect to ws server: {e:#}");
-                    if retry_count == num_retries {
-                        panic!("fail to connect to ws server after {} retries", num_retries);
+                    if backoff.max_retries.is_some_and(|max_retries| attempt >= max_retries) {
+                        error!("fail to connect to ws server after {} retries", attempt);
+                        state_sender.send_replace(ConnState::GaveUp);
+                        reject_bids(&bid_receiver);
+                        return;
                     }
 
-                    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-                    retry_count += 1;
+                    wait_rejecting_bids(backoff.delay(attempt), &bid_receiver).await;
+                    attempt += 1;
                     continue;
                 }
             };
+            info!("connected to ws server");
+            state_sender.send_replace(ConnState::Connected);
+
+            let mut ping = tokio::time::interval_at(
+                (Instant::now() + keepalive.ping_interval).into(),
+                keepalive.ping_interval,
+            );
+            let mut last_frame = Instant::now();
 
             'connected: loop {
-                // either receive from bid_receiver or wss_stream
+                // either receive from bid_receiver or wss_stream, or keep the connection alive
                 tokio::select! {
-                    Ok(bid) = bid_receiver.recv() => {
+                    _ = ping.tick() => {
+                        if let Err(e) = wss_stream.send(Message::Ping(vec![])).await {
+                            error!("fail to send ping to ws server: {e:#}");
+                            break 'connected;
+                        }
+                    }
+                    _ = tokio::time::sleep_until((last_frame + keepalive.stall_timeout).into()) => {
+                        warn!(stall_timeout = ?keepalive.stall_timeout, "ws server went quiet, reconnecting");
+                        break 'connected;
+                    }
+                    Ok((bid, result)) = bid_receiver.recv() => {
                         let msg = Message::Text(bid.to_string());
                         if let Err(e) = wss_stream.send(msg).await {
                             error!("fail to send message to ws server: {e:#}");
+                            let _ = result.send(Err(eyre!("fail to send bid to shio: {e:#}")));
                             break 'connected;
                         }
+                        let (reply_sender, reply) = oneshot::channel();
+                        prune_abandoned(&mut pending);
+                        let opp_tx_digest = bid["oppTxDigest"].as_str().map(str::to_string);
+                        pending.push_back((opp_tx_digest, reply_sender));
+                        let _ = result.send(Ok(reply));
                     }
-                    Some(msg) = wss_stream.next() => {
+                    msg = wss_stream.next() => {
+                        last_frame = Instant::now();
                         match msg {
-                            Ok(Message::Text(text)) => {
+                            Some(Ok(Message::Text(text))) => {
                                 let value = match serde_json::from_str::<Value>(&text) {
                                     Ok(v) => v,
                                     Err(e) => {
@@ -55,27 +182,347 @@ pub async fn new_shio_conn(wss_url: String, num_retries: u32) -> (Sender<Value>,
                                         continue;
                                     }
                                 };
-                                shio_item_sender.send(ShioItem::from(value)).await.unwrap();
+                                let item = ShioItem::from(value);
+                                if matches!(item, ShioItem::Other { .. } | ShioItem::Dummy(_)) {
+                                    if let Some((reply_sender, reply)) = take_reply(&mut pending, &item) {
+                                        debug!(frame = %text, "reply to a bid from ws server");
+                                        let _ = reply_sender.send(reply);
+                                        continue;
+                                    }
+                                    warn!(frame = %text, "unexpected frame from ws server");
+                                }
+                                if shio_item_sender.send(item).await.is_err() {
+                                    // nobody reads the feed anymore
+                                    return;
+                                }
                             }
-                            Ok(Message::Ping(val)) => {
+                            Some(Ok(Message::Ping(val))) => {
                                 if let Err(e) = wss_stream.send(Message::Pong(val)).await {
                                     error!("Failed to send pong: {}", e);
                                     break 'connected;
                                 }
                             }
-                            Ok(Message::Close(_)) | Ok(Message::Frame(_)) | Ok(Message::Pong(_)) | Ok(Message::Binary(_)) => {
-                                panic!("unexpected websocket message: {:?}", msg);
+                            Some(Ok(Message::Close(frame))) => {
+                                warn!(?frame, "ws server closed the connection");
+                                break 'connected;
+                            }
+                            // only resets the stall timer
+                            Some(Ok(Message::Pong(_))) => {}
+                            Some(Ok(msg @ (Message::Frame(_) | Message::Binary(_)))) => {
+                                warn!("unexpected websocket message: {:?}", msg);
                             }
-                            Err(e) => {
+                            Some(Err(e)) => {
                                 error!("error receiving websocket message: {:?}", e);
                                 break 'connected;
                             }
+                            None => {
+                                warn!("ws stream ended");
+                                break 'connected;
+                            }
                         }
                     }
                 }
             }
+
+            state_sender.send_replace(ConnState::Disconnected);
+            // bids queued for the dropped connection are stale by the time it is back, and the replies
+            // to the written ones are gone with it
+            reject_bids(&bid_receiver);
+            pending.clear();
         }
     });
 
-    (bid_sender, shio_item_receiver)
+    (bid_sender, shio_item_receiver, state_receiver)
+}
+
+// A frame that isn't an auction item is the reply to a pending bid if it names the `oppTxDigest` of the
+// bid, or if it is an error.
+fn take_reply(pending: &mut PendingBids, item: &ShioItem) -> Option<(oneshot::Sender<BidReply>, BidReply)> {
+    prune_abandoned(pending);
+
+    let error = frame_field(item, "error").map(|error| match error {
+        Value::String(reason) => reason.clone(),
+        reason => reason.to_string(),
+    });
+
+    let index = match frame_field(item, "oppTxDigest").and_then(Value::as_str) {
+        Some(opp_tx_digest) => pending
+            .iter()
+            .position(|(digest, _)| digest.as_deref() == Some(opp_tx_digest))?,
+        None if error.is_some() && !pending.is_empty() => 0,
+        None => return None,
+    };
+    let (_, reply_sender) = pending.remove(index)?;
+    Some((reply_sender, error.map_or(BidReply::Acked, BidReply::Rejected)))
+}
+
+// drops the bids whose sender stopped waiting for the reply, e.g. timed out
+fn prune_abandoned(pending: &mut PendingBids) {
+    pending.retain(|(_, reply_sender)| !reply_sender.is_closed());
+}
+
+fn frame_field<'a>(item: &'a ShioItem, name: &str) -> Option<&'a Value> {
+    match item {
+        // the kind may be the field itself, e.g. `{"error": "bid too late"}`
+        ShioItem::Other { kind, raw } if kind == name => Some(raw),
+        ShioItem::Other { raw, .. } => raw.get(name),
+        ShioItem::Dummy(frame) => frame.get(name),
+        _ => None,
+    }
+}
+
+fn reject_bids(bid_receiver: &Receiver<BidRequest>) {
+    while let Ok((_, result)) = bid_receiver.try_recv() {
+        let _ = result.send(Err(eyre!("shio feed is disconnected")));
+    }
+}
+
+async fn wait_rejecting_bids(delay: Duration, bid_receiver: &Receiver<BidRequest>) {
+    let sleep = tokio::time::sleep(delay);
+    tokio::pin!(sleep);
+
+    loop {
+        tokio::select! {
+            _ = &mut sleep => return,
+            Ok((_, result)) = bid_receiver.recv() => {
+                let _ = result.send(Err(eyre!("shio feed is disconnected")));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn fast_backoff() -> Backoff {
+        Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+            max_retries: None,
+        }
+    }
+
+    async fn write_bid(bid_sender: &Sender<BidRequest>, bid: Value) -> Result<oneshot::Receiver<BidReply>> {
+        let (result_sender, result) = oneshot::channel();
+        bid_sender.send((bid, result_sender)).await.unwrap();
+        result.await.unwrap()
+    }
+
+    async fn send_bid(bid_sender: &Sender<BidRequest>) -> Result<()> {
+        write_bid(bid_sender, json!({"bid": 1})).await.map(drop)
+    }
+
+    async fn wait_for(state: &mut watch::Receiver<ConnState>, f: impl FnMut(&ConnState) -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), state.wait_for(f))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff = Backoff::default();
+        for attempt in [0, 1, 5, 100] {
+            let upper = backoff.initial.saturating_mul(1 << attempt.min(16)).min(backoff.max);
+            let delay = backoff.delay(attempt);
+            assert!(delay <= upper && delay >= upper.mul_f64(0.75), "{attempt}: {delay:?}");
+        }
+        assert!(backoff.delay(100) <= backoff.max);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_after_server_closes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        // every connection gets one item, then the first one is closed mid-stream
+        tokio::spawn(async move {
+            for i in 0.. {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                ws.send(Message::Text(json!({"item": i}).to_string())).await.unwrap();
+                if i == 0 {
+                    ws.close(None).await.unwrap();
+                } else {
+                    // keep the connection open
+                    tokio::spawn(async move { while ws.next().await.is_some() {} });
+                }
+            }
+        });
+
+        let (_bid_sender, items, mut state) = new_shio_conn(url, fast_backoff(), Keepalive::default()).await;
+
+        let item = tokio::time::timeout(Duration::from_secs(5), items.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(item, ShioItem::Other { kind, raw } if kind == "item" && raw == json!(0)));
+        let item = tokio::time::timeout(Duration::from_secs(5), items.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(item, ShioItem::Other { kind, raw } if kind == "item" && raw == json!(1)));
+        wait_for(&mut state, |s| *s == ConnState::Connected).await;
+    }
+
+    #[tokio::test]
+    async fn test_bids_fail_while_disconnected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        let (bid_sender, _items, mut state) = new_shio_conn(url, fast_backoff(), Keepalive::default()).await;
+
+        // the first connection reads one bid then closes, later connections are refused
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        wait_for(&mut state, |s| *s == ConnState::Connected).await;
+        send_bid(&bid_sender).await.unwrap();
+        assert!(matches!(ws.next().await, Some(Ok(Message::Text(_)))));
+        ws.close(None).await.unwrap();
+        drop(listener);
+
+        // disconnected, then reconnecting in vain
+        wait_for(&mut state, |s| *s != ConnState::Connected).await;
+        assert!(send_bid(&bid_sender).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bids_dont_wait_for_replies() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (replies_sender, replies) = async_channel::unbounded::<&str>();
+
+        // the server replies once both bids are written, the second bid first
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            for _ in 0..2 {
+                assert!(matches!(ws.next().await, Some(Ok(Message::Text(_)))));
+            }
+            while let Ok(frame) = replies.recv().await {
+                ws.send(Message::Text(frame.to_string())).await.unwrap();
+            }
+            while ws.next().await.is_some() {}
+        });
+
+        let (bid_sender, items, mut state) = new_shio_conn(url, fast_backoff(), Keepalive::default()).await;
+        wait_for(&mut state, |s| *s == ConnState::Connected).await;
+
+        // both bids are done once written
+        let (first, second) = tokio::join!(
+            write_bid(&bid_sender, json!({"oppTxDigest": "a"})),
+            write_bid(&bid_sender, json!({"oppTxDigest": "b"}))
+        );
+        let (mut first, mut second) = (first.unwrap(), second.unwrap());
+        assert!(first.try_recv().is_err() && second.try_recv().is_err());
+
+        for frame in [
+            r#"{"item":0}"#,
+            r#"{"bidReceived":{"oppTxDigest":"b"}}"#,
+            r#"{"error":"bid too late"}"#,
+        ] {
+            replies_sender.send(frame).await.unwrap();
+        }
+        assert_eq!(second.await.unwrap(), BidReply::Acked);
+        // an error that names no bid is the reply to the oldest one
+        assert_eq!(first.await.unwrap(), BidReply::Rejected("bid too late".to_string()));
+
+        // the frames that aren't replies still come through
+        let item = tokio::time::timeout(Duration::from_secs(5), items.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(item.type_name(), "item");
+        assert!(items.is_empty());
+    }
+
+    #[test]
+    fn test_take_reply() {
+        let mut pending = PendingBids::new();
+        let mut replies = vec![];
+        for opp_tx_digest in ["a", "b", "c"] {
+            let (reply_sender, reply) = oneshot::channel();
+            pending.push_back((Some(opp_tx_digest.to_string()), reply_sender));
+            replies.push(reply);
+        }
+        // "a" stopped waiting
+        drop(replies.remove(0));
+
+        let frame = |value: Value| ShioItem::from(value);
+        // not a reply, nor a reply to an unknown bid
+        assert!(take_reply(&mut pending, &frame(json!({"item": 0}))).is_none());
+        assert!(take_reply(&mut pending, &frame(json!({"bidReceived": {"oppTxDigest": "x"}}))).is_none());
+
+        let (_, reply) = take_reply(&mut pending, &frame(json!({"oppTxDigest": "c", "error": {"code": 3}}))).unwrap();
+        assert_eq!(reply, BidReply::Rejected(r#"{"code":3}"#.to_string()));
+        let (_, reply) = take_reply(&mut pending, &frame(json!({"error": "bid too late"}))).unwrap();
+        assert_eq!(reply, BidReply::Rejected("bid too late".to_string()));
+        assert!(pending.is_empty());
+        assert!(take_reply(&mut pending, &frame(json!({"error": "bid too late"}))).is_none());
+    }
+
+    // the server sends one item on every connection then goes silent, and answers the pings if asked to.
+    // returns the number of connections in 500ms.
+    async fn test_keepalive(answer_pings: bool) -> usize {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (accepted_sender, accepted) = async_channel::unbounded();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                accepted_sender.send(()).await.unwrap();
+                ws.send(Message::Text(json!({"item": 0}).to_string())).await.unwrap();
+                tokio::spawn(async move {
+                    if answer_pings {
+                        // reading replies to the pings
+                        while ws.next().await.is_some() {}
+                    } else {
+                        tokio::time::sleep(Duration::from_secs(60)).await;
+                        drop(ws);
+                    }
+                });
+            }
+        });
+
+        let keepalive = Keepalive {
+            ping_interval: Duration::from_millis(50),
+            stall_timeout: Duration::from_millis(200),
+        };
+        let (_bid_sender, _items, _state) = new_shio_conn(url, fast_backoff(), keepalive).await;
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        accepted.len()
+    }
+
+    #[tokio::test]
+    async fn test_stalled_connection_is_recycled() {
+        // one item at connect then silence, so the connection is recycled every stall_timeout
+        assert!(test_keepalive(false).await >= 2);
+    }
+
+    #[tokio::test]
+    async fn test_answered_pings_keep_the_connection() {
+        // no more items after the first one, but the pongs keep the connection alive
+        assert_eq!(test_keepalive(true).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_give_up_after_max_retries() {
+        // nothing listens on the port
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let (_bid_sender, items, mut state) =
+            new_shio_conn(url, fast_backoff().with_max_retries(Some(2)), Keepalive::default()).await;
+
+        wait_for(&mut state, |s| *s == ConnState::GaveUp).await;
+        assert!(items.recv().await.is_err());
+    }
 }
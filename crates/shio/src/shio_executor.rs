@@ -1,23 +1,110 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
 use async_channel::Sender;
 use burberry::{async_trait, Executor};
 use eyre::Result;
-use fastcrypto::{encoding::Base64, hash::HashFunction};
+use fastcrypto::encoding::Base64;
 use serde_json::{json, Value};
-use shared_crypto::intent::{Intent, IntentMessage};
-use sui_types::{
-    crypto::{Signer, SuiKeyPair},
-    digests::TransactionDigest,
-    transaction::TransactionData,
-};
+use sui_types::{digests::TransactionDigest, transaction::TransactionData};
+use tokio::sync::oneshot;
+use tracing::{debug, warn};
+
+use crate::bid_signer::BidSigner;
+use crate::shio_conn::{BidReply, BidRequest};
+
+// a bid the ws server hasn't replied to by then is reported as unacknowledged
+pub const DEFAULT_BID_ACK_TIMEOUT: Duration = Duration::from_millis(150);
+
+/// Called with the reply of the ws server to a bid on `oppTxDigest`, `None` if it didn't reply in time.
+pub type ReplyCallback = Arc<dyn Fn(TransactionDigest, Option<BidReply>) + Send + Sync>;
 
 pub struct ShioExecutor {
-    keypair: SuiKeyPair,
-    bid_sender: Sender<Value>,
+    signer: Arc<dyn BidSigner>,
+    bid_sender: Sender<BidRequest>,
+    ack_timeout: Duration,
+    on_reply: Option<ReplyCallback>,
+    stats: Arc<BidStats>,
 }
 
 impl ShioExecutor {
-    pub async fn new(keypair: SuiKeyPair, bid_sender: Sender<Value>) -> Self {
-        Self { keypair, bid_sender }
+    pub async fn new(signer: Arc<dyn BidSigner>, bid_sender: Sender<BidRequest>) -> Self {
+        Self {
+            signer,
+            bid_sender,
+            ack_timeout: DEFAULT_BID_ACK_TIMEOUT,
+            on_reply: None,
+            stats: Arc::new(BidStats::default()),
+        }
+    }
+
+    pub fn with_ack_timeout(mut self, ack_timeout: Duration) -> Self {
+        self.ack_timeout = ack_timeout;
+        self
+    }
+
+    pub fn with_reply_callback(mut self, on_reply: ReplyCallback) -> Self {
+        self.on_reply = Some(on_reply);
+        self
+    }
+
+    /// Shared with the executor, so that the counters can be read after it's moved into the engine.
+    pub fn stats(&self) -> Arc<BidStats> {
+        self.stats.clone()
+    }
+
+    /// Submits the bid, done once it is written to the ws server. The reply of the server is waited for in
+    /// the background, up to `ack_timeout`, and counted in the stats.
+    pub async fn submit_bid(
+        &self,
+        tx_data: TransactionData,
+        bid_amount: u64,
+        opp_tx_digest: TransactionDigest,
+    ) -> Result<()> {
+        let bid = self.encode_bid(tx_data, bid_amount, opp_tx_digest).await?;
+
+        // fails if the bid isn't written to the ws server, e.g. while the feed is reconnecting
+        let (result_sender, result) = oneshot::channel();
+        self.bid_sender.send((bid, result_sender)).await?;
+        let reply = match result.await? {
+            Ok(reply) => reply,
+            Err(error) => {
+                self.stats.failed.fetch_add(1, Ordering::Relaxed);
+                return Err(error);
+            }
+        };
+        self.stats.submitted.fetch_add(1, Ordering::Relaxed);
+
+        let (ack_timeout, on_reply, stats) = (self.ack_timeout, self.on_reply.clone(), self.stats.clone());
+        tokio::spawn(async move {
+            // the reply is dropped unanswered when the connection drops
+            let reply = tokio::time::timeout(ack_timeout, reply).await.ok().and_then(Result::ok);
+            match &reply {
+                Some(BidReply::Acked) => {
+                    debug!(%opp_tx_digest, "shio bid acknowledged");
+                    stats.acked.fetch_add(1, Ordering::Relaxed)
+                }
+                Some(BidReply::Rejected(reason)) => {
+                    warn!(%opp_tx_digest, %reason, "shio bid rejected");
+                    stats.rejected.fetch_add(1, Ordering::Relaxed)
+                }
+                None => {
+                    debug!(%opp_tx_digest, ?ack_timeout, "shio bid unacknowledged");
+                    stats.timed_out.fetch_add(1, Ordering::Relaxed)
+                }
+            };
+            if let Some(on_reply) = on_reply {
+                on_reply(opp_tx_digest, reply);
+            }
+        });
+
+        Ok(())
     }
 
     pub async fn encode_bid(
@@ -28,18 +115,7 @@ impl ShioExecutor {
     ) -> Result<Value> {
         let tx_bytes = bcs::to_bytes(&tx_data)?;
         let tx_b64 = Base64::from_bytes(&tx_bytes).encoded();
-
-        let sig = {
-            let intent_msg = IntentMessage::new(Intent::sui_transaction(), tx_data);
-            let raw_tx = bcs::to_bytes(&intent_msg)?;
-            let digest = {
-                let mut hasher = sui_types::crypto::DefaultHash::default();
-                hasher.update(raw_tx.clone());
-                hasher.finalize().digest
-            };
-
-            self.keypair.sign(&digest)
-        };
+        let sig = self.signer.sign(&tx_bytes).await?;
 
         Ok(json!({
             "oppTxDigest": opp_tx_digest.base58_encode(),
@@ -51,17 +127,173 @@ impl ShioExecutor {
 }
 
 #[async_trait]
-impl Executor<(TransactionData, u64, TransactionDigest)> for ShioExecutor {
+impl Executor<(TransactionData, u64, TransactionDigest, u64)> for ShioExecutor {
     fn name(&self) -> &str {
         "ShioExecutor"
     }
 
     async fn execute(
         &self,
-        (tx_data, bid_amount, opp_tx_digest): (TransactionData, u64, TransactionDigest),
+        // a bid is written right away, the deadline doesn't shorten it
+        (tx_data, bid_amount, opp_tx_digest, _deadline_ms): (TransactionData, u64, TransactionDigest, u64),
     ) -> Result<()> {
-        let bid = self.encode_bid(tx_data, bid_amount, opp_tx_digest).await?;
-        self.bid_sender.send(bid).await?;
-        Ok(())
+        self.submit_bid(tx_data, bid_amount, opp_tx_digest).await
+    }
+}
+
+/// Bids submitted through the ws server, by whether they were written and how the server replied to the
+/// written ones.
+#[derive(Debug, Default)]
+pub struct BidStats {
+    pub submitted: AtomicU64,
+    pub acked: AtomicU64,
+    pub rejected: AtomicU64,
+    // no reply within the ack timeout
+    pub timed_out: AtomicU64,
+    // not written, e.g. no feed is connected
+    pub failed: AtomicU64,
+}
+
+impl fmt::Display for BidStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "submitted={}, acked={}, rejected={}, timed_out={}, failed={}",
+            self.submitted.load(Ordering::Relaxed),
+            self.acked.load(Ordering::Relaxed),
+            self.rejected.load(Ordering::Relaxed),
+            self.timed_out.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::{SinkExt, StreamExt};
+    use sui_types::{
+        base_types::{random_object_ref, SuiAddress},
+        crypto::{get_key_pair, AccountKeyPair, SuiKeyPair},
+    };
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::Message;
+
+    use super::*;
+    use crate::{
+        bid_signer::KeypairSigner,
+        shio_conn::{new_shio_conn, Backoff, ConnState, Keepalive},
+    };
+
+    fn new_bid_tx() -> TransactionData {
+        let sender = SuiAddress::random_for_testing_only();
+        TransactionData::new_transfer_sui(sender, sender, None, random_object_ref(), 10_000_000, 750)
+    }
+
+    // reads the bids and answers each of them with `reply`, if any
+    async fn new_mock_server(reply: fn(&Value) -> Option<Value>) -> (String, async_channel::Receiver<Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (bids_sender, bids) = async_channel::unbounded();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            while let Some(Ok(msg)) = ws.next().await {
+                if let Message::Text(text) = msg {
+                    let bid = serde_json::from_str::<Value>(&text).unwrap();
+                    if let Some(reply) = reply(&bid) {
+                        ws.send(Message::Text(reply.to_string())).await.unwrap();
+                    }
+                    bids_sender.send(bid).await.unwrap();
+                }
+            }
+        });
+
+        (url, bids)
+    }
+
+    // an executor on the mock server, and the replies to its bids as the callback gets them
+    async fn new_executor(
+        url: String,
+        ack_timeout: Duration,
+    ) -> (
+        ShioExecutor,
+        async_channel::Receiver<(TransactionDigest, Option<BidReply>)>,
+    ) {
+        // the mock server sends no items, so the item receiver can be dropped
+        let (bid_sender, _, mut state) = new_shio_conn(url, Backoff::default(), Keepalive::default()).await;
+        state.wait_for(|s| *s == ConnState::Connected).await.unwrap();
+        let (_, keypair): (_, AccountKeyPair) = get_key_pair();
+        let (replies_sender, replies) = async_channel::unbounded();
+
+        let executor = ShioExecutor::new(Arc::new(KeypairSigner::new(SuiKeyPair::Ed25519(keypair))), bid_sender)
+            .await
+            .with_ack_timeout(ack_timeout)
+            .with_reply_callback(Arc::new(move |opp_tx_digest, reply| {
+                replies_sender.try_send((opp_tx_digest, reply)).unwrap();
+            }));
+        (executor, replies)
+    }
+
+    async fn recv<T>(receiver: &async_channel::Receiver<T>) -> T {
+        tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_bids_time_out() {
+        let (url, bids) = new_mock_server(|_| None).await;
+        let (executor, replies) = new_executor(url, Duration::from_millis(50)).await;
+
+        let opp_tx_digest = TransactionDigest::random();
+        for _ in 0..2 {
+            executor.submit_bid(new_bid_tx(), 1, opp_tx_digest).await.unwrap();
+        }
+        for _ in 0..2 {
+            let bid = recv(&bids).await;
+            // the bid as the shio server expects it, nothing more
+            let mut keys = bid.as_object().unwrap().keys().collect::<Vec<_>>();
+            keys.sort();
+            assert_eq!(keys, ["bidAmount", "oppTxDigest", "sig", "txData"]);
+            assert_eq!(bid["oppTxDigest"], opp_tx_digest.base58_encode());
+        }
+        for _ in 0..2 {
+            assert_eq!(recv(&replies).await, (opp_tx_digest, None));
+        }
+        assert_eq!(
+            executor.stats().to_string(),
+            "submitted=2, acked=0, rejected=0, timed_out=2, failed=0"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bids_acked_or_rejected() {
+        // the bids of 1 MIST are below the reserve
+        let (url, _bids) = new_mock_server(|bid| {
+            Some(match bid["bidAmount"].as_u64().unwrap() {
+                1 => json!({"error": "bid below reserve"}),
+                _ => json!({"bidReceived": {"oppTxDigest": bid["oppTxDigest"]}}),
+            })
+        })
+        .await;
+        let (executor, replies) = new_executor(url, DEFAULT_BID_ACK_TIMEOUT).await;
+
+        let (acked, rejected) = (TransactionDigest::random(), TransactionDigest::random());
+        executor.submit_bid(new_bid_tx(), 100, acked).await.unwrap();
+        assert_eq!(recv(&replies).await, (acked, Some(BidReply::Acked)));
+        executor.submit_bid(new_bid_tx(), 1, rejected).await.unwrap();
+        assert_eq!(
+            recv(&replies).await,
+            (rejected, Some(BidReply::Rejected("bid below reserve".to_string())))
+        );
+
+        assert_eq!(
+            executor.stats().to_string(),
+            "submitted=2, acked=1, rejected=1, timed_out=0, failed=0"
+        );
     }
 }
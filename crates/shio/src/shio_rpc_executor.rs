@@ -1,25 +1,120 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
 use burberry::{async_trait, Executor};
-use eyre::Result;
-use fastcrypto::{encoding::Base64, hash::HashFunction};
+use eyre::{bail, eyre, Result};
+use fastcrypto::encoding::Base64;
 use serde_json::{json, Value};
-use shared_crypto::intent::{Intent, IntentMessage};
-use sui_types::{
-    crypto::{Signer, SuiKeyPair},
-    digests::TransactionDigest,
-    transaction::TransactionData,
-};
+use sui_types::{digests::TransactionDigest, transaction::TransactionData};
+
+use tracing::warn;
 
-use crate::SHIO_JSON_RPC_URL;
+use crate::{bid_signer::BidSigner, SHIO_JSON_RPC_URL};
+
+// an attempt never waits longer than this, even if the deadline is further away
+pub const DEFAULT_MAX_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(1);
+// the attempts after the first one, on the next endpoints in turn
+pub const DEFAULT_MAX_RETRIES: usize = 2;
+// the share of the time left until the deadline given to the first attempt, in percent. The primary endpoint
+// is usually the fastest, the retries split what it didn't use.
+const FIRST_ATTEMPT_SHARE: u32 = 60;
+
+/// Called with the endpoint that accepted a bid, and the time from the first attempt until then.
+pub type LatencyObserver = Arc<dyn Fn(&str, Duration) + Send + Sync>;
 
 pub struct ShioRPCExecutor {
-    keypair: SuiKeyPair,
+    signer: Arc<dyn BidSigner>,
     rpc_client: reqwest::Client,
+    endpoints: Vec<String>,
+    max_retries: usize,
+    max_attempt_timeout: Duration,
+    latency_observer: Option<LatencyObserver>,
 }
 
 impl ShioRPCExecutor {
-    pub fn new(keypair: SuiKeyPair) -> Self {
+    pub fn new(signer: Arc<dyn BidSigner>) -> Self {
         let rpc_client = reqwest::Client::new();
-        Self { keypair, rpc_client }
+        Self {
+            signer,
+            rpc_client,
+            endpoints: vec![SHIO_JSON_RPC_URL.to_string()],
+            max_retries: DEFAULT_MAX_RETRIES,
+            max_attempt_timeout: DEFAULT_MAX_ATTEMPT_TIMEOUT,
+            latency_observer: None,
+        }
+    }
+
+    /// The endpoints are tried in order, the ones after the first are only used when it fails.
+    pub fn with_endpoints(mut self, endpoints: Vec<String>) -> Self {
+        assert!(!endpoints.is_empty(), "no shio rpc endpoint");
+        self.endpoints = endpoints;
+        self
+    }
+
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn with_max_attempt_timeout(mut self, max_attempt_timeout: Duration) -> Self {
+        self.max_attempt_timeout = max_attempt_timeout;
+        self
+    }
+
+    pub fn with_latency_observer(mut self, latency_observer: LatencyObserver) -> Self {
+        self.latency_observer = Some(latency_observer);
+        self
+    }
+
+    /// Posts the bid to the endpoints in order until one accepts it, and returns the latency.
+    ///
+    /// An endpoint that answers with a 5xx, times out or can't be reached is followed by the next one,
+    /// wrapping around, up to `max_retries` times. The first attempt gets most of the time left until
+    /// `deadline_ms`, the retries an equal share of the rest. A 4xx is not retried.
+    pub async fn submit_bid(&self, bid: &Value, deadline_ms: u64) -> Result<Duration> {
+        let start = Instant::now();
+        let max_attempts = self.max_retries + 1;
+        let mut last_error = eyre!("bid deadline passed before the first attempt");
+
+        for attempt in 0..max_attempts {
+            let endpoint = &self.endpoints[attempt % self.endpoints.len()];
+            let remaining = Duration::from_millis(deadline_ms.saturating_sub(current_time_ms()));
+            if remaining.is_zero() {
+                return Err(last_error.wrap_err("bid deadline passed"));
+            }
+            let timeout = match attempt {
+                0 if max_attempts > 1 => remaining * FIRST_ATTEMPT_SHARE / 100,
+                _ => remaining / (max_attempts - attempt) as u32,
+            };
+            let timeout = timeout.min(self.max_attempt_timeout);
+
+            let resp = self.rpc_client.post(endpoint).json(bid).timeout(timeout).send().await;
+            let error = match resp {
+                Ok(resp) if resp.status().is_success() => {
+                    let latency = start.elapsed();
+                    let response = resp.text().await.unwrap_or_default();
+                    warn!(endpoint, attempt, ?latency, "🧀<< {:?}", response);
+                    if let Some(latency_observer) = &self.latency_observer {
+                        latency_observer(endpoint, latency);
+                    }
+                    return Ok(latency);
+                }
+                Ok(resp) if resp.status().is_server_error() => eyre!("{endpoint} answered {}", resp.status()),
+                Ok(resp) => {
+                    let status = resp.status();
+                    let response = resp.text().await.unwrap_or_default();
+                    bail!("{endpoint} refused the bid: {status} {response}");
+                }
+                Err(error) => eyre!(error).wrap_err(format!("{endpoint} failed, timeout {timeout:?}")),
+            };
+
+            warn!(endpoint, attempt, ?error, "bid attempt failed");
+            last_error = error;
+        }
+
+        Err(last_error)
     }
 
     pub async fn encode_bid(
@@ -30,18 +125,7 @@ impl ShioRPCExecutor {
     ) -> Result<Value> {
         let tx_bytes = bcs::to_bytes(&tx_data)?;
         let tx_b64 = Base64::from_bytes(&tx_bytes).encoded();
-
-        let sig = {
-            let intent_msg = IntentMessage::new(Intent::sui_transaction(), tx_data);
-            let raw_tx = bcs::to_bytes(&intent_msg)?;
-            let digest = {
-                let mut hasher = sui_types::crypto::DefaultHash::default();
-                hasher.update(raw_tx.clone());
-                hasher.finalize().digest
-            };
-
-            self.keypair.sign(&digest)
-        };
+        let sig = self.signer.sign(&tx_bytes).await?;
 
         Ok(json!({
             "jsonrpc": "2.0",
@@ -58,22 +142,176 @@ impl ShioRPCExecutor {
 }
 
 #[async_trait]
-impl Executor<(TransactionData, u64, TransactionDigest)> for ShioRPCExecutor {
+impl Executor<(TransactionData, u64, TransactionDigest, u64)> for ShioRPCExecutor {
     fn name(&self) -> &str {
         "ShioRPCExecutor"
     }
 
     async fn execute(
         &self,
-        (tx_data, bid_amount, opp_tx_digest): (TransactionData, u64, TransactionDigest),
+        (tx_data, bid_amount, opp_tx_digest, deadline_ms): (TransactionData, u64, TransactionDigest, u64),
     ) -> Result<()> {
         let bid = self.encode_bid(tx_data, bid_amount, opp_tx_digest).await?;
         tracing::warn!("🧀>> {}", bid);
-        let resp = self.rpc_client.post(SHIO_JSON_RPC_URL).json(&bid).send().await?;
-        let status = resp.status();
-        let response = resp.text().await?;
-        tracing::warn!("🧀<< {:?} {:?}", status, response);
+        self.submit_bid(&bid, deadline_ms).await?;
 
         Ok(())
     }
 }
+
+fn current_time_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use sui_types::crypto::{get_key_pair, AccountKeyPair, SuiKeyPair};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::bid_signer::KeypairSigner;
+
+    // answers the requests with `statuses` in turn (the last one repeats) after `delay`, the request
+    // bodies are reported as soon as they are read
+    async fn new_mock_server(delay: Duration, statuses: Vec<&'static str>) -> (String, async_channel::Receiver<Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (body_sender, body_receiver) = async_channel::unbounded();
+
+        tokio::spawn(async move {
+            for i in 0.. {
+                let (stream, _) = listener.accept().await.unwrap();
+                let status = statuses[i.min(statuses.len() - 1)];
+                let body_sender = body_sender.clone();
+                tokio::spawn(async move {
+                    let (mut stream, body) = read_request_body(stream).await;
+                    body_sender.send(serde_json::from_slice(&body).unwrap()).await.unwrap();
+
+                    tokio::time::sleep(delay).await;
+                    let response = format!("HTTP/1.1 {status}\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}");
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+
+        (url, body_receiver)
+    }
+
+    async fn read_request_body(mut stream: TcpStream) -> (TcpStream, Vec<u8>) {
+        let mut request = vec![];
+        let mut buf = [0u8; 4096];
+        let body = loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            let Some(header_end) = text.find("\r\n\r\n") else {
+                continue;
+            };
+            let content_length: usize = text[..header_end]
+                .lines()
+                .find_map(|line| line.to_lowercase().strip_prefix("content-length: ")?.parse().ok())
+                .unwrap();
+            if request.len() >= header_end + 4 + content_length {
+                break request[header_end + 4..header_end + 4 + content_length].to_vec();
+            }
+        };
+
+        (stream, body)
+    }
+
+    fn new_executor(endpoints: Vec<String>) -> ShioRPCExecutor {
+        let (_, keypair): (_, AccountKeyPair) = get_key_pair();
+        ShioRPCExecutor::new(Arc::new(KeypairSigner::new(SuiKeyPair::Ed25519(keypair)))).with_endpoints(endpoints)
+    }
+
+    fn new_bid() -> Value {
+        json!({"jsonrpc": "2.0", "id": 1, "method": "shio_submitBid", "params": ["digest", 1, "tx", "sig"]})
+    }
+
+    #[tokio::test]
+    async fn test_slow_primary_fails_over_to_fast_secondary() {
+        let (primary, primary_bodies) = new_mock_server(Duration::from_secs(2), vec!["200 OK"]).await;
+        let (secondary, secondary_bodies) = new_mock_server(Duration::ZERO, vec!["200 OK"]).await;
+
+        let observed = Arc::new(Mutex::new(vec![]));
+        let executor = new_executor(vec![primary, secondary.clone()]).with_latency_observer({
+            let observed = observed.clone();
+            Arc::new(move |endpoint: &str, latency| observed.lock().unwrap().push((endpoint.to_string(), latency)))
+        });
+
+        let latency = executor.submit_bid(&new_bid(), current_time_ms() + 600).await.unwrap();
+        assert!(latency < Duration::from_millis(600), "{latency:?}");
+        assert_eq!(*observed.lock().unwrap(), vec![(secondary, latency)]);
+
+        // both got the same payload
+        assert_eq!(primary_bodies.recv().await.unwrap(), new_bid());
+        assert_eq!(secondary_bodies.recv().await.unwrap(), new_bid());
+    }
+
+    #[tokio::test]
+    async fn test_primary_gets_most_of_the_budget() {
+        // a third of the budget would time the primary out
+        let (primary, _) = new_mock_server(Duration::from_millis(450), vec!["200 OK"]).await;
+        let (secondary, secondary_bodies) = new_mock_server(Duration::ZERO, vec!["200 OK"]).await;
+        let observed = Arc::new(Mutex::new(vec![]));
+        let executor = new_executor(vec![primary.clone(), secondary]).with_latency_observer({
+            let observed = observed.clone();
+            Arc::new(move |endpoint: &str, _| observed.lock().unwrap().push(endpoint.to_string()))
+        });
+
+        executor
+            .submit_bid(&new_bid(), current_time_ms() + 1_000)
+            .await
+            .unwrap();
+        assert_eq!(*observed.lock().unwrap(), vec![primary]);
+        assert!(secondary_bodies.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_max_retries() {
+        let (endpoint, bodies) = new_mock_server(Duration::ZERO, vec!["503 Service Unavailable"]).await;
+
+        for (max_retries, attempts) in [(0, 1), (3, 4)] {
+            let executor = new_executor(vec![endpoint.clone()]).with_max_retries(max_retries);
+            let before = bodies.len();
+            executor
+                .submit_bid(&new_bid(), current_time_ms() + 1_000)
+                .await
+                .unwrap_err();
+            assert_eq!(bodies.len() - before, attempts);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_server_error() {
+        let (endpoint, bodies) = new_mock_server(Duration::ZERO, vec!["503 Service Unavailable", "200 OK"]).await;
+        let executor = new_executor(vec![endpoint]);
+
+        executor
+            .submit_bid(&new_bid(), current_time_ms() + 1_000)
+            .await
+            .unwrap();
+        assert_eq!(bodies.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_on_client_error_or_past_deadline() {
+        let (endpoint, bodies) = new_mock_server(Duration::ZERO, vec!["400 Bad Request", "200 OK"]).await;
+        let executor = new_executor(vec![endpoint]);
+
+        let error = executor
+            .submit_bid(&new_bid(), current_time_ms() + 1_000)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("refused the bid"), "{error:#}");
+        assert_eq!(bodies.len(), 1);
+
+        assert!(executor.submit_bid(&new_bid(), current_time_ms()).await.is_err());
+        assert_eq!(bodies.len(), 1);
+    }
+}
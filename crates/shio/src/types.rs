@@ -1,15 +1,24 @@
-use serde::Deserialize;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
 use serde_json::Value;
+use sui_types::{base_types::SequenceNumber, digests::ObjectDigest};
+use tracing::warn;
+
+// the kinds of items that are parsed, the key of a frame
+const KNOWN_KINDS: [&str; 2] = ["auctionStarted", "auctionEnded"];
 
+/// An item of the feed. Unknown fields are ignored, and numbers may be sent either as json numbers or
+/// as strings.
 #[derive(Debug, Clone, Deserialize)]
 pub enum ShioItem {
     #[serde(rename = "auctionStarted")]
     AuctionStarted {
         #[serde(rename = "txDigest")]
         tx_digest: String,
-        #[serde(rename = "gasPrice")]
+        #[serde(rename = "gasPrice", deserialize_with = "u64_or_string")]
         gas_price: u64,
-        #[serde(rename = "deadlineTimestampMs")]
+        #[serde(rename = "deadlineTimestampMs", deserialize_with = "u64_or_string")]
         deadline_timestamp_ms: u64,
         #[serde(rename = "sideEffects")]
         side_effects: SideEffects,
@@ -17,14 +26,20 @@ pub enum ShioItem {
         _other: (),
     },
 
+    // sent once the auction of an opportunity tx is settled, it doesn't name the winner
     #[serde(rename = "auctionEnded")]
     AuctionEnded {
         #[serde(rename = "txDigest")]
         tx_digest: String,
-        #[serde(rename = "winningBidAmount")]
+        #[serde(rename = "winningBidAmount", deserialize_with = "u64_or_string")]
         winning_bid_amount: u64,
     },
 
+    // an item of a kind this version doesn't know, `raw` is its body
+    #[serde(skip)]
+    Other { kind: String, raw: Value },
+
+    // a frame that isn't an item, or an item of a known kind that doesn't parse
     #[serde(skip)]
     Dummy(Value),
 }
@@ -32,8 +47,8 @@ pub enum ShioItem {
 impl ShioItem {
     pub fn tx_digest(&self) -> &str {
         match self {
-            ShioItem::AuctionStarted { tx_digest, .. } => tx_digest,
-            ShioItem::AuctionEnded { .. } => "auctionEnded",
+            ShioItem::AuctionStarted { tx_digest, .. } | ShioItem::AuctionEnded { tx_digest, .. } => tx_digest,
+            ShioItem::Other { .. } => "other",
             ShioItem::Dummy(_) => "dummy",
         }
     }
@@ -42,6 +57,7 @@ impl ShioItem {
         match self {
             ShioItem::AuctionStarted { gas_price, .. } => *gas_price,
             ShioItem::AuctionEnded { .. } => 0,
+            ShioItem::Other { .. } => 0,
             ShioItem::Dummy(_) => 0,
         }
     }
@@ -52,6 +68,7 @@ impl ShioItem {
                 deadline_timestamp_ms, ..
             } => *deadline_timestamp_ms,
             ShioItem::AuctionEnded { .. } => 0,
+            ShioItem::Other { .. } => 0,
             ShioItem::Dummy(_) => 0,
         }
     }
@@ -60,6 +77,7 @@ impl ShioItem {
         match self {
             ShioItem::AuctionStarted { side_effects, .. } => side_effects.events.clone(),
             ShioItem::AuctionEnded { .. } => vec![],
+            ShioItem::Other { .. } => vec![],
             ShioItem::Dummy(_) => vec![],
         }
     }
@@ -72,6 +90,7 @@ impl ShioItem {
                 .chain(&side_effects.mutated_objects)
                 .collect(),
             ShioItem::AuctionEnded { .. } => vec![],
+            ShioItem::Other { .. } => vec![],
             ShioItem::Dummy(_) => vec![],
         }
     }
@@ -83,7 +102,7 @@ pub struct SideEffects {
     pub created_objects: Vec<ShioObject>,
     #[serde(rename = "mutatedObjects", default)]
     pub mutated_objects: Vec<ShioObject>,
-    #[serde(rename = "gasUsage")]
+    #[serde(rename = "gasUsage", deserialize_with = "u64_or_string")]
     pub gas_usage: u64,
     #[serde(rename = "events", default)]
     pub events: Vec<ShioEvent>,
@@ -97,9 +116,27 @@ pub struct ShioObject {
     pub owner: Value,
     pub content: ShioObjectContent,
     pub object_bcs: String, // base64 encoded
+    // the version and digest after the opportunity tx, the version may be a string or a number
+    #[serde(default)]
+    pub version: Option<Value>,
+    #[serde(default)]
+    pub digest: Option<String>,
 }
 
 impl ShioObject {
+    /// The version and digest of the object after the opportunity tx, `None` if the auctioneer
+    /// didn't send them.
+    pub fn version_and_digest(&self) -> Option<(SequenceNumber, ObjectDigest)> {
+        let version = match self.version.as_ref()? {
+            Value::Number(version) => version.as_u64()?,
+            Value::String(version) => version.parse().ok()?,
+            _ => return None,
+        };
+        let digest = ObjectDigest::from_str(self.digest.as_ref()?).ok()?;
+
+        Some((SequenceNumber::from_u64(version), digest))
+    }
+
     pub fn data_type(&self) -> &str {
         &self.content.data_type
     }
@@ -136,7 +173,7 @@ pub struct ShioEvent {
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ShioEventId {
-    #[serde(rename = "eventSeq")]
+    #[serde(rename = "eventSeq", deserialize_with = "string_or_number")]
     pub event_seq: String,
     #[serde(rename = "txDigest")]
     pub tx_digest: String,
@@ -144,7 +181,40 @@ pub struct ShioEventId {
 
 impl From<Value> for ShioItem {
     fn from(value: Value) -> Self {
-        serde_json::from_value(value.clone()).unwrap_or(ShioItem::Dummy(value))
+        // an item is an object with its kind as the only key
+        let (kind, raw) = match value.as_object() {
+            Some(object) if object.len() == 1 => object.iter().next().unwrap(),
+            _ => return ShioItem::Dummy(value),
+        };
+        if !KNOWN_KINDS.contains(&kind.as_str()) {
+            return ShioItem::Other {
+                kind: kind.clone(),
+                raw: raw.clone(),
+            };
+        }
+
+        serde_json::from_value(value.clone()).unwrap_or_else(|e| {
+            warn!(kind, "fail to parse shio item: {e}");
+            ShioItem::Dummy(value)
+        })
+    }
+}
+
+fn u64_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::Number(n) => n
+            .as_u64()
+            .ok_or_else(|| serde::de::Error::custom(format!("not a u64: {n}"))),
+        Value::String(s) => s.parse().map_err(serde::de::Error::custom),
+        v => Err(serde::de::Error::custom(format!("not a u64: {v}"))),
+    }
+}
+
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(s) => Ok(s),
+        Value::Number(n) => Ok(n.to_string()),
+        v => Err(serde::de::Error::custom(format!("not a string or a number: {v}"))),
     }
 }
 
@@ -153,7 +223,117 @@ impl ShioItem {
         match self {
             ShioItem::AuctionStarted { .. } => "auctionStarted",
             ShioItem::AuctionEnded { .. } => "auctionEnded",
+            ShioItem::Other { kind, .. } => kind,
             ShioItem::Dummy(_) => "dummy",
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_auction_ended() {
+        let frame = r#"{"auctionEnded":{"txDigest":"5LTUhG3MzZtoTEKqVNNnN6ZfVgKRDjGtLG3VsJDPYSWd","winningBidAmount":1234567}}"#;
+        let item = ShioItem::from(serde_json::from_str::<Value>(frame).unwrap());
+
+        let ShioItem::AuctionEnded {
+            tx_digest,
+            winning_bid_amount,
+        } = &item
+        else {
+            panic!("expected an auction end: {item:?}");
+        };
+        assert_eq!(tx_digest, "5LTUhG3MzZtoTEKqVNNnN6ZfVgKRDjGtLG3VsJDPYSWd");
+        assert_eq!(*winning_bid_amount, 1234567);
+        assert_eq!(item.tx_digest(), "5LTUhG3MzZtoTEKqVNNnN6ZfVgKRDjGtLG3VsJDPYSWd");
+        assert_eq!(item.type_name(), "auctionEnded");
+        assert!(item.events().is_empty());
+    }
+
+    #[test]
+    fn test_malformed_auction_ended_is_dummy() {
+        // the winning bid amount is missing
+        let value = json!({"auctionEnded": {"txDigest": "5LTUhG3MzZtoTEKqVNNnN6ZfVgKRDjGtLG3VsJDPYSWd"}});
+        assert!(matches!(ShioItem::from(value), ShioItem::Dummy(_)));
+    }
+
+    #[test]
+    fn test_parse_auction_started_with_extra_fields_and_string_numbers() {
+        let frame = json!({"auctionStarted": {
+            "txDigest": "5LTUhG3MzZtoTEKqVNNnN6ZfVgKRDjGtLG3VsJDPYSWd",
+            "gasPrice": "750",
+            "deadlineTimestampMs": 1726000000123u64,
+            "bidCount": 3,
+            "sideEffects": {
+                "gasUsage": "3126420",
+                "storageRebate": 1,
+                "createdObjects": [],
+                "mutatedObjects": [{
+                    "id": "0x1",
+                    "objectType": "0x2::coin::Coin<0x2::sui::SUI>",
+                    "owner": {"AddressOwner": "0x2"},
+                    "content": {"dataType": "moveObject", "hasPublicTransfer": true, "fields": {}},
+                    "objectBcs": "",
+                    "version": "42",
+                    "previousTransaction": "x",
+                }],
+                "events": [{
+                    "type": "0x3::pool::SwapEvent",
+                    "bcs": "",
+                    "id": {"eventSeq": 0, "txDigest": "5LTUhG3MzZtoTEKqVNNnN6ZfVgKRDjGtLG3VsJDPYSWd"},
+                    "packageId": "0x3",
+                    "sender": "0x2",
+                    "transactionModule": "pool",
+                    "timestampMs": "1726000000000",
+                }],
+            },
+        }});
+        let item = ShioItem::from(frame);
+
+        let ShioItem::AuctionStarted { side_effects, .. } = &item else {
+            panic!("expected an auction start: {item:?}");
+        };
+        assert_eq!(item.gas_price(), 750);
+        assert_eq!(item.deadline_timestamp_ms(), 1726000000123);
+        assert_eq!(side_effects.gas_usage, 3126420);
+        assert_eq!(item.created_mutated_objects().len(), 1);
+        assert_eq!(item.events()[0].event_id.event_seq, "0");
+    }
+
+    #[test]
+    fn test_string_encoded_amounts() {
+        let item = ShioItem::from(json!({"auctionEnded": {"txDigest": "a", "winningBidAmount": "100"}}));
+        assert!(matches!(
+            item,
+            ShioItem::AuctionEnded {
+                winning_bid_amount: 100,
+                ..
+            }
+        ));
+
+        // not a number either way
+        let item = ShioItem::from(json!({"auctionEnded": {"txDigest": "a", "winningBidAmount": "lots"}}));
+        assert!(matches!(item, ShioItem::Dummy(_)));
+    }
+
+    #[test]
+    fn test_unknown_kind_is_other() {
+        let item = ShioItem::from(json!({"auctionCancelled": {"txDigest": "a", "reason": "expired"}}));
+
+        let ShioItem::Other { kind, raw } = &item else {
+            panic!("expected an unknown item: {item:?}");
+        };
+        assert_eq!(kind, "auctionCancelled");
+        assert_eq!(raw, &json!({"txDigest": "a", "reason": "expired"}));
+        assert_eq!(item.type_name(), "auctionCancelled");
+        assert!(item.events().is_empty());
+
+        // not an item at all
+        assert!(matches!(ShioItem::from(json!([1, 2])), ShioItem::Dummy(_)));
+        assert!(matches!(ShioItem::from(json!({"a": 1, "b": 2})), ShioItem::Dummy(_)));
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use eyre::{Context, Result};
+use prometheus::Registry;
+use sui_config::{NodeConfig, PersistedConfig};
+use sui_core::authority::{authority_store_tables::AuthorityPerpetualTables, AuthorityStore};
+use sui_types::supported_protocol_versions::ProtocolConfig;
+
+use super::{DBSimulator, LayoutCache, ObjectUpdateSource, DEFAULT_CATCH_UP_INTERVAL};
+
+pub const SUI_DB_PATH_ENV: &str = "SUI_DB_PATH";
+pub const SUI_NODE_CONFIG_ENV: &str = "SUI_NODE_CONFIG";
+
+const DEFAULT_STORE_PATH: &str = "/home/ubuntu/sui/db/live/store";
+const DEFAULT_NODE_CONFIG_PATH: &str = "/home/ubuntu/sui/fullnode.yaml";
+
+/// Returned by `DBSimulatorBuilder::build` when configured paths don't exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingPaths {
+    pub paths: Vec<PathBuf>,
+}
+
+impl fmt::Display for MissingPaths {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let paths = self.paths.iter().map(|p| p.display().to_string()).collect::<Vec<_>>();
+        write!(f, "db simulator paths not found: {}", paths.join(", "))
+    }
+}
+
+impl std::error::Error for MissingPaths {}
+
+#[derive(Debug, Clone)]
+pub struct DBSimulatorBuilder {
+    store_path: PathBuf,
+    node_config_path: PathBuf,
+    update_source: Option<ObjectUpdateSource>,
+    catch_up_interval: Duration,
+    preload_ids_path: Option<PathBuf>,
+    with_fallback: bool,
+    protocol_config: Option<ProtocolConfig>,
+    layout_cache: Option<Arc<LayoutCache>>,
+}
+
+impl DBSimulatorBuilder {
+    pub fn new(store_path: impl Into<PathBuf>, node_config_path: impl Into<PathBuf>) -> Self {
+        Self {
+            store_path: store_path.into(),
+            node_config_path: node_config_path.into(),
+            update_source: None,
+            catch_up_interval: DEFAULT_CATCH_UP_INTERVAL,
+            preload_ids_path: None,
+            with_fallback: true,
+            protocol_config: None,
+            layout_cache: None,
+        }
+    }
+
+    /// Paths from `SUI_DB_PATH` and `SUI_NODE_CONFIG`, `None` if either is not set.
+    pub fn from_env() -> Option<Self> {
+        let store_path = std::env::var(SUI_DB_PATH_ENV).ok()?;
+        let node_config_path = std::env::var(SUI_NODE_CONFIG_ENV).ok()?;
+        Some(Self::new(store_path, node_config_path))
+    }
+
+    /// Paths from `SUI_DB_PATH` and `SUI_NODE_CONFIG`, each falls back to the path of our nodes.
+    pub fn from_env_or_default() -> Self {
+        let store_path = std::env::var(SUI_DB_PATH_ENV).unwrap_or_else(|_| DEFAULT_STORE_PATH.to_string());
+        let node_config_path =
+            std::env::var(SUI_NODE_CONFIG_ENV).unwrap_or_else(|_| DEFAULT_NODE_CONFIG_PATH.to_string());
+        Self::new(store_path, node_config_path)
+    }
+
+    /// The socket that the sui node sends object updates to.
+    pub fn with_update_socket(self, update_socket: impl Into<PathBuf>) -> Self {
+        self.with_update_source(ObjectUpdateSource::unix(update_socket))
+    }
+
+    /// The feed that the sui node sends object updates to, over a unix socket or tcp.
+    pub fn with_update_source(mut self, update_source: ObjectUpdateSource) -> Self {
+        self.update_source = Some(update_source);
+        self
+    }
+
+    /// How often the update thread reloads the underlying store, defaults to a day.
+    pub fn with_catch_up_interval(mut self, catch_up_interval: Duration) -> Self {
+        self.catch_up_interval = catch_up_interval;
+        self
+    }
+
+    /// A file of object ids, one per line, that are loaded into the cache on start.
+    pub fn with_preload_ids_path(mut self, preload_ids_path: impl Into<PathBuf>) -> Self {
+        self.preload_ids_path = Some(preload_ids_path.into());
+        self
+    }
+
+    /// Read objects that are not in the override objects from the store, defaults to true.
+    pub fn with_fallback(mut self, with_fallback: bool) -> Self {
+        self.with_fallback = with_fallback;
+        self
+    }
+
+    /// Replaces the protocol config of the latest mainnet version.
+    pub fn with_protocol_config(mut self, protocol_config: ProtocolConfig) -> Self {
+        self.protocol_config = Some(protocol_config);
+        self
+    }
+
+    /// Replaces the layout cache shared by the simulators of the process, e.g. to count its hits in tests.
+    pub fn with_layout_cache(mut self, layout_cache: Arc<LayoutCache>) -> Self {
+        self.layout_cache = Some(layout_cache);
+        self
+    }
+
+    /// Checks that every configured path exists, without opening anything.
+    pub fn check_paths(&self) -> Result<(), MissingPaths> {
+        let paths = [
+            Some(&self.store_path),
+            Some(&self.node_config_path),
+            self.preload_ids_path.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|path| !path.exists())
+        .cloned()
+        .collect::<Vec<_>>();
+
+        if paths.is_empty() {
+            Ok(())
+        } else {
+            Err(MissingPaths { paths })
+        }
+    }
+
+    pub async fn build_authority_store(&self) -> Result<Arc<AuthorityStore>> {
+        self.check_paths()?;
+        open_authority_store(&self.store_path, &self.node_config_path).await
+    }
+
+    pub async fn build(self) -> Result<DBSimulator> {
+        let authority_store = self.build_authority_store().await?;
+        let preload_ids = match &self.preload_ids_path {
+            Some(path) => super::read_preload_ids(path)?,
+            None => vec![],
+        };
+        let protocol_config = self.protocol_config.unwrap_or_else(super::default_protocol_config);
+
+        Ok(DBSimulator::new_with_protocol_config(
+            authority_store,
+            self.update_source,
+            self.catch_up_interval,
+            preload_ids,
+            self.with_fallback,
+            protocol_config,
+            self.layout_cache.unwrap_or_else(LayoutCache::global),
+        ))
+    }
+}
+
+async fn open_authority_store(store_path: &Path, node_config_path: &Path) -> Result<Arc<AuthorityStore>> {
+    let config: NodeConfig = PersistedConfig::read(node_config_path)
+        .map_err(|err| eyre::eyre!(err))
+        .wrap_err_with(|| format!("cannot read sui node config at {}", node_config_path.display()))?;
+
+    let genesis = config.genesis().map_err(|err| eyre::eyre!(err))?.clone();
+
+    let perpetual_tables = Arc::new(AuthorityPerpetualTables::open_readonly_as_rw(store_path));
+
+    AuthorityStore::open(perpetual_tables, &genesis, &config, &Registry::new())
+        .await
+        .map_err(|err| eyre::eyre!(err))
+        .wrap_err_with(|| format!("cannot open sui store at {}", store_path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_missing_paths() {
+        let builder = DBSimulatorBuilder::new("/nonexistent/store", "/nonexistent/fullnode.yaml")
+            .with_preload_ids_path("/nonexistent/ids.txt");
+
+        let error = builder.build().await.err().unwrap();
+        let missing = error.downcast_ref::<MissingPaths>().unwrap();
+        assert_eq!(
+            missing.paths,
+            vec![
+                PathBuf::from("/nonexistent/store"),
+                PathBuf::from("/nonexistent/fullnode.yaml"),
+                PathBuf::from("/nonexistent/ids.txt"),
+            ]
+        );
+        assert!(error.to_string().contains("/nonexistent/fullnode.yaml"), "{error}");
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use sui_types::base_types::ObjectID;
+
+/// How many of the most recent override misses are kept by id.
+pub const RECENT_MISSES_CAPACITY: usize = 10_000;
+
+/// Object read totals of a simulator since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    // reads served by the override objects of a simulation
+    pub hits: u64,
+    // reads that missed the override objects and went to the store
+    pub misses: u64,
+    // reads that missed the in-memory cache of the store and went to disk
+    pub disk_reads: u64,
+}
+
+/// Counts the object reads of a simulator, and keeps the ids of the most recent override misses to
+/// find objects worth preloading. Misses are recorded without a global lock, as every simulation does.
+#[derive(Debug)]
+pub(super) struct MissTracker {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    disk_reads: AtomicU64,
+    capacity: usize,
+    // a ring of the recent misses, the next one goes to the slot `cursor % capacity`
+    recent: DashMap<usize, ObjectID>,
+    cursor: AtomicUsize,
+    // how often each id appears in `recent`
+    counts: DashMap<ObjectID, u64>,
+}
+
+impl Default for MissTracker {
+    fn default() -> Self {
+        Self::new(RECENT_MISSES_CAPACITY)
+    }
+}
+
+impl MissTracker {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            disk_reads: AtomicU64::new(0),
+            capacity,
+            recent: DashMap::with_capacity(capacity),
+            cursor: AtomicUsize::new(0),
+            counts: DashMap::new(),
+        }
+    }
+
+    pub fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_miss(&self, object_id: ObjectID) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        if self.capacity == 0 {
+            return;
+        }
+        // counted before it takes its slot, so that an id is counted whenever it can be evicted
+        *self.counts.entry(object_id).or_default() += 1;
+        let slot = self.cursor.fetch_add(1, Ordering::Relaxed) % self.capacity;
+        let Some(evicted) = self.recent.insert(slot, object_id) else {
+            return;
+        };
+        if let Entry::Occupied(mut count) = self.counts.entry(evicted) {
+            *count.get_mut() -= 1;
+            if *count.get() == 0 {
+                count.remove();
+            }
+        }
+    }
+
+    pub fn record_disk_reads(&self, disk_reads: u64) {
+        self.disk_reads.fetch_add(disk_reads, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            disk_reads: self.disk_reads.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The most missed ids among the recent misses, most missed first.
+    pub fn top_misses(&self, n: usize) -> Vec<(ObjectID, u64)> {
+        let mut misses = self
+            .counts
+            .iter()
+            .map(|count| (*count.key(), *count.value()))
+            .collect::<Vec<_>>();
+        misses.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        misses.truncate(n);
+        misses
+    }
+
+    /// `top_misses` one per line, to be logged.
+    pub fn dump_top_misses(&self, n: usize) -> String {
+        let stats = self.stats();
+        let mut dump = format!(
+            "cache stats: hits={}, misses={}, disk_reads={}",
+            stats.hits, stats.misses, stats.disk_reads
+        );
+        for (id, count) in self.top_misses(n) {
+            let _ = write!(dump, "\n{id} {count}");
+        }
+        dump
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_misses_are_bounded() {
+        let tracker = MissTracker::new(4);
+        let (a, b, c) = (ObjectID::random(), ObjectID::random(), ObjectID::random());
+        for id in [a, a, b, a, c, c] {
+            tracker.record_miss(id);
+        }
+
+        // the first two misses of `a` are evicted
+        assert_eq!(tracker.top_misses(10), {
+            let mut expected = vec![(c, 2), (a, 1), (b, 1)];
+            expected[1..].sort_unstable();
+            expected
+        });
+        assert_eq!(tracker.top_misses(1), vec![(c, 2)]);
+        assert_eq!(tracker.stats().misses, 6);
+
+        let dump = tracker.dump_top_misses(1);
+        assert!(dump.ends_with(&format!("\n{c} 2")), "{dump}");
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock, RwLock,
+    },
+};
+
+use move_core_types::{
+    annotated_value::{MoveDatatypeLayout, MoveStructLayout},
+    language_storage::{StructTag, TypeTag},
+};
+use sui_types::{base_types::ObjectID, error::SuiError, layout_resolver::LayoutResolver, SYSTEM_PACKAGE_ADDRESSES};
+
+/// Annotated struct layouts by their type, shared by the simulators of a process.
+/// Published packages are immutable, only the system packages are upgraded in place, see `invalidate_packages`.
+#[derive(Debug, Default)]
+pub struct LayoutCache {
+    layouts: RwLock<HashMap<StructTag, MoveStructLayout>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl LayoutCache {
+    /// The cache used by simulators that are not given one.
+    pub fn global() -> Arc<Self> {
+        static GLOBAL: OnceLock<Arc<LayoutCache>> = OnceLock::new();
+        GLOBAL.get_or_init(|| Arc::new(Self::default())).clone()
+    }
+
+    pub fn get(&self, struct_tag: &StructTag) -> Option<MoveStructLayout> {
+        let layout = self.layouts.read().unwrap().get(struct_tag).cloned();
+        match layout {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        layout
+    }
+
+    pub fn insert(&self, struct_tag: StructTag, layout: MoveStructLayout) {
+        self.layouts.write().unwrap().insert(struct_tag, layout);
+    }
+
+    /// Concurrent misses of the same type may all resolve it, the layouts are the same.
+    pub fn get_or_resolve<E>(
+        &self,
+        struct_tag: &StructTag,
+        resolve: impl FnOnce() -> Result<MoveStructLayout, E>,
+    ) -> Result<MoveStructLayout, E> {
+        if let Some(layout) = self.get(struct_tag) {
+            return Ok(layout);
+        }
+
+        let layout = resolve()?;
+        self.insert(struct_tag.clone(), layout.clone());
+        Ok(layout)
+    }
+
+    /// Drops the layouts of types that are defined in, or have type parameters from, the packages.
+    pub fn invalidate_packages(&self, package_ids: &HashSet<ObjectID>) {
+        if package_ids.is_empty() {
+            return;
+        }
+        self.layouts
+            .write()
+            .unwrap()
+            .retain(|struct_tag, _| !mentions_packages(struct_tag, package_ids));
+    }
+
+    pub fn invalidate_system_packages(&self) {
+        self.invalidate_packages(
+            &SYSTEM_PACKAGE_ADDRESSES
+                .iter()
+                .map(|address| ObjectID::from(*address))
+                .collect(),
+        );
+    }
+
+    pub fn clear(&self) {
+        self.layouts.write().unwrap().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.layouts.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+fn mentions_packages(struct_tag: &StructTag, package_ids: &HashSet<ObjectID>) -> bool {
+    package_ids.contains(&ObjectID::from(struct_tag.address))
+        || struct_tag
+            .type_params
+            .iter()
+            .any(|type_param| mentions_packages_in(type_param, package_ids))
+}
+
+fn mentions_packages_in(type_tag: &TypeTag, package_ids: &HashSet<ObjectID>) -> bool {
+    match type_tag {
+        TypeTag::Struct(struct_tag) => mentions_packages(struct_tag, package_ids),
+        TypeTag::Vector(inner) => mentions_packages_in(inner, package_ids),
+        _ => false,
+    }
+}
+
+/// A `LayoutResolver` that looks up struct layouts in a `LayoutCache` before resolving them.
+pub struct CachedLayoutResolver<'a> {
+    inner: Box<dyn LayoutResolver + 'a>,
+    cache: &'a LayoutCache,
+}
+
+impl<'a> CachedLayoutResolver<'a> {
+    pub fn new(inner: Box<dyn LayoutResolver + 'a>, cache: &'a LayoutCache) -> Self {
+        Self { inner, cache }
+    }
+}
+
+impl LayoutResolver for CachedLayoutResolver<'_> {
+    fn get_annotated_layout(&mut self, struct_tag: &StructTag) -> Result<MoveDatatypeLayout, SuiError> {
+        if let Some(layout) = self.cache.get(struct_tag) {
+            return Ok(MoveDatatypeLayout::Struct(Box::new(layout)));
+        }
+
+        let layout = self.inner.get_annotated_layout(struct_tag)?;
+        // enums are rare in events, they are resolved every time
+        if let MoveDatatypeLayout::Struct(layout) = &layout {
+            self.cache.insert(struct_tag.clone(), layout.as_ref().clone());
+        }
+        Ok(layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use move_core_types::{
+        annotated_value::{MoveFieldLayout, MoveTypeLayout},
+        identifier::Identifier,
+    };
+
+    use super::*;
+
+    fn test_layout(struct_tag: &StructTag) -> MoveStructLayout {
+        MoveStructLayout {
+            type_: struct_tag.clone(),
+            fields: vec![MoveFieldLayout::new(
+                Identifier::new("value").unwrap(),
+                MoveTypeLayout::U64,
+            )],
+        }
+    }
+
+    #[test]
+    fn test_shared_layout_cache_concurrency() {
+        let cache = Arc::new(LayoutCache::default());
+        let tags = (0..16)
+            .map(|i| StructTag::from_str(&format!("0x{:x}::pool::Pool<0x2::sui::SUI>", i + 100)).unwrap())
+            .collect::<Vec<_>>();
+        let resolved = Arc::new(AtomicU64::new(0));
+
+        // 8 simulators sharing the cache, each resolving every pool 100 times
+        let handles = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                let tags = tags.clone();
+                let resolved = resolved.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        for tag in &tags {
+                            let layout = cache
+                                .get_or_resolve(tag, || {
+                                    resolved.fetch_add(1, Ordering::Relaxed);
+                                    Ok::<_, ()>(test_layout(tag))
+                                })
+                                .unwrap();
+                            assert_eq!(&layout.type_, tag);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let lookups = 8 * 100 * tags.len() as u64;
+        assert_eq!(cache.hits() + cache.misses(), lookups);
+        assert_eq!(cache.misses(), resolved.load(Ordering::Relaxed));
+        // at most one miss per type and simulator
+        assert!(cache.misses() >= tags.len() as u64 && cache.misses() <= 8 * tags.len() as u64);
+        assert_eq!(cache.len(), tags.len());
+    }
+
+    #[test]
+    fn test_invalidate_packages() {
+        let cache = LayoutCache::default();
+        let pool = StructTag::from_str("0x100::pool::Pool<0x2::sui::SUI, 0x200::usdc::USDC>").unwrap();
+        let coin = StructTag::from_str("0x2::coin::Coin<0x2::sui::SUI>").unwrap();
+        let other = StructTag::from_str("0x300::pool::Pool<vector<0x400::a::A>>").unwrap();
+        for tag in [&pool, &coin, &other] {
+            cache.insert(tag.clone(), test_layout(tag));
+        }
+
+        // an upgrade of the framework drops every type using it
+        cache.invalidate_packages(&HashSet::from([ObjectID::from_str("0x2").unwrap()]));
+        assert!(cache.get(&pool).is_none());
+        assert!(cache.get(&coin).is_none());
+        assert!(cache.get(&other).is_some());
+
+        cache.invalidate_packages(&HashSet::from([ObjectID::from_str("0x400").unwrap()]));
+        assert!(cache.is_empty());
+    }
+}
@@ -1,53 +1,70 @@
+mod builder;
+mod cache_stats;
+mod layout_cache;
 mod override_cache;
 mod replay_simulator;
-
-pub use replay_simulator::ReplaySimulator;
+mod update_listener;
+mod update_source;
+
+pub use builder::{DBSimulatorBuilder, MissingPaths, SUI_DB_PATH_ENV, SUI_NODE_CONFIG_ENV};
+pub use cache_stats::{CacheStats, RECENT_MISSES_CAPACITY};
+pub use layout_cache::{CachedLayoutResolver, LayoutCache};
+pub use replay_simulator::{ReplayIntervals, ReplaySimulator};
+pub use update_listener::{UpdateHealth, DEFAULT_CATCH_UP_INTERVAL};
+pub use update_source::{
+    accept_update_handshake, write_update_batch, ObjectUpdateSource, UpdateAddress, UpdateCompression,
+};
 
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     panic::{catch_unwind, AssertUnwindSafe},
     path::{Path, PathBuf},
     str::FromStr,
     sync::Arc,
+    time::Duration,
 };
 
 use async_trait::async_trait;
 use eyre::Result;
-use move_core_types::annotated_value::{MoveDatatypeLayout, MoveStructLayout};
+use move_core_types::{
+    annotated_value::{MoveDatatypeLayout, MoveStructLayout},
+    language_storage::StructTag,
+};
 use prometheus::Registry;
-use sui_config::{NodeConfig, PersistedConfig};
 use sui_core::{
-    authority::{authority_store_tables::AuthorityPerpetualTables, backpressure::BackpressureManager, AuthorityStore},
+    authority::{backpressure::BackpressureManager, AuthorityStore},
     execution_cache::{metrics::ExecutionCacheMetrics, ExecutionCacheWrite, ObjectCacheRead, WritebackCache},
 };
 use sui_execution::Executor;
 use sui_indexer::errors::IndexerError;
 use sui_json_rpc::{get_balance_changes_from_effect, ObjectProvider};
 use sui_json_rpc_types::{BalanceChange, SuiTransactionBlockEffects, SuiTransactionBlockEvents};
-use sui_sdk::SUI_COIN_TYPE;
+use sui_sdk::{SuiClient, SUI_COIN_TYPE};
 use sui_types::{
-    base_types::{ObjectID, SequenceNumber},
+    base_types::{ObjectID, ObjectRef, SequenceNumber, SuiAddress},
     committee::{EpochId, ProtocolVersion},
-    digests::TransactionDigest,
-    effects::TransactionEffects,
+    dynamic_field::derive_dynamic_field_id,
+    effects::{TransactionEffects, TransactionEffectsAPI},
     error::SuiError,
     gas::SuiGasStatus,
     inner_temporary_store::InnerTemporaryStore,
     metrics::LimitsMetrics,
-    object::{MoveObject, Object, Owner, OBJECT_START_VERSION},
+    object::{Object, Owner},
     storage::{BackingPackageStore, ObjectKey, ObjectStore},
+    sui_system_state::SuiSystemStateWrapper,
     supported_protocol_versions::{Chain, ProtocolConfig},
     transaction::{
         CheckedInputObjects, InputObjectKind, InputObjects, ObjectReadResult, ObjectReadResultKind, TransactionData,
         TransactionDataAPI,
     },
-    TypeTag,
+    TypeTag, SUI_SYSTEM_STATE_OBJECT_ID, SUI_SYSTEM_STATE_OBJECT_SHARED_VERSION,
 };
-use tokio::{io::AsyncReadExt, net::UnixStream};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-use super::{SimulateCtx, SimulateResult, Simulator};
+use super::{mock_gas_coin, mock_gas_id, SimulateCtx, SimulateResult, Simulator, SimulatorError, MOCK_GAS_BALANCE};
+use cache_stats::MissTracker;
 use override_cache::OverrideCache;
+use update_listener::UpdateListener;
 
 pub struct DBSimulator {
     pub store: Arc<WritebackCache>,
@@ -56,21 +73,22 @@ pub struct DBSimulator {
     metrics: Arc<LimitsMetrics>,
     writeback_metrics: Arc<ExecutionCacheMetrics>,
     with_fallback: bool,
+    layout_cache: Arc<LayoutCache>,
+    // `None` without an update socket
+    update_health: Option<Arc<UpdateHealth>>,
+    // log every read that misses the override objects, instead of a summary per simulation
+    warn_override_misses: bool,
+    miss_tracker: Arc<MissTracker>,
+    // the store has no index of dynamic fields, they are enumerated over the json rpc
+    dynamic_fields_client: Option<SuiClient>,
 }
 
 impl DBSimulator {
     pub async fn new_authority_store(store_path: &str, config_path: &str) -> Arc<AuthorityStore> {
-        let config: NodeConfig = PersistedConfig::read(&PathBuf::from(config_path))
-            .map_err(|err| err.context(format!("Cannot open Sui Node Config file at {:?}", config_path)))
-            .unwrap();
-
-        let genesis = config.genesis().unwrap().clone();
-
-        let perpetual_tables = Arc::new(AuthorityPerpetualTables::open_readonly_as_rw(Path::new(store_path)));
-
-        AuthorityStore::open(perpetual_tables, &genesis, &config, &Registry::new())
+        DBSimulatorBuilder::new(store_path, config_path)
+            .build_authority_store()
             .await
-            .unwrap()
+            .unwrap_or_else(|e| panic!("{e:#}"))
     }
 
     pub async fn new_slow(
@@ -79,38 +97,62 @@ impl DBSimulator {
         update_socket: Option<&str>,
         preload_path: Option<&str>,
     ) -> Self {
-        let authority_store = Self::new_authority_store(store_path, config_path).await;
-        Self::new(
-            authority_store,
-            update_socket.map(PathBuf::from),
-            preload_path.map(PathBuf::from),
-            true,
-        )
-        .await
+        let mut builder = DBSimulatorBuilder::new(store_path, config_path);
+        if let Some(update_socket) = update_socket {
+            builder = builder.with_update_socket(update_socket);
+        }
+        if let Some(preload_path) = preload_path {
+            builder = builder.with_preload_ids_path(preload_path);
+        }
+        builder.build().await.unwrap_or_else(|e| panic!("{e:#}"))
     }
 
+    /// Paths from `SUI_DB_PATH` and `SUI_NODE_CONFIG`, see `DBSimulatorBuilder::from_env_or_default`.
     pub async fn new_default_slow() -> Self {
-        Self::new_slow(
-            "/home/ubuntu/sui/db/live/store",
-            "/home/ubuntu/sui/fullnode.yaml",
-            None,
-            Some("/home/ubuntu/suiflow-relay/pool_related_ids.txt"),
-        )
-        .await
+        DBSimulatorBuilder::from_env_or_default()
+            .with_preload_ids_path("/home/ubuntu/suiflow-relay/pool_related_ids.txt")
+            .build()
+            .await
+            .unwrap_or_else(|e| panic!("{e:#}"))
     }
 
     pub async fn new_test(fallback: bool) -> Self {
-        let authority_store =
-            Self::new_authority_store("/home/ubuntu/sui/db/live/store", "/home/ubuntu/sui/fullnode.yaml").await;
-
-        Self::new(authority_store, None, None, fallback).await
+        DBSimulatorBuilder::from_env_or_default()
+            .with_fallback(fallback)
+            .build()
+            .await
+            .unwrap_or_else(|e| panic!("{e:#}"))
     }
 
     pub async fn new(
         authority_store: Arc<AuthorityStore>,
-        update_socket: Option<PathBuf>,
+        update_source: Option<ObjectUpdateSource>,
         preload_path: Option<PathBuf>,
         with_fallback: bool,
+    ) -> Self {
+        let preload_ids = match preload_path {
+            Some(preload_path) => read_preload_ids(&preload_path).unwrap(),
+            None => vec![],
+        };
+        Self::new_with_protocol_config(
+            authority_store,
+            update_source,
+            DEFAULT_CATCH_UP_INTERVAL,
+            preload_ids,
+            with_fallback,
+            default_protocol_config(),
+            LayoutCache::global(),
+        )
+    }
+
+    fn new_with_protocol_config(
+        authority_store: Arc<AuthorityStore>,
+        update_source: Option<ObjectUpdateSource>,
+        catch_up_interval: Duration,
+        preload_ids: Vec<ObjectID>,
+        with_fallback: bool,
+        protocol_config: ProtocolConfig,
+        layout_cache: Arc<LayoutCache>,
     ) -> Self {
         let metrics = Arc::new(ExecutionCacheMetrics::new(&Registry::new()));
         let backpressure_manager = BackpressureManager::new_for_tests();
@@ -122,36 +164,30 @@ impl DBSimulator {
             backpressure_manager,
         ));
 
-        let preload_ids = if let Some(preload_path) = preload_path {
-            let objects_ids = std::fs::read_to_string(preload_path).unwrap();
-            objects_ids
-                .trim()
-                .split("\n")
-                .map(|s| ObjectID::from_str(s).unwrap())
-                .collect::<HashSet<_>>()
-        } else {
-            HashSet::new()
-        };
-        let preload_ids = preload_ids.iter().cloned().collect::<Vec<_>>();
-
         // preload objects
         let _ = writeback_cache.multi_get_objects(&preload_ids);
 
-        if let Some(update_socket) = update_socket {
+        let update_health = update_source.map(|update_source| {
+            let health = Arc::new(UpdateHealth::default());
             let execution_cache_writer = writeback_cache.clone();
             let preload_ids = preload_ids.clone();
+            let layout_cache = layout_cache.clone();
+            let thread_health = health.clone();
             std::thread::Builder::new()
                 .name("update-thread".to_string())
-                .spawn(move || spawn_update_thread(update_socket, preload_ids, execution_cache_writer))
+                .spawn(move || {
+                    spawn_update_thread(
+                        update_source,
+                        catch_up_interval,
+                        thread_health,
+                        preload_ids,
+                        execution_cache_writer,
+                        layout_cache,
+                    )
+                })
                 .unwrap();
-        }
-
-        let mut protocol_config = ProtocolConfig::get_for_version(ProtocolVersion::MAX, Chain::Mainnet);
-
-        protocol_config.object_runtime_max_num_cached_objects = Some(1000000);
-        protocol_config.object_runtime_max_num_cached_objects_system_tx = Some(1000000);
-        protocol_config.object_runtime_max_num_store_entries = Some(1000000);
-        protocol_config.object_runtime_max_num_store_entries_system_tx = Some(1000000);
+            health
+        });
 
         let executor =
             sui_execution::executor(&protocol_config, true, None).expect("Creating an executor should not fail here");
@@ -163,74 +199,204 @@ impl DBSimulator {
             metrics: Arc::new(LimitsMetrics::new(&Registry::new())),
             writeback_metrics: metrics,
             with_fallback,
+            layout_cache,
+            update_health,
+            warn_override_misses: false,
+            miss_tracker: Arc::new(MissTracker::default()),
+            dynamic_fields_client: None,
         }
     }
 
+    pub fn with_override_miss_warnings(mut self, warn_override_misses: bool) -> Self {
+        self.warn_override_misses = warn_override_misses;
+        self
+    }
+
+    /// Enumerate dynamic fields with `client`, `get_dynamic_children` fails without one.
+    pub fn with_dynamic_fields_client(mut self, client: SuiClient) -> Self {
+        self.dynamic_fields_client = Some(client);
+        self
+    }
+
+    pub fn layout_cache(&self) -> &Arc<LayoutCache> {
+        &self.layout_cache
+    }
+
+    /// False while the update socket is disconnected, the simulator serves stale objects then.
+    /// Always true without an update socket.
+    pub fn updates_healthy(&self) -> bool {
+        self.update_health.as_ref().map_or(true, |health| health.is_healthy())
+    }
+
+    /// When the last update batch from the update socket was applied, in ms since the unix epoch.
+    pub fn last_update_ms(&self) -> Option<u64> {
+        self.update_health.as_ref()?.last_update_ms()
+    }
+
+    pub fn update_health(&self) -> Option<Arc<UpdateHealth>> {
+        self.update_health.clone()
+    }
+
+    /// Object read totals of the simulations so far.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.miss_tracker.stats()
+    }
+
+    /// The objects that missed the override objects most often recently, with their miss counts.
+    /// These are candidates for the preload ids.
+    pub fn top_misses(&self, n: usize) -> Vec<(ObjectID, u64)> {
+        self.miss_tracker.top_misses(n)
+    }
+
+    /// `cache_stats` and `top_misses`, formatted to be logged.
+    pub fn dump_top_misses(&self, n: usize) -> String {
+        self.miss_tracker.dump_top_misses(n)
+    }
+
     pub fn get_input_objects(
         &self,
         input_object_kinds: &[InputObjectKind],
         epoch_id: EpochId,
     ) -> Result<InputObjects, SuiError> {
-        let mut input_results = vec![None; input_object_kinds.len()];
-        let mut object_refs = Vec::with_capacity(input_object_kinds.len());
-        let mut fetch_indices = Vec::with_capacity(input_object_kinds.len());
+        let owned_objects = self.multi_get_owned_objects(input_object_kinds);
+        self.resolve_input_objects(input_object_kinds, epoch_id, &owned_objects, &[])
+    }
+
+    // Fetch the owned objects among the input objects in one call to the cache, mock objects are not found.
+    fn multi_get_owned_objects<'a>(
+        &self,
+        input_object_kinds: impl IntoIterator<Item = &'a InputObjectKind>,
+    ) -> HashMap<ObjectRef, Object> {
+        let object_refs = input_object_kinds
+            .into_iter()
+            .filter_map(|kind| match kind {
+                InputObjectKind::ImmOrOwnedMoveObject(objref) => Some(*objref),
+                _ => None,
+            })
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let objects = self
+            .store
+            .multi_get_objects_by_key(&object_refs.iter().map(ObjectKey::from).collect::<Vec<_>>());
+        assert_eq!(objects.len(), object_refs.len());
+        object_refs
+            .into_iter()
+            .zip(objects)
+            .filter_map(|(objref, object)| Some((objref, object?)))
+            .collect()
+    }
+
+    // The SuiSystemState wrapper and the dynamic field with its inner state, at their latest versions at or
+    // below `version`.
+    fn system_state_overrides(&self, version: SequenceNumber) -> eyre::Result<Vec<ObjectReadResult>> {
+        let not_found = || eyre::eyre!("system state at version {version} not found, it may have been pruned");
+
+        let wrapper = self
+            .store
+            .find_object_lt_or_eq_version(SUI_SYSTEM_STATE_OBJECT_ID, version)
+            .ok_or_else(not_found)?;
+        let move_obj = wrapper.data.try_as_move().ok_or_else(not_found)?;
+        let state: SuiSystemStateWrapper = bcs::from_bytes(move_obj.contents())?;
+
+        let inner_id = derive_dynamic_field_id(
+            SUI_SYSTEM_STATE_OBJECT_ID,
+            &TypeTag::U64,
+            &bcs::to_bytes(&state.version)?,
+        )?;
+        let inner = self
+            .store
+            .find_object_lt_or_eq_version(inner_id, version)
+            .ok_or_else(not_found)?;
+
+        let wrapper_kind = InputObjectKind::SharedMoveObject {
+            id: SUI_SYSTEM_STATE_OBJECT_ID,
+            initial_shared_version: SUI_SYSTEM_STATE_OBJECT_SHARED_VERSION,
+            mutable: true,
+        };
+        let inner_kind = InputObjectKind::ImmOrOwnedMoveObject(inner.compute_object_reference());
+        Ok(vec![
+            ObjectReadResult::new(wrapper_kind, ObjectReadResultKind::Object(wrapper)),
+            ObjectReadResult::new(inner_kind, ObjectReadResultKind::Object(inner)),
+        ])
+    }
+
+    // Objects that are not in the store yet, e.g. written by a previous simulation, are taken from the
+    // override objects.
+    fn resolve_input_objects(
+        &self,
+        input_object_kinds: &[InputObjectKind],
+        epoch_id: EpochId,
+        owned_objects: &HashMap<ObjectRef, Object>,
+        override_objects: &[ObjectReadResult],
+    ) -> Result<InputObjects, SuiError> {
+        let mut input_results = Vec::with_capacity(input_object_kinds.len());
 
-        for (i, kind) in input_object_kinds.iter().enumerate() {
+        for kind in input_object_kinds {
             match kind {
                 // Packages are loaded one at a time via the cache
                 InputObjectKind::MovePackage(id) => {
                     let Some(package) = self.store.get_package_object(id)?.map(|o| o.into()) else {
                         return Err(SuiError::from(kind.object_not_found_error()));
                     };
-                    input_results[i] = Some(ObjectReadResult {
+                    input_results.push(ObjectReadResult {
                         input_object_kind: *kind,
                         object: ObjectReadResultKind::Object(package),
                     });
                 }
-                InputObjectKind::SharedMoveObject { id, .. } => match self.store.get_object(id) {
-                    Some(object) => input_results[i] = Some(ObjectReadResult::new(*kind, object.into())),
-                    None => {
-                        if let Some((version, digest)) = self.store.get_last_shared_object_deletion_info(id, epoch_id) {
-                            input_results[i] = Some(ObjectReadResult {
-                                input_object_kind: *kind,
-                                object: ObjectReadResultKind::DeletedSharedObject(version, digest),
-                            });
-                        } else {
-                            return Err(SuiError::from(kind.object_not_found_error()));
-                        }
+                InputObjectKind::SharedMoveObject { id, .. } => {
+                    // an override may also be deleted
+                    if let Some(override_object) = find_override(override_objects, id) {
+                        input_results.push(ObjectReadResult::new(*kind, override_object.object.clone()));
+                    } else if let Some(object) = self.store.get_object(id) {
+                        input_results.push(ObjectReadResult::new(*kind, object.into()));
+                    } else if let Some((version, digest)) =
+                        self.store.get_last_shared_object_deletion_info(id, epoch_id)
+                    {
+                        input_results.push(ObjectReadResult {
+                            input_object_kind: *kind,
+                            object: ObjectReadResultKind::DeletedSharedObject(version, digest),
+                        });
+                    } else {
+                        return Err(SuiError::from(kind.object_not_found_error()));
                     }
-                },
+                }
                 InputObjectKind::ImmOrOwnedMoveObject(objref) => {
-                    object_refs.push(*objref);
-                    fetch_indices.push(i);
+                    let object = owned_objects.get(objref).or_else(|| {
+                        find_override(override_objects, &objref.0)
+                            .filter(|o| o.input_object_kind == *kind)
+                            .and_then(|o| o.as_object())
+                    });
+                    // ignore mock objects
+                    if let Some(object) = object {
+                        input_results.push(ObjectReadResult {
+                            input_object_kind: *kind,
+                            object: ObjectReadResultKind::Object(object.clone()),
+                        });
+                    }
                 }
             }
         }
 
-        let objects = self
-            .store
-            .multi_get_objects_by_key(&object_refs.iter().map(ObjectKey::from).collect::<Vec<_>>());
-        assert_eq!(objects.len(), object_refs.len());
-        for (index, object) in fetch_indices.into_iter().zip(objects.into_iter()) {
-            // ignore mock objects
-            if let Some(object) = object {
-                input_results[index] = Some(ObjectReadResult {
-                    input_object_kind: input_object_kinds[index],
-                    object: ObjectReadResultKind::Object(object),
-                });
-            }
-        }
-
-        Ok(input_results.into_iter().flatten().collect::<Vec<_>>().into())
+        Ok(input_results.into())
     }
 
+    // The objects written by a tx as override objects of a next simulation: mutated (excluding gas), created
+    // and unwrapped objects, and markers of the deleted shared objects among `input_object_kinds`.
     fn get_mutated_objects(
         &self,
         effects: &TransactionEffects,
         store: &InnerTemporaryStore,
+        input_object_kinds: &[InputObjectKind],
     ) -> eyre::Result<Vec<ObjectReadResult>> {
         let mut object_changes = vec![];
-        for (obj_ref, owner) in effects.mutated_excluding_gas() {
+        let written = effects
+            .mutated_excluding_gas()
+            .into_iter()
+            .chain(effects.created())
+            .chain(effects.unwrapped());
+        for (obj_ref, owner) in written {
             if let Some(obj) = store.written.get(&obj_ref.0) {
                 let object = ObjectReadResultKind::Object(obj.clone());
 
@@ -247,54 +413,63 @@ impl DBSimulator {
             }
         }
 
+        for (id, version, _) in effects.deleted() {
+            let shared_kind = input_object_kinds.iter().find(
+                |kind| matches!(kind, InputObjectKind::SharedMoveObject { id: shared_id, .. } if *shared_id == id),
+            );
+            if let Some(kind) = shared_kind {
+                let object = ObjectReadResultKind::DeletedSharedObject(version, *effects.transaction_digest());
+                object_changes.push(ObjectReadResult::new(*kind, object));
+            }
+        }
+
         Ok(object_changes)
     }
 }
 
-#[async_trait]
-impl Simulator for DBSimulator {
-    async fn simulate(&self, tx: TransactionData, ctx: SimulateCtx) -> eyre::Result<SimulateResult> {
+impl DBSimulator {
+    async fn simulate_with_owned_objects(
+        &self,
+        tx: TransactionData,
+        ctx: SimulateCtx,
+        owned_objects: &HashMap<ObjectRef, Object>,
+    ) -> eyre::Result<SimulateResult> {
         let cache_misses_before = self.writeback_metrics.cache_misses_count();
 
         let SimulateCtx {
             epoch,
             mut override_objects,
-            borrowed_coin,
+            borrowed_coins,
+            clock_timestamp_ms,
+            system_state_version,
         } = ctx;
 
-        let mut input_objects = self.get_input_objects(&tx.input_objects()?, epoch.epoch_id)?;
+        if let Some(version) = system_state_version {
+            override_objects.extend(self.system_state_overrides(version)?);
+        }
+
+        let mut input_objects = self
+            .resolve_input_objects(&tx.input_objects()?, epoch.epoch_id, owned_objects, &override_objects)
+            .map_err(SimulatorError::from_input_error)?;
 
         let sender = tx.sender();
         let original_gas = tx.gas().to_vec();
 
-        let mock_gas_id =
-            ObjectID::from_str("0x0000000000000000000000000000000000000000000000000000000000001337").unwrap();
+        let mock_gas_id = mock_gas_id();
         let use_mock_gas = original_gas.is_empty();
         let (gas_ref, gas_obj) = if use_mock_gas {
-            let sender = tx.sender();
-            // use a 1B sui coin
-            const MIST_TO_SUI: u64 = 1_000_000_000;
-            const DRY_RUN_SUI: u64 = 1_000_000_000;
-
-            let max_coin_value = MIST_TO_SUI * DRY_RUN_SUI;
-            let gas_object = Object::new_move(
-                MoveObject::new_gas_coin(OBJECT_START_VERSION, mock_gas_id, max_coin_value),
-                Owner::AddressOwner(sender),
-                TransactionDigest::genesis_marker(),
-            );
+            let gas_object = mock_gas_coin(sender);
             let gas_object_ref = gas_object.compute_object_reference();
             (vec![gas_object_ref], Some(gas_object))
         } else {
             (original_gas, None)
         };
 
-        let gas_status = match SuiGasStatus::new(tx.gas_budget(), tx.gas_price(), tx.gas_price(), &self.protocol_config)
-            .map_err(|e| eyre::eyre!(e))
-        {
+        let gas_status = match new_gas_status(tx.gas_budget(), tx.gas_price(), epoch.gas_price, &self.protocol_config) {
             Ok(gas_status) => gas_status,
             Err(e) => {
                 info!("simulate error: {:?}", e);
-                return Err(e);
+                return Err(e.into());
             }
         };
 
@@ -310,7 +485,7 @@ impl Simulator for DBSimulator {
             override_objects.push(object_read_result);
         }
 
-        if let Some((borrowed_coin, _borrowed_amount)) = &borrowed_coin {
+        for (borrowed_coin, _borrowed_amount) in &borrowed_coins {
             let object_read_result = ObjectReadResult {
                 input_object_kind: InputObjectKind::ImmOrOwnedMoveObject(borrowed_coin.compute_object_reference()),
                 object: ObjectReadResultKind::Object(borrowed_coin.clone()),
@@ -325,7 +500,10 @@ impl Simulator for DBSimulator {
             OverrideCache::new(Some(self.store.clone()), override_objects)
         } else {
             OverrideCache::new(None, override_objects)
-        };
+        }
+        .with_miss_warnings(self.warn_override_misses)
+        .with_clock_timestamp_ms(clock_timestamp_ms)
+        .with_miss_tracker(self.miss_tracker.clone());
 
         // update input objects again with override cache
         for object_read_result in input_objects.objects.iter_mut() {
@@ -360,11 +538,11 @@ impl Simulator for DBSimulator {
             );
             (inner_temporary_store, effects)
         }))
-        .map_err(|e| eyre::eyre!("failed to simulate: {e:?}"))?;
+        .map_err(SimulatorError::executor_panic)?;
 
         debug!("simulate tx_data elapsed: {:?}", simulate_start.elapsed());
 
-        let object_changes = self.get_mutated_objects(&effects, &inner_temporary_store)?;
+        let object_changes = self.get_mutated_objects(&effects, &inner_temporary_store, &input_object_kinds)?;
 
         let executed_db = ExecutedDB {
             db: &override_cache,
@@ -372,53 +550,24 @@ impl Simulator for DBSimulator {
         };
 
         // don't let sui calc balance change. we will do it manually
+        let borrowed_ids = borrowed_coins.iter().map(|(obj, _)| obj.id());
         let mut balance_changes = if !use_mock_gas {
-            // ignore borrowed coin
-            get_balance_changes_from_effect(
-                &executed_db,
-                &effects,
-                input_object_kinds,
-                borrowed_coin.clone().map(|(obj, _)| vec![obj.id()]),
-            )
-            .await?
+            // ignore borrowed coins
+            let ignore_ids = borrowed_ids.collect::<Vec<_>>();
+            let ignore_ids = (!ignore_ids.is_empty()).then_some(ignore_ids);
+            get_balance_changes_from_effect(&executed_db, &effects, input_object_kinds, ignore_ids).await?
         } else {
-            let mut ignore_ids = vec![mock_gas_id];
-            if let Some((borrowed_coin_obj, _)) = &borrowed_coin {
-                ignore_ids.push(borrowed_coin_obj.id());
-            }
+            let ignore_ids = std::iter::once(mock_gas_id).chain(borrowed_ids).collect();
             get_balance_changes_from_effect(&executed_db, &effects, input_object_kinds, Some(ignore_ids)).await?
         };
 
         // Subtract how much we borrowed
-        // TODO: we should borrow any coin other than just sui
-        if let Some((_borrowed_coin_obj, borrowed_amount)) = &borrowed_coin {
-            let mut found = false;
-            if let Some(bc) = balance_changes
-                .iter_mut()
-                .find(|bc| bc.owner == Owner::AddressOwner(sender) && bc.coin_type.to_string() == SUI_COIN_TYPE)
-            {
-                found = true;
-                bc.amount -= *borrowed_amount as i128;
-            }
-
-            if !found {
-                balance_changes.push(BalanceChange {
-                    owner: Owner::AddressOwner(sender),
-                    coin_type: TypeTag::Struct(Box::new(move_core_types::language_storage::StructTag {
-                        address: move_core_types::account_address::AccountAddress::TWO,
-                        module: sui_types::Identifier::new("sui").unwrap(),
-                        name: sui_types::Identifier::new("SUI").unwrap(),
-                        type_params: vec![],
-                    })),
-                    amount: -(*borrowed_amount as i128),
-                });
-            }
-        }
+        subtract_borrowed_coins(&mut balance_changes, sender, &borrowed_coins)?;
 
         if use_mock_gas {
             let mut found = false;
 
-            let init_amount = 1_000_000_000u64 * 1_000_000_000u64;
+            let init_amount = MOCK_GAS_BALANCE;
             let final_amount = inner_temporary_store
                 .written
                 .get(&mock_gas_id)
@@ -449,14 +598,26 @@ impl Simulator for DBSimulator {
             }
         }
 
-        let mut layout_resolver = self.executor.type_layout_resolver(Box::new(&self.store));
+        let mut layout_resolver = CachedLayoutResolver::new(
+            self.executor.type_layout_resolver(Box::new(&self.store)),
+            &self.layout_cache,
+        );
         let events =
-            SuiTransactionBlockEvents::try_from(inner_temporary_store.events, digest, None, layout_resolver.as_mut())?;
+            SuiTransactionBlockEvents::try_from(inner_temporary_store.events, digest, None, &mut layout_resolver)?;
 
         let cache_misses = self
             .writeback_metrics
             .cache_misses_count()
             .saturating_sub(cache_misses_before);
+        self.miss_tracker.record_disk_reads(cache_misses);
+        let override_misses = override_cache.misses();
+        debug!(
+            tx = %digest,
+            cache_misses,
+            override_misses = override_misses.total(),
+            "simulated, override misses: {:?}",
+            override_misses
+        );
 
         Ok(SimulateResult {
             effects: SuiTransactionBlockEffects::try_from(effects)?,
@@ -464,8 +625,33 @@ impl Simulator for DBSimulator {
             object_changes,
             balance_changes,
             cache_misses,
+            override_misses,
         })
     }
+}
+
+#[async_trait]
+impl Simulator for DBSimulator {
+    async fn simulate(&self, tx: TransactionData, ctx: SimulateCtx) -> eyre::Result<SimulateResult> {
+        let owned_objects = self.multi_get_owned_objects(&tx.input_objects()?);
+        self.simulate_with_owned_objects(tx, ctx, &owned_objects).await
+    }
+
+    // the owned input objects of all txs are resolved in one pass, the rest is per tx
+    async fn simulate_many(&self, txs: Vec<(TransactionData, SimulateCtx)>) -> Vec<eyre::Result<SimulateResult>> {
+        let input_object_kinds = txs
+            .iter()
+            .filter_map(|(tx, _)| tx.input_objects().ok())
+            .flatten()
+            .collect::<Vec<_>>();
+        let owned_objects = self.multi_get_owned_objects(&input_object_kinds);
+
+        let mut results = Vec::with_capacity(txs.len());
+        for (tx, ctx) in txs {
+            results.push(self.simulate_with_owned_objects(tx, ctx, &owned_objects).await);
+        }
+        results
+    }
 
     fn name(&self) -> &str {
         "DBSimulator"
@@ -475,20 +661,34 @@ impl Simulator for DBSimulator {
         self.store.get_object(obj_id)
     }
 
+    async fn multi_get_objects(&self, ids: &[ObjectID]) -> Vec<Option<Object>> {
+        self.store.multi_get_objects(ids)
+    }
+
+    async fn get_dynamic_children(&self, parent: &ObjectID) -> eyre::Result<Vec<ObjectID>> {
+        let Some(client) = &self.dynamic_fields_client else {
+            eyre::bail!("DBSimulator has no dynamic fields client to enumerate the dynamic fields of {parent}");
+        };
+        crate::http_simulator::get_dynamic_children(client, parent).await
+    }
+
     fn get_object_layout(&self, obj_id: &ObjectID) -> Option<MoveStructLayout> {
         let object = self.store.get_object(obj_id)?;
-        let obj_type = object.type_().cloned()?;
+        let struct_tag: StructTag = object.type_().cloned()?.into();
 
-        let layout = self
-            .executor
-            .type_layout_resolver(Box::new(&self.store))
-            .get_annotated_layout(&obj_type.into());
+        let layout = self.layout_cache.get_or_resolve(&struct_tag, || {
+            match self
+                .executor
+                .type_layout_resolver(Box::new(&self.store))
+                .get_annotated_layout(&struct_tag)
+            {
+                Ok(MoveDatatypeLayout::Struct(layout)) => Ok(*layout),
+                _ => Err(()),
+            }
+        });
 
         match layout {
-            Ok(layout) => match layout {
-                MoveDatatypeLayout::Struct(layout) => Some(*layout),
-                _ => None,
-            },
+            Ok(layout) => Some(layout),
             Err(_) => {
                 error!("failed to get layout for object: {:?}", obj_id);
                 None
@@ -497,6 +697,92 @@ impl Simulator for DBSimulator {
     }
 }
 
+// the latest override object of `id`
+fn find_override<'a>(override_objects: &'a [ObjectReadResult], id: &ObjectID) -> Option<&'a ObjectReadResult> {
+    override_objects.iter().rev().find(|o| o.id() == *id)
+}
+
+fn default_protocol_config() -> ProtocolConfig {
+    let mut protocol_config = ProtocolConfig::get_for_version(ProtocolVersion::MAX, Chain::Mainnet);
+
+    protocol_config.object_runtime_max_num_cached_objects = Some(1000000);
+    protocol_config.object_runtime_max_num_cached_objects_system_tx = Some(1000000);
+    protocol_config.object_runtime_max_num_store_entries = Some(1000000);
+    protocol_config.object_runtime_max_num_store_entries_system_tx = Some(1000000);
+
+    protocol_config
+}
+
+// one object id per line, duplicates are dropped
+fn read_preload_ids(path: &Path) -> Result<Vec<ObjectID>> {
+    let objects_ids =
+        std::fs::read_to_string(path).map_err(|e| eyre::eyre!("cannot read preload ids at {}: {e}", path.display()))?;
+    let preload_ids = objects_ids
+        .trim()
+        .split("\n")
+        .map(|s| ObjectID::from_str(s).map_err(|e| eyre::eyre!("invalid preload id {s:?}: {e}")))
+        .collect::<Result<HashSet<_>>>()?;
+
+    Ok(preload_ids.into_iter().collect())
+}
+
+// The tx pays at least the reference gas price of the simulated epoch, as validators reject a lower price, the
+// bump is logged. A reference gas price of 0, i.e. an unknown epoch, means the gas price of the tx.
+fn new_gas_status(
+    gas_budget: u64,
+    gas_price: u64,
+    reference_gas_price: u64,
+    protocol_config: &ProtocolConfig,
+) -> Result<SuiGasStatus, SimulatorError> {
+    let reference_gas_price = if reference_gas_price == 0 {
+        gas_price
+    } else {
+        reference_gas_price
+    };
+    if gas_price < reference_gas_price {
+        warn!(
+            gas_price,
+            reference_gas_price, "Tx gas price below the reference gas price, simulated at the reference gas price"
+        );
+    }
+    SuiGasStatus::new(
+        gas_budget,
+        gas_price.max(reference_gas_price),
+        reference_gas_price,
+        protocol_config,
+    )
+    .map_err(|e| SimulatorError::GasError { message: e.to_string() })
+}
+
+// The borrowed coins are repaid from the sender's balance of the same coin type, an entry is added if the
+// sender has no balance change in that coin.
+fn subtract_borrowed_coins(
+    balance_changes: &mut Vec<BalanceChange>,
+    sender: SuiAddress,
+    borrowed_coins: &[(Object, u64)],
+) -> Result<()> {
+    for (coin, borrowed_amount) in borrowed_coins {
+        let coin_type = coin
+            .coin_type_maybe()
+            .ok_or_else(|| eyre::eyre!("borrowed object {} is not a coin", coin.id()))?;
+
+        let owner = Owner::AddressOwner(sender);
+        match balance_changes
+            .iter_mut()
+            .find(|bc| bc.owner == owner && bc.coin_type == coin_type)
+        {
+            Some(bc) => bc.amount -= *borrowed_amount as i128,
+            None => balance_changes.push(BalanceChange {
+                owner,
+                coin_type,
+                amount: -(*borrowed_amount as i128),
+            }),
+        }
+    }
+
+    Ok(())
+}
+
 struct ExecutedDB<'a> {
     db: &'a OverrideCache,
     temp_store: &'a InnerTemporaryStore,
@@ -567,43 +853,330 @@ pub fn preload_objects(cache_writer: Arc<dyn CacheWriter>, preload_ids: &[Object
 }
 
 #[tokio::main]
-async fn spawn_update_thread(socket_path: PathBuf, preload_ids: Vec<ObjectID>, cache_writer: Arc<dyn CacheWriter>) {
-    let mut socket = UnixStream::connect(socket_path)
-        .await
-        .expect("failed to connect to update socket");
-
-    let mut buf = [0u8; 4];
-    let mut last_catch_up_time = std::time::Instant::now();
-    loop {
-        // Read length prefix
-        if let Err(e) = socket.read_exact(&mut buf).await {
-            println!("Error reading length prefix: {}", e);
-            break;
-        }
-        let len = u32::from_le_bytes(buf) as usize;
+async fn spawn_update_thread(
+    source: ObjectUpdateSource,
+    catch_up_interval: Duration,
+    health: Arc<UpdateHealth>,
+    preload_ids: Vec<ObjectID>,
+    cache_writer: Arc<dyn CacheWriter>,
+    layout_cache: Arc<LayoutCache>,
+) {
+    UpdateListener {
+        source,
+        catch_up_interval,
+        health,
+        apply: |objects: Vec<(ObjectID, Object)>| {
+            // system packages are upgraded in place, so the layouts of a reloaded package may have changed
+            let package_ids = objects
+                .iter()
+                .filter(|(_, object)| object.is_package())
+                .map(|(id, _)| *id)
+                .collect::<HashSet<_>>();
+            cache_writer.reload_objects(objects);
+            layout_cache.invalidate_packages(&package_ids);
+        },
+        catch_up: || {
+            cache_writer.update_underlying(true);
+            preload_objects(cache_writer.clone(), &preload_ids);
+            layout_cache.invalidate_system_packages();
+        },
+    }
+    .run()
+    .await
+}
 
-        // Read payload
-        let mut payload = vec![0u8; len];
-        if let Err(e) = socket.read_exact(&mut payload).await {
-            println!("Error reading payload: {}", e);
-            break;
-        }
+#[cfg(test)]
+mod tests {
+    use move_core_types::identifier::Identifier;
+    use sui_types::{
+        base_types::MoveObjectType,
+        digests::TransactionDigest,
+        gas_coin::GAS,
+        object::{MoveObject, OBJECT_START_VERSION},
+        programmable_transaction_builder::ProgrammableTransactionBuilder,
+        transaction::{Argument, CallArg, Command, ObjectArg},
+        SUI_CLOCK_OBJECT_ID, SUI_CLOCK_OBJECT_SHARED_VERSION, SUI_FRAMEWORK_PACKAGE_ID,
+    };
+
+    use crate::SimEpoch;
+
+    use super::*;
+
+    const USDC: &str = "0xdba34672e30cb065b1f93e3ab55318768fd6fef66c15942c9f7cb846e2f900e7::usdc::USDC";
+
+    fn coin(coin_type: &TypeTag, value: u64, owner: SuiAddress) -> Object {
+        let move_obj = MoveObject::new_coin(
+            MoveObjectType::coin(coin_type.clone()),
+            OBJECT_START_VERSION,
+            ObjectID::random(),
+            value,
+        );
+        Object::new_move(
+            move_obj,
+            Owner::AddressOwner(owner),
+            TransactionDigest::genesis_marker(),
+        )
+    }
 
-        match bcs::from_bytes::<Vec<(ObjectID, Object)>>(&payload) {
-            Ok(objects) => {
-                cache_writer.reload_objects(objects);
-            }
-            Err(e) => {
-                println!("Error deserializing cache update: {}", e);
-            }
+    #[test]
+    fn test_subtract_borrowed_non_sui_coin() {
+        let sender = SuiAddress::random_for_testing_only();
+        let usdc = TypeTag::from_str(USDC).unwrap();
+        let sui = GAS::type_tag();
+
+        // a flashloan of 1000 USDC that ends with 1010 USDC and 5 SUI of fees paid
+        let mut balance_changes = vec![
+            BalanceChange {
+                owner: Owner::AddressOwner(sender),
+                coin_type: usdc.clone(),
+                amount: 1_010,
+            },
+            BalanceChange {
+                owner: Owner::AddressOwner(sender),
+                coin_type: sui.clone(),
+                amount: -5,
+            },
+        ];
+        let borrowed = vec![(coin(&usdc, 1_000, sender), 1_000)];
+        subtract_borrowed_coins(&mut balance_changes, sender, &borrowed).unwrap();
+
+        assert_eq!(balance_changes.len(), 2);
+        assert_eq!(balance_changes[0].amount, 10);
+        assert_eq!(balance_changes[1].amount, -5);
+
+        // borrowing a coin the sender doesn't end up with
+        let borrowed = vec![(coin(&sui, 300, sender), 300), (coin(&usdc, 20, sender), 20)];
+        let mut balance_changes = vec![];
+        subtract_borrowed_coins(&mut balance_changes, sender, &borrowed).unwrap();
+        let amounts = balance_changes
+            .iter()
+            .map(|bc| (bc.coin_type.clone(), bc.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(amounts, vec![(sui, -300), (usdc, -20)]);
+    }
+
+    #[test]
+    fn test_simulator_error_variants() {
+        // a gas price above the protocol max
+        let protocol_config = ProtocolConfig::get_for_version(ProtocolVersion::MAX, Chain::Mainnet);
+        let error = new_gas_status(1_000_000_000, u64::MAX, 0, &protocol_config).unwrap_err();
+        assert!(matches!(error, SimulatorError::GasError { .. }));
+
+        // an input object that is not in the store
+        let id = ObjectID::random();
+        let kind = InputObjectKind::SharedMoveObject {
+            id,
+            initial_shared_version: OBJECT_START_VERSION,
+            mutable: true,
+        };
+        let error = SimulatorError::from_input_error(kind.object_not_found_error().into());
+        assert!(matches!(
+            error.downcast_ref::<SimulatorError>(),
+            Some(SimulatorError::InputObjectMissing { id: missing }) if *missing == id
+        ));
+        let package_id = ObjectID::random();
+        let error =
+            SimulatorError::from_input_error(InputObjectKind::MovePackage(package_id).object_not_found_error().into());
+        assert!(matches!(
+            error.downcast_ref::<SimulatorError>(),
+            Some(SimulatorError::InputObjectMissing { id: missing }) if *missing == package_id
+        ));
+
+        // a panic in the executor
+        let error = catch_unwind(|| panic!("invariant violation"))
+            .map_err(SimulatorError::executor_panic)
+            .unwrap_err();
+        assert!(
+            matches!(&error, SimulatorError::ExecutorPanic { message } if message == "invariant violation"),
+            "{error}"
+        );
+    }
+
+    // splits a coin of `clock::timestamp_ms` MIST off the gas and sends it to `recipient`, like a router
+    // that checks a deadline against the clock
+    fn clock_reading_tx(sender: SuiAddress, recipient: SuiAddress) -> TransactionData {
+        let mut ptb = ProgrammableTransactionBuilder::new();
+        let clock = ptb
+            .input(CallArg::Object(ObjectArg::SharedObject {
+                id: SUI_CLOCK_OBJECT_ID,
+                initial_shared_version: SUI_CLOCK_OBJECT_SHARED_VERSION,
+                mutable: false,
+            }))
+            .unwrap();
+        let timestamp_ms = ptb.command(Command::move_call(
+            SUI_FRAMEWORK_PACKAGE_ID,
+            Identifier::new("clock").unwrap(),
+            Identifier::new("timestamp_ms").unwrap(),
+            vec![],
+            vec![clock],
+        ));
+        let coin = ptb.command(Command::SplitCoins(Argument::GasCoin, vec![timestamp_ms]));
+        ptb.transfer_arg(recipient, coin);
+
+        TransactionData::new_programmable(sender, vec![], ptb.finish(), 1_000_000_000, 1_000)
+    }
+
+    #[tokio::test]
+    async fn test_simulate_with_pinned_clock() {
+        let Some(builder) = DBSimulatorBuilder::from_env() else {
+            eprintln!("skipped: {SUI_DB_PATH_ENV} and {SUI_NODE_CONFIG_ENV} are not set");
+            return;
+        };
+        let simulator = builder.build().await.unwrap();
+        let sender = SuiAddress::random_for_testing_only();
+        let recipient = SuiAddress::random_for_testing_only();
+        let timestamp_ms = 1_700_000_000_000;
+
+        let mut ctx = SimulateCtx::new(SimEpoch::default(), vec![]);
+        ctx.with_clock_timestamp_ms(timestamp_ms);
+
+        let mut received = vec![];
+        for _ in 0..2 {
+            let tx = clock_reading_tx(sender, recipient);
+            let resp = simulator.simulate(tx, ctx.clone()).await.unwrap();
+            resp.check_status().unwrap();
+            let bc = resp
+                .balance_changes
+                .iter()
+                .find(|bc| bc.owner == Owner::AddressOwner(recipient))
+                .unwrap();
+            received.push(bc.amount);
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
         }
 
-        // every day, update perpetual tables and clear cache
-        if last_catch_up_time.elapsed() > std::time::Duration::from_secs(3600 * 24) {
-            cache_writer.update_underlying(true);
-            preload_objects(cache_writer.clone(), &preload_ids);
+        assert_eq!(received, vec![timestamp_ms as i128; 2]);
+    }
 
-            last_catch_up_time = std::time::Instant::now();
+    #[tokio::test]
+    async fn test_gas_follows_ctx_epoch() {
+        let Some(builder) = DBSimulatorBuilder::from_env() else {
+            eprintln!("skipped: {SUI_DB_PATH_ENV} and {SUI_NODE_CONFIG_ENV} are not set");
+            return;
+        };
+        let simulator = builder.build().await.unwrap();
+        let sender = SuiAddress::random_for_testing_only();
+        let mut ptb = ProgrammableTransactionBuilder::new();
+        ptb.pay_sui(vec![SuiAddress::random_for_testing_only()], vec![1_000])
+            .unwrap();
+        // priced below the reference gas price of both epochs
+        let tx = TransactionData::new_programmable(sender, vec![], ptb.finish(), 1_000_000_000, 1);
+
+        let mut gas_costs = vec![];
+        for gas_price in [750, 1_500] {
+            let epoch = SimEpoch {
+                gas_price,
+                ..Default::default()
+            };
+            let resp = simulator
+                .simulate(tx.clone(), SimulateCtx::new(epoch, vec![]))
+                .await
+                .unwrap();
+            resp.check_status().unwrap();
+            gas_costs.push(resp.effects.gas_cost_summary().clone());
         }
+
+        assert_eq!(gas_costs[0].computation_cost % 750, 0);
+        assert_eq!(gas_costs[1].computation_cost, 2 * gas_costs[0].computation_cost);
+        assert_eq!(gas_costs[0].storage_cost, gas_costs[1].storage_cost);
+    }
+
+    #[tokio::test]
+    async fn test_pinned_system_state() {
+        let Some(builder) = DBSimulatorBuilder::from_env() else {
+            eprintln!("skipped: {SUI_DB_PATH_ENV} and {SUI_NODE_CONFIG_ENV} are not set");
+            return;
+        };
+        let simulator = builder.build().await.unwrap();
+        let latest = simulator
+            .store
+            .get_object(&SUI_SYSTEM_STATE_OBJECT_ID)
+            .unwrap()
+            .version();
+
+        let overrides = simulator.system_state_overrides(latest).unwrap();
+        assert_eq!(overrides[0].id(), SUI_SYSTEM_STATE_OBJECT_ID);
+        assert_eq!(overrides[0].as_object().unwrap().version(), latest);
+        assert!(overrides[1].as_object().unwrap().version() <= latest);
+
+        // before the object was created
+        assert!(simulator.system_state_overrides(SequenceNumber::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chain_simulations() {
+        let Some(builder) = DBSimulatorBuilder::from_env() else {
+            eprintln!("skipped: {SUI_DB_PATH_ENV} and {SUI_NODE_CONFIG_ENV} are not set");
+            return;
+        };
+        let simulator = builder.build().await.unwrap();
+        let sender = SuiAddress::random_for_testing_only();
+        let recipient = SuiAddress::random_for_testing_only();
+        let written_coin = |resp: &SimulateResult| {
+            resp.object_changes
+                .iter()
+                .filter_map(|o| o.as_object())
+                .find(|o| o.owner == Owner::AddressOwner(sender) && o.id() != mock_gas_id())
+                .cloned()
+                .unwrap()
+        };
+
+        // the first tx creates a coin of 1000 MIST
+        let mut ptb = ProgrammableTransactionBuilder::new();
+        ptb.pay_sui(vec![sender], vec![1_000]).unwrap();
+        let tx = TransactionData::new_programmable(sender, vec![], ptb.finish(), 1_000_000_000, 1_000);
+        let first = simulator
+            .simulate(tx, SimulateCtx::new(SimEpoch::default(), vec![]))
+            .await
+            .unwrap();
+        first.check_status().unwrap();
+        let created = written_coin(&first);
+        assert_eq!(created.as_coin_maybe().unwrap().value(), 1_000);
+
+        // the second tx only exists on top of the first, it sends 400 MIST of the created coin
+        let mut ptb = ProgrammableTransactionBuilder::new();
+        ptb.pay(vec![created.compute_object_reference()], vec![recipient], vec![400])
+            .unwrap();
+        let tx = TransactionData::new_programmable(sender, vec![], ptb.finish(), 1_000_000_000, 1_000);
+        let mut ctx = SimulateCtx::new(SimEpoch::default(), vec![]);
+        ctx.with_previous_result(&first);
+        let second = simulator.simulate(tx, ctx).await.unwrap();
+        second.check_status().unwrap();
+
+        assert!(second
+            .balance_changes
+            .iter()
+            .any(|bc| bc.owner == Owner::AddressOwner(recipient) && bc.amount == 400));
+        let mutated = written_coin(&second);
+        assert_eq!(mutated.id(), created.id());
+        assert_eq!(mutated.as_coin_maybe().unwrap().value(), 600);
+    }
+
+    #[tokio::test]
+    async fn test_simulators_share_layout_cache() {
+        let Some(builder) = DBSimulatorBuilder::from_env() else {
+            eprintln!("skipped: {SUI_DB_PATH_ENV} and {SUI_NODE_CONFIG_ENV} are not set");
+            return;
+        };
+        let layout_cache = Arc::new(LayoutCache::default());
+        let builder = builder.with_layout_cache(layout_cache.clone());
+        let simulators = [builder.clone().build().await.unwrap(), builder.build().await.unwrap()];
+
+        std::thread::scope(|s| {
+            for simulator in &simulators {
+                for _ in 0..4 {
+                    s.spawn(|| {
+                        for _ in 0..100 {
+                            let layout = simulator.get_object_layout(&SUI_CLOCK_OBJECT_ID).unwrap();
+                            assert_eq!(layout.type_, sui_types::clock::Clock::type_());
+                        }
+                    });
+                }
+            }
+        });
+
+        // the Clock layout is resolved at most once by each thread
+        assert_eq!(layout_cache.hits() + layout_cache.misses(), 800);
+        assert!(layout_cache.misses() <= 8, "{}", layout_cache.misses());
+        assert_eq!(layout_cache.len(), 1);
     }
 }
@@ -1,5 +1,6 @@
-use std::collections::BTreeMap;
-use std::sync::{Arc, RwLock};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use sui_core::authority::authority_per_epoch_store::AuthorityPerEpochStore;
@@ -22,10 +23,13 @@ use sui_types::{
     storage::{BackingPackageStore, ChildObjectResolver, ObjectStore, ParentSync},
     transaction::ObjectReadResult,
 };
-use tracing::warn;
+use tracing::{trace, warn};
 
-macro_rules! ret_latest_clock_obj {
-    () => {{
+use super::cache_stats::MissTracker;
+use crate::OverrideMisses;
+
+macro_rules! ret_clock_obj {
+    ($timestamp_ms:expr) => {{
         let object = ObjectInner {
             owner: Owner::Shared {
                 initial_shared_version: OBJECT_START_VERSION,
@@ -40,7 +44,7 @@ macro_rules! ret_latest_clock_obj {
                             bytes: SUI_CLOCK_OBJECT_ID,
                         },
                     },
-                    timestamp_ms: { SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64 },
+                    timestamp_ms: $timestamp_ms,
                 })
                 .unwrap(),
             }),
@@ -54,18 +58,72 @@ macro_rules! ret_latest_clock_obj {
 
 pub struct OverrideCache {
     pub fallback: Option<Arc<WritebackCache>>,
-    pub overrides: Vec<ObjectReadResult>, // usually it's small so vec is fine
+    // a shio item can bring hundreds of objects, and every object read during execution looks up here
+    pub overrides: HashMap<ObjectID, ObjectReadResult>,
 
     // when we reach fallback, we record the versioned object here
     pub versioned_cache: RwLock<BTreeMap<(ObjectID, SequenceNumber), Object>>,
+
+    // reads that reached the fallback, they are only logged one by one if `warn_on_miss` is set
+    misses: Mutex<OverrideMisses>,
+    warn_on_miss: bool,
+
+    // timestamp of the synthesized Clock, `None` means now
+    clock_timestamp_ms: Option<u64>,
+
+    // totals of the simulator that outlive this cache
+    miss_tracker: Option<Arc<MissTracker>>,
 }
 
 impl OverrideCache {
+    /// If an object appears more than once in `overrides`, the latest one wins.
     pub fn new(fallback: Option<Arc<WritebackCache>>, overrides: Vec<ObjectReadResult>) -> Self {
         Self {
             fallback,
-            overrides,
+            overrides: overrides.into_iter().map(|o| (o.id(), o)).collect(),
             versioned_cache: RwLock::new(BTreeMap::new()),
+            misses: Mutex::new(OverrideMisses::default()),
+            warn_on_miss: false,
+            clock_timestamp_ms: None,
+            miss_tracker: None,
+        }
+    }
+
+    /// Warn on every read that misses the overrides, instead of only counting it.
+    pub fn with_miss_warnings(mut self, warn_on_miss: bool) -> Self {
+        self.warn_on_miss = warn_on_miss;
+        self
+    }
+
+    pub fn with_clock_timestamp_ms(mut self, clock_timestamp_ms: Option<u64>) -> Self {
+        self.clock_timestamp_ms = clock_timestamp_ms;
+        self
+    }
+
+    pub(super) fn with_miss_tracker(mut self, miss_tracker: Arc<MissTracker>) -> Self {
+        self.miss_tracker = Some(miss_tracker);
+        self
+    }
+
+    pub fn misses(&self) -> OverrideMisses {
+        *self.misses.lock().unwrap()
+    }
+
+    fn record_miss(
+        &self,
+        counter: fn(&mut OverrideMisses) -> &mut u64,
+        method: &str,
+        object_id: &ObjectID,
+        key: &dyn fmt::Debug,
+    ) {
+        *counter(&mut self.misses.lock().unwrap()) += 1;
+        if let Some(miss_tracker) = &self.miss_tracker {
+            miss_tracker.record_miss(*object_id);
+        }
+        if self.warn_on_miss {
+            warn!("❗️ [{method}] override missing: {:?}", key);
+        } else {
+            trace!("[{method}] override missing: {:?}", key);
         }
     }
 
@@ -77,11 +135,20 @@ impl OverrideCache {
                     initial_shared_version: OBJECT_START_VERSION,
                     mutable: true,
                 },
-                object: ObjectReadResultKind::Object(ret_latest_clock_obj!()),
+                object: ObjectReadResultKind::Object(ret_clock_obj!(self.clock_timestamp_ms())),
             });
         }
 
-        self.overrides.iter().find(|o| o.id() == *object_id).cloned()
+        let object = self.overrides.get(object_id).cloned();
+        if let (Some(_), Some(miss_tracker)) = (&object, &self.miss_tracker) {
+            miss_tracker.record_hit();
+        }
+        object
+    }
+
+    fn clock_timestamp_ms(&self) -> u64 {
+        self.clock_timestamp_ms
+            .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64)
     }
 
     pub fn get_override_object(&self, object_id: &ObjectID) -> Option<Object> {
@@ -188,7 +255,7 @@ impl ObjectCacheRead for OverrideCache {
             }
         }
 
-        warn!("❗️ [get_package_object] override missing: {:?}", id);
+        self.record_miss(|m| &mut m.get_package_object, "get_package_object", id, id);
         if let Some(ref fallback) = self.fallback {
             fallback.get_package_object(id)
         } else {
@@ -214,7 +281,7 @@ impl ObjectCacheRead for OverrideCache {
             }
         }
 
-        warn!("❗️ [get_object] override missing: {:?}", id);
+        self.record_miss(|m| &mut m.get_object, "get_object", id, id);
         if let Some(ref fallback) = self.fallback {
             // if not, check the fallback
             let obj = fallback.get_object(id);
@@ -232,9 +299,11 @@ impl ObjectCacheRead for OverrideCache {
             return Some(override_object.compute_object_reference());
         }
 
-        warn!(
-            "❗️ [get_latest_object_ref_or_tombstone] override missing: {:?}",
-            object_id
+        self.record_miss(
+            |m| &mut m.get_latest_object_ref_or_tombstone,
+            "get_latest_object_ref_or_tombstone",
+            &object_id,
+            &object_id,
         );
         // if it's not found, we lookup in fallback
         // if it's deleted, also lookup in fallback because it's not deleted in fallback
@@ -271,7 +340,12 @@ impl ObjectCacheRead for OverrideCache {
             }
         }
 
-        warn!("❗️ [get_latest_object_or_tombstone] override missing: {:?}", object_id);
+        self.record_miss(
+            |m| &mut m.get_latest_object_or_tombstone,
+            "get_latest_object_or_tombstone",
+            &object_id,
+            &object_id,
+        );
         if let Some(ref fallback) = self.fallback {
             fallback.get_latest_object_or_tombstone(object_id)
         } else {
@@ -294,9 +368,11 @@ impl ObjectCacheRead for OverrideCache {
             }
         }
 
-        warn!(
-            "❗️ [get_object_by_key] override missing: {:?}, version: {:?}",
-            object_id, version
+        self.record_miss(
+            |m| &mut m.get_object_by_key,
+            "get_object_by_key",
+            object_id,
+            &(object_id, version),
         );
         if let Some(ref fallback) = self.fallback {
             fallback.get_object_by_key(object_id, version)
@@ -364,7 +440,7 @@ impl ObjectCacheRead for OverrideCache {
             }
         }
 
-        warn!("❗️ [_get_live_objref] override missing: {:?}", object_id);
+        self.record_miss(|m| &mut m.get_live_objref, "_get_live_objref", &object_id, &object_id);
         if let Some(ref fallback) = self.fallback {
             fallback._get_live_objref(object_id)
         } else {
@@ -409,13 +485,35 @@ impl ObjectCacheRead for OverrideCache {
         version: SequenceNumber,
         epoch_id: EpochId,
     ) -> Option<MarkerValue> {
-        // TODO: implement
+        // an override is the latest state of the object, so it has no markers at or after its version
+        match self.get_override(object_id).map(|o| o.object) {
+            Some(ObjectReadResultKind::DeletedSharedObject(deleted_version, digest)) if deleted_version == version => {
+                return Some(MarkerValue::SharedDeleted(digest));
+            }
+            Some(ObjectReadResultKind::DeletedSharedObject(deleted_version, _)) if deleted_version < version => {
+                return None;
+            }
+            Some(ObjectReadResultKind::Object(object)) if object.version() <= version => return None,
+            _ => {}
+        }
+
         self.fallback.as_ref()?.get_marker_value(object_id, version, epoch_id)
     }
 
     fn get_latest_marker(&self, object_id: &ObjectID, epoch_id: EpochId) -> Option<(SequenceNumber, MarkerValue)> {
-        // TODO: implement
-        self.fallback.as_ref()?.get_latest_marker(object_id, epoch_id)
+        let override_version = match self.get_override(object_id).map(|o| o.object) {
+            Some(ObjectReadResultKind::DeletedSharedObject(version, digest)) => {
+                return Some((version, MarkerValue::SharedDeleted(digest)));
+            }
+            Some(ObjectReadResultKind::Object(object)) => Some(object.version()),
+            _ => None,
+        };
+
+        // markers of the store that the override is newer than still stand
+        self.fallback
+            .as_ref()?
+            .get_latest_marker(object_id, epoch_id)
+            .filter(|(version, _)| override_version.map_or(true, |override_version| *version < override_version))
     }
 
     fn get_highest_pruned_checkpoint(&self) -> CheckpointSequenceNumber {
@@ -426,3 +524,192 @@ impl ObjectCacheRead for OverrideCache {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use sui_types::{base_types::SuiAddress, transaction::InputObjectKind};
+
+    use super::*;
+
+    fn gas_override(object: Object) -> ObjectReadResult {
+        ObjectReadResult::new(
+            InputObjectKind::ImmOrOwnedMoveObject(object.compute_object_reference()),
+            object.into(),
+        )
+    }
+
+    #[test]
+    fn test_lookup_many_overrides() {
+        let owner = SuiAddress::random_for_testing_only();
+        let objects = (0..1_000)
+            .map(|_| Object::new_gas_with_balance_and_owner_for_testing(1_000, owner))
+            .collect::<Vec<_>>();
+        let cache = OverrideCache::new(None, objects.iter().cloned().map(gas_override).collect());
+
+        for object in &objects {
+            let found = cache.get_override_object(&object.id()).unwrap();
+            assert_eq!(found.compute_object_reference(), object.compute_object_reference());
+        }
+        assert!(cache.get_override_object(&ObjectID::random()).is_none());
+    }
+
+    #[test]
+    fn test_count_override_misses() {
+        let object = Object::new_gas_for_testing();
+        let cache = OverrideCache::new(None, vec![gas_override(object.clone())]);
+
+        // everything is overridden
+        assert!((&cache as &dyn ObjectCacheRead).get_object(&object.id()).is_some());
+        assert!((&cache as &dyn ObjectCacheRead)
+            .get_object_by_key(&object.id(), object.version())
+            .is_some());
+        assert_eq!(cache.misses(), OverrideMisses::default());
+
+        // the fallback is hit
+        let missing = ObjectID::random();
+        assert!((&cache as &dyn ObjectCacheRead).get_object(&missing).is_none());
+        assert!((&cache as &dyn ObjectCacheRead).get_object(&missing).is_none());
+        assert!((&cache as &dyn ObjectCacheRead)
+            .get_latest_object_ref_or_tombstone(missing)
+            .is_none());
+        let misses = cache.misses();
+        assert_eq!(misses.get_object, 2);
+        assert_eq!(misses.get_latest_object_ref_or_tombstone, 1);
+        assert_eq!(misses.total(), 3);
+    }
+
+    #[test]
+    fn test_missing_object_in_top_misses() {
+        let object = Object::new_gas_for_testing();
+        let miss_tracker = Arc::new(MissTracker::default());
+        let missing = ObjectID::random();
+
+        // simulations against a store without `missing`
+        for _ in 0..3 {
+            let cache =
+                OverrideCache::new(None, vec![gas_override(object.clone())]).with_miss_tracker(miss_tracker.clone());
+            assert!((&cache as &dyn ObjectCacheRead).get_object(&object.id()).is_some());
+            assert!((&cache as &dyn ObjectCacheRead).get_object(&missing).is_none());
+        }
+        let cache = OverrideCache::new(None, vec![]).with_miss_tracker(miss_tracker.clone());
+        assert!((&cache as &dyn ObjectCacheRead).get_object(&object.id()).is_none());
+
+        assert_eq!(miss_tracker.top_misses(1), vec![(missing, 3)]);
+        assert!(miss_tracker.top_misses(10).contains(&(object.id(), 1)));
+        let stats = miss_tracker.stats();
+        assert_eq!((stats.hits, stats.misses), (3, 4));
+    }
+
+    #[test]
+    fn test_receive_overridden_object() {
+        // an object sent to a parent object, e.g. by the tx of a shio item
+        let parent = ObjectID::random();
+        let object = Object::new_gas_with_balance_and_owner_for_testing(1_000, parent.into());
+        let cache = OverrideCache::new(None, vec![gas_override(object.clone())]);
+
+        let received = cache
+            .get_object_received_at_version(&parent, &object.id(), object.version(), 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(received.compute_object_reference(), object.compute_object_reference());
+        assert!(!cache.have_received_object_at_version(&object.id(), object.version(), 0));
+        assert_eq!(cache.get_marker_value(&object.id(), object.version(), 0), None);
+        assert_eq!(cache.get_latest_marker(&object.id(), 0), None);
+
+        // at another version, or by another owner, it can't be received
+        let next_version = SequenceNumber::from_u64(object.version().value() + 1);
+        assert!(cache
+            .get_object_received_at_version(&parent, &object.id(), next_version, 0)
+            .unwrap()
+            .is_none());
+        assert!(cache
+            .get_object_received_at_version(&ObjectID::random(), &object.id(), object.version(), 0)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_deleted_shared_override_marker() {
+        let id = ObjectID::random();
+        let version = SequenceNumber::from_u64(10);
+        let digest = TransactionDigest::random();
+        let deleted = ObjectReadResult {
+            input_object_kind: InputObjectKind::SharedMoveObject {
+                id,
+                initial_shared_version: OBJECT_START_VERSION,
+                mutable: true,
+            },
+            object: ObjectReadResultKind::DeletedSharedObject(version, digest),
+        };
+        let cache = OverrideCache::new(None, vec![deleted]);
+
+        assert_eq!(
+            cache.get_marker_value(&id, version, 0),
+            Some(MarkerValue::SharedDeleted(digest))
+        );
+        assert_eq!(
+            cache.get_latest_marker(&id, 0),
+            Some((version, MarkerValue::SharedDeleted(digest)))
+        );
+        assert_eq!(
+            cache.get_last_shared_object_deletion_info(&id, 0),
+            Some((version, digest))
+        );
+        assert_eq!(cache.get_marker_value(&id, SequenceNumber::from_u64(11), 0), None);
+    }
+
+    #[test]
+    fn test_latest_override_wins() {
+        let object = Object::new_gas_for_testing();
+        let mut latest = object.clone();
+        latest
+            .data
+            .try_as_move_mut()
+            .unwrap()
+            .increment_version_to(SequenceNumber::from_u64(object.version().value() + 1));
+
+        let cache = OverrideCache::new(None, vec![gas_override(object.clone()), gas_override(latest.clone())]);
+        assert_eq!(
+            cache.get_override_object(&object.id()).unwrap().version(),
+            latest.version()
+        );
+    }
+
+    #[test]
+    fn test_clock_is_always_latest() {
+        // a stale clock passed as an override is ignored
+        let stale_clock = ObjectReadResult::new(
+            InputObjectKind::SharedMoveObject {
+                id: SUI_CLOCK_OBJECT_ID,
+                initial_shared_version: OBJECT_START_VERSION,
+                mutable: false,
+            },
+            Object::new_gas_for_testing().into(),
+        );
+        let cache = OverrideCache::new(None, vec![stale_clock]);
+
+        let clock = cache.get_override_object(&SUI_CLOCK_OBJECT_ID).unwrap();
+        assert_eq!(clock.id(), SUI_CLOCK_OBJECT_ID);
+        assert_eq!(clock.struct_tag(), Some(Clock::type_()));
+    }
+
+    #[test]
+    fn test_pinned_clock() {
+        let timestamp_ms = 1_700_000_000_000;
+        let cache = OverrideCache::new(None, vec![]).with_clock_timestamp_ms(Some(timestamp_ms));
+
+        let read_clock = || {
+            let clock = cache.get_override_object(&SUI_CLOCK_OBJECT_ID).unwrap();
+            bcs::from_bytes::<Clock>(clock.data.try_as_move().unwrap().contents()).unwrap()
+        };
+        assert_eq!(read_clock().timestamp_ms, timestamp_ms);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert_eq!(read_clock().timestamp_ms, timestamp_ms);
+
+        // not pinned
+        let cache = OverrideCache::new(None, vec![]);
+        let clock = cache.get_override_object(&SUI_CLOCK_OBJECT_ID).unwrap();
+        let clock = bcs::from_bytes::<Clock>(clock.data.try_as_move().unwrap().contents()).unwrap();
+        assert!(clock.timestamp_ms > timestamp_ms);
+    }
+}
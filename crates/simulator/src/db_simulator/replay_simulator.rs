@@ -1,4 +1,10 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use async_trait::async_trait;
 use sui_core::execution_cache::ExecutionCacheWrite;
@@ -12,7 +18,35 @@ use tokio::sync::mpsc::{Receiver, Sender};
 
 use crate::{SimulateCtx, SimulateResult, Simulator};
 
-use super::DBSimulator;
+use super::{update_listener::now_ms, DBSimulator, DBSimulatorBuilder};
+
+/// How often the replay simulator reloads the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayIntervals {
+    // if no tx submitted by us recently
+    pub long: Duration,
+    // after we submitted a tx
+    pub short: Duration,
+    // how many reloads use the short interval after a submission
+    pub short_updates: u32,
+}
+
+impl ReplayIntervals {
+    pub const DEFAULT_SHORT_UPDATES: u32 = 50;
+
+    pub fn new(long: Duration, short: Duration) -> Self {
+        Self {
+            long,
+            short,
+            short_updates: Self::DEFAULT_SHORT_UPDATES,
+        }
+    }
+
+    pub fn with_short_updates(mut self, short_updates: u32) -> Self {
+        self.short_updates = short_updates;
+        self
+    }
+}
 
 // A special purpose simulator
 // to ensure execution is always using latest state
@@ -21,6 +55,9 @@ pub struct ReplaySimulator {
 
     // channel for notifying us to update
     pub update_notifier: Arc<Sender<()>>,
+
+    // ms since the unix epoch of the last reload of the store
+    last_update_ms: Arc<AtomicU64>,
 }
 
 impl ReplaySimulator {
@@ -30,52 +67,101 @@ impl ReplaySimulator {
         long_interval: Duration,  // if no tx submitted by us recently, use this interval
         short_interval: Duration, // if we have submitted a tx recently, update more frequently
     ) -> Self {
-        let authority_store = DBSimulator::new_authority_store(store_path, config_path).await;
+        Self::from_builder(
+            DBSimulatorBuilder::new(store_path, config_path),
+            ReplayIntervals::new(long_interval, short_interval),
+        )
+        .await
+        .unwrap_or_else(|e| panic!("{e:#}"))
+    }
+
+    pub async fn from_builder(builder: DBSimulatorBuilder, intervals: ReplayIntervals) -> eyre::Result<Self> {
+        let db_simulator = builder.build().await?;
 
         let (tx, rx) = tokio::sync::mpsc::channel(100);
 
-        let db_simulator = DBSimulator::new(authority_store, None, None, true).await;
         let cache_writeback = db_simulator.store.clone();
+        let last_update_ms = Arc::new(AtomicU64::new(now_ms()));
+        let thread_last_update_ms = last_update_ms.clone();
 
         std::thread::Builder::new()
             .name("replay-update-thread".to_string())
-            .spawn(move || Self::spawn_update_loop(rx, cache_writeback, short_interval, long_interval))
+            .spawn(move || {
+                Self::spawn_update_loop(
+                    rx,
+                    move || cache_writeback.update_underlying(true),
+                    intervals,
+                    thread_last_update_ms,
+                )
+            })
             .unwrap();
 
-        Self {
+        Ok(Self {
             fallback: db_simulator,
             update_notifier: Arc::new(tx),
-        }
+            last_update_ms,
+        })
+    }
+
+    /// How long ago the store was last reloaded, the state of the simulations is at most this old.
+    pub fn lag(&self) -> Duration {
+        lag_since(&self.last_update_ms)
+    }
+
+    pub fn with_override_miss_warnings(mut self, warn_override_misses: bool) -> Self {
+        self.fallback = self.fallback.with_override_miss_warnings(warn_override_misses);
+        self
     }
 
     #[tokio::main]
     async fn spawn_update_loop(
-        mut receiver: Receiver<()>,
-        cache_writeback: Arc<dyn ExecutionCacheWrite>,
-        short_interval: Duration,
-        long_interval: Duration,
+        receiver: Receiver<()>,
+        update: impl Fn(),
+        intervals: ReplayIntervals,
+        last_update_ms: Arc<AtomicU64>,
     ) {
-        let mut quick_update_times = 0;
-        let mut current_interval = long_interval;
-        loop {
-            // Sleep for the current interval
-            tokio::time::sleep(current_interval).await;
-
-            // Check if we received any update notifications
-            while receiver.try_recv().is_ok() {
-                quick_update_times = 50;
-            }
+        update_loop(receiver, update, intervals, &last_update_ms).await
+    }
+}
 
-            // Update the cache
-            cache_writeback.update_underlying(true);
+fn lag_since(last_update_ms: &AtomicU64) -> Duration {
+    Duration::from_millis(now_ms().saturating_sub(last_update_ms.load(Ordering::Relaxed)))
+}
 
-            // Update interval based on quick_update_times
-            if quick_update_times > 0 {
-                current_interval = short_interval;
-                quick_update_times -= 1;
-            } else {
-                current_interval = long_interval;
-            }
+// Reloads the store every interval, and right away when notified of a tx we submitted. Ends when the
+// simulator is dropped.
+async fn update_loop(
+    mut receiver: Receiver<()>,
+    update: impl Fn(),
+    intervals: ReplayIntervals,
+    last_update_ms: &AtomicU64,
+) {
+    let mut quick_update_times = 0;
+    let mut current_interval = intervals.long;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(current_interval) => {}
+            notification = receiver.recv() => match notification {
+                Some(()) => quick_update_times = intervals.short_updates,
+                None => return,
+            },
+        }
+
+        // Check if we received any more update notifications
+        while receiver.try_recv().is_ok() {
+            quick_update_times = intervals.short_updates;
+        }
+
+        // Update the cache
+        update();
+        last_update_ms.store(now_ms(), Ordering::Relaxed);
+
+        // Update interval based on quick_update_times
+        if quick_update_times > 0 {
+            current_interval = intervals.short;
+            quick_update_times -= 1;
+        } else {
+            current_interval = intervals.long;
         }
     }
 }
@@ -99,7 +185,72 @@ impl Simulator for ReplaySimulator {
         self.fallback.get_object(obj_id).await
     }
 
+    async fn multi_get_objects(&self, ids: &[ObjectID]) -> Vec<Option<Object>> {
+        self.fallback.multi_get_objects(ids).await
+    }
+
+    async fn get_dynamic_children(&self, parent: &ObjectID) -> eyre::Result<Vec<ObjectID>> {
+        self.fallback.get_dynamic_children(parent).await
+    }
+
     fn name(&self) -> &str {
         "ReplaySimulator"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    async fn wait_until(condition: impl Fn() -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("timed out");
+    }
+
+    #[tokio::test]
+    async fn test_lag_goes_down_after_submission() {
+        let intervals =
+            ReplayIntervals::new(Duration::from_secs(3600), Duration::from_millis(10)).with_short_updates(3);
+        let (notifier, receiver) = tokio::sync::mpsc::channel(100);
+        let last_update_ms = Arc::new(AtomicU64::new(now_ms()));
+        let updates = Arc::new(AtomicUsize::new(0));
+
+        let handle = {
+            let last_update_ms = last_update_ms.clone();
+            let updates = updates.clone();
+            tokio::spawn(async move {
+                let update = move || {
+                    updates.fetch_add(1, Ordering::Relaxed);
+                };
+                update_loop(receiver, update, intervals, &last_update_ms).await
+            })
+        };
+
+        // nothing is reloaded within the long interval
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(lag_since(&last_update_ms) >= Duration::from_millis(100));
+        assert_eq!(updates.load(Ordering::Relaxed), 0);
+
+        // a submission reloads right away, then on the short interval
+        notifier.send(()).await.unwrap();
+        wait_until(|| updates.load(Ordering::Relaxed) == 1).await;
+        assert!(lag_since(&last_update_ms) < Duration::from_millis(100));
+        wait_until(|| updates.load(Ordering::Relaxed) == 4).await;
+
+        // and falls back to the long interval
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(updates.load(Ordering::Relaxed), 4);
+        assert!(lag_since(&last_update_ms) >= Duration::from_millis(100));
+
+        // dropping the simulator ends the loop
+        drop(notifier);
+        handle.await.unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use sui_types::{base_types::ObjectID, object::Object};
+use tracing::{info, warn};
+
+use super::update_source::{ObjectUpdateSource, UpdateStream};
+
+pub const DEFAULT_CATCH_UP_INTERVAL: Duration = Duration::from_secs(3600 * 24);
+
+const MIN_RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The state of the update feed, written by the update thread.
+#[derive(Debug, Default)]
+pub struct UpdateHealth {
+    connected: AtomicBool,
+    // ms since the unix epoch, 0 before the first batch
+    last_update_ms: AtomicU64,
+    reconnects: AtomicU64,
+}
+
+impl UpdateHealth {
+    /// False while the update feed is disconnected, the store serves stale objects then.
+    pub fn is_healthy(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// When the last update batch was applied, in ms since the unix epoch.
+    pub fn last_update_ms(&self) -> Option<u64> {
+        match self.last_update_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(ms),
+        }
+    }
+
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+}
+
+/// Applies the object updates that the sui node sends over its update feed. The listener reconnects
+/// with exponential backoff when the feed fails, and catches up with the store after a reconnect,
+/// since the updates sent while disconnected are lost.
+pub(super) struct UpdateListener<A, C> {
+    pub source: ObjectUpdateSource,
+    pub catch_up_interval: Duration,
+    pub health: Arc<UpdateHealth>,
+    // applies a batch of updated objects
+    pub apply: A,
+    // reloads the underlying store
+    pub catch_up: C,
+}
+
+impl<A, C> UpdateListener<A, C>
+where
+    A: Fn(Vec<(ObjectID, Object)>),
+    C: Fn(),
+{
+    pub async fn run(self) {
+        let mut backoff = MIN_RECONNECT_BACKOFF;
+        let mut connected_before = false;
+        let mut last_catch_up_time = Instant::now();
+
+        loop {
+            match self.source.connect().await {
+                Ok(stream) => {
+                    if connected_before {
+                        self.health.reconnects.fetch_add(1, Ordering::Relaxed);
+                        info!(source = %self.source, "update feed reconnected, catching up");
+                        (self.catch_up)();
+                        last_catch_up_time = Instant::now();
+                    }
+                    connected_before = true;
+                    self.health.connected.store(true, Ordering::Relaxed);
+                    backoff = MIN_RECONNECT_BACKOFF;
+
+                    let error = self.read_updates(stream, &mut last_catch_up_time).await;
+                    self.health.connected.store(false, Ordering::Relaxed);
+                    warn!(source = %self.source, "update feed failed, objects are stale until reconnected: {error}");
+                }
+                Err(error) => {
+                    warn!(source = %self.source, ?backoff, "failed to connect to update feed: {error}");
+                }
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+        }
+    }
+
+    // applies updates until the feed fails
+    async fn read_updates(&self, mut stream: UpdateStream, last_catch_up_time: &mut Instant) -> std::io::Error {
+        loop {
+            match stream.next_batch().await {
+                Ok(Some(objects)) => {
+                    (self.apply)(objects);
+                    self.health.last_update_ms.store(now_ms(), Ordering::Relaxed);
+                }
+                Ok(None) => {}
+                Err(e) => return e,
+            }
+
+            // update perpetual tables and clear cache
+            if last_catch_up_time.elapsed() > self.catch_up_interval {
+                (self.catch_up)();
+                *last_catch_up_time = Instant::now();
+            }
+        }
+    }
+}
+
+pub(super) fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use tokio::net::UnixListener;
+
+    use super::{
+        super::update_source::{write_update_batch, UpdateCompression},
+        *,
+    };
+
+    async fn wait_until(condition: impl Fn() -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("timed out");
+    }
+
+    #[tokio::test]
+    async fn test_updates_resume_after_reconnect() {
+        let socket_path = std::env::temp_dir().join(format!("sim-updates-{}.sock", ObjectID::random()));
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        let applied = Arc::new(AtomicUsize::new(0));
+        let catch_ups = Arc::new(AtomicUsize::new(0));
+        let health = Arc::new(UpdateHealth::default());
+        let update_listener = UpdateListener {
+            source: ObjectUpdateSource::unix(&socket_path),
+            catch_up_interval: DEFAULT_CATCH_UP_INTERVAL,
+            health: health.clone(),
+            apply: {
+                let applied = applied.clone();
+                move |objects: Vec<(ObjectID, Object)>| {
+                    applied.fetch_add(objects.len(), Ordering::Relaxed);
+                }
+            },
+            catch_up: {
+                let catch_ups = catch_ups.clone();
+                move || {
+                    catch_ups.fetch_add(1, Ordering::Relaxed);
+                }
+            },
+        };
+        let handle = tokio::spawn(update_listener.run());
+
+        let object = Object::new_gas_for_testing();
+        let (mut node, _) = listener.accept().await.unwrap();
+        write_update_batch(&mut node, &[(object.id(), object.clone())], UpdateCompression::None)
+            .await
+            .unwrap();
+        wait_until(|| applied.load(Ordering::Relaxed) == 1).await;
+        assert!(health.is_healthy());
+        let first_update_ms = health.last_update_ms().unwrap();
+
+        // the node goes away
+        drop(node);
+        wait_until(|| !health.is_healthy()).await;
+
+        // and comes back, the listener catches up and applies updates again
+        let (mut node, _) = listener.accept().await.unwrap();
+        write_update_batch(&mut node, &[(object.id(), object)], UpdateCompression::None)
+            .await
+            .unwrap();
+        wait_until(|| applied.load(Ordering::Relaxed) == 2).await;
+        assert!(health.is_healthy());
+        assert_eq!(health.reconnects(), 1);
+        assert_eq!(catch_ups.load(Ordering::Relaxed), 1);
+        assert!(health.last_update_ms().unwrap() >= first_update_ms);
+
+        handle.abort();
+        let _ = std::fs::remove_file(socket_path);
+    }
+}
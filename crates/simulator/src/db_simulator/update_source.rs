@@ -0,0 +1,305 @@
+use std::{fmt, io, path::PathBuf, str::FromStr};
+
+use eyre::{bail, eyre};
+use sui_types::{base_types::ObjectID, object::Object};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UnixStream},
+};
+use tracing::warn;
+
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_ZSTD: u8 = 1;
+
+/// Compression of the update frames. Compression is negotiated by a one-byte handshake, the client sends
+/// the compression it asks for and the node answers with the compression it will use. Without compression
+/// there is no handshake, so nodes that predate it keep working.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UpdateCompression {
+    #[default]
+    None,
+    Zstd,
+}
+
+impl UpdateCompression {
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            COMPRESSION_NONE => Ok(Self::None),
+            COMPRESSION_ZSTD => Ok(Self::Zstd),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown update compression: {byte}"),
+            )),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::None => COMPRESSION_NONE,
+            Self::Zstd => COMPRESSION_ZSTD,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateAddress {
+    Unix(PathBuf),
+    // host:port
+    Tcp(String),
+}
+
+/// Where a DBSimulator receives object updates from, e.g. `unix:///tmp/sui_cache_updates.sock` or
+/// `tcp://10.0.0.2:9100?compression=zstd`. A bare path is a unix socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectUpdateSource {
+    pub address: UpdateAddress,
+    pub compression: UpdateCompression,
+}
+
+impl ObjectUpdateSource {
+    pub fn unix(path: impl Into<PathBuf>) -> Self {
+        Self {
+            address: UpdateAddress::Unix(path.into()),
+            compression: UpdateCompression::None,
+        }
+    }
+
+    pub fn tcp(addr: impl Into<String>) -> Self {
+        Self {
+            address: UpdateAddress::Tcp(addr.into()),
+            compression: UpdateCompression::None,
+        }
+    }
+
+    pub fn with_compression(mut self, compression: UpdateCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub(super) async fn connect(&self) -> io::Result<UpdateStream> {
+        match &self.address {
+            UpdateAddress::Unix(path) => UpdateStream::new(UnixStream::connect(path).await?, self.compression).await,
+            UpdateAddress::Tcp(addr) => {
+                let stream = TcpStream::connect(addr).await?;
+                stream.set_nodelay(true)?;
+                UpdateStream::new(stream, self.compression).await
+            }
+        }
+    }
+}
+
+impl FromStr for ObjectUpdateSource {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (location, query) = s.split_once('?').unwrap_or((s, ""));
+
+        let address = if let Some(path) = location.strip_prefix("unix://") {
+            UpdateAddress::Unix(PathBuf::from(path))
+        } else if let Some(addr) = location.strip_prefix("tcp://") {
+            if addr.rsplit_once(':').is_none() {
+                bail!("tcp update source needs a port: {s:?}");
+            }
+            UpdateAddress::Tcp(addr.to_string())
+        } else if location.contains("://") {
+            bail!("unsupported update source: {s:?}");
+        } else {
+            UpdateAddress::Unix(PathBuf::from(location))
+        };
+
+        let compression = match query {
+            "" | "compression=none" => UpdateCompression::None,
+            "compression=zstd" => UpdateCompression::Zstd,
+            _ => return Err(eyre!("unsupported update source option {query:?} in {s:?}")),
+        };
+
+        Ok(Self { address, compression })
+    }
+}
+
+impl fmt::Display for ObjectUpdateSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.address {
+            UpdateAddress::Unix(path) => write!(f, "unix://{}", path.display())?,
+            UpdateAddress::Tcp(addr) => write!(f, "tcp://{addr}")?,
+        }
+        if self.compression == UpdateCompression::Zstd {
+            write!(f, "?compression=zstd")?;
+        }
+        Ok(())
+    }
+}
+
+/// A connected update feed, every frame is a u32 little-endian length and a bcs `Vec<(ObjectID, Object)>`,
+/// the bcs bytes are compressed if negotiated.
+pub(super) struct UpdateStream {
+    reader: Box<dyn AsyncRead + Unpin + Send>,
+    compression: UpdateCompression,
+}
+
+impl UpdateStream {
+    async fn new<S>(mut stream: S, requested: UpdateCompression) -> io::Result<Self>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let compression = match requested {
+            UpdateCompression::None => UpdateCompression::None,
+            _ => {
+                stream.write_u8(requested.to_byte()).await?;
+                UpdateCompression::from_byte(stream.read_u8().await?)?
+            }
+        };
+        if compression != requested {
+            warn!(
+                ?requested,
+                ?compression,
+                "update source doesn't support the compression"
+            );
+        }
+
+        Ok(Self {
+            reader: Box::new(stream),
+            compression,
+        })
+    }
+
+    /// The next batch of updated objects, `None` if the batch can't be decoded.
+    pub async fn next_batch(&mut self) -> io::Result<Option<Vec<(ObjectID, Object)>>> {
+        // Read length prefix
+        let mut buf = [0u8; 4];
+        self.reader.read_exact(&mut buf).await?;
+        let len = u32::from_le_bytes(buf) as usize;
+
+        // Read payload
+        let mut payload = vec![0u8; len];
+        self.reader.read_exact(&mut payload).await?;
+
+        if self.compression == UpdateCompression::Zstd {
+            payload = match zstd::decode_all(payload.as_slice()) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    warn!("Error decompressing cache update: {}", e);
+                    return Ok(None);
+                }
+            };
+        }
+
+        match bcs::from_bytes::<Vec<(ObjectID, Object)>>(&payload) {
+            Ok(objects) => Ok(Some(objects)),
+            Err(e) => {
+                warn!("Error deserializing cache update: {}", e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// The node side of the handshake, answers a client that asks for compression. Only for clients that
+/// ask for compression, see `UpdateCompression`.
+pub async fn accept_update_handshake<S>(stream: &mut S) -> io::Result<UpdateCompression>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let compression = UpdateCompression::from_byte(stream.read_u8().await?).unwrap_or(UpdateCompression::None);
+    stream.write_u8(compression.to_byte()).await?;
+    Ok(compression)
+}
+
+/// The node side of the framing, writes a batch of updated objects.
+pub async fn write_update_batch<S>(
+    stream: &mut S,
+    objects: &[(ObjectID, Object)],
+    compression: UpdateCompression,
+) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut payload = bcs::to_bytes(objects).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if compression == UpdateCompression::Zstd {
+        payload = zstd::encode_all(payload.as_slice(), 0)?;
+    }
+
+    stream.write_all(&(payload.len() as u32).to_le_bytes()).await?;
+    stream.write_all(&payload).await?;
+    stream.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_update_source() {
+        let source = ObjectUpdateSource::from_str("unix:///tmp/sui_cache_updates.sock").unwrap();
+        assert_eq!(source, ObjectUpdateSource::unix("/tmp/sui_cache_updates.sock"));
+        // the config value of before
+        assert_eq!(
+            ObjectUpdateSource::from_str("/tmp/sui_cache_updates.sock").unwrap(),
+            source
+        );
+
+        let source = ObjectUpdateSource::from_str("tcp://10.0.0.2:9100?compression=zstd").unwrap();
+        assert_eq!(
+            source,
+            ObjectUpdateSource::tcp("10.0.0.2:9100").with_compression(UpdateCompression::Zstd)
+        );
+        assert_eq!(source.to_string(), "tcp://10.0.0.2:9100?compression=zstd");
+
+        assert!(ObjectUpdateSource::from_str("tcp://10.0.0.2").is_err());
+        assert!(ObjectUpdateSource::from_str("http://10.0.0.2:9100").is_err());
+        assert!(ObjectUpdateSource::from_str("tcp://10.0.0.2:9100?compression=lz4").is_err());
+    }
+
+    async fn round_trip(compression: UpdateCompression) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let source = ObjectUpdateSource::tcp(listener.local_addr().unwrap().to_string()).with_compression(compression);
+
+        let batches = (1..=3)
+            .map(|n| {
+                (0..n * 10)
+                    .map(|_| {
+                        let object = Object::new_gas_for_testing();
+                        (object.id(), object)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let node = {
+            let batches = batches.clone();
+            tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let compression = match compression {
+                    UpdateCompression::None => UpdateCompression::None,
+                    _ => accept_update_handshake(&mut stream).await.unwrap(),
+                };
+                for batch in &batches {
+                    write_update_batch(&mut stream, batch, compression).await.unwrap();
+                }
+            })
+        };
+
+        let mut stream = source.connect().await.unwrap();
+        assert_eq!(stream.compression, compression);
+        for batch in &batches {
+            let received = stream.next_batch().await.unwrap().unwrap();
+            assert_eq!(&received, batch);
+        }
+        node.await.unwrap();
+
+        // the node closed the stream
+        assert!(stream.next_batch().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_round_trip() {
+        round_trip(UpdateCompression::None).await;
+    }
+
+    #[tokio::test]
+    async fn test_tcp_round_trip_with_zstd() {
+        round_trip(UpdateCompression::Zstd).await;
+    }
+}
@@ -0,0 +1,218 @@
+use std::{any::Any, fmt};
+
+use sui_json_rpc_types::SuiExecutionStatus;
+use sui_types::{
+    base_types::ObjectID,
+    error::{SuiError, UserInputError},
+};
+
+/// Errors of a simulation that callers may want to tell apart. Simulators return them inside an
+/// `eyre::Report`, use `error.downcast_ref::<SimulatorError>()` to match on them.
+#[derive(Debug)]
+pub enum SimulatorError {
+    /// An input object or package of the tx is neither in the store nor in the override objects.
+    InputObjectMissing { id: ObjectID },
+    /// The gas budget or gas price of the tx is rejected.
+    GasError { message: String },
+    /// The tx was executed but failed, e.g. a Move abort.
+    ExecutionFailure { status: SuiExecutionStatus },
+    /// The executor panicked while executing the tx.
+    ExecutorPanic { message: String },
+    /// The rpc request of a remote simulation failed.
+    Rpc { source: sui_sdk::error::Error },
+}
+
+impl SimulatorError {
+    /// Maps the errors of reading input objects, errors other than a missing object are not a `SimulatorError`.
+    pub fn from_input_error(error: SuiError) -> eyre::Report {
+        match error {
+            SuiError::UserInputError {
+                error: UserInputError::ObjectNotFound { object_id: id, .. },
+            }
+            | SuiError::UserInputError {
+                error: UserInputError::DependentPackageNotFound { package_id: id },
+            } => Self::InputObjectMissing { id }.into(),
+            error => eyre::eyre!(error),
+        }
+    }
+
+    pub fn executor_panic(payload: Box<dyn Any + Send>) -> Self {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown panic payload".to_string()
+        };
+        Self::ExecutorPanic { message }
+    }
+
+    /// True if the tx was executed and aborted in Move code.
+    pub fn is_move_abort(&self) -> bool {
+        self.failure_kind() == Some("MoveAbort")
+    }
+
+    /// True if the tx was executed and failed because the sender can't pay an amount.
+    pub fn is_insufficient_balance(&self) -> bool {
+        self.failure_kind() == Some("InsufficientCoinBalance")
+    }
+
+    /// Where the tx aborted in Move code, `None` if it didn't abort or the location can't be parsed.
+    pub fn move_abort_location(&self) -> Option<MoveAbortLocation> {
+        if !self.is_move_abort() {
+            return None;
+        }
+        let Self::ExecutionFailure {
+            status: SuiExecutionStatus::Failure { error },
+        } = self
+        else {
+            return None;
+        };
+        MoveAbortLocation::parse(error)
+    }
+
+    // the status of a failed tx is the debug string of its `ExecutionFailureStatus`, e.g.
+    // "MoveAbort(MoveLocation { .. }, 1) in command 2", the kind is the variant name
+    fn failure_kind(&self) -> Option<&str> {
+        let Self::ExecutionFailure {
+            status: SuiExecutionStatus::Failure { error },
+        } = self
+        else {
+            return None;
+        };
+        error
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .next()
+            .filter(|kind| !kind.is_empty())
+    }
+}
+
+/// The Move function a tx aborted in, parsed from the status of a failed tx, e.g.
+/// "MoveAbort(MoveLocation { module: ModuleId { address: 0x2, name: Identifier(\"balance\") }, function: 7,
+/// instruction: 10, function_name: Some(\"split\") }, 2) in command 3".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoveAbortLocation {
+    pub package: ObjectID,
+    pub module: String,
+    pub function: Option<String>,
+    pub abort_code: u64,
+    /// The index of the aborted command in the programmable tx.
+    pub command: Option<u16>,
+}
+
+impl MoveAbortLocation {
+    fn parse(error: &str) -> Option<Self> {
+        let address = between(error, "address: ", ",")?;
+        // the address is printed without the 0x prefix, except in short form
+        let package = ObjectID::from_hex_literal(&format!("0x{}", address.trim_start_matches("0x"))).ok()?;
+        let module = between(error, "name: Identifier(\"", "\"")?.to_string();
+        let function = between(error, "function_name: Some(\"", "\"").map(|f| f.to_string());
+
+        // the code follows the location: "MoveAbort(MoveLocation { .. }, 2)"
+        let (location, rest) = error.rsplit_once("}, ")?;
+        if !location.starts_with("MoveAbort(") {
+            return None;
+        }
+        let abort_code = rest.split(')').next()?.trim().parse().ok()?;
+        let command = rest
+            .split_once(" in command ")
+            .and_then(|(_, command)| command.trim().parse().ok());
+
+        Some(Self {
+            package,
+            module,
+            function,
+            abort_code,
+            command,
+        })
+    }
+
+    /// True if the abort is in the Move or Sui framework, e.g. splitting a coin with a too small balance.
+    pub fn is_framework(&self) -> bool {
+        self.package == ObjectID::from_single_byte(1) || self.package == ObjectID::from_single_byte(2)
+    }
+}
+
+// the text between the first `start` and the following `end`
+fn between<'a>(s: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let (_, rest) = s.split_once(start)?;
+    rest.split_once(end).map(|(value, _)| value)
+}
+
+impl fmt::Display for SimulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InputObjectMissing { id } => write!(f, "input object {id} not found"),
+            Self::GasError { message } => write!(f, "gas error: {message}"),
+            Self::ExecutionFailure { status } => write!(f, "execution failed: {status:?}"),
+            Self::ExecutorPanic { message } => write!(f, "executor panicked: {message}"),
+            Self::Rpc { source } => write!(f, "rpc error: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for SimulatorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Rpc { source } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure(error: &str) -> SimulatorError {
+        SimulatorError::ExecutionFailure {
+            status: SuiExecutionStatus::Failure {
+                error: error.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_execution_failure_kind() {
+        let abort = failure("MoveAbort(MoveLocation { module: ModuleId { address: 0x2, name: Identifier(\"balance\") }, function: 7, instruction: 10, function_name: Some(\"split\") }, 2) in command 3");
+        assert!(abort.is_move_abort());
+        assert!(!abort.is_insufficient_balance());
+
+        assert!(failure("InsufficientCoinBalance in command 0").is_insufficient_balance());
+        assert!(!failure("InsufficientGas").is_move_abort());
+        assert!(!failure("").is_move_abort());
+        assert!(!SimulatorError::GasError {
+            message: "MoveAbort".to_string()
+        }
+        .is_move_abort());
+    }
+
+    #[test]
+    fn test_move_abort_location() {
+        let abort = failure("MoveAbort(MoveLocation { module: ModuleId { address: 0x2, name: Identifier(\"balance\") }, function: 7, instruction: 10, function_name: Some(\"split\") }, 2) in command 3");
+        let location = abort.move_abort_location().unwrap();
+        assert_eq!(location.package, ObjectID::from_single_byte(2));
+        assert_eq!(location.module, "balance");
+        assert_eq!(location.function.as_deref(), Some("split"));
+        assert_eq!(location.abort_code, 2);
+        assert_eq!(location.command, Some(3));
+        assert!(location.is_framework());
+
+        let abort = failure("MoveAbort(MoveLocation { module: ModuleId { address: 1eabed72c53feb3805120a081dc15963c204dc8d091542592abaf7a35689b2fb, name: Identifier(\"pool\") }, function: 54, instruction: 29, function_name: None }, 7) in command 1");
+        let location = abort.move_abort_location().unwrap();
+        assert_eq!(
+            location.package,
+            ObjectID::from_hex_literal("0x1eabed72c53feb3805120a081dc15963c204dc8d091542592abaf7a35689b2fb").unwrap()
+        );
+        assert_eq!(location.module, "pool");
+        assert_eq!(location.function, None);
+        assert_eq!(location.abort_code, 7);
+        assert_eq!(location.command, Some(1));
+        assert!(!location.is_framework());
+
+        assert!(failure("InsufficientCoinBalance in command 0")
+            .move_abort_location()
+            .is_none());
+        assert!(failure("MoveAbort(garbage)").move_abort_location().is_none());
+    }
+}
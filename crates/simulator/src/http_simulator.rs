@@ -1,13 +1,30 @@
 use async_trait::async_trait;
-use sui_json_rpc_types::SuiObjectDataOptions;
+use eyre::ensure;
+use futures::future::join_all;
+use sui_json_rpc_types::{BalanceChange, SuiObjectDataOptions, SuiTransactionBlockEffectsAPI};
 use sui_sdk::{rpc_types::SuiProtocolConfigValue, SuiClient, SuiClientBuilder};
-use sui_types::{base_types::ObjectID, object::Object, transaction::TransactionData};
+use sui_types::{
+    base_types::ObjectID,
+    gas_coin::GAS,
+    object::{Object, Owner},
+    transaction::{TransactionData, TransactionDataAPI},
+};
+use tracing::debug;
 
-use super::{SimulateCtx, SimulateResult, Simulator};
+use super::{mock_gas_coin, SimulateCtx, SimulateResult, Simulator, SimulatorError};
+
+/// Simulates over the json rpc of a sui node, with the override dry run of our nodes by default.
+///
+/// Balance changes have the shape of `DBSimulator`: a tx without gas coins pays with the mock gas coin,
+/// which is charged to the sender's SUI balance, and borrowed coins are repaid from the sender's balances.
+/// `object_changes` stay empty, the rpc doesn't return the contents of written objects.
+// the max number of objects per `sui_multiGetObjects` request
+const MULTI_GET_CHUNK_SIZE: usize = 50;
 
 #[derive(Clone)]
 pub struct HttpSimulator {
     pub client: SuiClient,
+    dev_inspect: bool,
 }
 
 impl HttpSimulator {
@@ -20,7 +37,16 @@ impl HttpSimulator {
         }
         let client = builder.build(url).await.unwrap();
 
-        Self { client }
+        Self {
+            client,
+            dev_inspect: false,
+        }
+    }
+
+    /// Simulate with `dev_inspect_transaction_block` instead of the override dry run, see `dev_inspect`.
+    pub fn with_dev_inspect(mut self, dev_inspect: bool) -> Self {
+        self.dev_inspect = dev_inspect;
+        self
     }
 
     pub async fn max_budget(&self) -> u64 {
@@ -37,23 +63,30 @@ impl HttpSimulator {
 
         *max
     }
-}
 
-#[async_trait]
-impl Simulator for HttpSimulator {
-    // NOTE: Does not return object_changes
-    async fn simulate(&self, tx: TransactionData, ctx: SimulateCtx) -> eyre::Result<SimulateResult> {
-        let override_objects = ctx
+    /// Dry runs `tx` with the override objects, the borrowed coins and, without gas coins, the mock gas coin
+    /// as owned objects of the sender. The node reports the spent borrowed coins in the balance changes,
+    /// which is the repayment that `DBSimulator` subtracts.
+    pub async fn dry_run(&self, mut tx: TransactionData, ctx: SimulateCtx) -> eyre::Result<SimulateResult> {
+        let mut override_objects = ctx
             .override_objects
             .into_iter()
             .filter_map(|o| o.as_object().map(|obj| (obj.id(), obj.clone())))
             .collect::<Vec<_>>();
+        override_objects.extend(ctx.borrowed_coins.into_iter().map(|(coin, _)| (coin.id(), coin)));
+
+        if tx.gas().is_empty() {
+            let gas_coin = mock_gas_coin(tx.sender());
+            tx.gas_data_mut().payment = vec![gas_coin.compute_object_reference()];
+            override_objects.push((gas_coin.id(), gas_coin));
+        }
 
         let resp = self
             .client
             .read_api()
             .dry_run_transaction_block_override(tx, override_objects)
-            .await?;
+            .await
+            .map_err(|source| SimulatorError::Rpc { source })?;
 
         Ok(SimulateResult {
             effects: resp.effects,
@@ -61,9 +94,79 @@ impl Simulator for HttpSimulator {
             object_changes: vec![],
             balance_changes: resp.balance_changes,
             cache_misses: 0,
+            override_misses: Default::default(),
         })
     }
 
+    /// Executes `tx` with `dev_inspect_transaction_block`, which needs no gas coins, against the state of the
+    /// node. Override objects and borrowed coins are not supported. Dev inspect returns no balance changes,
+    /// only the gas charged to the sender is known.
+    pub async fn dev_inspect(&self, tx: TransactionData, ctx: SimulateCtx) -> eyre::Result<SimulateResult> {
+        ensure!(
+            ctx.override_objects.is_empty() && ctx.borrowed_coins.is_empty(),
+            "dev inspect doesn't support override objects or borrowed coins"
+        );
+
+        let sender = tx.sender();
+        let gas_price = tx.gas_price();
+        let resp = self
+            .client
+            .read_api()
+            .dev_inspect_transaction_block(
+                sender,
+                tx.into_kind(),
+                Some(gas_price.into()),
+                Some(ctx.epoch.epoch_id.into()),
+                None,
+            )
+            .await
+            .map_err(|source| SimulatorError::Rpc { source })?;
+        if let Some(error) = &resp.error {
+            debug!("dev inspect error: {error}");
+        }
+
+        let gas_used = resp.effects.gas_cost_summary().net_gas_usage();
+        let balance_changes = (gas_used != 0)
+            .then(|| BalanceChange {
+                owner: Owner::AddressOwner(sender),
+                coin_type: GAS::type_tag(),
+                amount: -(gas_used as i128),
+            })
+            .into_iter()
+            .collect();
+
+        Ok(SimulateResult {
+            effects: resp.effects,
+            events: resp.events,
+            object_changes: vec![],
+            balance_changes,
+            cache_misses: 0,
+            override_misses: Default::default(),
+        })
+    }
+}
+
+#[async_trait]
+impl Simulator for HttpSimulator {
+    async fn simulate(&self, tx: TransactionData, ctx: SimulateCtx) -> eyre::Result<SimulateResult> {
+        ensure!(
+            ctx.system_state_version.is_none(),
+            "HttpSimulator can't pin the system state version"
+        );
+
+        if self.dev_inspect {
+            self.dev_inspect(tx, ctx).await
+        } else {
+            self.dry_run(tx, ctx).await
+        }
+    }
+
+    // the override dry run is a custom method that the client can't batch into one request, but the client
+    // multiplexes concurrent requests over its connection pool, so a batch costs about one round trip
+    async fn simulate_many(&self, txs: Vec<(TransactionData, SimulateCtx)>) -> Vec<eyre::Result<SimulateResult>> {
+        join_all(txs.into_iter().map(|(tx, ctx)| self.simulate(tx, ctx))).await
+    }
+
     fn name(&self) -> &str {
         "HttpSimulator"
     }
@@ -78,4 +181,44 @@ impl Simulator for HttpSimulator {
             .try_into()
             .ok()
     }
+
+    async fn multi_get_objects(&self, ids: &[ObjectID]) -> Vec<Option<Object>> {
+        let mut objects = Vec::with_capacity(ids.len());
+        for chunk in ids.chunks(MULTI_GET_CHUNK_SIZE) {
+            match self
+                .client
+                .read_api()
+                .multi_get_object_with_options(chunk.to_vec(), SuiObjectDataOptions::bcs_lossless())
+                .await
+            {
+                Ok(resps) => objects.extend(resps.into_iter().map(|resp| resp.data?.try_into().ok())),
+                Err(error) => {
+                    debug!(?error, "multi_get_objects failed");
+                    objects.extend(chunk.iter().map(|_| None));
+                }
+            }
+        }
+        objects
+    }
+
+    async fn get_dynamic_children(&self, parent: &ObjectID) -> eyre::Result<Vec<ObjectID>> {
+        get_dynamic_children(&self.client, parent).await
+    }
+}
+
+/// Pages through the dynamic fields of `parent` with the json rpc.
+pub(crate) async fn get_dynamic_children(client: &SuiClient, parent: &ObjectID) -> eyre::Result<Vec<ObjectID>> {
+    let mut cursor = None;
+    let mut children = vec![];
+
+    loop {
+        let page = client.read_api().get_dynamic_fields(*parent, cursor, None).await?;
+        children.extend(page.data.iter().map(|info| info.object_id));
+        cursor = page.next_cursor;
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    Ok(children)
 }
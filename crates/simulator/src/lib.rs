@@ -1,31 +1,123 @@
 mod db_simulator;
+mod error;
 mod http_simulator;
 
 use async_trait::async_trait;
 use eyre::Result;
 use move_core_types::annotated_value::MoveStructLayout;
-use sui_json_rpc_types::{BalanceChange, SuiTransactionBlockEffects, SuiTransactionBlockEvents};
+use sui_json_rpc_types::{
+    BalanceChange, SuiTransactionBlockEffects, SuiTransactionBlockEffectsAPI, SuiTransactionBlockEvents,
+};
 use sui_types::{
-    base_types::ObjectID,
+    base_types::{ObjectID, SequenceNumber, SuiAddress},
     committee::EpochId,
+    digests::TransactionDigest,
+    gas::GasCostSummary,
     messages_checkpoint::CheckpointTimestamp,
-    object::Object,
+    object::{MoveObject, Object, Owner, OBJECT_START_VERSION},
     sui_system_state::sui_system_state_summary::SuiSystemStateSummary,
     transaction::{ObjectReadResult, TransactionData},
 };
 
-pub use db_simulator::{DBSimulator, ReplaySimulator};
+pub use db_simulator::{
+    accept_update_handshake, write_update_batch, CacheStats, DBSimulator, DBSimulatorBuilder, LayoutCache,
+    MissingPaths, ObjectUpdateSource, ReplayIntervals, ReplaySimulator, UpdateAddress, UpdateCompression, UpdateHealth,
+    SUI_DB_PATH_ENV, SUI_NODE_CONFIG_ENV,
+};
+pub use error::{MoveAbortLocation, SimulatorError};
 pub use http_simulator::HttpSimulator;
 
 #[derive(Debug, Clone)]
 pub struct SimulateResult {
     pub effects: SuiTransactionBlockEffects,
     pub events: SuiTransactionBlockEvents,
+    // objects written by the tx, see `SimulateCtx::with_previous_result`
     pub object_changes: Vec<ObjectReadResult>,
     pub balance_changes: Vec<BalanceChange>,
     pub cache_misses: u64,
+    pub override_misses: OverrideMisses,
+}
+
+impl SimulateResult {
+    /// Simulators return `Ok` for a tx that was executed but failed, this turns the failure into a
+    /// `SimulatorError::ExecutionFailure`.
+    pub fn check_status(&self) -> Result<(), SimulatorError> {
+        let status = self.effects.status();
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(SimulatorError::ExecutionFailure { status: status.clone() })
+        }
+    }
+}
+
+/// A gas budget for a tx from a simulation of it: the computation and storage cost, plus `safety_bps`
+/// basis points. The storage rebate is left out, it's only paid back after execution.
+///
+/// The budget is priced at the gas price of the simulated tx, see `rescale_gas_budget` if the tx is sent at
+/// another one.
+pub fn estimate_gas_budget(sim_result: &SimulateResult, safety_bps: u64) -> u64 {
+    gas_budget_for(&sim_result.effects.gas_cost_summary(), safety_bps)
+}
+
+/// The `gas_budget` estimated at `from_gas_price`, for the same tx at `to_gas_price`. The computation cost
+/// grows with the gas price, the whole budget is scaled so that the storage cost keeps its margin too.
+pub fn rescale_gas_budget(gas_budget: u64, from_gas_price: u64, to_gas_price: u64) -> u64 {
+    if from_gas_price == 0 || to_gas_price <= from_gas_price {
+        return gas_budget;
+    }
+    let budget = (gas_budget as u128 * to_gas_price as u128).div_ceil(from_gas_price as u128);
+    budget.try_into().unwrap_or(u64::MAX)
+}
+
+fn gas_budget_for(gas_cost_summary: &GasCostSummary, safety_bps: u64) -> u64 {
+    let cost = gas_cost_summary.computation_cost as u128 + gas_cost_summary.storage_cost as u128;
+    let budget = cost + cost * safety_bps as u128 / 10_000;
+    budget.try_into().unwrap_or(u64::MAX)
+}
+
+/// Object reads that were not found in the override objects of a simulation and went to the
+/// fallback store, by the cache method they went through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OverrideMisses {
+    pub get_package_object: u64,
+    pub get_object: u64,
+    pub get_latest_object_ref_or_tombstone: u64,
+    pub get_latest_object_or_tombstone: u64,
+    pub get_object_by_key: u64,
+    pub get_live_objref: u64,
+}
+
+impl OverrideMisses {
+    pub fn total(&self) -> u64 {
+        self.get_package_object
+            + self.get_object
+            + self.get_latest_object_ref_or_tombstone
+            + self.get_latest_object_or_tombstone
+            + self.get_object_by_key
+            + self.get_live_objref
+    }
+}
+
+/// The gas coin that simulators pay with when a tx has no gas coins, 1B SUI.
+pub const MOCK_GAS_BALANCE: u64 = 1_000_000_000 * 1_000_000_000;
+
+pub(crate) fn mock_gas_id() -> ObjectID {
+    ObjectID::from_hex_literal("0x1337").unwrap()
 }
 
+/// The mock gas coin of `sender`, see `MOCK_GAS_BALANCE`.
+pub(crate) fn mock_gas_coin(sender: SuiAddress) -> Object {
+    Object::new_move(
+        MoveObject::new_gas_coin(OBJECT_START_VERSION, mock_gas_id(), MOCK_GAS_BALANCE),
+        Owner::AddressOwner(sender),
+        TransactionDigest::genesis_marker(),
+    )
+}
+
+/// An epoch is treated as stale this long before it ends.
+pub const EPOCH_STALE_MARGIN_MS: u64 = 30_000;
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct SimEpoch {
     pub epoch_id: EpochId,
@@ -49,9 +141,13 @@ impl From<SuiSystemStateSummary> for SimEpoch {
 pub struct SimulateCtx {
     pub epoch: SimEpoch,
     pub override_objects: Vec<ObjectReadResult>,
-    // (coin, amount)
-    // assume we have this coin (flashloaned) during execution
-    pub borrowed_coin: Option<(Object, u64)>,
+    // (coin, amount) of any coin type
+    // assume we have these coins (flashloaned) during execution, they are repaid from the sender's balance changes
+    pub borrowed_coins: Vec<(Object, u64)>,
+    // timestamp of the Clock object seen by the tx, `None` means now
+    pub clock_timestamp_ms: Option<u64>,
+    // version of the SuiSystemState object (0x5) seen by the tx, `None` means latest
+    pub system_state_version: Option<SequenceNumber>,
 }
 
 impl SimulateCtx {
@@ -59,26 +155,61 @@ impl SimulateCtx {
         Self {
             epoch,
             override_objects,
-            borrowed_coin: None,
+            borrowed_coins: vec![],
+            clock_timestamp_ms: None,
+            system_state_version: None,
         }
     }
 
     pub fn with_borrowed_coin(&mut self, borrowed_coin: (Object, u64)) {
-        self.borrowed_coin = Some(borrowed_coin);
+        self.borrowed_coins.push(borrowed_coin);
     }
 
     pub fn with_gas_price(&mut self, gas_price: u64) {
         self.epoch.gas_price = gas_price;
     }
+
+    /// Pin the Clock, e.g. to the time of a historical opportunity or for deadline checks in Move code.
+    pub fn with_clock_timestamp_ms(&mut self, timestamp_ms: u64) {
+        self.clock_timestamp_ms = Some(timestamp_ms);
+    }
+
+    /// Pin the SuiSystemState object, and its inner state, to their latest versions at or below `version`,
+    /// e.g. the version at the start of `epoch` when replaying across an epoch boundary. Move code that reads
+    /// the epoch or the reference gas price from the system state then sees the same epoch as the gas
+    /// status. Only `DBSimulator` supports this, and only while the store still has these versions.
+    pub fn with_system_state_version(&mut self, version: SequenceNumber) {
+        self.system_state_version = Some(version);
+    }
+
+    /// Simulate on top of the state after `result`, e.g. a victim tx after ours. The objects it wrote become
+    /// override objects, and take precedence over earlier overrides of the same objects.
+    pub fn with_previous_result(&mut self, result: &SimulateResult) {
+        self.override_objects.extend(result.object_changes.iter().cloned());
+    }
 }
 
 impl SimEpoch {
+    /// True if the epoch has ended, or ends within `EPOCH_STALE_MARGIN_MS`, so that the gas price of the
+    /// next epoch is picked up in time.
     pub fn is_stale(&self) -> bool {
-        (std::time::SystemTime::now()
+        let now_ms = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
-            .as_millis() as u64)
-            < self.epoch_start_timestamp + self.epoch_duration_ms
+            .as_millis() as u64;
+        self.is_stale_at(now_ms)
+    }
+
+    pub fn is_stale_at(&self, now_ms: u64) -> bool {
+        now_ms.saturating_add(EPOCH_STALE_MARGIN_MS) >= self.end_timestamp_ms()
+    }
+
+    pub fn has_ended_at(&self, now_ms: u64) -> bool {
+        now_ms >= self.end_timestamp_ms()
+    }
+
+    pub fn end_timestamp_ms(&self) -> u64 {
+        self.epoch_start_timestamp.saturating_add(self.epoch_duration_ms)
     }
 }
 
@@ -88,7 +219,119 @@ pub trait Simulator: Sync + Send {
     async fn get_object(&self, obj_id: &ObjectID) -> Option<Object>;
     fn name(&self) -> &str;
 
+    /// Simulate independent txs, the results are in the order of `txs` and a failing tx doesn't fail the others.
+    async fn simulate_many(&self, txs: Vec<(TransactionData, SimulateCtx)>) -> Vec<Result<SimulateResult>> {
+        let mut results = Vec::with_capacity(txs.len());
+        for (tx, ctx) in txs {
+            results.push(self.simulate(tx, ctx).await);
+        }
+        results
+    }
+
+    /// The objects of `ids`, in the order of `ids`.
+    async fn multi_get_objects(&self, ids: &[ObjectID]) -> Vec<Option<Object>> {
+        let mut objects = Vec::with_capacity(ids.len());
+        for id in ids {
+            objects.push(self.get_object(id).await);
+        }
+        objects
+    }
+
+    /// The ids of the dynamic field objects of `parent`.
+    async fn get_dynamic_children(&self, parent: &ObjectID) -> Result<Vec<ObjectID>> {
+        eyre::bail!("{} can't enumerate the dynamic fields of {}", self.name(), parent)
+    }
+
     fn get_object_layout(&self, _: &ObjectID) -> Option<MoveStructLayout> {
         None
     }
 }
+
+#[async_trait]
+impl Simulator for Box<dyn Simulator> {
+    async fn simulate(&self, tx: TransactionData, ctx: SimulateCtx) -> Result<SimulateResult> {
+        self.as_ref().simulate(tx, ctx).await
+    }
+
+    async fn simulate_many(&self, txs: Vec<(TransactionData, SimulateCtx)>) -> Vec<Result<SimulateResult>> {
+        self.as_ref().simulate_many(txs).await
+    }
+
+    async fn get_object(&self, obj_id: &ObjectID) -> Option<Object> {
+        self.as_ref().get_object(obj_id).await
+    }
+
+    async fn multi_get_objects(&self, ids: &[ObjectID]) -> Vec<Option<Object>> {
+        self.as_ref().multi_get_objects(ids).await
+    }
+
+    async fn get_dynamic_children(&self, parent: &ObjectID) -> Result<Vec<ObjectID>> {
+        self.as_ref().get_dynamic_children(parent).await
+    }
+
+    fn name(&self) -> &str {
+        self.as_ref().name()
+    }
+
+    fn get_object_layout(&self, obj_id: &ObjectID) -> Option<MoveStructLayout> {
+        self.as_ref().get_object_layout(obj_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_MS: u64 = 3_600_000;
+
+    fn epoch(epoch_start_timestamp: u64) -> SimEpoch {
+        SimEpoch {
+            epoch_start_timestamp,
+            epoch_duration_ms: 24 * HOUR_MS,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_gas_budget_tracks_cost() {
+        let summary = GasCostSummary::new(2_000_000, 8_000_000, 7_000_000, 70_000);
+        // the rebate doesn't lower the budget
+        assert_eq!(gas_budget_for(&summary, 0), 10_000_000);
+        assert_eq!(gas_budget_for(&summary, 2_000), 12_000_000);
+        assert_eq!(gas_budget_for(&summary, 10_000), 20_000_000);
+
+        let summary = GasCostSummary::new(u64::MAX, 1, 0, 0);
+        assert_eq!(gas_budget_for(&summary, 1), u64::MAX);
+    }
+
+    #[test]
+    fn test_rescale_gas_budget() {
+        assert_eq!(rescale_gas_budget(1_000_000, 750, 1_000), 1_333_334);
+        assert_eq!(rescale_gas_budget(1_000_000, 750, 1_500), 2_000_000);
+        // never lowered
+        assert_eq!(rescale_gas_budget(1_000_000, 1_000, 750), 1_000_000);
+        assert_eq!(rescale_gas_budget(u64::MAX, 1, 2), u64::MAX);
+    }
+
+    #[test]
+    fn test_epoch_staleness() {
+        let start = 1_700_000_000_000;
+        let end = start + 24 * HOUR_MS;
+        let epoch = epoch(start);
+
+        // before and inside the window
+        assert!(!epoch.is_stale_at(start - HOUR_MS));
+        assert!(!epoch.is_stale_at(start));
+        assert!(!epoch.is_stale_at(end - EPOCH_STALE_MARGIN_MS - 1));
+        assert!(!epoch.has_ended_at(start + HOUR_MS));
+
+        // within the margin before the end
+        assert!(epoch.is_stale_at(end - EPOCH_STALE_MARGIN_MS));
+        assert!(!epoch.has_ended_at(end - 1));
+
+        // after the window
+        assert!(epoch.is_stale_at(end));
+        assert!(epoch.is_stale_at(end + HOUR_MS));
+        assert!(epoch.has_ended_at(end));
+    }
+}
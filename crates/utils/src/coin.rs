@@ -1,33 +1,128 @@
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
-use eyre::{eyre, Result};
+use eyre::{bail, ensure, eyre, Result};
+use move_core_types::language_storage::TypeTag;
 use sui_sdk::{rpc_types::Coin, SuiClient, SUI_COIN_TYPE};
 use sui_types::{
-    base_types::{ObjectID, ObjectRef, SuiAddress},
-    object::Object,
+    base_types::{MoveObjectType, ObjectID, ObjectRef, SuiAddress},
+    digests::TransactionDigest,
+    dynamic_field::derive_dynamic_field_id,
+    gas_coin::GAS,
+    object::{MoveObject, Object, Owner, OBJECT_START_VERSION},
 };
 
-pub async fn get_gas_coin_refs(
-    sui: &SuiClient,
-    owner: SuiAddress,
-    exclude: Option<ObjectID>,
-) -> Result<Vec<ObjectRef>> {
-    let coins = sui.coin_read_api().get_coins(owner, None, None, None).await?;
+const MOCKED_SUI_ID: &str = "0x0000000000000000000000000000000000000000000000000000000000001338";
 
-    let object_refs = coins
-        .data
-        .into_iter()
-        .filter_map(|c| {
-            if let Some(exclude) = exclude {
-                if c.coin_object_id == exclude {
-                    return None;
-                }
+/// The max number of gas coins of a tx (`max_gas_payment_objects` of the protocol config).
+pub const MAX_GAS_PAYMENT_OBJECTS: usize = 256;
+
+/// Which SUI coins `get_gas_coin_refs` returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GasCoinFilter {
+    /// coins with a lower balance are skipped
+    pub min_balance: u64,
+    /// the largest coins are kept when there are more
+    pub max_coins: usize,
+    pub exclude: Vec<ObjectID>,
+    /// fail with `InsufficientGas` if the coins don't add up to it
+    pub required_budget: Option<u64>,
+}
+
+impl Default for GasCoinFilter {
+    fn default() -> Self {
+        Self {
+            min_balance: 0,
+            max_coins: MAX_GAS_PAYMENT_OBJECTS,
+            exclude: vec![],
+            required_budget: None,
+        }
+    }
+}
+
+impl GasCoinFilter {
+    pub fn with_min_balance(mut self, min_balance: u64) -> Self {
+        self.min_balance = min_balance;
+        self
+    }
+
+    pub fn with_max_coins(mut self, max_coins: usize) -> Self {
+        self.max_coins = max_coins;
+        self
+    }
+
+    pub fn with_exclude(mut self, exclude: Vec<ObjectID>) -> Self {
+        self.exclude = exclude;
+        self
+    }
+
+    pub fn with_required_budget(mut self, required_budget: u64) -> Self {
+        self.required_budget = Some(required_budget);
+        self
+    }
+
+    // the largest coins first
+    fn select(&self, mut coins: Vec<Coin>) -> Result<Vec<ObjectRef>, InsufficientGas> {
+        coins.retain(|c| c.balance >= self.min_balance && !self.exclude.contains(&c.coin_object_id));
+        coins.sort_by(|a, b| b.balance.cmp(&a.balance));
+        coins.truncate(self.max_coins);
+
+        if let Some(required) = self.required_budget {
+            let available = coins.iter().map(|c| c.balance).sum::<u64>();
+            if available < required {
+                return Err(InsufficientGas {
+                    required,
+                    available,
+                    num_coins: coins.len(),
+                });
             }
-            Some(c.object_ref())
-        })
-        .collect();
+        }
 
-    Ok(object_refs)
+        Ok(coins.iter().map(|c| c.object_ref()).collect())
+    }
+}
+
+/// Returned by `get_gas_coin_refs` when the gas coins left by the filter don't cover the required budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsufficientGas {
+    pub required: u64,
+    pub available: u64,
+    pub num_coins: usize,
+}
+
+impl fmt::Display for InsufficientGas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "insufficient gas: {} in {} coins, {} required",
+            self.available, self.num_coins, self.required
+        )
+    }
+}
+
+impl std::error::Error for InsufficientGas {}
+
+pub async fn get_gas_coin_refs(sui: &SuiClient, owner: SuiAddress, filter: &GasCoinFilter) -> Result<Vec<ObjectRef>> {
+    let coins = get_all_coins(sui, owner, SUI_COIN_TYPE).await?;
+
+    Ok(filter.select(coins)?)
+}
+
+async fn get_all_coins(sui: &SuiClient, owner: SuiAddress, coin_type: &str) -> Result<Vec<Coin>> {
+    let mut coins = vec![];
+    let mut cursor = None;
+    loop {
+        let page = sui
+            .coin_read_api()
+            .get_coins(owner, Some(coin_type.to_string()), cursor, None)
+            .await?;
+        coins.extend(page.data);
+        if !page.has_next_page || page.next_cursor.is_none() {
+            break;
+        }
+        cursor = page.next_cursor;
+    }
+
+    Ok(coins)
 }
 
 pub async fn get_coins(sui: &SuiClient, owner: SuiAddress, coin_type: &str, min_balance: u64) -> Result<Vec<Coin>> {
@@ -54,12 +149,79 @@ pub async fn get_coin(sui: &SuiClient, owner: SuiAddress, coin_type: &str, min_b
         .ok_or_else(|| eyre!("No coins with balance >= {}", min_balance))
 }
 
+/// The fewest coins of `coin_type` whose balances add up to at least `amount`, to be merged by the caller
+/// (see `TradeCtx::merge_coins_objrefs`). A single coin if one is large enough.
+pub async fn get_coins_for_amount(
+    sui: &SuiClient,
+    owner: SuiAddress,
+    coin_type: &str,
+    amount: u64,
+) -> Result<Vec<Coin>> {
+    let coins = get_all_coins(sui, owner, coin_type).await?;
+
+    select_coins(coins, amount).map_err(|e| e.wrap_err(format!("{coin_type} of {owner}")))
+}
+
+fn select_coins(mut coins: Vec<Coin>, amount: u64) -> Result<Vec<Coin>> {
+    // the smallest coin that covers the amount alone, not to merge
+    if let Some(coin) = coins
+        .iter()
+        .filter(|coin| coin.balance >= amount)
+        .min_by_key(|coin| coin.balance)
+    {
+        return Ok(vec![coin.clone()]);
+    }
+
+    let total = coins.iter().map(|coin| coin.balance as u128).sum::<u128>();
+    ensure!(
+        total >= amount as u128,
+        "insufficient balance: {} in {} coins, {} needed",
+        total,
+        coins.len(),
+        amount
+    );
+
+    // the largest coins first, for the fewest coins
+    coins.sort_by(|a, b| b.balance.cmp(&a.balance));
+    let mut sum = 0;
+    let count = coins
+        .iter()
+        .take_while(|coin| {
+            let covered = sum >= amount;
+            sum += coin.balance;
+            !covered
+        })
+        .count();
+    coins.truncate(count);
+
+    Ok(coins)
+}
+
 pub fn mocked_sui(owner: SuiAddress, amount: u64) -> Object {
-    Object::with_id_owner_gas_for_testing(
-        ObjectID::from_str("0x0000000000000000000000000000000000000000000000000000000000001338").unwrap(),
-        owner,
-        amount,
-    )
+    Object::with_id_owner_gas_for_testing(ObjectID::from_str(MOCKED_SUI_ID).unwrap(), owner, amount)
+}
+
+/// `mocked_sui` of any coin type. The id is derived from the type, so the mocked coins of two types
+/// never collide, and it's the same for every call with the same type.
+pub fn mocked_coin(owner: SuiAddress, coin_type: &str, amount: u64) -> Result<Object> {
+    let coin_tag = TypeTag::from_str(coin_type).map_err(|e| eyre!("invalid coin type {coin_type:?}: {e}"))?;
+    let TypeTag::Struct(_) = coin_tag else {
+        bail!("invalid coin type {coin_type:?}: not a struct");
+    };
+    if coin_tag == GAS::type_tag() {
+        return Ok(mocked_sui(owner, amount));
+    }
+
+    // the id of a dynamic field of the mocked SUI coin keyed by the type, only used as a hash of the type
+    let id =
+        derive_dynamic_field_id(ObjectID::from_str(MOCKED_SUI_ID).unwrap(), &coin_tag, &[]).map_err(|e| eyre!(e))?;
+    let move_obj = MoveObject::new_coin(MoveObjectType::coin(coin_tag), OBJECT_START_VERSION, id, amount);
+
+    Ok(Object::new_move(
+        move_obj,
+        Owner::AddressOwner(owner),
+        TransactionDigest::genesis_marker(),
+    ))
 }
 
 pub fn is_native_coin(coin_type: &str) -> bool {
@@ -72,3 +234,139 @@ pub fn format_sui_with_symbol(value: u64) -> String {
 
     format!("{} SUI", value)
 }
+
+#[cfg(test)]
+mod tests {
+    use sui_types::{base_types::random_object_ref, digests::TransactionDigest};
+
+    use super::*;
+
+    // what the coin read api returns for a wallet holding these balances
+    fn coins(balances: &[u64]) -> Vec<Coin> {
+        balances
+            .iter()
+            .map(|&balance| {
+                let (coin_object_id, version, digest) = random_object_ref();
+                Coin {
+                    coin_type: SUI_COIN_TYPE.to_string(),
+                    coin_object_id,
+                    version,
+                    digest,
+                    balance,
+                    previous_transaction: TransactionDigest::random(),
+                }
+            })
+            .collect()
+    }
+
+    fn balances(coins: &[Coin]) -> Vec<u64> {
+        coins.iter().map(|coin| coin.balance).collect()
+    }
+
+    #[test]
+    fn test_select_a_single_coin() {
+        // the smallest coin that covers the amount, exact match included
+        assert_eq!(
+            balances(&select_coins(coins(&[50, 200, 100, 30]), 100).unwrap()),
+            vec![100]
+        );
+        assert_eq!(
+            balances(&select_coins(coins(&[50, 200, 120, 30]), 100).unwrap()),
+            vec![120]
+        );
+    }
+
+    #[test]
+    fn test_select_fragmented_coins() {
+        let selected = select_coins(coins(&[10, 40, 5, 30, 20]), 65).unwrap();
+        assert_eq!(balances(&selected), vec![40, 30]);
+
+        let selected = select_coins(coins(&[10, 40, 5, 30, 20]), 105).unwrap();
+        assert_eq!(balances(&selected), vec![40, 30, 20, 10, 5]);
+    }
+
+    #[test]
+    fn test_mocked_coin() {
+        let owner = SuiAddress::random_for_testing_only();
+        let usdc = "0xdba34672e30cb065b1f93e3ab55318768fd6fef66c15942c9f7cb846e2f900e7::usdc::USDC";
+        let cetus = "0x06864a6f921804860930db6ddbe2e16acdf8504495ea7481637a1c8b9a8fe54b::cetus::CETUS";
+
+        let coin = mocked_coin(owner, usdc, 1_000).unwrap();
+        assert_eq!(coin.coin_type_maybe(), Some(TypeTag::from_str(usdc).unwrap()));
+        assert_eq!(coin.get_coin_value_unsafe(), 1_000);
+        assert_eq!(coin.owner, Owner::AddressOwner(owner));
+        assert_eq!(coin.version(), OBJECT_START_VERSION);
+
+        // the id is per type
+        assert_eq!(mocked_coin(owner, usdc, 1).unwrap().id(), coin.id());
+        assert_ne!(mocked_coin(owner, cetus, 1_000).unwrap().id(), coin.id());
+        assert_eq!(
+            mocked_coin(owner, "0x2::sui::SUI", 1_000).unwrap(),
+            mocked_sui(owner, 1_000)
+        );
+
+        assert!(mocked_coin(owner, "0x2::sui", 1).is_err());
+        assert!(mocked_coin(owner, "u64", 1).is_err());
+    }
+
+    #[test]
+    fn test_gas_coin_filter() {
+        let all = coins(&[5, 300, 1, 200, 100, 2]);
+
+        let select = |filter: GasCoinFilter| {
+            let refs = filter.select(all.clone()).unwrap();
+            refs.iter()
+                .map(|r| all.iter().find(|c| c.coin_object_id == r.0).unwrap().balance)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(select(GasCoinFilter::default()), vec![300, 200, 100, 5, 2, 1]);
+        assert_eq!(
+            select(GasCoinFilter::default().with_min_balance(5)),
+            vec![300, 200, 100, 5]
+        );
+        assert_eq!(
+            select(GasCoinFilter::default().with_min_balance(5).with_max_coins(2)),
+            vec![300, 200]
+        );
+        assert_eq!(
+            select(GasCoinFilter::default().with_exclude(vec![all[1].coin_object_id])),
+            vec![200, 100, 5, 2, 1]
+        );
+        assert_eq!(
+            select(GasCoinFilter::default().with_max_coins(2).with_required_budget(500)),
+            vec![300, 200]
+        );
+    }
+
+    #[test]
+    fn test_gas_coin_filter_insufficient() {
+        let all = coins(&[5, 300, 1, 200, 100, 2]);
+
+        // the dust and the coins over the cap don't count
+        let filter = GasCoinFilter::default()
+            .with_min_balance(5)
+            .with_max_coins(2)
+            .with_required_budget(501);
+        assert_eq!(
+            filter.select(all.clone()),
+            Err(InsufficientGas {
+                required: 501,
+                available: 500,
+                num_coins: 2,
+            })
+        );
+
+        let error = eyre::Report::from(filter.select(vec![]).unwrap_err());
+        assert!(error.downcast_ref::<InsufficientGas>().is_some());
+        assert_eq!(error.to_string(), "insufficient gas: 0 in 0 coins, 501 required");
+    }
+
+    #[test]
+    fn test_select_insufficient_balance() {
+        let error = select_coins(coins(&[1, 2, 3]), 100).unwrap_err();
+        assert!(error.to_string().contains("6 in 3 coins, 100 needed"), "{error}");
+
+        let error = select_coins(vec![], 1).unwrap_err();
+        assert!(error.to_string().contains("0 in 0 coins"), "{error}");
+    }
+}
@@ -1,16 +1,205 @@
-use std::time::Duration;
+use std::{collections::HashMap, sync::Mutex, time::Duration};
 
+use burberry::executor::telegram_message::escape;
 use tokio::task::JoinHandle;
-use tracing::{debug, error, info};
+use tracing::{error, info, warn};
 
-pub fn start<T: Into<String>>(service_id: T, interval: Duration) -> JoinHandle<()> {
+use crate::telegram::{self, AlertThread};
+
+/// A probe unhealthy for more beats in a row than this is escalated to telegram.
+pub const DEFAULT_ESCALATE_AFTER: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthStatus {
+    Healthy,
+    /// with the reason, e.g. `disconnected`
+    Unhealthy(String),
+}
+
+/// Checked on every beat, it must be cheap and must not block.
+pub type HealthProbe = Box<dyn Fn() -> HealthStatus + Send + Sync>;
+
+static PROBES: Mutex<Vec<(String, HealthProbe)>> = Mutex::new(Vec::new());
+
+/// Adds a probe to the beats of every heartbeat, a probe registered before under the same name is replaced.
+pub fn register_probe<T: Into<String>>(name: T, probe: HealthProbe) {
+    let name = name.into();
+    let mut probes = PROBES.lock().unwrap();
+    match probes.iter_mut().find(|(registered, _)| *registered == name) {
+        Some((_, registered)) => *registered = probe,
+        None => probes.push((name, probe)),
+    }
+}
+
+/// Logs the status of the registered probes every `interval`. Once a probe stays unhealthy for more than
+/// `escalate_after` beats, the summary goes to the error thread of the alerts, and again once all
+/// probes are back.
+pub fn start<T: Into<String>>(service_id: T, interval: Duration, escalate_after: u32) -> JoinHandle<()> {
     let id = service_id.into();
 
-    tokio::spawn(worker(id, interval))
+    tokio::spawn(worker(id, interval, escalate_after))
 }
 
-async fn worker(id: String, interval: Duration) {
+async fn worker(id: String, interval: Duration, escalate_after: u32) {
     info!("Heartbeat worker started for {}", id);
 
-    // write your code here
+    let mut beats = Beats::new(escalate_after);
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let statuses = check_probes();
+        let summary = summarize(&statuses);
+
+        match beats.record(&statuses) {
+            Some(Escalation::Raised) => {
+                error!("{id} is unhealthy: {summary}");
+                notify(&format!("🚨 {id} is unhealthy\n{summary}")).await;
+            }
+            Some(Escalation::Cleared) => {
+                info!("{id} is healthy again: {summary}");
+                notify(&format!("✅ {id} is healthy again\n{summary}")).await;
+            }
+            None if beats.any_unhealthy() => warn!("{id} heartbeat: {summary}"),
+            None => info!("{id} heartbeat: {summary}"),
+        }
+    }
+}
+
+fn check_probes() -> Vec<(String, HealthStatus)> {
+    PROBES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, probe)| (name.clone(), probe()))
+        .collect()
+}
+
+// e.g. `shio: ok, db_sim: no update for 12s`
+fn summarize(statuses: &[(String, HealthStatus)]) -> String {
+    if statuses.is_empty() {
+        return "no probes".to_string();
+    }
+
+    statuses
+        .iter()
+        .map(|(name, status)| match status {
+            HealthStatus::Healthy => format!("{name}: ok"),
+            HealthStatus::Unhealthy(reason) => format!("{name}: {reason}"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+async fn notify(text: &str) {
+    if let Some(alerter) = telegram::alerter() {
+        alerter.send(AlertThread::Error, &escape(text)).await;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Escalation {
+    Raised,
+    Cleared,
+}
+
+// the beats in a row each probe has been unhealthy
+struct Beats {
+    escalate_after: u32,
+    unhealthy_beats: HashMap<String, u32>,
+    escalated: bool,
+}
+
+impl Beats {
+    fn new(escalate_after: u32) -> Self {
+        Self {
+            escalate_after,
+            unhealthy_beats: HashMap::new(),
+            escalated: false,
+        }
+    }
+
+    fn record(&mut self, statuses: &[(String, HealthStatus)]) -> Option<Escalation> {
+        for (name, status) in statuses {
+            match status {
+                HealthStatus::Healthy => {
+                    self.unhealthy_beats.remove(name);
+                }
+                HealthStatus::Unhealthy(_) => *self.unhealthy_beats.entry(name.clone()).or_default() += 1,
+            }
+        }
+
+        // stays escalated until every probe is healthy, not to flap while probes recover one by one
+        if !self.escalated && self.unhealthy_beats.values().any(|beats| *beats > self.escalate_after) {
+            self.escalated = true;
+            return Some(Escalation::Raised);
+        }
+        if self.escalated && self.unhealthy_beats.is_empty() {
+            self.escalated = false;
+            return Some(Escalation::Cleared);
+        }
+        None
+    }
+
+    fn any_unhealthy(&self) -> bool {
+        !self.unhealthy_beats.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statuses(shio: bool, db_sim: bool) -> Vec<(String, HealthStatus)> {
+        let status = |healthy| match healthy {
+            true => HealthStatus::Healthy,
+            false => HealthStatus::Unhealthy("down".to_string()),
+        };
+        vec![
+            ("shio".to_string(), status(shio)),
+            ("db_sim".to_string(), status(db_sim)),
+        ]
+    }
+
+    #[test]
+    fn test_escalates_after_n_unhealthy_beats() {
+        let mut beats = Beats::new(2);
+
+        assert_eq!(beats.record(&statuses(true, true)), None);
+        assert!(!beats.any_unhealthy());
+
+        // unhealthy for 2 beats is tolerated, the 3rd escalates
+        assert_eq!(beats.record(&statuses(false, true)), None);
+        assert_eq!(beats.record(&statuses(false, true)), None);
+        assert!(beats.any_unhealthy());
+        assert_eq!(beats.record(&statuses(false, true)), Some(Escalation::Raised));
+
+        // escalated once only, and cleared once all probes are back
+        assert_eq!(beats.record(&statuses(false, false)), None);
+        assert_eq!(beats.record(&statuses(true, false)), None);
+        assert_eq!(beats.record(&statuses(true, true)), Some(Escalation::Cleared));
+        assert_eq!(beats.record(&statuses(true, true)), None);
+    }
+
+    #[test]
+    fn test_a_healthy_beat_resets_the_count() {
+        let mut beats = Beats::new(2);
+
+        for _ in 0..3 {
+            assert_eq!(beats.record(&statuses(false, true)), None);
+            assert_eq!(beats.record(&statuses(false, true)), None);
+            assert_eq!(beats.record(&statuses(true, true)), None);
+        }
+
+        // probes that alternate never escalate together
+        assert_eq!(beats.record(&statuses(false, true)), None);
+        assert_eq!(beats.record(&statuses(true, false)), None);
+        assert_eq!(beats.record(&statuses(false, true)), None);
+        assert_eq!(beats.record(&statuses(true, false)), None);
+    }
+
+    #[test]
+    fn test_summary() {
+        assert_eq!(summarize(&[]), "no probes");
+        assert_eq!(summarize(&statuses(true, false)), "shio: ok, db_sim: down");
+    }
 }
@@ -1,56 +1,207 @@
+use std::{fmt::Write, str::FromStr, sync::OnceLock};
+
+use burberry::executor::telegram_message::escape;
+use eyre::{ensure, Result};
 use sui_types::{
-    base_types::{ObjectID, SequenceNumber, SuiAddress},
-    digests::{Digest, TransactionDigest},
+    base_types::{ObjectID, SuiAddress},
+    digests::TransactionDigest,
 };
 
-const SCAN_URL: &str = "https://suiscan.xyz/mainnet";
-
-// https://suiscan.xyz/mainnet/tx/WQ346mGc8sLjtcBPBfJNvTxCWar7U7Fsow9rTkmXgyE
-pub fn tx(digest: &TransactionDigest, tag: Option<String>) -> String {
-    format!(
-        "[{tag_str}]({prefix}/tx/{digest})",
-        tag_str = tag.unwrap_or_else(|| format!("{}", digest)),
-        prefix = SCAN_URL,
-        digest = digest,
-    )
-}
-
-// https://suiscan.xyz/mainnet/object/0xb8d7d9e66a60c239e7a60110efcf8de6c705580ed924d0dde141f4a0e2c90105
-pub fn object(object_id: ObjectID, tag: Option<String>) -> String {
-    format!(
-        "[{tag_str}]({prefix}/object/{object_id})",
-        tag_str = tag.unwrap_or_else(|| format!("{}", object_id)),
-        prefix = SCAN_URL,
-        object_id = object_id,
-    )
-}
-
-// https://suiscan.xyz/mainnet/account/0xac5bceec1b789ff840d7d4e6ce4ce61c90d190a7f8c4f4ddf0bff6ee2413c33c/portfolio
-pub fn account(address: &SuiAddress, tag: Option<String>) -> String {
-    format!(
-        "[{tag}]({prefix}/account/{address}/portfolio)",
-        tag = tag.unwrap_or_else(|| format!("{}", address)),
-        address = address,
-        prefix = SCAN_URL,
-    )
-}
-
-// https://suiscan.xyz/mainnet/coin/0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN/txs
-pub fn coin(coin_type: &str, tag: Option<String>) -> String {
-    format!(
-        "[{tag}]({prefix}/coin/{coin_type}/txs)",
-        tag = tag.unwrap_or_else(|| coin_type.to_string()),
-        coin_type = coin_type,
-        prefix = SCAN_URL,
-    )
-}
-
-// https://suiscan.xyz/mainnet/checkpoint/AYWtSh7XWdBiRaEyh4oq3pxoaPmLkPJ6U1LBdwHuEXT
-pub fn checkpoint(digest: &Digest, number: SequenceNumber) -> String {
-    format!(
-        "[{number}]({prefix}/checkpoint/{digest})",
-        number = number,
-        digest = digest,
-        prefix = SCAN_URL,
-    )
+const SUISCAN_URL: &str = "https://suiscan.xyz/mainnet";
+const SUIVISION_URL: &str = "https://suivision.xyz";
+
+static EXPLORER: OnceLock<Explorer> = OnceLock::new();
+
+/// Where the links of the notifications point to.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Explorer {
+    SuiVision,
+    #[default]
+    SuiScan,
+    /// an explorer with the paths of suiscan at this base url, e.g. `http://localhost:3000`
+    Local(String),
+}
+
+impl Explorer {
+    // https://suiscan.xyz/mainnet/tx/WQ346mGc8sLjtcBPBfJNvTxCWar7U7Fsow9rTkmXgyE
+    // https://suivision.xyz/txblock/WQ346mGc8sLjtcBPBfJNvTxCWar7U7Fsow9rTkmXgyE
+    pub fn tx_url(&self, digest: &TransactionDigest) -> String {
+        match self {
+            Self::SuiVision => format!("{SUIVISION_URL}/txblock/{digest}"),
+            _ => format!("{}/tx/{digest}", self.base_url()),
+        }
+    }
+
+    // https://suiscan.xyz/mainnet/object/0xb8d7d9e66a60c239e7a60110efcf8de6c705580ed924d0dde141f4a0e2c90105
+    pub fn object_url(&self, object_id: &ObjectID) -> String {
+        format!("{}/object/{object_id}", self.base_url())
+    }
+
+    // https://suiscan.xyz/mainnet/account/0xac5bceec1b789ff840d7d4e6ce4ce61c90d190a7f8c4f4ddf0bff6ee2413c33c/portfolio
+    // https://suivision.xyz/account/0xac5bceec1b789ff840d7d4e6ce4ce61c90d190a7f8c4f4ddf0bff6ee2413c33c
+    pub fn address_url(&self, address: &SuiAddress) -> String {
+        match self {
+            Self::SuiVision => format!("{SUIVISION_URL}/account/{address}"),
+            _ => format!("{}/account/{address}/portfolio", self.base_url()),
+        }
+    }
+
+    // https://suiscan.xyz/mainnet/coin/0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9%3A%3Aocean%3A%3AOCEAN/txs
+    // https://suivision.xyz/coin/0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9%3A%3Aocean%3A%3AOCEAN
+    pub fn coin_url(&self, coin_type: &str) -> String {
+        let coin_type = encode_path_segment(coin_type);
+        match self {
+            Self::SuiVision => format!("{SUIVISION_URL}/coin/{coin_type}"),
+            _ => format!("{}/coin/{coin_type}/txs", self.base_url()),
+        }
+    }
+
+    fn base_url(&self) -> &str {
+        match self {
+            Self::SuiVision => SUIVISION_URL,
+            Self::SuiScan => SUISCAN_URL,
+            Self::Local(base_url) => base_url.trim_end_matches('/'),
+        }
+    }
+}
+
+/// `suivision`, `suiscan`, or the base url of a local explorer.
+impl FromStr for Explorer {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "suivision" => Ok(Self::SuiVision),
+            "suiscan" => Ok(Self::SuiScan),
+            base_url => {
+                ensure!(
+                    base_url.starts_with("http://") || base_url.starts_with("https://"),
+                    "unknown explorer {base_url:?}, expected suivision, suiscan or a base url"
+                );
+                Ok(Self::Local(base_url.to_string()))
+            }
+        }
+    }
+}
+
+/// Sets the explorer of `explorer()`, returns false if it was already set.
+pub fn set_explorer(explorer: Explorer) -> bool {
+    EXPLORER.set(explorer).is_ok()
+}
+
+/// The explorer of the process, suiscan if not set.
+pub fn explorer() -> &'static Explorer {
+    EXPLORER.get_or_init(Explorer::default)
+}
+
+/// A MarkdownV2 link, `label` is escaped.
+pub fn markdown_link(label: &str, url: &str) -> String {
+    // within the url, only `)` and `\` are to be escaped
+    let url = url.replace('\\', "\\\\").replace(')', "\\)");
+    format!("[{}]({})", escape(label), url)
+}
+
+// percent-encodes everything but the unreserved chars, e.g. the `::` and the `<>` of a coin type
+fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => write!(encoded, "%{byte:02X}").unwrap(),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OCEAN: &str = "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9::ocean::OCEAN";
+    const OCEAN_ENCODED: &str =
+        "0xa8816d3a6e3136e86bc2873b1f94a15cadc8af2703c075f2d546c2ae367f4df9%3A%3Aocean%3A%3AOCEAN";
+
+    #[test]
+    fn test_urls() {
+        let digest = TransactionDigest::from_str("WQ346mGc8sLjtcBPBfJNvTxCWar7U7Fsow9rTkmXgyE").unwrap();
+        let object_id =
+            ObjectID::from_hex_literal("0xb8d7d9e66a60c239e7a60110efcf8de6c705580ed924d0dde141f4a0e2c90105").unwrap();
+        let address = SuiAddress::from(object_id);
+
+        let suiscan = Explorer::SuiScan;
+        assert_eq!(
+            suiscan.tx_url(&digest),
+            "https://suiscan.xyz/mainnet/tx/WQ346mGc8sLjtcBPBfJNvTxCWar7U7Fsow9rTkmXgyE"
+        );
+        assert_eq!(
+            suiscan.object_url(&object_id),
+            format!("https://suiscan.xyz/mainnet/object/{object_id}")
+        );
+        assert_eq!(
+            suiscan.address_url(&address),
+            format!("https://suiscan.xyz/mainnet/account/{address}/portfolio")
+        );
+        assert_eq!(
+            suiscan.coin_url(OCEAN),
+            format!("https://suiscan.xyz/mainnet/coin/{OCEAN_ENCODED}/txs")
+        );
+
+        let suivision = Explorer::SuiVision;
+        assert_eq!(
+            suivision.tx_url(&digest),
+            "https://suivision.xyz/txblock/WQ346mGc8sLjtcBPBfJNvTxCWar7U7Fsow9rTkmXgyE"
+        );
+        assert_eq!(
+            suivision.object_url(&object_id),
+            format!("https://suivision.xyz/object/{object_id}")
+        );
+        assert_eq!(
+            suivision.address_url(&address),
+            format!("https://suivision.xyz/account/{address}")
+        );
+        assert_eq!(
+            suivision.coin_url(OCEAN),
+            format!("https://suivision.xyz/coin/{OCEAN_ENCODED}")
+        );
+
+        let local = Explorer::from_str("http://localhost:3000/").unwrap();
+        assert_eq!(local, Explorer::Local("http://localhost:3000/".to_string()));
+        assert_eq!(
+            local.tx_url(&digest),
+            "http://localhost:3000/tx/WQ346mGc8sLjtcBPBfJNvTxCWar7U7Fsow9rTkmXgyE"
+        );
+    }
+
+    #[test]
+    fn test_coin_url_encoding() {
+        // the case of the hex is kept, only the separators are encoded, in uppercase hex
+        let coin_type = "0xDBA34672::usdc::USDC";
+        assert_eq!(
+            Explorer::SuiScan.coin_url(coin_type),
+            "https://suiscan.xyz/mainnet/coin/0xDBA34672%3A%3Ausdc%3A%3AUSDC/txs"
+        );
+
+        let lp_type = "0x2::lp::LP<0x2::sui::SUI, 0xDBA34672::usdc::USDC>";
+        assert_eq!(
+            encode_path_segment(lp_type),
+            "0x2%3A%3Alp%3A%3ALP%3C0x2%3A%3Asui%3A%3ASUI%2C%200xDBA34672%3A%3Ausdc%3A%3AUSDC%3E"
+        );
+    }
+
+    #[test]
+    fn test_markdown_link() {
+        let url = Explorer::SuiScan.coin_url("0x2::my_coin::MY_COIN");
+        assert_eq!(
+            markdown_link("0x2::my_coin::MY_COIN", &url),
+            "[0x2::my\\_coin::MY\\_COIN](https://suiscan.xyz/mainnet/coin/0x2%3A%3Amy_coin%3A%3AMY_COIN/txs)"
+        );
+        assert_eq!(markdown_link("a.b", "http://x/(1)"), "[a\\.b](http://x/(1\\))");
+    }
+
+    #[test]
+    fn test_explorer_from_str() {
+        assert_eq!(Explorer::from_str("suivision").unwrap(), Explorer::SuiVision);
+        assert_eq!(Explorer::from_str("suiscan").unwrap(), Explorer::SuiScan);
+        assert!(Explorer::from_str("etherscan").is_err());
+    }
 }
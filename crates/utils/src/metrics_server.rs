@@ -0,0 +1,125 @@
+//! A minimal prometheus endpoint: the metrics of a `Registry` served on `GET /metrics`.
+
+use std::net::SocketAddr;
+
+use eyre::Result;
+use prometheus::{core::Collector, Encoder, Registry, TextEncoder};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info, warn};
+
+/// Registers `metric` to `registry` and returns it. Metric names and labels are constants, so a failure here
+/// is a bug.
+pub fn register<T: Collector + Clone + 'static>(registry: &Registry, metric: prometheus::Result<T>) -> T {
+    let metric = metric.unwrap();
+    registry.register(Box::new(metric.clone())).unwrap();
+    metric
+}
+
+/// The metrics of `registry` in the prometheus text format.
+pub fn encode(registry: &Registry) -> Result<String> {
+    let mut buf = vec![];
+    TextEncoder::new().encode(&registry.gather(), &mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+/// Serve the metrics of `registry` on `GET /metrics` in the background, returns the bound address.
+pub async fn serve(addr: SocketAddr, registry: Registry) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    info!(%local_addr, "metrics server started");
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(error) => {
+                    warn!(?error, "Accept metrics connection failed");
+                    continue;
+                }
+            };
+
+            let registry = registry.clone();
+            tokio::spawn(async move {
+                if let Err(error) = handle_connection(stream, &registry).await {
+                    debug!(?error, "Serve metrics failed");
+                }
+            });
+        }
+    });
+
+    Ok(local_addr)
+}
+
+// a minimal HTTP/1.1 responder, the request line is all we need from a scraper
+async fn handle_connection(mut stream: TcpStream, registry: &Registry) -> Result<()> {
+    let mut buf = [0u8; 1024];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+
+    let (status, body) = if request.starts_with("GET /metrics ") {
+        ("200 OK", encode(registry)?)
+    } else {
+        ("404 Not Found", String::new())
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Fetch `/metrics` from the server like a prometheus scraper, for tests.
+pub async fn scrape(addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+    response
+}
+
+/// The value of a sample in the scraped text, e.g. `arb_events_total{source="shio"}`, 0 if absent.
+pub fn sample_value(scraped: &str, sample: &str) -> f64 {
+    scraped
+        .lines()
+        .find_map(|line| line.strip_prefix(sample)?.strip_prefix(' '))
+        .map_or(0.0, |value| value.parse().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::IntCounter;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scrape_metrics() {
+        let registry = Registry::new();
+        let counter = register(&registry, IntCounter::new("test_requests_total", "requests"));
+        let addr = serve("127.0.0.1:0".parse().unwrap(), registry).await.unwrap();
+
+        counter.inc();
+        assert_eq!(sample_value(&scrape(addr).await, "test_requests_total"), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_path_not_found() {
+        let addr = serve("127.0.0.1:0".parse().unwrap(), Registry::new()).await.unwrap();
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"), "{response}");
+    }
+}
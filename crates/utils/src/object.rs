@@ -1,4 +1,4 @@
-use eyre::{bail, OptionExt, Result};
+use eyre::{bail, eyre, Result};
 use move_core_types::annotated_value::{MoveStruct, MoveValue};
 use sui_types::{
     base_types::{ObjectID, SequenceNumber},
@@ -7,35 +7,106 @@ use sui_types::{
     transaction::ObjectArg,
 };
 
+fn extract_field<'a>(move_struct: &'a MoveStruct, field_name: &str) -> Result<&'a MoveValue> {
+    extract_field_from_move_struct(move_struct, field_name)
+        .ok_or_else(|| eyre!("field `{}` not found in {}", field_name, move_struct.type_))
+}
+
+// e.g. `U64` or `Vector`, for the errors
+fn variant_name(move_value: &MoveValue) -> &'static str {
+    match move_value {
+        MoveValue::U8(_) => "U8",
+        MoveValue::U16(_) => "U16",
+        MoveValue::U32(_) => "U32",
+        MoveValue::U64(_) => "U64",
+        MoveValue::U128(_) => "U128",
+        MoveValue::U256(_) => "U256",
+        MoveValue::Bool(_) => "Bool",
+        MoveValue::Address(_) => "Address",
+        MoveValue::Signer(_) => "Signer",
+        MoveValue::Vector(_) => "Vector",
+        MoveValue::Struct(_) => "Struct",
+        MoveValue::Variant(_) => "Variant",
+    }
+}
+
+fn unexpected<T>(move_struct: &MoveStruct, field_name: &str, expected: &str, found: &MoveValue) -> Result<T> {
+    bail!(
+        "field `{}` of {}: expected {}, found {}",
+        field_name,
+        move_struct.type_,
+        expected,
+        variant_name(found)
+    )
+}
+
+/// The struct at the end of a path of struct fields, e.g. `["id", "id"]` for the `ID` of a `UID` field.
+pub fn walk_path<'a>(move_struct: &'a MoveStruct, path: &[&str]) -> Result<&'a MoveStruct> {
+    let mut current = move_struct;
+    for field_name in path {
+        current = match extract_field(current, field_name)? {
+            MoveValue::Struct(move_struct) => move_struct,
+            other => return unexpected(current, field_name, "struct", other),
+        };
+    }
+
+    Ok(current)
+}
+
+/// The `id` and the `size` of a `Table` or a `Bag` field, its entries are the dynamic fields of `id`.
+pub fn extract_table_id_and_size(move_struct: &MoveStruct, field_name: &str) -> Result<(ObjectID, u64)> {
+    let table = walk_path(move_struct, &[field_name])?;
+    let id = extract_object_id_from_move_struct(walk_path(table, &["id", "id"])?, "bytes")?;
+    let size = extract_u64_from_move_struct(table, "size")?;
+
+    Ok((id, size))
+}
+
+/// An `Option<u64>` field, which is a struct with a vector of zero or one element.
+pub fn extract_option_u64_from_move_struct(move_struct: &MoveStruct, field_name: &str) -> Result<Option<u64>> {
+    let option = walk_path(move_struct, &[field_name])?;
+
+    match extract_u64_vec_from_move_struct(option, "vec")?.as_slice() {
+        [] => Ok(None),
+        [value] => Ok(Some(*value)),
+        values => bail!(
+            "field `{}` of {}: expected an option, found {} elements",
+            field_name,
+            move_struct.type_,
+            values.len()
+        ),
+    }
+}
+
 pub fn extract_struct_from_move_struct(move_struct: &MoveStruct, field_name: &str) -> Result<MoveStruct> {
-    let move_value = extract_field_from_move_struct(move_struct, field_name).ok_or_eyre("field not found")?;
+    let move_value = extract_field(move_struct, field_name)?;
 
     match move_value {
         MoveValue::Struct(move_struct) => Ok(move_struct.clone()),
-        _ => bail!("expected struct"),
+        other => unexpected(move_struct, field_name, "struct", other),
     }
 }
 
 pub fn extract_vec_from_move_struct(move_struct: &MoveStruct, field_name: &str) -> Result<Vec<MoveValue>> {
-    let move_value = extract_field_from_move_struct(move_struct, field_name).ok_or_eyre("field not found")?;
+    let move_value = extract_field(move_struct, field_name)?;
 
     match move_value {
         MoveValue::Vector(move_vec) => Ok(move_vec.clone()),
-        _ => bail!("expected vector"),
+        other => unexpected(move_struct, field_name, "vector", other),
     }
 }
 
 pub fn extract_object_id_from_move_struct(move_struct: &MoveStruct, field_name: &str) -> Result<ObjectID> {
-    let move_value = extract_field_from_move_struct(move_struct, field_name).ok_or_eyre("field not found")?;
+    let move_value = extract_field(move_struct, field_name)?;
 
     match move_value {
         MoveValue::Address(addr) => Ok(ObjectID::from_address(*addr)),
-        _ => bail!("expected address"),
+        other => unexpected(move_struct, field_name, "address", other),
     }
 }
 
-pub fn extract_struct_array_from_move_struct(move_struct: &MoveStruct, field_name: &str) -> Result<Vec<MoveStruct>> {
-    let move_value = extract_field_from_move_struct(move_struct, field_name).ok_or_eyre("field not found")?;
+pub fn extract_vec_struct_from_move_struct(move_struct: &MoveStruct, field_name: &str) -> Result<Vec<MoveStruct>> {
+    let move_value = extract_field(move_struct, field_name)?;
 
     match move_value {
         MoveValue::Vector(move_vector) => {
@@ -43,54 +114,54 @@ pub fn extract_struct_array_from_move_struct(move_struct: &MoveStruct, field_nam
                 .iter()
                 .map(|v| match v {
                     MoveValue::Struct(move_struct) => Ok(move_struct.clone()),
-                    _ => bail!("expected struct"),
+                    other => unexpected(move_struct, field_name, "vector of structs", other),
                 })
                 .collect::<Result<Vec<_>>>()?;
 
             Ok(structs)
         }
-        _ => bail!("expected array"),
+        other => unexpected(move_struct, field_name, "vector", other),
     }
 }
 
 pub fn extract_u128_from_move_struct(move_struct: &MoveStruct, field_name: &str) -> Result<u128> {
-    let move_value = extract_field_from_move_struct(move_struct, field_name).ok_or_eyre("field not found")?;
+    let move_value = extract_field(move_struct, field_name)?;
 
     match move_value {
         MoveValue::U128(u) => Ok(*u),
-        _ => bail!("expected u128"),
+        other => unexpected(move_struct, field_name, "u128", other),
     }
 }
 
 pub fn extract_u64_from_move_struct(move_struct: &MoveStruct, field_name: &str) -> Result<u64> {
-    let move_value = extract_field_from_move_struct(move_struct, field_name).ok_or_eyre("field not found")?;
+    let move_value = extract_field(move_struct, field_name)?;
 
     match move_value {
         MoveValue::U64(u) => Ok(*u),
-        _ => bail!("expected u64"),
+        other => unexpected(move_struct, field_name, "u64", other),
     }
 }
 
 pub fn extract_u32_from_move_struct(move_struct: &MoveStruct, field_name: &str) -> Result<u32> {
-    let move_value = extract_field_from_move_struct(move_struct, field_name).ok_or_eyre("field not found")?;
+    let move_value = extract_field(move_struct, field_name)?;
 
     match move_value {
         MoveValue::U32(u) => Ok(*u),
-        _ => bail!("expected u32"),
+        other => unexpected(move_struct, field_name, "u32", other),
     }
 }
 
 pub fn extract_bool_from_move_struct(move_struct: &MoveStruct, field_name: &str) -> Result<bool> {
-    let move_value = extract_field_from_move_struct(move_struct, field_name).ok_or_eyre("field not found")?;
+    let move_value = extract_field(move_struct, field_name)?;
 
     match move_value {
         MoveValue::Bool(b) => Ok(*b),
-        _ => bail!("expected bool"),
+        other => unexpected(move_struct, field_name, "bool", other),
     }
 }
 
 pub fn extract_u64_vec_from_move_struct(move_struct: &MoveStruct, field_name: &str) -> Result<Vec<u64>> {
-    let move_value = extract_field_from_move_struct(move_struct, field_name).ok_or_eyre("field not found")?;
+    let move_value = extract_field(move_struct, field_name)?;
 
     match move_value {
         MoveValue::Vector(move_vector) => {
@@ -98,18 +169,18 @@ pub fn extract_u64_vec_from_move_struct(move_struct: &MoveStruct, field_name: &s
                 .iter()
                 .map(|v| match v {
                     MoveValue::U64(u) => Ok(*u),
-                    _ => bail!("expected u64"),
+                    other => unexpected(move_struct, field_name, "vector of u64", other),
                 })
                 .collect::<Result<Vec<_>>>()?;
 
             Ok(values)
         }
-        _ => bail!("expected vector"),
+        other => unexpected(move_struct, field_name, "vector", other),
     }
 }
 
 pub fn extract_u128_vec_from_move_struct(move_struct: &MoveStruct, field_name: &str) -> Result<Vec<u128>> {
-    let move_value = extract_field_from_move_struct(move_struct, field_name).ok_or_eyre("field not found")?;
+    let move_value = extract_field(move_struct, field_name)?;
 
     match move_value {
         MoveValue::Vector(move_vector) => {
@@ -117,13 +188,13 @@ pub fn extract_u128_vec_from_move_struct(move_struct: &MoveStruct, field_name: &
                 .iter()
                 .map(|v| match v {
                     MoveValue::U128(u) => Ok(*u),
-                    _ => bail!("expected u128"),
+                    other => unexpected(move_struct, field_name, "vector of u128", other),
                 })
                 .collect::<Result<Vec<_>>>()?;
 
             Ok(values)
         }
-        _ => bail!("expected vector"),
+        other => unexpected(move_struct, field_name, "vector", other),
     }
 }
 
@@ -139,3 +210,151 @@ pub fn shared_obj_arg(obj: &Object, mutable: bool) -> ObjectArg {
         mutable,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use move_core_types::{
+        account_address::AccountAddress,
+        identifier::Identifier,
+        language_storage::{StructTag, TypeTag},
+    };
+
+    use super::*;
+
+    fn new_struct(address: AccountAddress, module: &str, name: &str, fields: Vec<(&str, MoveValue)>) -> MoveStruct {
+        MoveStruct {
+            type_: StructTag {
+                address,
+                module: Identifier::new(module).unwrap(),
+                name: Identifier::new(name).unwrap(),
+                type_params: vec![],
+            },
+            fields: fields
+                .into_iter()
+                .map(|(name, value)| (Identifier::new(name).unwrap(), value))
+                .collect(),
+        }
+    }
+
+    // `0x2::table::Table { id: UID { id: ID { bytes } }, size }`
+    fn new_table(id: ObjectID, size: u64) -> MoveValue {
+        let id = new_struct(
+            AccountAddress::TWO,
+            "object",
+            "ID",
+            vec![("bytes", MoveValue::Address(id.into()))],
+        );
+        let uid = new_struct(
+            AccountAddress::TWO,
+            "object",
+            "UID",
+            vec![("id", MoveValue::Struct(id))],
+        );
+        MoveValue::Struct(new_struct(
+            AccountAddress::TWO,
+            "table",
+            "Table",
+            vec![("id", MoveValue::Struct(uid)), ("size", MoveValue::U64(size))],
+        ))
+    }
+
+    fn new_option(value: Vec<MoveValue>) -> MoveValue {
+        let mut option = new_struct(
+            AccountAddress::ONE,
+            "option",
+            "Option",
+            vec![("vec", MoveValue::Vector(value))],
+        );
+        option.type_.type_params.push(TypeTag::U64);
+        MoveValue::Struct(option)
+    }
+
+    fn new_pool(table_id: ObjectID) -> MoveStruct {
+        let tick = |index| {
+            MoveValue::Struct(new_struct(
+                AccountAddress::ONE,
+                "pool",
+                "Tick",
+                vec![("index", MoveValue::U64(index))],
+            ))
+        };
+        new_struct(
+            AccountAddress::ONE,
+            "pool",
+            "Pool",
+            vec![
+                ("ticks", new_table(table_id, 3)),
+                ("tick_list", MoveValue::Vector(vec![tick(1), tick(2)])),
+                ("fee", MoveValue::U64(30)),
+                ("max_fee", new_option(vec![MoveValue::U64(100)])),
+                ("min_fee", new_option(vec![])),
+                ("bad_option", new_option(vec![MoveValue::U64(1), MoveValue::U64(2)])),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_walk_path() {
+        let table_id = ObjectID::random();
+        let pool = new_pool(table_id);
+
+        let id = walk_path(&pool, &["ticks", "id", "id"]).unwrap();
+        assert_eq!(extract_object_id_from_move_struct(id, "bytes").unwrap(), table_id);
+        assert_eq!(walk_path(&pool, &[]).unwrap(), &pool);
+
+        let error = walk_path(&pool, &["ticks", "uid"]).unwrap_err();
+        assert_eq!(error.to_string(), "field `uid` not found in 0x2::table::Table");
+        let error = walk_path(&pool, &["ticks", "size"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "field `size` of 0x2::table::Table: expected struct, found U64"
+        );
+    }
+
+    #[test]
+    fn test_extract_table_id_and_size() {
+        let table_id = ObjectID::random();
+        let pool = new_pool(table_id);
+
+        assert_eq!(extract_table_id_and_size(&pool, "ticks").unwrap(), (table_id, 3));
+        let error = extract_table_id_and_size(&pool, "fee").unwrap_err();
+        assert!(error.to_string().contains("`fee`"), "{error}");
+        assert!(error.to_string().contains("found U64"), "{error}");
+    }
+
+    #[test]
+    fn test_extract_option_u64() {
+        let pool = new_pool(ObjectID::random());
+
+        assert_eq!(
+            extract_option_u64_from_move_struct(&pool, "max_fee").unwrap(),
+            Some(100)
+        );
+        assert_eq!(extract_option_u64_from_move_struct(&pool, "min_fee").unwrap(), None);
+
+        let error = extract_option_u64_from_move_struct(&pool, "bad_option").unwrap_err();
+        assert!(error.to_string().contains("found 2 elements"), "{error}");
+        let error = extract_option_u64_from_move_struct(&pool, "fee").unwrap_err();
+        assert!(error.to_string().contains("expected struct, found U64"), "{error}");
+    }
+
+    #[test]
+    fn test_extract_vec_struct() {
+        let pool = new_pool(ObjectID::random());
+
+        let ticks = extract_vec_struct_from_move_struct(&pool, "tick_list").unwrap();
+        let indexes = ticks
+            .iter()
+            .map(|tick| extract_u64_from_move_struct(tick, "index").unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(indexes, vec![1, 2]);
+
+        let error = extract_vec_struct_from_move_struct(&pool, "ticks").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "field `ticks` of 0x1::pool::Pool: expected vector, found Struct"
+        );
+        let error = extract_vec_struct_from_move_struct(&pool, "missing").unwrap_err();
+        assert_eq!(error.to_string(), "field `missing` not found in 0x1::pool::Pool");
+    }
+}
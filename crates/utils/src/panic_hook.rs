@@ -0,0 +1,286 @@
+use std::{collections::BTreeMap, fmt, future::Future, time::Duration};
+
+use burberry::executor::telegram_message::escape;
+use tokio::runtime::{Builder, Handle, RuntimeFlavor};
+use tracing::{error, warn};
+
+use crate::telegram::{self, AlertConfig, AlertThread, Alerter};
+
+// longer args are redacted from the cmdline of the reports, e.g. private keys
+const MAX_ARG_LEN: usize = 32;
+
+pub const DEFAULT_WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a panic is reported with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanicReport {
+    /// the args of the process, the ones longer than 32 chars replaced with `[REDACTED]`
+    pub cmdline: String,
+    pub thread: String,
+    pub message: String,
+    /// `file:line`
+    pub location: Option<String>,
+}
+
+impl PanicReport {
+    pub fn new<I: IntoIterator<Item = String>>(args: I, thread: &str, message: &str, location: Option<String>) -> Self {
+        let cmdline = args
+            .into_iter()
+            .map(|arg| {
+                if arg.len() > MAX_ARG_LEN {
+                    "[REDACTED]".to_string()
+                } else {
+                    arg
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Self {
+            cmdline,
+            thread: thread.to_string(),
+            message: message.to_string(),
+            location,
+        }
+    }
+}
+
+// e.g. `thread 'main' panicked at 'boom': src/main.rs:10`
+impl fmt::Display for PanicReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "thread '{}' panicked at '{}'", self.thread, self.message)?;
+        if let Some(location) = &self.location {
+            write!(f, ": {}", location)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the panics are reported. `send` is called from the panic hook, on the panicking thread, which may
+/// be inside a tokio runtime: it must block until the report is sent, and must not panic.
+pub trait AlertSink: Send + Sync {
+    fn send(&self, report: &PanicReport);
+}
+
+/// Logs the panics, without any network call.
+pub struct LogSink;
+
+impl AlertSink for LogSink {
+    fn send(&self, report: &PanicReport) {
+        error!(target: "panic_hook", cmdline = %report.cmdline, "{}", report);
+    }
+}
+
+/// Sends the panics to the error thread of the telegram alerts.
+pub struct TelegramSink {
+    alerter: Alerter,
+}
+
+impl TelegramSink {
+    pub fn new(alerter: Alerter) -> Self {
+        Self { alerter }
+    }
+}
+
+impl AlertSink for TelegramSink {
+    fn send(&self, report: &PanicReport) {
+        let text = escape(&format!("cmd: {:?}\nerror: {:?}", report.cmdline, report.to_string()));
+        let alerter = self.alerter.clone();
+        block_on(async move { alerter.send(AlertThread::Error, &text).await });
+    }
+}
+
+/// Posts the panics as json to a webhook, e.g. an incident service:
+/// `{"cmdline": "..", "location": "..", "message": "..", "summary": "..", "thread": ".."}`.
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    timeout: Duration,
+}
+
+impl WebhookSink {
+    pub fn new(url: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            timeout: DEFAULT_WEBHOOK_TIMEOUT,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl AlertSink for WebhookSink {
+    fn send(&self, report: &PanicReport) {
+        let mut body = BTreeMap::new();
+        body.insert("cmdline", report.cmdline.clone());
+        body.insert("thread", report.thread.clone());
+        body.insert("message", report.message.clone());
+        body.insert("location", report.location.clone().unwrap_or_default());
+        body.insert("summary", report.to_string());
+
+        let request = self.client.post(&self.url).json(&body).timeout(self.timeout);
+        let url = self.url.clone();
+        block_on(async move {
+            match request.send().await {
+                Ok(resp) if resp.status().is_success() => {}
+                Ok(resp) => warn!(%url, status = %resp.status(), "panic webhook refused the report"),
+                Err(error) => warn!(%url, ?error, "fail to post the panic report"),
+            }
+        });
+    }
+}
+
+// runs `fut` to completion from sync code, whether or not it's called within a runtime
+fn block_on<F: Future<Output = ()> + Send>(fut: F) {
+    match Handle::try_current() {
+        Ok(handle) => match handle.runtime_flavor() {
+            // can't block the only thread of the runtime, run it on another thread
+            RuntimeFlavor::CurrentThread => std::thread::scope(move |s| {
+                s.spawn(move || {
+                    Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .unwrap()
+                        .block_on(fut)
+                })
+                .join()
+                .unwrap()
+            }),
+            _ => tokio::task::block_in_place(move || handle.block_on(fut)),
+        },
+        Err(_) => Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(fut),
+    }
+}
+
+/// Reports the panics to telegram, with the alerts set up from `alert_config` (see `telegram::init_alerts`),
+/// and to the logs.
+pub fn set_panic_hook(alert_config: Option<AlertConfig>) {
+    telegram::init_alerts(alert_config);
+
+    let mut sinks: Vec<Box<dyn AlertSink>> = vec![];
+    if let Some(alerter) = telegram::alerter() {
+        sinks.push(Box::new(TelegramSink::new(alerter)));
+    }
+    sinks.push(Box::new(LogSink));
+    set_panic_hook_with(sinks);
+}
+
+/// Reports the panics to the sinks, in order.
+pub fn set_panic_hook_with(sinks: Vec<Box<dyn AlertSink>>) {
+    std::panic::set_hook(Box::new(move |info| {
+        let thread = std::thread::current();
+        let thread = thread.name().unwrap_or("<unnamed>");
+
+        let message = match info.payload().downcast_ref::<&'static str>() {
+            Some(s) => *s,
+            None => match info.payload().downcast_ref::<String>() {
+                Some(s) => &**s,
+                None => "Box<Any>",
+            },
+        };
+        let location = info
+            .location()
+            .map(|location| format!("{}:{}", location.file(), location.line()));
+
+        let report = PanicReport::new(std::env::args(), thread, message, location);
+        for sink in &sinks {
+            sink.send(&report);
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_report_redacts_long_args() {
+        let key = "suiprivkey1qzc0c2v9qrh9rq4qr4rhd8wyxd9qqqpsjn6v0q5t";
+        let arg_32 = "a".repeat(32);
+        let report = PanicReport::new(
+            args(&["start-bot", "--private-key", key, "--tag", &arg_32]),
+            "main",
+            "boom",
+            None,
+        );
+
+        assert_eq!(
+            report.cmdline,
+            format!("start-bot --private-key [REDACTED] --tag {arg_32}")
+        );
+        assert_eq!(
+            PanicReport::new(args(&["arb", "-v"]), "main", "boom", None).cmdline,
+            "arb -v"
+        );
+    }
+
+    #[test]
+    fn test_report_summary() {
+        let report = PanicReport::new(args(&["arb"]), "worker-1", "boom", Some("src/arb.rs:10".to_string()));
+        assert_eq!(
+            report.to_string(),
+            "thread 'worker-1' panicked at 'boom': src/arb.rs:10"
+        );
+
+        let report = PanicReport::new(args(&["arb"]), "worker-1", "boom", None);
+        assert_eq!(report.to_string(), "thread 'worker-1' panicked at 'boom'");
+    }
+
+    // outside of a runtime, like a panic of a plain thread
+    #[test]
+    fn test_webhook_sink_posts_the_report() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buf = [0u8; 4096];
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let report = PanicReport::new(args(&["arb"]), "main", "boom \"quoted\"", None);
+        WebhookSink::new(url).send(&report);
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST "), "{request}");
+        assert!(request.contains(r#""message":"boom \"quoted\"""#), "{request}");
+        assert!(request.contains(r#""cmdline":"arb""#), "{request}");
+    }
+
+    // a refused report is logged, and never panics within the panic hook
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_webhook_sink_unreachable() {
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let report = PanicReport::new(args(&["arb"]), "main", "boom", None);
+        WebhookSink::new(url)
+            .with_timeout(Duration::from_millis(100))
+            .send(&report);
+    }
+}
@@ -1,9 +1,366 @@
-pub const R2D2_TELEGRAM_BOT_TOKEN: &str = "";
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
 
-pub const CHAT_MONEY_PRINTER: &str = "";
-pub const CHAT_MONEY_PRINTER_THREAD_ERROR_REPORT: &str = "";
-pub const CHAT_MONEY_PRINTER_THREAD_WALLET_WATCHER: &str = "";
+use burberry::{
+    async_trait,
+    executor::telegram_message::{Message, MessageBuilder, TelegramMessageDispatcher},
+    Executor,
+};
+use eyre::Result;
+use tracing::warn;
 
-pub const CHAT_MONEY_PRINTER_THREAD_TEST: &str = "";
+/// The same text sent again within this window is held back, see `Alerter::send`.
+pub const DEFAULT_COALESCE_WINDOW: Duration = Duration::from_secs(60);
+// telegram allows about 20 messages a minute in a group
+pub const DEFAULT_MAX_MESSAGES_PER_MINUTE: u32 = 20;
 
-pub const BIG_DICK_BOY_CHAT_ID: &str = "";
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertThread {
+    /// e.g. panics and unhealthy components
+    Error,
+    /// e.g. executed arbs
+    Notification,
+}
+
+/// Where the alerts go. Without one, alerts are disabled.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AlertConfig {
+    pub bot_token: String,
+    pub chat_id: String,
+    /// the messages go to the main thread of the chat if not set
+    pub error_thread_id: Option<String>,
+    pub notification_thread_id: Option<String>,
+    pub coalesce_window: Duration,
+    pub max_messages_per_minute: u32,
+}
+
+impl AlertConfig {
+    pub fn new(bot_token: String, chat_id: String) -> Self {
+        Self {
+            bot_token,
+            chat_id,
+            error_thread_id: None,
+            notification_thread_id: None,
+            coalesce_window: DEFAULT_COALESCE_WINDOW,
+            max_messages_per_minute: DEFAULT_MAX_MESSAGES_PER_MINUTE,
+        }
+    }
+
+    /// A message to the thread, `text` is in MarkdownV2.
+    pub fn message(&self, thread: AlertThread, text: String) -> Message {
+        let thread_id = match thread {
+            AlertThread::Error => &self.error_thread_id,
+            AlertThread::Notification => &self.notification_thread_id,
+        };
+
+        match thread_id {
+            Some(thread_id) => MessageBuilder::new()
+                .bot_token(&self.bot_token)
+                .chat_id(&self.chat_id)
+                .thread_id(thread_id)
+                .text(text)
+                .disable_link_preview(true)
+                .build(),
+            None => MessageBuilder::new()
+                .bot_token(&self.bot_token)
+                .chat_id(&self.chat_id)
+                .text(text)
+                .disable_link_preview(true)
+                .build(),
+        }
+    }
+}
+
+impl fmt::Debug for AlertConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AlertConfig")
+            .field("bot_token", &"<redacted>")
+            .field("chat_id", &self.chat_id)
+            .field("error_thread_id", &self.error_thread_id)
+            .field("notification_thread_id", &self.notification_thread_id)
+            .field("coalesce_window", &self.coalesce_window)
+            .field("max_messages_per_minute", &self.max_messages_per_minute)
+            .finish()
+    }
+}
+
+static ALERTER: OnceLock<Option<Alerter>> = OnceLock::new();
+
+/// Sets up the alerter of the process, `None` disables alerts. Only the first call has an effect.
+pub fn init_alerts(config: Option<AlertConfig>) {
+    let _ = ALERTER.set(
+        config.map(|config| Alerter::new(config, Arc::new(TelegramMessageDispatcher::new_without_error_report()))),
+    );
+}
+
+/// The alerter of the process, `None` if alerts are disabled or not set up.
+pub fn alerter() -> Option<Alerter> {
+    ALERTER.get().cloned().flatten()
+}
+
+/// Sends the alerts to telegram, at most `max_messages_per_minute` of them. The ones over the limit are
+/// dropped.
+#[derive(Clone)]
+pub struct Alerter {
+    config: Arc<AlertConfig>,
+    sink: Arc<dyn Executor<Message>>,
+    throttle: Arc<Mutex<Throttle>>,
+}
+
+impl Alerter {
+    pub fn new(config: AlertConfig, sink: Arc<dyn Executor<Message>>) -> Self {
+        let throttle = Throttle::new(config.coalesce_window, config.max_messages_per_minute);
+        Self {
+            config: Arc::new(config),
+            sink,
+            throttle: Arc::new(Mutex::new(throttle)),
+        }
+    }
+
+    pub fn config(&self) -> &AlertConfig {
+        &self.config
+    }
+
+    /// Sends `text`, in MarkdownV2, to the thread. The same text sent again within the coalesce window is
+    /// held back, and sent once with the number of repeats when the window closes.
+    pub async fn send(&self, thread: AlertThread, text: &str) {
+        let key = (thread, text.to_string());
+        let admission = self.throttle.lock().unwrap().admit(&key, Instant::now());
+
+        match admission {
+            Admission::Send => self.deliver(self.config.message(thread, text.to_string())).await,
+            Admission::Coalesced {
+                first_repeat_since: Some(since),
+            } => {
+                let alerter = self.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep_until((since + alerter.config.coalesce_window).into()).await;
+                    let repeats = {
+                        let mut throttle = alerter.throttle.lock().unwrap();
+                        match throttle.take_repeats(&key, since) {
+                            0 => 0,
+                            repeats if throttle.take_slot(Instant::now()) => repeats,
+                            _ => 0,
+                        }
+                    };
+                    if repeats == 0 {
+                        return;
+                    }
+
+                    let (thread, text) = key;
+                    let text = format!("{text}\n\n_repeated {repeats} more times_");
+                    alerter.deliver(alerter.config.message(thread, text)).await;
+                });
+            }
+            Admission::Coalesced { .. } => {}
+            Admission::RateLimited => warn!(?thread, "telegram rate limit reached, alert dropped"),
+        }
+    }
+
+    async fn deliver(&self, msg: Message) {
+        if let Err(error) = self.sink.execute(msg).await {
+            warn!(?error, "fail to send telegram message");
+        }
+    }
+}
+
+// Built messages are only rate limited, they are not coalesced.
+#[async_trait]
+impl Executor<Message> for Alerter {
+    fn name(&self) -> &str {
+        "Alerter"
+    }
+
+    async fn execute(&self, msg: Message) -> Result<()> {
+        if !self.throttle.lock().unwrap().take_slot(Instant::now()) {
+            warn!("telegram rate limit reached, message dropped");
+            return Ok(());
+        }
+        self.sink.execute(msg).await
+    }
+}
+
+type AlertKey = (AlertThread, String);
+
+#[derive(Debug, PartialEq, Eq)]
+enum Admission {
+    Send,
+    // `first_repeat_since` is the start of the window when the text is repeated for the first time in it,
+    // the repeats are to be sent when the window closes
+    Coalesced { first_repeat_since: Option<Instant> },
+    RateLimited,
+}
+
+struct Throttle {
+    coalesce_window: Duration,
+    max_per_minute: u32,
+    // the texts sent lately: when, and how many times they were repeated since
+    recent: HashMap<AlertKey, (Instant, u32)>,
+    // the messages sent within the last minute
+    sent: VecDeque<Instant>,
+}
+
+impl Throttle {
+    fn new(coalesce_window: Duration, max_per_minute: u32) -> Self {
+        Self {
+            coalesce_window,
+            max_per_minute,
+            recent: HashMap::new(),
+            sent: VecDeque::new(),
+        }
+    }
+
+    fn admit(&mut self, key: &AlertKey, now: Instant) -> Admission {
+        // the repeats of a closed window are kept until they are taken
+        let window = self.coalesce_window;
+        self.recent
+            .retain(|_, (since, repeats)| *repeats > 0 || now.saturating_duration_since(*since) < window);
+
+        if let Some((since, repeats)) = self.recent.get_mut(key) {
+            if now.saturating_duration_since(*since) < window {
+                *repeats += 1;
+                let first_repeat_since = (*repeats == 1).then_some(*since);
+                return Admission::Coalesced { first_repeat_since };
+            }
+        }
+
+        if !self.take_slot(now) {
+            return Admission::RateLimited;
+        }
+        self.recent.insert(key.clone(), (now, 0));
+        Admission::Send
+    }
+
+    // the repeats of the window of `key` that started at `since`
+    fn take_repeats(&mut self, key: &AlertKey, since: Instant) -> u32 {
+        match self.recent.get(key) {
+            Some((window_start, repeats)) if *window_start == since => {
+                let repeats = *repeats;
+                self.recent.remove(key);
+                repeats
+            }
+            _ => 0,
+        }
+    }
+
+    fn take_slot(&mut self, now: Instant) -> bool {
+        while let Some(sent_at) = self.sent.front() {
+            if now.saturating_duration_since(*sent_at) < Duration::from_secs(60) {
+                break;
+            }
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= self.max_per_minute as usize {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct MockDispatcher {
+        sent: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Executor<Message> for MockDispatcher {
+        fn name(&self) -> &str {
+            "MockDispatcher"
+        }
+
+        async fn execute(&self, msg: Message) -> Result<()> {
+            self.sent.lock().unwrap().push(format!("{msg:?}"));
+            Ok(())
+        }
+    }
+
+    fn key(text: &str) -> AlertKey {
+        (AlertThread::Error, text.to_string())
+    }
+
+    #[test]
+    fn test_debug_redacts_token() {
+        let config = AlertConfig::new("123:secret".to_string(), "chat".to_string());
+        assert!(!format!("{config:?}").contains("secret"));
+    }
+
+    #[test]
+    fn test_throttle_coalesces_repeats() {
+        let mut throttle = Throttle::new(Duration::from_secs(10), 100);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(throttle.admit(&key("a"), at(0)), Admission::Send);
+        assert_eq!(
+            throttle.admit(&key("a"), at(1)),
+            Admission::Coalesced {
+                first_repeat_since: Some(start)
+            }
+        );
+        assert_eq!(
+            throttle.admit(&key("a"), at(2)),
+            Admission::Coalesced {
+                first_repeat_since: None
+            }
+        );
+        // other texts and threads are not affected
+        assert_eq!(throttle.admit(&key("b"), at(2)), Admission::Send);
+        assert_eq!(
+            throttle.admit(&(AlertThread::Notification, "a".to_string()), at(2)),
+            Admission::Send
+        );
+
+        assert_eq!(throttle.take_repeats(&key("a"), start), 2);
+        assert_eq!(throttle.take_repeats(&key("a"), start), 0);
+        assert_eq!(throttle.admit(&key("a"), at(10)), Admission::Send);
+    }
+
+    #[test]
+    fn test_throttle_rate_limit() {
+        let mut throttle = Throttle::new(Duration::from_secs(10), 2);
+        let start = Instant::now();
+
+        assert_eq!(throttle.admit(&key("a"), start), Admission::Send);
+        assert_eq!(throttle.admit(&key("b"), start), Admission::Send);
+        assert_eq!(throttle.admit(&key("c"), start), Admission::RateLimited);
+        // a dropped text is not coalesced either
+        assert_eq!(throttle.admit(&key("c"), start), Admission::RateLimited);
+
+        assert_eq!(
+            throttle.admit(&key("c"), start + Duration::from_secs(60)),
+            Admission::Send
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alerter_sends_repeats_once_the_window_closes() {
+        let dispatcher = Arc::new(MockDispatcher::default());
+        let mut config = AlertConfig::new("token".to_string(), "chat".to_string());
+        config.coalesce_window = Duration::from_millis(100);
+        let alerter = Alerter::new(config, dispatcher.clone());
+
+        for _ in 0..3 {
+            alerter.send(AlertThread::Error, "db_sim is down").await;
+        }
+        alerter.send(AlertThread::Error, "shio is down").await;
+        assert_eq!(dispatcher.sent.lock().unwrap().len(), 2);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let sent = dispatcher.sent.lock().unwrap();
+        assert_eq!(sent.len(), 3);
+        assert!(
+            sent[2].contains("db_sim is down") && sent[2].contains("repeated 2 more times"),
+            "{sent:?}"
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use burberry::executor::telegram_message::escape;
+use tokio::runtime::Handle;
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    layer::Context,
+    Layer,
+};
+
+use crate::telegram::{AlertThread, Alerter};
+
+/// The same message of the same target is sent at most once within this window.
+pub const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+// where a failed alert is logged, which must not trigger another alert, and where the panics and the heartbeat
+// escalations are logged, which send their own alert
+const IGNORED_TARGETS: [&str; 4] = [
+    "utils::telegram",
+    "burberry::executor::telegram_message",
+    "panic_hook",
+    "utils::heartbeat",
+];
+
+/// Sends the log events at `level` or above, ERROR by default, to the error thread of the alerts, only those
+/// of the `targets` modules if set.
+///
+/// The alerts are sent on `handle`, so the logging thread never waits on telegram, and the rate limit of the
+/// `Alerter` applies on top of the dedup. The events of `telegram` and of the dispatcher are never sent, nor
+/// those of the panic hook and the heartbeat, which are alerted already.
+pub struct TelegramLayer {
+    alerter: Alerter,
+    handle: Handle,
+    level: Level,
+    targets: Option<Vec<String>>,
+    dedup: Mutex<Dedup>,
+}
+
+impl TelegramLayer {
+    pub fn new(alerter: Alerter, handle: Handle) -> Self {
+        Self {
+            alerter,
+            handle,
+            level: Level::ERROR,
+            targets: None,
+            dedup: Mutex::new(Dedup::new(DEFAULT_DEDUP_WINDOW)),
+        }
+    }
+
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Only send the events of these modules and of their submodules, e.g. the whitelisted modules of the
+    /// logger. The level of a `module=level` directive is ignored, the one of the layer applies.
+    pub fn with_targets<T: AsRef<str>>(mut self, targets: &[T]) -> Self {
+        let targets = targets
+            .iter()
+            .map(|target| target.as_ref().split('=').next().unwrap_or_default().to_string())
+            .collect();
+        self.targets = Some(targets);
+        self
+    }
+
+    pub fn with_dedup_window(mut self, window: Duration) -> Self {
+        self.dedup = Mutex::new(Dedup::new(window));
+        self
+    }
+
+    /// The layer with its level and targets as filter, which leaves the other layers unaffected by them.
+    pub fn filtered<S>(self) -> impl Layer<S> + Send + Sync
+    where
+        S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        let level = LevelFilter::from_level(self.level);
+        let filter = match &self.targets {
+            Some(targets) => Targets::new().with_targets(targets.iter().map(|target| (target.clone(), level))),
+            None => Targets::new().with_default(level),
+        };
+        self.with_filter(filter)
+    }
+
+    fn is_target(&self, target: &str) -> bool {
+        if IGNORED_TARGETS.contains(&target) {
+            return false;
+        }
+        match &self.targets {
+            Some(targets) => targets.iter().any(|module| {
+                target
+                    .strip_prefix(module.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            }),
+            None => true,
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for TelegramLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let target = metadata.target();
+        if *metadata.level() > self.level || !self.is_target(target) {
+            return;
+        }
+
+        let mut visitor = AlertVisitor::default();
+        event.record(&mut visitor);
+        if !self
+            .dedup
+            .lock()
+            .unwrap()
+            .admit(target, &visitor.message, Instant::now())
+        {
+            return;
+        }
+
+        let text = format!(
+            "*{level}* `{target}`\n{message}",
+            level = metadata.level(),
+            target = escape(target),
+            message = escape(&visitor.to_string()),
+        );
+        let alerter = self.alerter.clone();
+        self.handle.spawn(async move {
+            alerter.send(AlertThread::Error, &text).await;
+        });
+    }
+}
+
+// the message, then the other fields as `name=value`
+#[derive(Default)]
+struct AlertVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for AlertVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.message, "{value:?}").unwrap();
+        } else {
+            write!(self.fields, " {}={value:?}", field.name()).unwrap();
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            write!(self.fields, " {}={value}", field.name()).unwrap();
+        }
+    }
+}
+
+impl fmt::Display for AlertVisitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.message, self.fields)
+    }
+}
+
+// when each (target, message) was last sent
+struct Dedup {
+    window: Duration,
+    last_sent: HashMap<(String, String), Instant>,
+}
+
+impl Dedup {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last_sent: HashMap::new(),
+        }
+    }
+
+    fn admit(&mut self, target: &str, message: &str, now: Instant) -> bool {
+        let window = self.window;
+        self.last_sent
+            .retain(|_, sent_at| now.saturating_duration_since(*sent_at) < window);
+
+        let key = (target.to_string(), message.to_string());
+        if self.last_sent.contains_key(&key) {
+            return false;
+        }
+        self.last_sent.insert(key, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use burberry::{async_trait, executor::telegram_message::Message, Executor};
+    use eyre::Result;
+    use tracing::{error, info, warn};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::telegram::AlertConfig;
+
+    // holds every message for `delay`, like an unreachable telegram
+    #[derive(Default)]
+    struct MockDispatcher {
+        delay: Duration,
+        sent: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Executor<Message> for MockDispatcher {
+        fn name(&self) -> &str {
+            "MockDispatcher"
+        }
+
+        async fn execute(&self, msg: Message) -> Result<()> {
+            tokio::time::sleep(self.delay).await;
+            self.sent.lock().unwrap().push(format!("{msg:?}"));
+            Ok(())
+        }
+    }
+
+    fn new_alerter(dispatcher: Arc<MockDispatcher>) -> Alerter {
+        Alerter::new(AlertConfig::new("token".to_string(), "chat".to_string()), dispatcher)
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut dedup = Dedup::new(Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(dedup.admit("arb", "socket died", now));
+        assert!(!dedup.admit("arb", "socket died", now + Duration::from_secs(30)));
+        // another message or another target
+        assert!(dedup.admit("arb", "shio disconnected", now + Duration::from_secs(30)));
+        assert!(dedup.admit("shio", "socket died", now + Duration::from_secs(30)));
+        // once the window is over
+        assert!(dedup.admit("arb", "socket died", now + Duration::from_secs(60)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sends_deduped_errors() {
+        let dispatcher = Arc::new(MockDispatcher::default());
+        let layer = TelegramLayer::new(new_alerter(dispatcher.clone()), Handle::current());
+        let subscriber = tracing_subscriber::registry().with(layer.filtered());
+
+        tracing::subscriber::with_default(subscriber, || {
+            error!(attempts = 3, "update socket died");
+            error!(attempts = 3, "update socket died");
+            error!("executor submit failed");
+            warn!("below the level");
+            info!("below the level");
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let sent = dispatcher.sent.lock().unwrap();
+        assert_eq!(sent.len(), 2, "{sent:?}");
+        assert!(
+            sent.iter()
+                .any(|msg| msg.contains("update socket died") && msg.contains("attempts")),
+            "{sent:?}"
+        );
+        assert!(
+            sent.iter().any(|msg| msg.contains("executor submit failed")),
+            "{sent:?}"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sends_only_the_targets() {
+        let dispatcher = Arc::new(MockDispatcher::default());
+        let targets = ["arb", "shio", "cache_metrics=debug"];
+        let layer = TelegramLayer::new(new_alerter(dispatcher.clone()), Handle::current()).with_targets(&targets);
+        let subscriber = tracing_subscriber::registry().with(layer.filtered());
+
+        tracing::subscriber::with_default(subscriber, || {
+            error!(target: "arb::executor", "executor submit failed");
+            error!(target: "shio", "shio disconnected");
+            error!(target: "arbitrage", "not the arb module");
+            error!(target: "sui_core", "not whitelisted");
+            // alerted already by the panic hook and the heartbeat
+            error!(target: "panic_hook", "thread panicked");
+            error!(target: "utils::heartbeat", "sui-arb is unhealthy");
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let sent = dispatcher.sent.lock().unwrap();
+        assert_eq!(sent.len(), 2, "{sent:?}");
+        assert!(sent.iter().any(|msg| msg.contains("submit failed")), "{sent:?}");
+        assert!(sent.iter().any(|msg| msg.contains("shio disconnected")), "{sent:?}");
+    }
+
+    #[tokio::test]
+    async fn test_is_target() {
+        let layer = TelegramLayer::new(new_alerter(Arc::new(MockDispatcher::default())), Handle::current());
+        assert!(layer.is_target("sui_core"));
+        assert!(!layer.is_target("panic_hook"));
+
+        let layer = layer.with_targets(&["arb", "cache_metrics=debug"]);
+        assert!(layer.is_target("arb"));
+        assert!(layer.is_target("arb::strategy::worker"));
+        assert!(layer.is_target("cache_metrics"));
+        assert!(!layer.is_target("arbitrage"));
+        assert!(!layer.is_target("utils::heartbeat"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_never_blocks_the_caller() {
+        let dispatcher = Arc::new(MockDispatcher {
+            delay: Duration::from_secs(10),
+            ..Default::default()
+        });
+        let layer = TelegramLayer::new(new_alerter(dispatcher.clone()), Handle::current()).with_level(Level::WARN);
+        let subscriber = tracing_subscriber::registry().with(layer.filtered());
+
+        let start = Instant::now();
+        tracing::subscriber::with_default(subscriber, || {
+            for i in 0..10 {
+                warn!("shio disconnected {i}");
+            }
+        });
+        assert!(start.elapsed() < Duration::from_secs(1), "{:?}", start.elapsed());
+        assert!(dispatcher.sent.lock().unwrap().is_empty());
+    }
+}
@@ -1,13 +1,23 @@
 extern crate proc_macro;
-use std::{ops::Not, process::Command, str::FromStr};
+use std::{
+    ops::Not,
+    process::Command,
+    str::FromStr,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use eyre::ensure;
 use proc_macro::TokenStream;
 
+// the version of every `build_version!()` of the crate being compiled
+static BUILD_VERSION: OnceLock<String> = OnceLock::new();
+
 fn get_git_commit() -> eyre::Result<String> {
     let output = Command::new("git")
         .args(["log", "-1", "--pretty=format:%h,%ad", "--date=format:%Y-%m-%d"])
         .output()?;
+    ensure!(output.status.success(), "git log failed");
     let output_str = std::str::from_utf8(&output.stdout)?.trim().to_string();
     let parts: Vec<&str> = output_str.split(',').collect();
     ensure!(parts.len() == 2, "Unexpected output format");
@@ -26,14 +36,130 @@ fn get_git_commit() -> eyre::Result<String> {
     let branch = std::str::from_utf8(&branch_output.stdout)?.trim().to_string();
 
     Ok(format!(
-        "\"{branch}-{commit}{dirty}@{date}\"",
+        "{branch}-{commit}{dirty}@{date}",
         commit = parts[0],
         date = parts[1]
     ))
 }
 
+// e.g. `rustc 1.81.0`, the compiler of the crate, which runs the macro
+fn get_rustc_version() -> Option<String> {
+    let output = Command::new("rustc").arg("--version").output().ok()?;
+    let version = std::str::from_utf8(&output.stdout).ok()?;
+    let version = version.split_whitespace().take(2).collect::<Vec<_>>().join(" ");
+    (output.status.success() && !version.is_empty()).then_some(version)
+}
+
+// `SOURCE_DATE_EPOCH` for reproducible builds, now otherwise
+fn get_build_timestamp() -> u64 {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        })
+}
+
+/// `2024-01-31T12:00:00Z` for the seconds since the epoch.
+fn format_timestamp(secs: u64) -> String {
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // civil date from days, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// The git commit if known, the package version otherwise, with `git_sha` (`BUILD_GIT_SHA`) if set, e.g.
+/// `main-1a2b3c4@2024-01-31 (rustc 1.81.0, built 2024-01-31T12:00:00Z)` or `0.1.0-1a2b3c4 (...)`.
+fn format_version(
+    git_commit: Option<&str>,
+    pkg_version: &str,
+    git_sha: Option<&str>,
+    rustc_version: Option<&str>,
+    built_at: u64,
+) -> String {
+    let source = match (git_commit, git_sha) {
+        (Some(git_commit), _) => git_commit.to_string(),
+        (None, Some(git_sha)) => format!("{pkg_version}-{git_sha}"),
+        (None, None) => pkg_version.to_string(),
+    };
+    let rustc_version = rustc_version.unwrap_or("rustc unknown");
+
+    format!("{source} ({rustc_version}, built {})", format_timestamp(built_at))
+}
+
+fn build_version_string() -> String {
+    // the variables of the crate invoking the macro
+    let pkg_version = std::env::var("CARGO_PKG_VERSION").unwrap_or_else(|_| "unknown".to_string());
+    let git_sha = std::env::var("BUILD_GIT_SHA").ok().filter(|sha| !sha.trim().is_empty());
+
+    format_version(
+        get_git_commit().ok().as_deref(),
+        &pkg_version,
+        git_sha.as_deref().map(str::trim),
+        get_rustc_version().as_deref(),
+        get_build_timestamp(),
+    )
+}
+
+/// The version of the build as a string literal, see `format_version`. Never fails, without git (a source
+/// tarball, a docker stage...) it falls back to the package version.
 #[proc_macro]
 pub fn build_version(_item: TokenStream) -> TokenStream {
-    let version = get_git_commit().unwrap();
-    TokenStream::from_str(version.as_str()).unwrap()
+    let version = BUILD_VERSION.get_or_init(build_version_string);
+    TokenStream::from_str(&format!("{version:?}")).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951_827_696), "2000-02-29T12:34:56Z");
+        assert_eq!(format_timestamp(1_706_702_400), "2024-01-31T12:00:00Z");
+    }
+
+    #[test]
+    fn test_format_version() {
+        let built_at = 1_706_702_400;
+        assert_eq!(
+            format_version(
+                Some("main-1a2b3c4-dirty@2024-01-31"),
+                "0.1.0",
+                Some("ffffff"),
+                Some("rustc 1.81.0"),
+                built_at
+            ),
+            "main-1a2b3c4-dirty@2024-01-31 (rustc 1.81.0, built 2024-01-31T12:00:00Z)"
+        );
+
+        // without git
+        assert_eq!(
+            format_version(None, "0.1.0", Some("1a2b3c4"), Some("rustc 1.81.0"), built_at),
+            "0.1.0-1a2b3c4 (rustc 1.81.0, built 2024-01-31T12:00:00Z)"
+        );
+        assert_eq!(
+            format_version(None, "0.1.0", None, None, built_at),
+            "0.1.0 (rustc unknown, built 2024-01-31T12:00:00Z)"
+        );
+    }
 }